@@ -0,0 +1,84 @@
+//! Indexed transaction lookups against a linear scan of the log.
+//!
+//! Run with `cargo bench --bench transactions`.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use securevault::time::Timestamp;
+use securevault::{amount, CustodySystem, WalletType};
+
+const WALLETS: usize = 1_000;
+
+/// A system with `transactions` deposits spread evenly over the wallets
+fn system(transactions: usize) -> CustodySystem {
+    let mut system = CustodySystem::new();
+    for i in 0..WALLETS {
+        system
+            .create_wallet(format!("w{}", i), format!("0x{:x}", i), WalletType::Hot)
+            .unwrap();
+    }
+    for i in 0..transactions {
+        system
+            .deposit(&format!("w{}", i % WALLETS), amount!(1))
+            .unwrap();
+    }
+    system
+}
+
+fn wallet_history(c: &mut Criterion) {
+    let mut group = c.benchmark_group("wallet_history");
+    for size in [10_000, 100_000] {
+        let system = system(size);
+        group.bench_with_input(BenchmarkId::new("indexed", size), &system, |b, system| {
+            b.iter(|| system.get_wallet_transactions(black_box("w42")).len())
+        });
+        group.bench_with_input(BenchmarkId::new("scan", size), &system, |b, system| {
+            b.iter(|| {
+                system
+                    .get_all_transactions()
+                    .iter()
+                    .filter(|tx| tx.involves(black_box("w42")))
+                    .count()
+            })
+        });
+    }
+    group.finish();
+}
+
+fn lookups(c: &mut Criterion) {
+    let system = system(100_000);
+    let id = system.get_all_transactions()[90_000].id;
+    let later = Timestamp::from_unix(Timestamp::now().as_unix() + 3_600);
+
+    let mut group = c.benchmark_group("lookups");
+    group.bench_function("by_id/indexed", |b| {
+        b.iter(|| system.get_transaction(black_box(id)).is_some())
+    });
+    group.bench_function("by_id/scan", |b| {
+        b.iter(|| {
+            system
+                .get_all_transactions()
+                .iter()
+                .any(|tx| tx.id == black_box(id))
+        })
+    });
+    group.bench_function("time_range/indexed", |b| {
+        b.iter(|| {
+            system
+                .get_transactions_between(black_box(later), later)
+                .len()
+        })
+    });
+    group.bench_function("time_range/scan", |b| {
+        b.iter(|| {
+            system
+                .get_all_transactions()
+                .iter()
+                .filter(|tx| tx.timestamp >= black_box(later))
+                .count()
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, wallet_history, lookups);
+criterion_main!(benches);
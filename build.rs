@@ -0,0 +1,146 @@
+//! Generates the gRPC service stubs (feature `grpc`).
+//!
+//! The service is described here rather than compiled from
+//! `proto/securevault/v1/custody.proto`, so building does not need
+//! `protoc`. Keep the two in sync; the message types live in `src/grpc.rs`.
+
+fn main() {
+    #[cfg(feature = "grpc")]
+    grpc::compile();
+}
+
+#[cfg(feature = "grpc")]
+mod grpc {
+    use tonic_build::manual::{Builder, Method, Service};
+
+    /// (method name, route name, input type, output type, server streaming)
+    const METHODS: &[(&str, &str, &str, &str, bool)] = &[
+        (
+            "create_wallet",
+            "CreateWallet",
+            "CreateWalletRequest",
+            "Wallet",
+            false,
+        ),
+        (
+            "get_wallet",
+            "GetWallet",
+            "GetWalletRequest",
+            "Wallet",
+            false,
+        ),
+        (
+            "list_wallets",
+            "ListWallets",
+            "ListWalletsRequest",
+            "ListWalletsResponse",
+            false,
+        ),
+        ("deposit", "Deposit", "DepositRequest", "Transaction", false),
+        (
+            "withdraw",
+            "Withdraw",
+            "WithdrawRequest",
+            "Transaction",
+            false,
+        ),
+        (
+            "transfer",
+            "Transfer",
+            "TransferRequest",
+            "Transaction",
+            false,
+        ),
+        (
+            "list_transactions",
+            "ListTransactions",
+            "ListTransactionsRequest",
+            "ListTransactionsResponse",
+            false,
+        ),
+        (
+            "freeze_wallet",
+            "FreezeWallet",
+            "WalletStatusRequest",
+            "Wallet",
+            false,
+        ),
+        (
+            "unfreeze_wallet",
+            "UnfreezeWallet",
+            "WalletStatusRequest",
+            "Wallet",
+            false,
+        ),
+        (
+            "close_wallet",
+            "CloseWallet",
+            "WalletStatusRequest",
+            "Wallet",
+            false,
+        ),
+        (
+            "request_withdrawal",
+            "RequestWithdrawal",
+            "RequestWithdrawalRequest",
+            "OperationResponse",
+            false,
+        ),
+        (
+            "approve_withdrawal",
+            "ApproveWithdrawal",
+            "ApproveWithdrawalRequest",
+            "OperationResponse",
+            false,
+        ),
+        (
+            "reject_withdrawal",
+            "RejectWithdrawal",
+            "RejectWithdrawalRequest",
+            "OperationResponse",
+            false,
+        ),
+        (
+            "request_multisig_withdrawal",
+            "RequestMultisigWithdrawal",
+            "RequestMultisigWithdrawalRequest",
+            "OperationResponse",
+            false,
+        ),
+        (
+            "sign_withdrawal",
+            "SignWithdrawal",
+            "SignWithdrawalRequest",
+            "OperationResponse",
+            false,
+        ),
+        (
+            "stream_transactions",
+            "StreamTransactions",
+            "StreamTransactionsRequest",
+            "Transaction",
+            true,
+        ),
+    ];
+
+    pub fn compile() {
+        println!("cargo:rerun-if-changed=build.rs");
+        let mut service = Service::builder().name("Custody").package("securevault.v1");
+        for &(name, route, input, output, streaming) in METHODS {
+            let mut method = Method::builder()
+                .name(name)
+                .route_name(route)
+                .input_type(format!("super::{}", input))
+                .output_type(format!("super::{}", output))
+                .codec_path("tonic_prost::ProstCodec");
+            if streaming {
+                method = method.server_streaming();
+            }
+            service = service.method(method.build());
+        }
+        Builder::new()
+            .build_client(true)
+            .build_server(true)
+            .compile(&[service.build()]);
+    }
+}
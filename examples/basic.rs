@@ -2,7 +2,7 @@
 //
 // Run with: cargo run --example basic
 
-use securevault::{CustodySystem, WalletType};
+use securevault::{amount, CustodySystem, WalletType};
 
 fn main() {
     println!("=== Basic Wallet Operations Example ===\n");
@@ -32,12 +32,12 @@ fn main() {
 
     // Deposit operations
     println!("\nPerforming deposits...");
-    match system.deposit("alice_hot", 50.0) {
+    match system.deposit("alice_hot", amount!(50.0)) {
         Ok(_) => println!("✓ Deposited 50.0 BTC to alice_hot"),
         Err(e) => println!("✗ Deposit failed: {}", e),
     }
 
-    match system.deposit("alice_cold", 200.0) {
+    match system.deposit("alice_cold", amount!(200.0)) {
         Ok(_) => println!("✓ Deposited 200.0 BTC to alice_cold"),
         Err(e) => println!("✗ Deposit failed: {}", e),
     }
@@ -55,7 +55,7 @@ fn main() {
 
     // Withdrawal operation
     println!("\nWithdrawing 10.0 BTC from alice_hot...");
-    match system.withdraw("alice_hot", 10.0) {
+    match system.withdraw("alice_hot", amount!(10.0)) {
         Ok(_) => println!("✓ Withdrawal successful"),
         Err(e) => println!("✗ Withdrawal failed: {}", e),
     }
@@ -2,7 +2,7 @@
 //
 // Run with: cargo run --example error_handling
 
-use securevault::{CustodySystem, WalletType};
+use securevault::{amount, CustodySystem, WalletType};
 
 fn main() {
     println!("=== Error Handling Example ===\n");
@@ -33,63 +33,63 @@ fn main() {
 
     // Test 3: Deposit to non-existent wallet
     println!("\nTest 3: Depositing to non-existent wallet (should fail)");
-    match system.deposit("nonexistent_wallet", 10.0) {
+    match system.deposit("nonexistent_wallet", amount!(10.0)) {
         Ok(_) => println!("✗ Unexpectedly succeeded"),
         Err(e) => println!("✓ Expected error: {}", e),
     }
 
     // Test 4: Deposit negative amount
     println!("\nTest 4: Depositing negative amount (should fail)");
-    match system.deposit("test_wallet", -10.0) {
+    match system.deposit("test_wallet", amount!(-10.0)) {
         Ok(_) => println!("✗ Unexpectedly succeeded"),
         Err(e) => println!("✓ Expected error: {}", e),
     }
 
     // Test 5: Deposit zero amount
     println!("\nTest 5: Depositing zero amount (should fail)");
-    match system.deposit("test_wallet", 0.0) {
+    match system.deposit("test_wallet", amount!(0.0)) {
         Ok(_) => println!("✗ Unexpectedly succeeded"),
         Err(e) => println!("✓ Expected error: {}", e),
     }
 
     // Test 6: Successful deposit
     println!("\nTest 6: Successful deposit");
-    match system.deposit("test_wallet", 100.0) {
+    match system.deposit("test_wallet", amount!(100.0)) {
         Ok(_) => println!("✓ Deposited 100.0 BTC"),
         Err(e) => println!("✗ Unexpected error: {}", e),
     }
 
     // Test 7: Withdraw more than balance
     println!("\nTest 7: Withdrawing more than balance (should fail)");
-    match system.withdraw("test_wallet", 150.0) {
+    match system.withdraw("test_wallet", amount!(150.0)) {
         Ok(_) => println!("✗ Unexpectedly succeeded"),
         Err(e) => println!("✓ Expected error: {}", e),
     }
 
     // Test 8: Withdraw negative amount
     println!("\nTest 8: Withdrawing negative amount (should fail)");
-    match system.withdraw("test_wallet", -10.0) {
+    match system.withdraw("test_wallet", amount!(-10.0)) {
         Ok(_) => println!("✗ Unexpectedly succeeded"),
         Err(e) => println!("✓ Expected error: {}", e),
     }
 
     // Test 9: Successful withdrawal
     println!("\nTest 9: Successful withdrawal");
-    match system.withdraw("test_wallet", 30.0) {
+    match system.withdraw("test_wallet", amount!(30.0)) {
         Ok(_) => println!("✓ Withdrew 30.0 BTC"),
         Err(e) => println!("✗ Unexpected error: {}", e),
     }
 
     // Test 10: Transfer to non-existent wallet
     println!("\nTest 10: Transferring to non-existent wallet (should fail)");
-    match system.transfer("test_wallet", "nonexistent", 10.0) {
+    match system.transfer("test_wallet", "nonexistent", amount!(10.0)) {
         Ok(_) => println!("✗ Unexpectedly succeeded"),
         Err(e) => println!("✓ Expected error: {}", e),
     }
 
     // Test 11: Transfer from non-existent wallet
     println!("\nTest 11: Transferring from non-existent wallet (should fail)");
-    match system.transfer("nonexistent", "test_wallet", 10.0) {
+    match system.transfer("nonexistent", "test_wallet", amount!(10.0)) {
         Ok(_) => println!("✗ Unexpectedly succeeded"),
         Err(e) => println!("✓ Expected error: {}", e),
     }
@@ -104,14 +104,14 @@ fn main() {
         .unwrap();
 
     println!("\nTest 12: Transferring negative amount (should fail)");
-    match system.transfer("test_wallet", "receiver", -10.0) {
+    match system.transfer("test_wallet", "receiver", amount!(-10.0)) {
         Ok(_) => println!("✗ Unexpectedly succeeded"),
         Err(e) => println!("✓ Expected error: {}", e),
     }
 
     // Test 13: Successful transfer
     println!("\nTest 13: Successful transfer");
-    match system.transfer("test_wallet", "receiver", 20.0) {
+    match system.transfer("test_wallet", "receiver", amount!(20.0)) {
         Ok(_) => {
             println!("✓ Transferred 20.0 BTC");
             let sender = system.get_wallet("test_wallet").unwrap();
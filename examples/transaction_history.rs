@@ -2,7 +2,7 @@
 //
 // Run with: cargo run --example transaction_history
 
-use securevault::{CustodySystem, TransactionType, WalletType};
+use securevault::{amount, Amount, CustodySystem, TransactionType, WalletType};
 
 fn main() {
     println!("=== Transaction History Example ===\n");
@@ -22,19 +22,19 @@ fn main() {
 
     // Perform various operations
     println!("Performing transactions...");
-    system.deposit("trader_wallet", 100.0).unwrap();
+    system.deposit("trader_wallet", amount!(100.0)).unwrap();
     println!("✓ Deposited 100.0 BTC");
 
-    system.withdraw("trader_wallet", 25.0).unwrap();
+    system.withdraw("trader_wallet", amount!(25.0)).unwrap();
     println!("✓ Withdrew 25.0 BTC");
 
-    system.deposit("trader_wallet", 50.0).unwrap();
+    system.deposit("trader_wallet", amount!(50.0)).unwrap();
     println!("✓ Deposited 50.0 BTC");
 
-    system.withdraw("trader_wallet", 30.0).unwrap();
+    system.withdraw("trader_wallet", amount!(30.0)).unwrap();
     println!("✓ Withdrew 30.0 BTC");
 
-    system.deposit("trader_wallet", 15.0).unwrap();
+    system.deposit("trader_wallet", amount!(15.0)).unwrap();
     println!("✓ Deposited 15.0 BTC");
 
     // Show current balance
@@ -45,20 +45,20 @@ fn main() {
     println!("\n=== Transaction History ===");
     let transactions = system.get_wallet_transactions("trader_wallet");
 
-    let mut total_deposits = 0.0;
-    let mut total_withdrawals = 0.0;
+    let mut total_deposits = Amount::ZERO;
+    let mut total_withdrawals = Amount::ZERO;
 
     for (i, tx) in transactions.iter().enumerate() {
         let tx_type = match tx.transaction_type {
-            TransactionType::Deposit => {
-                total_deposits += tx.amount;
-                "DEPOSIT   "
-            }
-            TransactionType::Withdrawal => {
-                total_withdrawals += tx.amount;
-                "WITHDRAWAL"
-            }
+            TransactionType::Deposit => "DEPOSIT   ",
+            TransactionType::Withdrawal => "WITHDRAWAL",
+            _ => "OTHER     ",
         };
+        if tx.transaction_type.is_credit() {
+            total_deposits += tx.amount;
+        } else {
+            total_withdrawals += tx.amount;
+        }
 
         println!(
             "{}. {} | Amount: {:>8.2} BTC | Timestamp: {}",
@@ -79,7 +79,7 @@ fn main() {
 
     // Verify balance matches transaction history
     let calculated_balance = total_deposits - total_withdrawals;
-    if (calculated_balance - wallet.balance).abs() < 0.001 {
+    if calculated_balance == wallet.balance {
         println!("\n✓ Balance verified against transaction history");
     } else {
         println!("\n✗ Balance mismatch detected!");
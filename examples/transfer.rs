@@ -2,7 +2,7 @@
 //
 // Run with: cargo run --example transfer
 
-use securevault::{CustodySystem, WalletType};
+use securevault::{amount, CustodySystem, WalletType};
 
 fn main() {
     println!("=== Transfer Operations Example ===\n");
@@ -39,7 +39,7 @@ fn main() {
 
     // Initial deposit to operations wallet
     println!("Initial deposit...");
-    system.deposit("operations", 100.0).unwrap();
+    system.deposit("operations", amount!(100.0)).unwrap();
     println!("✓ Deposited 100.0 BTC to operations wallet\n");
 
     // Show initial state
@@ -47,7 +47,7 @@ fn main() {
 
     // Transfer to savings (cold storage)
     println!("\nTransferring 60.0 BTC from operations to savings...");
-    match system.transfer("operations", "savings", 60.0) {
+    match system.transfer("operations", "savings", amount!(60.0)) {
         Ok(_) => {
             println!("✓ Transfer successful");
             print_balances(&system);
@@ -57,7 +57,7 @@ fn main() {
 
     // Transfer to backup
     println!("\nTransferring 20.0 BTC from operations to backup...");
-    match system.transfer("operations", "backup", 20.0) {
+    match system.transfer("operations", "backup", amount!(20.0)) {
         Ok(_) => {
             println!("✓ Transfer successful");
             print_balances(&system);
@@ -67,21 +67,39 @@ fn main() {
 
     // Attempt to transfer more than available (should fail)
     println!("\nAttempting to transfer 50.0 BTC from operations (only has 20.0)...");
-    match system.transfer("operations", "savings", 50.0) {
+    match system.transfer("operations", "savings", amount!(50.0)) {
         Ok(_) => println!("✓ Transfer successful"),
         Err(e) => println!("✗ Expected failure: {}", e),
     }
 
-    // Rebalancing: move some funds from savings back to operations
+    // Cold wallets never pay out directly
     println!("\nRebalancing: Moving 30.0 BTC from savings back to operations...");
-    match system.transfer("savings", "operations", 30.0) {
-        Ok(_) => {
-            println!("✓ Transfer successful");
-            print_balances(&system);
-        }
-        Err(e) => println!("✗ Transfer failed: {}", e),
+    match system.transfer("savings", "operations", amount!(30.0)) {
+        Ok(_) => println!("✓ Transfer successful"),
+        Err(e) => println!("✗ Expected failure: {}", e),
     }
 
+    // Instead the withdrawal is requested and signed off by the approvers
+    println!("\nRequesting an approved withdrawal of 30.0 BTC from savings...");
+    system
+        .set_withdrawal_approvers("savings", &["alice", "bob"])
+        .unwrap();
+    let request = system
+        .request_withdrawal(
+            "savings",
+            "treasurer",
+            amount!(30.0),
+            Some("0x1111111111111111"),
+        )
+        .unwrap();
+    for approver in ["alice", "bob"] {
+        let status = system.approve_withdrawal(request, approver).unwrap();
+        println!("✓ Approved by {}: {:?}", approver, status);
+    }
+    system.deposit("operations", amount!(30.0)).unwrap();
+    println!("✓ Funds arrived at operations");
+    print_balances(&system);
+
     // Final summary
     println!("\n=== Final Summary ===");
     println!("Total system balance: {} BTC", system.get_total_balance());
@@ -0,0 +1,316 @@
+//! Client accounts and organizations.
+//!
+//! A custodian serving many clients groups their wallets under an
+//! [`Account`], and accounts under an [`Organization`], so balances and
+//! activity can be reported per client rather than only across the whole
+//! book. A wallet belongs to at most one account; the assignment is kept
+//! on the wallet and persists with it. Accounts and organizations carry
+//! free-form labels (segment, region, tier, ...) for filtering.
+
+use crate::{Amount, Asset, CustodyError, CustodySystem, Transaction, Wallet};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// A client organization owning one or more accounts
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Organization {
+    pub id: String,
+    pub name: String,
+    pub labels: BTreeSet<String>,
+}
+
+/// A client account grouping wallets
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Account {
+    pub id: String,
+    pub name: String,
+    /// Organization the account belongs to, if any
+    pub organization: Option<String>,
+    pub labels: BTreeSet<String>,
+}
+
+impl CustodySystem {
+    /// Registers an organization
+    pub fn create_organization(&mut self, id: &str, name: &str) -> Result<(), CustodyError> {
+        if self.organizations.contains_key(id) {
+            return Err(CustodyError::OrganizationAlreadyExists(id.to_string()));
+        }
+        self.organizations.insert(
+            id.to_string(),
+            Organization {
+                id: id.to_string(),
+                name: name.to_string(),
+                labels: BTreeSet::new(),
+            },
+        );
+        Ok(())
+    }
+
+    /// Registers an account, optionally within an existing organization
+    pub fn create_account(
+        &mut self,
+        id: &str,
+        name: &str,
+        organization: Option<&str>,
+    ) -> Result<(), CustodyError> {
+        if self.accounts.contains_key(id) {
+            return Err(CustodyError::AccountAlreadyExists(id.to_string()));
+        }
+        if let Some(organization) = organization {
+            self.get_organization(organization)?;
+        }
+        self.accounts.insert(
+            id.to_string(),
+            Account {
+                id: id.to_string(),
+                name: name.to_string(),
+                organization: organization.map(str::to_string),
+                labels: BTreeSet::new(),
+            },
+        );
+        Ok(())
+    }
+
+    /// Gets an organization by id
+    pub fn get_organization(&self, id: &str) -> Result<&Organization, CustodyError> {
+        self.organizations
+            .get(id)
+            .ok_or_else(|| CustodyError::OrganizationNotFound(id.to_string()))
+    }
+
+    /// Gets an account by id
+    pub fn get_account(&self, id: &str) -> Result<&Account, CustodyError> {
+        self.accounts
+            .get(id)
+            .ok_or_else(|| CustodyError::AccountNotFound(id.to_string()))
+    }
+
+    /// Adds a label to an account
+    pub fn label_account(&mut self, id: &str, label: &str) -> Result<(), CustodyError> {
+        let account = self
+            .accounts
+            .get_mut(id)
+            .ok_or_else(|| CustodyError::AccountNotFound(id.to_string()))?;
+        account.labels.insert(label.to_string());
+        Ok(())
+    }
+
+    /// Adds a label to an organization
+    pub fn label_organization(&mut self, id: &str, label: &str) -> Result<(), CustodyError> {
+        let organization = self
+            .organizations
+            .get_mut(id)
+            .ok_or_else(|| CustodyError::OrganizationNotFound(id.to_string()))?;
+        organization.labels.insert(label.to_string());
+        Ok(())
+    }
+
+    /// Lists the accounts carrying `label`, ordered by id
+    pub fn accounts_labeled(&self, label: &str) -> Vec<&Account> {
+        let mut accounts: Vec<_> = self
+            .accounts
+            .values()
+            .filter(|account| account.labels.contains(label))
+            .collect();
+        accounts.sort_by(|a, b| a.id.cmp(&b.id));
+        accounts
+    }
+
+    /// Places a wallet in an account, moving it out of any previous one
+    pub fn assign_wallet(&mut self, wallet_id: &str, account_id: &str) -> Result<(), CustodyError> {
+        self.get_account(account_id)?;
+        self.update_wallet_annotations(wallet_id, |wallet| {
+            wallet.account = Some(account_id.to_string());
+        })
+    }
+
+    /// Removes a wallet from its account
+    pub fn unassign_wallet(&mut self, wallet_id: &str) -> Result<(), CustodyError> {
+        self.update_wallet_annotations(wallet_id, |wallet| wallet.account = None)
+    }
+
+    /// Lists an account's wallets, ordered by id
+    pub fn get_account_wallets(&self, account_id: &str) -> Result<Vec<&Wallet>, CustodyError> {
+        self.get_account(account_id)?;
+        let mut wallets: Vec<_> = self
+            .wallets
+            .values()
+            .filter(|wallet| wallet.account.as_deref() == Some(account_id))
+            .collect();
+        wallets.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(wallets)
+    }
+
+    /// Sums an account's balances per asset
+    ///
+    /// # Example
+    /// ```
+    /// use securevault::{amount, Asset, CustodySystem, WalletType};
+    /// let mut system = CustodySystem::new();
+    /// system.create_organization("acme", "Acme Corp").unwrap();
+    /// system.create_account("acme-treasury", "Treasury", Some("acme")).unwrap();
+    /// for id in ["hot", "cold"] {
+    ///     system.create_wallet(id.to_string(), format!("bc1q{}", id), WalletType::Hot).unwrap();
+    ///     system.deposit(id, amount!(2)).unwrap();
+    ///     system.assign_wallet(id, "acme-treasury").unwrap();
+    /// }
+    ///
+    /// let balance = system.get_account_balance("acme-treasury").unwrap();
+    /// assert_eq!(balance[&Asset::Btc], amount!(4));
+    /// ```
+    pub fn get_account_balance(
+        &self,
+        account_id: &str,
+    ) -> Result<BTreeMap<Asset, Amount>, CustodyError> {
+        sum_balances(self.get_account_wallets(account_id)?)
+    }
+
+    /// Lists the transactions touching an account's wallets, oldest first
+    pub fn get_account_transactions(
+        &self,
+        account_id: &str,
+    ) -> Result<Vec<&Transaction>, CustodyError> {
+        let wallets: BTreeSet<_> = self
+            .get_account_wallets(account_id)?
+            .into_iter()
+            .map(|wallet| wallet.id.as_str())
+            .collect();
+        Ok(self
+            .transactions
+            .iter()
+            .filter(|tx| wallets.iter().any(|id| tx.involves(id)))
+            .collect())
+    }
+
+    /// Lists an organization's accounts, ordered by id
+    pub fn get_organization_accounts(
+        &self,
+        organization_id: &str,
+    ) -> Result<Vec<&Account>, CustodyError> {
+        self.get_organization(organization_id)?;
+        let mut accounts: Vec<_> = self
+            .accounts
+            .values()
+            .filter(|account| account.organization.as_deref() == Some(organization_id))
+            .collect();
+        accounts.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(accounts)
+    }
+
+    /// Sums the balances of every account in an organization per asset
+    pub fn get_organization_balance(
+        &self,
+        organization_id: &str,
+    ) -> Result<BTreeMap<Asset, Amount>, CustodyError> {
+        let mut wallets = Vec::new();
+        for account in self.get_organization_accounts(organization_id)? {
+            wallets.extend(self.get_account_wallets(&account.id)?);
+        }
+        sum_balances(wallets)
+    }
+}
+
+fn sum_balances<'a>(
+    wallets: impl IntoIterator<Item = &'a Wallet>,
+) -> Result<BTreeMap<Asset, Amount>, CustodyError> {
+    let mut totals: BTreeMap<Asset, Amount> = BTreeMap::new();
+    for wallet in wallets {
+        let balances = std::iter::once((&wallet.asset, &wallet.balance)).chain(&wallet.holdings);
+        for (asset, balance) in balances {
+            let total = totals.entry(asset.clone()).or_default();
+            *total = total
+                .checked_add(*balance)
+                .ok_or(CustodyError::AmountOverflow)?;
+        }
+    }
+    Ok(totals)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::WalletType;
+
+    fn system() -> CustodySystem {
+        let mut system = CustodySystem::new();
+        system.create_organization("acme", "Acme Corp").unwrap();
+        system
+            .create_account("ops", "Operations", Some("acme"))
+            .unwrap();
+        system
+            .create_account("payroll", "Payroll", Some("acme"))
+            .unwrap();
+        system
+            .create_account("solo", "Retail client", None)
+            .unwrap();
+        for (id, account) in [("a", "ops"), ("b", "payroll"), ("c", "solo")] {
+            system
+                .create_wallet(id.to_string(), id.to_string(), WalletType::Hot)
+                .unwrap();
+            system.deposit(id, amount!(10)).unwrap();
+            system.assign_wallet(id, account).unwrap();
+        }
+        system
+    }
+
+    #[test]
+    fn test_balances_and_transactions_per_account() {
+        let mut system = system();
+        system.transfer("a", "c", amount!(4)).unwrap();
+        assert_eq!(
+            system.get_account_balance("ops").unwrap(),
+            BTreeMap::from([(Asset::Btc, amount!(6))])
+        );
+        assert_eq!(system.get_account_transactions("solo").unwrap().len(), 2);
+        assert_eq!(
+            system.get_organization_balance("acme").unwrap(),
+            BTreeMap::from([(Asset::Btc, amount!(16))])
+        );
+
+        system.unassign_wallet("a").unwrap();
+        assert!(system.get_account_wallets("ops").unwrap().is_empty());
+        assert!(system.get_account_balance("ops").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_hierarchy_and_labels() {
+        let mut system = system();
+        let accounts: Vec<_> = system
+            .get_organization_accounts("acme")
+            .unwrap()
+            .into_iter()
+            .map(|account| account.id.as_str())
+            .collect();
+        assert_eq!(accounts, ["ops", "payroll"]);
+
+        system.label_account("payroll", "tier-1").unwrap();
+        system.label_account("solo", "tier-1").unwrap();
+        assert_eq!(system.accounts_labeled("tier-1").len(), 2);
+        system.label_organization("acme", "institutional").unwrap();
+        assert!(system
+            .get_organization("acme")
+            .unwrap()
+            .labels
+            .contains("institutional"));
+    }
+
+    #[test]
+    fn test_unknown_ids_are_errors() {
+        let mut system = system();
+        assert_eq!(
+            system.create_account("ops", "Again", None),
+            Err(CustodyError::AccountAlreadyExists("ops".to_string()))
+        );
+        assert_eq!(
+            system.create_account("x", "X", Some("globex")),
+            Err(CustodyError::OrganizationNotFound("globex".to_string()))
+        );
+        assert_eq!(
+            system.assign_wallet("a", "missing"),
+            Err(CustodyError::AccountNotFound("missing".to_string()))
+        );
+        assert_eq!(
+            system.assign_wallet("missing", "ops"),
+            Err(CustodyError::WalletNotFound("missing".to_string()))
+        );
+    }
+}
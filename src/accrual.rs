@@ -0,0 +1,343 @@
+//! Interest and staking reward accrual.
+//!
+//! A wallet enrolled in a [`YieldProgram`] earns its annual rate on its
+//! balance in the wallet's primary asset, compounded on the program's
+//! schedule. [`CustodySystem::accrue`] credits every enrolled wallet for the
+//! compounding periods completed since it last accrued, each as one
+//! [`TransactionType::Reward`] transaction, so the log tells earnings apart
+//! from deposits and [`CustodySystem::yield_report`] can split a balance
+//! into principal and accrued rewards.
+
+use crate::time::Timestamp;
+use crate::{Amount, Asset, CustodyError, CustodySystem, Transaction, TransactionType};
+
+/// Seconds in the 365-day year rates are quoted over
+const SECS_PER_YEAR: u64 = 365 * 86_400;
+
+/// How often accrued rewards start earning themselves
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compounding {
+    Daily,
+    Weekly,
+    Monthly,
+    Annually,
+}
+
+impl Compounding {
+    /// Returns the number of compounding periods in a year
+    pub fn periods_per_year(self) -> u32 {
+        match self {
+            Compounding::Daily => 365,
+            Compounding::Weekly => 52,
+            Compounding::Monthly => 12,
+            Compounding::Annually => 1,
+        }
+    }
+
+    /// Returns the length of one period, a month being a twelfth of a year
+    pub fn period_secs(self) -> u64 {
+        SECS_PER_YEAR / u64::from(self.periods_per_year())
+    }
+}
+
+/// An annual rate and its compounding schedule
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct YieldProgram {
+    /// Nominal annual rate, e.g. `0.05` for 5%
+    pub annual_rate: f64,
+    pub compounding: Compounding,
+}
+
+impl YieldProgram {
+    /// Creates a program paying `annual_rate` compounded on `compounding`
+    pub fn new(annual_rate: f64, compounding: Compounding) -> Self {
+        Self {
+            annual_rate,
+            compounding,
+        }
+    }
+
+    /// Returns the fraction a balance grows by over `periods` periods
+    fn growth(&self, periods: u64) -> f64 {
+        let per_period = self.annual_rate / f64::from(self.compounding.periods_per_year());
+        (1.0 + per_period).powf(periods as f64) - 1.0
+    }
+}
+
+/// A wallet's place in a yield program
+#[derive(Debug, Clone, PartialEq)]
+pub struct YieldEnrollment {
+    pub program: YieldProgram,
+    pub enrolled_at: Timestamp,
+    /// End of the last compounding period credited
+    pub accrued_through: Timestamp,
+}
+
+/// A wallet's balance split into principal and accrued rewards
+#[derive(Debug, Clone, PartialEq)]
+pub struct YieldReport {
+    pub wallet_id: String,
+    pub asset: Asset,
+    /// Balance not accounted for by rewards
+    pub principal: Amount,
+    /// Sum of the rewards credited to the wallet
+    pub accrued: Amount,
+    pub balance: Amount,
+}
+
+impl CustodySystem {
+    /// Enrolls a wallet in `program`, accruing from now
+    ///
+    /// Enrolling a wallet that is already enrolled switches it to the new
+    /// program and drops any partly completed period; call
+    /// [`accrue`](Self::accrue) first to credit what it has earned.
+    ///
+    /// # Errors
+    /// [`CustodyError::WalletNotFound`], and [`CustodyError::InvalidRate`]
+    /// if the rate is negative or not finite
+    ///
+    /// # Example
+    /// ```
+    /// use securevault::time::{ManualClock, Timestamp};
+    /// use securevault::{amount, Compounding, CustodySystem, WalletType, YieldProgram};
+    /// use std::sync::Arc;
+    ///
+    /// let clock = Arc::new(ManualClock::new(Timestamp::from_unix(1_700_000_000)));
+    /// let mut system = CustodySystem::new();
+    /// system.set_clock(clock.clone());
+    /// system.create_wallet("w".to_string(), "0x1".to_string(), WalletType::Hot).unwrap();
+    /// system.deposit("w", amount!(1000)).unwrap();
+    ///
+    /// system.enroll_in_yield("w", YieldProgram::new(0.12, Compounding::Monthly)).unwrap();
+    /// clock.advance(Compounding::Monthly.period_secs());
+    /// system.accrue(system.now()).unwrap();
+    ///
+    /// let report = system.yield_report("w").unwrap();
+    /// assert_eq!((report.principal, report.accrued), (amount!(1000), amount!(10)));
+    /// ```
+    pub fn enroll_in_yield(
+        &mut self,
+        wallet_id: &str,
+        program: YieldProgram,
+    ) -> Result<(), CustodyError> {
+        if !self.wallet_exists(wallet_id) {
+            return Err(CustodyError::WalletNotFound(wallet_id.to_string()));
+        }
+        if !program.annual_rate.is_finite() || program.annual_rate < 0.0 {
+            return Err(CustodyError::InvalidRate(program.annual_rate));
+        }
+        let now = self.current_timestamp();
+        self.yield_enrollments.insert(
+            wallet_id.to_string(),
+            YieldEnrollment {
+                program,
+                enrolled_at: now,
+                accrued_through: now,
+            },
+        );
+        Ok(())
+    }
+
+    /// Takes a wallet out of its yield program, forfeiting any partly
+    /// completed period, and returns its enrollment
+    pub fn leave_yield(&mut self, wallet_id: &str) -> Option<YieldEnrollment> {
+        self.yield_enrollments.remove(wallet_id)
+    }
+
+    /// Returns the wallet's enrollment, if it is in a yield program
+    pub fn yield_enrollment(&self, wallet_id: &str) -> Option<&YieldEnrollment> {
+        self.yield_enrollments.get(wallet_id)
+    }
+
+    /// Credits every enrolled wallet for the compounding periods completed
+    /// by `now`, returning the ids of the reward transactions
+    ///
+    /// Rewards are rounded to the asset's precision and recorded at the
+    /// current time. A frozen or closed wallet earns nothing for the
+    /// periods that end while it cannot be credited.
+    ///
+    /// # Errors
+    /// [`CustodyError::PeriodClosed`] if the current time falls in a closed
+    /// accounting period, and [`CustodyError::AmountOverflow`]; no reward is
+    /// posted then
+    pub fn accrue(&mut self, now: Timestamp) -> Result<Vec<u64>, CustodyError> {
+        if let Some(reason) = self.period_blockers() {
+            return Err(reason);
+        }
+
+        let mut wallet_ids: Vec<String> = self.yield_enrollments.keys().cloned().collect();
+        wallet_ids.sort();
+        let mut rewards = Vec::new();
+        for wallet_id in wallet_ids {
+            let enrollment = &self.yield_enrollments[&wallet_id];
+            let period_secs = enrollment.program.compounding.period_secs();
+            let periods = now
+                .as_unix()
+                .saturating_sub(enrollment.accrued_through.as_unix())
+                / period_secs;
+            if periods == 0 {
+                continue;
+            }
+            let accrued_through =
+                Timestamp::from_unix(enrollment.accrued_through.as_unix() + periods * period_secs);
+            let growth = enrollment.program.growth(periods);
+            let Some(wallet) = self.wallets.get(&wallet_id) else {
+                continue;
+            };
+            let reward = if self.credit_blockers(wallet).is_empty() {
+                wallet
+                    .balance
+                    .checked_mul_f64(growth)
+                    .ok_or(CustodyError::AmountOverflow)?
+                    .round_dp(wallet.asset.decimals())
+            } else {
+                Amount::ZERO
+            };
+            wallet
+                .balance
+                .checked_add(reward)
+                .ok_or(CustodyError::AmountOverflow)?;
+            rewards.push((wallet_id, wallet.asset.clone(), reward, accrued_through));
+        }
+
+        let mut ids = Vec::new();
+        for (wallet_id, asset, reward, accrued_through) in rewards {
+            if reward.is_positive() {
+                let tx = Transaction::new(
+                    &wallet_id,
+                    TransactionType::Reward,
+                    reward,
+                    asset,
+                    self.current_timestamp(),
+                );
+                self.record_transaction(tx)?;
+                ids.push(self.transactions.last().expect("just recorded").id);
+            }
+            if let Some(enrollment) = self.yield_enrollments.get_mut(&wallet_id) {
+                enrollment.accrued_through = accrued_through;
+            }
+        }
+        Ok(ids)
+    }
+
+    /// Splits an enrolled wallet's balance into principal and the rewards
+    /// credited to it
+    pub fn yield_report(&self, wallet_id: &str) -> Option<YieldReport> {
+        self.yield_enrollments.get(wallet_id)?;
+        let wallet = self.wallets.get(wallet_id)?;
+        let accrued: Amount = self
+            .transactions
+            .iter()
+            .filter(|tx| tx.transaction_type == TransactionType::Reward && tx.asset == wallet.asset)
+            .map(|tx| tx.balance_change(wallet_id))
+            .sum();
+        Some(YieldReport {
+            wallet_id: wallet_id.to_string(),
+            asset: wallet.asset.clone(),
+            principal: wallet.balance - accrued,
+            accrued,
+            balance: wallet.balance,
+        })
+    }
+
+    /// Returns the reports of every enrolled wallet, ordered by wallet id
+    pub fn yield_reports(&self) -> Vec<YieldReport> {
+        let mut reports: Vec<YieldReport> = self
+            .yield_enrollments
+            .keys()
+            .filter_map(|wallet_id| self.yield_report(wallet_id))
+            .collect();
+        reports.sort_by(|a, b| a.wallet_id.cmp(&b.wallet_id));
+        reports
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::time::ManualClock;
+    use crate::WalletType;
+    use std::sync::Arc;
+
+    const DAY: u64 = 86_400;
+
+    fn system() -> (CustodySystem, Arc<ManualClock>) {
+        let clock = Arc::new(ManualClock::new(Timestamp::from_unix(1_700_000_000)));
+        let mut system = CustodySystem::new();
+        system.set_clock(clock.clone());
+        for id in ["a", "b"] {
+            system
+                .create_wallet(id.to_string(), id.to_string(), WalletType::Hot)
+                .unwrap();
+            system.deposit(id, amount!(1000)).unwrap();
+        }
+        (system, clock)
+    }
+
+    #[test]
+    fn test_rewards_compound() {
+        let (mut system, clock) = system();
+        system
+            .enroll_in_yield("a", YieldProgram::new(0.365, Compounding::Daily))
+            .unwrap();
+        // Partial periods earn nothing yet
+        clock.advance(DAY - 1);
+        assert_eq!(system.accrue(system.now()), Ok(Vec::new()));
+
+        clock.advance(DAY + 1);
+        let ids = system.accrue(system.now()).unwrap();
+        assert_eq!(ids.len(), 1);
+        let reward = system.get_transaction(ids[0]).unwrap();
+        assert_eq!(reward.transaction_type, TransactionType::Reward);
+        // 0.1% a day, twice: 1000 × (1.001² − 1)
+        assert_eq!(reward.amount, amount!(2.001));
+        assert_eq!(system.get_wallet("a").unwrap().balance, amount!(1002.001));
+        assert_eq!(system.get_wallet("b").unwrap().balance, amount!(1000));
+        // Already credited through today
+        assert_eq!(system.accrue(system.now()), Ok(Vec::new()));
+
+        assert!(matches!(
+            system.enroll_in_yield("b", YieldProgram::new(-0.1, Compounding::Daily)),
+            Err(CustodyError::InvalidRate(_))
+        ));
+        assert_eq!(
+            system.enroll_in_yield("x", YieldProgram::new(0.1, Compounding::Daily)),
+            Err(CustodyError::WalletNotFound("x".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_report_separates_principal() {
+        let (mut system, clock) = system();
+        let program = YieldProgram::new(0.12, Compounding::Monthly);
+        system.enroll_in_yield("a", program).unwrap();
+        system.enroll_in_yield("b", program).unwrap();
+        system.freeze_wallet("b", "review").unwrap();
+
+        clock.advance(Compounding::Monthly.period_secs());
+        system.accrue(system.now()).unwrap();
+        system.deposit("a", amount!(500)).unwrap();
+        system.withdraw("a", amount!(100)).unwrap();
+
+        let reports = system.yield_reports();
+        assert_eq!(
+            reports[0],
+            YieldReport {
+                wallet_id: "a".to_string(),
+                asset: Asset::default(),
+                principal: amount!(1400),
+                accrued: amount!(10),
+                balance: amount!(1410),
+            }
+        );
+        // The frozen wallet earned nothing for the period
+        assert_eq!(reports[1].accrued, Amount::ZERO);
+        assert_eq!(
+            system.yield_enrollment("b").unwrap().accrued_through,
+            system.yield_enrollment("a").unwrap().accrued_through
+        );
+
+        assert!(system.leave_yield("a").is_some());
+        assert_eq!(system.yield_report("a"), None);
+    }
+}
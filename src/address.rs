@@ -0,0 +1,579 @@
+//! Address validation per blockchain.
+//!
+//! A wallet created with [`CustodySystem::create_wallet_on_chain`] records
+//! the [`Chain`] its address lives on, and the address must pass that
+//! chain's [`AddressValidator`]. Withdrawal destinations from such a
+//! wallet are checked the same way. Wallets created without a chain are not
+//! validated.
+//!
+//! Validators are registered per chain. With the `bitcoin` feature,
+//! [`BitcoinAddressValidator`] (base58check and bech32/bech32m) is
+//! registered for [`Chain::Bitcoin`]; with the `ethereum` feature,
+//! [`EthereumAddressValidator`] (EIP-55 checksums) is registered for
+//! [`Chain::Ethereum`]. Other chains plug in their own.
+
+use crate::{Asset, CustodyError, CustodySystem, Wallet, WalletType};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::Arc;
+
+/// A blockchain wallet addresses belong to
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Chain {
+    Bitcoin,
+    Ethereum,
+    /// Any other chain, by name
+    Other(String),
+}
+
+impl Chain {
+    /// Returns the lowercase chain name
+    pub fn name(&self) -> &str {
+        match self {
+            Chain::Bitcoin => "bitcoin",
+            Chain::Ethereum => "ethereum",
+            Chain::Other(name) => name,
+        }
+    }
+}
+
+impl fmt::Display for Chain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Checks that addresses are well-formed for one chain
+pub trait AddressValidator: fmt::Debug + Send + Sync {
+    /// Returns why `address` is invalid, if it is
+    fn validate(&self, address: &str) -> Result<(), String>;
+}
+
+impl CustodySystem {
+    /// Registers the validator for `chain`, replacing any previous one
+    pub fn register_address_validator(
+        &mut self,
+        chain: Chain,
+        validator: Arc<dyn AddressValidator>,
+    ) {
+        self.address_validators.insert(chain, validator);
+    }
+
+    /// Validates an address with the validator registered for `chain`
+    pub fn validate_address(&self, chain: &Chain, address: &str) -> Result<(), CustodyError> {
+        let validator = self
+            .address_validators
+            .get(chain)
+            .ok_or_else(|| CustodyError::NoAddressValidator(chain.to_string()))?;
+        validator
+            .validate(address)
+            .map_err(|reason| CustodyError::InvalidAddress {
+                chain: chain.to_string(),
+                address: address.to_string(),
+                reason,
+            })
+    }
+
+    /// Creates a wallet on `chain`, rejecting addresses the chain's
+    /// validator does not accept
+    ///
+    /// # Example
+    /// ```
+    /// # #[cfg(feature = "ethereum")] {
+    /// use securevault::{Asset, Chain, CustodySystem, WalletType};
+    /// let mut system = CustodySystem::new();
+    /// let address = "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed";
+    /// system
+    ///     .create_wallet_on_chain("eth".to_string(), address.to_string(), WalletType::Hot, Chain::Ethereum, Asset::Eth)
+    ///     .unwrap();
+    ///
+    /// let mistyped = "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAeD";
+    /// assert!(system
+    ///     .create_wallet_on_chain("eth2".to_string(), mistyped.to_string(), WalletType::Hot, Chain::Ethereum, Asset::Eth)
+    ///     .is_err());
+    /// # }
+    /// ```
+    pub fn create_wallet_on_chain(
+        &mut self,
+        id: String,
+        address: String,
+        wallet_type: WalletType,
+        chain: Chain,
+        asset: Asset,
+    ) -> Result<Wallet, CustodyError> {
+        if self.wallet_id_taken(&id) {
+            return Err(CustodyError::WalletAlreadyExists(id));
+        }
+        self.validate_address(&chain, &address)?;
+        self.insert_wallet(id, address, wallet_type, asset, Some(chain))
+    }
+
+    /// Returns the validators registered out of the box
+    pub(crate) fn builtin_validators() -> im::HashMap<Chain, Arc<dyn AddressValidator>> {
+        #[allow(unused_mut)]
+        let mut validators = im::HashMap::new();
+        #[cfg(feature = "bitcoin")]
+        validators.insert(
+            Chain::Bitcoin,
+            Arc::new(BitcoinAddressValidator::mainnet()) as Arc<dyn AddressValidator>,
+        );
+        #[cfg(feature = "ethereum")]
+        validators.insert(
+            Chain::Ethereum,
+            Arc::new(EthereumAddressValidator) as Arc<dyn AddressValidator>,
+        );
+        validators
+    }
+
+    /// Checks a withdrawal destination against the wallet's chain
+    pub(crate) fn destination_blockers(
+        &self,
+        wallet: &Wallet,
+        destination: Option<&str>,
+    ) -> Option<CustodyError> {
+        let chain = wallet.chain.as_ref()?;
+        let destination = destination.filter(|d| !d.trim().is_empty())?;
+        self.validate_address(chain, destination).err()
+    }
+}
+
+/// Bitcoin addresses: base58check P2PKH/P2SH and bech32/bech32m segwit
+#[cfg(feature = "bitcoin")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BitcoinAddressValidator {
+    hrp: &'static str,
+    p2pkh: u8,
+    p2sh: u8,
+}
+
+#[cfg(feature = "bitcoin")]
+impl BitcoinAddressValidator {
+    /// Validates mainnet addresses (`1...`, `3...`, `bc1...`)
+    pub fn mainnet() -> Self {
+        Self {
+            hrp: "bc",
+            p2pkh: 0x00,
+            p2sh: 0x05,
+        }
+    }
+
+    /// Validates testnet addresses (`m...`, `n...`, `2...`, `tb1...`)
+    pub fn testnet() -> Self {
+        Self {
+            hrp: "tb",
+            p2pkh: 0x6f,
+            p2sh: 0xc4,
+        }
+    }
+
+    /// Returns the output script paying to `address`
+    pub(crate) fn script_pubkey(&self, address: &str) -> Result<Vec<u8>, String> {
+        let separator = format!("{}1", self.hrp);
+        if address.len() > separator.len()
+            && address[..separator.len()].eq_ignore_ascii_case(&separator)
+        {
+            self.segwit_script(address)
+        } else {
+            self.base58_script(address)
+        }
+    }
+
+    /// Returns the segwit address of witness `program` at `version`
+    #[cfg(feature = "psbt")]
+    pub(crate) fn segwit_address(&self, version: u8, program: &[u8]) -> String {
+        bitcoin::bech32_encode(self.hrp, version, program)
+    }
+
+    fn base58_script(&self, address: &str) -> Result<Vec<u8>, String> {
+        use sha2::{Digest, Sha256};
+
+        let bytes = bitcoin::base58_decode(address)?;
+        if bytes.len() != 25 {
+            return Err(format!("decodes to {} bytes instead of 25", bytes.len()));
+        }
+        let (payload, checksum) = bytes.split_at(21);
+        if &Sha256::digest(Sha256::digest(payload))[..4] != checksum {
+            return Err("checksum mismatch".to_string());
+        }
+        let hash = &payload[1..];
+        if payload[0] == self.p2pkh {
+            // OP_DUP OP_HASH160 <hash> OP_EQUALVERIFY OP_CHECKSIG
+            Ok([&[0x76, 0xa9, 0x14], hash, &[0x88, 0xac]].concat())
+        } else if payload[0] == self.p2sh {
+            // OP_HASH160 <hash> OP_EQUAL
+            Ok([&[0xa9, 0x14], hash, &[0x87]].concat())
+        } else {
+            Err(format!("unexpected version byte {:#04x}", payload[0]))
+        }
+    }
+
+    fn segwit_script(&self, address: &str) -> Result<Vec<u8>, String> {
+        let (hrp, data, variant) = bitcoin::bech32_decode(address)?;
+        if hrp != self.hrp {
+            return Err(format!("unexpected prefix '{}'", hrp));
+        }
+        let (&version, program) = data.split_first().ok_or("missing witness version")?;
+        if version > 16 {
+            return Err(format!("invalid witness version {}", version));
+        }
+        let expected = if version == 0 {
+            bitcoin::Variant::Bech32
+        } else {
+            bitcoin::Variant::Bech32m
+        };
+        if variant != expected {
+            return Err("wrong checksum variant for the witness version".to_string());
+        }
+        let program = bitcoin::convert_bits(program)?;
+        if !(2..=40).contains(&program.len()) || version == 0 && ![20, 32].contains(&program.len())
+        {
+            return Err(format!("invalid witness program length {}", program.len()));
+        }
+        // OP_0 or OP_1..OP_16, then the program
+        let version = if version == 0 { 0 } else { 0x50 + version };
+        Ok([&[version, program.len() as u8], program.as_slice()].concat())
+    }
+}
+
+#[cfg(feature = "bitcoin")]
+impl Default for BitcoinAddressValidator {
+    fn default() -> Self {
+        Self::mainnet()
+    }
+}
+
+#[cfg(feature = "bitcoin")]
+impl AddressValidator for BitcoinAddressValidator {
+    fn validate(&self, address: &str) -> Result<(), String> {
+        self.script_pubkey(address).map(|_| ())
+    }
+}
+
+/// Base58 and bech32 decoding (BIP-173, BIP-350)
+#[cfg(feature = "bitcoin")]
+mod bitcoin {
+    const BASE58: &[u8] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+    const BECH32: &[u8] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+    #[derive(Debug, PartialEq, Eq)]
+    pub(super) enum Variant {
+        Bech32,
+        Bech32m,
+    }
+
+    pub(super) fn base58_decode(input: &str) -> Result<Vec<u8>, String> {
+        let mut bytes: Vec<u8> = Vec::new();
+        for c in input.bytes() {
+            let mut carry = BASE58
+                .iter()
+                .position(|&b| b == c)
+                .ok_or_else(|| format!("invalid base58 character '{}'", c as char))?;
+            for byte in bytes.iter_mut().rev() {
+                carry += *byte as usize * 58;
+                *byte = carry as u8;
+                carry >>= 8;
+            }
+            while carry > 0 {
+                bytes.insert(0, carry as u8);
+                carry >>= 8;
+            }
+        }
+        let zeros = input.bytes().take_while(|&c| c == b'1').count();
+        let mut decoded = vec![0; zeros];
+        decoded.extend(bytes);
+        Ok(decoded)
+    }
+
+    fn polymod(values: impl Iterator<Item = u8>) -> u32 {
+        const GENERATOR: [u32; 5] = [
+            0x3b6a_57b2,
+            0x2650_8e6d,
+            0x1ea1_19fa,
+            0x3d42_33dd,
+            0x2a14_62b3,
+        ];
+        let mut chk: u32 = 1;
+        for value in values {
+            let top = chk >> 25;
+            chk = (chk & 0x01ff_ffff) << 5 ^ u32::from(value);
+            for (i, generator) in GENERATOR.iter().enumerate() {
+                if (top >> i) & 1 == 1 {
+                    chk ^= generator;
+                }
+            }
+        }
+        chk
+    }
+
+    fn hrp_expand(hrp: &str) -> impl Iterator<Item = u8> + '_ {
+        hrp.bytes()
+            .map(|c| c >> 5)
+            .chain(std::iter::once(0))
+            .chain(hrp.bytes().map(|c| c & 31))
+    }
+
+    /// Returns the human-readable part, the data without its checksum, and
+    /// the checksum variant
+    pub(super) fn bech32_decode(input: &str) -> Result<(String, Vec<u8>, Variant), String> {
+        if input.len() > 90 {
+            return Err("longer than 90 characters".to_string());
+        }
+        if input.chars().any(|c| c.is_ascii_lowercase())
+            && input.chars().any(|c| c.is_ascii_uppercase())
+        {
+            return Err("mixed case".to_string());
+        }
+        let input = input.to_ascii_lowercase();
+        let (hrp, data) = input.rsplit_once('1').ok_or("missing separator")?;
+        if hrp.is_empty() || data.len() < 6 {
+            return Err("too short".to_string());
+        }
+        let data = data
+            .bytes()
+            .map(|c| {
+                BECH32
+                    .iter()
+                    .position(|&b| b == c)
+                    .map(|value| value as u8)
+                    .ok_or_else(|| format!("invalid bech32 character '{}'", c as char))
+            })
+            .collect::<Result<Vec<u8>, String>>()?;
+
+        let variant = match polymod(hrp_expand(hrp).chain(data.iter().copied())) {
+            1 => Variant::Bech32,
+            0x2bc8_30a3 => Variant::Bech32m,
+            _ => return Err("checksum mismatch".to_string()),
+        };
+        Ok((hrp.to_string(), data[..data.len() - 6].to_vec(), variant))
+    }
+
+    /// Encodes a segwit address: bech32 for version 0, bech32m above
+    #[cfg(feature = "psbt")]
+    pub(super) fn bech32_encode(hrp: &str, version: u8, program: &[u8]) -> String {
+        let mut data = vec![version];
+        let mut acc: u32 = 0;
+        let mut bits = 0;
+        for &byte in program {
+            acc = acc << 8 | u32::from(byte);
+            bits += 8;
+            while bits >= 5 {
+                bits -= 5;
+                data.push(((acc >> bits) & 31) as u8);
+            }
+        }
+        if bits > 0 {
+            data.push(((acc << (5 - bits)) & 31) as u8);
+        }
+        let constant = if version == 0 { 1 } else { 0x2bc8_30a3 };
+        let checksum =
+            polymod(hrp_expand(hrp).chain(data.iter().copied()).chain([0; 6])) ^ constant;
+        data.extend((0..6).map(|i| ((checksum >> (5 * (5 - i))) & 31) as u8));
+
+        let mut address = format!("{}1", hrp);
+        address.extend(data.iter().map(|&value| char::from(BECH32[value as usize])));
+        address
+    }
+
+    /// Regroups 5-bit values into bytes, rejecting non-zero padding
+    pub(super) fn convert_bits(data: &[u8]) -> Result<Vec<u8>, String> {
+        let mut acc: u32 = 0;
+        let mut bits = 0;
+        let mut out = Vec::new();
+        for &value in data {
+            acc = acc << 5 | u32::from(value);
+            bits += 5;
+            if bits >= 8 {
+                bits -= 8;
+                out.push((acc >> bits) as u8);
+            }
+        }
+        if bits >= 5 || (acc << (8 - bits)) & 0xff != 0 {
+            return Err("invalid padding".to_string());
+        }
+        Ok(out)
+    }
+}
+
+/// Ethereum addresses: `0x` and 40 hex digits, with an EIP-55 checksum
+/// when mixed case
+#[cfg(feature = "ethereum")]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EthereumAddressValidator;
+
+#[cfg(feature = "ethereum")]
+impl AddressValidator for EthereumAddressValidator {
+    fn validate(&self, address: &str) -> Result<(), String> {
+        use sha3::{Digest, Keccak256};
+
+        let hex = address.strip_prefix("0x").ok_or("missing 0x prefix")?;
+        if hex.len() != 40 || !hex.bytes().all(|c| c.is_ascii_hexdigit()) {
+            return Err("expected 40 hex digits".to_string());
+        }
+        // All-lowercase and all-uppercase addresses carry no checksum
+        if !hex.bytes().any(|c| c.is_ascii_lowercase())
+            || !hex.bytes().any(|c| c.is_ascii_uppercase())
+        {
+            return Ok(());
+        }
+        let hash = Keccak256::digest(hex.to_ascii_lowercase().as_bytes());
+        for (i, c) in hex.bytes().enumerate() {
+            let nibble = (hash[i / 2] >> if i % 2 == 0 { 4 } else { 0 }) & 0x0f;
+            if c.is_ascii_alphabetic() && c.is_ascii_uppercase() != (nibble >= 8) {
+                return Err("EIP-55 checksum mismatch".to_string());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Accepts addresses starting with its prefix
+    #[derive(Debug)]
+    struct Prefixed(&'static str);
+
+    impl AddressValidator for Prefixed {
+        fn validate(&self, address: &str) -> Result<(), String> {
+            if address.starts_with(self.0) {
+                Ok(())
+            } else {
+                Err(format!("must start with {}", self.0))
+            }
+        }
+    }
+
+    fn solana() -> Chain {
+        Chain::Other("solana".to_string())
+    }
+
+    #[test]
+    fn test_custom_chain_validator() {
+        let mut system = CustodySystem::new();
+        let sol = Asset::Custom {
+            symbol: "SOL".to_string(),
+            decimals: 9,
+        };
+        assert_eq!(
+            system.create_wallet_on_chain(
+                "s".to_string(),
+                "So1".to_string(),
+                WalletType::Hot,
+                solana(),
+                sol.clone()
+            ),
+            Err(CustodyError::NoAddressValidator("solana".to_string()))
+        );
+
+        system.register_address_validator(solana(), Arc::new(Prefixed("So")));
+        let wallet = system
+            .create_wallet_on_chain(
+                "s".to_string(),
+                "So1".to_string(),
+                WalletType::Hot,
+                solana(),
+                sol.clone(),
+            )
+            .unwrap();
+        assert_eq!(wallet.chain, Some(solana()));
+        assert_eq!(
+            system.create_wallet_on_chain(
+                "t".to_string(),
+                "x".to_string(),
+                WalletType::Hot,
+                solana(),
+                sol
+            ),
+            Err(CustodyError::InvalidAddress {
+                chain: "solana".to_string(),
+                address: "x".to_string(),
+                reason: "must start with So".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_destinations_are_validated() {
+        let mut system = CustodySystem::new();
+        system.register_address_validator(solana(), Arc::new(Prefixed("So")));
+        system
+            .create_wallet_on_chain(
+                "s".to_string(),
+                "So1".to_string(),
+                WalletType::Hot,
+                solana(),
+                Asset::default(),
+            )
+            .unwrap();
+        system.deposit("s", amount!(5)).unwrap();
+        assert!(system
+            .can_withdraw("s", amount!(1), Some("So2"))
+            .is_allowed());
+        assert!(!system
+            .can_withdraw("s", amount!(1), Some("bc1"))
+            .is_allowed());
+
+        // Wallets without a chain take any destination
+        system
+            .create_wallet("w".to_string(), "0x1".to_string(), WalletType::Hot)
+            .unwrap();
+        system.deposit("w", amount!(5)).unwrap();
+        assert!(system
+            .can_withdraw("w", amount!(1), Some("bc1"))
+            .is_allowed());
+    }
+
+    #[cfg(feature = "bitcoin")]
+    #[test]
+    fn test_bitcoin_addresses() {
+        let mainnet = BitcoinAddressValidator::mainnet();
+        for valid in [
+            "1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN2",
+            "3J98t1WpEZ73CNmQviecrnyiWrnqRhWNLy",
+            "bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq",
+            "BC1QAR0SRRR7XFKVY5L643LYDNW9RE59GTZZWF5MDQ",
+            "bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqzk5jj0",
+        ] {
+            assert_eq!(mainnet.validate(valid), Ok(()), "{}", valid);
+        }
+        for invalid in [
+            "1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN3",
+            "bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdx",
+            "bc1Qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq",
+            // Segwit v1 with a bech32 rather than bech32m checksum
+            "bc1pw508d6qejxtdg4y5r3zarvary0c5xw7kw508d6qejxtdg4y5r3zarvary0c5xw7k7grplx",
+            "0OIl",
+        ] {
+            assert!(mainnet.validate(invalid).is_err(), "{}", invalid);
+        }
+        assert!(BitcoinAddressValidator::testnet()
+            .validate("bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq")
+            .is_err());
+        assert!(BitcoinAddressValidator::testnet()
+            .validate("tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx")
+            .is_ok());
+    }
+
+    #[cfg(feature = "ethereum")]
+    #[test]
+    fn test_ethereum_addresses() {
+        let validator = EthereumAddressValidator;
+        for valid in [
+            "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed",
+            "0xfB6916095ca1df60bB79Ce92cE3Ea74c37c5d359",
+            "0x52908400098527886e0f7030069857d2e4169ee7",
+            "0x8617E340B3D01FA5F11F306F4090FD50E238070D",
+        ] {
+            assert_eq!(validator.validate(valid), Ok(()), "{}", valid);
+        }
+        for invalid in [
+            "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAeD",
+            "5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed",
+            "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeA",
+            "0xZZAeb6053F3E94C9b9A09f33669435E7Ef1BeAed",
+        ] {
+            assert!(validator.validate(invalid).is_err(), "{}", invalid);
+        }
+    }
+}
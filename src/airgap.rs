@@ -0,0 +1,509 @@
+//! Air-gapped signing transport over BC-UR (feature `airgap`).
+//!
+//! Unsigned withdrawal payloads are encoded as Uniform Resources
+//! (`ur:<type>/...`, Blockchain Commons BCR-2020-005) split into parts that
+//! an offline signer can read from an animated QR sequence with its camera.
+//! The signer answers with a signed response in the same format.
+//!
+//! Parts are bytewords-encoded with the minimal (two letter) style and carry
+//! the CBOR part header from the multi-part UR spec. The encoder emits the
+//! simple fragments in a loop; the decoder accepts simple fragments only,
+//! which every conforming encoder emits first.
+
+use crate::precheck::Authorization;
+use crate::{Amount, CustodyError, CustodySystem};
+
+/// UR type of unsigned withdrawal requests
+pub const WITHDRAWAL_UR_TYPE: &str = "securevault-withdrawal";
+
+/// UR type of signed withdrawal responses
+pub const SIGNED_UR_TYPE: &str = "securevault-signed";
+
+/// The 256 bytewords, four letters each, in byte order
+const BYTEWORDS: &str = "ableacidalsoapexaquaarchatomauntawayaxisbackbaldbarnbeltbetabiasbluebodybragbrewbulbbuzzcalmcashcatschefcityclawcodecolacookcostcruxcurlcuspcyandarkdatadaysdelidicedietdoordowndrawdropdrumdulldutyeacheasyechoedgeepicevenexamexiteyesfactfairfernfigsfilmfishfizzflapflewfluxfoxyfreefrogfuelfundgalagamegeargemsgiftgirlglowgoodgraygrimgurugushgyrohalfhanghardhawkheathelphighhillholyhopehornhutsicedideaidleinchinkyintoirisironitemjadejazzjoinjoltjowljudojugsjumpjunkjurykeepkenokeptkeyskickkilnkingkitekiwiknoblamblavalazyleaflegsliarlimplionlistlogoloudloveluaulucklungmainmanymathmazememomenumeowmildmintmissmonknailnavyneednewsnextnoonnotenumbobeyoboeomitonyxopenovalowlspaidpartpeckplaypluspoempoolposepuffpumapurrquadquizraceramprealredorichroadrockroofrubyruinrunsrustsafesagascarsetssilkskewslotsoapsolosongstubsurfswantacotasktaxitenttiedtimetinytoiltombtoystriptunatwinuglyundouniturgeuservastveryvetovialvibeviewvisavoidvowswallwandwarmwaspwavewaxywebswhatwhenwhizwolfworkyankyawnyellyogayurtzapszerozestzinczonezoom";
+
+/// A withdrawal awaiting an offline signature
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnsignedWithdrawal {
+    /// Unique per request, so a signature cannot be replayed
+    pub nonce: u64,
+    pub wallet_id: String,
+    pub asset: String,
+    pub destination: String,
+    pub amount: Amount,
+}
+
+impl UnsignedWithdrawal {
+    /// Canonical byte encoding signed by the offline signer
+    pub fn to_bytes(&self) -> Vec<u8> {
+        format!(
+            "v1\nnonce={}\nwallet={}\nasset={}\ndestination={}\namount={}\n",
+            self.nonce, self.wallet_id, self.asset, self.destination, self.amount
+        )
+        .into_bytes()
+    }
+
+    /// Parses the output of [`to_bytes`](Self::to_bytes)
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, CustodyError> {
+        let invalid = || invalid_ur("malformed withdrawal payload");
+        let text = std::str::from_utf8(bytes).map_err(|_| invalid())?;
+        let mut lines = text.lines();
+        if lines.next() != Some("v1") {
+            return Err(invalid());
+        }
+        let mut field = |name: &str| -> Result<String, CustodyError> {
+            lines
+                .next()
+                .and_then(|line| line.strip_prefix(name))
+                .and_then(|rest| rest.strip_prefix('='))
+                .map(str::to_string)
+                .ok_or_else(invalid)
+        };
+        Ok(Self {
+            nonce: field("nonce")?.parse().map_err(|_| invalid())?,
+            wallet_id: field("wallet")?,
+            asset: field("asset")?,
+            destination: field("destination")?,
+            amount: field("amount")?.parse().map_err(|_| invalid())?,
+        })
+    }
+
+    /// Encodes the request as UR parts of at most `max_fragment_len` payload
+    /// bytes each, ready to display as an animated QR sequence
+    pub fn to_ur_parts(&self, max_fragment_len: usize) -> Vec<String> {
+        let mut message = Vec::new();
+        cbor_bytes(&mut message, &self.to_bytes());
+        encode_ur(WITHDRAWAL_UR_TYPE, &message, max_fragment_len)
+    }
+}
+
+/// An offline signer's answer to an [`UnsignedWithdrawal`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct SignedWithdrawal {
+    pub request: UnsignedWithdrawal,
+    pub signature: Vec<u8>,
+}
+
+impl SignedWithdrawal {
+    /// Encodes the response as UR parts, as an offline signer would
+    pub fn to_ur_parts(&self, max_fragment_len: usize) -> Vec<String> {
+        let mut message = vec![0x82];
+        cbor_bytes(&mut message, &self.request.to_bytes());
+        cbor_bytes(&mut message, &self.signature);
+        encode_ur(SIGNED_UR_TYPE, &message, max_fragment_len)
+    }
+
+    /// Decodes a response assembled by a [`UrDecoder`]
+    pub fn from_ur(ur_type: &str, message: &[u8]) -> Result<Self, CustodyError> {
+        if ur_type != SIGNED_UR_TYPE {
+            return Err(invalid_ur("unexpected UR type"));
+        }
+        let mut reader = Cbor(message);
+        if reader.header(4)? != 2 {
+            return Err(invalid_ur("signed response must have two elements"));
+        }
+        let request = UnsignedWithdrawal::from_bytes(reader.bytes()?)?;
+        let signature = reader.bytes()?.to_vec();
+        Ok(Self { request, signature })
+    }
+}
+
+/// Encodes `message` (CBOR) as one or more UR strings
+///
+/// A message that fits in one fragment yields a single-part UR; otherwise
+/// one part per fragment is returned, to be shown in a loop.
+pub fn encode_ur(ur_type: &str, message: &[u8], max_fragment_len: usize) -> Vec<String> {
+    let max_fragment_len = max_fragment_len.max(1);
+    if message.len() <= max_fragment_len {
+        return vec![format!("ur:{}/{}", ur_type, bytewords(message))];
+    }
+
+    let count = message.len().div_ceil(max_fragment_len);
+    let fragment_len = message.len().div_ceil(count);
+    let checksum = crc32fast::hash(message);
+    (0..count)
+        .map(|i| {
+            let start = i * fragment_len;
+            let mut fragment = message[start..message.len().min(start + fragment_len)].to_vec();
+            fragment.resize(fragment_len, 0);
+
+            let mut part = vec![0x85];
+            cbor_uint(&mut part, 0, (i + 1) as u64);
+            cbor_uint(&mut part, 0, count as u64);
+            cbor_uint(&mut part, 0, message.len() as u64);
+            cbor_uint(&mut part, 0, checksum as u64);
+            cbor_bytes(&mut part, &fragment);
+            format!("ur:{}/{}-{}/{}", ur_type, i + 1, count, bytewords(&part))
+        })
+        .collect()
+}
+
+/// Reassembles a UR from parts scanned in any order, with repeats
+#[derive(Debug, Default)]
+pub struct UrDecoder {
+    ur_type: Option<String>,
+    /// (part count, message length, checksum) from the first part seen
+    header: Option<(usize, usize, u32)>,
+    fragments: Vec<Option<Vec<u8>>>,
+    message: Option<Vec<u8>>,
+}
+
+impl UrDecoder {
+    /// Creates an empty decoder
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feeds one scanned part (case-insensitive)
+    pub fn receive(&mut self, part: &str) -> Result<(), CustodyError> {
+        let part = part.trim().to_ascii_lowercase();
+        let rest = part
+            .strip_prefix("ur:")
+            .ok_or_else(|| invalid_ur("missing ur: scheme"))?;
+        let segments: Vec<&str> = rest.split('/').collect();
+        let (ur_type, body) = match segments.as_slice() {
+            [ur_type, body] => (*ur_type, *body),
+            [ur_type, _, body] => (*ur_type, *body),
+            _ => return Err(invalid_ur("malformed UR path")),
+        };
+        if self.ur_type.get_or_insert_with(|| ur_type.to_string()) != ur_type {
+            return Err(invalid_ur("part belongs to a different UR type"));
+        }
+        let data = from_bytewords(body)?;
+
+        if segments.len() == 2 {
+            self.message = Some(data);
+            return Ok(());
+        }
+
+        let mut reader = Cbor(&data);
+        if reader.header(4)? != 5 {
+            return Err(invalid_ur("malformed part header"));
+        }
+        let seq = reader.header(0)? as usize;
+        let count = reader.header(0)? as usize;
+        let message_len = reader.header(0)? as usize;
+        let checksum = reader.header(0)? as u32;
+        let fragment = reader.bytes()?.to_vec();
+        if seq > count {
+            return Err(invalid_ur("mixed fountain parts are not supported"));
+        }
+        if count == 0 || seq == 0 {
+            return Err(invalid_ur("malformed part header"));
+        }
+        match self.header {
+            None => {
+                self.header = Some((count, message_len, checksum));
+                self.fragments = vec![None; count];
+            }
+            Some(header) if header != (count, message_len, checksum) => {
+                return Err(invalid_ur("part belongs to a different message"));
+            }
+            Some(_) => {}
+        }
+        self.fragments[seq - 1] = Some(fragment);
+
+        if self.fragments.iter().all(Option::is_some) {
+            let mut message: Vec<u8> = self.fragments.iter().flatten().flatten().copied().collect();
+            message.truncate(message_len);
+            if crc32fast::hash(&message) != checksum {
+                return Err(invalid_ur("message checksum mismatch"));
+            }
+            self.message = Some(message);
+        }
+        Ok(())
+    }
+
+    /// Fraction of parts received, from 0.0 to 1.0
+    pub fn progress(&self) -> f64 {
+        if self.message.is_some() {
+            return 1.0;
+        }
+        if self.fragments.is_empty() {
+            return 0.0;
+        }
+        let received = self.fragments.iter().filter(|f| f.is_some()).count();
+        received as f64 / self.fragments.len() as f64
+    }
+
+    /// Returns the UR type and CBOR message once every part was received
+    pub fn result(&self) -> Option<(&str, &[u8])> {
+        Some((self.ur_type.as_deref()?, self.message.as_deref()?))
+    }
+}
+
+impl CustodySystem {
+    /// Checks a withdrawal and prepares it for offline signing
+    ///
+    /// Nothing is debited until the signed response is submitted with
+    /// [`complete_airgap_withdrawal`](Self::complete_airgap_withdrawal).
+    pub fn prepare_airgap_withdrawal(
+        &mut self,
+        wallet_id: &str,
+        destination: &str,
+        amount: Amount,
+    ) -> Result<UnsignedWithdrawal, CustodyError> {
+        if let Some(reason) = self
+            .withdrawal_blockers(
+                wallet_id,
+                None,
+                amount,
+                Some(destination),
+                Authorization::Direct,
+            )
+            .into_iter()
+            .next()
+        {
+            return Err(reason);
+        }
+        let asset = self
+            .get_wallet(wallet_id)
+            .expect("checked by withdrawal_blockers")
+            .asset
+            .symbol()
+            .to_string();
+        Ok(UnsignedWithdrawal {
+            nonce: self.allocate_operation_id(),
+            wallet_id: wallet_id.to_string(),
+            asset,
+            destination: destination.to_string(),
+            amount,
+        })
+    }
+
+    /// Books a withdrawal once its signed response came back
+    ///
+    /// The response must answer exactly `request`; the signature itself is
+    /// checked by the chain integration that broadcasts it.
+    pub fn complete_airgap_withdrawal(
+        &mut self,
+        request: &UnsignedWithdrawal,
+        signed: &SignedWithdrawal,
+    ) -> Result<(), CustodyError> {
+        if &signed.request != request {
+            return Err(invalid_ur("signed response does not match the request"));
+        }
+        if signed.signature.is_empty() {
+            return Err(invalid_ur("signed response has no signature"));
+        }
+        self.execute_withdrawal(
+            &request.wallet_id,
+            None,
+            request.amount,
+            Some(&request.destination),
+            Authorization::Direct,
+        )
+    }
+}
+
+fn invalid_ur(reason: &str) -> CustodyError {
+    CustodyError::InvalidUr(reason.to_string())
+}
+
+fn bytewords(data: &[u8]) -> String {
+    let checksum = crc32fast::hash(data).to_be_bytes();
+    data.iter()
+        .chain(checksum.iter())
+        .flat_map(|byte| {
+            let word = &BYTEWORDS.as_bytes()[*byte as usize * 4..*byte as usize * 4 + 4];
+            [word[0] as char, word[3] as char]
+        })
+        .collect()
+}
+
+fn from_bytewords(text: &str) -> Result<Vec<u8>, CustodyError> {
+    let letters = text.as_bytes();
+    if !letters.len().is_multiple_of(2) || letters.len() < 10 {
+        return Err(invalid_ur("bytewords body too short"));
+    }
+    let mut data = letters
+        .chunks(2)
+        .map(|pair| {
+            (0..256)
+                .find(|i| {
+                    let word = &BYTEWORDS.as_bytes()[i * 4..i * 4 + 4];
+                    word[0] == pair[0] && word[3] == pair[1]
+                })
+                .map(|i| i as u8)
+                .ok_or_else(|| invalid_ur("invalid byteword"))
+        })
+        .collect::<Result<Vec<u8>, _>>()?;
+    let checksum = data.split_off(data.len() - 4);
+    if crc32fast::hash(&data).to_be_bytes() != checksum.as_slice() {
+        return Err(invalid_ur("bytewords checksum mismatch"));
+    }
+    Ok(data)
+}
+
+fn cbor_uint(out: &mut Vec<u8>, major: u8, value: u64) {
+    let major = major << 5;
+    match value {
+        0..=23 => out.push(major | value as u8),
+        24..=0xff => out.extend([major | 24, value as u8]),
+        0x100..=0xffff => {
+            out.push(major | 25);
+            out.extend((value as u16).to_be_bytes());
+        }
+        0x1_0000..=0xffff_ffff => {
+            out.push(major | 26);
+            out.extend((value as u32).to_be_bytes());
+        }
+        _ => {
+            out.push(major | 27);
+            out.extend(value.to_be_bytes());
+        }
+    }
+}
+
+fn cbor_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    cbor_uint(out, 2, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+/// Minimal CBOR reader for the unsigned ints, byte strings, and arrays
+/// used by UR parts
+struct Cbor<'a>(&'a [u8]);
+
+impl<'a> Cbor<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], CustodyError> {
+        if self.0.len() < n {
+            return Err(invalid_ur("truncated CBOR"));
+        }
+        let (head, tail) = self.0.split_at(n);
+        self.0 = tail;
+        Ok(head)
+    }
+
+    /// Reads an item header of the given major type, returning its argument
+    fn header(&mut self, major: u8) -> Result<u64, CustodyError> {
+        let initial = self.take(1)?[0];
+        if initial >> 5 != major {
+            return Err(invalid_ur("unexpected CBOR type"));
+        }
+        let size = match initial & 0x1f {
+            n @ 0..=23 => return Ok(n as u64),
+            24 => 1,
+            25 => 2,
+            26 => 4,
+            27 => 8,
+            _ => return Err(invalid_ur("unsupported CBOR encoding")),
+        };
+        Ok(self
+            .take(size)?
+            .iter()
+            .fold(0u64, |acc, byte| (acc << 8) | *byte as u64))
+    }
+
+    fn bytes(&mut self) -> Result<&'a [u8], CustodyError> {
+        let len = self.header(2)? as usize;
+        self.take(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::WalletType;
+
+    fn system() -> CustodySystem {
+        let mut system = CustodySystem::new();
+        system
+            .create_wallet("hot".to_string(), "bc1qhot".to_string(), WalletType::Hot)
+            .unwrap();
+        system.deposit("hot", amount!(5.0)).unwrap();
+        system
+    }
+
+    fn decode(parts: &[String]) -> UrDecoder {
+        let mut decoder = UrDecoder::new();
+        for part in parts {
+            decoder.receive(part).unwrap();
+        }
+        decoder
+    }
+
+    #[test]
+    fn test_bytewords_round_trip() {
+        let data = [0u8, 1, 254, 255];
+        let encoded = bytewords(&data);
+        assert!(encoded.starts_with("aeadzezm"));
+        assert_eq!(from_bytewords(&encoded).unwrap(), data);
+        let mut corrupted = encoded.clone();
+        corrupted.replace_range(0..2, "ad");
+        assert!(from_bytewords(&corrupted).is_err());
+    }
+
+    #[test]
+    fn test_single_and_multi_part_round_trip() {
+        let mut system = system();
+        let request = system
+            .prepare_airgap_withdrawal("hot", "bc1qdest", amount!(1.25))
+            .unwrap();
+
+        let single = request.to_ur_parts(1000);
+        assert_eq!(single.len(), 1);
+        assert!(single[0].starts_with("ur:securevault-withdrawal/"));
+
+        let mut parts = request.to_ur_parts(20);
+        assert!(parts.len() > 1);
+        // Scanned out of order, uppercase, with a repeat
+        parts.reverse();
+        parts.push(parts[0].clone());
+        let upper: Vec<String> = parts.iter().map(|p| p.to_uppercase()).collect();
+        let decoder = decode(&upper);
+        assert_eq!(decoder.progress(), 1.0);
+
+        let (ur_type, message) = decoder.result().unwrap();
+        assert_eq!(ur_type, WITHDRAWAL_UR_TYPE);
+        let mut reader = Cbor(message);
+        assert_eq!(
+            UnsignedWithdrawal::from_bytes(reader.bytes().unwrap()).unwrap(),
+            request
+        );
+    }
+
+    #[test]
+    fn test_signed_response_completes_withdrawal() {
+        let mut system = system();
+        let request = system
+            .prepare_airgap_withdrawal("hot", "bc1qdest", amount!(2.0))
+            .unwrap();
+        assert_eq!(system.get_wallet("hot").unwrap().balance, amount!(5.0));
+
+        let response = SignedWithdrawal {
+            request: request.clone(),
+            signature: vec![0x30; 71],
+        };
+        let decoder = decode(&response.to_ur_parts(30));
+        let (ur_type, message) = decoder.result().unwrap();
+        let signed = SignedWithdrawal::from_ur(ur_type, message).unwrap();
+        assert_eq!(signed, response);
+
+        system
+            .complete_airgap_withdrawal(&request, &signed)
+            .unwrap();
+        assert_eq!(system.get_wallet("hot").unwrap().balance, amount!(3.0));
+    }
+
+    #[test]
+    fn test_mismatched_response_rejected() {
+        let mut system = system();
+        let request = system
+            .prepare_airgap_withdrawal("hot", "bc1qdest", amount!(2.0))
+            .unwrap();
+        let mut tampered = request.clone();
+        tampered.destination = "bc1qattacker".to_string();
+        let signed = SignedWithdrawal {
+            request: tampered,
+            signature: vec![1],
+        };
+        assert!(matches!(
+            system.complete_airgap_withdrawal(&request, &signed),
+            Err(CustodyError::InvalidUr(_))
+        ));
+        assert!(matches!(
+            system.prepare_airgap_withdrawal("hot", "bc1qdest", amount!(50.0)),
+            Err(CustodyError::InsufficientBalance { .. })
+        ));
+    }
+}
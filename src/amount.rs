@@ -0,0 +1,401 @@
+//! Fixed-point amounts.
+//!
+//! [`Amount`] is a signed decimal with 18 fractional digits stored as an
+//! `i128`, enough to represent every asset the ledger supports exactly
+//! (satoshis need 8 digits, wei 18) up to about 1.7 × 10²⁰ whole units.
+//! Addition and subtraction are exact; the ledger uses the checked
+//! variants and reports overflow as an error. The operator impls panic on
+//! overflow, like integer arithmetic in debug builds.
+//!
+//! Amounts serialize as decimal strings (`"2.5"`) so no precision is lost
+//! in JSON. Records written with floating-point amounts still deserialize.
+
+use serde::de::{self, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::iter::Sum;
+use std::ops::{Add, AddAssign, Neg, Sub, SubAssign};
+use std::str::FromStr;
+
+/// Builds an [`Amount`] from a decimal literal, exactly
+///
+/// ```
+/// use securevault::{amount, Amount};
+/// assert_eq!(amount!(0.1) + amount!(0.2), amount!(0.3));
+/// assert_eq!(amount!(-2), Amount::from_units(-2));
+/// ```
+#[macro_export]
+macro_rules! amount {
+    ($value:literal) => {
+        $crate::Amount::from_literal(stringify!($value))
+    };
+    (- $value:literal) => {
+        -$crate::Amount::from_literal(stringify!($value))
+    };
+}
+
+/// A fixed-point decimal amount with 18 fractional digits
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Amount(i128);
+
+/// Error returned when parsing an [`Amount`] from a string
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseAmountError {
+    /// The string is not a plain decimal number
+    Invalid(String),
+    /// The number has more fractional digits than [`Amount::DECIMALS`]
+    TooPrecise(String),
+    /// The number does not fit in an [`Amount`]
+    Overflow(String),
+}
+
+impl fmt::Display for ParseAmountError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseAmountError::Invalid(s) => write!(f, "invalid amount '{}'", s),
+            ParseAmountError::TooPrecise(s) => write!(
+                f,
+                "amount '{}' has more than {} decimal places",
+                s,
+                Amount::DECIMALS
+            ),
+            ParseAmountError::Overflow(s) => write!(f, "amount '{}' is out of range", s),
+        }
+    }
+}
+
+impl std::error::Error for ParseAmountError {}
+
+impl Amount {
+    /// Number of fractional digits
+    pub const DECIMALS: u8 = 18;
+    /// Raw units per whole unit
+    const SCALE: i128 = 1_000_000_000_000_000_000;
+
+    pub const ZERO: Amount = Amount(0);
+    pub const MAX: Amount = Amount(i128::MAX);
+    pub const MIN: Amount = Amount(i128::MIN);
+
+    /// Creates an amount of whole units
+    pub fn from_units(units: i64) -> Self {
+        Amount(i128::from(units) * Self::SCALE)
+    }
+
+    /// Creates an amount from integer minor units of an asset with
+    /// `decimals` places, e.g. satoshis with 8
+    ///
+    /// Returns `None` if `decimals` exceeds [`Amount::DECIMALS`] or the
+    /// result overflows.
+    pub fn from_minor_units(units: i128, decimals: u8) -> Option<Self> {
+        let factor = 10i128.checked_pow(u32::from(Self::DECIMALS.checked_sub(decimals)?))?;
+        units.checked_mul(factor).map(Amount)
+    }
+
+    /// Returns the amount in integer minor units of an asset with
+    /// `decimals` places, or `None` if it is not a whole number of them
+    pub fn to_minor_units(self, decimals: u8) -> Option<i128> {
+        let factor = 10i128.checked_pow(u32::from(Self::DECIMALS.checked_sub(decimals)?))?;
+        (self.0 % factor == 0).then_some(self.0 / factor)
+    }
+
+    /// Converts from a float, rounding to the nearest representable amount
+    ///
+    /// Returns `None` for NaN, infinities, and out-of-range values.
+    pub fn from_f64(value: f64) -> Option<Self> {
+        if !value.is_finite() {
+            return None;
+        }
+        // Go through the shortest decimal representation so that e.g. 0.1
+        // becomes exactly 0.1 rather than the nearest binary fraction
+        match value.to_string().parse() {
+            Err(ParseAmountError::TooPrecise(_)) => format!("{:.18}", value).parse().ok(),
+            parsed => parsed.ok(),
+        }
+    }
+
+    /// Converts to a float, for valuation and display where exactness is
+    /// not required
+    pub fn to_f64(self) -> f64 {
+        let whole = (self.0 / Self::SCALE) as f64;
+        let fraction = (self.0 % Self::SCALE) as f64 / Self::SCALE as f64;
+        whole + fraction
+    }
+
+    /// Parses a macro literal; panics on invalid input
+    #[doc(hidden)]
+    pub fn from_literal(literal: &str) -> Self {
+        let cleaned: String = literal.chars().filter(|c| *c != '_').collect();
+        match cleaned.parse() {
+            Ok(amount) => amount,
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    pub fn is_zero(self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn is_positive(self) -> bool {
+        self.0 > 0
+    }
+
+    pub fn is_negative(self) -> bool {
+        self.0 < 0
+    }
+
+    /// Returns the absolute value, saturating at [`Amount::MAX`]
+    pub fn abs(self) -> Self {
+        Amount(self.0.saturating_abs())
+    }
+
+    pub fn checked_add(self, other: Amount) -> Option<Self> {
+        self.0.checked_add(other.0).map(Amount)
+    }
+
+    pub fn checked_sub(self, other: Amount) -> Option<Self> {
+        self.0.checked_sub(other.0).map(Amount)
+    }
+
+    /// Multiplies by a floating-point factor such as an exchange rate or
+    /// fee rate, rounding to the nearest representable amount
+    ///
+    /// Exact for amounts up to about 16 significant digits; beyond that the
+    /// result carries the factor's floating-point error.
+    pub fn checked_mul_f64(self, factor: f64) -> Option<Self> {
+        let product = self.0 as f64 * factor;
+        if !product.is_finite() || product.abs() >= i128::MAX as f64 {
+            return None;
+        }
+        Some(Amount(product.round() as i128))
+    }
+
+    /// Rounds half away from zero to `decimals` fractional digits
+    pub fn round_dp(self, decimals: u8) -> Self {
+        let Some(places) = Self::DECIMALS.checked_sub(decimals) else {
+            return self;
+        };
+        let factor = 10i128.pow(u32::from(places));
+        let remainder = self.0 % factor;
+        let truncated = self.0 - remainder;
+        if remainder.abs() * 2 >= factor {
+            Amount(truncated.saturating_add(factor * self.0.signum()))
+        } else {
+            Amount(truncated)
+        }
+    }
+}
+
+impl fmt::Display for Amount {
+    /// Writes the amount as a plain decimal. Without a precision, trailing
+    /// zeros are dropped; with one (`{:.8}`) the amount is rounded and
+    /// padded to exactly that many places.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = match f.precision() {
+            Some(places) => self.round_dp(places.min(usize::from(Self::DECIMALS)) as u8),
+            None => *self,
+        };
+        let sign = if value.0 < 0 { "-" } else { "" };
+        let raw = value.0.unsigned_abs();
+        let scale = Self::SCALE as u128;
+        let whole = raw / scale;
+        let fraction = format!("{:018}", raw % scale);
+        let fraction = match f.precision() {
+            Some(places) if places <= fraction.len() => fraction[..places].to_string(),
+            Some(places) => format!("{:0<width$}", fraction, width = places),
+            None => fraction.trim_end_matches('0').to_string(),
+        };
+        let text = if fraction.is_empty() {
+            format!("{}{}", sign, whole)
+        } else {
+            format!("{}{}.{}", sign, whole, fraction)
+        };
+        f.pad_integral(true, "", &text)
+    }
+}
+
+impl FromStr for Amount {
+    type Err = ParseAmountError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || ParseAmountError::Invalid(s.to_string());
+        let trimmed = s.trim();
+        let (negative, digits) = match trimmed.strip_prefix('-') {
+            Some(rest) => (true, rest.trim_start()),
+            None => (false, trimmed.strip_prefix('+').unwrap_or(trimmed)),
+        };
+        let (whole, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+        if (whole.is_empty() && fraction.is_empty())
+            || !whole.bytes().all(|b| b.is_ascii_digit())
+            || !fraction.bytes().all(|b| b.is_ascii_digit())
+        {
+            return Err(invalid());
+        }
+        let fraction = fraction.trim_end_matches('0');
+        if fraction.len() > usize::from(Self::DECIMALS) {
+            return Err(ParseAmountError::TooPrecise(s.to_string()));
+        }
+
+        let overflow = || ParseAmountError::Overflow(s.to_string());
+        let whole: i128 = if whole.is_empty() {
+            0
+        } else {
+            whole.parse().map_err(|_| overflow())?
+        };
+        let fraction: i128 = format!("{:0<18}", fraction)
+            .parse()
+            .map_err(|_| invalid())?;
+        let raw = whole
+            .checked_mul(Self::SCALE)
+            .and_then(|w| w.checked_add(fraction))
+            .ok_or_else(overflow)?;
+        Ok(Amount(if negative { -raw } else { raw }))
+    }
+}
+
+impl Add for Amount {
+    type Output = Amount;
+
+    fn add(self, other: Amount) -> Amount {
+        self.checked_add(other).expect("amount overflow")
+    }
+}
+
+impl Sub for Amount {
+    type Output = Amount;
+
+    fn sub(self, other: Amount) -> Amount {
+        self.checked_sub(other).expect("amount overflow")
+    }
+}
+
+impl Neg for Amount {
+    type Output = Amount;
+
+    fn neg(self) -> Amount {
+        Amount(self.0.checked_neg().expect("amount overflow"))
+    }
+}
+
+impl AddAssign for Amount {
+    fn add_assign(&mut self, other: Amount) {
+        *self = *self + other;
+    }
+}
+
+impl SubAssign for Amount {
+    fn sub_assign(&mut self, other: Amount) {
+        *self = *self - other;
+    }
+}
+
+impl Sum for Amount {
+    fn sum<I: Iterator<Item = Amount>>(iter: I) -> Amount {
+        iter.fold(Amount::ZERO, Add::add)
+    }
+}
+
+impl<'a> Sum<&'a Amount> for Amount {
+    fn sum<I: Iterator<Item = &'a Amount>>(iter: I) -> Amount {
+        iter.copied().sum()
+    }
+}
+
+impl Serialize for Amount {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Amount {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct AmountVisitor;
+
+        impl Visitor<'_> for AmountVisitor {
+            type Value = Amount;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("a decimal string or number")
+            }
+
+            fn visit_str<E: de::Error>(self, value: &str) -> Result<Amount, E> {
+                value.parse().map_err(E::custom)
+            }
+
+            fn visit_i64<E: de::Error>(self, value: i64) -> Result<Amount, E> {
+                Ok(Amount::from_units(value))
+            }
+
+            fn visit_u64<E: de::Error>(self, value: u64) -> Result<Amount, E> {
+                Amount::from_minor_units(i128::from(value), 0)
+                    .ok_or_else(|| E::custom("amount out of range"))
+            }
+
+            fn visit_f64<E: de::Error>(self, value: f64) -> Result<Amount, E> {
+                Amount::from_f64(value).ok_or_else(|| E::custom("amount out of range"))
+            }
+        }
+
+        deserializer.deserialize_any(AmountVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_display() {
+        assert_eq!("2.5".parse::<Amount>().unwrap().to_string(), "2.5");
+        assert_eq!(
+            "-0.00000001".parse::<Amount>().unwrap().to_string(),
+            "-0.00000001"
+        );
+        assert_eq!("10.000".parse::<Amount>().unwrap().to_string(), "10");
+        assert_eq!(format!("{:.3}", amount!(1.23456)), "1.235");
+        assert_eq!(format!("{:.2}", amount!(7)), "7.00");
+        assert!(matches!(
+            "1.0000000000000000001".parse::<Amount>(),
+            Err(ParseAmountError::TooPrecise(_))
+        ));
+        assert!("1e5".parse::<Amount>().is_err());
+        assert!(".".parse::<Amount>().is_err());
+    }
+
+    #[test]
+    fn test_arithmetic_is_exact() {
+        let total: Amount = std::iter::repeat_n(amount!(0.1), 10).sum();
+        assert_eq!(total, Amount::from_units(1));
+        assert_eq!(amount!(0.3) - amount!(0.1), amount!(0.2));
+        assert_eq!(Amount::MAX.checked_add(amount!(0.000000000000000001)), None);
+        assert_eq!(-amount!(1.5), amount!(-1.5));
+    }
+
+    #[test]
+    fn test_minor_units_and_floats() {
+        let btc = Amount::from_minor_units(150_000_000, 8).unwrap();
+        assert_eq!(btc, amount!(1.5));
+        assert_eq!(btc.to_minor_units(8), Some(150_000_000));
+        assert_eq!(amount!(0.000000001).to_minor_units(8), None);
+        assert_eq!(amount!(1).to_minor_units(18), Some(10i128.pow(18)));
+
+        assert_eq!(Amount::from_f64(0.1), Some(amount!(0.1)));
+        assert_eq!(Amount::from_f64(f64::NAN), None);
+        assert_eq!(amount!(2.5).to_f64(), 2.5);
+        assert_eq!(amount!(3).checked_mul_f64(0.5), Some(amount!(1.5)));
+    }
+
+    #[test]
+    fn test_serde() {
+        let json = serde_json::to_string(&amount!(1.25)).unwrap();
+        assert_eq!(json, "\"1.25\"");
+        assert_eq!(
+            serde_json::from_str::<Amount>("1.25").unwrap(),
+            amount!(1.25)
+        );
+        assert_eq!(serde_json::from_str::<Amount>("3").unwrap(), amount!(3));
+        assert_eq!(
+            serde_json::from_str::<Amount>(&json).unwrap(),
+            amount!(1.25)
+        );
+    }
+}
@@ -0,0 +1,466 @@
+//! Scoped API keys for integrations.
+//!
+//! An [`ApiKey`] grants a [`Permission`], read-only, deposit-only or full,
+//! on every wallet or on a named set of wallets, optionally until an
+//! expiry time. [`CustodySystem::create_api_key`] returns the key's
+//! bearer token once; only its SHA-256 hash is kept, so a lost token is
+//! replaced rather than recovered. Revoked and expired keys stay listed for
+//! the record but no longer authenticate.
+//!
+//! [`CustodySystem::with_key`] wraps the system in a [`KeyedSystem`] that
+//! checks every call against the key's scope; the gRPC service does the
+//! same for the token a client presents once keys are required. Mutating
+//! calls made either way count against the operator rate limit under the
+//! key's id.
+
+use crate::digest::sha256_hex;
+use crate::time::Timestamp;
+use crate::{Amount, CustodyError, CustodySystem, Transaction, Wallet};
+use std::collections::BTreeSet;
+use uuid::Uuid;
+
+/// Prefix of every API key token
+pub const API_KEY_PREFIX: &str = "svk_";
+
+/// What a key may do on the wallets in its scope
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Permission {
+    /// Read wallets and transactions
+    ReadOnly,
+    /// Read and deposit
+    DepositOnly,
+    /// Every operation, including withdrawals and wallet management
+    Full,
+}
+
+/// An operation checked against a key's scope
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ApiOperation {
+    Read,
+    Deposit,
+    Withdraw,
+    Transfer,
+    /// Creating wallets, changing their status and deciding approvals
+    Manage,
+}
+
+impl ApiOperation {
+    /// Returns the operation's name, as used in error messages
+    pub fn name(&self) -> &'static str {
+        match self {
+            ApiOperation::Read => "read",
+            ApiOperation::Deposit => "deposit",
+            ApiOperation::Withdraw => "withdraw",
+            ApiOperation::Transfer => "transfer",
+            ApiOperation::Manage => "manage",
+        }
+    }
+}
+
+impl Permission {
+    /// Returns true if the permission covers `operation`
+    pub fn allows(&self, operation: ApiOperation) -> bool {
+        match self {
+            Permission::ReadOnly => operation == ApiOperation::Read,
+            Permission::DepositOnly => {
+                matches!(operation, ApiOperation::Read | ApiOperation::Deposit)
+            }
+            Permission::Full => true,
+        }
+    }
+}
+
+/// The permission a key grants and the wallets it applies to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyScope {
+    pub permission: Permission,
+    /// Wallets the key may act on; `None` for every wallet
+    pub wallets: Option<BTreeSet<String>>,
+}
+
+impl KeyScope {
+    /// Read-only access to every wallet
+    pub fn read_only() -> Self {
+        Self::new(Permission::ReadOnly)
+    }
+
+    /// Read and deposit access to every wallet
+    pub fn deposit_only() -> Self {
+        Self::new(Permission::DepositOnly)
+    }
+
+    /// Full access to every wallet
+    pub fn full() -> Self {
+        Self::new(Permission::Full)
+    }
+
+    fn new(permission: Permission) -> Self {
+        Self {
+            permission,
+            wallets: None,
+        }
+    }
+
+    /// Restricts the scope to `wallet_id` and any other wallets it was
+    /// restricted to
+    pub fn with_wallet(mut self, wallet_id: &str) -> Self {
+        self.wallets
+            .get_or_insert_with(BTreeSet::new)
+            .insert(wallet_id.to_string());
+        self
+    }
+
+    /// Returns true if the scope applies to `wallet_id`
+    pub fn covers(&self, wallet_id: &str) -> bool {
+        self.wallets
+            .as_ref()
+            .is_none_or(|wallets| wallets.contains(wallet_id))
+    }
+}
+
+/// An issued API key; the token itself is not kept
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApiKey {
+    pub id: String,
+    pub label: String,
+    pub scope: KeyScope,
+    pub created_at: Timestamp,
+    /// The key stops authenticating at this time
+    pub expires_at: Option<Timestamp>,
+    pub revoked_at: Option<Timestamp>,
+    token_hash: String,
+}
+
+impl ApiKey {
+    /// Returns true if the key authenticates at `now`
+    pub fn is_active(&self, now: Timestamp) -> bool {
+        self.revoked_at.is_none() && self.expires_at.is_none_or(|expiry| now < expiry)
+    }
+}
+
+impl CustodySystem {
+    /// Issues a key granting `scope`, valid for `ttl_secs` seconds or until
+    /// revoked
+    ///
+    /// # Returns
+    /// The key and its bearer token, which cannot be retrieved later
+    ///
+    /// # Errors
+    /// [`CustodyError::WalletNotFound`] if the scope names a wallet that
+    /// does not exist
+    ///
+    /// # Example
+    /// ```
+    /// use securevault::{amount, CustodyError, CustodySystem, KeyScope, WalletType};
+    /// let mut system = CustodySystem::new();
+    /// system.create_wallet("w".to_string(), "0x1".to_string(), WalletType::Hot).unwrap();
+    /// let (_, token) = system
+    ///     .create_api_key("payments", KeyScope::deposit_only().with_wallet("w"), None)
+    ///     .unwrap();
+    ///
+    /// let mut keyed = system.with_key(&token).unwrap();
+    /// keyed.deposit("w", amount!(5)).unwrap();
+    /// assert!(matches!(
+    ///     keyed.withdraw("w", amount!(1)),
+    ///     Err(CustodyError::ScopeDenied { .. })
+    /// ));
+    /// ```
+    pub fn create_api_key(
+        &mut self,
+        label: &str,
+        scope: KeyScope,
+        ttl_secs: Option<u64>,
+    ) -> Result<(ApiKey, String), CustodyError> {
+        if let Some(missing) = scope
+            .wallets
+            .iter()
+            .flatten()
+            .find(|id| !self.wallet_exists(id))
+        {
+            return Err(CustodyError::WalletNotFound(missing.clone()));
+        }
+        let id = Uuid::new_v4().simple().to_string()[..12].to_string();
+        let token = format!("{}{}.{}", API_KEY_PREFIX, id, Uuid::new_v4().simple());
+        let created_at = self.current_timestamp();
+        let key = ApiKey {
+            id: id.clone(),
+            label: label.to_string(),
+            scope,
+            created_at,
+            expires_at: ttl_secs
+                .map(|ttl| Timestamp::from_unix(created_at.as_unix().saturating_add(ttl))),
+            revoked_at: None,
+            token_hash: sha256_hex(token.as_bytes()),
+        };
+        self.api_keys.insert(id, key.clone());
+        Ok((key, token))
+    }
+
+    /// Revokes a key; it stays listed but no longer authenticates
+    pub fn revoke_api_key(&mut self, key_id: &str) -> Result<(), CustodyError> {
+        let now = self.current_timestamp();
+        let key = self
+            .api_keys
+            .get_mut(key_id)
+            .ok_or_else(|| CustodyError::ApiKeyNotFound(key_id.to_string()))?;
+        key.revoked_at.get_or_insert(now);
+        Ok(())
+    }
+
+    /// Returns a key by id
+    pub fn get_api_key(&self, key_id: &str) -> Option<&ApiKey> {
+        self.api_keys.get(key_id)
+    }
+
+    /// Lists every issued key, revoked and expired ones included, ordered
+    /// by id
+    pub fn api_keys(&self) -> Vec<&ApiKey> {
+        let mut keys: Vec<_> = self.api_keys.values().collect();
+        keys.sort_by(|a, b| a.id.cmp(&b.id));
+        keys
+    }
+
+    /// Returns the active key a bearer token belongs to
+    ///
+    /// # Errors
+    /// [`CustodyError::InvalidApiKey`] for an unknown token, and
+    /// [`CustodyError::ApiKeyRevoked`] or [`CustodyError::ApiKeyExpired`]
+    /// for a key that no longer authenticates
+    pub fn authenticate(&self, token: &str) -> Result<&ApiKey, CustodyError> {
+        let key = token
+            .strip_prefix(API_KEY_PREFIX)
+            .and_then(|rest| rest.split_once('.'))
+            .and_then(|(id, _)| self.api_keys.get(id))
+            .filter(|key| key.token_hash == sha256_hex(token.as_bytes()))
+            .ok_or(CustodyError::InvalidApiKey)?;
+        self.check_key(key)?;
+        Ok(key)
+    }
+
+    /// Checks that the key holding `token` may perform `operation` on
+    /// every wallet in `wallet_ids`
+    pub fn authorize(
+        &self,
+        token: &str,
+        operation: ApiOperation,
+        wallet_ids: &[&str],
+    ) -> Result<&ApiKey, CustodyError> {
+        let key = self.authenticate(token)?;
+        check_scope(key, operation, wallet_ids)?;
+        Ok(key)
+    }
+
+    /// Wraps the system so every call is checked against the scope of the
+    /// key holding `token`
+    pub fn with_key(&mut self, token: &str) -> Result<KeyedSystem<'_>, CustodyError> {
+        let key_id = self.authenticate(token)?.id.clone();
+        Ok(KeyedSystem {
+            system: self,
+            key_id,
+        })
+    }
+
+    fn check_key(&self, key: &ApiKey) -> Result<(), CustodyError> {
+        if key.revoked_at.is_some() {
+            return Err(CustodyError::ApiKeyRevoked(key.id.clone()));
+        }
+        if !key.is_active(self.current_timestamp()) {
+            return Err(CustodyError::ApiKeyExpired(key.id.clone()));
+        }
+        Ok(())
+    }
+}
+
+/// Fails with [`CustodyError::ScopeDenied`] unless `key` may perform
+/// `operation` on every wallet in `wallet_ids`
+pub(crate) fn check_scope(
+    key: &ApiKey,
+    operation: ApiOperation,
+    wallet_ids: &[&str],
+) -> Result<(), CustodyError> {
+    if key.scope.permission.allows(operation) && wallet_ids.iter().all(|id| key.scope.covers(id)) {
+        Ok(())
+    } else {
+        Err(CustodyError::ScopeDenied {
+            key_id: key.id.clone(),
+            operation: operation.name().to_string(),
+        })
+    }
+}
+
+/// A custody system seen through an API key
+///
+/// The key is checked again on every call, so revoking it or letting it
+/// expire takes effect at once.
+pub struct KeyedSystem<'a> {
+    system: &'a mut CustodySystem,
+    key_id: String,
+}
+
+impl KeyedSystem<'_> {
+    /// Returns the key the calls are made with
+    pub fn key(&self) -> &ApiKey {
+        &self.system.api_keys[&self.key_id]
+    }
+
+    /// Returns a wallet in the key's scope
+    pub fn get_wallet(&self, wallet_id: &str) -> Result<&Wallet, CustodyError> {
+        self.check(ApiOperation::Read, &[wallet_id])?;
+        self.system
+            .get_wallet(wallet_id)
+            .ok_or_else(|| CustodyError::WalletNotFound(wallet_id.to_string()))
+    }
+
+    /// Returns the transactions of a wallet in the key's scope
+    pub fn get_wallet_transactions(
+        &self,
+        wallet_id: &str,
+    ) -> Result<Vec<&Transaction>, CustodyError> {
+        self.check(ApiOperation::Read, &[wallet_id])?;
+        Ok(self.system.get_wallet_transactions(wallet_id))
+    }
+
+    pub fn deposit(&mut self, wallet_id: &str, amount: Amount) -> Result<(), CustodyError> {
+        self.check_mutation(ApiOperation::Deposit, &[wallet_id])?;
+        self.system.deposit(wallet_id, amount)
+    }
+
+    pub fn withdraw(&mut self, wallet_id: &str, amount: Amount) -> Result<(), CustodyError> {
+        self.check_mutation(ApiOperation::Withdraw, &[wallet_id])?;
+        self.system.withdraw(wallet_id, amount)
+    }
+
+    pub fn withdraw_to(
+        &mut self,
+        wallet_id: &str,
+        amount: Amount,
+        destination: &str,
+    ) -> Result<(), CustodyError> {
+        self.check_mutation(ApiOperation::Withdraw, &[wallet_id])?;
+        self.system.withdraw_to(wallet_id, amount, destination)
+    }
+
+    /// Transfers between two wallets, both of which must be in scope
+    pub fn transfer(&mut self, from: &str, to: &str, amount: Amount) -> Result<(), CustodyError> {
+        self.check_mutation(ApiOperation::Transfer, &[from, to])?;
+        self.system.transfer(from, to, amount)
+    }
+
+    fn check(&self, operation: ApiOperation, wallet_ids: &[&str]) -> Result<(), CustodyError> {
+        let key = self.key();
+        self.system.check_key(key)?;
+        check_scope(key, operation, wallet_ids)
+    }
+
+    /// Checks a mutating call and takes a token from the key's operator
+    /// rate limit
+    fn check_mutation(
+        &mut self,
+        operation: ApiOperation,
+        wallet_ids: &[&str],
+    ) -> Result<(), CustodyError> {
+        self.check(operation, wallet_ids)?;
+        self.system.throttle_operator(&self.key_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::time::ManualClock;
+    use crate::WalletType;
+    use std::sync::Arc;
+
+    fn system() -> (CustodySystem, Arc<ManualClock>) {
+        let clock = Arc::new(ManualClock::new(Timestamp::from_unix(1_700_000_000)));
+        let mut system = CustodySystem::new();
+        system.set_clock(clock.clone());
+        for id in ["a", "b"] {
+            system
+                .create_wallet(id.to_string(), id.to_string(), WalletType::Hot)
+                .unwrap();
+            system.deposit(id, amount!(10)).unwrap();
+        }
+        (system, clock)
+    }
+
+    #[test]
+    fn test_scopes() {
+        let (mut system, _) = system();
+        let (_, read) = system
+            .create_api_key("audit", KeyScope::read_only(), None)
+            .unwrap();
+        let (key, full) = system
+            .create_api_key("ops", KeyScope::full().with_wallet("a"), None)
+            .unwrap();
+        assert!(full.starts_with(API_KEY_PREFIX));
+        assert_eq!(system.authenticate(&full).unwrap(), &key);
+
+        let mut keyed = system.with_key(&read).unwrap();
+        assert_eq!(keyed.get_wallet("b").unwrap().balance, amount!(10));
+        assert!(matches!(
+            keyed.deposit("b", amount!(1)),
+            Err(CustodyError::ScopeDenied { .. })
+        ));
+
+        let mut keyed = system.with_key(&full).unwrap();
+        keyed.withdraw("a", amount!(1)).unwrap();
+        assert_eq!(
+            keyed.transfer("a", "b", amount!(1)),
+            Err(CustodyError::ScopeDenied {
+                key_id: key.id.clone(),
+                operation: "transfer".to_string(),
+            })
+        );
+        assert!(keyed.get_wallet_transactions("b").is_err());
+        assert!(system
+            .authorize(&full, ApiOperation::Manage, &["a"])
+            .is_ok());
+
+        assert_eq!(
+            system.create_api_key("x", KeyScope::full().with_wallet("zz"), None),
+            Err(CustodyError::WalletNotFound("zz".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_expiry_and_revocation() {
+        let (mut system, clock) = system();
+        let (temp, temp_token) = system
+            .create_api_key("temp", KeyScope::full(), Some(60))
+            .unwrap();
+        let (ops, ops_token) = system
+            .create_api_key("ops", KeyScope::full(), None)
+            .unwrap();
+
+        assert_eq!(
+            system.authenticate("svk_nope.nope"),
+            Err(CustodyError::InvalidApiKey)
+        );
+        // A token is checked in full, not just its key id
+        let forged = format!("{}{}.{}", API_KEY_PREFIX, ops.id, "0".repeat(32));
+        assert_eq!(
+            system.authenticate(&forged),
+            Err(CustodyError::InvalidApiKey)
+        );
+
+        clock.advance(60);
+        assert_eq!(
+            system.authenticate(&temp_token),
+            Err(CustodyError::ApiKeyExpired(temp.id.clone()))
+        );
+
+        let mut keyed = system.with_key(&ops_token).unwrap();
+        keyed.deposit("a", amount!(1)).unwrap();
+        keyed.system.revoke_api_key(&ops.id).unwrap();
+        assert_eq!(
+            keyed.deposit("a", amount!(1)),
+            Err(CustodyError::ApiKeyRevoked(ops.id.clone()))
+        );
+        assert_eq!(system.api_keys().len(), 2);
+        assert_eq!(
+            system.revoke_api_key("zz"),
+            Err(CustodyError::ApiKeyNotFound("zz".to_string()))
+        );
+    }
+}
@@ -0,0 +1,549 @@
+//! Approval workflow for cold wallet withdrawals.
+//!
+//! Cold wallets never pay out instantly. A withdrawal is requested with
+//! [`CustodySystem::request_withdrawal`] and waits as a
+//! [`PendingWithdrawal`] until enough of the wallet's named approvers sign
+//! it off with [`CustodySystem::approve_withdrawal`]; any one of them can
+//! stop it with [`CustodySystem::reject_withdrawal`].
+//!
+//! The threshold is the wallet's [`Quorum`](crate::Quorum) (`required` of
+//! the approvers), or every approver when the wallet has no quorum. The
+//! requester never counts towards their own request. Every decision is
+//! kept in [`CustodySystem::approval_log`].
+//!
+//! Only a wallet's first list of approvers is set directly. Replacing it is
+//! a [`QuorumChange`](crate::QuorumChange) that the current approvers sign
+//! off like a withdrawal, so no single operator can name themselves.
+
+use crate::precheck::Authorization;
+use crate::screening::Workflow;
+use crate::time::Timestamp;
+use crate::{
+    Amount, CustodyError, CustodyEvent, CustodySystem, OperationStatus, Quorum, WalletType,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+
+/// A decision recorded by an approver
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApprovalVerdict {
+    Approved,
+    Rejected,
+}
+
+/// An entry in the approval audit log
+#[derive(Debug, Clone, PartialEq)]
+pub struct ApprovalEntry {
+    pub withdrawal_id: u64,
+    pub wallet_id: String,
+    pub approver: String,
+    pub verdict: ApprovalVerdict,
+    /// Reason given for a rejection
+    pub reason: Option<String>,
+    pub timestamp: Timestamp,
+}
+
+/// A cold wallet withdrawal awaiting approval
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PendingWithdrawal {
+    pub id: u64,
+    pub wallet_id: String,
+    pub amount: Amount,
+    pub destination: Option<String>,
+    pub requested_by: String,
+    pub requested_at: Timestamp,
+    /// Approvers who have signed off, excluding the requester
+    pub approvals: BTreeSet<String>,
+    pub status: OperationStatus,
+}
+
+impl CustodySystem {
+    /// Names the first approvers of withdrawals from a cold wallet
+    ///
+    /// Fails with [`CustodyError::ApproversAlreadySet`] once the wallet has
+    /// approvers; replace them with
+    /// [`propose_approver_change`](Self::propose_approver_change). Fails if
+    /// the wallet's quorum needs more approvals than there are approvers.
+    pub fn set_withdrawal_approvers(
+        &mut self,
+        wallet_id: &str,
+        approvers: &[&str],
+    ) -> Result<(), CustodyError> {
+        let approvers = self.check_approvers(wallet_id, approvers)?;
+        if self.withdrawal_approvers.contains_key(wallet_id) {
+            return Err(CustodyError::ApproversAlreadySet(wallet_id.to_string()));
+        }
+        self.withdrawal_approvers
+            .insert(wallet_id.to_string(), approvers);
+        Ok(())
+    }
+
+    /// Proposes replacing the approvers of a cold wallet, approved by
+    /// `approver`, who must be one of the current approvers
+    ///
+    /// The change executes once as many current approvers as a withdrawal
+    /// needs have approved it with
+    /// [`approve_quorum_change`](Self::approve_quorum_change).
+    ///
+    /// # Returns
+    /// The id of the [`QuorumChange`](crate::QuorumChange)
+    pub fn propose_approver_change(
+        &mut self,
+        wallet_id: &str,
+        approver: &str,
+        approvers: &[&str],
+    ) -> Result<u64, CustodyError> {
+        let approvers = self.check_approvers(wallet_id, approvers)?;
+        self.check_approver(wallet_id, approver)?;
+        let wallet = &self.wallets[wallet_id];
+        let quorum = wallet.quorum.unwrap_or(Quorum {
+            required: approvers.len(),
+            signers: approvers.len(),
+        });
+        self.open_quorum_change(wallet_id, approver, quorum, Some(approvers))
+    }
+
+    /// Gets the approvers of withdrawals from a cold wallet, if configured
+    pub fn withdrawal_approvers(&self, wallet_id: &str) -> Option<&BTreeSet<String>> {
+        self.withdrawal_approvers.get(wallet_id)
+    }
+
+    /// Requests a withdrawal from a cold wallet on behalf of `requester`
+    ///
+    /// The withdrawal is checked now and again when it executes.
+    ///
+    /// # Returns
+    /// The id approvers sign with
+    ///
+    /// # Example
+    /// ```
+    /// use securevault::{amount, CustodySystem, OperationStatus, Quorum, WalletType};
+    /// let mut system = CustodySystem::new();
+    /// system.set_default_quorum(WalletType::Cold, Quorum::new(2, 3).unwrap());
+    /// system.create_wallet("vault".to_string(), "bc1q...".to_string(), WalletType::Cold).unwrap();
+    /// system.deposit("vault", amount!(10.0)).unwrap();
+    /// system.set_withdrawal_approvers("vault", &["alice", "bob", "carol"]).unwrap();
+    ///
+    /// let id = system.request_withdrawal("vault", "dave", amount!(4.0), Some("bc1qdest")).unwrap();
+    /// assert_eq!(system.approve_withdrawal(id, "alice").unwrap(), OperationStatus::Pending);
+    /// assert_eq!(system.approve_withdrawal(id, "carol").unwrap(), OperationStatus::Executed);
+    /// assert_eq!(system.get_wallet("vault").unwrap().balance, amount!(6.0));
+    /// ```
+    pub fn request_withdrawal(
+        &mut self,
+        wallet_id: &str,
+        requester: &str,
+        amount: Amount,
+        destination: Option<&str>,
+    ) -> Result<u64, CustodyError> {
+        self.check_approval_request(wallet_id, amount, destination)?;
+        let workflow = Workflow::Approval {
+            requester: requester.to_string(),
+        };
+        self.screen_withdrawal_request(wallet_id, amount, destination, workflow)?;
+        self.open_approval_request(wallet_id, requester, amount, destination)
+    }
+
+    /// Opens a screened withdrawal request, checking it again
+    pub(crate) fn open_approval_request(
+        &mut self,
+        wallet_id: &str,
+        requester: &str,
+        amount: Amount,
+        destination: Option<&str>,
+    ) -> Result<u64, CustodyError> {
+        self.check_approval_request(wallet_id, amount, destination)?;
+        let id = self.allocate_operation_id();
+        self.pending_withdrawals.insert(
+            id,
+            PendingWithdrawal {
+                id,
+                wallet_id: wallet_id.to_string(),
+                amount,
+                destination: destination.map(str::to_string),
+                requested_by: requester.to_string(),
+                requested_at: self.current_timestamp(),
+                approvals: BTreeSet::new(),
+                status: OperationStatus::Pending,
+            },
+        );
+        Ok(id)
+    }
+
+    fn check_approval_request(
+        &self,
+        wallet_id: &str,
+        amount: Amount,
+        destination: Option<&str>,
+    ) -> Result<(), CustodyError> {
+        let wallet = self
+            .get_wallet(wallet_id)
+            .ok_or_else(|| CustodyError::WalletNotFound(wallet_id.to_string()))?;
+        if wallet.wallet_type != WalletType::Cold {
+            return Err(CustodyError::NotColdWallet(wallet_id.to_string()));
+        }
+        if !self.withdrawal_approvers.contains_key(wallet_id) {
+            return Err(CustodyError::NoApprovers(wallet_id.to_string()));
+        }
+        let reasons = self.withdrawal_blockers(
+            wallet_id,
+            None,
+            amount,
+            destination,
+            Authorization::Approved,
+        );
+        match reasons.into_iter().next() {
+            Some(reason) => Err(reason),
+            None => Ok(()),
+        }
+    }
+
+    /// Records `approver`'s approval, executing the withdrawal once the
+    /// threshold is met
+    ///
+    /// # Returns
+    /// The request's status after approving
+    pub fn approve_withdrawal(
+        &mut self,
+        withdrawal_id: u64,
+        approver: &str,
+    ) -> Result<OperationStatus, CustodyError> {
+        let request = self.pending_for(withdrawal_id, approver)?;
+        if request.requested_by == approver {
+            return Err(CustodyError::SelfApproval(withdrawal_id));
+        }
+        let wallet_id = request.wallet_id.clone();
+        self.log_decision(
+            withdrawal_id,
+            &wallet_id,
+            approver,
+            ApprovalVerdict::Approved,
+            None,
+        );
+        if let Some(request) = self.pending_withdrawals.get_mut(&withdrawal_id) {
+            request.approvals.insert(approver.to_string());
+        }
+        self.try_release(withdrawal_id)
+    }
+
+    /// Rejects a pending withdrawal; it can no longer execute
+    pub fn reject_withdrawal(
+        &mut self,
+        withdrawal_id: u64,
+        approver: &str,
+        reason: &str,
+    ) -> Result<(), CustodyError> {
+        let wallet_id = self.pending_for(withdrawal_id, approver)?.wallet_id.clone();
+        self.log_decision(
+            withdrawal_id,
+            &wallet_id,
+            approver,
+            ApprovalVerdict::Rejected,
+            Some(reason.to_string()),
+        );
+        if let Some(request) = self.pending_withdrawals.get_mut(&withdrawal_id) {
+            request.status = OperationStatus::Rejected;
+        }
+        Ok(())
+    }
+
+    /// Gets a cold wallet withdrawal request by id
+    pub fn get_pending_withdrawal(&self, withdrawal_id: u64) -> Option<&PendingWithdrawal> {
+        self.pending_withdrawals.get(&withdrawal_id)
+    }
+
+    /// Lists withdrawals from `wallet_id` still awaiting approval
+    pub fn pending_withdrawals(&self, wallet_id: &str) -> Vec<&PendingWithdrawal> {
+        self.pending_withdrawals
+            .values()
+            .filter(|w| w.wallet_id == wallet_id && w.status == OperationStatus::Pending)
+            .collect()
+    }
+
+    /// Returns every approval decision, oldest first
+    pub fn approval_log(&self) -> &im::Vector<ApprovalEntry> {
+        &self.approval_log
+    }
+
+    /// Validates a list of approvers for a cold wallet
+    fn check_approvers(
+        &self,
+        wallet_id: &str,
+        approvers: &[&str],
+    ) -> Result<BTreeSet<String>, CustodyError> {
+        let wallet = self
+            .get_wallet(wallet_id)
+            .ok_or_else(|| CustodyError::WalletNotFound(wallet_id.to_string()))?;
+        if wallet.wallet_type != WalletType::Cold {
+            return Err(CustodyError::NotColdWallet(wallet_id.to_string()));
+        }
+        let approvers: BTreeSet<String> = approvers.iter().map(|a| a.to_string()).collect();
+        let required = wallet.quorum.map_or(approvers.len(), |q| q.required);
+        if required == 0 || required > approvers.len() {
+            return Err(CustodyError::InvalidThreshold {
+                required,
+                available: approvers.len(),
+            });
+        }
+        Ok(approvers)
+    }
+
+    /// Checks that `user` is a current approver of the wallet's withdrawals
+    pub(crate) fn check_approver(&self, wallet_id: &str, user: &str) -> Result<(), CustodyError> {
+        let allowed = self
+            .withdrawal_approvers
+            .get(wallet_id)
+            .is_some_and(|approvers| approvers.contains(user));
+        if !allowed {
+            return Err(CustodyError::NotAnApprover {
+                wallet_id: wallet_id.to_string(),
+                user: user.to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Counts the current approvers among `approvals` and how many a
+    /// withdrawal from the wallet needs
+    pub(crate) fn approver_tally(
+        &self,
+        wallet_id: &str,
+        approvals: &BTreeSet<String>,
+    ) -> (usize, usize) {
+        let Some(approvers) = self.withdrawal_approvers.get(wallet_id) else {
+            // Nothing is approved for a wallet without approvers
+            return (0, 1);
+        };
+        let needed = self
+            .wallets
+            .get(wallet_id)
+            .and_then(|wallet| wallet.quorum)
+            .map_or(approvers.len(), |q| q.required);
+        // Approvers removed since signing no longer count
+        (approvals.intersection(approvers).count(), needed)
+    }
+
+    /// Looks up a pending request that `approver` may decide on
+    fn pending_for(
+        &self,
+        withdrawal_id: u64,
+        approver: &str,
+    ) -> Result<&PendingWithdrawal, CustodyError> {
+        let request = self
+            .pending_withdrawals
+            .get(&withdrawal_id)
+            .ok_or(CustodyError::OperationNotFound(withdrawal_id))?;
+        if request.status != OperationStatus::Pending {
+            return Err(CustodyError::OperationNotPending(withdrawal_id));
+        }
+        self.check_approver(&request.wallet_id, approver)?;
+        Ok(request)
+    }
+
+    fn log_decision(
+        &mut self,
+        withdrawal_id: u64,
+        wallet_id: &str,
+        approver: &str,
+        verdict: ApprovalVerdict,
+        reason: Option<String>,
+    ) {
+        self.approval_log.push_back(ApprovalEntry {
+            withdrawal_id,
+            wallet_id: wallet_id.to_string(),
+            approver: approver.to_string(),
+            verdict,
+            reason,
+            timestamp: self.current_timestamp(),
+        });
+    }
+
+    fn try_release(&mut self, withdrawal_id: u64) -> Result<OperationStatus, CustodyError> {
+        let request = self.pending_withdrawals[&withdrawal_id].clone();
+        let (approved, needed) = self.approver_tally(&request.wallet_id, &request.approvals);
+        if approved < needed {
+            return Ok(OperationStatus::Pending);
+        }
+
+        let wallet_id = &request.wallet_id;
+        let reasons = self.withdrawal_blockers(
+            wallet_id,
+            None,
+            request.amount,
+            request.destination.as_deref(),
+            Authorization::Approved,
+        );
+        if let Some(reason) = reasons.into_iter().next() {
+            return Err(reason);
+        }
+        self.emit(CustodyEvent::WithdrawalApproved {
+            wallet_id: wallet_id.clone(),
+            operation_id: withdrawal_id,
+            amount: request.amount,
+            asset: self.wallets[wallet_id].asset.clone(),
+        });
+        self.execute_withdrawal(
+            wallet_id,
+            None,
+            request.amount,
+            request.destination.as_deref(),
+            Authorization::Approved,
+        )?;
+        if let Some(request) = self.pending_withdrawals.get_mut(&withdrawal_id) {
+            request.status = OperationStatus::Executed;
+        }
+        Ok(OperationStatus::Executed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn system() -> CustodySystem {
+        let mut system = CustodySystem::new();
+        system.set_default_quorum(WalletType::Cold, Quorum::new(2, 3).unwrap());
+        system
+            .create_wallet("vault".to_string(), "0x1".to_string(), WalletType::Cold)
+            .unwrap();
+        system
+            .create_wallet("hot".to_string(), "0x2".to_string(), WalletType::Hot)
+            .unwrap();
+        system.deposit("vault", amount!(10.0)).unwrap();
+        system
+            .set_withdrawal_approvers("vault", &["alice", "bob", "carol"])
+            .unwrap();
+        system
+    }
+
+    #[test]
+    fn test_cold_wallets_cannot_withdraw_directly() {
+        let mut system = system();
+        assert_eq!(
+            system.withdraw("vault", amount!(1.0)),
+            Err(CustodyError::ApprovalRequired("vault".to_string()))
+        );
+        assert!(system.transfer("vault", "hot", amount!(1.0)).is_err());
+        assert_eq!(
+            system.request_withdrawal("hot", "dave", amount!(1.0), None),
+            Err(CustodyError::NotColdWallet("hot".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_threshold_and_audit_log() {
+        let mut system = system();
+        let id = system
+            .request_withdrawal("vault", "alice", amount!(3.0), Some("bc1q"))
+            .unwrap();
+        assert_eq!(
+            system.approve_withdrawal(id, "alice"),
+            Err(CustodyError::SelfApproval(id))
+        );
+        assert!(matches!(
+            system.approve_withdrawal(id, "mallory"),
+            Err(CustodyError::NotAnApprover { .. })
+        ));
+        // Repeated approvals by the same approver count once
+        system.approve_withdrawal(id, "bob").unwrap();
+        assert_eq!(
+            system.approve_withdrawal(id, "bob").unwrap(),
+            OperationStatus::Pending
+        );
+        assert_eq!(
+            system.approve_withdrawal(id, "carol").unwrap(),
+            OperationStatus::Executed
+        );
+        assert_eq!(system.get_wallet("vault").unwrap().balance, amount!(7.0));
+        assert!(system.pending_withdrawals("vault").is_empty());
+
+        let approvers: Vec<_> = system
+            .approval_log()
+            .iter()
+            .map(|entry| entry.approver.as_str())
+            .collect();
+        assert_eq!(approvers, ["bob", "bob", "carol"]);
+        assert_eq!(
+            system.approve_withdrawal(id, "alice"),
+            Err(CustodyError::OperationNotPending(id))
+        );
+    }
+
+    #[test]
+    fn test_rejection_is_final() {
+        let mut system = system();
+        let id = system
+            .request_withdrawal("vault", "dave", amount!(3.0), None)
+            .unwrap();
+        system.approve_withdrawal(id, "alice").unwrap();
+        system
+            .reject_withdrawal(id, "bob", "destination not verified")
+            .unwrap();
+
+        let request = system.get_pending_withdrawal(id).unwrap();
+        assert_eq!(request.status, OperationStatus::Rejected);
+        assert_eq!(
+            system.approve_withdrawal(id, "carol"),
+            Err(CustodyError::OperationNotPending(id))
+        );
+        let last = system.approval_log().last().unwrap();
+        assert_eq!(last.verdict, ApprovalVerdict::Rejected);
+        assert_eq!(last.reason.as_deref(), Some("destination not verified"));
+        assert_eq!(system.get_wallet("vault").unwrap().balance, amount!(10.0));
+    }
+
+    #[test]
+    fn test_approvers_must_cover_quorum() {
+        let mut system = system();
+        assert_eq!(
+            system.propose_approver_change("vault", "alice", &["alice"]),
+            Err(CustodyError::InvalidThreshold {
+                required: 2,
+                available: 1
+            })
+        );
+        system
+            .create_wallet("other".to_string(), "0x3".to_string(), WalletType::Cold)
+            .unwrap();
+        assert_eq!(
+            system.request_withdrawal("other", "dave", amount!(1.0), None),
+            Err(CustodyError::NoApprovers("other".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_replacing_approvers_needs_their_approval() {
+        let mut system = system();
+        assert_eq!(
+            system.set_withdrawal_approvers("vault", &["mallory", "eve"]),
+            Err(CustodyError::ApproversAlreadySet("vault".to_string()))
+        );
+        assert_eq!(
+            system.propose_approver_change("vault", "mallory", &["mallory", "eve"]),
+            Err(CustodyError::NotAnApprover {
+                wallet_id: "vault".to_string(),
+                user: "mallory".to_string()
+            })
+        );
+
+        let id = system
+            .propose_approver_change("vault", "alice", &["alice", "dave"])
+            .unwrap();
+        assert!(system.approve_quorum_change(id, "eve").is_err());
+        assert_eq!(
+            system.withdrawal_approvers("vault").unwrap().len(),
+            3,
+            "one approval is not enough"
+        );
+        assert_eq!(
+            system.approve_quorum_change(id, "bob"),
+            Ok(OperationStatus::Executed)
+        );
+        let approvers = system.withdrawal_approvers("vault").unwrap();
+        assert!(approvers.contains("dave") && !approvers.contains("carol"));
+        assert_eq!(
+            system.get_wallet("vault").unwrap().quorum.unwrap().required,
+            2
+        );
+    }
+}
@@ -0,0 +1,202 @@
+//! Archival of inactive wallets.
+//!
+//! [`CustodySystem::archive_wallet`] sets aside an empty wallet that is no
+//! longer used. It drops out of [`CustodySystem::get_wallet`],
+//! [`CustodySystem::get_all_wallets`], the total balance and every other
+//! query over active wallets, and takes no further operations, but its
+//! row is kept in an archive store and its transactions stay in the log.
+//! Its id cannot be reused. [`CustodySystem::unarchive_wallet`] puts it
+//! back.
+//!
+//! Archived wallets are persisted and replicated with an `archived_at`
+//! timestamp on their row.
+
+use crate::{CustodyError, CustodySystem, HoldStatus, OperationStatus, Wallet};
+
+impl CustodySystem {
+    /// Moves an empty wallet with nothing in progress to the archive
+    ///
+    /// # Errors
+    /// * [`CustodyError::WalletNotEmpty`] if it holds funds in any asset
+    /// * [`CustodyError::WalletInUse`] if it has active holds, queued
+    ///   withdrawals, or withdrawals or joint operations awaiting approval
+    ///   or signatures
+    ///
+    /// # Example
+    /// ```
+    /// use securevault::{amount, CustodySystem, WalletType};
+    /// let mut system = CustodySystem::new();
+    /// system.create_wallet("old".to_string(), "0x1".to_string(), WalletType::Hot).unwrap();
+    /// system.deposit("old", amount!(2)).unwrap();
+    /// system.withdraw("old", amount!(2)).unwrap();
+    ///
+    /// system.archive_wallet("old").unwrap();
+    /// assert!(system.get_wallet("old").is_none());
+    /// assert_eq!(system.list_archived()[0].id, "old");
+    /// assert_eq!(system.get_wallet_transactions("old").len(), 2);
+    ///
+    /// system.unarchive_wallet("old").unwrap();
+    /// system.deposit("old", amount!(1)).unwrap();
+    /// ```
+    pub fn archive_wallet(&mut self, wallet_id: &str) -> Result<Wallet, CustodyError> {
+        let wallet = self
+            .wallets
+            .get(wallet_id)
+            .ok_or_else(|| CustodyError::WalletNotFound(wallet_id.to_string()))?;
+        if wallet.balances().any(|(_, balance)| !balance.is_zero()) {
+            return Err(CustodyError::WalletNotEmpty(wallet_id.to_string()));
+        }
+        if self.has_operations_in_progress(wallet_id) {
+            return Err(CustodyError::WalletInUse(wallet_id.to_string()));
+        }
+        let mut wallet = wallet.clone();
+        wallet.archived_at = Some(self.current_timestamp());
+        self.commit_wallet(wallet.clone())?;
+        Ok(wallet)
+    }
+
+    /// Returns an archived wallet to the active wallets
+    pub fn unarchive_wallet(&mut self, wallet_id: &str) -> Result<Wallet, CustodyError> {
+        let mut wallet = self
+            .archived_wallets
+            .get(wallet_id)
+            .cloned()
+            .ok_or_else(|| CustodyError::WalletNotFound(wallet_id.to_string()))?;
+        wallet.archived_at = None;
+        self.commit_wallet(wallet.clone())?;
+        Ok(wallet)
+    }
+
+    /// Returns the archived wallets, ordered by id
+    pub fn list_archived(&self) -> Vec<&Wallet> {
+        let mut wallets: Vec<_> = self.archived_wallets.values().collect();
+        wallets.sort_by(|a, b| a.id.cmp(&b.id));
+        wallets
+    }
+
+    /// Gets an archived wallet by its ID
+    pub fn get_archived_wallet(&self, wallet_id: &str) -> Option<&Wallet> {
+        self.archived_wallets.get(wallet_id)
+    }
+
+    /// Checks whether an active or archived wallet has the id
+    pub(crate) fn wallet_id_taken(&self, wallet_id: &str) -> bool {
+        self.wallets.contains_key(wallet_id) || self.archived_wallets.contains_key(wallet_id)
+    }
+
+    fn has_operations_in_progress(&self, wallet_id: &str) -> bool {
+        let pending = |status: &OperationStatus| *status == OperationStatus::Pending;
+        self.holds
+            .values()
+            .any(|hold| hold.wallet_id == wallet_id && hold.status == HoldStatus::Active)
+            || self
+                .pending_withdrawals
+                .values()
+                .any(|op| op.wallet_id == wallet_id && pending(&op.status))
+            || self
+                .multisig_withdrawals
+                .values()
+                .any(|op| op.wallet_id == wallet_id && pending(&op.status))
+            || self
+                .joint_operations
+                .values()
+                .any(|op| op.wallet_id == wallet_id && pending(&op.status))
+            || self
+                .unsigned_txs
+                .values()
+                .any(|tx| tx.wallet_id == wallet_id)
+            || self
+                .queued_withdrawals()
+                .iter()
+                .any(|entry| entry.wallet_id == wallet_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Asset, Snapshot, WalletType};
+
+    fn system() -> CustodySystem {
+        let mut system = CustodySystem::new();
+        for id in ["a", "b"] {
+            system
+                .create_wallet(id.to_string(), format!("0x{}", id), WalletType::Hot)
+                .unwrap();
+        }
+        system.deposit("a", amount!(10)).unwrap();
+        system.deposit("b", amount!(3)).unwrap();
+        system
+    }
+
+    #[test]
+    fn test_only_idle_empty_wallets_are_archived() {
+        let mut system = system();
+        assert_eq!(
+            system.archive_wallet("b"),
+            Err(CustodyError::WalletNotEmpty("b".to_string()))
+        );
+        let hold = system.place_hold("b", amount!(1), "fee").unwrap();
+        system.transfer("b", "a", amount!(2)).unwrap();
+        system.capture_hold(hold).unwrap();
+        system.deposit_asset("b", &Asset::Eth, amount!(1)).unwrap();
+        assert!(system.archive_wallet("b").is_err());
+        system.withdraw_asset("b", &Asset::Eth, amount!(1)).unwrap();
+        system.place_hold("a", amount!(1), "payout").unwrap();
+
+        let archived = system.archive_wallet("b").unwrap();
+        assert!(archived.archived_at.is_some());
+        assert_eq!(
+            system.archive_wallet("a"),
+            Err(CustodyError::WalletNotEmpty("a".to_string()))
+        );
+        assert_eq!(
+            system.archive_wallet("b"),
+            Err(CustodyError::WalletNotFound("b".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_archived_wallets_leave_active_queries() {
+        let mut system = system();
+        system.withdraw("b", amount!(3)).unwrap();
+        system.archive_wallet("b").unwrap();
+
+        assert_eq!(system.wallet_count(), 1);
+        assert_eq!(system.get_total_balance(), amount!(10));
+        assert!(!system.wallet_exists("b"));
+        assert_eq!(
+            system.deposit("b", amount!(1)),
+            Err(CustodyError::WalletNotFound("b".to_string()))
+        );
+        assert_eq!(
+            system.create_wallet("b".to_string(), "0x9".to_string(), WalletType::Hot),
+            Err(CustodyError::WalletAlreadyExists("b".to_string()))
+        );
+        assert_eq!(system.get_archived_wallet("b").unwrap().address, "0xb");
+        assert_eq!(system.get_wallet_transactions("b").len(), 2);
+
+        let unarchived = system.unarchive_wallet("b").unwrap();
+        assert_eq!(unarchived.archived_at, None);
+        assert!(system.list_archived().is_empty());
+        assert_eq!(system.wallet_count(), 2);
+        assert!(system.unarchive_wallet("b").is_err());
+    }
+
+    #[test]
+    fn test_archive_survives_snapshots_and_replication() {
+        let mut system = system();
+        system.withdraw("b", amount!(3)).unwrap();
+        system.archive_wallet("b").unwrap();
+
+        let snapshot = Snapshot::from_json(&system.snapshot().to_json().unwrap()).unwrap();
+        let mut restored = CustodySystem::new();
+        restored.restore(snapshot).unwrap();
+        assert_eq!(restored.wallet_count(), 1);
+        assert_eq!(restored.list_archived().len(), 1);
+
+        let rebuilt = CustodySystem::rebuild_from_events(system.events_since(0).cloned()).unwrap();
+        assert_eq!(rebuilt.snapshot(), system.snapshot());
+        assert!(rebuilt.get_wallet("b").is_none());
+    }
+}
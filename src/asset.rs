@@ -0,0 +1,127 @@
+//! Assets held in custody.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// A custodied asset
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Serialize, Deserialize)]
+pub enum Asset {
+    /// Bitcoin, 8 decimal places (satoshis)
+    #[default]
+    Btc,
+    /// Ether, 18 decimal places (wei)
+    Eth,
+    /// An ERC-20 token identified by its contract address
+    Erc20 {
+        symbol: String,
+        contract: String,
+        decimals: u8,
+    },
+    /// A fiat currency identified by its ISO 4217 code
+    Fiat(String),
+    /// Any other asset
+    Custom { symbol: String, decimals: u8 },
+}
+
+impl Asset {
+    /// Returns the ticker symbol (or ISO 4217 code for fiat)
+    pub fn symbol(&self) -> &str {
+        match self {
+            Asset::Btc => "BTC",
+            Asset::Eth => "ETH",
+            Asset::Erc20 { symbol, .. } => symbol,
+            Asset::Fiat(code) => code,
+            Asset::Custom { symbol, .. } => symbol,
+        }
+    }
+
+    /// Returns the number of decimal places of the asset's smallest unit
+    pub fn decimals(&self) -> u8 {
+        match self {
+            Asset::Btc => 8,
+            Asset::Eth => 18,
+            Asset::Erc20 { decimals, .. } => *decimals,
+            Asset::Fiat(code) => match code.as_str() {
+                "JPY" | "KRW" | "CLP" => 0,
+                _ => 2,
+            },
+            Asset::Custom { decimals, .. } => *decimals,
+        }
+    }
+
+    /// Returns true for fiat currencies
+    pub fn is_fiat(&self) -> bool {
+        matches!(self, Asset::Fiat(_))
+    }
+
+    /// Returns the currency sign conventionally written before fiat amounts
+    pub fn currency_sign(&self) -> Option<&'static str> {
+        match self {
+            Asset::Fiat(code) => match code.as_str() {
+                "USD" => Some("$"),
+                "EUR" => Some("€"),
+                "GBP" => Some("£"),
+                "BRL" => Some("R$"),
+                "JPY" => Some("¥"),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Asset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.symbol())
+    }
+}
+
+/// Serializes asset-keyed maps as `[asset, value]` pairs, since formats
+/// like JSON only allow string keys
+pub(crate) mod as_pairs {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::collections::BTreeMap;
+
+    pub fn serialize<K, V, S>(map: &BTreeMap<K, V>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        K: Serialize,
+        V: Serialize,
+        S: Serializer,
+    {
+        serializer.collect_seq(map)
+    }
+
+    pub fn deserialize<'de, K, V, D>(deserializer: D) -> Result<BTreeMap<K, V>, D::Error>
+    where
+        K: Deserialize<'de> + Ord,
+        V: Deserialize<'de>,
+        D: Deserializer<'de>,
+    {
+        Vec::<(K, V)>::deserialize(deserializer).map(|pairs| pairs.into_iter().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_asset_decimals() {
+        assert_eq!(Asset::Btc.decimals(), 8);
+        assert_eq!(Asset::Eth.decimals(), 18);
+        assert_eq!(Asset::Fiat("USD".to_string()).decimals(), 2);
+        assert_eq!(Asset::Fiat("JPY".to_string()).decimals(), 0);
+    }
+
+    #[test]
+    fn test_asset_symbol() {
+        let usdc = Asset::Erc20 {
+            symbol: "USDC".to_string(),
+            contract: "0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48".to_string(),
+            decimals: 6,
+        };
+        assert_eq!(usdc.symbol(), "USDC");
+        assert_eq!(usdc.to_string(), "USDC");
+        assert_eq!(Asset::default(), Asset::Btc);
+    }
+}
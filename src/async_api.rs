@@ -0,0 +1,366 @@
+//! Async API for Tokio applications (feature `async`).
+//!
+//! [`AsyncCustodySystem`] is a cloneable handle to a shared ledger whose
+//! operations run on Tokio's blocking thread pool, so write-through
+//! [`Storage`] I/O never stalls the async executor. Committed events are
+//! rebroadcast on a `tokio::sync::broadcast` channel.
+//!
+//! [`AsyncStorage`] backends load and save whole snapshots asynchronously:
+//! [`TokioFileStorage`] writes the same document as [`JsonFileStorage`]
+//! through `tokio::fs`, and [`BlockingStorage`] adapts any synchronous
+//! backend.
+//!
+//! [`JsonFileStorage`]: crate::JsonFileStorage
+
+use crate::{
+    Amount, CustodyError, CustodyEvent, CustodySystem, EventListener, Snapshot, Storage, Wallet,
+    WalletType,
+};
+use std::future::Future;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use tokio::sync::broadcast;
+
+/// Events buffered per subscriber before slow ones start missing events
+pub const EVENT_CAPACITY: usize = 256;
+
+/// A backend that loads and saves ledger snapshots asynchronously
+pub trait AsyncStorage: Send + Sync {
+    /// Reads the stored ledger, or `None` if nothing has been saved yet
+    fn load(&self) -> impl Future<Output = Result<Option<Snapshot>, CustodyError>> + Send;
+
+    /// Replaces everything stored with `snapshot`
+    fn save(&self, snapshot: &Snapshot) -> impl Future<Output = Result<(), CustodyError>> + Send;
+}
+
+/// Stores the ledger as a JSON document using `tokio::fs`
+///
+/// Reads and writes the format of [`JsonFileStorage`](crate::JsonFileStorage),
+/// replacing the file atomically on save.
+#[derive(Debug, Clone)]
+pub struct TokioFileStorage {
+    path: PathBuf,
+}
+
+impl TokioFileStorage {
+    /// Uses the document at `path`, created on first save
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+}
+
+impl AsyncStorage for TokioFileStorage {
+    async fn load(&self) -> Result<Option<Snapshot>, CustodyError> {
+        match tokio::fs::read_to_string(&self.path).await {
+            Ok(json) => Snapshot::from_json(&json).map(Some),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(storage_failed(err)),
+        }
+    }
+
+    async fn save(&self, snapshot: &Snapshot) -> Result<(), CustodyError> {
+        let json = snapshot.to_json()?;
+        let mut staging = self.path.clone().into_os_string();
+        staging.push(".tmp");
+        let staging = PathBuf::from(staging);
+        let mut file = tokio::fs::File::create(&staging)
+            .await
+            .map_err(storage_failed)?;
+        tokio::io::AsyncWriteExt::write_all(&mut file, json.as_bytes())
+            .await
+            .map_err(storage_failed)?;
+        file.sync_all().await.map_err(storage_failed)?;
+        tokio::fs::rename(&staging, &self.path)
+            .await
+            .map_err(storage_failed)
+    }
+}
+
+/// Runs a synchronous [`Storage`] backend on the blocking thread pool
+#[derive(Clone)]
+pub struct BlockingStorage(pub Arc<dyn Storage>);
+
+impl AsyncStorage for BlockingStorage {
+    async fn load(&self) -> Result<Option<Snapshot>, CustodyError> {
+        let backend = self.0.clone();
+        blocking(move || backend.load()).await
+    }
+
+    async fn save(&self, snapshot: &Snapshot) -> Result<(), CustodyError> {
+        let backend = self.0.clone();
+        let snapshot = snapshot.clone();
+        blocking(move || backend.save(&snapshot)).await
+    }
+}
+
+/// Forwards committed events to a broadcast channel
+struct Broadcaster(broadcast::Sender<CustodyEvent>);
+
+impl EventListener for Broadcaster {
+    fn on_event(&self, event: &CustodyEvent) {
+        // Without subscribers there is nobody to tell
+        let _ = self.0.send(event.clone());
+    }
+}
+
+/// Cloneable async handle to a shared custody system
+#[derive(Clone)]
+pub struct AsyncCustodySystem {
+    system: Arc<Mutex<CustodySystem>>,
+    events: broadcast::Sender<CustodyEvent>,
+}
+
+impl AsyncCustodySystem {
+    /// Wraps a custody system
+    ///
+    /// # Example
+    /// ```
+    /// use securevault::{amount, AsyncCustodySystem, CustodySystem, WalletType};
+    /// # tokio::runtime::Builder::new_current_thread().build().unwrap().block_on(async {
+    /// let system = AsyncCustodySystem::new(CustodySystem::new());
+    /// let mut events = system.subscribe();
+    /// system.create_wallet("w", "0x1", WalletType::Hot).await.unwrap();
+    /// system.deposit("w", amount!(5)).await.unwrap();
+    ///
+    /// assert_eq!(events.recv().await.unwrap().kind(), "deposit_received");
+    /// assert_eq!(system.get_wallet("w").await.unwrap().balance, amount!(5));
+    /// # });
+    /// ```
+    pub fn new(mut system: CustodySystem) -> Self {
+        let (events, _) = broadcast::channel(EVENT_CAPACITY);
+        system.subscribe(Arc::new(Broadcaster(events.clone())));
+        Self {
+            system: Arc::new(Mutex::new(system)),
+            events,
+        }
+    }
+
+    /// Opens a ledger with a write-through [`Storage`] backend, loading it
+    /// on the blocking thread pool
+    pub async fn with_storage(storage: Arc<dyn Storage>) -> Result<Self, CustodyError> {
+        blocking(move || CustodySystem::with_storage(storage))
+            .await
+            .map(Self::new)
+    }
+
+    /// Restores a ledger from an async backend; starts empty if nothing was
+    /// saved yet
+    pub async fn load(storage: &impl AsyncStorage) -> Result<Self, CustodyError> {
+        let mut system = CustodySystem::new();
+        if let Some(snapshot) = storage.load().await? {
+            system.restore(snapshot)?;
+        }
+        Ok(Self::new(system))
+    }
+
+    /// Saves a snapshot of the ledger to an async backend
+    pub async fn save(&self, storage: &impl AsyncStorage) -> Result<(), CustodyError> {
+        let snapshot = self.read(CustodySystem::snapshot).await;
+        storage.save(&snapshot).await
+    }
+
+    /// Receives every event committed after this call
+    ///
+    /// A subscriber that falls more than [`EVENT_CAPACITY`] events behind
+    /// gets [`broadcast::error::RecvError::Lagged`] and skips ahead.
+    pub fn subscribe(&self) -> broadcast::Receiver<CustodyEvent> {
+        self.events.subscribe()
+    }
+
+    /// Runs `f` with shared access on the blocking thread pool
+    pub async fn read<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&CustodySystem) -> T + Send + 'static,
+    ) -> T {
+        let system = self.system.clone();
+        blocking(move || f(&lock(&system))).await
+    }
+
+    /// Runs `f` with exclusive access on the blocking thread pool
+    pub async fn write<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&mut CustodySystem) -> T + Send + 'static,
+    ) -> T {
+        let system = self.system.clone();
+        blocking(move || f(&mut lock(&system))).await
+    }
+
+    /// See [`CustodySystem::create_wallet`]
+    pub async fn create_wallet(
+        &self,
+        id: &str,
+        address: &str,
+        wallet_type: WalletType,
+    ) -> Result<Wallet, CustodyError> {
+        let (id, address) = (id.to_string(), address.to_string());
+        self.write(move |system| system.create_wallet(id, address, wallet_type))
+            .await
+    }
+
+    /// See [`CustodySystem::deposit`]
+    pub async fn deposit(&self, wallet_id: &str, amount: Amount) -> Result<(), CustodyError> {
+        let wallet_id = wallet_id.to_string();
+        self.write(move |system| system.deposit(&wallet_id, amount))
+            .await
+    }
+
+    /// See [`CustodySystem::withdraw`]
+    pub async fn withdraw(&self, wallet_id: &str, amount: Amount) -> Result<(), CustodyError> {
+        let wallet_id = wallet_id.to_string();
+        self.write(move |system| system.withdraw(&wallet_id, amount))
+            .await
+    }
+
+    /// See [`CustodySystem::transfer`]
+    pub async fn transfer(
+        &self,
+        from_id: &str,
+        to_id: &str,
+        amount: Amount,
+    ) -> Result<(), CustodyError> {
+        let (from_id, to_id) = (from_id.to_string(), to_id.to_string());
+        self.write(move |system| system.transfer(&from_id, &to_id, amount))
+            .await
+    }
+
+    /// Returns a copy of a wallet
+    pub async fn get_wallet(&self, wallet_id: &str) -> Option<Wallet> {
+        let wallet_id = wallet_id.to_string();
+        self.read(move |system| system.get_wallet(&wallet_id).cloned())
+            .await
+    }
+}
+
+impl std::fmt::Debug for AsyncCustodySystem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AsyncCustodySystem")
+            .field("subscribers", &self.events.receiver_count())
+            .finish_non_exhaustive()
+    }
+}
+
+fn lock(system: &Mutex<CustodySystem>) -> MutexGuard<'_, CustodySystem> {
+    system
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Runs `f` on the blocking pool, resuming its panic if it panics
+async fn blocking<T: Send + 'static>(f: impl FnOnce() -> T + Send + 'static) -> T {
+    match tokio::task::spawn_blocking(f).await {
+        Ok(value) => value,
+        Err(err) => std::panic::resume_unwind(err.into_panic()),
+    }
+}
+
+fn storage_failed(err: io::Error) -> CustodyError {
+    CustodyError::StorageFailed(err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::JsonFileStorage;
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "securevault-async-{}-{}.json",
+            name,
+            std::process::id()
+        ))
+    }
+
+    #[test]
+    fn test_operations_and_event_stream() {
+        block_on(async {
+            let system = AsyncCustodySystem::new(CustodySystem::new());
+            let mut events = system.subscribe();
+            for id in ["a", "b"] {
+                system.create_wallet(id, id, WalletType::Hot).await.unwrap();
+            }
+            system.deposit("a", amount!(10)).await.unwrap();
+            system.transfer("a", "b", amount!(4)).await.unwrap();
+            system.withdraw("b", amount!(1)).await.unwrap();
+            assert_eq!(
+                system.withdraw("b", amount!(9)).await,
+                Err(CustodyError::InsufficientBalance {
+                    available: amount!(3),
+                    requested: amount!(9),
+                })
+            );
+
+            let mut kinds = Vec::new();
+            while let Ok(event) = events.try_recv() {
+                kinds.push(event.kind());
+            }
+            assert_eq!(
+                kinds,
+                [
+                    "deposit_received",
+                    "withdrawal_settled",
+                    "deposit_received",
+                    "transfer_completed",
+                    "withdrawal_settled"
+                ]
+            );
+            let clone = system.clone();
+            assert_eq!(clone.get_wallet("b").await.unwrap().balance, amount!(3));
+        });
+    }
+
+    #[test]
+    fn test_tokio_file_storage_round_trip() {
+        let path = temp_path("tokio-fs");
+        block_on(async {
+            let storage = TokioFileStorage::new(&path);
+            let empty = AsyncCustodySystem::load(&storage).await.unwrap();
+            assert!(empty.get_wallet("w").await.is_none());
+
+            empty
+                .create_wallet("w", "0x1", WalletType::Hot)
+                .await
+                .unwrap();
+            empty.deposit("w", amount!(2.5)).await.unwrap();
+            empty.save(&storage).await.unwrap();
+
+            let loaded = AsyncCustodySystem::load(&storage).await.unwrap();
+            assert_eq!(loaded.get_wallet("w").await.unwrap().balance, amount!(2.5));
+            // Same document format as the synchronous file backend
+            let sync = JsonFileStorage::new(&path);
+            assert_eq!(Storage::load(&sync).unwrap().unwrap().wallets.len(), 1);
+        });
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_write_through_storage_runs_off_the_executor() {
+        let path = temp_path("write-through");
+        block_on(async {
+            let backend: Arc<dyn Storage> = Arc::new(JsonFileStorage::new(&path));
+            let system = AsyncCustodySystem::with_storage(backend.clone())
+                .await
+                .unwrap();
+            system
+                .create_wallet("w", "0x1", WalletType::Hot)
+                .await
+                .unwrap();
+            system.deposit("w", amount!(1)).await.unwrap();
+
+            let reloaded = AsyncCustodySystem::load(&BlockingStorage(backend))
+                .await
+                .unwrap();
+            assert_eq!(reloaded.get_wallet("w").await.unwrap().balance, amount!(1));
+        });
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,231 @@
+//! One-call integrity audit.
+//!
+//! [`CustodySystem::audit`] runs every internal consistency check in a
+//! single pass and returns a structured report. It is read-only and cheap
+//! enough to back a health endpoint or to run as part of a DR drill.
+
+use crate::chain::GENESIS_HASH;
+use crate::CustodySystem;
+use std::fmt;
+
+/// Outcome of a single audit check
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuditCheck {
+    /// Stable identifier of the check, e.g. `balance_recomputation`
+    pub name: &'static str,
+    pub passed: bool,
+    /// Human-readable findings; empty when the check passed
+    pub details: Vec<String>,
+}
+
+impl AuditCheck {
+    fn new(name: &'static str, details: Vec<String>) -> Self {
+        Self {
+            name,
+            passed: details.is_empty(),
+            details,
+        }
+    }
+}
+
+/// Structured result of [`CustodySystem::audit`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuditReport {
+    pub checks: Vec<AuditCheck>,
+}
+
+impl AuditReport {
+    /// Returns true if every check passed
+    pub fn passed(&self) -> bool {
+        self.checks.iter().all(|check| check.passed)
+    }
+
+    /// Returns the checks that failed
+    pub fn failures(&self) -> Vec<&AuditCheck> {
+        self.checks.iter().filter(|check| !check.passed).collect()
+    }
+
+    /// Gets a check by name
+    pub fn check(&self, name: &str) -> Option<&AuditCheck> {
+        self.checks.iter().find(|check| check.name == name)
+    }
+}
+
+impl fmt::Display for AuditReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for check in &self.checks {
+            let status = if check.passed { "PASS" } else { "FAIL" };
+            writeln!(f, "[{}] {}", status, check.name)?;
+            for detail in &check.details {
+                writeln!(f, "       {}", detail)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl CustodySystem {
+    /// Runs all integrity checks and returns a pass/fail report
+    ///
+    /// # Example
+    /// ```
+    /// use securevault::CustodySystem;
+    /// let system = CustodySystem::new();
+    /// assert!(system.audit().passed());
+    /// ```
+    pub fn audit(&self) -> AuditReport {
+        AuditReport {
+            checks: vec![
+                self.check_balance_recomputation(),
+                self.check_wallet_invariants(),
+                self.check_transaction_digests(),
+                self.check_audit_chain(),
+                self.check_state_commitments(),
+            ],
+        }
+    }
+
+    fn check_balance_recomputation(&self) -> AuditCheck {
+        let replay = self.replay(self.transactions.iter());
+        let mut details = Vec::new();
+        for mismatch in &replay.mismatches {
+            details.push(format!(
+                "wallet '{}': stored {} balance {} but transaction log gives {}",
+                mismatch.wallet_id, mismatch.asset, mismatch.stored, mismatch.replayed
+            ));
+        }
+        for wallet_id in &replay.unknown_wallets {
+            details.push(format!(
+                "transaction log references unknown wallet '{}'",
+                wallet_id
+            ));
+        }
+        for position in &replay.overdrafts {
+            details.push(format!("transaction #{} overdraws its wallet", position));
+        }
+        AuditCheck::new("balance_recomputation", details)
+    }
+
+    fn check_wallet_invariants(&self) -> AuditCheck {
+        let mut details = Vec::new();
+        for (key, wallet) in self.wallets.iter() {
+            if key != &wallet.id {
+                details.push(format!(
+                    "wallet stored under '{}' has id '{}'",
+                    key, wallet.id
+                ));
+            }
+            for (asset, balance) in wallet.balances() {
+                if balance.is_negative() {
+                    details.push(format!(
+                        "wallet '{}' has invalid {} balance {}",
+                        wallet.id, asset, balance
+                    ));
+                }
+            }
+        }
+        details.sort();
+        AuditCheck::new("wallet_invariants", details)
+    }
+
+    fn check_transaction_digests(&self) -> AuditCheck {
+        let details = self
+            .transactions
+            .iter()
+            .filter(|tx| !tx.verify_digest())
+            .map(|tx| format!("transaction {} does not match its sealed digest", tx.id))
+            .collect();
+        AuditCheck::new("transaction_digests", details)
+    }
+
+    fn check_audit_chain(&self) -> AuditCheck {
+        let details = match self.verify_audit_chain() {
+            Ok(()) => Vec::new(),
+            Err(err) => vec![err.to_string()],
+        };
+        AuditCheck::new("audit_chain", details)
+    }
+
+    fn check_state_commitments(&self) -> AuditCheck {
+        let mut details = Vec::new();
+        for commitment in self.state_commitments().iter() {
+            if !commitment.is_consistent() {
+                details.push(format!(
+                    "state commitment {} does not match its root",
+                    commitment.id
+                ));
+            }
+            let anchored = commitment.chain_head == GENESIS_HASH
+                || self
+                    .transactions
+                    .iter()
+                    .any(|tx| tx.chain_hash == commitment.chain_head);
+            if !anchored {
+                details.push(format!(
+                    "state commitment {} is anchored to a chain head missing from the log",
+                    commitment.id
+                ));
+            }
+        }
+        AuditCheck::new("state_commitments", details)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::WalletType;
+
+    fn system() -> CustodySystem {
+        let mut system = CustodySystem::new();
+        system
+            .create_wallet("a".to_string(), "0x1".to_string(), WalletType::Hot)
+            .unwrap();
+        system
+            .create_wallet("b".to_string(), "0x2".to_string(), WalletType::Cold)
+            .unwrap();
+        system.deposit("a", amount!(10.0)).unwrap();
+        system.transfer("a", "b", amount!(3.0)).unwrap();
+        system
+    }
+
+    #[test]
+    fn test_audit_passes_on_consistent_system() {
+        let report = system().audit();
+        assert!(report.passed());
+        assert!(report.failures().is_empty());
+        assert!(report.check("balance_recomputation").unwrap().passed);
+    }
+
+    #[test]
+    fn test_audit_detects_tampered_balance() {
+        let mut system = system();
+        system.wallets.get_mut("b").unwrap().balance = amount!(5);
+
+        let report = system.audit();
+        assert!(!report.passed());
+        let check = report.check("balance_recomputation").unwrap();
+        assert!(!check.passed);
+        assert!(check.details[0].contains("wallet 'b'"));
+    }
+
+    #[test]
+    fn test_audit_detects_invalid_balance() {
+        let mut system = system();
+        system.wallets.get_mut("a").unwrap().balance = amount!(-1);
+
+        let report = system.audit();
+        assert!(!report.check("wallet_invariants").unwrap().passed);
+        assert!(report.to_string().contains("[FAIL] wallet_invariants"));
+    }
+
+    #[test]
+    fn test_audit_detects_tampered_transaction() {
+        let mut system = system();
+        system.transactions.get_mut(1).unwrap().counterparty = Some("x".to_string());
+
+        let check = system.audit().check("transaction_digests").unwrap().clone();
+        assert!(!check.passed);
+        assert_eq!(check.details.len(), 1);
+    }
+}
@@ -0,0 +1,333 @@
+//! Paper backups of cold wallets (feature `paper-backup`).
+//!
+//! [`CustodySystem::paper_backup`] produces a printable document for a vault
+//! deposit box: the wallet's public address and derivation path as text and
+//! QR codes, an integrity checksum over the whole payload, and, only when
+//! explicitly confirmed, the seed encrypted under a passphrase.
+//!
+//! Seeds are encrypted with ChaCha20-Poly1305 under a key derived from the
+//! passphrase with Argon2id and a random salt. The plaintext seed is never
+//! written to the document.
+
+use crate::time::Timestamp;
+use crate::{CustodyError, CustodySystem, WalletType};
+use argon2::Argon2;
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use qrcode::render::svg;
+use qrcode::QrCode;
+use sha2::{Digest, Sha256};
+use std::fmt::Write as _;
+use zeroize::Zeroizing;
+
+/// Prefix of the encoded encrypted seed, identifying the format version
+const SEED_FORMAT: &str = "svseed1";
+
+/// Minimum accepted passphrase length for seed encryption
+const MIN_PASSPHRASE_CHARS: usize = 12;
+
+/// Seed material to include in a backup
+///
+/// `confirmation` must be exactly `EXPORT SEED <wallet id>`; anything else
+/// refuses the export, so seeds are never included by accident.
+pub struct SeedExport<'a> {
+    pub seed: &'a [u8],
+    pub passphrase: &'a str,
+    pub confirmation: &'a str,
+}
+
+/// A seed encrypted under a passphrase
+#[derive(Debug, Clone, PartialEq)]
+pub struct EncryptedSeed {
+    salt: [u8; 16],
+    nonce: [u8; 12],
+    ciphertext: Vec<u8>,
+}
+
+impl EncryptedSeed {
+    fn seal(seed: &[u8], passphrase: &str) -> Result<Self, CustodyError> {
+        let mut salt = [0u8; 16];
+        let mut nonce = [0u8; 12];
+        getrandom::getrandom(&mut salt).map_err(backup_failed)?;
+        getrandom::getrandom(&mut nonce).map_err(backup_failed)?;
+        let cipher = cipher(passphrase, &salt)?;
+        let ciphertext = cipher
+            .encrypt(Nonce::from_slice(&nonce), seed)
+            .map_err(backup_failed)?;
+        Ok(Self {
+            salt,
+            nonce,
+            ciphertext,
+        })
+    }
+
+    /// Decrypts the seed, failing if the passphrase is wrong or the data
+    /// was altered
+    pub fn decrypt(&self, passphrase: &str) -> Result<Zeroizing<Vec<u8>>, CustodyError> {
+        let cipher = cipher(passphrase, &self.salt)?;
+        cipher
+            .decrypt(Nonce::from_slice(&self.nonce), self.ciphertext.as_slice())
+            .map(Zeroizing::new)
+            .map_err(|_| {
+                CustodyError::BackupFailed("wrong passphrase or corrupted seed".to_string())
+            })
+    }
+
+    /// Encodes as `svseed1:<salt>:<nonce>:<ciphertext>` in hex
+    pub fn encode(&self) -> String {
+        format!(
+            "{}:{}:{}:{}",
+            SEED_FORMAT,
+            hex::encode(self.salt),
+            hex::encode(self.nonce),
+            hex::encode(&self.ciphertext)
+        )
+    }
+
+    /// Parses the output of [`encode`](Self::encode)
+    pub fn decode(encoded: &str) -> Result<Self, CustodyError> {
+        let invalid = || CustodyError::BackupFailed("malformed encrypted seed".to_string());
+        let parts: Vec<&str> = encoded.trim().split(':').collect();
+        if parts.len() != 4 || parts[0] != SEED_FORMAT {
+            return Err(invalid());
+        }
+        let salt = hex::decode(parts[1]).map_err(|_| invalid())?;
+        let nonce = hex::decode(parts[2]).map_err(|_| invalid())?;
+        Ok(Self {
+            salt: salt.try_into().map_err(|_| invalid())?,
+            nonce: nonce.try_into().map_err(|_| invalid())?,
+            ciphertext: hex::decode(parts[3]).map_err(|_| invalid())?,
+        })
+    }
+}
+
+/// A printable cold wallet backup
+#[derive(Debug, Clone, PartialEq)]
+pub struct PaperBackup {
+    pub wallet_id: String,
+    pub asset: String,
+    pub address: String,
+    pub derivation_path: String,
+    pub created: Timestamp,
+    pub encrypted_seed: Option<EncryptedSeed>,
+    /// SHA-256 of [`payload`](Self::payload), in hex
+    pub checksum: String,
+}
+
+impl PaperBackup {
+    /// Canonical text covered by the checksum
+    pub fn payload(&self) -> String {
+        let mut payload = format!(
+            "wallet={}\nasset={}\naddress={}\npath={}\ncreated={}\n",
+            self.wallet_id, self.asset, self.address, self.derivation_path, self.created
+        );
+        if let Some(seed) = &self.encrypted_seed {
+            let _ = writeln!(payload, "seed={}", seed.encode());
+        }
+        payload
+    }
+
+    /// Returns true if the checksum matches the payload
+    pub fn verify(&self) -> bool {
+        checksum(&self.payload()) == self.checksum
+    }
+
+    /// Renders the backup as a self-contained, printable HTML page
+    pub fn to_html(&self) -> Result<String, CustodyError> {
+        let mut html = String::new();
+        html.push_str("<!DOCTYPE html><html><head><meta charset=\"utf-8\">");
+        let _ = write!(
+            html,
+            "<title>Cold wallet backup {}</title>",
+            escape(&self.wallet_id)
+        );
+        html.push_str("<style>body{font-family:monospace;margin:2em}");
+        html.push_str("section{page-break-inside:avoid;margin-bottom:2em}");
+        html.push_str("code{word-break:break-all}</style></head><body>");
+        let _ = write!(
+            html,
+            "<h1>Cold wallet backup: {}</h1><p>Asset: {}<br>Created: {}<br>Derivation path: <code>{}</code></p>",
+            escape(&self.wallet_id),
+            escape(&self.asset),
+            self.created,
+            escape(&self.derivation_path)
+        );
+        let _ = write!(
+            html,
+            "<section><h2>Public address</h2>{}<p><code>{}</code></p></section>",
+            qr_svg(&self.address)?,
+            escape(&self.address)
+        );
+        if let Some(seed) = &self.encrypted_seed {
+            let encoded = seed.encode();
+            let _ = write!(
+                html,
+                "<section><h2>Encrypted seed</h2><p>Decrypt only on an offline machine with the backup passphrase.</p>{}<p><code>{}</code></p></section>",
+                qr_svg(&encoded)?,
+                encoded
+            );
+        }
+        let _ = write!(
+            html,
+            "<section><h2>Integrity checksum (SHA-256)</h2><p><code>{}</code></p></section></body></html>",
+            self.checksum
+        );
+        Ok(html)
+    }
+}
+
+impl CustodySystem {
+    /// Creates a paper backup of a cold wallet
+    ///
+    /// # Arguments
+    /// * `wallet_id` - Cold wallet to back up
+    /// * `derivation_path` - HD derivation path of the wallet's key
+    /// * `seed` - Seed material to include encrypted, or `None` for a
+    ///   public-only backup
+    pub fn paper_backup(
+        &self,
+        wallet_id: &str,
+        derivation_path: &str,
+        seed: Option<SeedExport<'_>>,
+    ) -> Result<PaperBackup, CustodyError> {
+        let wallet = self
+            .get_wallet(wallet_id)
+            .ok_or_else(|| CustodyError::WalletNotFound(wallet_id.to_string()))?;
+        if wallet.wallet_type != WalletType::Cold {
+            return Err(CustodyError::NotColdWallet(wallet_id.to_string()));
+        }
+
+        let encrypted_seed = match seed {
+            None => None,
+            Some(export) => {
+                if export.confirmation != format!("EXPORT SEED {}", wallet_id) {
+                    return Err(CustodyError::SeedExportNotConfirmed(wallet_id.to_string()));
+                }
+                if export.passphrase.chars().count() < MIN_PASSPHRASE_CHARS {
+                    return Err(CustodyError::BackupFailed(format!(
+                        "passphrase must be at least {} characters",
+                        MIN_PASSPHRASE_CHARS
+                    )));
+                }
+                Some(EncryptedSeed::seal(export.seed, export.passphrase)?)
+            }
+        };
+
+        let mut backup = PaperBackup {
+            wallet_id: wallet.id.clone(),
+            asset: wallet.asset.symbol().to_string(),
+            address: wallet.address.clone(),
+            derivation_path: derivation_path.to_string(),
+            created: self.current_timestamp(),
+            encrypted_seed,
+            checksum: String::new(),
+        };
+        backup.checksum = checksum(&backup.payload());
+        Ok(backup)
+    }
+}
+
+fn cipher(passphrase: &str, salt: &[u8]) -> Result<ChaCha20Poly1305, CustodyError> {
+    let mut key = Zeroizing::new([0u8; 32]);
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, key.as_mut())
+        .map_err(backup_failed)?;
+    Ok(ChaCha20Poly1305::new(Key::from_slice(key.as_ref())))
+}
+
+fn checksum(payload: &str) -> String {
+    hex::encode(Sha256::digest(payload.as_bytes()))
+}
+
+fn qr_svg(data: &str) -> Result<String, CustodyError> {
+    let code = QrCode::new(data.as_bytes()).map_err(backup_failed)?;
+    Ok(code.render::<svg::Color>().min_dimensions(200, 200).build())
+}
+
+fn backup_failed(err: impl std::fmt::Display) -> CustodyError {
+    CustodyError::BackupFailed(err.to_string())
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PATH: &str = "m/84'/0'/0'/0/0";
+    const PASSPHRASE: &str = "correct horse battery";
+
+    fn system() -> CustodySystem {
+        let mut system = CustodySystem::new();
+        system
+            .create_wallet(
+                "vault".to_string(),
+                "bc1qvault".to_string(),
+                WalletType::Cold,
+            )
+            .unwrap();
+        system
+            .create_wallet("hot".to_string(), "bc1qhot".to_string(), WalletType::Hot)
+            .unwrap();
+        system
+    }
+
+    fn export(confirmation: &str) -> SeedExport<'_> {
+        SeedExport {
+            seed: b"seed bytes",
+            passphrase: PASSPHRASE,
+            confirmation,
+        }
+    }
+
+    #[test]
+    fn test_public_only_backup() {
+        let backup = system().paper_backup("vault", PATH, None).unwrap();
+        assert!(backup.encrypted_seed.is_none());
+        assert!(backup.verify());
+
+        let html = backup.to_html().unwrap();
+        assert!(html.contains("bc1qvault"));
+        assert!(html.contains("<svg"));
+        assert!(html.contains(&backup.checksum));
+        assert!(!html.contains("Encrypted seed"));
+    }
+
+    #[test]
+    fn test_seed_round_trip() {
+        let backup = system()
+            .paper_backup("vault", PATH, Some(export("EXPORT SEED vault")))
+            .unwrap();
+        let encoded = backup.encrypted_seed.as_ref().unwrap().encode();
+        assert!(!encoded.contains(&hex::encode(b"seed bytes")));
+
+        let seed = EncryptedSeed::decode(&encoded).unwrap();
+        assert_eq!(seed.decrypt(PASSPHRASE).unwrap().as_slice(), b"seed bytes");
+        assert!(seed.decrypt("wrong passphrase").is_err());
+    }
+
+    #[test]
+    fn test_seed_export_requires_confirmation() {
+        let system = system();
+        assert_eq!(
+            system.paper_backup("vault", PATH, Some(export("yes"))),
+            Err(CustodyError::SeedExportNotConfirmed("vault".to_string()))
+        );
+        assert_eq!(
+            system.paper_backup("hot", PATH, None),
+            Err(CustodyError::NotColdWallet("hot".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_tampering_breaks_checksum() {
+        let mut backup = system().paper_backup("vault", PATH, None).unwrap();
+        backup.address = "bc1qattacker".to_string();
+        assert!(!backup.verify());
+    }
+}
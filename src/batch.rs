@@ -0,0 +1,216 @@
+//! Batches of deposits, withdrawals and transfers.
+//!
+//! Settlement files carry thousands of entries that are booked together.
+//! [`CustodySystem::apply_batch`] executes them in order, either atomically,
+//! where one failure leaves the ledger untouched, or best-effort, where
+//! every entry that can be booked is.
+
+use crate::{Amount, CustodyError, CustodySystem};
+use serde::{Deserialize, Serialize};
+
+/// One entry of a batch
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Operation {
+    Deposit {
+        wallet_id: String,
+        amount: Amount,
+    },
+    Withdrawal {
+        wallet_id: String,
+        amount: Amount,
+    },
+    Transfer {
+        from_wallet_id: String,
+        to_wallet_id: String,
+        amount: Amount,
+    },
+}
+
+/// How a batch reacts to a failing entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BatchMode {
+    /// Apply every entry or none
+    Atomic,
+    /// Apply every entry that succeeds and report the others
+    BestEffort,
+}
+
+/// Outcome of a batch, entry by entry
+#[derive(Debug, Clone, PartialEq)]
+pub struct BatchResult {
+    /// Result of each entry, in batch order
+    ///
+    /// When an atomic batch aborts, entries after the failing one report
+    /// [`CustodyError::BatchAborted`] and none of the entries are applied.
+    pub results: Vec<Result<(), CustodyError>>,
+    /// Whether the successful entries were applied to the ledger
+    pub committed: bool,
+}
+
+impl BatchResult {
+    /// Returns true if every entry succeeded
+    pub fn is_success(&self) -> bool {
+        self.results.iter().all(Result::is_ok)
+    }
+
+    /// Number of entries that succeeded
+    pub fn succeeded(&self) -> usize {
+        self.results.iter().filter(|result| result.is_ok()).count()
+    }
+
+    /// Failing entries with their index in the batch
+    pub fn failures(&self) -> Vec<(usize, &CustodyError)> {
+        self.results
+            .iter()
+            .enumerate()
+            .filter_map(|(index, result)| result.as_ref().err().map(|err| (index, err)))
+            .collect()
+    }
+}
+
+impl CustodySystem {
+    /// Executes `ops` in order
+    ///
+    /// An atomic batch is first rehearsed on a [`fork`](Self::fork), so a
+    /// failing entry aborts it before anything is recorded, persisted or
+    /// announced to listeners.
+    ///
+    /// # Example
+    /// ```
+    /// use securevault::{amount, BatchMode, CustodySystem, Operation, WalletType};
+    /// let mut system = CustodySystem::new();
+    /// system.create_wallet("w".to_string(), "0x1".to_string(), WalletType::Hot).unwrap();
+    /// let ops = vec![
+    ///     Operation::Deposit { wallet_id: "w".to_string(), amount: amount!(5) },
+    ///     Operation::Withdrawal { wallet_id: "w".to_string(), amount: amount!(8) },
+    /// ];
+    ///
+    /// let result = system.apply_batch(ops.clone(), BatchMode::Atomic);
+    /// assert!(!result.committed);
+    /// assert!(system.get_all_transactions().is_empty());
+    ///
+    /// let result = system.apply_batch(ops, BatchMode::BestEffort);
+    /// assert_eq!(result.succeeded(), 1);
+    /// assert_eq!(system.get_wallet("w").unwrap().balance, amount!(5));
+    /// ```
+    pub fn apply_batch(&mut self, ops: Vec<Operation>, mode: BatchMode) -> BatchResult {
+        if mode == BatchMode::Atomic {
+            let mut rehearsal = self.fork();
+            let mut results = Vec::with_capacity(ops.len());
+            for (index, op) in ops.iter().enumerate() {
+                let result = rehearsal.apply_operation(op);
+                let failed = result.is_err();
+                results.push(result);
+                if failed {
+                    results.resize(ops.len(), Err(CustodyError::BatchAborted(index)));
+                    return BatchResult {
+                        results,
+                        committed: false,
+                    };
+                }
+            }
+        }
+        let results = ops.iter().map(|op| self.apply_operation(op)).collect();
+        BatchResult {
+            results,
+            committed: true,
+        }
+    }
+
+    fn apply_operation(&mut self, op: &Operation) -> Result<(), CustodyError> {
+        match op {
+            Operation::Deposit { wallet_id, amount } => self.deposit(wallet_id, *amount),
+            Operation::Withdrawal { wallet_id, amount } => self.withdraw(wallet_id, *amount),
+            Operation::Transfer {
+                from_wallet_id,
+                to_wallet_id,
+                amount,
+            } => self.transfer(from_wallet_id, to_wallet_id, *amount),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::WalletType;
+
+    fn system() -> CustodySystem {
+        let mut system = CustodySystem::new();
+        for id in ["a", "b"] {
+            system
+                .create_wallet(id.to_string(), id.to_string(), WalletType::Hot)
+                .unwrap();
+        }
+        system.deposit("a", amount!(10)).unwrap();
+        system
+    }
+
+    fn settlement() -> Vec<Operation> {
+        vec![
+            Operation::Transfer {
+                from_wallet_id: "a".to_string(),
+                to_wallet_id: "b".to_string(),
+                amount: amount!(6),
+            },
+            Operation::Withdrawal {
+                wallet_id: "a".to_string(),
+                amount: amount!(6),
+            },
+            Operation::Deposit {
+                wallet_id: "b".to_string(),
+                amount: amount!(1),
+            },
+        ]
+    }
+
+    #[test]
+    fn test_atomic_batch_is_all_or_nothing() {
+        let mut system = system();
+        let result = system.apply_batch(settlement(), BatchMode::Atomic);
+        assert!(!result.committed);
+        assert_eq!(
+            result.results,
+            [
+                Ok(()),
+                Err(CustodyError::InsufficientBalance {
+                    available: amount!(4),
+                    requested: amount!(6),
+                }),
+                Err(CustodyError::BatchAborted(1)),
+            ]
+        );
+        assert_eq!(system.get_wallet("a").unwrap().balance, amount!(10));
+        assert_eq!(system.get_all_transactions().len(), 1);
+
+        let mut ops = settlement();
+        ops.remove(1);
+        let result = system.apply_batch(ops, BatchMode::Atomic);
+        assert!(result.is_success() && result.committed);
+        assert_eq!(system.get_wallet("b").unwrap().balance, amount!(7));
+    }
+
+    #[test]
+    fn test_best_effort_reports_each_entry() {
+        let mut system = system();
+        let result = system.apply_batch(settlement(), BatchMode::BestEffort);
+        assert!(result.committed);
+        assert_eq!(result.succeeded(), 2);
+        assert_eq!(result.failures()[0].0, 1);
+        assert_eq!(system.get_wallet("a").unwrap().balance, amount!(4));
+        assert_eq!(system.get_wallet("b").unwrap().balance, amount!(7));
+    }
+
+    #[test]
+    fn test_operations_parse_from_settlement_json() {
+        let json = r#"[
+            {"op": "deposit", "wallet_id": "a", "amount": "1.5"},
+            {"op": "transfer", "from_wallet_id": "a", "to_wallet_id": "b", "amount": "2"}
+        ]"#;
+        let ops: Vec<Operation> = serde_json::from_str(json).unwrap();
+        let mut system = system();
+        assert!(system.apply_batch(ops, BatchMode::Atomic).is_success());
+        assert_eq!(system.get_wallet("a").unwrap().balance, amount!(9.5));
+    }
+}
@@ -0,0 +1,437 @@
+//! Deposit detection through a Bitcoin Core node (feature `bitcoin-rpc`).
+//!
+//! A [`BitcoinDepositWatcher`] polls the node's `listsinceblock` for
+//! payments to custody addresses, which must be imported into the node's
+//! wallet (watch-only is enough), and feeds them to
+//! [`CustodySystem::ingest_external_deposit`]. Each poll asks for
+//! everything since the block the previous poll stopped at, held back by
+//! the confirmations BTC deposits need, so a payment keeps being reported
+//! with its growing confirmation count until it is credited.
+//!
+//! Call [`poll`](BitcoinDepositWatcher::poll) on a timer; the watcher keeps
+//! no thread of its own. The client also broadcasts signed withdrawals as
+//! a [`Broadcaster`] and, with the `psbt` feature, lists the unspent
+//! outputs PSBTs spend as a [`UtxoSource`](crate::UtxoSource).
+
+use crate::http;
+use crate::{
+    Amount, Asset, Broadcaster, CustodyError, CustodySystem, DepositStatus, FeeEstimator, FeeRate,
+    TxHash,
+};
+use serde_json::{json, Value};
+use std::time::Duration;
+
+/// A payment the node's wallet received
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodeTransaction {
+    pub txid: String,
+    pub address: String,
+    pub amount: Amount,
+    /// Negative for transactions conflicting with the best chain
+    pub confirmations: i64,
+}
+
+/// Payments received since a block, and the block to continue from
+#[derive(Debug, Clone, PartialEq)]
+pub struct SinceBlock {
+    pub transactions: Vec<NodeTransaction>,
+    pub last_block: String,
+}
+
+impl SinceBlock {
+    /// Reads the result of `listsinceblock`, keeping only receipts
+    fn from_json(result: &Value) -> Result<Self, String> {
+        let malformed = || "malformed listsinceblock result".to_string();
+        let last_block = result["lastblock"].as_str().ok_or_else(malformed)?;
+        let entries = result["transactions"].as_array().ok_or_else(malformed)?;
+        let transactions = entries
+            .iter()
+            .filter(|entry| entry["category"] == "receive")
+            .map(|entry| {
+                let amount = entry["amount"].as_number().ok_or_else(malformed)?;
+                Ok(NodeTransaction {
+                    txid: entry["txid"].as_str().ok_or_else(malformed)?.to_string(),
+                    address: entry["address"].as_str().ok_or_else(malformed)?.to_string(),
+                    amount: amount.to_string().parse().map_err(|_| malformed())?,
+                    confirmations: entry["confirmations"].as_i64().ok_or_else(malformed)?,
+                })
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(Self {
+            transactions,
+            last_block: last_block.to_string(),
+        })
+    }
+}
+
+/// A source of wallet transactions from a Bitcoin node
+pub trait BitcoinNode: Send + Sync {
+    /// Lists payments received in blocks after `block`, or ever if `None`,
+    /// with the block that is `target_confirmations` deep as the place to
+    /// continue from
+    fn list_since_block(
+        &self,
+        block: Option<&str>,
+        target_confirmations: u32,
+    ) -> Result<SinceBlock, String>;
+}
+
+/// JSON-RPC client for a Bitcoin Core node on a plain `http://` URL
+#[derive(Debug, Clone)]
+pub struct BitcoinRpcClient {
+    url: String,
+    authorization: String,
+    timeout: Duration,
+}
+
+impl BitcoinRpcClient {
+    /// Connects to `url`, e.g. `http://127.0.0.1:8332/wallet/custody`,
+    /// with the node's `rpcuser` and `rpcpassword`
+    pub fn new(url: &str, user: &str, password: &str) -> Self {
+        Self {
+            url: url.to_string(),
+            authorization: format!(
+                "Basic {}",
+                base64(format!("{}:{}", user, password).as_bytes())
+            ),
+            timeout: Duration::from_secs(30),
+        }
+    }
+
+    /// Sets the request timeout
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Calls `method` and returns its result
+    pub fn call(&self, method: &str, params: Value) -> Result<Value, String> {
+        let request = json!({
+            "jsonrpc": "1.0",
+            "id": "securevault",
+            "method": method,
+            "params": params,
+        });
+        let headers = [
+            ("Authorization", self.authorization.clone()),
+            ("Content-Type", "application/json".to_string()),
+        ];
+        let response = http::send(
+            "POST",
+            &self.url,
+            &headers,
+            request.to_string().as_bytes(),
+            self.timeout,
+        )?;
+        // Failed calls come back as 500 with the error in the body
+        let Ok(mut reply) = serde_json::from_slice::<Value>(&response.body) else {
+            return Err(format!("HTTP {}", response.status));
+        };
+        match reply["error"].take() {
+            Value::Null => Ok(reply["result"].take()),
+            error => Err(error["message"]
+                .as_str()
+                .map_or_else(|| error.to_string(), str::to_string)),
+        }
+    }
+}
+
+impl BitcoinNode for BitcoinRpcClient {
+    fn list_since_block(
+        &self,
+        block: Option<&str>,
+        target_confirmations: u32,
+    ) -> Result<SinceBlock, String> {
+        let result = self.call(
+            "listsinceblock",
+            json!([block.unwrap_or(""), target_confirmations.max(1), true]),
+        )?;
+        SinceBlock::from_json(&result)
+    }
+}
+
+impl Broadcaster for BitcoinRpcClient {
+    fn broadcast(&self, raw_tx: &[u8]) -> Result<TxHash, String> {
+        let txid = self.call("sendrawtransaction", json!([hex(raw_tx)]))?;
+        txid.as_str()
+            .map(str::to_string)
+            .ok_or_else(|| "malformed sendrawtransaction result".to_string())
+    }
+
+    fn confirmations(&self, tx_hash: &str) -> Result<u32, String> {
+        // Needs `txindex` for transactions the node's wallet is not party to
+        let tx = self.call("getrawtransaction", json!([tx_hash, true]))?;
+        let confirmations = tx["confirmations"].as_u64().unwrap_or(0);
+        Ok(u32::try_from(confirmations).unwrap_or(u32::MAX))
+    }
+
+    fn fee_paid(&self, tx_hash: &str) -> Result<Option<Amount>, String> {
+        // Only the node's wallet knows the inputs, and so the fee
+        let tx = self.call("gettransaction", json!([tx_hash]))?;
+        let Some(fee) = tx["fee"].as_number() else {
+            return Ok(None);
+        };
+        let fee: Amount = fee
+            .to_string()
+            .parse()
+            .map_err(|_| format!("malformed fee {}", fee))?;
+        Ok(Some(fee.abs()))
+    }
+}
+
+/// Blocks within which estimated fees aim to confirm
+pub const FEE_TARGET_BLOCKS: u32 = 6;
+
+impl FeeEstimator for BitcoinRpcClient {
+    fn fee_rate(&self, asset: &Asset) -> Result<FeeRate, String> {
+        if *asset != Asset::Btc {
+            return Err(format!("no fee rate for {}", asset));
+        }
+        let estimate = self.call("estimatesmartfee", json!([FEE_TARGET_BLOCKS]))?;
+        let Some(feerate) = estimate["feerate"].as_number() else {
+            return Err(format!("node has no fee estimate: {}", estimate["errors"]));
+        };
+        // BTC per kilo-virtual-byte, rounded up to whole sat/vB
+        let sat_per_kvb = feerate
+            .to_string()
+            .parse::<Amount>()
+            .ok()
+            .and_then(|btc| btc.round_dp(8).to_minor_units(8))
+            .and_then(|sats| u64::try_from(sats).ok())
+            .ok_or_else(|| format!("malformed fee rate {}", feerate))?;
+        Ok(FeeRate::SatPerVbyte(sat_per_kvb.div_ceil(1000).max(1)))
+    }
+}
+
+#[cfg(feature = "psbt")]
+impl crate::UtxoSource for BitcoinRpcClient {
+    fn unspent(&self, address: &str) -> Result<Vec<crate::Utxo>, String> {
+        let unspent = self.call("listunspent", json!([1, 9_999_999, [address]]))?;
+        let outputs = unspent.as_array().ok_or("malformed listunspent reply")?;
+        outputs
+            .iter()
+            .map(|output| {
+                let amount = output["amount"]
+                    .as_number()
+                    .and_then(|amount| amount.to_string().parse().ok())
+                    .ok_or_else(|| format!("malformed amount in {}", output))?;
+                Ok(crate::Utxo {
+                    txid: output["txid"]
+                        .as_str()
+                        .ok_or_else(|| format!("missing txid in {}", output))?
+                        .to_string(),
+                    vout: output["vout"]
+                        .as_u64()
+                        .and_then(|vout| u32::try_from(vout).ok())
+                        .ok_or_else(|| format!("missing vout in {}", output))?,
+                    amount,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Outcome of one poll
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PollReport {
+    /// Transaction hashes of deposits credited by this poll
+    pub credited: Vec<String>,
+    /// Transaction hashes of deposits still awaiting confirmations
+    pub pending: Vec<String>,
+    /// Payments the ledger refused, with the reason
+    pub rejected: Vec<(String, CustodyError)>,
+}
+
+/// Polls a Bitcoin node for deposits to custody addresses
+pub struct BitcoinDepositWatcher {
+    node: Box<dyn BitcoinNode>,
+    last_block: Option<String>,
+}
+
+impl BitcoinDepositWatcher {
+    /// Watches everything the node's wallet has received
+    pub fn new(node: Box<dyn BitcoinNode>) -> Self {
+        Self {
+            node,
+            last_block: None,
+        }
+    }
+
+    /// Starts from `block` instead, e.g. the block a previous run
+    /// stopped at
+    pub fn from_block(node: Box<dyn BitcoinNode>, block: &str) -> Self {
+        Self {
+            node,
+            last_block: Some(block.to_string()),
+        }
+    }
+
+    /// Gets the block the next poll continues from
+    pub fn last_block(&self) -> Option<&str> {
+        self.last_block.as_deref()
+    }
+
+    /// Fetches new payments and confirmations from the node and ingests
+    /// those to custody addresses
+    ///
+    /// Payments to addresses the ledger does not watch, deposits already
+    /// credited and transactions that fell out of the best chain are
+    /// skipped.
+    ///
+    /// # Errors
+    /// [`CustodyError::NodeError`] if the node cannot be queried; the
+    /// watcher then retries from the same block on the next poll
+    pub fn poll(&mut self, system: &mut CustodySystem) -> Result<PollReport, CustodyError> {
+        let since = self
+            .node
+            .list_since_block(
+                self.last_block.as_deref(),
+                system.confirmations_required(&Asset::Btc),
+            )
+            .map_err(CustodyError::NodeError)?;
+
+        let mut report = PollReport::default();
+        for tx in since.transactions {
+            let Ok(confirmations) = u32::try_from(tx.confirmations) else {
+                continue;
+            };
+            if system.wallet_for_address(&tx.address).is_none() {
+                continue;
+            }
+            match system.ingest_external_deposit(&tx.address, tx.amount, &tx.txid, confirmations) {
+                Ok(DepositStatus::Credited) => report.credited.push(tx.txid),
+                Ok(DepositStatus::Pending) => report.pending.push(tx.txid),
+                Err(CustodyError::DuplicateReference(_)) => {}
+                Err(error) => report.rejected.push((tx.txid, error)),
+            }
+        }
+        self.last_block = Some(since.last_block);
+        Ok(report)
+    }
+}
+
+/// Lower-case hex, as raw transactions are sent
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Standard base64 with padding, for the basic auth header
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let group = chunk.iter().enumerate().fold(0u32, |group, (i, byte)| {
+            group | u32::from(*byte) << (16 - 8 * i)
+        });
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(char::from(ALPHABET[(group >> (18 - 6 * i) & 63) as usize]));
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::WalletType;
+    use std::sync::{Arc, Mutex};
+
+    /// Block and target confirmations of a `listsinceblock` call
+    type Request = (Option<String>, u32);
+
+    /// Serves canned `listsinceblock` results, recording the requests
+    #[derive(Clone, Default)]
+    struct FakeNode {
+        replies: Arc<Mutex<Vec<Result<Value, String>>>>,
+        requests: Arc<Mutex<Vec<Request>>>,
+    }
+
+    impl BitcoinNode for FakeNode {
+        fn list_since_block(
+            &self,
+            block: Option<&str>,
+            target_confirmations: u32,
+        ) -> Result<SinceBlock, String> {
+            let mut requests = self.requests.lock().unwrap();
+            requests.push((block.map(str::to_string), target_confirmations));
+            let reply = self.replies.lock().unwrap().remove(0)?;
+            SinceBlock::from_json(&reply)
+        }
+    }
+
+    fn receipt(txid: &str, address: &str, amount: f64, confirmations: i64) -> Value {
+        json!({
+            "category": "receive",
+            "txid": txid,
+            "address": address,
+            "amount": amount,
+            "confirmations": confirmations,
+        })
+    }
+
+    #[test]
+    fn test_deposits_tracked_until_confirmed() {
+        let mut system = CustodySystem::new();
+        system
+            .create_wallet("w".to_string(), "bc1qcustody".to_string(), WalletType::Hot)
+            .unwrap();
+        system.set_confirmation_policy(Asset::Btc, 3);
+
+        let node = FakeNode::default();
+        *node.replies.lock().unwrap() = vec![
+            Ok(json!({
+                "lastblock": "b1",
+                "transactions": [
+                    receipt("aa", "bc1qcustody", 0.25, 1),
+                    receipt("bb", "bc1qsomeoneelse", 9.0, 1),
+                    receipt("cc", "bc1qcustody", 1.0, -1),
+                    {"category": "send", "txid": "dd", "address": "bc1qcustody",
+                     "amount": -0.1, "confirmations": 1},
+                ],
+            })),
+            Err("connection refused".to_string()),
+            Ok(json!({
+                "lastblock": "b2",
+                "transactions": [receipt("aa", "bc1qcustody", 0.25, 3)],
+            })),
+        ];
+        let mut watcher = BitcoinDepositWatcher::new(Box::new(node.clone()));
+
+        let report = watcher.poll(&mut system).unwrap();
+        assert_eq!(report.pending, ["aa"]);
+        assert!(system.get_wallet("w").unwrap().balance.is_zero());
+        assert_eq!(watcher.last_block(), Some("b1"));
+
+        assert_eq!(
+            watcher.poll(&mut system),
+            Err(CustodyError::NodeError("connection refused".to_string()))
+        );
+        assert_eq!(watcher.last_block(), Some("b1"));
+
+        let report = watcher.poll(&mut system).unwrap();
+        assert_eq!(report.credited, ["aa"]);
+        assert_eq!(system.get_wallet("w").unwrap().balance, amount!(0.25));
+        assert_eq!(
+            *node.requests.lock().unwrap(),
+            [
+                (None, 3),
+                (Some("b1".to_string()), 3),
+                (Some("b1".to_string()), 3)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_basic_auth_encoding() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        let client = BitcoinRpcClient::new("http://127.0.0.1:8332", "user", "pass");
+        assert_eq!(client.authorization, "Basic dXNlcjpwYXNz");
+    }
+}
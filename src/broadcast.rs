@@ -0,0 +1,423 @@
+//! Broadcasting signed withdrawals to their chain.
+//!
+//! [`CustodySystem::broadcast_withdrawal`] takes a withdrawal end to end:
+//! it runs the usual withdrawal checks, has the [`Signer`] sign it, puts a
+//! hold on the funds and hands the signed transaction to a
+//! [`Broadcaster`]. [`CustodySystem::track_broadcasts`] then follows the
+//! on-chain transaction; once it has the confirmations its asset needs the
+//! hold is captured as a withdrawal whose reference is the on-chain hash.
+//!
+//! Signers used for broadcasting return the fully serialised signed
+//! transaction as their signature bytes, which is what the node expects.
+//! The Bitcoin Core client (feature `bitcoin-rpc`) and the Ethereum client
+//! (feature `eth-rpc`) are broadcasters; other chains plug in their own.
+
+use crate::fees::{ACTUAL_FEE_METADATA_KEY, ESTIMATED_FEE_METADATA_KEY};
+use crate::holds::HoldStatus;
+use crate::precheck::Authorization;
+use crate::signer::{Signer, UnsignedTx};
+use crate::time::Timestamp;
+use crate::{Amount, Asset, CustodyError, CustodySystem, HoldId, OperationKind, ScreeningRequest};
+
+/// Hash identifying a transaction on its chain
+pub type TxHash = String;
+
+/// Submits signed transactions to a chain and follows them
+pub trait Broadcaster: Send + Sync {
+    /// Sends the serialised signed transaction, returning its hash
+    fn broadcast(&self, raw_tx: &[u8]) -> Result<TxHash, String>;
+
+    /// Returns the confirmations of a broadcast transaction, 0 while it
+    /// waits to be mined
+    fn confirmations(&self, tx_hash: &str) -> Result<u32, String>;
+
+    /// Returns the network fee a mined transaction paid, if the chain
+    /// tells
+    fn fee_paid(&self, _tx_hash: &str) -> Result<Option<Amount>, String> {
+        Ok(None)
+    }
+}
+
+/// Lifecycle of a broadcast withdrawal
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BroadcastStatus {
+    /// Sent to the chain; the funds are on hold
+    Broadcast,
+    /// Confirmed and booked as a withdrawal
+    Confirmed,
+    /// Given up on, e.g. dropped from the mempool; the hold was released
+    Abandoned,
+}
+
+/// A withdrawal sent to its chain
+#[derive(Debug, Clone, PartialEq)]
+pub struct BroadcastTx {
+    /// Id of the prepared withdrawal it was signed from
+    pub id: u64,
+    pub wallet_id: String,
+    pub asset: Asset,
+    pub destination: String,
+    pub amount: Amount,
+    /// Hash of the latest payout sent
+    pub tx_hash: TxHash,
+    /// Payouts it replaced, e.g. with a higher fee; any of them may be
+    /// the one that is mined
+    pub replaced: Vec<TxHash>,
+    /// Transaction sent to cancel the payout, if any
+    pub cancellation: Option<TxHash>,
+    /// Account nonce, for withdrawals from Ethereum wallets
+    pub nonce: Option<u64>,
+    pub hold: HoldId,
+    /// Network fee quoted when it was prepared
+    pub estimated_fee: Option<Amount>,
+    /// Network fee paid, once mined
+    pub actual_fee: Option<Amount>,
+    pub broadcast_at: Timestamp,
+    /// Confirmations seen when last tracked
+    pub confirmations: u32,
+    pub status: BroadcastStatus,
+    /// Ledger transaction booking the withdrawal, once confirmed
+    pub transaction_id: Option<u64>,
+}
+
+impl CustodySystem {
+    /// Checks, signs and broadcasts a withdrawal, holding the funds until
+    /// it confirms
+    ///
+    /// # Errors
+    /// The withdrawal's check or signing error, or
+    /// [`CustodyError::NodeError`] if the chain refuses the transaction;
+    /// nothing stays pending or held then. A withdrawal the compliance
+    /// screener flags is queued for review like any signed withdrawal and
+    /// not broadcast.
+    ///
+    /// # Example
+    /// ```
+    /// use securevault::{amount, Broadcaster, CallbackSigner, CustodySystem, WalletType};
+    ///
+    /// struct Node;
+    /// impl Broadcaster for Node {
+    ///     fn broadcast(&self, _raw_tx: &[u8]) -> Result<String, String> {
+    ///         Ok("f00d".to_string())
+    ///     }
+    ///     fn confirmations(&self, _tx_hash: &str) -> Result<u32, String> {
+    ///         Ok(6)
+    ///     }
+    /// }
+    ///
+    /// let mut system = CustodySystem::new();
+    /// system.create_wallet("w".to_string(), "bc1qcustody".to_string(), WalletType::Hot).unwrap();
+    /// system.deposit("w", amount!(1)).unwrap();
+    /// let signer = CallbackSigner::new("node-wallet", |_| Ok(vec![0x02, 0x00]));
+    ///
+    /// let sent = system.broadcast_withdrawal("w", "bc1qdest", amount!(0.4), &signer, &Node).unwrap();
+    /// assert_eq!(system.available_balance("w"), Some(amount!(0.6)));
+    ///
+    /// assert_eq!(system.track_broadcasts(&Node).unwrap(), [sent.id]);
+    /// let booked = system.get_wallet_transactions("w").pop().unwrap();
+    /// assert_eq!(booked.reference.as_deref(), Some("f00d"));
+    /// assert_eq!(system.get_wallet("w").unwrap().balance, amount!(0.6));
+    /// ```
+    pub fn broadcast_withdrawal(
+        &mut self,
+        wallet_id: &str,
+        destination: &str,
+        amount: Amount,
+        signer: &dyn Signer,
+        broadcaster: &dyn Broadcaster,
+    ) -> Result<BroadcastTx, CustodyError> {
+        let tx = self.prepare_withdrawal(wallet_id, destination, amount)?;
+        // Taken out of the signing queue with its nonce still reserved
+        self.unsigned_txs.remove(&tx.id);
+        let sent = self.send_prepared(&tx, signer, broadcaster);
+        if let (Err(_), Some(nonce)) = (&sent, tx.nonce) {
+            self.release_nonce(wallet_id, nonce);
+        }
+        sent
+    }
+
+    fn send_prepared(
+        &mut self,
+        tx: &UnsignedTx,
+        signer: &dyn Signer,
+        broadcaster: &dyn Broadcaster,
+    ) -> Result<BroadcastTx, CustodyError> {
+        let request = ScreeningRequest {
+            kind: OperationKind::Withdrawal,
+            wallet_id: tx.wallet_id.clone(),
+            asset: tx.asset.clone(),
+            amount: tx.amount,
+            destination: Some(tx.destination.clone()),
+            to_wallet_id: None,
+        };
+        self.screen(request, Authorization::Signed)?;
+        let signature = signer.sign(&tx.payload())?;
+        if signature.bytes.is_empty() {
+            return Err(CustodyError::SignatureRejected(
+                "signature is empty".to_string(),
+            ));
+        }
+        let reason = format!("broadcast #{}", tx.id);
+        let hold = self.place_hold(&tx.wallet_id, tx.amount, &reason)?;
+        let tx_hash = match broadcaster.broadcast(&signature.bytes) {
+            Ok(tx_hash) => tx_hash,
+            Err(message) => {
+                self.release_hold(hold)?;
+                return Err(CustodyError::NodeError(message));
+            }
+        };
+        if let Some(nonce) = tx.nonce {
+            self.nonce_broadcast(&tx.wallet_id, nonce, &tx_hash);
+        }
+        let sent = BroadcastTx {
+            id: tx.id,
+            wallet_id: tx.wallet_id.clone(),
+            asset: tx.asset.clone(),
+            destination: tx.destination.clone(),
+            amount: tx.amount,
+            tx_hash,
+            replaced: Vec::new(),
+            cancellation: None,
+            nonce: tx.nonce,
+            hold,
+            estimated_fee: tx.fee.as_ref().map(|fee| fee.fee),
+            actual_fee: None,
+            broadcast_at: self.current_timestamp(),
+            confirmations: 0,
+            status: BroadcastStatus::Broadcast,
+            transaction_id: None,
+        };
+        self.broadcasts.insert(sent.id, sent.clone());
+        Ok(sent)
+    }
+
+    /// Updates the confirmations of broadcast withdrawals, booking those
+    /// that reached their asset's threshold
+    ///
+    /// A withdrawal whose cancellation is mined instead is abandoned and
+    /// its funds released. Transactions left unconfirmed for too long are
+    /// reported as stuck, see
+    /// [`check_stuck_transactions`](Self::check_stuck_transactions).
+    ///
+    /// # Returns
+    /// Ids of the withdrawals booked
+    ///
+    /// # Errors
+    /// [`CustodyError::NodeError`] if the chain cannot be queried, or
+    /// [`CustodyError::StorageFailed`] if a booking cannot be written;
+    /// withdrawals booked before the failure stay booked
+    pub fn track_broadcasts(
+        &mut self,
+        broadcaster: &dyn Broadcaster,
+    ) -> Result<Vec<u64>, CustodyError> {
+        let pending: Vec<BroadcastTx> = self
+            .broadcasts
+            .values()
+            .filter(|sent| sent.status == BroadcastStatus::Broadcast)
+            .cloned()
+            .collect();
+        let mut booked = Vec::new();
+        for mut sent in pending {
+            let required = self.confirmations_required(&sent.asset);
+            let mut mined = None;
+            // Whichever transaction sharing the nonce gets mined settles it
+            let candidates = sent
+                .cancellation
+                .iter()
+                .chain(std::iter::once(&sent.tx_hash).chain(sent.replaced.iter().rev()));
+            for tx_hash in candidates {
+                let confirmations = broadcaster
+                    .confirmations(tx_hash)
+                    .map_err(CustodyError::NodeError)?;
+                if confirmations > 0 {
+                    sent.confirmations = confirmations;
+                    mined = Some(tx_hash.clone());
+                    break;
+                }
+            }
+            let Some(tx_hash) = mined.filter(|_| sent.confirmations >= required) else {
+                self.broadcasts.insert(sent.id, sent);
+                continue;
+            };
+            if let Some(nonce) = sent.nonce {
+                self.nonce_mined(&sent.wallet_id, nonce);
+            }
+            if sent.cancellation.as_ref() == Some(&tx_hash) {
+                sent.status = BroadcastStatus::Abandoned;
+                self.release_hold(sent.hold)?;
+            } else {
+                // A fee the chain will not report leaves the record without it
+                sent.actual_fee = broadcaster.fee_paid(&tx_hash).ok().flatten();
+                sent.transaction_id = Some(self.next_transaction_id);
+                // If the booking cannot be written the broadcast is tracked
+                // again next time
+                self.settle_withdrawal(&sent.wallet_id, sent.asset.clone(), sent.amount)?;
+                // The funds left on chain; only the hold kept them here
+                self.set_hold_status(sent.hold, HoldStatus::Captured);
+                let fees = [
+                    (ESTIMATED_FEE_METADATA_KEY, sent.estimated_fee),
+                    (ACTUAL_FEE_METADATA_KEY, sent.actual_fee),
+                ];
+                let reference = tx_hash.clone();
+                sent.tx_hash = tx_hash;
+                sent.status = BroadcastStatus::Confirmed;
+                booked.push(sent.id);
+                self.broadcasts.insert(sent.id, sent);
+                self.update_last_transaction(|tx| {
+                    tx.reference = Some(reference);
+                    for (key, fee) in fees {
+                        if let Some(fee) = fee {
+                            tx.metadata.insert(key.to_string(), fee.to_string());
+                        }
+                    }
+                })?;
+                continue;
+            }
+            self.broadcasts.insert(sent.id, sent);
+        }
+        self.check_stuck_transactions();
+        Ok(booked)
+    }
+
+    /// Gives up on a broadcast withdrawal that will not confirm, releasing
+    /// its funds
+    ///
+    /// Only for transactions known to be gone from the chain, e.g. dropped
+    /// from the mempool or replaced; a late confirmation would leave the
+    /// ledger short of the withdrawal.
+    pub fn abandon_broadcast(&mut self, id: u64) -> Result<(), CustodyError> {
+        let sent = self
+            .broadcasts
+            .get_mut(&id)
+            .filter(|sent| sent.status == BroadcastStatus::Broadcast)
+            .ok_or(CustodyError::BroadcastNotFound(id))?;
+        sent.status = BroadcastStatus::Abandoned;
+        let (wallet_id, nonce, hold) = (sent.wallet_id.clone(), sent.nonce, sent.hold);
+        if let Some(nonce) = nonce {
+            self.release_nonce(&wallet_id, nonce);
+        }
+        self.release_hold(hold)
+    }
+
+    /// Gets a broadcast withdrawal by id
+    pub fn get_broadcast(&self, id: u64) -> Option<&BroadcastTx> {
+        self.broadcasts.get(&id)
+    }
+
+    /// Lists broadcast withdrawals awaiting confirmations, oldest first
+    pub fn pending_broadcasts(&self) -> Vec<&BroadcastTx> {
+        self.broadcasts
+            .values()
+            .filter(|sent| sent.status == BroadcastStatus::Broadcast)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CallbackSigner, WalletType};
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    /// Accepts transactions and reports whatever confirmations tests set
+    #[derive(Default)]
+    struct FakeChain {
+        sent: Mutex<Vec<Vec<u8>>>,
+        confirmations: Mutex<HashMap<String, u32>>,
+        down: bool,
+    }
+
+    impl FakeChain {
+        fn confirm(&self, tx_hash: &str, confirmations: u32) {
+            let mut seen = self.confirmations.lock().unwrap();
+            seen.insert(tx_hash.to_string(), confirmations);
+        }
+    }
+
+    impl Broadcaster for FakeChain {
+        fn broadcast(&self, raw_tx: &[u8]) -> Result<TxHash, String> {
+            if self.down {
+                return Err("connection refused".to_string());
+            }
+            let mut sent = self.sent.lock().unwrap();
+            sent.push(raw_tx.to_vec());
+            Ok(format!("tx{}", sent.len()))
+        }
+
+        fn confirmations(&self, tx_hash: &str) -> Result<u32, String> {
+            let seen = self.confirmations.lock().unwrap();
+            Ok(seen.get(tx_hash).copied().unwrap_or(0))
+        }
+    }
+
+    fn setup() -> (CustodySystem, CallbackSigner) {
+        let mut system = CustodySystem::new();
+        system
+            .create_wallet("w".to_string(), "bc1qw".to_string(), WalletType::Hot)
+            .unwrap();
+        system.deposit("w", amount!(10)).unwrap();
+        system.set_confirmation_policy(Asset::Btc, 2);
+        let signer = CallbackSigner::new("k", |payload| Ok(payload[..4].to_vec()));
+        (system, signer)
+    }
+
+    #[test]
+    fn test_withdrawal_booked_once_confirmed() {
+        let (mut system, signer) = setup();
+        let chain = FakeChain::default();
+        let sent = system
+            .broadcast_withdrawal("w", "bc1qdest", amount!(3), &signer, &chain)
+            .unwrap();
+        assert_eq!(sent.tx_hash, "tx1");
+        assert_eq!(chain.sent.lock().unwrap()[0], b"secu");
+        assert!(system.unsigned_transactions().is_empty());
+        assert_eq!(system.available_balance("w"), Some(amount!(7)));
+        assert!(system.withdraw("w", amount!(8)).is_err());
+
+        chain.confirm("tx1", 1);
+        assert!(system.track_broadcasts(&chain).unwrap().is_empty());
+        assert_eq!(system.get_broadcast(sent.id).unwrap().confirmations, 1);
+        assert_eq!(system.get_wallet("w").unwrap().balance, amount!(10));
+
+        chain.confirm("tx1", 2);
+        assert_eq!(system.track_broadcasts(&chain).unwrap(), [sent.id]);
+        let booked = system.get_broadcast(sent.id).unwrap();
+        assert_eq!(booked.status, BroadcastStatus::Confirmed);
+        let tx = system
+            .get_transaction(booked.transaction_id.unwrap())
+            .unwrap();
+        assert_eq!(tx.reference.as_deref(), Some("tx1"));
+        assert_eq!(system.get_wallet("w").unwrap().balance, amount!(7));
+        assert_eq!(system.available_balance("w"), Some(amount!(7)));
+        assert!(system.pending_broadcasts().is_empty());
+    }
+
+    #[test]
+    fn test_failed_broadcast_and_abandon_release_funds() {
+        let (mut system, signer) = setup();
+        let down = FakeChain {
+            down: true,
+            ..FakeChain::default()
+        };
+        assert_eq!(
+            system.broadcast_withdrawal("w", "bc1qdest", amount!(3), &signer, &down),
+            Err(CustodyError::NodeError("connection refused".to_string()))
+        );
+        assert_eq!(system.available_balance("w"), Some(amount!(10)));
+        assert!(system.unsigned_transactions().is_empty());
+
+        let chain = FakeChain::default();
+        let sent = system
+            .broadcast_withdrawal("w", "bc1qdest", amount!(3), &signer, &chain)
+            .unwrap();
+        system.abandon_broadcast(sent.id).unwrap();
+        assert_eq!(system.available_balance("w"), Some(amount!(10)));
+        assert_eq!(
+            system.abandon_broadcast(sent.id),
+            Err(CustodyError::BroadcastNotFound(sent.id))
+        );
+        chain.confirm("tx1", 6);
+        assert!(system.track_broadcasts(&chain).unwrap().is_empty());
+    }
+}
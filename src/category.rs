@@ -0,0 +1,228 @@
+//! Transaction categories and category reporting.
+//!
+//! Transactions can be categorised when they are created or retroactively,
+//! and [`CustodySystem::category_report`] breaks down flows by category for
+//! a period.
+
+use crate::time::Timestamp;
+use crate::{Amount, Asset, CustodyError, CustodySystem};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Business purpose of a transaction
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Category {
+    Operations,
+    Payroll,
+    Rebalancing,
+    ClientWithdrawal,
+    /// Any other category, by name
+    Other(String),
+}
+
+impl fmt::Display for Category {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Category::Operations => f.write_str("operations"),
+            Category::Payroll => f.write_str("payroll"),
+            Category::Rebalancing => f.write_str("rebalancing"),
+            Category::ClientWithdrawal => f.write_str("client withdrawal"),
+            Category::Other(name) => f.write_str(name),
+        }
+    }
+}
+
+/// Flows of one asset within one category
+#[derive(Debug, Clone, PartialEq)]
+pub struct CategoryFlow {
+    /// `None` for uncategorised transactions
+    pub category: Option<Category>,
+    pub asset: Asset,
+    /// Sum of credits (deposits, conversion proceeds)
+    pub inflow: Amount,
+    /// Sum of debits (withdrawals, conversion costs)
+    pub outflow: Amount,
+    pub transactions: usize,
+}
+
+impl CategoryFlow {
+    /// Inflow minus outflow
+    pub fn net(&self) -> Amount {
+        self.inflow - self.outflow
+    }
+}
+
+/// Breakdown of flows by category over a period
+#[derive(Debug, Clone, PartialEq)]
+pub struct CategoryReport {
+    /// Start of the period (inclusive)
+    pub from: Timestamp,
+    /// End of the period (exclusive)
+    pub to: Timestamp,
+    /// One row per category and asset, uncategorised rows first
+    pub flows: Vec<CategoryFlow>,
+}
+
+impl CategoryReport {
+    /// Returns the rows for `category`
+    pub fn flows_for(&self, category: Option<&Category>) -> Vec<&CategoryFlow> {
+        self.flows
+            .iter()
+            .filter(|flow| flow.category.as_ref() == category)
+            .collect()
+    }
+}
+
+impl CustodySystem {
+    /// Deposits funds and records the transaction under `category`
+    pub fn deposit_with_category(
+        &mut self,
+        id: &str,
+        amount: Amount,
+        category: Category,
+    ) -> Result<(), CustodyError> {
+        self.deposit(id, amount)?;
+        self.categorize_last(category)
+    }
+
+    /// Withdraws funds and records the transaction under `category`
+    pub fn withdraw_with_category(
+        &mut self,
+        id: &str,
+        amount: Amount,
+        category: Category,
+    ) -> Result<(), CustodyError> {
+        self.withdraw(id, amount)?;
+        self.categorize_last(category)
+    }
+
+    /// Sets or clears the category of the transaction at `index` in the log
+    pub fn categorize_transaction(
+        &mut self,
+        index: usize,
+        category: Option<Category>,
+    ) -> Result<(), CustodyError> {
+        self.check_transaction_open(index)?;
+        if self.update_transaction(index, |tx| tx.category = category)? {
+            Ok(())
+        } else {
+            Err(CustodyError::TransactionNotFound(index))
+        }
+    }
+
+    /// Breaks down flows by category and asset for transactions with
+    /// `from <= timestamp < to`
+    pub fn category_report(&self, from: Timestamp, to: Timestamp) -> CategoryReport {
+        let mut flows: BTreeMap<(Option<Category>, String), CategoryFlow> = BTreeMap::new();
+        for tx in self
+            .transactions
+            .iter()
+            .filter(|tx| tx.timestamp >= from && tx.timestamp < to)
+        {
+            let flow = flows
+                .entry((tx.category.clone(), tx.asset.symbol().to_string()))
+                .or_insert_with(|| CategoryFlow {
+                    category: tx.category.clone(),
+                    asset: tx.asset.clone(),
+                    inflow: Amount::ZERO,
+                    outflow: Amount::ZERO,
+                    transactions: 0,
+                });
+            // A transfer between custodied wallets is both
+            for (_, change) in tx.postings() {
+                if change.is_positive() {
+                    flow.inflow += change;
+                } else {
+                    flow.outflow -= change;
+                }
+            }
+            flow.transactions += 1;
+        }
+        CategoryReport {
+            from,
+            to,
+            flows: flows.into_values().collect(),
+        }
+    }
+
+    fn categorize_last(&mut self, category: Category) -> Result<(), CustodyError> {
+        self.update_last_transaction(|tx| tx.category = Some(category))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::WalletType;
+
+    fn system() -> CustodySystem {
+        let mut system = CustodySystem::new();
+        system
+            .create_wallet("ops".to_string(), "0x1".to_string(), WalletType::Hot)
+            .unwrap();
+        system
+            .deposit_with_category("ops", amount!(10.0), Category::Operations)
+            .unwrap();
+        system
+            .withdraw_with_category("ops", amount!(2.0), Category::Payroll)
+            .unwrap();
+        system
+            .withdraw_with_category("ops", amount!(1.0), Category::Payroll)
+            .unwrap();
+        system.deposit("ops", amount!(4.0)).unwrap();
+        system
+    }
+
+    fn all_time(system: &CustodySystem) -> CategoryReport {
+        system.category_report(Timestamp::EPOCH, Timestamp::from_unix(u32::MAX as u64))
+    }
+
+    #[test]
+    fn test_category_report_breakdown() {
+        let report = all_time(&system());
+        let payroll = report.flows_for(Some(&Category::Payroll));
+        assert_eq!(payroll.len(), 1);
+        assert_eq!(payroll[0].outflow, amount!(3.0));
+        assert_eq!(payroll[0].transactions, 2);
+        assert_eq!(payroll[0].net(), amount!(-3.0));
+        assert_eq!(report.flows_for(None)[0].inflow, amount!(4.0));
+        assert_eq!(report.flows[0].category, None);
+    }
+
+    #[test]
+    fn test_retroactive_categorization() {
+        let mut system = system();
+        system
+            .categorize_transaction(3, Some(Category::Other("refund".to_string())))
+            .unwrap();
+        let report = all_time(&system);
+        assert!(report.flows_for(None).is_empty());
+        assert_eq!(
+            report.flows_for(Some(&Category::Other("refund".to_string())))[0].inflow,
+            amount!(4.0)
+        );
+        assert_eq!(
+            system.categorize_transaction(99, None),
+            Err(CustodyError::TransactionNotFound(99))
+        );
+    }
+
+    #[test]
+    fn test_category_report_period_is_half_open() {
+        let system = system();
+        let report = system.category_report(Timestamp::EPOCH, Timestamp::EPOCH);
+        assert!(report.flows.is_empty());
+    }
+
+    #[test]
+    fn test_failed_operation_does_not_categorize() {
+        let mut system = system();
+        assert!(system
+            .withdraw_with_category("ops", amount!(100.0), Category::ClientWithdrawal)
+            .is_err());
+        assert!(all_time(&system)
+            .flows_for(Some(&Category::ClientWithdrawal))
+            .is_empty());
+    }
+}
@@ -0,0 +1,367 @@
+//! Change-data-capture stream.
+//!
+//! Every committed change to wallets or the transaction log is appended to
+//! a change log with a gap-free, increasing sequence number. Analytics
+//! replicas poll [`CustodySystem::changes_since`] with the last sequence
+//! they applied instead of re-exporting full snapshots.
+//!
+//! # Format
+//!
+//! [`ChangeRecord`] serializes (e.g. as JSON) to:
+//!
+//! ```text
+//! {"sequence": 7, "timestamp": 1700000000, "op": "wallet_upsert", "wallet": {...}}
+//! {"sequence": 8, "timestamp": 1700000000, "op": "transaction_append", "index": 3, "transaction": {...}}
+//! {"sequence": 9, "timestamp": 1700000001, "op": "transaction_update", "index": 3, "transaction": {...}}
+//! ```
+//!
+//! * `wallet_upsert` carries the full wallet row; replicas upsert it by
+//!   `wallet.id`. Archived wallets carry an `archived_at` timestamp.
+//! * `transaction_append` carries a new transaction and its position in the
+//!   log; `transaction_update` replaces the row at `index` (metadata such as
+//!   categories or references added after the fact).
+//!
+//! Applying records in sequence order reproduces the source exactly.
+//!
+//! # Event sourcing
+//!
+//! The change log doubles as the ledger's event stream. Appends to and
+//! updates of the transaction log are made only by applying a [`Change`],
+//! and every wallet row is captured after each operation that touches it,
+//! so [`CustodySystem::rebuild_from_events`] reconstructs wallets and the
+//! transaction log from [`CustodySystem::events_since`]`(0)` for disaster
+//! recovery or audit replay. [`CustodySystem::apply_event`] checks each
+//! event's sequence number and audit chain links before applying it.
+//! Configuration such as policies and approvers is not part of the stream.
+
+use crate::time::Timestamp;
+use crate::{CustodyError, CustodySystem, Transaction, Wallet};
+use serde::{Deserialize, Serialize};
+
+/// A single committed change
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Change {
+    WalletUpsert {
+        wallet: Wallet,
+    },
+    TransactionAppend {
+        index: usize,
+        transaction: Transaction,
+    },
+    TransactionUpdate {
+        index: usize,
+        transaction: Transaction,
+    },
+}
+
+/// A change with its position in the stream
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChangeRecord {
+    /// Starts at 1 and increases by one per change
+    pub sequence: u64,
+    /// When the change was committed
+    pub timestamp: Timestamp,
+    #[serde(flatten)]
+    pub change: Change,
+}
+
+impl CustodySystem {
+    /// Returns changes with a sequence number greater than `sequence`, in
+    /// order. Pass 0 to read from the start of the retained log.
+    pub fn changes_since(&self, sequence: u64) -> impl Iterator<Item = &ChangeRecord> {
+        let first = self.changes.front().map_or(1, |record| record.sequence);
+        let skip = sequence.saturating_sub(first - 1) as usize;
+        self.changes.iter().skip(skip)
+    }
+
+    /// Sequence number of the most recent change, or 0 if none
+    pub fn latest_sequence(&self) -> u64 {
+        self.changes.back().map_or(0, |record| record.sequence)
+    }
+
+    /// Discards changes up to and including `sequence`, once every replica
+    /// has applied them. Sequence numbers are never reused.
+    pub fn truncate_changes(&mut self, sequence: u64) {
+        while self
+            .changes
+            .front()
+            .is_some_and(|record| record.sequence <= sequence)
+        {
+            self.changes.pop_front();
+        }
+    }
+
+    /// Returns the events after `sequence`, in order; the same stream as
+    /// [`changes_since`](Self::changes_since)
+    pub fn events_since(&self, sequence: u64) -> impl Iterator<Item = &ChangeRecord> {
+        self.changes_since(sequence)
+    }
+
+    /// Applies an event from another system's stream, e.g. a standby
+    /// following the primary
+    ///
+    /// Events must arrive in sequence order with no gaps, and appended
+    /// transactions must link to the current chain head; otherwise the
+    /// event is rejected and nothing changes. Updates may only touch a
+    /// transaction's annotations.
+    pub fn apply_event(&mut self, event: ChangeRecord) -> Result<(), CustodyError> {
+        let expected = self.change_sequence + 1;
+        if event.sequence != expected {
+            return Err(CustodyError::EventOutOfOrder {
+                expected,
+                found: event.sequence,
+            });
+        }
+        match &event.change {
+            Change::WalletUpsert { .. } => {}
+            Change::TransactionAppend { index, transaction } => {
+                if *index != self.transactions.len() {
+                    return Err(CustodyError::EventRejected(event.sequence));
+                }
+                if transaction.previous_hash != self.chain_head()
+                    || transaction.chain_hash != transaction.compute_chain_hash()
+                    || !transaction.verify_digest()
+                {
+                    return Err(CustodyError::AuditChainBroken(transaction.id));
+                }
+            }
+            Change::TransactionUpdate { index, transaction } => {
+                let Some(current) = self.transactions.get(*index) else {
+                    return Err(CustodyError::EventRejected(event.sequence));
+                };
+                if transaction.chain_hash != current.chain_hash
+                    || transaction.compute_chain_hash() != current.chain_hash
+                    || !transaction.verify_digest()
+                {
+                    return Err(CustodyError::AuditChainBroken(transaction.id));
+                }
+            }
+        }
+        self.persist(std::slice::from_ref(&event.change))?;
+        self.apply_change(&event.change);
+        self.change_sequence = event.sequence;
+        self.changes.push_back(event);
+        Ok(())
+    }
+
+    /// Reconstructs a system by applying an event stream from the start
+    ///
+    /// # Example
+    /// ```
+    /// use securevault::{amount, CustodySystem, WalletType};
+    /// let mut system = CustodySystem::new();
+    /// system.create_wallet("w".to_string(), "0x1".to_string(), WalletType::Hot).unwrap();
+    /// system.deposit("w", amount!(5)).unwrap();
+    ///
+    /// let events = system.events_since(0).cloned();
+    /// let rebuilt = CustodySystem::rebuild_from_events(events).unwrap();
+    /// assert_eq!(rebuilt.snapshot(), system.snapshot());
+    /// assert_eq!(rebuilt.chain_head(), system.chain_head());
+    /// ```
+    pub fn rebuild_from_events(
+        events: impl IntoIterator<Item = ChangeRecord>,
+    ) -> Result<Self, CustodyError> {
+        let mut system = Self::new();
+        for event in events {
+            system.apply_event(event)?;
+        }
+        Ok(system)
+    }
+
+    /// Commits a new or changed wallet row
+    pub(crate) fn commit_wallet(&mut self, wallet: Wallet) -> Result<(), CustodyError> {
+        self.commit(vec![Change::WalletUpsert { wallet }])
+    }
+
+    /// Writes the changes of one operation to storage, then applies them
+    /// to the ledger and appends them to the change log
+    ///
+    /// If the write fails nothing is applied and the error is returned.
+    pub(crate) fn commit(&mut self, changes: Vec<Change>) -> Result<(), CustodyError> {
+        self.persist(&changes)?;
+        for change in changes {
+            self.apply_change(&change);
+            self.capture(change);
+        }
+        Ok(())
+    }
+
+    fn apply_change(&mut self, change: &Change) {
+        match change {
+            Change::WalletUpsert { wallet } => {
+                let (from, to) = if wallet.archived_at.is_some() {
+                    (&mut self.wallets, &mut self.archived_wallets)
+                } else {
+                    (&mut self.archived_wallets, &mut self.wallets)
+                };
+                from.remove(&wallet.id);
+                to.insert(wallet.id.clone(), wallet.clone());
+            }
+            Change::TransactionAppend { index, transaction } => {
+                self.index.insert(*index, transaction);
+                self.transactions.push_back(transaction.clone());
+                self.next_transaction_id = self.next_transaction_id.max(transaction.id + 1);
+            }
+            Change::TransactionUpdate { index, transaction } => {
+                self.transactions[*index] = transaction.clone();
+            }
+        }
+    }
+
+    fn capture(&mut self, change: Change) {
+        self.change_sequence += 1;
+        self.changes.push_back(ChangeRecord {
+            sequence: self.change_sequence,
+            timestamp: self.current_timestamp(),
+            change,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Category, WalletMap, WalletType};
+
+    fn system() -> CustodySystem {
+        let mut system = CustodySystem::new();
+        system
+            .create_wallet("a".to_string(), "0x1".to_string(), WalletType::Hot)
+            .unwrap();
+        system
+            .create_wallet("b".to_string(), "0x2".to_string(), WalletType::Cold)
+            .unwrap();
+        system.deposit("a", amount!(10.0)).unwrap();
+        system.transfer("a", "b", amount!(4.0)).unwrap();
+        system
+            .categorize_transaction(0, Some(Category::Operations))
+            .unwrap();
+        system
+    }
+
+    /// Applies a change stream the way an external replica would
+    fn replicate<'a>(
+        records: impl Iterator<Item = &'a ChangeRecord>,
+    ) -> (WalletMap, Vec<Transaction>) {
+        let mut wallets = WalletMap::new();
+        let mut transactions = Vec::new();
+        for record in records {
+            match &record.change {
+                Change::WalletUpsert { wallet } => {
+                    wallets.insert(wallet.id.clone(), wallet.clone());
+                }
+                Change::TransactionAppend { index, transaction } => {
+                    assert_eq!(*index, transactions.len());
+                    transactions.push(transaction.clone());
+                }
+                Change::TransactionUpdate { index, transaction } => {
+                    transactions[*index] = transaction.clone();
+                }
+            }
+        }
+        (wallets, transactions)
+    }
+
+    #[test]
+    fn test_replica_matches_source() {
+        let system = system();
+        let (wallets, transactions) = replicate(system.changes_since(0));
+        assert_eq!(&wallets, system.get_all_wallets());
+        assert_eq!(
+            transactions,
+            system
+                .get_all_transactions()
+                .iter()
+                .cloned()
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_sequences_are_gap_free_and_resumable() {
+        let mut system = system();
+        let sequences: Vec<u64> = system.changes_since(0).map(|r| r.sequence).collect();
+        assert_eq!(
+            sequences,
+            (1..=system.latest_sequence()).collect::<Vec<_>>()
+        );
+
+        let cursor = system.latest_sequence();
+        system.deposit("b", amount!(1.0)).unwrap();
+        let new: Vec<_> = system.changes_since(cursor).collect();
+        assert_eq!(new.len(), 2);
+        assert!(matches!(
+            new[0].change,
+            Change::TransactionAppend { index: 2, .. }
+        ));
+
+        system.truncate_changes(cursor);
+        assert_eq!(system.changes_since(0).count(), 2);
+        assert_eq!(system.changes_since(cursor + 1).count(), 1);
+    }
+
+    #[test]
+    fn test_rebuild_from_serialized_events() {
+        let mut system = system();
+        let transfer = system.get_all_transactions()[1].id;
+        system
+            .reverse_transaction(transfer, "sent to the wrong wallet")
+            .unwrap();
+        let json: Vec<String> = system
+            .events_since(0)
+            .map(|event| serde_json::to_string(event).unwrap())
+            .collect();
+
+        let events = json.iter().map(|line| serde_json::from_str(line).unwrap());
+        let mut rebuilt = CustodySystem::rebuild_from_events(events).unwrap();
+        assert_eq!(rebuilt.snapshot(), system.snapshot());
+        assert_eq!(rebuilt.latest_sequence(), system.latest_sequence());
+        assert!(rebuilt.audit().passed());
+
+        // The rebuilt system carries on where the source left off
+        rebuilt.deposit("a", amount!(1.0)).unwrap();
+        system.deposit("a", amount!(1.0)).unwrap();
+        let newest = |s: &CustodySystem| s.get_all_transactions().last().unwrap().id;
+        assert_eq!(newest(&rebuilt), newest(&system));
+    }
+
+    #[test]
+    fn test_events_are_checked_before_applying() {
+        let system = system();
+        let events: Vec<ChangeRecord> = system.events_since(0).cloned().collect();
+        let mut replica = CustodySystem::new();
+        assert_eq!(
+            replica.apply_event(events[1].clone()),
+            Err(CustodyError::EventOutOfOrder {
+                expected: 1,
+                found: 2,
+            })
+        );
+
+        let mut tampered = events.clone();
+        let append = tampered
+            .iter_mut()
+            .find_map(|event| match &mut event.change {
+                Change::TransactionAppend { transaction, .. } => Some(transaction),
+                _ => None,
+            })
+            .unwrap();
+        append.amount = amount!(1000.0);
+        let id = append.id;
+        assert_eq!(
+            CustodySystem::rebuild_from_events(tampered).err(),
+            Some(CustodyError::AuditChainBroken(id))
+        );
+    }
+
+    #[test]
+    fn test_record_format() {
+        let system = system();
+        let record = system.changes_since(0).next().unwrap();
+        let json = serde_json::to_value(record).unwrap();
+        assert_eq!(json["sequence"], 1);
+        assert_eq!(json["op"], "wallet_upsert");
+        assert_eq!(json["wallet"]["id"], "a");
+    }
+}
@@ -0,0 +1,203 @@
+//! Hash-chained, append-only audit log.
+//!
+//! Every recorded transaction carries the chain hash of the entry before
+//! it ([`Transaction::previous_hash`]) and its own
+//! ([`Transaction::chain_hash`]): the SHA-256 of the previous hash and the
+//! transaction's ledger fields. Editing, reordering or deleting an entry
+//! breaks the links after it, which [`CustodySystem::verify_audit_chain`]
+//! reports. The first entry links to a hash of 64 zeros.
+//!
+//! # Chain encoding
+//!
+//! The domain tag `securevault/chain/v1`, the previous hash, then the
+//! ledger fields in declaration order (`id`, `uuid`, `wallet_id`,
+//! `transaction_type`, `amount`, `timestamp`, `asset`, `rate`,
+//! `counterparty`, `idempotency_key`), encoded as for the sealed digest.
+//!
+//! Annotations (category, fiat value, reference, memo, tags, metadata) may
+//! be amended after the fact and are left out of the chain; each entry's
+//! sealed digest still covers them, and the chain check verifies it too.
+//! Dropping the newest entries of a stored log leaves a valid, shorter
+//! chain: publish [`CustodySystem::chain_head`] somewhere the ledger cannot
+//! write to and compare it after a restore.
+
+use crate::digest::{sha256_hex, type_tag, Encoder};
+use crate::{CustodyError, CustodySystem, Transaction};
+
+const DOMAIN: &[u8] = b"securevault/chain/v1";
+
+/// Previous hash of the first entry
+pub(crate) const GENESIS_HASH: &str =
+    "0000000000000000000000000000000000000000000000000000000000000000";
+
+impl Transaction {
+    /// Computes the chain hash from `previous_hash` and the ledger fields,
+    /// as lowercase hex
+    pub fn compute_chain_hash(&self) -> String {
+        let mut out = Encoder(Vec::new());
+        out.field(DOMAIN)
+            .str(&self.previous_hash)
+            .u64(self.id)
+            .field(self.uuid.as_bytes())
+            .str(&self.wallet_id)
+            .str(type_tag(&self.transaction_type))
+            .amount(self.amount)
+            .u64(self.timestamp.as_unix())
+            .asset(&self.asset);
+        match self.rate {
+            Some(rate) => out.f64(rate),
+            None => out.absent(),
+        };
+        match &self.counterparty {
+            Some(counterparty) => out.str(counterparty),
+            None => out.absent(),
+        };
+        match &self.idempotency_key {
+            Some(key) => out.str(key),
+            None => out.absent(),
+        };
+        sha256_hex(&out.0)
+    }
+}
+
+impl CustodySystem {
+    /// Returns the chain hash of the newest entry, which commits to the
+    /// whole log
+    pub fn chain_head(&self) -> &str {
+        self.transactions
+            .last()
+            .map_or(GENESIS_HASH, |tx| tx.chain_hash.as_str())
+    }
+
+    /// Links transactions about to be appended, in order, to the current
+    /// head
+    pub(crate) fn chain(&self, txs: &mut [Transaction]) {
+        let mut head = self.chain_head().to_string();
+        for tx in txs {
+            tx.previous_hash = head;
+            tx.chain_hash = tx.compute_chain_hash();
+            head = tx.chain_hash.clone();
+        }
+    }
+
+    /// Walks the log from the first entry and checks every link, chain
+    /// hash and sealed digest
+    ///
+    /// Fails with the id of the first entry that does not check out. An
+    /// entry missing from the end of the log is reported by the id it was
+    /// recorded under.
+    ///
+    /// # Example
+    /// ```
+    /// use securevault::{amount, CustodySystem, WalletType};
+    /// let mut system = CustodySystem::new();
+    /// system.create_wallet("w".to_string(), "0x1".to_string(), WalletType::Hot).unwrap();
+    /// system.deposit("w", amount!(5.0)).unwrap();
+    /// system.withdraw("w", amount!(2.0)).unwrap();
+    ///
+    /// assert!(system.verify_audit_chain().is_ok());
+    /// ```
+    pub fn verify_audit_chain(&self) -> Result<(), CustodyError> {
+        let mut previous = GENESIS_HASH;
+        for tx in self.transactions.iter() {
+            if tx.previous_hash != previous
+                || tx.chain_hash != tx.compute_chain_hash()
+                || !tx.verify_digest()
+            {
+                return Err(CustodyError::AuditChainBroken(tx.id));
+            }
+            previous = &tx.chain_hash;
+        }
+
+        let newest = self.transactions.last().map_or(0, |tx| tx.id);
+        if newest + 1 != self.next_transaction_id {
+            return Err(CustodyError::AuditChainBroken(self.next_transaction_id - 1));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Category, WalletType};
+
+    fn system() -> CustodySystem {
+        let mut system = CustodySystem::new();
+        for id in ["a", "b"] {
+            system
+                .create_wallet(id.to_string(), format!("0x{}", id), WalletType::Hot)
+                .unwrap();
+        }
+        system.deposit("a", amount!(10.0)).unwrap();
+        system.transfer("a", "b", amount!(4.0)).unwrap();
+        system.withdraw("b", amount!(1.0)).unwrap();
+        system
+    }
+
+    #[test]
+    fn test_entries_link_to_their_predecessor() {
+        let system = system();
+        let log = system.get_all_transactions();
+        assert_eq!(log[0].previous_hash, GENESIS_HASH);
+        assert_eq!(log[1].previous_hash, log[0].chain_hash);
+        assert_eq!(log[2].previous_hash, log[1].chain_hash);
+        assert_eq!(system.chain_head(), log[2].chain_hash);
+        assert_eq!(CustodySystem::new().chain_head(), GENESIS_HASH);
+        assert!(system.verify_audit_chain().is_ok());
+    }
+
+    #[test]
+    fn test_detects_mutation() {
+        let mut system = system();
+        system.transactions.get_mut(1).unwrap().amount = amount!(1.0);
+        assert_eq!(
+            system.verify_audit_chain(),
+            Err(CustodyError::AuditChainBroken(2))
+        );
+
+        // Resealing the tampered entry does not repair the chain
+        let tx = system.transactions.get_mut(1).unwrap();
+        tx.seal();
+        assert_eq!(
+            system.verify_audit_chain(),
+            Err(CustodyError::AuditChainBroken(2))
+        );
+    }
+
+    #[test]
+    fn test_detects_deletion() {
+        let mut middle = system();
+        middle.transactions.remove(1);
+        assert_eq!(
+            middle.verify_audit_chain(),
+            Err(CustodyError::AuditChainBroken(3))
+        );
+
+        let mut newest = system();
+        newest.transactions.pop_back();
+        assert_eq!(
+            newest.verify_audit_chain(),
+            Err(CustodyError::AuditChainBroken(3))
+        );
+    }
+
+    #[test]
+    fn test_annotations_keep_the_chain() {
+        let mut system = system();
+        let head = system.chain_head().to_string();
+        system
+            .categorize_transaction(0, Some(Category::Operations))
+            .unwrap();
+        assert!(system.verify_audit_chain().is_ok());
+        assert_eq!(system.chain_head(), head);
+        assert!(system.audit().passed());
+
+        system.transactions.get_mut(0).unwrap().category = None;
+        assert_eq!(
+            system.verify_audit_chain(),
+            Err(CustodyError::AuditChainBroken(1))
+        );
+        assert!(!system.audit().check("audit_chain").unwrap().passed);
+    }
+}
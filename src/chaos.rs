@@ -0,0 +1,261 @@
+//! Fault injection for integration testing (feature `chaos`).
+//!
+//! A [`ChaosHarness`] runs commands against a [`CustodySystem`] while
+//! injecting adverse conditions, so integrators can exercise their error
+//! handling and observe how the ledger recovers:
+//!
+//! * **Storage failures**: the command runs, then its commit "fails". The
+//!   system is rolled back to the state before the command and the caller
+//!   gets [`CustodyError::InjectedFault`]. Event listeners may already have
+//!   seen events for the rolled-back command, as they would if a real
+//!   write failed after notification.
+//! * **Clock skew**: transactions recorded while the harness is active are
+//!   timestamped with a fixed offset from the real clock.
+//! * **Duplicate commands**: a successful command is submitted a second
+//!   time, as a retrying client would after a lost acknowledgement. The
+//!   report counts whether the duplicate was rejected or applied.
+//! * **Delayed confirmations**: the ledger has no chain client of its own,
+//!   so [`ChaosHarness::observed_confirmations`] lags confirmation counts
+//!   for integrators' chain watchers to consume.
+//!
+//! Faults are drawn from a seeded generator, so a run is reproducible.
+
+use crate::time::Timestamp;
+use crate::{CustodyError, CustodySystem};
+
+/// Which faults to inject and how often
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChaosConfig {
+    /// Seed of the fault generator
+    pub seed: u64,
+    /// Probability in `[0, 1]` that a command's commit fails
+    pub storage_failure_rate: f64,
+    /// Probability in `[0, 1]` that a successful command is submitted twice
+    pub duplicate_rate: f64,
+    /// Seconds added to the timestamp of recorded transactions (may be
+    /// negative)
+    pub clock_skew_secs: i64,
+    /// Confirmations withheld from [`ChaosHarness::observed_confirmations`]
+    pub confirmation_delay: u32,
+}
+
+impl Default for ChaosConfig {
+    fn default() -> Self {
+        Self {
+            seed: 0x5eed,
+            storage_failure_rate: 0.0,
+            duplicate_rate: 0.0,
+            clock_skew_secs: 0,
+            confirmation_delay: 0,
+        }
+    }
+}
+
+/// Counts of the faults injected so far
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChaosReport {
+    /// Commands submitted through the harness
+    pub commands: usize,
+    /// Commands rolled back by an injected storage failure
+    pub storage_failures: usize,
+    /// Duplicates the system rejected
+    pub duplicates_rejected: usize,
+    /// Duplicates the system applied a second time
+    pub duplicates_applied: usize,
+}
+
+/// Runs commands against a system under injected faults
+#[derive(Debug, Clone)]
+pub struct ChaosHarness {
+    config: ChaosConfig,
+    state: u64,
+    report: ChaosReport,
+}
+
+impl ChaosHarness {
+    /// Creates a harness with the given configuration
+    pub fn new(config: ChaosConfig) -> Self {
+        Self {
+            state: config.seed,
+            config,
+            report: ChaosReport::default(),
+        }
+    }
+
+    /// Returns the faults injected so far
+    pub fn report(&self) -> &ChaosReport {
+        &self.report
+    }
+
+    /// Runs `command` against `system`, injecting faults according to the
+    /// configuration
+    ///
+    /// # Example
+    /// ```
+    /// use securevault::{amount, ChaosConfig, ChaosHarness, CustodyError, CustodySystem, WalletType};
+    /// let mut system = CustodySystem::new();
+    /// system.create_wallet("w".to_string(), "0x1".to_string(), WalletType::Hot).unwrap();
+    ///
+    /// let mut chaos = ChaosHarness::new(ChaosConfig {
+    ///     storage_failure_rate: 1.0,
+    ///     ..ChaosConfig::default()
+    /// });
+    /// let result = chaos.run(&mut system, |s| s.deposit("w", amount!(5.0)));
+    /// assert!(matches!(result, Err(CustodyError::InjectedFault(_))));
+    /// assert_eq!(system.get_wallet("w").unwrap().balance, amount!(0.0));
+    /// ```
+    pub fn run<T, F>(&mut self, system: &mut CustodySystem, command: F) -> Result<T, CustodyError>
+    where
+        F: Fn(&mut CustodySystem) -> Result<T, CustodyError>,
+    {
+        self.report.commands += 1;
+        let snapshot = system.clone();
+        let previous_skew = std::mem::replace(&mut system.clock_skew, self.config.clock_skew_secs);
+
+        let result = command(system);
+        let result = match result {
+            Ok(_) if self.roll(self.config.storage_failure_rate) => {
+                self.report.storage_failures += 1;
+                *system = snapshot;
+                Err(CustodyError::InjectedFault(
+                    "storage write failed".to_string(),
+                ))
+            }
+            Ok(value) => {
+                if self.roll(self.config.duplicate_rate) {
+                    match command(system) {
+                        Ok(_) => self.report.duplicates_applied += 1,
+                        Err(_) => self.report.duplicates_rejected += 1,
+                    }
+                }
+                Ok(value)
+            }
+            Err(err) => Err(err),
+        };
+
+        system.clock_skew = previous_skew;
+        result
+    }
+
+    /// Returns the confirmation count a chain watcher should observe when
+    /// the chain actually reports `actual`
+    pub fn observed_confirmations(&self, actual: u32) -> u32 {
+        actual.saturating_sub(self.config.confirmation_delay)
+    }
+
+    /// Draws from the fault generator (SplitMix64)
+    fn roll(&mut self, probability: f64) -> bool {
+        if probability <= 0.0 {
+            return false;
+        }
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+        ((z >> 11) as f64 / (1u64 << 53) as f64) < probability
+    }
+}
+
+/// Shifts a timestamp by the injected clock skew
+pub(crate) fn skew(timestamp: Timestamp, secs: i64) -> Timestamp {
+    Timestamp::from_unix(timestamp.as_unix().saturating_add_signed(secs))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::WalletType;
+
+    fn system() -> CustodySystem {
+        let mut system = CustodySystem::new();
+        system
+            .create_wallet("a".to_string(), "0x1".to_string(), WalletType::Hot)
+            .unwrap();
+        system.deposit("a", amount!(10.0)).unwrap();
+        system
+    }
+
+    #[test]
+    fn test_storage_failure_rolls_back() {
+        let mut system = system();
+        let mut chaos = ChaosHarness::new(ChaosConfig {
+            storage_failure_rate: 1.0,
+            ..ChaosConfig::default()
+        });
+        let result = chaos.run(&mut system, |s| s.withdraw("a", amount!(4.0)));
+        assert!(matches!(result, Err(CustodyError::InjectedFault(_))));
+        assert_eq!(system.get_wallet("a").unwrap().balance, amount!(10.0));
+        assert_eq!(system.get_all_transactions().len(), 1);
+        assert!(system.audit().passed());
+        assert_eq!(chaos.report().storage_failures, 1);
+    }
+
+    #[test]
+    fn test_duplicates_are_reported() {
+        let mut system = system();
+        let mut chaos = ChaosHarness::new(ChaosConfig {
+            duplicate_rate: 1.0,
+            ..ChaosConfig::default()
+        });
+        chaos
+            .run(&mut system, |s| s.withdraw("a", amount!(6.0)))
+            .unwrap();
+        assert_eq!(chaos.report().duplicates_rejected, 1);
+        chaos
+            .run(&mut system, |s| s.deposit("a", amount!(1.0)))
+            .unwrap();
+        assert_eq!(chaos.report().duplicates_applied, 1);
+        assert_eq!(system.get_wallet("a").unwrap().balance, amount!(6.0));
+    }
+
+    #[test]
+    fn test_clock_skew_applies_only_inside_harness() {
+        let mut system = system();
+        let mut chaos = ChaosHarness::new(ChaosConfig {
+            clock_skew_secs: -3600,
+            ..ChaosConfig::default()
+        });
+        chaos
+            .run(&mut system, |s| s.deposit("a", amount!(1.0)))
+            .unwrap();
+        system.deposit("a", amount!(1.0)).unwrap();
+
+        let log = system.get_all_transactions();
+        assert!(log[2].timestamp.as_unix() - log[1].timestamp.as_unix() >= 3600);
+        assert!(system.verify_transaction(log[1].id).is_ok());
+    }
+
+    #[test]
+    fn test_faults_are_reproducible() {
+        let config = ChaosConfig {
+            storage_failure_rate: 0.5,
+            seed: 42,
+            ..ChaosConfig::default()
+        };
+        let outcomes = |config: &ChaosConfig| {
+            let mut system = system();
+            let mut chaos = ChaosHarness::new(config.clone());
+            (0..20)
+                .map(|_| {
+                    chaos
+                        .run(&mut system, |s| s.deposit("a", amount!(1.0)))
+                        .is_ok()
+                })
+                .collect::<Vec<_>>()
+        };
+        let first = outcomes(&config);
+        assert_eq!(first, outcomes(&config));
+        assert!(first.contains(&true) && first.contains(&false));
+    }
+
+    #[test]
+    fn test_confirmation_delay() {
+        let chaos = ChaosHarness::new(ChaosConfig {
+            confirmation_delay: 3,
+            ..ChaosConfig::default()
+        });
+        assert_eq!(chaos.observed_confirmations(2), 0);
+        assert_eq!(chaos.observed_confirmations(6), 3);
+    }
+}
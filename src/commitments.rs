@@ -0,0 +1,363 @@
+//! Merkle commitments to wallet state.
+//!
+//! [`CustodySystem::commit_wallet_state`] hashes every wallet's balances
+//! into a Merkle tree and keeps the root, anchored to the audit chain by
+//! the [`chain_head`](CustodySystem::chain_head) it was taken at. Once the
+//! root is published, [`CustodySystem::prove_wallet_state`] hands a client
+//! or auditor their wallet's balances at that commitment with the sibling
+//! hashes up to the root, and [`WalletStateProof::verify`] checks them
+//! without seeing any other wallet. With
+//! [`CustodySystem::set_state_commitment_interval`] commitments are taken
+//! automatically as transactions are recorded.
+//!
+//! # Tree encoding
+//!
+//! Leaves are ordered by wallet id. A leaf is the SHA-256 of the domain tag
+//! `securevault/state-leaf/v1`, the wallet id, the number of balances and
+//! each asset and balance in asset order, encoded as for the sealed digest.
+//! A node is the SHA-256 of `securevault/state-node/v1` and its two
+//! children's hex hashes. A node without a sibling is carried up a level
+//! unchanged. The root of no wallets is the hash of the node tag alone.
+
+use crate::digest::{sha256_hex, Encoder};
+use crate::time::Timestamp;
+use crate::{asset, Amount, Asset, CustodyError, CustodySystem, Wallet};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+const LEAF_DOMAIN: &[u8] = b"securevault/state-leaf/v1";
+const NODE_DOMAIN: &[u8] = b"securevault/state-node/v1";
+
+/// The balances of one wallet, as committed
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WalletState {
+    pub wallet_id: String,
+    /// Balance in the primary asset and every other holding
+    #[serde(with = "asset::as_pairs")]
+    pub balances: BTreeMap<Asset, Amount>,
+}
+
+impl WalletState {
+    fn of(wallet: &Wallet) -> Self {
+        Self {
+            wallet_id: wallet.id.clone(),
+            balances: wallet
+                .balances()
+                .map(|(asset, balance)| (asset.clone(), balance))
+                .collect(),
+        }
+    }
+
+    /// Returns the leaf hash, as lowercase hex
+    pub fn leaf_hash(&self) -> String {
+        let mut out = Encoder(Vec::new());
+        out.field(LEAF_DOMAIN)
+            .str(&self.wallet_id)
+            .u64(self.balances.len() as u64);
+        for (asset, balance) in &self.balances {
+            out.asset(asset).amount(*balance);
+        }
+        sha256_hex(&out.0)
+    }
+}
+
+fn node_hash(left: &str, right: &str) -> String {
+    let mut out = Encoder(Vec::new());
+    out.field(NODE_DOMAIN).str(left).str(right);
+    sha256_hex(&out.0)
+}
+
+/// Which side of the path a sibling hash sits on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Side {
+    Left,
+    Right,
+}
+
+/// A sibling hash on the way from a leaf to the root
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProofStep {
+    pub sibling: String,
+    pub side: Side,
+}
+
+/// A Merkle root over every wallet's balances at one moment
+#[derive(Debug, Clone, PartialEq)]
+pub struct StateCommitment {
+    /// Sequence number, starting at 1
+    pub id: u64,
+    pub committed_at: Timestamp,
+    /// Root hash, as lowercase hex
+    pub root: String,
+    /// Audit chain head when the commitment was taken
+    pub chain_head: String,
+    /// Committed wallet states, ordered by wallet id
+    states: Vec<WalletState>,
+}
+
+impl StateCommitment {
+    /// Returns the number of wallets committed to
+    pub fn wallet_count(&self) -> usize {
+        self.states.len()
+    }
+
+    /// Returns true if the root still matches the committed states
+    pub(crate) fn is_consistent(&self) -> bool {
+        root_of(&self.states) == self.root
+    }
+}
+
+/// Evidence that a wallet held given balances under a commitment's root
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WalletStateProof {
+    pub commitment_id: u64,
+    pub state: WalletState,
+    /// Sibling hashes from the leaf up to the root
+    pub path: Vec<ProofStep>,
+}
+
+impl WalletStateProof {
+    /// Recomputes the root from the state and path
+    pub fn root(&self) -> String {
+        self.path
+            .iter()
+            .fold(self.state.leaf_hash(), |hash, step| match step.side {
+                Side::Left => node_hash(&step.sibling, &hash),
+                Side::Right => node_hash(&hash, &step.sibling),
+            })
+    }
+
+    /// Returns true if the proof leads to `published_root`
+    pub fn verify(&self, published_root: &str) -> bool {
+        self.root() == published_root
+    }
+}
+
+/// Returns every level of the tree over `leaves`, from the leaves up to
+/// the root
+fn levels(leaves: Vec<String>) -> Vec<Vec<String>> {
+    let mut levels = vec![leaves];
+    while let Some(level) = levels.last().filter(|level| level.len() > 1) {
+        let next = level
+            .chunks(2)
+            .map(|pair| match pair {
+                [left, right] => node_hash(left, right),
+                [single] => single.clone(),
+                _ => unreachable!("chunks of two"),
+            })
+            .collect();
+        levels.push(next);
+    }
+    levels
+}
+
+fn root_of(states: &[WalletState]) -> String {
+    let leaves = states.iter().map(WalletState::leaf_hash).collect();
+    match levels(leaves).last().and_then(|level| level.first()) {
+        Some(root) => root.clone(),
+        None => sha256_hex(&Encoder(Vec::new()).field(NODE_DOMAIN).0),
+    }
+}
+
+impl CustodySystem {
+    /// Commits to every wallet's current balances and returns the
+    /// commitment, whose root can then be published
+    ///
+    /// # Example
+    /// ```
+    /// use securevault::{amount, CustodySystem, WalletType};
+    /// let mut system = CustodySystem::new();
+    /// for id in ["a", "b", "c"] {
+    ///     system.create_wallet(id.to_string(), id.to_string(), WalletType::Hot).unwrap();
+    /// }
+    /// system.deposit("b", amount!(7)).unwrap();
+    /// let commitment = system.commit_wallet_state();
+    ///
+    /// // Later balances do not change what was committed
+    /// system.deposit("b", amount!(1)).unwrap();
+    /// let proof = system.prove_wallet_state("b", commitment.id).unwrap();
+    /// assert_eq!(proof.state.balances.values().next(), Some(&amount!(7)));
+    /// assert!(proof.verify(&commitment.root));
+    /// ```
+    pub fn commit_wallet_state(&mut self) -> StateCommitment {
+        let mut states: Vec<WalletState> = self.wallets.values().map(WalletState::of).collect();
+        states.sort_by(|a, b| a.wallet_id.cmp(&b.wallet_id));
+        let commitment = StateCommitment {
+            id: self.state_commitments.len() as u64 + 1,
+            committed_at: self.current_timestamp(),
+            root: root_of(&states),
+            chain_head: self.chain_head().to_string(),
+            states,
+        };
+        self.state_commitments.push_back(commitment.clone());
+        commitment
+    }
+
+    /// Takes a commitment whenever a transaction is recorded at least
+    /// `interval_secs` after the previous one, or stops with `None`
+    pub fn set_state_commitment_interval(&mut self, interval_secs: Option<u64>) {
+        self.state_commitment_interval = interval_secs;
+    }
+
+    /// Returns every commitment, oldest first
+    pub fn state_commitments(&self) -> &im::Vector<StateCommitment> {
+        &self.state_commitments
+    }
+
+    /// Gets a commitment by id
+    pub fn get_state_commitment(&self, commitment_id: u64) -> Option<&StateCommitment> {
+        let index = usize::try_from(commitment_id.checked_sub(1)?).ok()?;
+        self.state_commitments.get(index)
+    }
+
+    /// Proves a wallet's balances under commitment `at_commitment`
+    ///
+    /// # Errors
+    /// [`CustodyError::CommitmentNotFound`], and
+    /// [`CustodyError::WalletNotFound`] if the wallet was not part of the
+    /// commitment
+    pub fn prove_wallet_state(
+        &self,
+        wallet_id: &str,
+        at_commitment: u64,
+    ) -> Result<WalletStateProof, CustodyError> {
+        let commitment = self
+            .get_state_commitment(at_commitment)
+            .ok_or(CustodyError::CommitmentNotFound(at_commitment))?;
+        let position = commitment
+            .states
+            .binary_search_by(|state| state.wallet_id.as_str().cmp(wallet_id))
+            .map_err(|_| CustodyError::WalletNotFound(wallet_id.to_string()))?;
+
+        let leaves = commitment
+            .states
+            .iter()
+            .map(WalletState::leaf_hash)
+            .collect();
+        let (mut index, mut path) = (position, Vec::new());
+        for level in levels(leaves).iter().filter(|level| level.len() > 1) {
+            let step = match index % 2 {
+                0 => level.get(index + 1).map(|sibling| ProofStep {
+                    sibling: sibling.clone(),
+                    side: Side::Right,
+                }),
+                _ => Some(ProofStep {
+                    sibling: level[index - 1].clone(),
+                    side: Side::Left,
+                }),
+            };
+            path.extend(step);
+            index /= 2;
+        }
+        Ok(WalletStateProof {
+            commitment_id: commitment.id,
+            state: commitment.states[position].clone(),
+            path,
+        })
+    }
+
+    /// Takes a commitment if the interval has passed since the last one
+    pub(crate) fn commit_state_if_due(&mut self) {
+        let Some(interval) = self.state_commitment_interval else {
+            return;
+        };
+        let now = self.current_timestamp().as_unix();
+        let due = self
+            .state_commitments
+            .last()
+            .is_none_or(|last| now.saturating_sub(last.committed_at.as_unix()) >= interval);
+        if due {
+            self.commit_wallet_state();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::time::ManualClock;
+    use crate::WalletType;
+    use std::sync::Arc;
+
+    fn system(wallets: usize) -> CustodySystem {
+        let mut system = CustodySystem::new();
+        for n in 0..wallets {
+            let id = format!("w{}", n);
+            system
+                .create_wallet(id.clone(), id.clone(), WalletType::Hot)
+                .unwrap();
+            system
+                .deposit(&id, Amount::from_units(n as i64 + 1))
+                .unwrap();
+        }
+        system
+    }
+
+    #[test]
+    fn test_every_wallet_proves_against_the_root() {
+        for wallets in [1, 2, 5, 8] {
+            let mut system = system(wallets);
+            let commitment = system.commit_wallet_state();
+            assert_eq!(commitment.wallet_count(), wallets);
+            assert_eq!(commitment.chain_head, system.chain_head());
+            for n in 0..wallets {
+                let proof = system
+                    .prove_wallet_state(&format!("w{}", n), commitment.id)
+                    .unwrap();
+                assert!(proof.verify(&commitment.root), "{} of {}", n, wallets);
+
+                // A doctored balance no longer leads to the root
+                let mut forged = proof.clone();
+                forged
+                    .state
+                    .balances
+                    .insert(Asset::default(), amount!(1000));
+                assert!(!forged.verify(&commitment.root));
+            }
+        }
+
+        let system = system(0);
+        assert_eq!(
+            system.prove_wallet_state("w0", 1),
+            Err(CustodyError::CommitmentNotFound(1))
+        );
+    }
+
+    #[test]
+    fn test_commitments_are_kept_per_point_in_time() {
+        let clock = Arc::new(ManualClock::new(Timestamp::from_unix(1_700_000_000)));
+        let mut system = system(3);
+        system.set_clock(clock.clone());
+        system.set_state_commitment_interval(Some(3600));
+        system.deposit("w0", amount!(1)).unwrap();
+        system.deposit("w0", amount!(1)).unwrap();
+        assert_eq!(system.state_commitments().len(), 1);
+        let first = system.state_commitments()[0].clone();
+
+        clock.advance(3600);
+        system
+            .create_wallet("w9".to_string(), "w9".to_string(), WalletType::Hot)
+            .unwrap();
+        system.withdraw("w0", amount!(2)).unwrap();
+        let second = system.state_commitments()[1].clone();
+        assert_ne!(first.root, second.root);
+
+        let then = system.prove_wallet_state("w0", first.id).unwrap();
+        let now = system.prove_wallet_state("w0", second.id).unwrap();
+        assert_eq!(then.state.balances[&Asset::default()], amount!(2));
+        assert_eq!(now.state.balances[&Asset::default()], amount!(1));
+        assert!(then.verify(&first.root) && !then.verify(&second.root));
+        assert_eq!(
+            system.prove_wallet_state("w9", first.id),
+            Err(CustodyError::WalletNotFound("w9".to_string()))
+        );
+        system.prove_wallet_state("w9", second.id).unwrap();
+        assert!(system.audit().check("state_commitments").unwrap().passed);
+
+        // Proofs travel as JSON
+        let json = serde_json::to_string(&now).unwrap();
+        let parsed: WalletStateProof = serde_json::from_str(&json).unwrap();
+        assert!(parsed.verify(&second.root));
+    }
+}
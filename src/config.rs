@@ -0,0 +1,461 @@
+//! Deployment configuration.
+//!
+//! A [`Config`] gathers the settings a deployment fixes up front: where
+//! the ledger is stored, default withdrawal limits and signing quorums per
+//! wallet type, and deposit confirmation thresholds.
+//! [`CustodySystem::from_config`] builds a system from it.
+//!
+//! With the `config` feature a configuration is read from a TOML file:
+//!
+//! ```toml
+//! [storage]
+//! backend = "sqlite"            # or "json" (the default), "wal"
+//! path = "/var/lib/securevault/ledger.db"
+//!
+//! [limits.hot]
+//! max_per_transaction = "5"
+//! daily_limit = "20"
+//!
+//! [quorums]
+//! cold = { required = 3, signers = 5 }
+//!
+//! [confirmations]
+//! required = 6
+//! assets = { BTC = 3, ETH = 12 }
+//! ```
+//!
+//! Environment variables override individual values. The name is
+//! `SECUREVAULT_` followed by the key path with `__` between segments, e.g.
+//! `SECUREVAULT_STORAGE__PATH` or `SECUREVAULT_LIMITS__HOT__DAILY_LIMIT`.
+//! Other `SECUREVAULT_` variables are ignored.
+//!
+//! Fees are charged by the exchange connector passed to
+//! [`CustodySystem::exchange`], not by the ledger, so they are configured
+//! there.
+
+use crate::extension::builtin_asset;
+use crate::{
+    CustodyError, CustodySystem, JsonFileStorage, Quorum, Storage, WalStorage, WalletType,
+    WithdrawalPolicy, DEFAULT_REQUIRED_CONFIRMATIONS,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// Prefix of the environment variables read by [`Config::with_env`]
+pub const ENV_PREFIX: &str = "SECUREVAULT_";
+
+/// Top-level sections environment variables may override
+const SECTIONS: [&str; 4] = ["storage", "limits", "quorums", "confirmations"];
+
+/// Settings a custody system is built from
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Where the ledger is persisted; in memory only if unset
+    pub storage: Option<StorageConfig>,
+    /// Default withdrawal limits per wallet type
+    pub limits: WalletTypeDefaults<WithdrawalPolicy>,
+    /// Default signing quorums per wallet type
+    pub quorums: WalletTypeDefaults<Quorum>,
+    pub confirmations: ConfirmationsConfig,
+}
+
+/// Storage backend and location
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StorageConfig {
+    #[serde(default)]
+    pub backend: StorageBackend,
+    pub path: PathBuf,
+}
+
+/// Storage backend kinds
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    /// [`JsonFileStorage`]
+    #[default]
+    Json,
+    /// `SqliteStorage` (feature `sqlite`)
+    Sqlite,
+    /// [`WalStorage`]; `path` is its directory
+    Wal,
+}
+
+/// A setting applied by default to hot and cold wallets
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WalletTypeDefaults<T> {
+    pub hot: Option<T>,
+    pub cold: Option<T>,
+}
+
+impl<T> Default for WalletTypeDefaults<T> {
+    fn default() -> Self {
+        Self {
+            hot: None,
+            cold: None,
+        }
+    }
+}
+
+impl<T> WalletTypeDefaults<T> {
+    /// Returns the settings given, with their section name and wallet type
+    fn entries(&self) -> impl Iterator<Item = (&'static str, WalletType, &T)> {
+        [
+            ("hot", WalletType::Hot, self.hot.as_ref()),
+            ("cold", WalletType::Cold, self.cold.as_ref()),
+        ]
+        .into_iter()
+        .filter_map(|(name, wallet_type, value)| Some((name, wallet_type, value?)))
+    }
+}
+
+/// Deposit confirmation thresholds
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConfirmationsConfig {
+    /// Confirmations required for assets without a threshold of their own
+    pub required: u32,
+    /// Thresholds keyed by asset symbol, e.g. `BTC`; symbols other than
+    /// `BTC` and `ETH` are read as fiat currency codes
+    pub assets: BTreeMap<String, u32>,
+}
+
+impl Default for ConfirmationsConfig {
+    fn default() -> Self {
+        Self {
+            required: DEFAULT_REQUIRED_CONFIRMATIONS,
+            assets: BTreeMap::new(),
+        }
+    }
+}
+
+impl Config {
+    /// Parses a TOML document and validates it (feature `config`)
+    #[cfg(feature = "config")]
+    pub fn from_toml(toml: &str) -> Result<Self, CustodyError> {
+        let config: Config = toml::from_str(toml).map_err(|err| invalid(err.message()))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Reads a TOML file and applies the process environment on top of it
+    /// (feature `config`)
+    #[cfg(feature = "config")]
+    pub fn load(path: impl AsRef<std::path::Path>) -> Result<Self, CustodyError> {
+        let path = path.as_ref();
+        let toml = std::fs::read_to_string(path)
+            .map_err(|err| invalid(format!("{}: {}", path.display(), err)))?;
+        Self::from_toml(&toml)?.with_env(std::env::vars())
+    }
+
+    /// Builds a configuration from the process environment alone
+    pub fn from_env() -> Result<Self, CustodyError> {
+        Self::default().with_env(std::env::vars())
+    }
+
+    /// Overrides values with `SECUREVAULT_` variables from `vars` and
+    /// validates the result
+    ///
+    /// # Example
+    /// ```
+    /// use securevault::{amount, Config};
+    /// let vars = [
+    ///     ("SECUREVAULT_CONFIRMATIONS__ASSETS__BTC", "3"),
+    ///     ("SECUREVAULT_LIMITS__HOT__DAILY_LIMIT", "20"),
+    ///     ("PATH", "/usr/bin"),
+    /// ];
+    /// let config = Config::default()
+    ///     .with_env(vars.map(|(k, v)| (k.to_string(), v.to_string())))
+    ///     .unwrap();
+    /// assert_eq!(config.confirmations.assets["btc"], 3);
+    /// assert_eq!(config.limits.hot.unwrap().daily_limit, Some(amount!(20)));
+    /// ```
+    pub fn with_env(
+        self,
+        vars: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<Self, CustodyError> {
+        let mut tree = serde_json::to_value(&self).map_err(invalid)?;
+        for (name, raw) in vars {
+            let Some(key) = name.strip_prefix(ENV_PREFIX) else {
+                continue;
+            };
+            let path: Vec<String> = key.split("__").map(str::to_lowercase).collect();
+            if !SECTIONS.contains(&path[0].as_str()) {
+                continue;
+            }
+            // Numbers and booleans are read as such, anything else as text
+            let value = serde_json::from_str(&raw).unwrap_or(Value::String(raw));
+            set_path(&mut tree, &path, value);
+        }
+        let config: Config = serde_json::from_value(tree).map_err(invalid)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Checks that every value is usable
+    pub fn validate(&self) -> Result<(), CustodyError> {
+        if let Some(storage) = &self.storage {
+            if storage.path.as_os_str().is_empty() {
+                return Err(invalid("storage.path is empty"));
+            }
+            if cfg!(not(feature = "sqlite")) && storage.backend == StorageBackend::Sqlite {
+                return Err(invalid("storage.backend sqlite needs the `sqlite` feature"));
+            }
+        }
+        for (name, _, policy) in self.limits.entries() {
+            validate_policy(name, policy)?;
+        }
+        for (name, _, quorum) in self.quorums.entries() {
+            if Quorum::new(quorum.required, quorum.signers).is_err() {
+                return Err(invalid(format!(
+                    "quorums.{} {} is not satisfiable",
+                    name, quorum
+                )));
+            }
+        }
+        if self
+            .confirmations
+            .assets
+            .keys()
+            .any(|symbol| symbol.is_empty())
+        {
+            return Err(invalid("confirmations.assets has an empty symbol"));
+        }
+        Ok(())
+    }
+}
+
+impl StorageConfig {
+    fn open(&self) -> Result<Arc<dyn Storage>, CustodyError> {
+        match self.backend {
+            StorageBackend::Json => Ok(Arc::new(JsonFileStorage::new(&self.path))),
+            StorageBackend::Wal => Ok(Arc::new(WalStorage::open(&self.path)?)),
+            #[cfg(feature = "sqlite")]
+            StorageBackend::Sqlite => Ok(Arc::new(crate::SqliteStorage::open(&self.path)?)),
+            #[cfg(not(feature = "sqlite"))]
+            StorageBackend::Sqlite => {
+                Err(invalid("storage.backend sqlite needs the `sqlite` feature"))
+            }
+        }
+    }
+}
+
+impl CustodySystem {
+    /// Creates a custody system configured by `config`
+    ///
+    /// The configuration is validated first. With storage configured the
+    /// ledger is restored from it, as with
+    /// [`with_storage`](Self::with_storage).
+    ///
+    /// # Example
+    /// ```
+    /// use securevault::{Asset, Config, CustodySystem, Quorum, WalletType};
+    /// let mut config = Config::default();
+    /// config.quorums.cold = Some(Quorum::new(2, 3).unwrap());
+    /// config.confirmations.assets.insert("ETH".to_string(), 12);
+    ///
+    /// let system = CustodySystem::from_config(&config).unwrap();
+    /// assert_eq!(system.default_quorum(&WalletType::Cold), Some(Quorum::new(2, 3).unwrap()));
+    /// assert_eq!(system.confirmations_required(&Asset::Eth), 12);
+    /// ```
+    pub fn from_config(config: &Config) -> Result<Self, CustodyError> {
+        config.validate()?;
+        let mut system = match &config.storage {
+            Some(storage) => CustodySystem::with_storage(storage.open()?)?,
+            None => CustodySystem::new(),
+        };
+        for (_, wallet_type, policy) in config.limits.entries() {
+            system.replace_default_withdrawal_policy(wallet_type, policy.clone());
+        }
+        for (_, wallet_type, quorum) in config.quorums.entries() {
+            system.set_default_quorum(wallet_type, *quorum);
+        }
+        system.set_required_confirmations(config.confirmations.required);
+        for (symbol, confirmations) in &config.confirmations.assets {
+            system.set_confirmation_policy(builtin_asset(&symbol.to_uppercase()), *confirmations);
+        }
+        Ok(system)
+    }
+}
+
+fn validate_policy(name: &str, policy: &WithdrawalPolicy) -> Result<(), CustodyError> {
+    let limits = [
+        ("max_per_transaction", policy.max_per_transaction),
+        ("daily_limit", policy.daily_limit),
+        ("weekly_limit", policy.weekly_limit),
+    ];
+    for (key, limit) in limits {
+        if limit.is_some_and(|limit| !limit.is_positive()) {
+            return Err(invalid(format!("limits.{}.{} must be positive", name, key)));
+        }
+    }
+    // Each limit must fit within the longer-term ones
+    for (i, (key, limit)) in limits.iter().enumerate() {
+        for (wider_key, wider) in &limits[i + 1..] {
+            if let (Some(limit), Some(wider)) = (limit, wider) {
+                if limit > wider {
+                    return Err(invalid(format!(
+                        "limits.{}.{} exceeds {}",
+                        name, key, wider_key
+                    )));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Sets the value at `path`, creating tables along the way
+fn set_path(tree: &mut Value, path: &[String], value: Value) {
+    let mut node = tree;
+    for key in path {
+        if !node.is_object() {
+            *node = Value::Object(Map::new());
+        }
+        node = node
+            .as_object_mut()
+            .expect("made an object above")
+            .entry(key.clone())
+            .or_insert(Value::Null);
+    }
+    *node = value;
+}
+
+fn invalid(reason: impl ToString) -> CustodyError {
+    CustodyError::InvalidConfig(reason.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[cfg(feature = "config")]
+    #[test]
+    fn test_toml_configures_the_system() {
+        let path =
+            std::env::temp_dir().join(format!("securevault-config-{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let toml = format!(
+            r#"
+            [storage]
+            path = "{}"
+
+            [limits.hot]
+            max_per_transaction = "5"
+            daily_limit = 20
+
+            [quorums]
+            cold = {{ required = 3, signers = 5 }}
+
+            [confirmations]
+            required = 2
+            assets = {{ BTC = 3 }}
+            "#,
+            path.display()
+        );
+        let config = Config::from_toml(&toml).unwrap();
+        assert_eq!(
+            config.storage.as_ref().unwrap().backend,
+            StorageBackend::Json
+        );
+
+        let mut system = CustodySystem::from_config(&config).unwrap();
+        assert_eq!(
+            system.default_quorum(&WalletType::Cold),
+            Some(Quorum::new(3, 5).unwrap())
+        );
+        assert_eq!(system.required_confirmations(), 2);
+        assert_eq!(system.confirmations_required(&crate::Asset::Btc), 3);
+        system
+            .create_wallet("w".to_string(), "0x1".to_string(), WalletType::Hot)
+            .unwrap();
+        system.deposit("w", amount!(50)).unwrap();
+        assert!(matches!(
+            system.withdraw("w", amount!(6)),
+            Err(CustodyError::PolicyViolation { .. })
+        ));
+        assert!(path.exists());
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(matches!(
+            Config::from_toml("[limits.warm]\ndaily_limit = 1"),
+            Err(CustodyError::InvalidConfig(_))
+        ));
+    }
+
+    #[test]
+    fn test_environment_overrides() {
+        let mut config = Config::default();
+        config.confirmations.required = 10;
+        let config = config
+            .with_env(env(&[
+                ("SECUREVAULT_CONFIRMATIONS__REQUIRED", "4"),
+                ("SECUREVAULT_STORAGE__PATH", "/tmp/ledger.json"),
+                ("SECUREVAULT_QUORUMS__HOT__REQUIRED", "1"),
+                ("SECUREVAULT_QUORUMS__HOT__SIGNERS", "2"),
+                ("SECUREVAULT_LOG", "debug"),
+            ]))
+            .unwrap();
+        assert_eq!(config.confirmations.required, 4);
+        assert_eq!(
+            config.storage,
+            Some(StorageConfig {
+                backend: StorageBackend::Json,
+                path: PathBuf::from("/tmp/ledger.json"),
+            })
+        );
+        assert_eq!(config.quorums.hot, Some(Quorum::new(1, 2).unwrap()));
+
+        assert!(matches!(
+            Config::default().with_env(env(&[("SECUREVAULT_STORAGE__KIND", "json")])),
+            Err(CustodyError::InvalidConfig(_))
+        ));
+    }
+
+    #[test]
+    fn test_validation() {
+        let mut config = Config::default();
+        config.quorums.cold = Some(Quorum {
+            required: 4,
+            signers: 3,
+        });
+        assert_eq!(
+            CustodySystem::from_config(&config).unwrap_err(),
+            CustodyError::InvalidConfig("quorums.cold 4-of-3 is not satisfiable".to_string())
+        );
+
+        let mut config = Config::default();
+        config.limits.hot = Some(WithdrawalPolicy {
+            daily_limit: Some(amount!(50)),
+            weekly_limit: Some(amount!(20)),
+            ..WithdrawalPolicy::default()
+        });
+        assert_eq!(
+            config.validate(),
+            Err(CustodyError::InvalidConfig(
+                "limits.hot.daily_limit exceeds weekly_limit".to_string()
+            ))
+        );
+        config.limits.hot = Some(WithdrawalPolicy {
+            max_per_transaction: Some(amount!(0)),
+            ..WithdrawalPolicy::default()
+        });
+        assert_eq!(
+            config.validate(),
+            Err(CustodyError::InvalidConfig(
+                "limits.hot.max_per_transaction must be positive".to_string()
+            ))
+        );
+    }
+}
@@ -0,0 +1,314 @@
+//! Cross-asset conversion between custodied wallets.
+//!
+//! A conversion debits the source wallet in its asset and credits the
+//! destination wallet in its asset at a rate supplied by a [`RateProvider`].
+//! Both legs are recorded in the audit trail together with the applied rate.
+//!
+//! The source wallet is checked like a withdrawal, so cold, multisig and
+//! joint wallets cannot convert their funds out, and conversions are
+//! screened. A flagged conversion is booked at the rate it was submitted
+//! with once a compliance officer clears it.
+
+use crate::precheck::Authorization;
+use crate::{
+    Amount, Asset, CustodyError, CustodySystem, OperationKind, ScreeningRequest, Transaction,
+    TransactionType,
+};
+use std::collections::HashMap;
+
+/// Source of exchange rates between assets
+pub trait RateProvider {
+    /// Returns how many units of `to` one unit of `from` is worth, or `None`
+    /// if no rate is available for the pair
+    fn rate(&self, from: &Asset, to: &Asset) -> Option<f64>;
+}
+
+/// A rate provider backed by a fixed table of rates
+#[derive(Debug, Clone, Default)]
+pub struct StaticRateProvider {
+    rates: HashMap<(Asset, Asset), f64>,
+}
+
+impl StaticRateProvider {
+    /// Creates an empty rate table
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the rate for converting `from` into `to`. The inverse pair is
+    /// derived automatically unless set explicitly.
+    pub fn set_rate(&mut self, from: Asset, to: Asset, rate: f64) {
+        self.rates.insert((from, to), rate);
+    }
+}
+
+impl RateProvider for StaticRateProvider {
+    fn rate(&self, from: &Asset, to: &Asset) -> Option<f64> {
+        if from == to {
+            return Some(1.0);
+        }
+        if let Some(rate) = self.rates.get(&(from.clone(), to.clone())) {
+            return Some(*rate);
+        }
+        self.rates
+            .get(&(to.clone(), from.clone()))
+            .filter(|rate| **rate > 0.0)
+            .map(|rate| 1.0 / rate)
+    }
+}
+
+/// Result of a completed conversion
+#[derive(Debug, Clone, PartialEq)]
+pub struct Conversion {
+    pub from_wallet: String,
+    pub to_wallet: String,
+    pub from_asset: Asset,
+    pub to_asset: Asset,
+    /// Amount debited from the source wallet
+    pub debited: Amount,
+    /// Amount credited to the destination wallet
+    pub credited: Amount,
+    /// Rate applied (units of `to_asset` per unit of `from_asset`)
+    pub rate: f64,
+}
+
+impl CustodySystem {
+    /// Converts funds between two wallets holding different assets
+    ///
+    /// # Arguments
+    /// * `from_wallet` - Wallet to debit, in its own asset
+    /// * `to_wallet` - Wallet to credit, in its own asset
+    /// * `amount` - Amount to debit from the source wallet
+    /// * `rate_provider` - Source of the exchange rate to apply
+    ///
+    /// # Returns
+    /// The executed conversion with both amounts and the applied rate
+    pub fn convert(
+        &mut self,
+        from_wallet: &str,
+        to_wallet: &str,
+        amount: Amount,
+        rate_provider: &dyn RateProvider,
+    ) -> Result<Conversion, CustodyError> {
+        if !amount.is_positive() {
+            return Err(CustodyError::NonPositiveAmount(OperationKind::Conversion));
+        }
+        if from_wallet == to_wallet {
+            return Err(CustodyError::SameWallet);
+        }
+
+        let source = self
+            .get_wallet(from_wallet)
+            .ok_or_else(|| CustodyError::SourceWalletNotFound(from_wallet.to_string()))?;
+        let destination = self
+            .get_wallet(to_wallet)
+            .ok_or_else(|| CustodyError::DestinationWalletNotFound(to_wallet.to_string()))?;
+        let (from_asset, to_asset) = (source.asset.clone(), destination.asset.clone());
+        let rate = rate_provider.rate(&from_asset, &to_asset).ok_or_else(|| {
+            CustodyError::RateUnavailable {
+                from: from_asset.symbol().to_string(),
+                to: to_asset.symbol().to_string(),
+            }
+        })?;
+        if !rate.is_finite() || rate <= 0.0 {
+            return Err(CustodyError::InvalidRate(rate));
+        }
+
+        self.check_conversion(from_wallet, to_wallet, amount)?;
+        let request = ScreeningRequest {
+            kind: OperationKind::Conversion,
+            wallet_id: from_wallet.to_string(),
+            asset: from_asset,
+            amount,
+            destination: Some(self.wallets[to_wallet].address.clone()),
+            to_wallet_id: Some(to_wallet.to_string()),
+        };
+        self.screen_conversion(request, rate)?;
+        self.book_conversion(from_wallet, to_wallet, amount, rate)
+    }
+
+    /// Checks that `amount` may leave the source wallet like a withdrawal
+    /// and that the destination wallet may be credited
+    pub(crate) fn check_conversion(
+        &self,
+        from_wallet: &str,
+        to_wallet: &str,
+        amount: Amount,
+    ) -> Result<(), CustodyError> {
+        let destination = self
+            .get_wallet(to_wallet)
+            .ok_or_else(|| CustodyError::DestinationWalletNotFound(to_wallet.to_string()))?;
+        let reason = self
+            .withdrawal_blockers(from_wallet, None, amount, None, Authorization::Direct)
+            .into_iter()
+            // The proceeds stay in custody
+            .filter(|reason| !matches!(reason, CustodyError::DestinationRequired(_)))
+            .chain(self.credit_blockers(destination))
+            .next();
+        match reason {
+            Some(reason) => Err(reason),
+            None => Ok(()),
+        }
+    }
+
+    /// Records both legs of a checked conversion at `rate`
+    pub(crate) fn book_conversion(
+        &mut self,
+        from_wallet: &str,
+        to_wallet: &str,
+        amount: Amount,
+        rate: f64,
+    ) -> Result<Conversion, CustodyError> {
+        let from_asset = self.wallets[from_wallet].asset.clone();
+        let destination = &self.wallets[to_wallet];
+        let to_asset = destination.asset.clone();
+        // Credited in the destination asset's own precision
+        let credited = amount
+            .checked_mul_f64(rate)
+            .map(|credited| credited.round_dp(to_asset.decimals()))
+            .ok_or(CustodyError::AmountOverflow)?;
+        destination
+            .balance
+            .checked_add(credited)
+            .ok_or(CustodyError::AmountOverflow)?;
+
+        let mut debit = Transaction::new(
+            from_wallet,
+            TransactionType::ConversionOut,
+            amount,
+            from_asset.clone(),
+            self.current_timestamp(),
+        );
+        debit.rate = Some(rate);
+        debit.counterparty = Some(to_wallet.to_string());
+
+        let mut credit = Transaction::new(
+            to_wallet,
+            TransactionType::ConversionIn,
+            credited,
+            to_asset.clone(),
+            self.current_timestamp(),
+        );
+        credit.rate = Some(rate);
+        credit.counterparty = Some(from_wallet.to_string());
+        self.record_transactions(vec![debit, credit])?;
+        self.take_wallet_token(from_wallet);
+
+        Ok(Conversion {
+            from_wallet: from_wallet.to_string(),
+            to_wallet: to_wallet.to_string(),
+            from_asset,
+            to_asset,
+            debited: amount,
+            credited,
+            rate,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::WalletType;
+
+    fn setup() -> (CustodySystem, StaticRateProvider) {
+        let mut system = CustodySystem::new();
+        system
+            .create_wallet_with_asset(
+                "btc".to_string(),
+                "bc1qbtc".to_string(),
+                WalletType::Hot,
+                Asset::Btc,
+            )
+            .unwrap();
+        system
+            .create_wallet_with_asset(
+                "eth".to_string(),
+                "0xeth".to_string(),
+                WalletType::Hot,
+                Asset::Eth,
+            )
+            .unwrap();
+        system.deposit("btc", amount!(2.0)).unwrap();
+
+        let mut rates = StaticRateProvider::new();
+        rates.set_rate(Asset::Btc, Asset::Eth, 20.0);
+        (system, rates)
+    }
+
+    #[test]
+    fn test_convert_records_both_legs() {
+        let (mut system, rates) = setup();
+        let conversion = system.convert("btc", "eth", amount!(0.5), &rates).unwrap();
+
+        assert_eq!(conversion.credited, amount!(10.0));
+        assert_eq!(conversion.rate, 20.0);
+        assert_eq!(system.get_wallet("btc").unwrap().balance, amount!(1.5));
+        assert_eq!(system.get_wallet("eth").unwrap().balance, amount!(10.0));
+
+        let debit = system.get_wallet_transactions("btc")[1];
+        assert_eq!(debit.transaction_type, TransactionType::ConversionOut);
+        assert_eq!(debit.asset, Asset::Btc);
+        assert_eq!(debit.rate, Some(20.0));
+        assert_eq!(debit.counterparty.as_deref(), Some("eth"));
+
+        let credit = system.get_wallet_transactions("eth")[0];
+        assert_eq!(credit.transaction_type, TransactionType::ConversionIn);
+        assert_eq!(credit.asset, Asset::Eth);
+        assert_eq!(credit.amount, amount!(10.0));
+    }
+
+    #[test]
+    fn test_convert_uses_inverse_rate() {
+        let (mut system, rates) = setup();
+        system.convert("btc", "eth", amount!(1.0), &rates).unwrap();
+        let conversion = system.convert("eth", "btc", amount!(10.0), &rates).unwrap();
+        assert_eq!(conversion.credited, amount!(0.5));
+    }
+
+    #[test]
+    fn test_convert_without_rate_fails() {
+        let (mut system, _) = setup();
+        let result = system.convert("btc", "eth", amount!(1.0), &StaticRateProvider::new());
+        assert!(matches!(result, Err(CustodyError::RateUnavailable { .. })));
+        assert_eq!(system.get_wallet("btc").unwrap().balance, amount!(2.0));
+        assert_eq!(system.get_all_transactions().len(), 1);
+    }
+
+    #[test]
+    fn test_convert_insufficient_balance() {
+        let (mut system, rates) = setup();
+        let result = system.convert("btc", "eth", amount!(3.0), &rates);
+        assert!(matches!(
+            result,
+            Err(CustodyError::InsufficientBalance { .. })
+        ));
+    }
+
+    #[test]
+    fn test_source_is_checked_like_a_withdrawal() {
+        let (mut system, rates) = setup();
+        system
+            .create_wallet("vault".to_string(), "bc1qv".to_string(), WalletType::Cold)
+            .unwrap();
+        system.deposit("vault", amount!(1.0)).unwrap();
+        assert_eq!(
+            system.convert("vault", "eth", amount!(0.5), &rates),
+            Err(CustodyError::ApprovalRequired("vault".to_string()))
+        );
+        assert_eq!(system.get_wallet("vault").unwrap().balance, amount!(1.0));
+
+        let mut screener = crate::RuleScreener::new();
+        screener.review_above(amount!(1.0));
+        system.set_compliance_screener(std::sync::Arc::new(screener));
+        let Err(CustodyError::FlaggedForReview(id)) =
+            system.convert("btc", "eth", amount!(1.5), &rates)
+        else {
+            panic!("expected a review");
+        };
+        assert_eq!(system.get_wallet("eth").unwrap().balance, amount!(0.0));
+        system.approve_flagged(id, "compliance").unwrap();
+        assert_eq!(system.get_wallet("btc").unwrap().balance, amount!(0.5));
+        assert_eq!(system.get_wallet("eth").unwrap().balance, amount!(30.0));
+    }
+}
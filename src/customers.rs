@@ -0,0 +1,319 @@
+//! Customer funds and their segregation.
+//!
+//! Customer money sits either in a segregated wallet, owned by one
+//! [`Customer`], or in an omnibus wallet pooling many customers. An
+//! omnibus wallet keeps a sub-ledger of each customer's claim on its
+//! primary asset, moved by [`CustodySystem::credit_customer`] and
+//! [`CustodySystem::debit_customer`]. The claims must always add up to the
+//! wallet's balance; [`CustodySystem::verify_omnibus`] checks it, so
+//! deposits or withdrawals that bypass the sub-ledger are caught.
+//!
+//! Customer bookings are tagged with the customer id under the
+//! [`CUSTOMER_METADATA_KEY`] transaction metadata key.
+
+use crate::{Amount, CustodyError, CustodySystem, Wallet};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Transaction metadata key holding the customer a booking is for
+pub const CUSTOMER_METADATA_KEY: &str = "customer";
+
+/// A client whose funds are held in custody
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Customer {
+    pub id: String,
+    pub name: String,
+}
+
+/// How a wallet holds customer funds
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CustodyModel {
+    /// All funds belong to one customer
+    Segregated { customer: String },
+    /// Funds are pooled; each customer's claim is tracked separately
+    Omnibus { claims: BTreeMap<String, Amount> },
+}
+
+impl CustodySystem {
+    /// Registers a customer
+    pub fn create_customer(&mut self, id: &str, name: &str) -> Result<(), CustodyError> {
+        if self.customers.contains_key(id) {
+            return Err(CustodyError::CustomerAlreadyExists(id.to_string()));
+        }
+        self.customers.insert(
+            id.to_string(),
+            Customer {
+                id: id.to_string(),
+                name: name.to_string(),
+            },
+        );
+        Ok(())
+    }
+
+    /// Gets a customer by id
+    pub fn get_customer(&self, id: &str) -> Result<&Customer, CustodyError> {
+        self.customers
+            .get(id)
+            .ok_or_else(|| CustodyError::CustomerNotFound(id.to_string()))
+    }
+
+    /// Dedicates an empty wallet to one customer's funds
+    pub fn make_segregated(&mut self, wallet_id: &str, customer: &str) -> Result<(), CustodyError> {
+        self.get_customer(customer)?;
+        self.set_custody_model(
+            wallet_id,
+            CustodyModel::Segregated {
+                customer: customer.to_string(),
+            },
+        )
+    }
+
+    /// Turns an empty wallet into an omnibus wallet pooling customer funds
+    pub fn make_omnibus(&mut self, wallet_id: &str) -> Result<(), CustodyError> {
+        self.set_custody_model(
+            wallet_id,
+            CustodyModel::Omnibus {
+                claims: BTreeMap::new(),
+            },
+        )
+    }
+
+    /// Credits `amount` to `customer` in a wallet holding their funds
+    ///
+    /// # Example
+    /// ```
+    /// use securevault::{amount, CustodySystem, WalletType};
+    /// let mut system = CustodySystem::new();
+    /// system.create_wallet("pool".to_string(), "bc1qpool".to_string(), WalletType::Hot).unwrap();
+    /// system.make_omnibus("pool").unwrap();
+    /// system.create_customer("alice", "Alice").unwrap();
+    /// system.create_customer("bob", "Bob").unwrap();
+    ///
+    /// system.credit_customer("pool", "alice", amount!(3)).unwrap();
+    /// system.credit_customer("pool", "bob", amount!(2)).unwrap();
+    /// system.debit_customer("pool", "alice", amount!(1)).unwrap();
+    ///
+    /// assert_eq!(system.customer_balance("pool", "alice").unwrap(), amount!(2));
+    /// assert_eq!(system.get_wallet("pool").unwrap().balance, amount!(4));
+    /// system.verify_omnibus("pool").unwrap();
+    /// ```
+    pub fn credit_customer(
+        &mut self,
+        wallet_id: &str,
+        customer: &str,
+        amount: Amount,
+    ) -> Result<(), CustodyError> {
+        let claim = self.customer_balance(wallet_id, customer)?;
+        let claim = claim
+            .checked_add(amount)
+            .ok_or(CustodyError::AmountOverflow)?;
+        self.deposit(wallet_id, amount)?;
+        self.book_customer(wallet_id, customer, claim)
+    }
+
+    /// Debits `amount` from `customer` in a wallet holding their funds
+    ///
+    /// Fails with [`CustodyError::InsufficientBalance`] if it exceeds the
+    /// customer's claim; otherwise the withdrawal goes through the usual
+    /// checks.
+    pub fn debit_customer(
+        &mut self,
+        wallet_id: &str,
+        customer: &str,
+        amount: Amount,
+    ) -> Result<(), CustodyError> {
+        let claim = self.customer_balance(wallet_id, customer)?;
+        if amount.is_positive() && claim < amount {
+            return Err(CustodyError::InsufficientBalance {
+                available: claim,
+                requested: amount,
+            });
+        }
+        self.withdraw(wallet_id, amount)?;
+        self.book_customer(wallet_id, customer, claim - amount)
+    }
+
+    /// Returns `customer`'s funds in a wallet: their claim on an omnibus
+    /// wallet, or the whole balance of their segregated wallet
+    pub fn customer_balance(
+        &self,
+        wallet_id: &str,
+        customer: &str,
+    ) -> Result<Amount, CustodyError> {
+        self.get_customer(customer)?;
+        let wallet = self
+            .get_wallet(wallet_id)
+            .ok_or_else(|| CustodyError::WalletNotFound(wallet_id.to_string()))?;
+        match &wallet.custody {
+            Some(CustodyModel::Omnibus { claims }) => {
+                Ok(claims.get(customer).copied().unwrap_or_default())
+            }
+            Some(CustodyModel::Segregated { customer: owner }) if owner == customer => {
+                Ok(wallet.balance)
+            }
+            _ => Err(CustodyError::NotCustomerWallet {
+                wallet_id: wallet_id.to_string(),
+                customer: customer.to_string(),
+            }),
+        }
+    }
+
+    /// Checks that the customer claims on an omnibus wallet add up to its
+    /// balance
+    pub fn verify_omnibus(&self, wallet_id: &str) -> Result<(), CustodyError> {
+        let wallet = self
+            .get_wallet(wallet_id)
+            .ok_or_else(|| CustodyError::WalletNotFound(wallet_id.to_string()))?;
+        match claims_mismatch(wallet) {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+
+    /// Checks every omnibus wallet, returning one
+    /// [`CustodyError::ClaimsMismatch`] per wallet that does not balance,
+    /// ordered by wallet id
+    pub fn verify_all_omnibus(&self) -> Vec<CustodyError> {
+        let mut wallets: Vec<_> = self.wallets.values().collect();
+        wallets.sort_by(|a, b| a.id.cmp(&b.id));
+        wallets.into_iter().filter_map(claims_mismatch).collect()
+    }
+
+    fn set_custody_model(
+        &mut self,
+        wallet_id: &str,
+        model: CustodyModel,
+    ) -> Result<(), CustodyError> {
+        let wallet = self
+            .get_wallet(wallet_id)
+            .ok_or_else(|| CustodyError::WalletNotFound(wallet_id.to_string()))?;
+        if !wallet.balance.is_zero() || !wallet.holdings.is_empty() {
+            return Err(CustodyError::WalletNotEmpty(wallet_id.to_string()));
+        }
+        self.update_wallet_annotations(wallet_id, |wallet| wallet.custody = Some(model))
+    }
+
+    /// Records the customer's new claim and tags the booking just made
+    fn book_customer(
+        &mut self,
+        wallet_id: &str,
+        customer: &str,
+        claim: Amount,
+    ) -> Result<(), CustodyError> {
+        self.update_last_transaction(|tx| {
+            tx.metadata
+                .insert(CUSTOMER_METADATA_KEY.to_string(), customer.to_string());
+        })?;
+        self.update_wallet_annotations(wallet_id, |wallet| {
+            if let Some(CustodyModel::Omnibus { claims }) = &mut wallet.custody {
+                if claim.is_zero() {
+                    claims.remove(customer);
+                } else {
+                    claims.insert(customer.to_string(), claim);
+                }
+            }
+        })
+    }
+}
+
+fn claims_mismatch(wallet: &Wallet) -> Option<CustodyError> {
+    let Some(CustodyModel::Omnibus { claims }) = &wallet.custody else {
+        return None;
+    };
+    let total = claims
+        .values()
+        .try_fold(Amount::ZERO, |total, claim| total.checked_add(*claim));
+    match total {
+        Some(total) if total == wallet.balance => None,
+        total => Some(CustodyError::ClaimsMismatch {
+            wallet_id: wallet.id.clone(),
+            claims: total.unwrap_or(Amount::MAX),
+            balance: wallet.balance,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::WalletType;
+
+    fn system() -> CustodySystem {
+        let mut system = CustodySystem::new();
+        for id in ["pool", "alice-vault"] {
+            system
+                .create_wallet(id.to_string(), id.to_string(), WalletType::Hot)
+                .unwrap();
+        }
+        system.create_customer("alice", "Alice").unwrap();
+        system.create_customer("bob", "Bob").unwrap();
+        system.make_omnibus("pool").unwrap();
+        system.make_segregated("alice-vault", "alice").unwrap();
+        system
+    }
+
+    #[test]
+    fn test_omnibus_sub_ledgers() {
+        let mut system = system();
+        system.credit_customer("pool", "alice", amount!(5)).unwrap();
+        system.credit_customer("pool", "bob", amount!(1)).unwrap();
+        assert_eq!(
+            system.debit_customer("pool", "bob", amount!(2)),
+            Err(CustodyError::InsufficientBalance {
+                available: amount!(1),
+                requested: amount!(2),
+            })
+        );
+        system.debit_customer("pool", "bob", amount!(1)).unwrap();
+        assert_eq!(
+            system.customer_balance("pool", "bob").unwrap(),
+            Amount::ZERO
+        );
+        assert_eq!(system.get_wallet("pool").unwrap().balance, amount!(5));
+        assert!(system.verify_all_omnibus().is_empty());
+
+        let tx = system.get_all_transactions().last().unwrap();
+        assert_eq!(tx.metadata[CUSTOMER_METADATA_KEY], "bob");
+    }
+
+    #[test]
+    fn test_bookings_outside_the_sub_ledger_break_the_invariant() {
+        let mut system = system();
+        system.credit_customer("pool", "alice", amount!(5)).unwrap();
+        system.withdraw("pool", amount!(2)).unwrap();
+        let mismatch = CustodyError::ClaimsMismatch {
+            wallet_id: "pool".to_string(),
+            claims: amount!(5),
+            balance: amount!(3),
+        };
+        assert_eq!(system.verify_omnibus("pool"), Err(mismatch.clone()));
+        assert_eq!(system.verify_all_omnibus(), [mismatch]);
+    }
+
+    #[test]
+    fn test_segregated_wallet_serves_its_customer_only() {
+        let mut system = system();
+        system
+            .credit_customer("alice-vault", "alice", amount!(4))
+            .unwrap();
+        assert_eq!(
+            system.customer_balance("alice-vault", "alice").unwrap(),
+            amount!(4)
+        );
+        assert_eq!(
+            system.credit_customer("alice-vault", "bob", amount!(1)),
+            Err(CustodyError::NotCustomerWallet {
+                wallet_id: "alice-vault".to_string(),
+                customer: "bob".to_string(),
+            })
+        );
+        assert_eq!(
+            system.make_omnibus("alice-vault"),
+            Err(CustodyError::WalletNotEmpty("alice-vault".to_string()))
+        );
+        assert_eq!(
+            system.credit_customer("pool", "carol", amount!(1)),
+            Err(CustodyError::CustomerNotFound("carol".to_string()))
+        );
+    }
+}
@@ -0,0 +1,291 @@
+//! Embedded web dashboard (feature `dashboard`).
+//!
+//! Serves a single self-contained HTML page summarising balances, hot/cold
+//! allocation, recent transactions, pending approvals, and audit alerts.
+//! The page has no external assets or scripts, so small deployments get
+//! basic observability without building or hosting a frontend.
+
+use crate::format::AmountFormatter;
+use crate::{Amount, CustodySystem, JointOperationKind, OperationStatus, WalletType};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, RwLock};
+
+/// Number of transactions shown in the "recent" table
+const RECENT_TRANSACTIONS: usize = 20;
+
+const STYLE: &str = "body{font-family:sans-serif;margin:2em;color:#222}\
+table{border-collapse:collapse;margin-bottom:2em}\
+th,td{border:1px solid #ccc;padding:4px 8px;text-align:left}\
+th{background:#f4f4f4}.num{text-align:right}\
+.alert{color:#a00}.ok{color:#070}";
+
+/// Renders the dashboard page for the current state of `system`
+pub fn render_dashboard(system: &CustodySystem) -> String {
+    let formatter = AmountFormatter::default();
+    let mut html = String::new();
+    html.push_str("<!DOCTYPE html><html><head><meta charset=\"utf-8\">");
+    html.push_str("<meta http-equiv=\"refresh\" content=\"30\">");
+    let _ = write!(html, "<title>SecureVault</title><style>{}</style>", STYLE);
+    html.push_str("</head><body><h1>SecureVault</h1>");
+
+    // Alerts first so problems are visible without scrolling
+    html.push_str("<h2>Alerts</h2>");
+    let report = system.audit();
+    if report.passed() {
+        html.push_str("<p class=\"ok\">All integrity checks passed.</p>");
+    } else {
+        html.push_str("<ul>");
+        for check in report.failures() {
+            for detail in &check.details {
+                let _ = write!(
+                    html,
+                    "<li class=\"alert\">{}: {}</li>",
+                    check.name,
+                    escape(detail)
+                );
+            }
+        }
+        html.push_str("</ul>");
+    }
+
+    // Hot/cold allocation per asset; multi-signature wallets count as cold
+    let mut allocation: BTreeMap<String, (crate::Asset, Amount, Amount)> = BTreeMap::new();
+    for wallet in system.get_all_wallets().values() {
+        for (asset, balance) in wallet.balances() {
+            let entry = allocation
+                .entry(asset.symbol().to_string())
+                .or_insert_with(|| (asset.clone(), Amount::ZERO, Amount::ZERO));
+            match wallet.wallet_type {
+                WalletType::Hot => entry.1 += balance,
+                WalletType::Cold | WalletType::MultiSig { .. } => entry.2 += balance,
+            }
+        }
+    }
+    html.push_str("<h2>Allocation</h2><table><tr><th>Asset</th><th>Hot</th><th>Cold</th><th>Hot share</th></tr>");
+    for (symbol, (asset, hot, cold)) in &allocation {
+        let total = *hot + *cold;
+        let share = if total.is_positive() {
+            hot.to_f64() / total.to_f64() * 100.0
+        } else {
+            0.0
+        };
+        let _ = write!(
+            html,
+            "<tr><td>{}</td><td class=\"num\">{}</td><td class=\"num\">{}</td><td class=\"num\">{:.1}%</td></tr>",
+            escape(symbol),
+            formatter.format(*hot, asset),
+            formatter.format(*cold, asset),
+            share
+        );
+    }
+    html.push_str("</table>");
+
+    // Balances
+    let mut wallets: Vec<_> = system.get_all_wallets().values().collect();
+    wallets.sort_by(|a, b| a.id.cmp(&b.id));
+    html.push_str("<h2>Balances</h2><table><tr><th>Wallet</th><th>Type</th><th>Address</th><th>Balance</th></tr>");
+    for wallet in wallets {
+        let _ = write!(
+            html,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td class=\"num\">{}</td></tr>",
+            escape(&wallet.id),
+            wallet.wallet_type,
+            escape(&wallet.address),
+            formatter.format(wallet.balance, &wallet.asset)
+        );
+    }
+    html.push_str("</table>");
+
+    // Pending approvals
+    html.push_str("<h2>Pending approvals</h2>");
+    let pending: Vec<_> = system
+        .joint_operations
+        .values()
+        .filter(|op| op.status == OperationStatus::Pending)
+        .collect();
+    if pending.is_empty() {
+        html.push_str("<p>None.</p>");
+    } else {
+        html.push_str(
+            "<table><tr><th>Id</th><th>Wallet</th><th>Operation</th><th>Signed by</th></tr>",
+        );
+        for op in pending {
+            let description = match &op.kind {
+                JointOperationKind::Withdrawal { amount } => {
+                    let asset = system
+                        .get_wallet(&op.wallet_id)
+                        .map(|wallet| wallet.asset.clone())
+                        .unwrap_or_default();
+                    format!("Withdrawal of {}", formatter.format(*amount, &asset))
+                }
+                JointOperationKind::OwnershipChange(change) => format!("{:?}", change),
+            };
+            let signers: Vec<_> = op.signatures.iter().map(String::as_str).collect();
+            let _ = write!(
+                html,
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                op.id,
+                escape(&op.wallet_id),
+                escape(&description),
+                escape(&signers.join(", "))
+            );
+        }
+        html.push_str("</table>");
+    }
+
+    // Recent transactions, newest first
+    html.push_str("<h2>Recent transactions</h2><table><tr><th>Time</th><th>Wallet</th><th>Type</th><th>Amount</th></tr>");
+    for tx in system
+        .get_all_transactions()
+        .iter()
+        .rev()
+        .take(RECENT_TRANSACTIONS)
+    {
+        let _ = write!(
+            html,
+            "<tr><td>{}</td><td>{}</td><td>{:?}</td><td class=\"num\">{}</td></tr>",
+            tx.timestamp,
+            escape(&tx.wallet_id),
+            tx.transaction_type,
+            formatter.format(tx.amount, &tx.asset)
+        );
+    }
+    html.push_str("</table></body></html>");
+    html
+}
+
+/// Serves the dashboard on `listener` until the listener fails
+///
+/// `GET /` returns the dashboard page; any other path returns 404. Each
+/// request renders from a read lock on the shared system, so the dashboard
+/// never blocks writers for longer than one render.
+pub fn serve_dashboard(
+    listener: TcpListener,
+    system: Arc<RwLock<CustodySystem>>,
+) -> io::Result<()> {
+    for stream in listener.incoming() {
+        // A misbehaving client must not take the dashboard down
+        let _ = handle_connection(stream?, &system);
+    }
+    Ok(())
+}
+
+fn handle_connection(stream: TcpStream, system: &RwLock<CustodySystem>) -> io::Result<()> {
+    let mut reader = BufReader::new(&stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // Drain headers; the dashboard does not use them
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+
+    let (status, content_type, body) = route(&request_line, system);
+    let mut stream = &stream;
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
+
+fn route(
+    request_line: &str,
+    system: &RwLock<CustodySystem>,
+) -> (&'static str, &'static str, String) {
+    let mut parts = request_line.split_whitespace();
+    match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/")) => {
+            let system = system
+                .read()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            (
+                "200 OK",
+                "text/html; charset=utf-8",
+                render_dashboard(&system),
+            )
+        }
+        (Some("GET"), Some(_)) => ("404 Not Found", "text/plain", "Not found".to_string()),
+        _ => (
+            "405 Method Not Allowed",
+            "text/plain",
+            "Method not allowed".to_string(),
+        ),
+    }
+}
+
+fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    fn sample() -> CustodySystem {
+        let mut system = CustodySystem::new();
+        system
+            .create_wallet("hot1".to_string(), "<script>".to_string(), WalletType::Hot)
+            .unwrap();
+        system
+            .create_wallet("cold1".to_string(), "bc1c".to_string(), WalletType::Cold)
+            .unwrap();
+        system.deposit("hot1", amount!(1.0)).unwrap();
+        system.deposit("cold1", amount!(3.0)).unwrap();
+        system.make_joint_wallet("hot1", &["a", "b"], 2).unwrap();
+        system
+            .request_joint_withdrawal("hot1", "a", amount!(0.5))
+            .unwrap();
+        system
+    }
+
+    #[test]
+    fn test_render_dashboard_sections() {
+        let html = render_dashboard(&sample());
+        assert!(html.contains("All integrity checks passed."));
+        assert!(html.contains("25.0%"));
+        assert!(html.contains("Withdrawal of 0.50000000 BTC"));
+        assert!(html.contains("3.00000000 BTC"));
+        assert!(html.contains("&lt;script&gt;"));
+        assert!(!html.contains("<script>"));
+    }
+
+    #[test]
+    fn test_serve_dashboard() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let system = Arc::new(RwLock::new(sample()));
+        std::thread::spawn(move || serve_dashboard(listener, system));
+
+        let fetch = |path: &str| {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        };
+        let page = fetch("/");
+        assert!(page.starts_with("HTTP/1.1 200 OK"));
+        assert!(page.contains("<h2>Balances</h2>"));
+        assert!(fetch("/missing").starts_with("HTTP/1.1 404"));
+    }
+}
@@ -0,0 +1,465 @@
+//! On-chain deposits observed by a chain watcher.
+//!
+//! A node or indexer reports each incoming payment with
+//! [`CustodySystem::ingest_external_deposit`]. The payment is matched to a
+//! wallet by its receive address: the wallet's own address, an address
+//! registered with [`CustodySystem::watch_address`], or, for HD wallets,
+//! any deposit address handed out so far. It is held as pending until it
+//! has enough confirmations, then credited once, with the source
+//! transaction hash as the transaction's reference.
+//!
+//! The threshold is set per asset with
+//! [`CustodySystem::set_confirmation_policy`], falling back to
+//! [`CustodySystem::set_required_confirmations`]. A chain watcher reports
+//! new blocks with [`CustodySystem::update_confirmations`]; each credited
+//! deposit emits [`CustodyEvent::DepositFinalized`].
+
+use crate::time::Timestamp;
+use crate::{Amount, Asset, CustodyError, CustodyEvent, CustodySystem, OperationKind};
+
+/// Confirmations a deposit needs before it is credited, unless configured
+pub const DEFAULT_REQUIRED_CONFIRMATIONS: u32 = 6;
+
+/// Lifecycle state of an external deposit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DepositStatus {
+    /// Seen on chain, not yet credited
+    Pending,
+    /// Credited to the wallet
+    Credited,
+}
+
+/// A payment to a custody address seen on chain
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExternalDeposit {
+    pub tx_hash: String,
+    pub address: String,
+    pub wallet_id: String,
+    pub asset: Asset,
+    pub amount: Amount,
+    pub confirmations: u32,
+    pub first_seen: Timestamp,
+    pub status: DepositStatus,
+}
+
+impl CustodySystem {
+    /// Sets the confirmations a deposit needs before it is credited, for
+    /// assets without a policy of their own
+    pub fn set_required_confirmations(&mut self, confirmations: u32) {
+        self.required_confirmations = confirmations;
+    }
+
+    /// Gets the confirmations a deposit needs before it is credited, for
+    /// assets without a policy of their own
+    pub fn required_confirmations(&self) -> u32 {
+        self.required_confirmations
+    }
+
+    /// Sets the confirmations deposits in `asset` need before they are
+    /// credited, e.g. 3 for BTC and 12 for ETH
+    ///
+    /// Pending deposits are held to the new policy from their next
+    /// confirmation update.
+    pub fn set_confirmation_policy(&mut self, asset: Asset, confirmations: u32) {
+        self.confirmation_policies.insert(asset, confirmations);
+    }
+
+    /// Removes `asset`'s policy; its deposits need the default
+    /// confirmations again
+    pub fn clear_confirmation_policy(&mut self, asset: &Asset) {
+        self.confirmation_policies.remove(asset);
+    }
+
+    /// Gets the confirmations deposits in `asset` need before they are
+    /// credited
+    pub fn confirmations_required(&self, asset: &Asset) -> u32 {
+        self.confirmation_policies
+            .get(asset)
+            .copied()
+            .unwrap_or(self.required_confirmations)
+    }
+
+    /// Routes deposits to `address` into `wallet_id`, in addition to the
+    /// wallet's own address
+    pub fn watch_address(&mut self, wallet_id: &str, address: &str) -> Result<(), CustodyError> {
+        if !self.wallets.contains_key(wallet_id) {
+            return Err(CustodyError::WalletNotFound(wallet_id.to_string()));
+        }
+        self.watched_addresses
+            .insert(address.to_string(), wallet_id.to_string());
+        Ok(())
+    }
+
+    /// Returns the wallet that receives deposits at `address`
+    pub fn wallet_for_address(&self, address: &str) -> Option<&str> {
+        if let Some(wallet_id) = self.watched_addresses.get(address) {
+            return Some(wallet_id);
+        }
+        self.wallets
+            .values()
+            .find(|wallet| {
+                wallet.addresses().any(|candidate| candidate == address)
+                    || wallet.hd.as_ref().is_some_and(|hd| hd.has_issued(address))
+            })
+            .map(|wallet| wallet.id.as_str())
+    }
+
+    /// Records a payment to a custody address seen on chain
+    ///
+    /// The deposit is credited to the wallet's primary asset as soon as it
+    /// has the required confirmations; until then it stays pending.
+    /// Reporting a pending deposit again updates its confirmations.
+    ///
+    /// # Arguments
+    /// * `address` - Receive address the payment was sent to
+    /// * `amount` - Amount received
+    /// * `tx_hash` - Hash of the on-chain transaction
+    /// * `confirmations` - Confirmations the transaction has so far
+    ///
+    /// # Returns
+    /// The deposit's status after ingestion
+    ///
+    /// # Example
+    /// ```
+    /// use securevault::{amount, CustodySystem, DepositStatus, WalletType};
+    /// let mut system = CustodySystem::new();
+    /// system.create_wallet("w".to_string(), "bc1qcustody".to_string(), WalletType::Hot).unwrap();
+    /// system.set_required_confirmations(3);
+    ///
+    /// let status = system.ingest_external_deposit("bc1qcustody", amount!(0.5), "f00d", 1).unwrap();
+    /// assert_eq!(status, DepositStatus::Pending);
+    /// assert!(system.get_wallet("w").unwrap().balance.is_zero());
+    ///
+    /// let status = system.ingest_external_deposit("bc1qcustody", amount!(0.5), "f00d", 3).unwrap();
+    /// assert_eq!(status, DepositStatus::Credited);
+    /// assert_eq!(system.get_wallet("w").unwrap().balance, amount!(0.5));
+    /// ```
+    pub fn ingest_external_deposit(
+        &mut self,
+        address: &str,
+        amount: Amount,
+        tx_hash: &str,
+        confirmations: u32,
+    ) -> Result<DepositStatus, CustodyError> {
+        let asset = self
+            .wallet_for_address(address)
+            .map(|wallet_id| self.wallets[wallet_id].asset.clone())
+            .ok_or_else(|| CustodyError::UnknownDepositAddress(address.to_string()))?;
+        self.ingest_external_asset_deposit(address, &asset, amount, tx_hash, confirmations)
+    }
+
+    /// Records a payment of `asset` to a custody address seen on chain,
+    /// e.g. an ERC-20 token sent to an ETH wallet
+    ///
+    /// Works like [`ingest_external_deposit`](Self::ingest_external_deposit),
+    /// crediting `asset` instead of the wallet's primary asset.
+    pub fn ingest_external_asset_deposit(
+        &mut self,
+        address: &str,
+        asset: &Asset,
+        amount: Amount,
+        tx_hash: &str,
+        confirmations: u32,
+    ) -> Result<DepositStatus, CustodyError> {
+        let wallet_id = self
+            .wallet_for_address(address)
+            .ok_or_else(|| CustodyError::UnknownDepositAddress(address.to_string()))?
+            .to_string();
+        let key = (tx_hash.to_string(), address.to_string());
+        match self.external_deposits.get(&key) {
+            Some(deposit) if deposit.status == DepositStatus::Credited => {
+                return Err(CustodyError::DuplicateReference(tx_hash.to_string()));
+            }
+            Some(deposit) if deposit.amount != amount => {
+                return Err(CustodyError::DepositMismatch(tx_hash.to_string()));
+            }
+            Some(_) => {}
+            None => {
+                if !amount.is_positive() {
+                    return Err(CustodyError::NonPositiveAmount(OperationKind::Deposit));
+                }
+                let deposit = ExternalDeposit {
+                    tx_hash: tx_hash.to_string(),
+                    address: address.to_string(),
+                    asset: asset.clone(),
+                    wallet_id,
+                    amount,
+                    confirmations,
+                    first_seen: self.current_timestamp(),
+                    status: DepositStatus::Pending,
+                };
+                self.external_deposits.insert(key.clone(), deposit);
+            }
+        }
+        self.confirm_external_deposit(&key, confirmations)
+    }
+
+    /// Sets the confirmations of every deposit made by `tx_hash`,
+    /// crediting those that reach their asset's threshold
+    ///
+    /// Confirmations may go down after a chain reorganisation; deposits
+    /// that were already credited are not reversed.
+    ///
+    /// # Returns
+    /// [`DepositStatus::Credited`] once every deposit of the transaction is
+    /// credited, [`DepositStatus::Pending`] otherwise
+    ///
+    /// # Example
+    /// ```
+    /// use securevault::{amount, Asset, CustodySystem, CustodyEvent, DepositStatus, WalletType};
+    /// let mut system = CustodySystem::new();
+    /// system
+    ///     .create_wallet_with_asset("eth".to_string(), "0xabc".to_string(), WalletType::Hot, Asset::Eth)
+    ///     .unwrap();
+    /// system.set_confirmation_policy(Asset::Eth, 12);
+    ///
+    /// system.ingest_external_deposit("0xabc", amount!(2), "0xfeed", 1).unwrap();
+    /// assert_eq!(system.update_confirmations("0xfeed", 11).unwrap(), DepositStatus::Pending);
+    /// assert_eq!(system.update_confirmations("0xfeed", 12).unwrap(), DepositStatus::Credited);
+    /// assert_eq!(system.get_wallet("eth").unwrap().balance, amount!(2));
+    /// ```
+    pub fn update_confirmations(
+        &mut self,
+        tx_hash: &str,
+        confirmations: u32,
+    ) -> Result<DepositStatus, CustodyError> {
+        let keys: Vec<_> = self
+            .external_deposits
+            .keys()
+            .filter(|(hash, _)| hash == tx_hash)
+            .cloned()
+            .collect();
+        if keys.is_empty() {
+            return Err(CustodyError::DepositNotFound(tx_hash.to_string()));
+        }
+        let mut status = DepositStatus::Credited;
+        for key in &keys {
+            if self.confirm_external_deposit(key, confirmations)? == DepositStatus::Pending {
+                status = DepositStatus::Pending;
+            }
+        }
+        Ok(status)
+    }
+
+    /// Forgets the pending deposits made by `tx_hash`, e.g. after a chain
+    /// reorganisation dropped the transaction
+    ///
+    /// Deposits already credited are kept; reversing those is a ledger
+    /// correction.
+    ///
+    /// # Returns
+    /// The deposits discarded
+    pub fn discard_pending_deposits(&mut self, tx_hash: &str) -> Vec<ExternalDeposit> {
+        let keys: Vec<_> = self
+            .external_deposits
+            .iter()
+            .filter(|((hash, _), deposit)| {
+                hash == tx_hash && deposit.status == DepositStatus::Pending
+            })
+            .map(|(key, _)| key.clone())
+            .collect();
+        keys.iter()
+            .filter_map(|key| self.external_deposits.remove(key))
+            .collect()
+    }
+
+    /// Gets the deposits `tx_hash` made to custody addresses
+    pub fn external_deposits(&self, tx_hash: &str) -> Vec<&ExternalDeposit> {
+        self.external_deposits
+            .values()
+            .filter(|deposit| deposit.tx_hash == tx_hash)
+            .collect()
+    }
+
+    /// Lists deposits to `wallet_id` awaiting confirmations
+    pub fn pending_deposits(&self, wallet_id: &str) -> Vec<&ExternalDeposit> {
+        self.external_deposits
+            .values()
+            .filter(|d| d.wallet_id == wallet_id && d.status == DepositStatus::Pending)
+            .collect()
+    }
+
+    /// Updates a deposit's confirmations, crediting it once they reach
+    /// its asset's threshold
+    fn confirm_external_deposit(
+        &mut self,
+        key: &(String, String),
+        confirmations: u32,
+    ) -> Result<DepositStatus, CustodyError> {
+        let deposit = self
+            .external_deposits
+            .get_mut(key)
+            .expect("deposit is recorded");
+        deposit.confirmations = confirmations;
+        let deposit = deposit.clone();
+        if deposit.status == DepositStatus::Credited
+            || deposit.confirmations < self.confirmations_required(&deposit.asset)
+        {
+            return Ok(deposit.status);
+        }
+        self.deposit_asset(&deposit.wallet_id, &deposit.asset, deposit.amount)?;
+        self.update_last_transaction(|tx| tx.reference = Some(deposit.tx_hash.clone()))?;
+        if let Some(deposit) = self.external_deposits.get_mut(key) {
+            deposit.status = DepositStatus::Credited;
+        }
+        self.emit(CustodyEvent::DepositFinalized {
+            wallet_id: deposit.wallet_id,
+            tx_hash: deposit.tx_hash,
+            amount: deposit.amount,
+            asset: deposit.asset,
+            confirmations,
+        });
+        Ok(DepositStatus::Credited)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::WalletType;
+
+    fn system() -> CustodySystem {
+        let mut system = CustodySystem::new();
+        system
+            .create_wallet("w".to_string(), "bc1qmain".to_string(), WalletType::Hot)
+            .unwrap();
+        system.set_required_confirmations(2);
+        system
+    }
+
+    #[test]
+    fn test_deposit_credited_once_confirmed() {
+        let mut system = system();
+        assert_eq!(
+            system.ingest_external_deposit("bc1qmain", amount!(1), "aa", 0),
+            Ok(DepositStatus::Pending)
+        );
+        assert_eq!(system.pending_deposits("w").len(), 1);
+        assert!(system.get_all_transactions().is_empty());
+
+        assert_eq!(
+            system.ingest_external_deposit("bc1qmain", amount!(1), "aa", 2),
+            Ok(DepositStatus::Credited)
+        );
+        assert_eq!(system.get_wallet("w").unwrap().balance, amount!(1));
+        let tx = &system.get_all_transactions()[0];
+        assert_eq!(tx.reference.as_deref(), Some("aa"));
+        assert!(system.pending_deposits("w").is_empty());
+
+        assert_eq!(
+            system.ingest_external_deposit("bc1qmain", amount!(1), "aa", 3),
+            Err(CustodyError::DuplicateReference("aa".to_string()))
+        );
+        assert_eq!(system.get_wallet("w").unwrap().balance, amount!(1));
+    }
+
+    #[test]
+    fn test_deposits_routed_by_watched_address() {
+        let mut system = system();
+        system.watch_address("w", "bc1qextra").unwrap();
+        assert_eq!(system.wallet_for_address("bc1qextra"), Some("w"));
+        system
+            .ingest_external_deposit("bc1qextra", amount!(2), "bb", 6)
+            .unwrap();
+        assert_eq!(system.get_wallet("w").unwrap().balance, amount!(2));
+        assert_eq!(
+            system.ingest_external_deposit("bc1qextra", amount!(3), "dd", 0),
+            Ok(DepositStatus::Pending)
+        );
+        assert_eq!(
+            system.ingest_external_deposit("bc1qextra", amount!(4), "dd", 1),
+            Err(CustodyError::DepositMismatch("dd".to_string()))
+        );
+
+        assert_eq!(
+            system.ingest_external_deposit("bc1qother", amount!(2), "cc", 6),
+            Err(CustodyError::UnknownDepositAddress("bc1qother".to_string()))
+        );
+        assert_eq!(
+            system.watch_address("missing", "bc1qx"),
+            Err(CustodyError::WalletNotFound("missing".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_policy_per_asset_and_finalization_events() {
+        use crate::EventListener;
+        use std::sync::{Arc, Mutex};
+
+        #[derive(Default)]
+        struct Recorder(Mutex<Vec<CustodyEvent>>);
+
+        impl EventListener for Recorder {
+            fn on_event(&self, event: &CustodyEvent) {
+                self.0.lock().unwrap().push(event.clone());
+            }
+        }
+
+        let mut system = system();
+        let recorder = Arc::new(Recorder::default());
+        system.subscribe(recorder.clone());
+        system
+            .create_wallet_with_asset(
+                "e".to_string(),
+                "0xe".to_string(),
+                WalletType::Hot,
+                Asset::Eth,
+            )
+            .unwrap();
+        system.watch_address("e", "0xe2").unwrap();
+        system.set_confirmation_policy(Asset::Eth, 12);
+        assert_eq!(system.confirmations_required(&Asset::Eth), 12);
+        assert_eq!(system.confirmations_required(&Asset::Btc), 2);
+
+        // One transaction paying two custody addresses
+        system
+            .ingest_external_deposit("0xe", amount!(1), "tx", 3)
+            .unwrap();
+        system
+            .ingest_external_deposit("0xe2", amount!(2), "tx", 3)
+            .unwrap();
+        assert_eq!(
+            system.update_confirmations("tx", 11),
+            Ok(DepositStatus::Pending)
+        );
+        // A reorg lowers the count again
+        assert_eq!(
+            system.update_confirmations("tx", 4),
+            Ok(DepositStatus::Pending)
+        );
+        assert_eq!(system.external_deposits("tx")[0].confirmations, 4);
+        assert!(recorder.0.lock().unwrap().is_empty());
+
+        assert_eq!(
+            system.update_confirmations("tx", 12),
+            Ok(DepositStatus::Credited)
+        );
+        assert_eq!(system.get_wallet("e").unwrap().balance, amount!(3));
+        let finalized: Vec<_> = recorder
+            .0
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|event| match event {
+                CustodyEvent::DepositFinalized {
+                    amount,
+                    confirmations,
+                    ..
+                } => Some((*amount, *confirmations)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(finalized, [(amount!(1), 12), (amount!(2), 12)]);
+
+        // Later blocks are recorded without crediting again
+        assert_eq!(
+            system.update_confirmations("tx", 20),
+            Ok(DepositStatus::Credited)
+        );
+        assert_eq!(system.get_wallet("e").unwrap().balance, amount!(3));
+        assert_eq!(
+            system.update_confirmations("unknown", 1),
+            Err(CustodyError::DepositNotFound("unknown".to_string()))
+        );
+    }
+}
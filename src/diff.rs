@@ -0,0 +1,153 @@
+//! Comparison of two custody states.
+//!
+//! [`CustodySystem::diff`] reports what differs between two systems, e.g. a
+//! primary and a replica, or a live system and a restored backup. `self` is
+//! the left-hand side and `other` the right-hand side of the comparison.
+
+use crate::{Amount, CustodySystem, Transaction, Wallet};
+
+/// A wallet present on both sides whose state differs
+#[derive(Debug, Clone, PartialEq)]
+pub struct WalletDiff {
+    pub id: String,
+    pub left: Wallet,
+    pub right: Wallet,
+}
+
+impl WalletDiff {
+    /// Right-hand balance minus left-hand balance
+    pub fn balance_delta(&self) -> Amount {
+        self.right.balance - self.left.balance
+    }
+}
+
+/// Differences between two custody states
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StateDiff {
+    /// Ids of wallets that exist only on the left-hand side
+    pub wallets_only_in_left: Vec<String>,
+    /// Ids of wallets that exist only on the right-hand side
+    pub wallets_only_in_right: Vec<String>,
+    /// Wallets present on both sides with different state
+    pub changed_wallets: Vec<WalletDiff>,
+    /// Length of the transaction history shared by both sides
+    pub common_transactions: usize,
+    /// Transactions recorded after the shared history on the left-hand side
+    pub transactions_only_in_left: Vec<Transaction>,
+    /// Transactions recorded after the shared history on the right-hand side
+    pub transactions_only_in_right: Vec<Transaction>,
+}
+
+impl StateDiff {
+    /// Returns true if both states are identical
+    pub fn is_empty(&self) -> bool {
+        self.wallets_only_in_left.is_empty()
+            && self.wallets_only_in_right.is_empty()
+            && self.changed_wallets.is_empty()
+            && self.transactions_only_in_left.is_empty()
+            && self.transactions_only_in_right.is_empty()
+    }
+}
+
+impl CustodySystem {
+    /// Compares this system with `other`
+    ///
+    /// Wallets are matched by id. Transaction logs are append-only, so they
+    /// are compared by their longest common prefix: everything after the
+    /// first divergence is reported as present on one side only.
+    pub fn diff(&self, other: &CustodySystem) -> StateDiff {
+        let mut diff = StateDiff::default();
+
+        for (id, left) in self.wallets.iter() {
+            match other.wallets.get(id) {
+                None => diff.wallets_only_in_left.push(id.clone()),
+                Some(right) if right != left => diff.changed_wallets.push(WalletDiff {
+                    id: id.clone(),
+                    left: left.clone(),
+                    right: right.clone(),
+                }),
+                Some(_) => {}
+            }
+        }
+        for id in other.wallets.keys() {
+            if !self.wallets.contains_key(id) {
+                diff.wallets_only_in_right.push(id.clone());
+            }
+        }
+        diff.wallets_only_in_left.sort();
+        diff.wallets_only_in_right.sort();
+        diff.changed_wallets.sort_by(|a, b| a.id.cmp(&b.id));
+
+        let common = self
+            .transactions
+            .iter()
+            .zip(other.transactions.iter())
+            .take_while(|(left, right)| left == right)
+            .count();
+        diff.common_transactions = common;
+        diff.transactions_only_in_left = self.transactions.iter().skip(common).cloned().collect();
+        diff.transactions_only_in_right = other.transactions.iter().skip(common).cloned().collect();
+
+        diff
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::WalletType;
+
+    fn system() -> CustodySystem {
+        let mut system = CustodySystem::new();
+        system
+            .create_wallet("a".to_string(), "0x1".to_string(), WalletType::Hot)
+            .unwrap();
+        system
+            .create_wallet("b".to_string(), "0x2".to_string(), WalletType::Cold)
+            .unwrap();
+        system.deposit("a", amount!(10.0)).unwrap();
+        system
+    }
+
+    #[test]
+    fn test_identical_systems_have_empty_diff() {
+        let system = system();
+        let diff = system.diff(&system.fork());
+        assert!(diff.is_empty());
+        assert_eq!(diff.common_transactions, 1);
+    }
+
+    #[test]
+    fn test_diff_reports_wallets_and_balances() {
+        let left = system();
+        let mut right = left.fork();
+        right.transfer("a", "b", amount!(4.0)).unwrap();
+        right
+            .create_wallet("c".to_string(), "0x3".to_string(), WalletType::Hot)
+            .unwrap();
+
+        let diff = left.diff(&right);
+        assert!(diff.wallets_only_in_left.is_empty());
+        assert_eq!(diff.wallets_only_in_right, vec!["c".to_string()]);
+        assert_eq!(diff.changed_wallets.len(), 2);
+        assert_eq!(diff.changed_wallets[0].id, "a");
+        assert_eq!(diff.changed_wallets[0].balance_delta(), amount!(-4.0));
+        assert_eq!(diff.changed_wallets[1].balance_delta(), amount!(4.0));
+    }
+
+    #[test]
+    fn test_diff_reports_divergent_transactions() {
+        let base = system();
+        let mut left = base.fork();
+        let mut right = base.fork();
+        left.deposit("b", amount!(1.0)).unwrap();
+        right.withdraw("a", amount!(2.0)).unwrap();
+        right.deposit("a", amount!(3.0)).unwrap();
+
+        let diff = left.diff(&right);
+        assert_eq!(diff.common_transactions, 1);
+        assert_eq!(diff.transactions_only_in_left.len(), 1);
+        assert_eq!(diff.transactions_only_in_right.len(), 2);
+        assert_eq!(diff.transactions_only_in_left[0].wallet_id, "b");
+    }
+}
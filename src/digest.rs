@@ -0,0 +1,307 @@
+//! Per-transaction content digests.
+//!
+//! Every recorded transaction is sealed with a SHA-256 digest of its
+//! canonical encoding. A single record handed to an auditor or counterparty
+//! can be checked on its own by recomputing [`Transaction::digest`] and
+//! comparing it with [`Transaction::sealed_digest`], without shipping the
+//! rest of the log.
+//!
+//! # Canonical encoding
+//!
+//! The encoding starts with the domain tag `securevault/tx/v4` followed by
+//! each field in declaration order, every field as a big-endian `u64`
+//! length and its bytes:
+//!
+//! * integers (`id`, timestamp in Unix seconds) as big-endian `u64`;
+//! * the UUID as its 16 raw bytes;
+//! * amounts as the big-endian `i128` count of 10⁻¹⁸ units;
+//! * floating-point rates and fiat values as the big-endian IEEE-754 bit
+//!   pattern;
+//! * strings as UTF-8;
+//! * enums as a lowercase tag, with their payload as further fields;
+//! * tag sets and metadata maps as their entry count, then each entry in
+//!   sorted order (a metadata entry as its key, then its value);
+//! * absent optional values as a zero-length field.
+//!
+//! The sealed digest and the audit chain hashes are not part of the
+//! encoding.
+
+use crate::{
+    Amount, Asset, Category, CustodyError, CustodySystem, Transaction, TransactionType, Uuid,
+};
+use sha2::{Digest, Sha256};
+
+const DOMAIN: &[u8] = b"securevault/tx/v4";
+
+/// Length-prefixed field writer for the canonical encoding
+pub(crate) struct Encoder(pub(crate) Vec<u8>);
+
+impl Encoder {
+    pub(crate) fn field(&mut self, bytes: &[u8]) -> &mut Self {
+        self.0
+            .extend_from_slice(&(bytes.len() as u64).to_be_bytes());
+        self.0.extend_from_slice(bytes);
+        self
+    }
+
+    pub(crate) fn str(&mut self, value: &str) -> &mut Self {
+        self.field(value.as_bytes())
+    }
+
+    pub(crate) fn u64(&mut self, value: u64) -> &mut Self {
+        self.field(&value.to_be_bytes())
+    }
+
+    pub(crate) fn amount(&mut self, value: Amount) -> &mut Self {
+        let units = value
+            .to_minor_units(Amount::DECIMALS)
+            .expect("every amount is a whole number of its smallest unit");
+        self.field(&units.to_be_bytes())
+    }
+
+    pub(crate) fn f64(&mut self, value: f64) -> &mut Self {
+        self.field(&value.to_bits().to_be_bytes())
+    }
+
+    pub(crate) fn asset(&mut self, asset: &Asset) -> &mut Self {
+        match asset {
+            Asset::Btc => self.str("btc"),
+            Asset::Eth => self.str("eth"),
+            Asset::Erc20 {
+                symbol,
+                contract,
+                decimals,
+            } => self
+                .str("erc20")
+                .str(symbol)
+                .str(contract)
+                .u64(u64::from(*decimals)),
+            Asset::Fiat(code) => self.str("fiat").str(code),
+            Asset::Custom { symbol, decimals } => {
+                self.str("custom").str(symbol).u64(u64::from(*decimals))
+            }
+        }
+    }
+
+    pub(crate) fn absent(&mut self) -> &mut Self {
+        self.field(&[])
+    }
+}
+
+/// Returns the lowercase tag a transaction type is encoded as
+pub(crate) fn type_tag(kind: &TransactionType) -> &'static str {
+    match kind {
+        TransactionType::Deposit => "deposit",
+        TransactionType::Withdrawal => "withdrawal",
+        TransactionType::ConversionOut => "conversion_out",
+        TransactionType::ConversionIn => "conversion_in",
+        TransactionType::Transfer => "transfer",
+        TransactionType::Reward => "reward",
+    }
+}
+
+/// Returns the SHA-256 of `bytes` as lowercase hex
+pub(crate) fn sha256_hex(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+impl Transaction {
+    /// Returns the canonical byte encoding this transaction's digest is
+    /// computed over
+    pub fn canonical_bytes(&self) -> Vec<u8> {
+        let mut out = Encoder(Vec::new());
+        out.field(DOMAIN)
+            .u64(self.id)
+            .field(self.uuid.as_bytes())
+            .str(&self.wallet_id)
+            .str(type_tag(&self.transaction_type))
+            .amount(self.amount)
+            .u64(self.timestamp.as_unix())
+            .asset(&self.asset);
+        match self.rate {
+            Some(rate) => out.f64(rate),
+            None => out.absent(),
+        };
+        match &self.counterparty {
+            Some(counterparty) => out.str(counterparty),
+            None => out.absent(),
+        };
+        match &self.category {
+            Some(Category::Operations) => out.str("operations"),
+            Some(Category::Payroll) => out.str("payroll"),
+            Some(Category::Rebalancing) => out.str("rebalancing"),
+            Some(Category::ClientWithdrawal) => out.str("client_withdrawal"),
+            Some(Category::Other(name)) => out.str("other").str(name),
+            None => out.absent(),
+        };
+        match &self.fiat_value {
+            Some(value) => out.asset(&value.currency).f64(value.amount),
+            None => out.absent(),
+        };
+        match &self.reference {
+            Some(reference) => out.str(reference),
+            None => out.absent(),
+        };
+        match &self.idempotency_key {
+            Some(key) => out.str(key),
+            None => out.absent(),
+        };
+        match &self.memo {
+            Some(memo) => out.str(memo),
+            None => out.absent(),
+        };
+        out.u64(self.tags.len() as u64);
+        for tag in &self.tags {
+            out.str(tag);
+        }
+        out.u64(self.metadata.len() as u64);
+        for (key, value) in &self.metadata {
+            out.str(key).str(value);
+        }
+        out.0
+    }
+
+    /// Computes the SHA-256 digest of the canonical encoding, as lowercase
+    /// hex
+    pub fn digest(&self) -> String {
+        sha256_hex(&self.canonical_bytes())
+    }
+
+    /// Returns true if the sealed digest matches the current contents
+    pub fn verify_digest(&self) -> bool {
+        self.sealed_digest == self.digest()
+    }
+
+    /// Recomputes and stores the digest
+    pub(crate) fn seal(&mut self) {
+        self.sealed_digest = self.digest();
+    }
+}
+
+impl CustodySystem {
+    /// Gets a transaction by id
+    pub fn get_transaction(&self, id: u64) -> Option<&Transaction> {
+        let position = self.index.position(id)?;
+        self.transactions.get(position)
+    }
+
+    /// Gets a transaction by its globally unique id
+    pub fn get_transaction_by_uuid(&self, uuid: &Uuid) -> Option<&Transaction> {
+        self.transactions.iter().find(|tx| tx.uuid == *uuid)
+    }
+
+    /// Recomputes the digest of a transaction and compares it with the one
+    /// sealed when it was recorded
+    ///
+    /// # Example
+    /// ```
+    /// use securevault::{amount, CustodySystem, WalletType};
+    /// let mut system = CustodySystem::new();
+    /// system.create_wallet("w".to_string(), "0x1".to_string(), WalletType::Hot).unwrap();
+    /// system.deposit("w", amount!(5.0)).unwrap();
+    ///
+    /// let id = system.get_all_transactions()[0].id;
+    /// assert!(system.verify_transaction(id).is_ok());
+    /// ```
+    pub fn verify_transaction(&self, id: u64) -> Result<(), CustodyError> {
+        let tx = self
+            .get_transaction(id)
+            .ok_or(CustodyError::TransactionIdNotFound(id))?;
+        if tx.verify_digest() {
+            Ok(())
+        } else {
+            Err(CustodyError::DigestMismatch(id))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::WalletType;
+
+    fn system() -> CustodySystem {
+        let mut system = CustodySystem::new();
+        system
+            .create_wallet("a".to_string(), "0x1".to_string(), WalletType::Hot)
+            .unwrap();
+        system.deposit("a", amount!(10.0)).unwrap();
+        system.withdraw("a", amount!(4.0)).unwrap();
+        system
+    }
+
+    #[test]
+    fn test_recorded_transactions_are_sealed() {
+        let system = system();
+        let ids: Vec<u64> = system
+            .get_all_transactions()
+            .iter()
+            .map(|tx| tx.id)
+            .collect();
+        assert_eq!(ids, vec![1, 2]);
+        for id in ids {
+            assert!(system.verify_transaction(id).is_ok());
+        }
+        let tx = system.get_transaction(1).unwrap();
+        assert_eq!(tx.sealed_digest.len(), 64);
+        assert_ne!(
+            tx.sealed_digest,
+            system.get_transaction(2).unwrap().sealed_digest
+        );
+    }
+
+    #[test]
+    fn test_verify_detects_tampering() {
+        let mut system = system();
+        system.transactions.get_mut(0).unwrap().amount = amount!(100.0);
+        assert_eq!(
+            system.verify_transaction(1),
+            Err(CustodyError::DigestMismatch(1))
+        );
+        assert_eq!(
+            system.verify_transaction(9),
+            Err(CustodyError::TransactionIdNotFound(9))
+        );
+    }
+
+    #[test]
+    fn test_metadata_updates_reseal() {
+        let mut system = system();
+        system
+            .categorize_transaction(0, Some(Category::Other("operations".to_string())))
+            .unwrap();
+        assert!(system.verify_transaction(1).is_ok());
+
+        // Custom categories cannot collide with the built-in ones
+        let mut builtin = system.get_transaction(1).unwrap().clone();
+        builtin.category = Some(Category::Operations);
+        assert_ne!(
+            builtin.digest(),
+            system.get_transaction(1).unwrap().digest()
+        );
+    }
+
+    #[test]
+    fn test_transactions_have_unique_uuids() {
+        let system = system();
+        let first = system.get_transaction(1).unwrap();
+        let second = system.get_transaction(2).unwrap();
+        assert!(!first.uuid.is_nil());
+        assert_ne!(first.uuid, second.uuid);
+        assert_eq!(
+            system.get_transaction_by_uuid(&second.uuid).map(|tx| tx.id),
+            Some(2)
+        );
+        assert!(system.get_transaction_by_uuid(&Uuid::nil()).is_none());
+    }
+
+    #[test]
+    fn test_standalone_record_verifies() {
+        let system = system();
+        let exported = system.get_transaction(2).unwrap().clone();
+        assert!(exported.verify_digest());
+    }
+}
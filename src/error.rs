@@ -0,0 +1,765 @@
+//! Error type returned by custody operations.
+//!
+//! Errors are structured so they can be rendered in the operator's or
+//! customer's language via [`CustodyError::localized`]. The `Display`
+//! implementation renders English.
+
+use crate::i18n::{self, Locale};
+use crate::time::Timestamp;
+use crate::{Amount, HoldId, WalletStatus};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// The kind of operation an error refers to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OperationKind {
+    Deposit,
+    Withdrawal,
+    Transfer,
+    Conversion,
+    Hold,
+}
+
+impl OperationKind {
+    fn key(&self) -> &'static str {
+        match self {
+            OperationKind::Deposit => "op.deposit",
+            OperationKind::Withdrawal => "op.withdrawal",
+            OperationKind::Transfer => "op.transfer",
+            OperationKind::Conversion => "op.conversion",
+            OperationKind::Hold => "op.hold",
+        }
+    }
+}
+
+/// The withdrawal policy rule an operation breaks
+#[derive(Debug, Clone, PartialEq)]
+pub enum PolicyViolation {
+    /// The amount is above the per-transaction maximum
+    PerTransactionLimit { limit: Amount, requested: Amount },
+    /// The amount would take the last 24 hours' withdrawals over the limit
+    DailyLimit {
+        limit: Amount,
+        used: Amount,
+        requested: Amount,
+    },
+    /// The amount would take the last 7 days' withdrawals over the limit
+    WeeklyLimit {
+        limit: Amount,
+        used: Amount,
+        requested: Amount,
+    },
+    /// The previous withdrawal was too recent
+    Cooldown { remaining_secs: u64 },
+}
+
+/// Errors produced by the custody system
+#[derive(Debug, Clone, PartialEq)]
+pub enum CustodyError {
+    /// A wallet with this id is already registered
+    WalletAlreadyExists(String),
+    /// No wallet with this id exists
+    WalletNotFound(String),
+    /// The source wallet of a transfer does not exist
+    SourceWalletNotFound(String),
+    /// The destination wallet of a transfer does not exist
+    DestinationWalletNotFound(String),
+    /// Amounts must be strictly positive
+    NonPositiveAmount(OperationKind),
+    /// The wallet does not hold enough funds
+    InsufficientBalance {
+        available: Amount,
+        requested: Amount,
+    },
+    /// Source and destination of a transfer are the same wallet
+    SameWallet,
+    /// The two wallets hold different assets
+    AssetMismatch { expected: String, found: String },
+    /// No exchange rate is available for the asset pair
+    RateUnavailable { from: String, to: String },
+    /// The rate provider returned a zero, negative, or non-finite rate
+    InvalidRate(f64),
+    /// No wallet template is registered under this name
+    TemplateNotFound(String),
+    /// The destination address is malformed or not permitted
+    InvalidDestination(String),
+    /// Withdrawals from this joint wallet must be co-signed by its owners
+    CoSignatureRequired(String),
+    /// The wallet is not a joint wallet
+    NotJointWallet(String),
+    /// The wallet is already a joint wallet
+    AlreadyJoint(String),
+    /// The user is not an owner of the joint wallet
+    NotAnOwner { wallet_id: String, user: String },
+    /// A signing threshold is zero or exceeds the number of signers
+    InvalidThreshold { required: usize, available: usize },
+    /// No pending operation exists with this id
+    OperationNotFound(u64),
+    /// The operation has already been executed or rejected
+    OperationNotPending(u64),
+    /// No transaction exists at this position in the log
+    TransactionNotFound(usize),
+    /// The wallet is not denominated in a fiat currency
+    NotFiatWallet(String),
+    /// An external gateway rejected the request
+    GatewayError(String),
+    /// A transaction with this external reference was already booked
+    DuplicateReference(String),
+    /// The operation is only allowed from hot wallets
+    NotHotWallet(String),
+    /// The operation is only allowed on cold wallets
+    NotColdWallet(String),
+    /// Seed material was requested without the explicit confirmation
+    SeedExportNotConfirmed(String),
+    /// A backup could not be produced or read
+    BackupFailed(String),
+    /// A Uniform Resource (UR) part or message could not be decoded
+    InvalidUr(String),
+    /// No wallet id scheme is registered under this name
+    IdSchemeNotFound(String),
+    /// A derived wallet id is already used by a wallet for different inputs
+    IdCollision(String),
+    /// No extension of this kind is registered under this name
+    ExtensionNotFound { kind: String, name: String },
+    /// An extension factory rejected its settings
+    ExtensionFailed { name: String, reason: String },
+    /// A validation script blocked the operation
+    ScriptRejected { hook: String, reason: String },
+    /// A validation script failed to compile or run
+    ScriptError { hook: String, message: String },
+    /// No transaction exists with this id
+    TransactionIdNotFound(u64),
+    /// A transaction's contents no longer match its sealed digest
+    DigestMismatch(u64),
+    /// A fault injected by the chaos harness
+    InjectedFault(String),
+    /// No queued withdrawal exists with this id
+    QueuedWithdrawalNotFound(u64),
+    /// An amount calculation exceeded the representable range
+    AmountOverflow,
+    /// The storage backend could not read or write the ledger
+    StorageFailed(String),
+    /// Withdrawals from this cold wallet must go through approval
+    ApprovalRequired(String),
+    /// No withdrawal approvers are configured for this wallet
+    NoApprovers(String),
+    /// The user is not an approver of the wallet's withdrawals
+    NotAnApprover { wallet_id: String, user: String },
+    /// The requester of a withdrawal cannot approve it
+    SelfApproval(u64),
+    /// Idempotency key already used for an operation with different
+    /// parameters
+    IdempotencyConflict(String),
+    /// The audit chain does not check out at this transaction
+    AuditChainBroken(u64),
+    /// The wallet's withdrawal policy forbids the operation
+    PolicyViolation {
+        wallet_id: String,
+        violation: PolicyViolation,
+    },
+    /// The address is not valid on the wallet's chain
+    InvalidAddress {
+        chain: String,
+        address: String,
+        reason: String,
+    },
+    /// No address validator is registered for the chain
+    NoAddressValidator(String),
+    /// A snapshot document could not be parsed
+    InvalidSnapshot(String),
+    /// The snapshot was written in a format version this build cannot read
+    UnsupportedSnapshotVersion(u32),
+    /// Withdrawals from this multi-signature wallet must be signed
+    SignaturesRequired(String),
+    /// The wallet is not a multi-signature wallet
+    NotMultiSigWallet(String),
+    /// The user is not a signer of the multi-signature wallet
+    NotASigner { wallet_id: String, user: String },
+    /// The wallet is frozen
+    WalletFrozen(String),
+    /// The wallet is closing or closed
+    WalletClosed(String),
+    /// The wallet cannot move between these statuses
+    InvalidStatusTransition {
+        wallet_id: String,
+        from: WalletStatus,
+        to: WalletStatus,
+    },
+    /// No hold has this id
+    HoldNotFound(HoldId),
+    /// The hold was already released or captured
+    HoldNotActive(HoldId),
+    /// An extended public key could not be parsed or derived from
+    InvalidXpub(String),
+    /// The wallet has no extended public key to derive addresses from
+    NotAnHdWallet(String),
+    /// The key vault must be unlocked first
+    VaultLocked,
+    /// The passphrase does not unlock the key vault
+    InvalidPassphrase,
+    /// The key vault holds no key for this wallet
+    KeyNotFound(String),
+    /// The key vault could not be created, read or decrypted
+    KeyVaultFailed(String),
+    /// A signer could not produce a signature
+    SigningFailed(String),
+    /// A submitted signature or signed transaction was not accepted
+    SignatureRejected(String),
+    /// No prepared withdrawal awaits a signature under this id
+    UnsignedTxNotFound(u64),
+    /// An atomic batch was not applied because this entry failed
+    BatchAborted(usize),
+    /// A sampling interval must be at least one second
+    InvalidInterval,
+    /// The compliance screener blocked the operation
+    ScreeningBlocked(String),
+    /// The operation was held for manual compliance review under this id
+    FlaggedForReview(u64),
+    /// No wallet receives deposits at this address
+    UnknownDepositAddress(String),
+    /// A deposit was reported again with a different amount
+    DepositMismatch(String),
+    /// No external deposit was seen with this transaction hash
+    DepositNotFound(String),
+    /// No account has this id
+    AccountNotFound(String),
+    /// An account with this id already exists
+    AccountAlreadyExists(String),
+    /// No organization has this id
+    OrganizationNotFound(String),
+    /// An organization with this id already exists
+    OrganizationAlreadyExists(String),
+    /// No customer has this id
+    CustomerNotFound(String),
+    /// A customer with this id already exists
+    CustomerAlreadyExists(String),
+    /// The wallet must be empty for this operation
+    WalletNotEmpty(String),
+    /// The wallet does not hold funds on behalf of this customer
+    NotCustomerWallet { wallet_id: String, customer: String },
+    /// The customer claims on an omnibus wallet do not add up to its
+    /// balance
+    ClaimsMismatch {
+        wallet_id: String,
+        claims: Amount,
+        balance: Amount,
+    },
+    /// The transaction has already been reversed
+    AlreadyReversed(u64),
+    /// A configuration value is missing or out of range
+    InvalidConfig(String),
+    /// An event was applied out of sequence
+    EventOutOfOrder { expected: u64, found: u64 },
+    /// The event does not apply to the current state
+    EventRejected(u64),
+    /// The action needs a second operator's confirmation under the
+    /// two-person rule
+    DualControlRequired(String),
+    /// The proposed action was not confirmed in time
+    ActionExpired(u64),
+    /// The withdrawal destination is not on the wallet's whitelist
+    AddressNotWhitelisted { wallet_id: String, address: String },
+    /// The whitelisted address is still in its activation delay
+    WhitelistNotActive {
+        address: String,
+        active_from: Timestamp,
+    },
+    /// The withdrawal is at or above the travel-rule threshold and carries
+    /// no originator and beneficiary information
+    TravelRuleRequired { amount: Amount, threshold: Amount },
+    /// A required travel-rule field is blank
+    IncompleteTravelRule(String),
+    /// The API key token is unknown or malformed
+    InvalidApiKey,
+    /// No API key with this id was issued
+    ApiKeyNotFound(String),
+    /// The API key has passed its expiry time
+    ApiKeyExpired(String),
+    /// The API key has been revoked
+    ApiKeyRevoked(String),
+    /// The API key's scope does not cover the operation or wallet
+    ScopeDenied { key_id: String, operation: String },
+    /// The wallet or operator has used up its rate limit for now
+    RateLimited {
+        subject: String,
+        retry_after_secs: u64,
+    },
+    /// A signed operations file could not be read
+    InvalidBatchFile(String),
+    /// Tenant not found
+    TenantNotFound(String),
+    /// A tenant with this id already exists
+    TenantAlreadyExists(String),
+    /// The tenant already has as many wallets as its quota allows
+    TenantQuotaExceeded { tenant_id: String, quota: usize },
+    /// The change would fall in an accounting period that is already closed
+    PeriodClosed { closed_until: Timestamp },
+    /// A period can only be closed up to a moment that has already passed
+    PeriodNotEnded { until: Timestamp },
+    /// Alert not found
+    AlertNotFound(u64),
+    /// The alert has already been resolved
+    AlertAlreadyResolved(u64),
+    /// State commitment not found
+    CommitmentNotFound(u64),
+    /// A row of an import file could not be imported
+    InvalidImportRow(String),
+    /// An import file could not be read
+    InvalidImportFile(String),
+    /// The chain node could not be reached or rejected a request
+    NodeError(String),
+    /// Broadcast withdrawal not found, or no longer awaiting confirmations
+    BroadcastNotFound(u64),
+    /// The broadcast has no nonce to send a replacement under, or was
+    /// already cancelled
+    NotReplaceable(u64),
+    /// No network fee could be quoted for a withdrawal
+    FeeUnavailable(String),
+    /// No new key could be generated for a wallet
+    KeyGenerationFailed(String),
+    /// The address already belongs to a wallet
+    AddressInUse(String),
+    /// A master key share is malformed or does not fit with the others
+    InvalidKeyShare(String),
+    /// A seed phrase is malformed or keys cannot be derived from it
+    InvalidMnemonic(String),
+    /// A PSBT is malformed, belongs to another transaction or cannot be
+    /// built
+    InvalidPsbt(String),
+    /// A multi-signature wallet's public keys are missing, malformed or do
+    /// not match its address
+    InvalidMultisigKeys(String),
+    /// An encrypted backup was written by a newer format version
+    UnsupportedBackupVersion(u32),
+    /// The wallet has operations in progress
+    WalletInUse(String),
+    /// The wallet has its own withdrawal approvals and cannot be made joint
+    JointNotAllowed(String),
+    /// Withdrawals from this whitelisted wallet must name a destination
+    DestinationRequired(String),
+    /// The wallet already has withdrawal approvers; replacing them needs approval
+    ApproversAlreadySet(String),
+    /// A conversion leg whose opposite leg cannot be found, so it cannot
+    /// be reversed alone
+    UnpairedConversionLeg(u64),
+}
+
+impl CustodyError {
+    /// Returns the catalog key and template arguments for this error
+    fn message(&self, locale: Locale) -> (&'static str, Vec<String>) {
+        match self {
+            CustodyError::WalletAlreadyExists(id) => {
+                ("error.wallet_already_exists", vec![id.clone()])
+            }
+            CustodyError::WalletNotFound(id) => ("error.wallet_not_found", vec![id.clone()]),
+            CustodyError::SourceWalletNotFound(id) => {
+                ("error.source_wallet_not_found", vec![id.clone()])
+            }
+            CustodyError::DestinationWalletNotFound(id) => {
+                ("error.destination_wallet_not_found", vec![id.clone()])
+            }
+            CustodyError::NonPositiveAmount(op) => (
+                "error.non_positive_amount",
+                vec![i18n::lookup(locale, op.key()).to_string()],
+            ),
+            CustodyError::InsufficientBalance {
+                available,
+                requested,
+            } => (
+                "error.insufficient_balance",
+                vec![available.to_string(), requested.to_string()],
+            ),
+            CustodyError::SameWallet => ("error.same_wallet", vec![]),
+            CustodyError::AssetMismatch { expected, found } => (
+                "error.asset_mismatch",
+                vec![expected.clone(), found.clone()],
+            ),
+            CustodyError::RateUnavailable { from, to } => {
+                ("error.rate_unavailable", vec![from.clone(), to.clone()])
+            }
+            CustodyError::InvalidRate(rate) => ("error.invalid_rate", vec![rate.to_string()]),
+            CustodyError::InvalidDestination(address) => {
+                ("error.invalid_destination", vec![address.clone()])
+            }
+            CustodyError::CoSignatureRequired(id) => {
+                ("error.cosignature_required", vec![id.clone()])
+            }
+            CustodyError::NotJointWallet(id) => ("error.not_joint_wallet", vec![id.clone()]),
+            CustodyError::AlreadyJoint(id) => ("error.already_joint", vec![id.clone()]),
+            CustodyError::NotAnOwner { wallet_id, user } => {
+                ("error.not_an_owner", vec![user.clone(), wallet_id.clone()])
+            }
+            CustodyError::InvalidThreshold {
+                required,
+                available,
+            } => (
+                "error.invalid_threshold",
+                vec![required.to_string(), available.to_string()],
+            ),
+            CustodyError::OperationNotFound(id) => {
+                ("error.operation_not_found", vec![id.to_string()])
+            }
+            CustodyError::OperationNotPending(id) => {
+                ("error.operation_not_pending", vec![id.to_string()])
+            }
+            CustodyError::TransactionNotFound(index) => {
+                ("error.transaction_not_found", vec![index.to_string()])
+            }
+            CustodyError::NotFiatWallet(id) => ("error.not_fiat_wallet", vec![id.clone()]),
+            CustodyError::GatewayError(message) => ("error.gateway", vec![message.clone()]),
+            CustodyError::DuplicateReference(reference) => {
+                ("error.duplicate_reference", vec![reference.clone()])
+            }
+            CustodyError::NotHotWallet(id) => ("error.not_hot_wallet", vec![id.clone()]),
+            CustodyError::NotColdWallet(id) => ("error.not_cold_wallet", vec![id.clone()]),
+            CustodyError::SeedExportNotConfirmed(id) => {
+                ("error.seed_export_not_confirmed", vec![id.clone()])
+            }
+            CustodyError::BackupFailed(reason) => ("error.backup_failed", vec![reason.clone()]),
+            CustodyError::InvalidUr(reason) => ("error.invalid_ur", vec![reason.clone()]),
+            CustodyError::IdSchemeNotFound(name) => {
+                ("error.id_scheme_not_found", vec![name.clone()])
+            }
+            CustodyError::IdCollision(id) => ("error.id_collision", vec![id.clone()]),
+            CustodyError::ExtensionNotFound { kind, name } => (
+                "error.extension_not_found",
+                vec![kind.clone(), name.clone()],
+            ),
+            CustodyError::ExtensionFailed { name, reason } => {
+                ("error.extension_failed", vec![name.clone(), reason.clone()])
+            }
+            CustodyError::ScriptRejected { hook, reason } => {
+                ("error.script_rejected", vec![hook.clone(), reason.clone()])
+            }
+            CustodyError::ScriptError { hook, message } => {
+                ("error.script_error", vec![hook.clone(), message.clone()])
+            }
+            CustodyError::TransactionIdNotFound(id) => {
+                ("error.transaction_id_not_found", vec![id.to_string()])
+            }
+            CustodyError::DigestMismatch(id) => ("error.digest_mismatch", vec![id.to_string()]),
+            CustodyError::InjectedFault(fault) => ("error.injected_fault", vec![fault.clone()]),
+            CustodyError::QueuedWithdrawalNotFound(id) => {
+                ("error.queued_withdrawal_not_found", vec![id.to_string()])
+            }
+            CustodyError::AmountOverflow => ("error.amount_overflow", vec![]),
+            CustodyError::StorageFailed(reason) => ("error.storage_failed", vec![reason.clone()]),
+            CustodyError::ApprovalRequired(id) => ("error.approval_required", vec![id.clone()]),
+            CustodyError::NoApprovers(id) => ("error.no_approvers", vec![id.clone()]),
+            CustodyError::NotAnApprover { wallet_id, user } => (
+                "error.not_an_approver",
+                vec![user.clone(), wallet_id.clone()],
+            ),
+            CustodyError::SelfApproval(id) => ("error.self_approval", vec![id.to_string()]),
+            CustodyError::IdempotencyConflict(key) => {
+                ("error.idempotency_conflict", vec![key.clone()])
+            }
+            CustodyError::AuditChainBroken(id) => {
+                ("error.audit_chain_broken", vec![id.to_string()])
+            }
+            CustodyError::PolicyViolation {
+                wallet_id,
+                violation,
+            } => match violation {
+                PolicyViolation::PerTransactionLimit { limit, requested } => (
+                    "error.policy_per_transaction",
+                    vec![wallet_id.clone(), requested.to_string(), limit.to_string()],
+                ),
+                PolicyViolation::DailyLimit {
+                    limit,
+                    used,
+                    requested,
+                } => (
+                    "error.policy_daily_limit",
+                    vec![
+                        wallet_id.clone(),
+                        requested.to_string(),
+                        limit.to_string(),
+                        used.to_string(),
+                    ],
+                ),
+                PolicyViolation::WeeklyLimit {
+                    limit,
+                    used,
+                    requested,
+                } => (
+                    "error.policy_weekly_limit",
+                    vec![
+                        wallet_id.clone(),
+                        requested.to_string(),
+                        limit.to_string(),
+                        used.to_string(),
+                    ],
+                ),
+                PolicyViolation::Cooldown { remaining_secs } => (
+                    "error.policy_cooldown",
+                    vec![wallet_id.clone(), remaining_secs.to_string()],
+                ),
+            },
+            CustodyError::InvalidAddress {
+                chain,
+                address,
+                reason,
+            } => (
+                "error.invalid_address",
+                vec![address.clone(), chain.clone(), reason.clone()],
+            ),
+            CustodyError::NoAddressValidator(chain) => {
+                ("error.no_address_validator", vec![chain.clone()])
+            }
+            CustodyError::InvalidSnapshot(reason) => {
+                ("error.invalid_snapshot", vec![reason.clone()])
+            }
+            CustodyError::UnsupportedSnapshotVersion(version) => (
+                "error.unsupported_snapshot_version",
+                vec![version.to_string()],
+            ),
+            CustodyError::SignaturesRequired(id) => ("error.signatures_required", vec![id.clone()]),
+            CustodyError::NotMultiSigWallet(id) => ("error.not_multisig_wallet", vec![id.clone()]),
+            CustodyError::NotASigner { wallet_id, user } => {
+                ("error.not_a_signer", vec![user.clone(), wallet_id.clone()])
+            }
+            CustodyError::WalletFrozen(id) => ("error.wallet_frozen", vec![id.clone()]),
+            CustodyError::WalletClosed(id) => ("error.wallet_closed", vec![id.clone()]),
+            CustodyError::InvalidStatusTransition {
+                wallet_id,
+                from,
+                to,
+            } => (
+                "error.invalid_status_transition",
+                vec![wallet_id.clone(), from.to_string(), to.to_string()],
+            ),
+            CustodyError::HoldNotFound(id) => ("error.hold_not_found", vec![id.to_string()]),
+            CustodyError::HoldNotActive(id) => ("error.hold_not_active", vec![id.to_string()]),
+            CustodyError::InvalidXpub(reason) => ("error.invalid_xpub", vec![reason.clone()]),
+            CustodyError::NotAnHdWallet(id) => ("error.not_an_hd_wallet", vec![id.clone()]),
+            CustodyError::VaultLocked => ("error.vault_locked", vec![]),
+            CustodyError::InvalidPassphrase => ("error.invalid_passphrase", vec![]),
+            CustodyError::KeyNotFound(id) => ("error.key_not_found", vec![id.clone()]),
+            CustodyError::KeyVaultFailed(reason) => {
+                ("error.key_vault_failed", vec![reason.clone()])
+            }
+            CustodyError::SigningFailed(reason) => ("error.signing_failed", vec![reason.clone()]),
+            CustodyError::SignatureRejected(reason) => {
+                ("error.signature_rejected", vec![reason.clone()])
+            }
+            CustodyError::UnsignedTxNotFound(id) => {
+                ("error.unsigned_tx_not_found", vec![id.to_string()])
+            }
+            CustodyError::BatchAborted(index) => ("error.batch_aborted", vec![index.to_string()]),
+            CustodyError::InvalidInterval => ("error.invalid_interval", vec![]),
+            CustodyError::ScreeningBlocked(reason) => {
+                ("error.screening_blocked", vec![reason.clone()])
+            }
+            CustodyError::FlaggedForReview(id) => {
+                ("error.flagged_for_review", vec![id.to_string()])
+            }
+            CustodyError::UnknownDepositAddress(address) => {
+                ("error.unknown_deposit_address", vec![address.clone()])
+            }
+            CustodyError::DepositMismatch(tx_hash) => {
+                ("error.deposit_mismatch", vec![tx_hash.clone()])
+            }
+            CustodyError::DepositNotFound(tx_hash) => {
+                ("error.deposit_not_found", vec![tx_hash.clone()])
+            }
+            CustodyError::AccountNotFound(id) => ("error.account_not_found", vec![id.clone()]),
+            CustodyError::AccountAlreadyExists(id) => {
+                ("error.account_already_exists", vec![id.clone()])
+            }
+            CustodyError::OrganizationNotFound(id) => {
+                ("error.organization_not_found", vec![id.clone()])
+            }
+            CustodyError::OrganizationAlreadyExists(id) => {
+                ("error.organization_already_exists", vec![id.clone()])
+            }
+            CustodyError::CustomerNotFound(id) => ("error.customer_not_found", vec![id.clone()]),
+            CustodyError::CustomerAlreadyExists(id) => {
+                ("error.customer_already_exists", vec![id.clone()])
+            }
+            CustodyError::WalletNotEmpty(id) => ("error.wallet_not_empty", vec![id.clone()]),
+            CustodyError::NotCustomerWallet {
+                wallet_id,
+                customer,
+            } => (
+                "error.not_customer_wallet",
+                vec![wallet_id.clone(), customer.clone()],
+            ),
+            CustodyError::ClaimsMismatch {
+                wallet_id,
+                claims,
+                balance,
+            } => (
+                "error.claims_mismatch",
+                vec![wallet_id.clone(), claims.to_string(), balance.to_string()],
+            ),
+            CustodyError::AlreadyReversed(id) => ("error.already_reversed", vec![id.to_string()]),
+            CustodyError::InvalidConfig(reason) => ("error.invalid_config", vec![reason.clone()]),
+            CustodyError::EventOutOfOrder { expected, found } => (
+                "error.event_out_of_order",
+                vec![expected.to_string(), found.to_string()],
+            ),
+            CustodyError::EventRejected(sequence) => {
+                ("error.event_rejected", vec![sequence.to_string()])
+            }
+            CustodyError::DualControlRequired(action) => {
+                ("error.dual_control_required", vec![action.clone()])
+            }
+            CustodyError::ActionExpired(id) => ("error.action_expired", vec![id.to_string()]),
+            CustodyError::AddressNotWhitelisted { wallet_id, address } => (
+                "error.address_not_whitelisted",
+                vec![address.clone(), wallet_id.clone()],
+            ),
+            CustodyError::WhitelistNotActive {
+                address,
+                active_from,
+            } => (
+                "error.whitelist_not_active",
+                vec![address.clone(), active_from.to_string()],
+            ),
+            CustodyError::TravelRuleRequired { amount, threshold } => (
+                "error.travel_rule_required",
+                vec![amount.to_string(), threshold.to_string()],
+            ),
+            CustodyError::IncompleteTravelRule(field) => {
+                ("error.incomplete_travel_rule", vec![field.clone()])
+            }
+            CustodyError::InvalidApiKey => ("error.invalid_api_key", vec![]),
+            CustodyError::ApiKeyNotFound(id) => ("error.api_key_not_found", vec![id.clone()]),
+            CustodyError::ApiKeyExpired(id) => ("error.api_key_expired", vec![id.clone()]),
+            CustodyError::ApiKeyRevoked(id) => ("error.api_key_revoked", vec![id.clone()]),
+            CustodyError::ScopeDenied { key_id, operation } => (
+                "error.scope_denied",
+                vec![key_id.clone(), operation.clone()],
+            ),
+            CustodyError::RateLimited {
+                subject,
+                retry_after_secs,
+            } => (
+                "error.rate_limited",
+                vec![subject.clone(), retry_after_secs.to_string()],
+            ),
+            CustodyError::InvalidBatchFile(reason) => {
+                ("error.invalid_batch_file", vec![reason.clone()])
+            }
+            CustodyError::TenantNotFound(id) => ("error.tenant_not_found", vec![id.clone()]),
+            CustodyError::TenantAlreadyExists(id) => {
+                ("error.tenant_already_exists", vec![id.clone()])
+            }
+            CustodyError::TenantQuotaExceeded { tenant_id, quota } => (
+                "error.tenant_quota_exceeded",
+                vec![tenant_id.clone(), quota.to_string()],
+            ),
+            CustodyError::PeriodClosed { closed_until } => {
+                ("error.period_closed", vec![closed_until.to_string()])
+            }
+            CustodyError::PeriodNotEnded { until } => {
+                ("error.period_not_ended", vec![until.to_string()])
+            }
+            CustodyError::AlertNotFound(id) => ("error.alert_not_found", vec![id.to_string()]),
+            CustodyError::AlertAlreadyResolved(id) => {
+                ("error.alert_already_resolved", vec![id.to_string()])
+            }
+            CustodyError::CommitmentNotFound(id) => {
+                ("error.commitment_not_found", vec![id.to_string()])
+            }
+            CustodyError::InvalidImportRow(reason) => {
+                ("error.invalid_import_row", vec![reason.clone()])
+            }
+            CustodyError::InvalidImportFile(reason) => {
+                ("error.invalid_import_file", vec![reason.clone()])
+            }
+            CustodyError::NodeError(message) => ("error.node", vec![message.clone()]),
+            CustodyError::BroadcastNotFound(id) => {
+                ("error.broadcast_not_found", vec![id.to_string()])
+            }
+            CustodyError::NotReplaceable(id) => ("error.not_replaceable", vec![id.to_string()]),
+            CustodyError::FeeUnavailable(reason) => ("error.fee_unavailable", vec![reason.clone()]),
+            CustodyError::KeyGenerationFailed(reason) => {
+                ("error.key_generation_failed", vec![reason.clone()])
+            }
+            CustodyError::AddressInUse(address) => ("error.address_in_use", vec![address.clone()]),
+            CustodyError::InvalidKeyShare(reason) => {
+                ("error.invalid_key_share", vec![reason.clone()])
+            }
+            CustodyError::InvalidMnemonic(reason) => {
+                ("error.invalid_mnemonic", vec![reason.clone()])
+            }
+            CustodyError::InvalidPsbt(reason) => ("error.invalid_psbt", vec![reason.clone()]),
+            CustodyError::InvalidMultisigKeys(reason) => {
+                ("error.invalid_multisig_keys", vec![reason.clone()])
+            }
+            CustodyError::UnsupportedBackupVersion(version) => (
+                "error.unsupported_backup_version",
+                vec![version.to_string()],
+            ),
+            CustodyError::WalletInUse(id) => ("error.wallet_in_use", vec![id.clone()]),
+            CustodyError::JointNotAllowed(id) => ("error.joint_not_allowed", vec![id.clone()]),
+            CustodyError::DestinationRequired(id) => {
+                ("error.destination_required", vec![id.clone()])
+            }
+            CustodyError::ApproversAlreadySet(id) => {
+                ("error.approvers_already_set", vec![id.clone()])
+            }
+            CustodyError::UnpairedConversionLeg(id) => {
+                ("error.unpaired_conversion_leg", vec![id.to_string()])
+            }
+            CustodyError::TemplateNotFound(name) => {
+                ("error.template_not_found", vec![name.clone()])
+            }
+        }
+    }
+
+    /// Renders the error message in the given locale
+    pub fn localized(&self, locale: Locale) -> String {
+        let (key, args) = self.message(locale);
+        i18n::render(locale, key, &args)
+    }
+}
+
+impl fmt::Display for CustodyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.localized(Locale::En))
+    }
+}
+
+impl std::error::Error for CustodyError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display_is_english() {
+        let err = CustodyError::WalletNotFound("w1".to_string());
+        assert_eq!(err.to_string(), "Wallet 'w1' not found");
+
+        let err = CustodyError::NonPositiveAmount(OperationKind::Deposit);
+        assert_eq!(err.to_string(), "Deposit amount must be positive");
+    }
+
+    #[test]
+    fn test_localized_operation_name() {
+        let err = CustodyError::NonPositiveAmount(OperationKind::Withdrawal);
+        assert_eq!(
+            err.localized(Locale::PtBr),
+            "O valor de saque deve ser positivo"
+        );
+        assert_eq!(
+            err.localized(Locale::Es),
+            "El monto de retiro debe ser positivo"
+        );
+    }
+
+    #[test]
+    fn test_localized_insufficient_balance() {
+        let err = CustodyError::InsufficientBalance {
+            available: amount!(1.5),
+            requested: amount!(2.0),
+        };
+        assert_eq!(
+            err.localized(Locale::Es),
+            "Saldo insuficiente: 1.5 disponible, 2 solicitado"
+        );
+    }
+}
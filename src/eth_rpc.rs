@@ -0,0 +1,673 @@
+//! Deposit detection through an Ethereum node (feature `eth-rpc`).
+//!
+//! An [`EthereumDepositScanner`] walks the chain block by block over
+//! JSON-RPC, picking up ether sent to custody addresses and the ERC-20
+//! `Transfer` logs of registered token contracts. Each payment is fed to
+//! [`CustodySystem::ingest_external_asset_deposit`] and followed on later
+//! scans until it has the confirmations its asset needs.
+//!
+//! The scanner remembers the hashes of recent blocks. When the node's
+//! chain no longer contains one of them, the blocks from there on were
+//! reorganised away: the scanner discards the pending deposits they
+//! carried and scans the replacement blocks. Credited deposits are final.
+//!
+//! Token payments are referenced as `<tx hash>:<log index>`, since one
+//! transaction can make several transfers to the same address.
+//!
+//! The client also broadcasts signed withdrawals as a [`Broadcaster`].
+
+use crate::http;
+use crate::{
+    Amount, Asset, Broadcaster, CustodyError, CustodySystem, DepositStatus, FeeEstimator, FeeRate,
+    TxHash, Wallet,
+};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, VecDeque};
+use std::time::Duration;
+
+/// Topic of the ERC-20 `Transfer(address,address,uint256)` event
+pub const TRANSFER_TOPIC: &str =
+    "0xddf252ad1be2c89b69c2b068fc378daa952ba7f163c4a11628f55a4df523b3ef";
+
+/// Blocks whose hashes are kept to detect reorganisations
+const REORG_WINDOW: usize = 128;
+
+/// A transaction in a block
+#[derive(Debug, Clone, PartialEq)]
+pub struct EthTransaction {
+    pub hash: String,
+    /// Recipient; `None` for contract creations
+    pub to: Option<String>,
+    /// Ether sent, in wei
+    pub value: u128,
+}
+
+/// A block with its transactions
+#[derive(Debug, Clone, PartialEq)]
+pub struct EthBlock {
+    pub number: u64,
+    pub hash: String,
+    pub parent_hash: String,
+    pub transactions: Vec<EthTransaction>,
+}
+
+/// An ERC-20 `Transfer` log
+#[derive(Debug, Clone, PartialEq)]
+pub struct TransferLog {
+    pub tx_hash: String,
+    pub log_index: u64,
+    /// Token contract that emitted the log
+    pub contract: String,
+    pub to: String,
+    /// Tokens sent, in the token's smallest unit
+    pub value: u128,
+}
+
+/// Chain data from an Ethereum node
+pub trait EthereumNode: Send + Sync {
+    /// Returns the number of the latest block
+    fn block_number(&self) -> Result<u64, String>;
+
+    /// Returns block `number` of the node's best chain, if it exists yet
+    fn block(&self, number: u64) -> Result<Option<EthBlock>, String>;
+
+    /// Returns the `Transfer` logs `contracts` emitted in the block with
+    /// hash `block_hash`
+    fn transfer_logs(
+        &self,
+        block_hash: &str,
+        contracts: &[String],
+    ) -> Result<Vec<TransferLog>, String>;
+}
+
+/// Parses a `0x`-prefixed hex quantity
+fn quantity(value: &Value) -> Result<u128, String> {
+    let malformed = || format!("malformed quantity {}", value);
+    let digits = value
+        .as_str()
+        .and_then(|hex| hex.strip_prefix("0x"))
+        .ok_or_else(malformed)?;
+    let digits = digits.trim_start_matches('0');
+    if digits.is_empty() {
+        return Ok(0);
+    }
+    u128::from_str_radix(digits, 16).map_err(|_| malformed())
+}
+
+fn string(value: &Value) -> Result<String, String> {
+    value
+        .as_str()
+        .map(str::to_string)
+        .ok_or_else(|| format!("expected a string, found {}", value))
+}
+
+/// JSON-RPC client for an Ethereum node on a plain `http://` URL
+#[derive(Debug, Clone)]
+pub struct EthereumRpcClient {
+    url: String,
+    timeout: Duration,
+}
+
+impl EthereumRpcClient {
+    /// Connects to `url`, e.g. `http://127.0.0.1:8545`
+    pub fn new(url: &str) -> Self {
+        Self {
+            url: url.to_string(),
+            timeout: Duration::from_secs(30),
+        }
+    }
+
+    /// Sets the request timeout
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Calls `method` and returns its result
+    pub fn call(&self, method: &str, params: Value) -> Result<Value, String> {
+        let request = json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": method,
+            "params": params,
+        });
+        let headers = [("Content-Type", "application/json".to_string())];
+        let response = http::send(
+            "POST",
+            &self.url,
+            &headers,
+            request.to_string().as_bytes(),
+            self.timeout,
+        )?;
+        let Ok(mut reply) = serde_json::from_slice::<Value>(&response.body) else {
+            return Err(format!("HTTP {}", response.status));
+        };
+        match reply["error"].take() {
+            Value::Null => Ok(reply["result"].take()),
+            error => Err(error["message"]
+                .as_str()
+                .map_or_else(|| error.to_string(), str::to_string)),
+        }
+    }
+}
+
+impl EthereumNode for EthereumRpcClient {
+    fn block_number(&self) -> Result<u64, String> {
+        let number = quantity(&self.call("eth_blockNumber", json!([]))?)?;
+        u64::try_from(number).map_err(|err| err.to_string())
+    }
+
+    fn block(&self, number: u64) -> Result<Option<EthBlock>, String> {
+        let block = self.call(
+            "eth_getBlockByNumber",
+            json!([format!("{:#x}", number), true]),
+        )?;
+        if block.is_null() {
+            return Ok(None);
+        }
+        let transactions = block["transactions"]
+            .as_array()
+            .ok_or("block without transactions")?
+            .iter()
+            .map(|tx| {
+                Ok(EthTransaction {
+                    hash: string(&tx["hash"])?,
+                    to: tx["to"].as_str().map(str::to_string),
+                    value: quantity(&tx["value"])?,
+                })
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(Some(EthBlock {
+            number,
+            hash: string(&block["hash"])?,
+            parent_hash: string(&block["parentHash"])?,
+            transactions,
+        }))
+    }
+
+    fn transfer_logs(
+        &self,
+        block_hash: &str,
+        contracts: &[String],
+    ) -> Result<Vec<TransferLog>, String> {
+        let logs = self.call(
+            "eth_getLogs",
+            json!([{
+                "blockHash": block_hash,
+                "address": contracts,
+                "topics": [TRANSFER_TOPIC],
+            }]),
+        )?;
+        logs.as_array()
+            .ok_or("malformed eth_getLogs result")?
+            .iter()
+            // Transfers have the sender and recipient as indexed topics
+            .filter(|log| log["topics"].as_array().is_some_and(|t| t.len() == 3))
+            .map(|log| {
+                let topic = string(&log["topics"][2])?;
+                let recipient = topic
+                    .get(topic.len().saturating_sub(40)..)
+                    .ok_or("malformed Transfer topic")?;
+                let index = quantity(&log["logIndex"])?;
+                Ok(TransferLog {
+                    tx_hash: string(&log["transactionHash"])?,
+                    log_index: u64::try_from(index).map_err(|err| err.to_string())?,
+                    contract: string(&log["address"])?,
+                    to: format!("0x{}", recipient),
+                    value: quantity(&log["data"])?,
+                })
+            })
+            .collect()
+    }
+}
+
+impl Broadcaster for EthereumRpcClient {
+    fn broadcast(&self, raw_tx: &[u8]) -> Result<TxHash, String> {
+        let raw: String = raw_tx.iter().map(|byte| format!("{:02x}", byte)).collect();
+        let hash = self.call("eth_sendRawTransaction", json!([format!("0x{}", raw)]))?;
+        string(&hash)
+    }
+
+    fn confirmations(&self, tx_hash: &str) -> Result<u32, String> {
+        let receipt = self.call("eth_getTransactionReceipt", json!([tx_hash]))?;
+        if receipt.is_null() {
+            return Ok(0);
+        }
+        // A reverted transaction is mined but moved no funds
+        if receipt["status"] == "0x0" {
+            return Err(format!("transaction {} reverted", tx_hash));
+        }
+        let block = quantity(&receipt["blockNumber"])?;
+        let head = u128::from(self.block_number()?);
+        Ok(u32::try_from((head + 1).saturating_sub(block)).unwrap_or(u32::MAX))
+    }
+
+    fn fee_paid(&self, tx_hash: &str) -> Result<Option<Amount>, String> {
+        let receipt = self.call("eth_getTransactionReceipt", json!([tx_hash]))?;
+        if receipt.is_null() {
+            return Ok(None);
+        }
+        let wei = quantity(&receipt["gasUsed"])?
+            .checked_mul(quantity(&receipt["effectiveGasPrice"])?)
+            .and_then(|wei| i128::try_from(wei).ok())
+            .ok_or("fee overflows")?;
+        Ok(Amount::from_minor_units(wei, Asset::Eth.decimals()))
+    }
+}
+
+impl FeeEstimator for EthereumRpcClient {
+    fn fee_rate(&self, asset: &Asset) -> Result<FeeRate, String> {
+        if !matches!(asset, Asset::Eth | Asset::Erc20 { .. }) {
+            return Err(format!("no fee rate for {}", asset));
+        }
+        let latest = self.call("eth_getBlockByNumber", json!(["latest", false]))?;
+        // Chains without EIP-1559 have no base fee and price gas directly
+        if latest["baseFeePerGas"].is_null() {
+            let price = self.call("eth_gasPrice", json!([]))?;
+            return Ok(FeeRate::GasPrice(quantity(&price)?));
+        }
+        let priority = self.call("eth_maxPriorityFeePerGas", json!([]))?;
+        Ok(FeeRate::Eip1559 {
+            base_fee_per_gas: quantity(&latest["baseFeePerGas"])?,
+            priority_fee_per_gas: quantity(&priority)?,
+        })
+    }
+}
+
+/// Outcome of one scan
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ScanReport {
+    /// Latest block seen
+    pub head: u64,
+    /// References of deposits credited by this scan
+    pub credited: Vec<String>,
+    /// References of deposits still awaiting confirmations
+    pub pending: Vec<String>,
+    /// References of pending deposits dropped by a reorganisation
+    pub rolled_back: Vec<String>,
+    /// Payments the ledger refused, with the reason
+    pub rejected: Vec<(String, CustodyError)>,
+}
+
+/// A pending deposit and the block that carried it
+#[derive(Debug, Clone)]
+struct Tracked {
+    reference: String,
+    block: u64,
+}
+
+/// Scans an Ethereum node for ETH and ERC-20 deposits to custody
+/// addresses
+pub struct EthereumDepositScanner {
+    node: Box<dyn EthereumNode>,
+    tokens: BTreeMap<String, Asset>,
+    next_block: u64,
+    /// Number and hash of the most recent scanned blocks, oldest first
+    recent: VecDeque<(u64, String)>,
+    tracked: Vec<Tracked>,
+}
+
+impl EthereumDepositScanner {
+    /// Scans from block `start` on
+    pub fn new(node: Box<dyn EthereumNode>, start: u64) -> Self {
+        Self {
+            node,
+            tokens: BTreeMap::new(),
+            next_block: start,
+            recent: VecDeque::new(),
+            tracked: Vec::new(),
+        }
+    }
+
+    /// Also detects transfers of `token`, an [`Asset::Erc20`]
+    ///
+    /// # Errors
+    /// [`CustodyError::AssetMismatch`] for any other kind of asset
+    pub fn with_token(mut self, token: Asset) -> Result<Self, CustodyError> {
+        let Asset::Erc20 { contract, .. } = &token else {
+            return Err(CustodyError::AssetMismatch {
+                expected: "ERC-20 token".to_string(),
+                found: token.symbol().to_string(),
+            });
+        };
+        self.tokens.insert(contract.to_lowercase(), token);
+        Ok(self)
+    }
+
+    /// Gets the block the next scan starts from
+    pub fn next_block(&self) -> u64 {
+        self.next_block
+    }
+
+    /// Scans the blocks produced since the last scan and updates the
+    /// confirmations of pending deposits
+    ///
+    /// # Errors
+    /// [`CustodyError::NodeError`] if the node cannot be queried. Blocks
+    /// scanned before the failure stay scanned.
+    pub fn scan(&mut self, system: &mut CustodySystem) -> Result<ScanReport, CustodyError> {
+        let node_error = CustodyError::NodeError;
+        let head = self.node.block_number().map_err(node_error)?;
+        let mut report = ScanReport {
+            head,
+            ..ScanReport::default()
+        };
+
+        // Step back over scanned blocks the node no longer has
+        while let Some((number, hash)) = self.recent.back() {
+            let current = self.node.block(*number).map_err(node_error)?;
+            if current.is_some_and(|block| block.hash == *hash) {
+                break;
+            }
+            self.roll_back(*number, system, &mut report);
+        }
+
+        while self.next_block <= head {
+            let Some(block) = self.node.block(self.next_block).map_err(node_error)? else {
+                break;
+            };
+            if let Some((_, hash)) = self.recent.back() {
+                if block.parent_hash != *hash {
+                    // Reorganised while scanning: the previous block is gone
+                    let number = self.next_block - 1;
+                    self.roll_back(number, system, &mut report);
+                    continue;
+                }
+            }
+            self.scan_block(&block, head, system, &mut report)?;
+            self.recent.push_back((block.number, block.hash));
+            if self.recent.len() > REORG_WINDOW {
+                self.recent.pop_front();
+            }
+            self.next_block += 1;
+        }
+
+        let tracked = std::mem::take(&mut self.tracked);
+        for deposit in tracked {
+            let confirmations = confirmations(head, deposit.block);
+            match system.update_confirmations(&deposit.reference, confirmations) {
+                Ok(DepositStatus::Credited) => report.credited.push(deposit.reference),
+                Ok(DepositStatus::Pending) => {
+                    report.pending.push(deposit.reference.clone());
+                    self.tracked.push(deposit);
+                }
+                Err(error) => report.rejected.push((deposit.reference, error)),
+            }
+        }
+        Ok(report)
+    }
+
+    /// Ingests the payments in `block` to custody addresses
+    fn scan_block(
+        &mut self,
+        block: &EthBlock,
+        head: u64,
+        system: &mut CustodySystem,
+        report: &mut ScanReport,
+    ) -> Result<(), CustodyError> {
+        let mut payments = Vec::new();
+        for tx in &block.transactions {
+            if let Some(to) = &tx.to {
+                if tx.value > 0 {
+                    payments.push((tx.hash.clone(), to.clone(), Asset::Eth, tx.value));
+                }
+            }
+        }
+        if !self.tokens.is_empty() {
+            let contracts: Vec<String> = self.tokens.keys().cloned().collect();
+            let logs = self
+                .node
+                .transfer_logs(&block.hash, &contracts)
+                .map_err(CustodyError::NodeError)?;
+            for log in logs {
+                if let Some(token) = self.tokens.get(&log.contract.to_lowercase()) {
+                    let reference = format!("{}:{}", log.tx_hash, log.log_index);
+                    payments.push((reference, log.to, token.clone(), log.value));
+                }
+            }
+        }
+
+        for (reference, to, asset, value) in payments {
+            let Some(address) = custody_address(system, &to) else {
+                continue;
+            };
+            let Some(amount) = i128::try_from(value)
+                .ok()
+                .and_then(|units| Amount::from_minor_units(units, asset.decimals()))
+            else {
+                report
+                    .rejected
+                    .push((reference, CustodyError::AmountOverflow));
+                continue;
+            };
+            if self.tracked.iter().any(|t| t.reference == reference) {
+                continue;
+            }
+            match system.ingest_external_asset_deposit(
+                &address,
+                &asset,
+                amount,
+                &reference,
+                confirmations(head, block.number),
+            ) {
+                Ok(DepositStatus::Credited) => report.credited.push(reference),
+                Ok(DepositStatus::Pending) => self.tracked.push(Tracked {
+                    reference,
+                    block: block.number,
+                }),
+                Err(error) => report.rejected.push((reference, error)),
+            }
+        }
+        Ok(())
+    }
+
+    /// Forgets scanned block `number` and everything after it, discarding
+    /// the pending deposits they carried
+    fn roll_back(&mut self, number: u64, system: &mut CustodySystem, report: &mut ScanReport) {
+        while self.recent.back().is_some_and(|(n, _)| *n >= number) {
+            self.recent.pop_back();
+        }
+        let (orphaned, kept) = std::mem::take(&mut self.tracked)
+            .into_iter()
+            .partition(|deposit| deposit.block >= number);
+        self.tracked = kept;
+        for deposit in orphaned {
+            system.discard_pending_deposits(&deposit.reference);
+            report.rolled_back.push(deposit.reference);
+        }
+        self.next_block = number;
+    }
+}
+
+fn confirmations(head: u64, block: u64) -> u32 {
+    u32::try_from((head + 1).saturating_sub(block)).unwrap_or(u32::MAX)
+}
+
+/// Returns the custody address matching `address`, which nodes report in
+/// lower case whatever the checksum casing it was registered with
+fn custody_address(system: &CustodySystem, address: &str) -> Option<String> {
+    if system.wallet_for_address(address).is_some() {
+        return Some(address.to_string());
+    }
+    system
+        .watched_addresses
+        .keys()
+        .map(String::as_str)
+        .chain(system.wallets.values().flat_map(Wallet::addresses))
+        .find(|candidate| candidate.eq_ignore_ascii_case(address))
+        .map(str::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::WalletType;
+    use std::sync::{Arc, Mutex};
+
+    const CUSTODY: &str = "0xAbC0000000000000000000000000000000000001";
+    const USDC: &str = "0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48";
+
+    /// A block and the token transfers it carries
+    type Mined = (EthBlock, Vec<TransferLog>);
+
+    /// A chain that tests can extend and reorganise
+    #[derive(Clone, Default)]
+    struct FakeChain {
+        blocks: Arc<Mutex<Vec<Mined>>>,
+    }
+
+    impl FakeChain {
+        /// Appends a block, named by `tag` so forks get other hashes
+        fn mine(&self, tag: &str, transactions: Vec<EthTransaction>, logs: Vec<TransferLog>) {
+            let mut blocks = self.blocks.lock().unwrap();
+            let number = blocks.len() as u64;
+            let parent_hash = blocks
+                .last()
+                .map_or_else(String::new, |(block, _)| block.hash.clone());
+            let block = EthBlock {
+                number,
+                hash: format!("{}-{}", tag, number),
+                parent_hash,
+                transactions,
+            };
+            blocks.push((block, logs));
+        }
+
+        /// Drops every block from `number` on
+        fn fork_at(&self, number: usize) {
+            self.blocks.lock().unwrap().truncate(number);
+        }
+    }
+
+    impl EthereumNode for FakeChain {
+        fn block_number(&self) -> Result<u64, String> {
+            Ok(self.blocks.lock().unwrap().len() as u64 - 1)
+        }
+
+        fn block(&self, number: u64) -> Result<Option<EthBlock>, String> {
+            let blocks = self.blocks.lock().unwrap();
+            Ok(blocks.get(number as usize).map(|(block, _)| block.clone()))
+        }
+
+        fn transfer_logs(
+            &self,
+            block_hash: &str,
+            contracts: &[String],
+        ) -> Result<Vec<TransferLog>, String> {
+            let blocks = self.blocks.lock().unwrap();
+            Ok(blocks
+                .iter()
+                .filter(|(block, _)| block.hash == block_hash)
+                .flat_map(|(_, logs)| logs.clone())
+                .filter(|log| contracts.contains(&log.contract))
+                .collect())
+        }
+    }
+
+    fn payment(hash: &str, to: &str, wei: u128) -> EthTransaction {
+        EthTransaction {
+            hash: hash.to_string(),
+            to: Some(to.to_string()),
+            value: wei,
+        }
+    }
+
+    fn usdc() -> Asset {
+        Asset::Erc20 {
+            symbol: "USDC".to_string(),
+            contract: USDC.to_string(),
+            decimals: 6,
+        }
+    }
+
+    fn setup() -> (CustodySystem, FakeChain, EthereumDepositScanner) {
+        let mut system = CustodySystem::new();
+        system
+            .create_wallet_with_asset(
+                "eth".to_string(),
+                CUSTODY.to_string(),
+                WalletType::Hot,
+                Asset::Eth,
+            )
+            .unwrap();
+        system.set_confirmation_policy(Asset::Eth, 3);
+        system.set_confirmation_policy(usdc(), 2);
+        let chain = FakeChain::default();
+        chain.mine("a", vec![], vec![]);
+        let scanner = EthereumDepositScanner::new(Box::new(chain.clone()), 1)
+            .with_token(usdc())
+            .unwrap();
+        (system, chain, scanner)
+    }
+
+    #[test]
+    fn test_eth_and_token_deposits_credited_after_confirmations() {
+        let (mut system, chain, mut scanner) = setup();
+        let token_transfer = TransferLog {
+            tx_hash: "0xt1".to_string(),
+            log_index: 4,
+            contract: USDC.to_string(),
+            to: CUSTODY.to_lowercase(),
+            value: 2_500_000,
+        };
+        chain.mine(
+            "a",
+            vec![
+                payment("0xe1", &CUSTODY.to_lowercase(), 1_500_000_000_000_000_000),
+                payment("0xe2", "0x0000000000000000000000000000000000000009", 7),
+            ],
+            vec![token_transfer],
+        );
+
+        let report = scanner.scan(&mut system).unwrap();
+        assert_eq!(report.pending, ["0xe1", "0xt1:4"]);
+        assert_eq!(scanner.next_block(), 2);
+
+        chain.mine("a", vec![], vec![]);
+        let report = scanner.scan(&mut system).unwrap();
+        assert_eq!(report.credited, ["0xt1:4"]);
+        chain.mine("a", vec![], vec![]);
+        let report = scanner.scan(&mut system).unwrap();
+        assert_eq!(report.credited, ["0xe1"]);
+
+        let wallet = system.get_wallet("eth").unwrap();
+        assert_eq!(wallet.balance, amount!(1.5));
+        assert_eq!(wallet.balance_of(&usdc()), amount!(2.5));
+        assert_eq!(
+            scanner.scan(&mut system).unwrap(),
+            ScanReport {
+                head: 3,
+                ..ScanReport::default()
+            }
+        );
+        assert!(scanner.with_token(Asset::Btc).is_err());
+    }
+
+    #[test]
+    fn test_reorg_rolls_back_unconfirmed_deposits() {
+        let (mut system, chain, mut scanner) = setup();
+        chain.mine("a", vec![payment("0xe1", CUSTODY, 1_000)], vec![]);
+        chain.mine("a", vec![], vec![]);
+        assert_eq!(scanner.scan(&mut system).unwrap().pending, ["0xe1"]);
+
+        // The fork replaces blocks 1 and 2; the payment lands later
+        chain.fork_at(1);
+        chain.mine("b", vec![], vec![]);
+        chain.mine("b", vec![], vec![]);
+        chain.mine("b", vec![payment("0xe1", CUSTODY, 1_000)], vec![]);
+        let report = scanner.scan(&mut system).unwrap();
+        assert_eq!(report.rolled_back, ["0xe1"]);
+        assert_eq!(report.pending, ["0xe1"]);
+        let pending = system.pending_deposits("eth");
+        assert_eq!(pending[0].confirmations, 1);
+
+        chain.mine("b", vec![], vec![]);
+        chain.mine("b", vec![], vec![]);
+        assert_eq!(scanner.scan(&mut system).unwrap().credited, ["0xe1"]);
+        assert_eq!(
+            system.get_wallet("eth").unwrap().balance,
+            Amount::from_minor_units(1_000, 18).unwrap()
+        );
+        assert!(system.discard_pending_deposits("0xe1").is_empty());
+    }
+}
@@ -0,0 +1,183 @@
+//! Custody events and listeners.
+//!
+//! The system emits a [`CustodyEvent`] after each committed state change
+//! that downstream parties care about. Listeners registered with
+//! [`CustodySystem::subscribe`] are called synchronously, in registration
+//! order, after the change has been applied.
+
+use crate::{Amount, Asset, CustodySystem};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::Arc;
+
+/// A committed custody event
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum CustodyEvent {
+    /// Funds were credited to a wallet
+    DepositReceived {
+        wallet_id: String,
+        amount: Amount,
+        asset: Asset,
+    },
+    /// A withdrawal collected all approvals it needed
+    WithdrawalApproved {
+        wallet_id: String,
+        operation_id: u64,
+        amount: Amount,
+        asset: Asset,
+    },
+    /// Funds were debited from a wallet
+    WithdrawalSettled {
+        wallet_id: String,
+        amount: Amount,
+        asset: Asset,
+    },
+    /// Funds moved between two wallets; emitted after the debit and credit
+    /// of the two legs
+    TransferCompleted {
+        wallet_id: String,
+        to_wallet_id: String,
+        amount: Amount,
+        asset: Asset,
+    },
+    /// A wallet was frozen
+    WalletFrozen { wallet_id: String, reason: String },
+    /// An on-chain deposit reached its confirmation threshold and was
+    /// credited; follows the deposit's [`DepositReceived`](Self::DepositReceived)
+    DepositFinalized {
+        wallet_id: String,
+        tx_hash: String,
+        amount: Amount,
+        asset: Asset,
+        confirmations: u32,
+    },
+    /// Compliance screening held a withdrawal or transfer for manual review
+    OperationFlagged {
+        wallet_id: String,
+        review_id: u64,
+        amount: Amount,
+        asset: Asset,
+        reason: String,
+    },
+    /// A broadcast transaction has waited longer than allowed to be mined
+    TransactionStuck {
+        wallet_id: String,
+        tx_hash: String,
+        nonce: u64,
+        pending_secs: u64,
+    },
+}
+
+impl CustodyEvent {
+    /// Returns the wallet the event concerns
+    pub fn wallet_id(&self) -> &str {
+        match self {
+            CustodyEvent::DepositReceived { wallet_id, .. }
+            | CustodyEvent::WithdrawalApproved { wallet_id, .. }
+            | CustodyEvent::WithdrawalSettled { wallet_id, .. }
+            | CustodyEvent::TransferCompleted { wallet_id, .. }
+            | CustodyEvent::DepositFinalized { wallet_id, .. }
+            | CustodyEvent::WalletFrozen { wallet_id, .. }
+            | CustodyEvent::OperationFlagged { wallet_id, .. }
+            | CustodyEvent::TransactionStuck { wallet_id, .. } => wallet_id,
+        }
+    }
+
+    /// Returns a stable snake_case name for the event kind
+    pub fn kind(&self) -> &'static str {
+        match self {
+            CustodyEvent::DepositReceived { .. } => "deposit_received",
+            CustodyEvent::WithdrawalApproved { .. } => "withdrawal_approved",
+            CustodyEvent::WithdrawalSettled { .. } => "withdrawal_settled",
+            CustodyEvent::TransferCompleted { .. } => "transfer_completed",
+            CustodyEvent::DepositFinalized { .. } => "deposit_finalized",
+            CustodyEvent::WalletFrozen { .. } => "wallet_frozen",
+            CustodyEvent::OperationFlagged { .. } => "operation_flagged",
+            CustodyEvent::TransactionStuck { .. } => "transaction_stuck",
+        }
+    }
+}
+
+/// Receives custody events
+pub trait EventListener: Send + Sync {
+    fn on_event(&self, event: &CustodyEvent);
+}
+
+/// Registered listeners
+#[derive(Clone, Default)]
+pub(crate) struct Listeners(Vec<Arc<dyn EventListener>>);
+
+impl fmt::Debug for Listeners {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Listeners({})", self.0.len())
+    }
+}
+
+impl CustodySystem {
+    /// Registers a listener for custody events
+    pub fn subscribe(&mut self, listener: Arc<dyn EventListener>) {
+        self.listeners.0.push(listener);
+    }
+
+    /// Delivers an event to every listener
+    pub(crate) fn emit(&self, event: CustodyEvent) {
+        for listener in &self.listeners.0 {
+            listener.on_event(&event);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::WalletType;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<CustodyEvent>>);
+
+    impl EventListener for Recorder {
+        fn on_event(&self, event: &CustodyEvent) {
+            self.0.lock().unwrap().push(event.clone());
+        }
+    }
+
+    #[test]
+    fn test_deposit_and_withdrawal_emit_events() {
+        let recorder = Arc::new(Recorder::default());
+        let mut system = CustodySystem::new();
+        system.subscribe(recorder.clone());
+        system
+            .create_wallet("w".to_string(), "0x1".to_string(), WalletType::Hot)
+            .unwrap();
+        system.deposit("w", amount!(5.0)).unwrap();
+        system.withdraw("w", amount!(2.0)).unwrap();
+        let _ = system.withdraw("w", amount!(20.0));
+
+        let events = recorder.0.lock().unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].kind(), "deposit_received");
+        assert_eq!(
+            events[1],
+            CustodyEvent::WithdrawalSettled {
+                wallet_id: "w".to_string(),
+                amount: amount!(2.0),
+                asset: Asset::Btc,
+            }
+        );
+    }
+
+    #[test]
+    fn test_fork_does_not_notify() {
+        let recorder = Arc::new(Recorder::default());
+        let mut system = CustodySystem::new();
+        system
+            .create_wallet("w".to_string(), "0x1".to_string(), WalletType::Hot)
+            .unwrap();
+        system.subscribe(recorder.clone());
+
+        let mut fork = system.fork();
+        fork.deposit("w", amount!(5.0)).unwrap();
+        assert!(recorder.0.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,292 @@
+//! Exchange integration for treasury conversions.
+//!
+//! An [`ExchangeConnector`] executes market orders on an external venue.
+//! [`CustodySystem::exchange`] sells funds from a hot wallet and credits
+//! the proceeds to another wallet; the order, its fill, and both ledger
+//! legs are recorded together, or not at all.
+
+use crate::precheck::Authorization;
+use crate::{
+    Amount, Asset, CustodyError, CustodySystem, RateProvider, StaticRateProvider, Transaction,
+    TransactionType, WalletType,
+};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// A market order selling `amount` of `sell` for `buy`
+#[derive(Debug, Clone, PartialEq)]
+pub struct MarketOrder {
+    pub sell: Asset,
+    pub buy: Asset,
+    pub amount: Amount,
+}
+
+/// Execution report for an order
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fill {
+    /// Venue-assigned order id
+    pub order_id: String,
+    /// Units of the sold asset actually sold
+    pub sold: Amount,
+    /// Units of the bought asset received, net of fees
+    pub bought: Amount,
+    /// Fee charged, in the bought asset
+    pub fee: Amount,
+}
+
+impl Fill {
+    /// Effective rate received (bought units per sold unit, net of fees)
+    pub fn rate(&self) -> f64 {
+        self.bought.to_f64() / self.sold.to_f64()
+    }
+}
+
+/// An executed exchange trade as recorded by the custody system
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExchangeTrade {
+    /// Connector that executed the order
+    pub venue: String,
+    pub from_wallet: String,
+    pub to_wallet: String,
+    pub order: MarketOrder,
+    pub fill: Fill,
+}
+
+/// Connection to a trading venue
+pub trait ExchangeConnector {
+    /// Name of the venue, recorded with each trade
+    fn name(&self) -> &str;
+
+    /// Executes a market order, returning its fill
+    fn place_market_order(&self, order: &MarketOrder) -> Result<Fill, String>;
+}
+
+/// Reference connector that fills every order immediately at fixed rates
+/// minus a proportional fee. Useful for testing and simulations.
+#[derive(Debug, Default)]
+pub struct SimulatedExchange {
+    rates: StaticRateProvider,
+    fee_rate: f64,
+    next_order: AtomicU64,
+}
+
+impl SimulatedExchange {
+    /// Creates a venue quoting `rates` and charging `fee_rate` (e.g. `0.001`
+    /// for 10 bps) of the proceeds
+    pub fn new(rates: StaticRateProvider, fee_rate: f64) -> Self {
+        Self {
+            rates,
+            fee_rate,
+            next_order: AtomicU64::new(1),
+        }
+    }
+}
+
+impl ExchangeConnector for SimulatedExchange {
+    fn name(&self) -> &str {
+        "simulated"
+    }
+
+    fn place_market_order(&self, order: &MarketOrder) -> Result<Fill, String> {
+        let rate = self
+            .rates
+            .rate(&order.sell, &order.buy)
+            .ok_or_else(|| format!("no market for {}/{}", order.sell, order.buy))?;
+        let decimals = order.buy.decimals();
+        let gross = order
+            .amount
+            .checked_mul_f64(rate)
+            .ok_or("order size out of range")?
+            .round_dp(decimals);
+        let fee = gross
+            .checked_mul_f64(self.fee_rate)
+            .ok_or("fee out of range")?
+            .round_dp(decimals);
+        let id = self.next_order.fetch_add(1, Ordering::Relaxed);
+        Ok(Fill {
+            order_id: format!("SIM-{}", id),
+            sold: order.amount,
+            bought: gross - fee,
+            fee,
+        })
+    }
+}
+
+impl CustodySystem {
+    /// Sells `amount` from a hot wallet on `connector` and credits the
+    /// proceeds to `to_wallet`
+    ///
+    /// The source wallet is checked like a withdrawal before the order is
+    /// placed. Only the amount the venue reports as sold is debited.
+    pub fn exchange(
+        &mut self,
+        from_wallet: &str,
+        to_wallet: &str,
+        amount: Amount,
+        connector: &dyn ExchangeConnector,
+    ) -> Result<ExchangeTrade, CustodyError> {
+        if from_wallet == to_wallet {
+            return Err(CustodyError::SameWallet);
+        }
+        let source = self
+            .get_wallet(from_wallet)
+            .ok_or_else(|| CustodyError::SourceWalletNotFound(from_wallet.to_string()))?;
+        if source.wallet_type != WalletType::Hot {
+            return Err(CustodyError::NotHotWallet(from_wallet.to_string()));
+        }
+        let destination = self
+            .get_wallet(to_wallet)
+            .ok_or_else(|| CustodyError::DestinationWalletNotFound(to_wallet.to_string()))?;
+        let order = MarketOrder {
+            sell: source.asset.clone(),
+            buy: destination.asset.clone(),
+            amount,
+        };
+        if let Some(reason) = self
+            .withdrawal_blockers(from_wallet, None, amount, None, Authorization::Direct)
+            .into_iter()
+            // The proceeds stay in custody
+            .filter(|reason| !matches!(reason, CustodyError::DestinationRequired(_)))
+            .chain(self.credit_blockers(destination))
+            .next()
+        {
+            return Err(reason);
+        }
+
+        let fill = connector
+            .place_market_order(&order)
+            .map_err(CustodyError::GatewayError)?;
+        let valid = fill.sold.is_positive() && fill.sold <= amount && !fill.bought.is_negative();
+        if !valid {
+            return Err(CustodyError::GatewayError(format!(
+                "invalid fill {} for order of {}",
+                fill.order_id, amount
+            )));
+        }
+
+        self.wallets[to_wallet]
+            .balance
+            .checked_add(fill.bought)
+            .ok_or(CustodyError::AmountOverflow)?;
+
+        let legs = [
+            (
+                from_wallet,
+                to_wallet,
+                TransactionType::ConversionOut,
+                fill.sold,
+                &order.sell,
+            ),
+            (
+                to_wallet,
+                from_wallet,
+                TransactionType::ConversionIn,
+                fill.bought,
+                &order.buy,
+            ),
+        ];
+        let txs = legs
+            .into_iter()
+            .map(|(wallet, counterparty, kind, units, asset)| {
+                let mut tx =
+                    Transaction::new(wallet, kind, units, asset.clone(), self.current_timestamp());
+                tx.rate = Some(fill.rate());
+                tx.counterparty = Some(counterparty.to_string());
+                tx.reference = Some(fill.order_id.clone());
+                tx
+            })
+            .collect();
+        // Both legs are recorded together
+        self.record_transactions(txs)?;
+
+        let trade = ExchangeTrade {
+            venue: connector.name().to_string(),
+            from_wallet: from_wallet.to_string(),
+            to_wallet: to_wallet.to_string(),
+            order,
+            fill,
+        };
+        self.trades.push_back(trade.clone());
+        Ok(trade)
+    }
+
+    /// Returns all exchange trades in execution order
+    pub fn exchange_trades(&self) -> &im::Vector<ExchangeTrade> {
+        &self.trades
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn usd() -> Asset {
+        Asset::Fiat("USD".to_string())
+    }
+
+    fn setup() -> (CustodySystem, SimulatedExchange) {
+        let mut system = CustodySystem::new();
+        system
+            .create_wallet("hot".to_string(), "bc1h".to_string(), WalletType::Hot)
+            .unwrap();
+        system
+            .create_wallet("cold".to_string(), "bc1c".to_string(), WalletType::Cold)
+            .unwrap();
+        system
+            .create_wallet_with_asset(
+                "usd".to_string(),
+                "acct".to_string(),
+                WalletType::Hot,
+                usd(),
+            )
+            .unwrap();
+        system.deposit("hot", amount!(2.0)).unwrap();
+        system.deposit("cold", amount!(2.0)).unwrap();
+
+        let mut rates = StaticRateProvider::new();
+        rates.set_rate(Asset::Btc, usd(), 30000.0);
+        (system, SimulatedExchange::new(rates, 0.001))
+    }
+
+    #[test]
+    fn test_exchange_records_order_fill_and_legs() {
+        let (mut system, venue) = setup();
+        let trade = system.exchange("hot", "usd", amount!(1.0), &venue).unwrap();
+
+        assert_eq!(trade.venue, "simulated");
+        assert_eq!(trade.fill.fee, amount!(30.0));
+        assert_eq!(system.get_wallet("hot").unwrap().balance, amount!(1.0));
+        assert_eq!(system.get_wallet("usd").unwrap().balance, amount!(29970.0));
+        assert_eq!(system.exchange_trades().len(), 1);
+
+        let credit = system.get_wallet_transactions("usd")[0];
+        assert_eq!(credit.transaction_type, TransactionType::ConversionIn);
+        assert_eq!(credit.reference.as_deref(), Some("SIM-1"));
+        assert!(system.replay(system.get_all_transactions()).is_consistent());
+    }
+
+    #[test]
+    fn test_exchange_requires_hot_wallet_and_funds() {
+        let (mut system, venue) = setup();
+        assert_eq!(
+            system.exchange("cold", "usd", amount!(1.0), &venue),
+            Err(CustodyError::NotHotWallet("cold".to_string()))
+        );
+        assert!(matches!(
+            system.exchange("hot", "usd", amount!(5.0), &venue),
+            Err(CustodyError::InsufficientBalance { .. })
+        ));
+        assert!(system.exchange_trades().is_empty());
+    }
+
+    #[test]
+    fn test_rejected_order_changes_nothing() {
+        let (mut system, _) = setup();
+        let venue = SimulatedExchange::new(StaticRateProvider::new(), 0.0);
+        let before = system.fork();
+        assert!(matches!(
+            system.exchange("hot", "usd", amount!(1.0), &venue),
+            Err(CustodyError::GatewayError(_))
+        ));
+        assert!(system.diff(&before).is_empty());
+    }
+}
@@ -0,0 +1,423 @@
+//! Runtime extension registry.
+//!
+//! Institutions plug their own implementations of the crate's traits
+//! (notifiers, rate providers, gateways, id schemes, ...) into an
+//! [`ExtensionRegistry`] under a name. Deployments then select and
+//! configure implementations by name, e.g. from a config file, without
+//! forking the crate.
+//!
+//! Every pluggable trait is an [`ExtensionPoint`]; new traits become
+//! pluggable by implementing it for their trait object type.
+
+use crate::notify::Notifier;
+use crate::{
+    AddressValidator, Asset, CustodyError, CustodySystem, EventListener, ExchangeConnector,
+    FiatGateway, HashedIdScheme, IdScheme, JsonFileStorage, RateProvider, Signer,
+    StaticRateProvider, Storage,
+};
+use serde::{Deserialize, Serialize};
+use std::any::{Any, TypeId};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+/// A trait whose implementations can be registered by name
+pub trait ExtensionPoint: 'static {
+    /// Name of the extension point used in configuration and errors
+    const KIND: &'static str;
+}
+
+impl ExtensionPoint for dyn EventListener {
+    const KIND: &'static str = "event_listener";
+}
+
+impl ExtensionPoint for dyn Notifier {
+    const KIND: &'static str = "notifier";
+}
+
+impl ExtensionPoint for dyn RateProvider {
+    const KIND: &'static str = "rate_provider";
+}
+
+impl ExtensionPoint for dyn FiatGateway {
+    const KIND: &'static str = "fiat_gateway";
+}
+
+impl ExtensionPoint for dyn ExchangeConnector {
+    const KIND: &'static str = "exchange_connector";
+}
+
+impl ExtensionPoint for dyn IdScheme {
+    const KIND: &'static str = "id_scheme";
+}
+
+impl ExtensionPoint for dyn Storage {
+    const KIND: &'static str = "storage";
+}
+
+impl ExtensionPoint for dyn AddressValidator {
+    const KIND: &'static str = "address_validator";
+}
+
+impl ExtensionPoint for dyn Signer {
+    const KIND: &'static str = "signer";
+}
+
+impl ExtensionPoint for dyn crate::ComplianceScreener {
+    const KIND: &'static str = "compliance_screener";
+}
+
+impl ExtensionPoint for dyn crate::PriceOracle {
+    const KIND: &'static str = "price_oracle";
+}
+
+impl ExtensionPoint for dyn crate::DetectionRule {
+    const KIND: &'static str = "detection_rule";
+}
+
+impl ExtensionPoint for dyn crate::time::Clock {
+    const KIND: &'static str = "clock";
+}
+
+impl ExtensionPoint for dyn crate::Broadcaster {
+    const KIND: &'static str = "broadcaster";
+}
+
+impl ExtensionPoint for dyn crate::FeeEstimator {
+    const KIND: &'static str = "fee_estimator";
+}
+
+impl ExtensionPoint for dyn crate::KeyGenerator {
+    const KIND: &'static str = "key_generator";
+}
+
+#[cfg(feature = "hw-wallet")]
+impl ExtensionPoint for dyn crate::DeviceTransport {
+    const KIND: &'static str = "device_transport";
+}
+
+#[cfg(feature = "mpc")]
+impl ExtensionPoint for dyn crate::ThresholdSigner {
+    const KIND: &'static str = "threshold_signer";
+}
+
+#[cfg(feature = "mpc")]
+impl ExtensionPoint for dyn crate::ThresholdParticipant {
+    const KIND: &'static str = "threshold_participant";
+}
+
+#[cfg(feature = "psbt")]
+impl ExtensionPoint for dyn crate::UtxoSource {
+    const KIND: &'static str = "utxo_source";
+}
+
+#[cfg(feature = "bitcoin-rpc")]
+impl ExtensionPoint for dyn crate::BitcoinNode {
+    const KIND: &'static str = "bitcoin_node";
+}
+
+#[cfg(feature = "eth-rpc")]
+impl ExtensionPoint for dyn crate::EthereumNode {
+    const KIND: &'static str = "ethereum_node";
+}
+
+#[cfg(feature = "webhooks")]
+impl ExtensionPoint for dyn crate::WebhookTransport {
+    const KIND: &'static str = "webhook_transport";
+}
+
+/// Free-form settings passed to an extension factory
+pub type Settings = BTreeMap<String, String>;
+
+/// Builds an extension instance from its settings
+pub type Factory<T> = Arc<dyn Fn(&Settings) -> Result<Arc<T>, String> + Send + Sync>;
+
+/// Selects a registered implementation and its settings
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ExtensionSpec {
+    /// Name the implementation was registered under
+    pub name: String,
+    #[serde(default)]
+    pub settings: Settings,
+}
+
+/// Extensions to install into a custody system
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ExtensionsConfig {
+    /// Listeners subscribed to custody events
+    #[serde(default)]
+    pub event_listeners: Vec<ExtensionSpec>,
+    /// Wallet id schemes, keyed by the name to register them under
+    #[serde(default)]
+    pub id_schemes: BTreeMap<String, ExtensionSpec>,
+}
+
+/// Named factories for every extension point
+#[derive(Default)]
+pub struct ExtensionRegistry {
+    factories: HashMap<(TypeId, String), Box<dyn Any + Send + Sync>>,
+}
+
+impl std::fmt::Debug for ExtensionRegistry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ExtensionRegistry({})", self.factories.len())
+    }
+}
+
+impl ExtensionRegistry {
+    /// Creates an empty registry
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a registry with the crate's own implementations:
+    /// `id_scheme/hashed` (setting `prefix`), `rate_provider/static`
+    /// (settings `<FROM>/<TO>` = rate, e.g. `BTC/USD = 30000`; symbols
+    /// other than `BTC` and `ETH` are read as fiat currency codes), and
+    /// `storage/json` (setting `path`)
+    pub fn with_builtins() -> Self {
+        let mut registry = Self::new();
+        registry.register::<dyn IdScheme>(
+            "hashed",
+            Arc::new(|settings: &Settings| {
+                let mut scheme = HashedIdScheme::default();
+                if let Some(prefix) = settings.get("prefix") {
+                    scheme.prefix = prefix.clone();
+                }
+                Ok(Arc::new(scheme) as Arc<dyn IdScheme>)
+            }),
+        );
+        registry.register::<dyn RateProvider>(
+            "static",
+            Arc::new(|settings: &Settings| {
+                let mut rates = StaticRateProvider::new();
+                for (pair, rate) in settings {
+                    let (from, to) = pair
+                        .split_once('/')
+                        .ok_or_else(|| format!("invalid pair '{}'", pair))?;
+                    let rate: f64 = rate
+                        .parse()
+                        .map_err(|_| format!("invalid rate '{}' for {}", rate, pair))?;
+                    rates.set_rate(builtin_asset(from), builtin_asset(to), rate);
+                }
+                Ok(Arc::new(rates) as Arc<dyn RateProvider>)
+            }),
+        );
+        registry.register::<dyn Storage>(
+            "json",
+            Arc::new(|settings: &Settings| {
+                let path = settings.get("path").ok_or("missing setting 'path'")?;
+                Ok(Arc::new(JsonFileStorage::new(path)) as Arc<dyn Storage>)
+            }),
+        );
+        #[cfg(feature = "bitcoin")]
+        registry.register::<dyn AddressValidator>(
+            "bitcoin",
+            Arc::new(|settings: &Settings| {
+                let validator = match settings.get("network").map(String::as_str) {
+                    None | Some("mainnet") => crate::BitcoinAddressValidator::mainnet(),
+                    Some("testnet") => crate::BitcoinAddressValidator::testnet(),
+                    Some(other) => return Err(format!("unknown network '{}'", other)),
+                };
+                Ok(Arc::new(validator) as Arc<dyn AddressValidator>)
+            }),
+        );
+        #[cfg(feature = "ethereum")]
+        registry.register_instance::<dyn AddressValidator>(
+            "ethereum",
+            Arc::new(crate::EthereumAddressValidator),
+        );
+        registry
+    }
+
+    /// Registers a factory for `T` under `name`, replacing any previous one
+    pub fn register<T: ?Sized + ExtensionPoint>(&mut self, name: &str, factory: Factory<T>) {
+        self.factories
+            .insert((TypeId::of::<T>(), name.to_string()), Box::new(factory));
+    }
+
+    /// Registers a ready-made instance of `T` under `name`; every build
+    /// returns the same shared instance
+    pub fn register_instance<T: ?Sized + ExtensionPoint + Send + Sync>(
+        &mut self,
+        name: &str,
+        instance: Arc<T>,
+    ) {
+        self.register::<T>(name, Arc::new(move |_: &Settings| Ok(instance.clone())));
+    }
+
+    /// Returns the names registered for `T`, sorted
+    pub fn names<T: ?Sized + ExtensionPoint>(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self
+            .factories
+            .keys()
+            .filter(|(type_id, _)| *type_id == TypeId::of::<T>())
+            .map(|(_, name)| name.as_str())
+            .collect();
+        names.sort_unstable();
+        names
+    }
+
+    /// Builds the implementation of `T` registered under `name`
+    pub fn build<T: ?Sized + ExtensionPoint>(
+        &self,
+        name: &str,
+        settings: &Settings,
+    ) -> Result<Arc<T>, CustodyError> {
+        let factory = self
+            .factories
+            .get(&(TypeId::of::<T>(), name.to_string()))
+            .and_then(|factory| factory.downcast_ref::<Factory<T>>())
+            .ok_or_else(|| CustodyError::ExtensionNotFound {
+                kind: T::KIND.to_string(),
+                name: name.to_string(),
+            })?;
+        factory(settings).map_err(|reason| CustodyError::ExtensionFailed {
+            name: name.to_string(),
+            reason,
+        })
+    }
+
+    /// Builds the implementation selected by `spec`
+    pub fn build_spec<T: ?Sized + ExtensionPoint>(
+        &self,
+        spec: &ExtensionSpec,
+    ) -> Result<Arc<T>, CustodyError> {
+        self.build::<T>(&spec.name, &spec.settings)
+    }
+}
+
+impl CustodySystem {
+    /// Builds and installs the extensions selected by `config`
+    ///
+    /// Everything is built before anything is installed, so a bad entry
+    /// leaves the system unchanged.
+    pub fn install_extensions(
+        &mut self,
+        registry: &ExtensionRegistry,
+        config: &ExtensionsConfig,
+    ) -> Result<(), CustodyError> {
+        let listeners = config
+            .event_listeners
+            .iter()
+            .map(|spec| registry.build_spec::<dyn EventListener>(spec))
+            .collect::<Result<Vec<_>, _>>()?;
+        let schemes = config
+            .id_schemes
+            .iter()
+            .map(|(name, spec)| Ok((name, registry.build_spec::<dyn IdScheme>(spec)?)))
+            .collect::<Result<Vec<_>, CustodyError>>()?;
+
+        for listener in listeners {
+            self.subscribe(listener);
+        }
+        for (name, scheme) in schemes {
+            self.register_id_scheme(name, scheme);
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn builtin_asset(symbol: &str) -> Asset {
+    match symbol {
+        "BTC" => Asset::Btc,
+        "ETH" => Asset::Eth,
+        code => Asset::Fiat(code.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CustodyEvent, IdInput, WalletType};
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct Counter(Mutex<usize>);
+
+    impl EventListener for Counter {
+        fn on_event(&self, _: &CustodyEvent) {
+            *self.0.lock().unwrap() += 1;
+        }
+    }
+
+    fn spec(name: &str, settings: &[(&str, &str)]) -> ExtensionSpec {
+        ExtensionSpec {
+            name: name.to_string(),
+            settings: settings
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_builtin_rate_provider_from_settings() {
+        let registry = ExtensionRegistry::with_builtins();
+        let rates = registry
+            .build_spec::<dyn RateProvider>(&spec("static", &[("BTC/USD", "30000")]))
+            .unwrap();
+        assert_eq!(
+            rates.rate(&Asset::Btc, &Asset::Fiat("USD".to_string())),
+            Some(30000.0)
+        );
+        assert!(matches!(
+            registry.build_spec::<dyn RateProvider>(&spec("static", &[("BTC", "1")])),
+            Err(CustodyError::ExtensionFailed { .. })
+        ));
+    }
+
+    #[test]
+    fn test_install_extensions_from_config() {
+        let counter = Arc::new(Counter::default());
+        let mut registry = ExtensionRegistry::with_builtins();
+        registry.register_instance::<dyn EventListener>("counter", counter.clone());
+        assert_eq!(registry.names::<dyn EventListener>(), vec!["counter"]);
+
+        let config = ExtensionsConfig {
+            event_listeners: vec![spec("counter", &[])],
+            id_schemes: BTreeMap::from([(
+                "customer".to_string(),
+                spec("hashed", &[("prefix", "cust")]),
+            )]),
+        };
+        let mut system = CustodySystem::new();
+        system.install_extensions(&registry, &config).unwrap();
+
+        let input = IdInput {
+            customer_id: "c1".to_string(),
+            asset: Asset::Btc,
+            purpose: "deposit".to_string(),
+        };
+        let wallet = system
+            .create_wallet_derived("customer", &input, "bc1".to_string(), WalletType::Hot)
+            .unwrap();
+        assert!(wallet.id.starts_with("cust_"));
+        system.deposit(&wallet.id, amount!(1.0)).unwrap();
+        assert_eq!(*counter.0.lock().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_unknown_extension_installs_nothing() {
+        let counter = Arc::new(Counter::default());
+        let mut registry = ExtensionRegistry::new();
+        registry.register_instance::<dyn EventListener>("counter", counter.clone());
+        let config = ExtensionsConfig {
+            event_listeners: vec![spec("counter", &[]), spec("missing", &[])],
+            ..ExtensionsConfig::default()
+        };
+
+        let mut system = CustodySystem::new();
+        assert_eq!(
+            system.install_extensions(&registry, &config),
+            Err(CustodyError::ExtensionNotFound {
+                kind: "event_listener".to_string(),
+                name: "missing".to_string(),
+            })
+        );
+        system
+            .create_wallet("w".to_string(), "0x1".to_string(), WalletType::Hot)
+            .unwrap();
+        system.deposit("w", amount!(1.0)).unwrap();
+        assert_eq!(*counter.0.lock().unwrap(), 0);
+    }
+}
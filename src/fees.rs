@@ -0,0 +1,320 @@
+//! Network fee estimation for withdrawals.
+//!
+//! A [`FeeEstimator`] set with [`CustodySystem::set_fee_estimator`] is
+//! consulted whenever a withdrawal is prepared for signing: the quoted
+//! [`FeeRate`] goes into the signed payload and the fee it implies for a
+//! typical transaction is kept as the withdrawal's estimate. When a
+//! broadcast withdrawal is booked, the fee actually paid is read from the
+//! chain, and both are recorded on the ledger entry under
+//! [`ESTIMATED_FEE_METADATA_KEY`] and [`ACTUAL_FEE_METADATA_KEY`].
+//!
+//! Fees are recorded, not debited: they are paid in the chain's native
+//! asset and booked by whoever reconciles the hot wallet against the
+//! chain.
+//!
+//! [`StaticFeeEstimator`] quotes fixed rates. The Bitcoin Core client
+//! (feature `bitcoin-rpc`) and the Ethereum client (feature `eth-rpc`)
+//! estimate from the node.
+
+use crate::{Amount, Asset, CustodyError, CustodySystem};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+/// Metadata key of a withdrawal's estimated network fee
+pub const ESTIMATED_FEE_METADATA_KEY: &str = "estimated_fee";
+
+/// Metadata key of the network fee a withdrawal actually paid
+pub const ACTUAL_FEE_METADATA_KEY: &str = "actual_fee";
+
+/// Virtual size of a one-input, two-output P2WPKH Bitcoin transaction
+pub const BTC_WITHDRAWAL_VBYTES: u64 = 141;
+
+/// Gas of a plain ether transfer
+pub const ETH_TRANSFER_GAS: u64 = 21_000;
+
+/// Gas budgeted for an ERC-20 `transfer` call
+pub const ERC20_TRANSFER_GAS: u64 = 65_000;
+
+/// Price of block space
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FeeRate {
+    /// Bitcoin, in satoshis per virtual byte
+    SatPerVbyte(u64),
+    /// Ethereum legacy transactions, in wei per gas
+    GasPrice(u128),
+    /// Ethereum EIP-1559 transactions, in wei per gas
+    Eip1559 {
+        base_fee_per_gas: u128,
+        priority_fee_per_gas: u128,
+    },
+}
+
+impl FeeRate {
+    /// Returns the fee cap to sign: twice the base fee plus the tip, so
+    /// the transaction stays valid through a few full blocks
+    pub fn max_fee_per_gas(&self) -> Option<u128> {
+        match self {
+            FeeRate::SatPerVbyte(_) => None,
+            FeeRate::GasPrice(price) => Some(*price),
+            FeeRate::Eip1559 {
+                base_fee_per_gas,
+                priority_fee_per_gas,
+            } => base_fee_per_gas
+                .checked_mul(2)?
+                .checked_add(*priority_fee_per_gas),
+        }
+    }
+
+    /// Returns the expected fee of a transaction of `units` virtual bytes
+    /// or gas, in the chain's native asset
+    pub fn fee(&self, units: u64) -> Option<Amount> {
+        let (per_unit, decimals) = match self {
+            FeeRate::SatPerVbyte(sats) => (u128::from(*sats), Asset::Btc.decimals()),
+            FeeRate::GasPrice(price) => (*price, Asset::Eth.decimals()),
+            FeeRate::Eip1559 {
+                base_fee_per_gas,
+                priority_fee_per_gas,
+            } => (
+                base_fee_per_gas.checked_add(*priority_fee_per_gas)?,
+                Asset::Eth.decimals(),
+            ),
+        };
+        let total = per_unit.checked_mul(u128::from(units))?;
+        Amount::from_minor_units(i128::try_from(total).ok()?, decimals)
+    }
+}
+
+impl fmt::Display for FeeRate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FeeRate::SatPerVbyte(sats) => write!(f, "{} sat/vB", sats),
+            FeeRate::GasPrice(price) => write!(f, "{} wei/gas", price),
+            FeeRate::Eip1559 {
+                priority_fee_per_gas,
+                ..
+            } => write!(
+                f,
+                "max {} wei/gas, tip {} wei/gas",
+                self.max_fee_per_gas().unwrap_or(u128::MAX),
+                priority_fee_per_gas
+            ),
+        }
+    }
+}
+
+/// A fee quoted for a withdrawal
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FeeEstimate {
+    pub rate: FeeRate,
+    /// Virtual bytes or gas assumed for the transaction
+    pub units: u64,
+    /// Expected fee, in the chain's native asset
+    pub fee: Amount,
+}
+
+/// Quotes network fee rates
+pub trait FeeEstimator: fmt::Debug + Send + Sync {
+    /// Returns the current rate for transactions moving `asset`
+    fn fee_rate(&self, asset: &Asset) -> Result<FeeRate, String>;
+}
+
+/// Returns the size of a typical withdrawal of `asset`, or `None` for
+/// assets that pay no network fee
+pub(crate) fn typical_units(asset: &Asset) -> Option<u64> {
+    match asset {
+        Asset::Btc => Some(BTC_WITHDRAWAL_VBYTES),
+        Asset::Eth => Some(ETH_TRANSFER_GAS),
+        Asset::Erc20 { .. } => Some(ERC20_TRANSFER_GAS),
+        Asset::Fiat(_) | Asset::Custom { .. } => None,
+    }
+}
+
+/// Quotes fixed rates, e.g. for tests or as a fallback
+#[derive(Debug, Clone, Default)]
+pub struct StaticFeeEstimator {
+    rates: HashMap<Asset, FeeRate>,
+}
+
+impl StaticFeeEstimator {
+    /// Creates an estimator without rates
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Quotes `rate` for `asset`; ERC-20 tokens fall back to the rate of
+    /// ETH
+    pub fn with_rate(mut self, asset: Asset, rate: FeeRate) -> Self {
+        self.rates.insert(asset, rate);
+        self
+    }
+}
+
+impl FeeEstimator for StaticFeeEstimator {
+    fn fee_rate(&self, asset: &Asset) -> Result<FeeRate, String> {
+        self.rates
+            .get(asset)
+            .or_else(|| match asset {
+                Asset::Erc20 { .. } => self.rates.get(&Asset::Eth),
+                _ => None,
+            })
+            .copied()
+            .ok_or_else(|| format!("no fee rate for {}", asset))
+    }
+}
+
+impl CustodySystem {
+    /// Quotes withdrawal fees with `estimator` from now on
+    pub fn set_fee_estimator(&mut self, estimator: Arc<dyn FeeEstimator>) {
+        self.fee_estimator = Some(estimator);
+    }
+
+    /// Stops quoting withdrawal fees
+    pub fn clear_fee_estimator(&mut self) {
+        self.fee_estimator = None;
+    }
+
+    /// Quotes the network fee of withdrawing `asset`
+    ///
+    /// # Returns
+    /// `None` without an estimator or for assets that pay no network fee
+    ///
+    /// # Errors
+    /// [`CustodyError::FeeUnavailable`] if the estimator has no rate
+    ///
+    /// # Example
+    /// ```
+    /// use securevault::{amount, Asset, CustodySystem, FeeRate, StaticFeeEstimator};
+    /// use std::sync::Arc;
+    /// let mut system = CustodySystem::new();
+    /// let rates = StaticFeeEstimator::new().with_rate(Asset::Btc, FeeRate::SatPerVbyte(10));
+    /// system.set_fee_estimator(Arc::new(rates));
+    ///
+    /// let estimate = system.estimate_fee(&Asset::Btc).unwrap().unwrap();
+    /// assert_eq!(estimate.fee, amount!(0.0000141));
+    /// assert!(system.estimate_fee(&Asset::Eth).is_err());
+    /// ```
+    pub fn estimate_fee(&self, asset: &Asset) -> Result<Option<FeeEstimate>, CustodyError> {
+        let (Some(estimator), Some(units)) = (&self.fee_estimator, typical_units(asset)) else {
+            return Ok(None);
+        };
+        let rate = estimator
+            .fee_rate(asset)
+            .map_err(CustodyError::FeeUnavailable)?;
+        let fee = rate.fee(units).ok_or(CustodyError::AmountOverflow)?;
+        Ok(Some(FeeEstimate { rate, units, fee }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Broadcaster, CallbackSigner, TxHash, WalletType};
+
+    /// Mines everything at once, for a fixed fee
+    #[derive(Debug)]
+    struct MinedChain;
+
+    impl Broadcaster for MinedChain {
+        fn broadcast(&self, _raw_tx: &[u8]) -> Result<TxHash, String> {
+            Ok("tx1".to_string())
+        }
+
+        fn confirmations(&self, _tx_hash: &str) -> Result<u32, String> {
+            Ok(6)
+        }
+
+        fn fee_paid(&self, _tx_hash: &str) -> Result<Option<Amount>, String> {
+            Ok(Some(amount!(0.00001)))
+        }
+    }
+
+    fn usdc() -> Asset {
+        Asset::Erc20 {
+            symbol: "USDC".to_string(),
+            contract: "0xa0b8".to_string(),
+            decimals: 6,
+        }
+    }
+
+    #[test]
+    fn test_fee_rates() {
+        let rate = FeeRate::Eip1559 {
+            base_fee_per_gas: 30_000_000_000,
+            priority_fee_per_gas: 2_000_000_000,
+        };
+        assert_eq!(rate.max_fee_per_gas(), Some(62_000_000_000));
+        assert_eq!(rate.fee(ETH_TRANSFER_GAS), Some(amount!(0.000672)));
+        assert_eq!(
+            rate.to_string(),
+            "max 62000000000 wei/gas, tip 2000000000 wei/gas"
+        );
+        assert_eq!(FeeRate::SatPerVbyte(3).max_fee_per_gas(), None);
+        assert_eq!(FeeRate::GasPrice(u128::MAX).fee(2), None);
+    }
+
+    #[test]
+    fn test_withdrawals_carry_fee_estimates() {
+        let mut system = CustodySystem::new();
+        system
+            .create_wallet_with_asset(
+                "usdc".to_string(),
+                "0x1".to_string(),
+                WalletType::Hot,
+                usdc(),
+            )
+            .unwrap();
+        system.deposit("usdc", amount!(100)).unwrap();
+        let tx = system
+            .prepare_withdrawal("usdc", "0xdest", amount!(10))
+            .unwrap();
+        assert_eq!(tx.fee, None);
+
+        let rate = FeeRate::GasPrice(20_000_000_000);
+        let rates = StaticFeeEstimator::new().with_rate(Asset::Eth, rate);
+        system.set_fee_estimator(Arc::new(rates));
+        let tx = system
+            .prepare_withdrawal("usdc", "0xdest", amount!(10))
+            .unwrap();
+        let fee = tx.fee.clone().unwrap();
+        assert_eq!((fee.units, fee.fee), (ERC20_TRANSFER_GAS, amount!(0.0013)));
+        let payload = String::from_utf8(tx.payload()).unwrap();
+        assert!(payload.ends_with("fee_rate=20000000000 wei/gas\n"));
+
+        system.set_fee_estimator(Arc::new(StaticFeeEstimator::new()));
+        assert_eq!(
+            system.prepare_withdrawal("usdc", "0xdest", amount!(10)),
+            Err(CustodyError::FeeUnavailable(
+                "no fee rate for USDC".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_booked_withdrawals_record_fees() {
+        let mut system = CustodySystem::new();
+        system
+            .create_wallet("w".to_string(), "bc1qw".to_string(), WalletType::Hot)
+            .unwrap();
+        system.deposit("w", amount!(1)).unwrap();
+        let rates = StaticFeeEstimator::new().with_rate(Asset::Btc, FeeRate::SatPerVbyte(10));
+        system.set_fee_estimator(Arc::new(rates));
+        let signer = CallbackSigner::new("k", |payload| Ok(payload.to_vec()));
+        let sent = system
+            .broadcast_withdrawal("w", "bc1qdest", amount!(0.5), &signer, &MinedChain)
+            .unwrap();
+        assert_eq!(sent.estimated_fee, Some(amount!(0.0000141)));
+
+        system.track_broadcasts(&MinedChain).unwrap();
+        let booked = system.get_broadcast(sent.id).unwrap();
+        assert_eq!(booked.actual_fee, Some(amount!(0.00001)));
+        let tx = system
+            .get_transaction(booked.transaction_id.unwrap())
+            .unwrap();
+        assert_eq!(tx.metadata[ESTIMATED_FEE_METADATA_KEY], "0.0000141");
+        assert_eq!(tx.metadata[ACTUAL_FEE_METADATA_KEY], "0.00001");
+        // Fees are recorded, not debited
+        assert_eq!(system.get_wallet("w").unwrap().balance, amount!(0.5));
+    }
+}
@@ -0,0 +1,257 @@
+//! Fiat on/off-ramp.
+//!
+//! A [`FiatGateway`] connects the ledger to a bank or payment processor.
+//! Fiat legs of customer flows are booked as ordinary transactions on
+//! wallets denominated in a fiat asset, tagged with the gateway's reference,
+//! so the ledger captures the full lifecycle from incoming wire to payout.
+
+use crate::precheck::Authorization;
+use crate::{Amount, Asset, CustodyError, CustodySystem, TransactionType};
+
+/// A request to pay fiat out to a bank account
+#[derive(Debug, Clone, PartialEq)]
+pub struct PayoutRequest {
+    pub wallet_id: String,
+    pub amount: Amount,
+    pub currency: Asset,
+    /// Beneficiary bank account identifier (IBAN, account number, ...)
+    pub beneficiary: String,
+}
+
+/// An incoming wire as confirmed by the gateway
+#[derive(Debug, Clone, PartialEq)]
+pub struct IncomingWire {
+    pub reference: String,
+    pub amount: Amount,
+    pub currency: Asset,
+    /// Originating bank account, as reported by the bank
+    pub sender: String,
+}
+
+/// Connection to a bank or payment processor
+pub trait FiatGateway {
+    /// Initiates a bank payout, returning the gateway's payout reference
+    fn initiate_payout(&self, request: &PayoutRequest) -> Result<String, String>;
+
+    /// Looks up a received wire by reference, failing if it has not
+    /// cleared
+    fn confirm_wire(&self, reference: &str) -> Result<IncomingWire, String>;
+}
+
+impl CustodySystem {
+    /// Pays out fiat from a fiat wallet to a bank account
+    ///
+    /// The withdrawal is checked like any other before the gateway is
+    /// called, and booked only if the gateway accepts the payout. Bank
+    /// beneficiaries are screened by the gateway, so payouts bypass the
+    /// [`ComplianceScreener`](crate::ComplianceScreener).
+    ///
+    /// # Returns
+    /// The gateway's payout reference, also stored on the transaction
+    pub fn fiat_payout(
+        &mut self,
+        wallet_id: &str,
+        amount: Amount,
+        beneficiary: &str,
+        gateway: &dyn FiatGateway,
+    ) -> Result<String, CustodyError> {
+        let currency = self.fiat_currency(wallet_id)?;
+        if let Some(reason) = self
+            .withdrawal_blockers(
+                wallet_id,
+                None,
+                amount,
+                Some(beneficiary),
+                Authorization::Direct,
+            )
+            .into_iter()
+            .next()
+        {
+            return Err(reason);
+        }
+
+        let request = PayoutRequest {
+            wallet_id: wallet_id.to_string(),
+            amount,
+            currency,
+            beneficiary: beneficiary.to_string(),
+        };
+        let reference = gateway
+            .initiate_payout(&request)
+            .map_err(CustodyError::GatewayError)?;
+        self.settle_withdrawal(wallet_id, self.wallets[wallet_id].asset.clone(), amount)?;
+        self.reference_last(&reference)?;
+        Ok(reference)
+    }
+
+    /// Credits a cleared incoming wire to a fiat wallet
+    ///
+    /// Each wire reference can be credited only once.
+    pub fn credit_incoming_wire(
+        &mut self,
+        wallet_id: &str,
+        reference: &str,
+        gateway: &dyn FiatGateway,
+    ) -> Result<IncomingWire, CustodyError> {
+        let currency = self.fiat_currency(wallet_id)?;
+        let already_credited = self.transactions.iter().any(|tx| {
+            tx.transaction_type == TransactionType::Deposit
+                && tx.reference.as_deref() == Some(reference)
+        });
+        if already_credited {
+            return Err(CustodyError::DuplicateReference(reference.to_string()));
+        }
+
+        let wire = gateway
+            .confirm_wire(reference)
+            .map_err(CustodyError::GatewayError)?;
+        if wire.currency != currency {
+            return Err(CustodyError::AssetMismatch {
+                expected: currency.symbol().to_string(),
+                found: wire.currency.symbol().to_string(),
+            });
+        }
+        self.deposit(wallet_id, wire.amount)?;
+        self.reference_last(reference)?;
+        Ok(wire)
+    }
+
+    fn fiat_currency(&self, wallet_id: &str) -> Result<Asset, CustodyError> {
+        let wallet = self
+            .get_wallet(wallet_id)
+            .ok_or_else(|| CustodyError::WalletNotFound(wallet_id.to_string()))?;
+        if !wallet.asset.is_fiat() {
+            return Err(CustodyError::NotFiatWallet(wallet_id.to_string()));
+        }
+        Ok(wallet.asset.clone())
+    }
+
+    fn reference_last(&mut self, reference: &str) -> Result<(), CustodyError> {
+        self.update_last_transaction(|tx| tx.reference = Some(reference.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::WalletType;
+    use std::cell::RefCell;
+
+    #[derive(Default)]
+    struct FakeBank {
+        payouts: RefCell<Vec<PayoutRequest>>,
+        reject_payouts: bool,
+    }
+
+    impl FiatGateway for FakeBank {
+        fn initiate_payout(&self, request: &PayoutRequest) -> Result<String, String> {
+            if self.reject_payouts {
+                return Err("beneficiary bank unreachable".to_string());
+            }
+            let mut payouts = self.payouts.borrow_mut();
+            payouts.push(request.clone());
+            Ok(format!("PO-{}", payouts.len()))
+        }
+
+        fn confirm_wire(&self, reference: &str) -> Result<IncomingWire, String> {
+            match reference {
+                "WIRE-1" => Ok(IncomingWire {
+                    reference: reference.to_string(),
+                    amount: amount!(1000.0),
+                    currency: usd(),
+                    sender: "DE89370400440532013000".to_string(),
+                }),
+                _ => Err("wire not cleared".to_string()),
+            }
+        }
+    }
+
+    fn usd() -> Asset {
+        Asset::Fiat("USD".to_string())
+    }
+
+    fn system() -> CustodySystem {
+        let mut system = CustodySystem::new();
+        system
+            .create_wallet_with_asset(
+                "usd".to_string(),
+                "acct".to_string(),
+                WalletType::Hot,
+                usd(),
+            )
+            .unwrap();
+        system
+            .create_wallet("btc".to_string(), "bc1".to_string(), WalletType::Hot)
+            .unwrap();
+        system
+    }
+
+    #[test]
+    fn test_wire_in_and_payout_out() {
+        let mut system = system();
+        let bank = FakeBank::default();
+
+        let wire = system.credit_incoming_wire("usd", "WIRE-1", &bank).unwrap();
+        assert_eq!(wire.amount, amount!(1000.0));
+        let reference = system
+            .fiat_payout("usd", amount!(400.0), "GB29NWBK", &bank)
+            .unwrap();
+        assert_eq!(reference, "PO-1");
+        assert_eq!(bank.payouts.borrow()[0].currency, usd());
+
+        assert_eq!(system.get_wallet("usd").unwrap().balance, amount!(600.0));
+        let references: Vec<_> = system
+            .get_wallet_transactions("usd")
+            .iter()
+            .map(|tx| tx.reference.clone().unwrap())
+            .collect();
+        assert_eq!(references, vec!["WIRE-1", "PO-1"]);
+    }
+
+    #[test]
+    fn test_wire_credited_once() {
+        let mut system = system();
+        let bank = FakeBank::default();
+        system.credit_incoming_wire("usd", "WIRE-1", &bank).unwrap();
+        assert_eq!(
+            system.credit_incoming_wire("usd", "WIRE-1", &bank),
+            Err(CustodyError::DuplicateReference("WIRE-1".to_string()))
+        );
+        assert!(matches!(
+            system.credit_incoming_wire("usd", "WIRE-2", &bank),
+            Err(CustodyError::GatewayError(_))
+        ));
+    }
+
+    #[test]
+    fn test_payout_checks_before_calling_gateway() {
+        let mut system = system();
+        let bank = FakeBank::default();
+        assert!(matches!(
+            system.fiat_payout("usd", amount!(1.0), "GB29NWBK", &bank),
+            Err(CustodyError::InsufficientBalance { .. })
+        ));
+        assert_eq!(
+            system.fiat_payout("btc", amount!(1.0), "GB29NWBK", &bank),
+            Err(CustodyError::NotFiatWallet("btc".to_string()))
+        );
+        assert!(bank.payouts.borrow().is_empty());
+    }
+
+    #[test]
+    fn test_rejected_payout_is_not_booked() {
+        let mut system = system();
+        system
+            .credit_incoming_wire("usd", "WIRE-1", &FakeBank::default())
+            .unwrap();
+        let bank = FakeBank {
+            reject_payouts: true,
+            ..FakeBank::default()
+        };
+        assert!(matches!(
+            system.fiat_payout("usd", amount!(100.0), "GB29NWBK", &bank),
+            Err(CustodyError::GatewayError(_))
+        ));
+        assert_eq!(system.get_wallet("usd").unwrap().balance, amount!(1000.0));
+    }
+}
@@ -0,0 +1,208 @@
+//! Display helpers for amounts.
+//!
+//! All user-facing rendering (CLI, terminal views, statements) should go
+//! through [`AmountFormatter`] so amounts are presented the same way
+//! everywhere: the asset's native precision, grouped thousands, and the
+//! symbol placed according to the asset's convention.
+
+use crate::asset::Asset;
+use crate::i18n::Locale;
+use crate::Amount;
+
+/// Where the asset symbol is written relative to the number
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SymbolPosition {
+    /// Currency sign before fiat amounts (`$1.00`), ticker after crypto
+    /// amounts (`1.00000000 BTC`)
+    #[default]
+    Conventional,
+    /// Ticker before the number (`BTC 1.00000000`)
+    Prefix,
+    /// Ticker after the number (`1.00000000 BTC`)
+    Suffix,
+    /// Number only
+    Hidden,
+}
+
+/// Formats amounts according to asset and locale conventions
+#[derive(Debug, Clone, PartialEq)]
+pub struct AmountFormatter {
+    /// Separator between groups of thousands, or `None` for no grouping
+    pub thousands_separator: Option<char>,
+    /// Separator between the integer and fractional parts
+    pub decimal_separator: char,
+    /// Placement of the asset symbol
+    pub symbol_position: SymbolPosition,
+    /// Overrides the asset's native number of decimal places
+    pub decimals: Option<usize>,
+}
+
+impl Default for AmountFormatter {
+    fn default() -> Self {
+        Self::for_locale(Locale::En)
+    }
+}
+
+impl AmountFormatter {
+    /// Creates a formatter using the separators customary in `locale`
+    pub fn for_locale(locale: Locale) -> Self {
+        let (thousands, decimal) = match locale {
+            Locale::En => (',', '.'),
+            Locale::PtBr | Locale::Es => ('.', ','),
+        };
+        Self {
+            thousands_separator: Some(thousands),
+            decimal_separator: decimal,
+            symbol_position: SymbolPosition::Conventional,
+            decimals: None,
+        }
+    }
+
+    /// Formats the number only, without any symbol
+    pub fn format_number(&self, amount: Amount, asset: &Asset) -> String {
+        let decimals = self.decimals.unwrap_or(asset.decimals() as usize);
+        let fixed = format!("{:.*}", decimals, amount.abs());
+        let (integer, fraction) = match fixed.split_once('.') {
+            Some((i, f)) => (i, Some(f)),
+            None => (fixed.as_str(), None),
+        };
+
+        let mut out = String::new();
+        if amount.is_negative() && fixed.chars().any(|c| c.is_ascii_digit() && c != '0') {
+            out.push('-');
+        }
+        out.push_str(&group_thousands(integer, self.thousands_separator));
+        if let Some(fraction) = fraction {
+            out.push(self.decimal_separator);
+            out.push_str(fraction);
+        }
+        out
+    }
+
+    /// Formats an amount with the asset symbol
+    pub fn format(&self, amount: Amount, asset: &Asset) -> String {
+        let number = self.format_number(amount, asset);
+        match self.symbol_position {
+            SymbolPosition::Conventional => match asset.currency_sign() {
+                Some(sign) => match number.strip_prefix('-') {
+                    Some(unsigned) => format!("-{}{}", sign, unsigned),
+                    None => format!("{}{}", sign, number),
+                },
+                None => format!("{} {}", number, asset.symbol()),
+            },
+            SymbolPosition::Prefix => format!("{} {}", asset.symbol(), number),
+            SymbolPosition::Suffix => format!("{} {}", number, asset.symbol()),
+            SymbolPosition::Hidden => number,
+        }
+    }
+
+    /// Formats an amount followed by its fiat equivalent, e.g.
+    /// `0.50000000 BTC (≈ $15,000.00)`
+    pub fn format_with_fiat(
+        &self,
+        amount: Amount,
+        asset: &Asset,
+        fiat_amount: Amount,
+        fiat: &Asset,
+    ) -> String {
+        let fiat_formatter = Self {
+            decimals: None,
+            ..self.clone()
+        };
+        format!(
+            "{} (≈ {})",
+            self.format(amount, asset),
+            fiat_formatter.format(fiat_amount, fiat)
+        )
+    }
+}
+
+/// Formats an amount with the default (English) conventions
+pub fn format_amount(amount: Amount, asset: &Asset) -> String {
+    AmountFormatter::default().format(amount, asset)
+}
+
+fn group_thousands(digits: &str, separator: Option<char>) -> String {
+    let separator = match separator {
+        Some(separator) => separator,
+        None => return digits.to_string(),
+    };
+    let mut out = String::with_capacity(digits.len() + digits.len() / 3);
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            out.push(separator);
+        }
+        out.push(c);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn usd() -> Asset {
+        Asset::Fiat("USD".to_string())
+    }
+
+    #[test]
+    fn test_format_btc_uses_eight_decimals() {
+        assert_eq!(format_amount(amount!(1.5), &Asset::Btc), "1.50000000 BTC");
+        assert_eq!(
+            format_amount(amount!(1234567.0), &Asset::Btc),
+            "1,234,567.00000000 BTC"
+        );
+    }
+
+    #[test]
+    fn test_format_fiat_places_sign_first() {
+        assert_eq!(format_amount(amount!(1234.5), &usd()), "$1,234.50");
+        assert_eq!(format_amount(amount!(-20.0), &usd()), "-$20.00");
+    }
+
+    #[test]
+    fn test_format_for_locale() {
+        let formatter = AmountFormatter::for_locale(Locale::PtBr);
+        assert_eq!(
+            formatter.format(amount!(1234.5), &Asset::Fiat("BRL".to_string())),
+            "R$1.234,50"
+        );
+        assert_eq!(
+            formatter.format(amount!(0.25), &Asset::Btc),
+            "0,25000000 BTC"
+        );
+    }
+
+    #[test]
+    fn test_format_symbol_positions() {
+        let mut formatter = AmountFormatter {
+            decimals: Some(2),
+            ..AmountFormatter::default()
+        };
+        formatter.symbol_position = SymbolPosition::Prefix;
+        assert_eq!(formatter.format(amount!(3.0), &Asset::Eth), "ETH 3.00");
+        formatter.symbol_position = SymbolPosition::Hidden;
+        assert_eq!(formatter.format(amount!(3.0), &Asset::Eth), "3.00");
+    }
+
+    #[test]
+    fn test_format_with_fiat() {
+        let formatter = AmountFormatter::default();
+        assert_eq!(
+            formatter.format_with_fiat(amount!(0.5), &Asset::Btc, amount!(15000), &usd()),
+            "0.50000000 BTC (≈ $15,000.00)"
+        );
+    }
+
+    #[test]
+    fn test_negative_zero_has_no_sign() {
+        let formatter = AmountFormatter {
+            decimals: Some(2),
+            ..AmountFormatter::default()
+        };
+        assert_eq!(
+            formatter.format_number(amount!(-0.001), &Asset::Btc),
+            "0.00"
+        );
+    }
+}
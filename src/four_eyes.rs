@@ -0,0 +1,584 @@
+//! Two-person rule for destructive admin actions.
+//!
+//! Once a [`FourEyesRule`] is in force, closing or freezing a wallet,
+//! changing a withdrawal policy (a wallet's, a type's default or a
+//! tenant's), removing a whitelisted address, changing rate limits, making
+//! a wallet joint, and withdrawing more than the rule's threshold can no
+//! longer be done by one operator: the direct calls fail with
+//! [`CustodyError::DualControlRequired`]. Instead one operator
+//! proposes the [`AdminAction`] with [`CustodySystem::propose_action`] and
+//! a different one carries it out with [`CustodySystem::confirm_action`].
+//! Proposals left unconfirmed past the rule's expiry lapse.
+//!
+//! Withdrawals released by an approval workflow (cold wallet approvers,
+//! joint owners, multisig signers) already had several people involved and
+//! are not held back by the threshold. The rule itself can be put in place
+//! directly, but changing or lifting it takes two operators as well.
+
+use crate::precheck::Authorization;
+use crate::time::Timestamp;
+use crate::whitelist::WhitelistOwner;
+use crate::{
+    Amount, CustodyError, CustodySystem, OperationStatus, RateLimit, WalletType, WithdrawalPolicy,
+};
+use serde::{Deserialize, Serialize};
+
+/// Default time a proposed action waits for confirmation
+pub const DEFAULT_ACTION_EXPIRY_SECS: u64 = 24 * 60 * 60;
+
+/// Which actions need a second operator
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FourEyesRule {
+    /// Seconds a proposal stays open for confirmation
+    pub expiry_secs: u64,
+    /// Withdrawals above this amount need confirmation; `None` leaves
+    /// withdrawals to the usual checks
+    pub withdrawal_threshold: Option<Amount>,
+}
+
+impl Default for FourEyesRule {
+    fn default() -> Self {
+        Self {
+            expiry_secs: DEFAULT_ACTION_EXPIRY_SECS,
+            withdrawal_threshold: None,
+        }
+    }
+}
+
+/// An admin action that needs a second operator under the two-person rule
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum AdminAction {
+    CloseWallet {
+        wallet_id: String,
+        reason: String,
+    },
+    FreezeWallet {
+        wallet_id: String,
+        reason: String,
+    },
+    /// Sets a wallet's own policy, or clears it with `None`
+    SetWithdrawalPolicy {
+        wallet_id: String,
+        policy: Option<WithdrawalPolicy>,
+    },
+    /// Sets the policy of wallets of a type without one of their own
+    SetDefaultWithdrawalPolicy {
+        wallet_type: WalletType,
+        policy: WithdrawalPolicy,
+    },
+    SetTenantWithdrawalPolicy {
+        tenant_id: String,
+        wallet_type: WalletType,
+        policy: WithdrawalPolicy,
+    },
+    RemoveWhitelistedAddress {
+        wallet_id: String,
+        address: String,
+    },
+    RemoveAccountWhitelistedAddress {
+        account_id: String,
+        address: String,
+    },
+    /// Sets the per-wallet rate limit, or lifts it with `None`
+    SetWalletRateLimit(Option<RateLimit>),
+    /// Sets the per-operator rate limit, or lifts it with `None`
+    SetOperatorRateLimit(Option<RateLimit>),
+    Withdrawal {
+        wallet_id: String,
+        amount: Amount,
+        destination: Option<String>,
+    },
+    MakeJointWallet {
+        wallet_id: String,
+        owners: Vec<String>,
+        required: usize,
+    },
+    /// Changes the rule, or lifts it with `None`
+    SetFourEyesRule(Option<FourEyesRule>),
+}
+
+impl AdminAction {
+    pub const CLOSE_WALLET: &'static str = "close_wallet";
+    pub const FREEZE_WALLET: &'static str = "freeze_wallet";
+    pub const SET_WITHDRAWAL_POLICY: &'static str = "set_withdrawal_policy";
+    pub const SET_DEFAULT_WITHDRAWAL_POLICY: &'static str = "set_default_withdrawal_policy";
+    pub const SET_TENANT_WITHDRAWAL_POLICY: &'static str = "set_tenant_withdrawal_policy";
+    pub const REMOVE_WHITELISTED_ADDRESS: &'static str = "remove_whitelisted_address";
+    pub const REMOVE_ACCOUNT_WHITELISTED_ADDRESS: &'static str =
+        "remove_account_whitelisted_address";
+    pub const SET_WALLET_RATE_LIMIT: &'static str = "set_wallet_rate_limit";
+    pub const SET_OPERATOR_RATE_LIMIT: &'static str = "set_operator_rate_limit";
+    pub const WITHDRAWAL: &'static str = "withdrawal";
+    pub const MAKE_JOINT_WALLET: &'static str = "make_joint_wallet";
+    pub const SET_FOUR_EYES_RULE: &'static str = "set_four_eyes_rule";
+
+    /// Returns a stable snake_case name for the action
+    pub fn name(&self) -> &'static str {
+        match self {
+            AdminAction::CloseWallet { .. } => Self::CLOSE_WALLET,
+            AdminAction::FreezeWallet { .. } => Self::FREEZE_WALLET,
+            AdminAction::SetWithdrawalPolicy { .. } => Self::SET_WITHDRAWAL_POLICY,
+            AdminAction::SetDefaultWithdrawalPolicy { .. } => Self::SET_DEFAULT_WITHDRAWAL_POLICY,
+            AdminAction::SetTenantWithdrawalPolicy { .. } => Self::SET_TENANT_WITHDRAWAL_POLICY,
+            AdminAction::RemoveWhitelistedAddress { .. } => Self::REMOVE_WHITELISTED_ADDRESS,
+            AdminAction::RemoveAccountWhitelistedAddress { .. } => {
+                Self::REMOVE_ACCOUNT_WHITELISTED_ADDRESS
+            }
+            AdminAction::SetWalletRateLimit(_) => Self::SET_WALLET_RATE_LIMIT,
+            AdminAction::SetOperatorRateLimit(_) => Self::SET_OPERATOR_RATE_LIMIT,
+            AdminAction::Withdrawal { .. } => Self::WITHDRAWAL,
+            AdminAction::MakeJointWallet { .. } => Self::MAKE_JOINT_WALLET,
+            AdminAction::SetFourEyesRule(_) => Self::SET_FOUR_EYES_RULE,
+        }
+    }
+
+    /// Returns the wallet the action applies to, if any
+    pub fn wallet_id(&self) -> Option<&str> {
+        match self {
+            AdminAction::CloseWallet { wallet_id, .. }
+            | AdminAction::FreezeWallet { wallet_id, .. }
+            | AdminAction::SetWithdrawalPolicy { wallet_id, .. }
+            | AdminAction::RemoveWhitelistedAddress { wallet_id, .. }
+            | AdminAction::Withdrawal { wallet_id, .. }
+            | AdminAction::MakeJointWallet { wallet_id, .. } => Some(wallet_id),
+            AdminAction::SetDefaultWithdrawalPolicy { .. }
+            | AdminAction::SetTenantWithdrawalPolicy { .. }
+            | AdminAction::RemoveAccountWhitelistedAddress { .. }
+            | AdminAction::SetWalletRateLimit(_)
+            | AdminAction::SetOperatorRateLimit(_)
+            | AdminAction::SetFourEyesRule(_) => None,
+        }
+    }
+}
+
+/// An admin action awaiting a second operator
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProposedAction {
+    pub id: u64,
+    pub action: AdminAction,
+    pub proposer: String,
+    pub proposed_at: Timestamp,
+    /// Confirmations after this time are refused
+    pub expires_at: Timestamp,
+    /// Operator who confirmed the action, once executed
+    pub confirmed_by: Option<String>,
+    pub status: OperationStatus,
+}
+
+impl CustodySystem {
+    /// Puts the two-person rule in force
+    ///
+    /// Fails with [`CustodyError::DualControlRequired`] if a rule is already
+    /// in force; propose [`AdminAction::SetFourEyesRule`] to change it.
+    pub fn set_four_eyes_rule(&mut self, rule: FourEyesRule) -> Result<(), CustodyError> {
+        self.require_second_operator(AdminAction::SET_FOUR_EYES_RULE)?;
+        self.four_eyes = Some(rule);
+        Ok(())
+    }
+
+    /// Gets the two-person rule in force, if any
+    pub fn four_eyes_rule(&self) -> Option<&FourEyesRule> {
+        self.four_eyes.as_ref()
+    }
+
+    /// Proposes an admin action on behalf of `operator`
+    ///
+    /// The action is checked now and again when confirmed; a withdrawal
+    /// that could not go through is refused right away.
+    ///
+    /// # Returns
+    /// The id a second operator confirms with
+    /// [`confirm_action`](Self::confirm_action)
+    ///
+    /// # Example
+    /// ```
+    /// use securevault::{AdminAction, CustodyError, CustodySystem, FourEyesRule, WalletType};
+    /// let mut system = CustodySystem::new();
+    /// system.create_wallet("w".to_string(), "0x1".to_string(), WalletType::Hot).unwrap();
+    /// system.set_four_eyes_rule(FourEyesRule::default()).unwrap();
+    /// assert_eq!(
+    ///     system.freeze_wallet("w", "suspected compromise"),
+    ///     Err(CustodyError::DualControlRequired("freeze_wallet".to_string()))
+    /// );
+    ///
+    /// let action = AdminAction::FreezeWallet {
+    ///     wallet_id: "w".to_string(),
+    ///     reason: "suspected compromise".to_string(),
+    /// };
+    /// let id = system.propose_action("alice", action).unwrap();
+    /// system.confirm_action(id, "bob").unwrap();
+    /// assert!(system.withdraw("w", securevault::amount!(1)).is_err());
+    /// ```
+    pub fn propose_action(
+        &mut self,
+        operator: &str,
+        action: AdminAction,
+    ) -> Result<u64, CustodyError> {
+        self.check_action(&action)?;
+        let expiry_secs = self
+            .four_eyes
+            .map_or(DEFAULT_ACTION_EXPIRY_SECS, |rule| rule.expiry_secs);
+        let proposed_at = self.current_timestamp();
+        let id = self.allocate_operation_id();
+        self.admin_actions.insert(
+            id,
+            ProposedAction {
+                id,
+                action,
+                proposer: operator.to_string(),
+                proposed_at,
+                expires_at: Timestamp::from_unix(proposed_at.as_unix().saturating_add(expiry_secs)),
+                confirmed_by: None,
+                status: OperationStatus::Pending,
+            },
+        );
+        Ok(id)
+    }
+
+    /// Confirms a proposed action as `second_operator` and executes it
+    ///
+    /// The proposer cannot confirm their own action. A proposal past its
+    /// expiry is marked expired and refused with
+    /// [`CustodyError::ActionExpired`]. If the action fails, it stays
+    /// pending and the error is returned.
+    pub fn confirm_action(
+        &mut self,
+        action_id: u64,
+        second_operator: &str,
+    ) -> Result<OperationStatus, CustodyError> {
+        let proposal = self
+            .admin_actions
+            .get(&action_id)
+            .ok_or(CustodyError::OperationNotFound(action_id))?;
+        if proposal.status != OperationStatus::Pending {
+            return Err(CustodyError::OperationNotPending(action_id));
+        }
+        if proposal.proposer == second_operator {
+            return Err(CustodyError::SelfApproval(action_id));
+        }
+        if self.current_timestamp() > proposal.expires_at {
+            self.close_action(action_id, OperationStatus::Expired, None);
+            return Err(CustodyError::ActionExpired(action_id));
+        }
+
+        let action = proposal.action.clone();
+        self.check_action(&action)?;
+        match action {
+            AdminAction::CloseWallet { wallet_id, reason } => {
+                self.close(&wallet_id, &reason)?;
+            }
+            AdminAction::FreezeWallet { wallet_id, reason } => self.freeze(&wallet_id, &reason)?,
+            AdminAction::SetWithdrawalPolicy { wallet_id, policy } => {
+                self.replace_withdrawal_policy(&wallet_id, policy)?;
+            }
+            AdminAction::SetDefaultWithdrawalPolicy {
+                wallet_type,
+                policy,
+            } => self.replace_default_withdrawal_policy(wallet_type, policy),
+            AdminAction::SetTenantWithdrawalPolicy {
+                tenant_id,
+                wallet_type,
+                policy,
+            } => self.replace_tenant_withdrawal_policy(&tenant_id, wallet_type, policy)?,
+            AdminAction::RemoveWhitelistedAddress { wallet_id, address } => {
+                self.remove_from_whitelist(WhitelistOwner::Wallet(wallet_id), &address);
+            }
+            AdminAction::RemoveAccountWhitelistedAddress {
+                account_id,
+                address,
+            } => {
+                self.remove_from_whitelist(WhitelistOwner::Account(account_id), &address);
+            }
+            AdminAction::SetWalletRateLimit(limit) => self.replace_wallet_rate_limit(limit)?,
+            AdminAction::SetOperatorRateLimit(limit) => self.replace_operator_rate_limit(limit)?,
+            AdminAction::Withdrawal {
+                wallet_id,
+                amount,
+                destination,
+            } => self.execute_withdrawal(
+                &wallet_id,
+                None,
+                amount,
+                destination.as_deref(),
+                Authorization::Confirmed,
+            )?,
+            AdminAction::MakeJointWallet {
+                wallet_id,
+                owners,
+                required,
+            } => {
+                let owners: Vec<&str> = owners.iter().map(String::as_str).collect();
+                self.make_joint(&wallet_id, &owners, required)?;
+            }
+            AdminAction::SetFourEyesRule(rule) => self.four_eyes = rule,
+        }
+        self.close_action(
+            action_id,
+            OperationStatus::Executed,
+            Some(second_operator.to_string()),
+        );
+        Ok(OperationStatus::Executed)
+    }
+
+    /// Gets a proposed action by id
+    pub fn get_proposed_action(&self, action_id: u64) -> Option<&ProposedAction> {
+        self.admin_actions.get(&action_id)
+    }
+
+    /// Lists proposals still awaiting confirmation, oldest first
+    pub fn pending_actions(&self) -> Vec<&ProposedAction> {
+        self.admin_actions
+            .values()
+            .filter(|proposal| proposal.status == OperationStatus::Pending)
+            .collect()
+    }
+
+    /// Marks every pending proposal past its expiry as expired
+    ///
+    /// # Returns
+    /// The ids of the proposals that lapsed
+    pub fn expire_actions(&mut self) -> Vec<u64> {
+        let now = self.current_timestamp();
+        let expired: Vec<u64> = self
+            .pending_actions()
+            .into_iter()
+            .filter(|proposal| now > proposal.expires_at)
+            .map(|proposal| proposal.id)
+            .collect();
+        for &id in &expired {
+            self.close_action(id, OperationStatus::Expired, None);
+        }
+        expired
+    }
+
+    /// Fails with [`CustodyError::DualControlRequired`] if the rule in
+    /// force keeps one operator from doing `action` alone
+    pub(crate) fn require_second_operator(&self, action: &str) -> Result<(), CustodyError> {
+        match self.four_eyes {
+            Some(_) => Err(CustodyError::DualControlRequired(action.to_string())),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns true if a withdrawal of `amount` needs a second operator
+    pub(crate) fn needs_second_operator(&self, amount: Amount) -> bool {
+        self.four_eyes
+            .and_then(|rule| rule.withdrawal_threshold)
+            .is_some_and(|threshold| amount > threshold)
+    }
+
+    fn check_action(&self, action: &AdminAction) -> Result<(), CustodyError> {
+        if let Some(wallet_id) = action.wallet_id() {
+            if !self.wallet_exists(wallet_id) {
+                return Err(CustodyError::WalletNotFound(wallet_id.to_string()));
+            }
+        }
+        match action {
+            AdminAction::SetTenantWithdrawalPolicy { tenant_id, .. } => {
+                self.get_tenant(tenant_id)?;
+            }
+            AdminAction::RemoveAccountWhitelistedAddress { account_id, .. } => {
+                self.get_account(account_id)?;
+            }
+            AdminAction::SetWalletRateLimit(Some(limit))
+            | AdminAction::SetOperatorRateLimit(Some(limit)) => limit.validate()?,
+            _ => {}
+        }
+        if let AdminAction::Withdrawal {
+            wallet_id,
+            amount,
+            destination,
+        } = action
+        {
+            let blockers = self.withdrawal_blockers(
+                wallet_id,
+                None,
+                *amount,
+                destination.as_deref(),
+                Authorization::Confirmed,
+            );
+            if let Some(reason) = blockers.into_iter().next() {
+                return Err(reason);
+            }
+        }
+        Ok(())
+    }
+
+    fn close_action(&mut self, action_id: u64, status: OperationStatus, by: Option<String>) {
+        if let Some(proposal) = self.admin_actions.get_mut(&action_id) {
+            proposal.status = status;
+            proposal.confirmed_by = by;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::time::ManualClock;
+    use crate::WalletType;
+    use std::sync::Arc;
+
+    fn system() -> (CustodySystem, Arc<ManualClock>) {
+        let clock = Arc::new(ManualClock::new(Timestamp::from_unix(1_700_000_000)));
+        let mut system = CustodySystem::new();
+        system.set_clock(clock.clone());
+        system
+            .create_wallet("w".to_string(), "0x1".to_string(), WalletType::Hot)
+            .unwrap();
+        system.deposit("w", amount!(100)).unwrap();
+        system
+            .set_four_eyes_rule(FourEyesRule {
+                expiry_secs: 3600,
+                withdrawal_threshold: Some(amount!(10)),
+            })
+            .unwrap();
+        (system, clock)
+    }
+
+    fn withdrawal(amount: Amount) -> AdminAction {
+        AdminAction::Withdrawal {
+            wallet_id: "w".to_string(),
+            amount,
+            destination: Some("bc1qdest".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_large_withdrawals_need_a_second_operator() {
+        let (mut system, _) = system();
+        system.withdraw("w", amount!(10)).unwrap();
+        assert_eq!(
+            system.withdraw("w", amount!(11)),
+            Err(CustodyError::DualControlRequired("withdrawal".to_string()))
+        );
+
+        let id = system
+            .propose_action("alice", withdrawal(amount!(50)))
+            .unwrap();
+        assert_eq!(
+            system.confirm_action(id, "alice"),
+            Err(CustodyError::SelfApproval(id))
+        );
+        assert_eq!(system.get_wallet("w").unwrap().balance, amount!(90));
+        assert_eq!(
+            system.confirm_action(id, "bob"),
+            Ok(OperationStatus::Executed)
+        );
+        assert_eq!(system.get_wallet("w").unwrap().balance, amount!(40));
+        let proposal = system.get_proposed_action(id).unwrap();
+        assert_eq!(proposal.confirmed_by.as_deref(), Some("bob"));
+        assert_eq!(
+            system.confirm_action(id, "carol"),
+            Err(CustodyError::OperationNotPending(id))
+        );
+
+        // Refused at proposal time if it could not go through
+        assert!(matches!(
+            system.propose_action("alice", withdrawal(amount!(500))),
+            Err(CustodyError::InsufficientBalance { .. })
+        ));
+    }
+
+    #[test]
+    fn test_unconfirmed_proposals_expire() {
+        let (mut system, clock) = system();
+        let policy = AdminAction::SetWithdrawalPolicy {
+            wallet_id: "w".to_string(),
+            policy: None,
+        };
+        let late = system.propose_action("alice", policy.clone()).unwrap();
+        clock.advance(1800);
+        let swept = system.propose_action("alice", policy).unwrap();
+        clock.advance(1801);
+        assert_eq!(
+            system.confirm_action(late, "bob"),
+            Err(CustodyError::ActionExpired(late))
+        );
+        assert_eq!(
+            system.get_proposed_action(late).unwrap().status,
+            OperationStatus::Expired
+        );
+        assert_eq!(system.expire_actions(), Vec::<u64>::new());
+        clock.advance(1800);
+        assert_eq!(system.expire_actions(), [swept]);
+        assert!(system.pending_actions().is_empty());
+    }
+
+    #[test]
+    fn test_admin_actions_and_the_rule_itself() {
+        let (mut system, _) = system();
+        assert_eq!(
+            system.clear_withdrawal_policy("w"),
+            Err(CustodyError::DualControlRequired(
+                "set_withdrawal_policy".to_string()
+            ))
+        );
+        assert_eq!(
+            system.close_wallet("w", "client left"),
+            Err(CustodyError::DualControlRequired(
+                "close_wallet".to_string()
+            ))
+        );
+        assert_eq!(
+            system.set_four_eyes_rule(FourEyesRule::default()),
+            Err(CustodyError::DualControlRequired(
+                "set_four_eyes_rule".to_string()
+            ))
+        );
+
+        let lift = system
+            .propose_action("alice", AdminAction::SetFourEyesRule(None))
+            .unwrap();
+        system.confirm_action(lift, "bob").unwrap();
+        assert!(system.four_eyes_rule().is_none());
+        system.withdraw("w", amount!(50)).unwrap();
+        system.freeze_wallet("w", "review").unwrap();
+    }
+
+    #[test]
+    fn test_limits_and_whitelist_removals_need_two_operators() {
+        let (mut system, _) = system();
+        let refused = |action: &str| CustodyError::DualControlRequired(action.to_string());
+        assert_eq!(
+            system
+                .set_default_withdrawal_policy(WalletType::Hot, WithdrawalPolicy::default())
+                .unwrap_err(),
+            refused("set_default_withdrawal_policy")
+        );
+        assert_eq!(
+            system.set_wallet_rate_limit(None).unwrap_err(),
+            refused("set_wallet_rate_limit")
+        );
+        assert_eq!(
+            system.set_operator_rate_limit(None).unwrap_err(),
+            refused("set_operator_rate_limit")
+        );
+        assert_eq!(
+            system
+                .remove_whitelisted_address("w", "bc1qdest")
+                .unwrap_err(),
+            refused("remove_whitelisted_address")
+        );
+
+        system
+            .whitelist_address("w", "bc1qdest", "payouts")
+            .unwrap();
+        let remove = AdminAction::RemoveWhitelistedAddress {
+            wallet_id: "w".to_string(),
+            address: "bc1qdest".to_string(),
+        };
+        let id = system.propose_action("alice", remove).unwrap();
+        system.confirm_action(id, "bob").unwrap();
+        assert!(system.whitelisted_addresses("w").is_empty());
+
+        // Checked when proposed
+        assert!(matches!(
+            system.propose_action(
+                "alice",
+                AdminAction::SetWalletRateLimit(Some(RateLimit::new(0, 10)))
+            ),
+            Err(CustodyError::InvalidConfig(_))
+        ));
+    }
+}
@@ -0,0 +1,1219 @@
+//! gRPC service (feature `grpc`).
+//!
+//! [`CustodyService`] exposes wallets, transactions and the custody
+//! operations over gRPC with tonic, for service-to-service integration.
+//! The contract is `proto/securevault/v1/custody.proto`; the messages in
+//! [`proto`] mirror it field for field and tag for tag, and the client
+//! and server stubs are generated from the same service description at
+//! build time.
+//!
+//! `StreamTransactions` is a live feed built on the change data capture
+//! stream: it sends every transaction recorded after the requested
+//! sequence number, then keeps the call open and sends new ones as they
+//! are recorded. Changes made through this service are pushed at once;
+//! changes made to the shared system by other code are picked up within
+//! [`CustodyService::poll_interval`].
+//!
+//! With [`CustodyService::require_api_keys`] every call must carry an API
+//! key token, as `authorization: Bearer <token>` or `x-api-key: <token>`
+//! metadata, whose scope covers the operation and the wallets it touches.
+//! Listings and streams that name no wallet are narrowed to the key's
+//! wallets.
+
+use crate::cdc::Change;
+use crate::digest::type_tag;
+use crate::time::Timestamp;
+use crate::{
+    Amount, ApiKey, ApiOperation, Asset, CustodyError, CustodySystem, OperationStatus,
+    TransactionFilter, TransactionType, WalletType,
+};
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::Duration;
+use tokio::sync::{mpsc, watch};
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Request, Response, Status};
+
+/// Messages of the `securevault.v1` package and the generated stubs
+pub mod proto {
+    /// Kind of wallet
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+    #[repr(i32)]
+    pub enum WalletKind {
+        Hot = 0,
+        Cold = 1,
+        Multisig = 2,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct Wallet {
+        #[prost(string, tag = "1")]
+        pub id: String,
+        #[prost(string, tag = "2")]
+        pub address: String,
+        #[prost(enumeration = "WalletKind", tag = "3")]
+        pub kind: i32,
+        #[prost(uint32, tag = "4")]
+        pub required_signatures: u32,
+        #[prost(string, repeated, tag = "5")]
+        pub signers: Vec<String>,
+        #[prost(string, tag = "6")]
+        pub asset: String,
+        #[prost(string, tag = "7")]
+        pub balance: String,
+        #[prost(string, tag = "8")]
+        pub status: String,
+        #[prost(string, repeated, tag = "9")]
+        pub tags: Vec<String>,
+        #[prost(uint64, tag = "10")]
+        pub created_at: u64,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct Transaction {
+        #[prost(uint64, tag = "1")]
+        pub id: u64,
+        #[prost(string, tag = "2")]
+        pub uuid: String,
+        #[prost(string, tag = "3")]
+        pub wallet_id: String,
+        #[prost(string, tag = "4")]
+        pub transaction_type: String,
+        #[prost(string, tag = "5")]
+        pub amount: String,
+        #[prost(string, tag = "6")]
+        pub asset: String,
+        #[prost(uint64, tag = "7")]
+        pub timestamp: u64,
+        #[prost(string, optional, tag = "8")]
+        pub counterparty: Option<String>,
+        #[prost(string, optional, tag = "9")]
+        pub idempotency_key: Option<String>,
+        #[prost(string, tag = "10")]
+        pub chain_hash: String,
+        #[prost(string, optional, tag = "11")]
+        pub memo: Option<String>,
+        #[prost(string, repeated, tag = "12")]
+        pub tags: Vec<String>,
+        #[prost(btree_map = "string, string", tag = "13")]
+        pub metadata: ::std::collections::BTreeMap<String, String>,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct CreateWalletRequest {
+        #[prost(string, tag = "1")]
+        pub id: String,
+        #[prost(string, tag = "2")]
+        pub address: String,
+        #[prost(enumeration = "WalletKind", tag = "3")]
+        pub kind: i32,
+        #[prost(uint32, tag = "4")]
+        pub required_signatures: u32,
+        #[prost(string, repeated, tag = "5")]
+        pub signers: Vec<String>,
+        #[prost(string, tag = "6")]
+        pub asset: String,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct GetWalletRequest {
+        #[prost(string, tag = "1")]
+        pub id: String,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct ListWalletsRequest {}
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct ListWalletsResponse {
+        #[prost(message, repeated, tag = "1")]
+        pub wallets: Vec<Wallet>,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct DepositRequest {
+        #[prost(string, tag = "1")]
+        pub wallet_id: String,
+        #[prost(string, tag = "2")]
+        pub amount: String,
+        #[prost(string, optional, tag = "3")]
+        pub idempotency_key: Option<String>,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct WithdrawRequest {
+        #[prost(string, tag = "1")]
+        pub wallet_id: String,
+        #[prost(string, tag = "2")]
+        pub amount: String,
+        #[prost(string, optional, tag = "3")]
+        pub idempotency_key: Option<String>,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct TransferRequest {
+        #[prost(string, tag = "1")]
+        pub from_wallet_id: String,
+        #[prost(string, tag = "2")]
+        pub to_wallet_id: String,
+        #[prost(string, tag = "3")]
+        pub amount: String,
+        #[prost(string, optional, tag = "4")]
+        pub idempotency_key: Option<String>,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct ListTransactionsRequest {
+        #[prost(string, optional, tag = "1")]
+        pub wallet_id: Option<String>,
+        #[prost(uint64, optional, tag = "2")]
+        pub from: Option<u64>,
+        #[prost(uint64, optional, tag = "3")]
+        pub until: Option<u64>,
+        #[prost(string, optional, tag = "4")]
+        pub transaction_type: Option<String>,
+        #[prost(string, optional, tag = "5")]
+        pub tag: Option<String>,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct ListTransactionsResponse {
+        #[prost(message, repeated, tag = "1")]
+        pub transactions: Vec<Transaction>,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct WalletStatusRequest {
+        #[prost(string, tag = "1")]
+        pub wallet_id: String,
+        #[prost(string, tag = "2")]
+        pub reason: String,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct RequestWithdrawalRequest {
+        #[prost(string, tag = "1")]
+        pub wallet_id: String,
+        #[prost(string, tag = "2")]
+        pub requester: String,
+        #[prost(string, tag = "3")]
+        pub amount: String,
+        #[prost(string, optional, tag = "4")]
+        pub destination: Option<String>,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct ApproveWithdrawalRequest {
+        #[prost(uint64, tag = "1")]
+        pub withdrawal_id: u64,
+        #[prost(string, tag = "2")]
+        pub approver: String,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct RejectWithdrawalRequest {
+        #[prost(uint64, tag = "1")]
+        pub withdrawal_id: u64,
+        #[prost(string, tag = "2")]
+        pub approver: String,
+        #[prost(string, tag = "3")]
+        pub reason: String,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct RequestMultisigWithdrawalRequest {
+        #[prost(string, tag = "1")]
+        pub wallet_id: String,
+        #[prost(string, tag = "2")]
+        pub signer: String,
+        #[prost(string, tag = "3")]
+        pub amount: String,
+        #[prost(string, optional, tag = "4")]
+        pub destination: Option<String>,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct SignWithdrawalRequest {
+        #[prost(string, tag = "1")]
+        pub wallet_id: String,
+        #[prost(uint64, tag = "2")]
+        pub operation_id: u64,
+        #[prost(string, tag = "3")]
+        pub signer: String,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct OperationResponse {
+        #[prost(uint64, tag = "1")]
+        pub operation_id: u64,
+        #[prost(string, tag = "2")]
+        pub status: String,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct StreamTransactionsRequest {
+        #[prost(uint64, tag = "1")]
+        pub from_sequence: u64,
+        #[prost(string, optional, tag = "2")]
+        pub wallet_id: Option<String>,
+    }
+
+    include!(concat!(env!("OUT_DIR"), "/securevault.v1.Custody.rs"));
+}
+
+use proto::custody_server::{Custody, CustodyServer};
+
+/// How often a live transaction stream checks for changes made outside
+/// the service, by default
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Messages buffered per live stream before it waits for the client
+const STREAM_BUFFER: usize = 64;
+
+/// gRPC front end to a custody system shared with the rest of the process
+#[derive(Debug, Clone)]
+pub struct CustodyService {
+    system: Arc<RwLock<CustodySystem>>,
+    /// Latest change sequence after each operation made through the service
+    recorded: watch::Sender<u64>,
+    poll_interval: Duration,
+    api_keys_required: bool,
+}
+
+impl CustodyService {
+    /// Creates a service operating on `system`
+    pub fn new(system: Arc<RwLock<CustodySystem>>) -> Self {
+        let latest = read(&system).latest_sequence();
+        Self {
+            system,
+            recorded: watch::Sender::new(latest),
+            poll_interval: DEFAULT_POLL_INTERVAL,
+            api_keys_required: false,
+        }
+    }
+
+    /// Sets how often live streams check for changes made outside the
+    /// service
+    pub fn with_poll_interval(mut self, interval: Duration) -> Self {
+        self.poll_interval = interval;
+        self
+    }
+
+    /// How often live streams check for changes made outside the service
+    pub fn poll_interval(&self) -> Duration {
+        self.poll_interval
+    }
+
+    /// Requires an API key on every call, checked against its scope
+    pub fn require_api_keys(mut self) -> Self {
+        self.api_keys_required = true;
+        self
+    }
+
+    /// Wraps the service for [`tonic::transport::Server::add_service`]
+    pub fn into_server(self) -> CustodyServer<Self> {
+        CustodyServer::new(self)
+    }
+
+    /// Runs `op` on the system and wakes the live streams
+    fn mutate<T>(
+        &self,
+        op: impl FnOnce(&mut CustodySystem) -> Result<T, CustodyError>,
+    ) -> Result<T, Status> {
+        let mut system = write(&self.system);
+        let result = op(&mut system);
+        self.recorded.send_replace(system.latest_sequence());
+        result.map_err(status)
+    }
+
+    /// Checks the request's API key, when keys are required, against
+    /// `operation` on `wallet_ids`, and returns the key
+    fn authorize<T>(
+        &self,
+        request: &Request<T>,
+        operation: ApiOperation,
+        wallet_ids: &[&str],
+    ) -> Result<Option<ApiKey>, Status> {
+        if !self.api_keys_required {
+            return Ok(None);
+        }
+        let metadata = request.metadata();
+        let token = metadata
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .or_else(|| {
+                metadata
+                    .get("x-api-key")
+                    .and_then(|value| value.to_str().ok())
+            })
+            .ok_or_else(|| Status::unauthenticated("missing API key"))?;
+        let key = read(&self.system)
+            .authorize(token, operation, wallet_ids)
+            .map_err(status)?
+            .clone();
+        if operation != ApiOperation::Read {
+            write(&self.system)
+                .throttle_operator(&key.id)
+                .map_err(status)?;
+        }
+        Ok(Some(key))
+    }
+}
+
+/// Serves the custody gRPC service on `addr` until the server fails
+///
+/// # Example
+/// ```no_run
+/// use securevault::{serve_grpc, CustodySystem};
+/// use std::sync::{Arc, RwLock};
+///
+/// # async fn run() -> Result<(), tonic::transport::Error> {
+/// let system = Arc::new(RwLock::new(CustodySystem::new()));
+/// serve_grpc("127.0.0.1:50051".parse().unwrap(), system).await
+/// # }
+/// ```
+pub async fn serve_grpc(
+    addr: SocketAddr,
+    system: Arc<RwLock<CustodySystem>>,
+) -> Result<(), tonic::transport::Error> {
+    tonic::transport::Server::builder()
+        .add_service(CustodyService::new(system).into_server())
+        .serve(addr)
+        .await
+}
+
+#[tonic::async_trait]
+impl Custody for CustodyService {
+    async fn create_wallet(
+        &self,
+        request: Request<proto::CreateWalletRequest>,
+    ) -> Result<Response<proto::Wallet>, Status> {
+        self.authorize(&request, ApiOperation::Manage, &[&request.get_ref().id])?;
+        let request = request.into_inner();
+        let wallet_type = match proto::WalletKind::try_from(request.kind) {
+            Ok(proto::WalletKind::Hot) => WalletType::Hot,
+            Ok(proto::WalletKind::Cold) => WalletType::Cold,
+            Ok(proto::WalletKind::Multisig) => WalletType::MultiSig {
+                required: request.required_signatures as usize,
+                signers: request.signers.into_iter().collect(),
+            },
+            Err(_) => {
+                return Err(Status::invalid_argument(format!(
+                    "unknown wallet kind {}",
+                    request.kind
+                )))
+            }
+        };
+        let asset = parse_asset(&request.asset)?;
+        let wallet = self.mutate(|system| {
+            system.create_wallet_with_asset(request.id, request.address, wallet_type, asset)
+        })?;
+        Ok(Response::new(wallet_message(&wallet)))
+    }
+
+    async fn get_wallet(
+        &self,
+        request: Request<proto::GetWalletRequest>,
+    ) -> Result<Response<proto::Wallet>, Status> {
+        self.authorize(&request, ApiOperation::Read, &[&request.get_ref().id])?;
+        let id = request.into_inner().id;
+        let system = read(&self.system);
+        let wallet = system
+            .get_wallet(&id)
+            .ok_or_else(|| status(CustodyError::WalletNotFound(id.clone())))?;
+        Ok(Response::new(wallet_message(wallet)))
+    }
+
+    async fn list_wallets(
+        &self,
+        request: Request<proto::ListWalletsRequest>,
+    ) -> Result<Response<proto::ListWalletsResponse>, Status> {
+        let key = self.authorize(&request, ApiOperation::Read, &[])?;
+        let system = read(&self.system);
+        let mut wallets: Vec<_> = system
+            .get_all_wallets()
+            .values()
+            .filter(|wallet| key.as_ref().is_none_or(|key| key.scope.covers(&wallet.id)))
+            .map(wallet_message)
+            .collect();
+        wallets.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(Response::new(proto::ListWalletsResponse { wallets }))
+    }
+
+    async fn deposit(
+        &self,
+        request: Request<proto::DepositRequest>,
+    ) -> Result<Response<proto::Transaction>, Status> {
+        self.authorize(
+            &request,
+            ApiOperation::Deposit,
+            &[&request.get_ref().wallet_id],
+        )?;
+        let request = request.into_inner();
+        let amount = parse_amount(&request.amount)?;
+        let key = request.idempotency_key;
+        self.mutate(|system| match &key {
+            Some(key) => system.deposit_idempotent(key, &request.wallet_id, amount),
+            None => system.deposit(&request.wallet_id, amount),
+        })?;
+        self.recorded_transaction(key.as_deref())
+    }
+
+    async fn withdraw(
+        &self,
+        request: Request<proto::WithdrawRequest>,
+    ) -> Result<Response<proto::Transaction>, Status> {
+        self.authorize(
+            &request,
+            ApiOperation::Withdraw,
+            &[&request.get_ref().wallet_id],
+        )?;
+        let request = request.into_inner();
+        let amount = parse_amount(&request.amount)?;
+        let key = request.idempotency_key;
+        self.mutate(|system| match &key {
+            Some(key) => system.withdraw_idempotent(key, &request.wallet_id, amount),
+            None => system.withdraw(&request.wallet_id, amount),
+        })?;
+        self.recorded_transaction(key.as_deref())
+    }
+
+    async fn transfer(
+        &self,
+        request: Request<proto::TransferRequest>,
+    ) -> Result<Response<proto::Transaction>, Status> {
+        let wallets = [
+            request.get_ref().from_wallet_id.as_str(),
+            &request.get_ref().to_wallet_id,
+        ];
+        self.authorize(&request, ApiOperation::Transfer, &wallets)?;
+        let request = request.into_inner();
+        let amount = parse_amount(&request.amount)?;
+        let (from, to) = (&request.from_wallet_id, &request.to_wallet_id);
+        let key = request.idempotency_key.as_deref();
+        self.mutate(|system| match key {
+            Some(key) => system.transfer_idempotent(key, from, to, amount),
+            None => system.transfer(from, to, amount),
+        })?;
+        self.recorded_transaction(key)
+    }
+
+    async fn list_transactions(
+        &self,
+        request: Request<proto::ListTransactionsRequest>,
+    ) -> Result<Response<proto::ListTransactionsResponse>, Status> {
+        let wallet_id = request.get_ref().wallet_id.as_deref();
+        let key = self.authorize(&request, ApiOperation::Read, wallet_id.as_slice())?;
+        let request = request.into_inner();
+        let filter = TransactionFilter {
+            wallet_id: request.wallet_id,
+            from: request.from.map(Timestamp::from_unix),
+            until: request.until.map(Timestamp::from_unix),
+            transaction_type: request
+                .transaction_type
+                .as_deref()
+                .map(parse_transaction_type)
+                .transpose()?,
+            tag: request.tag,
+        };
+        let system = read(&self.system);
+        if let Some(id) = &filter.wallet_id {
+            if !system.wallet_exists(id) {
+                return Err(status(CustodyError::WalletNotFound(id.clone())));
+            }
+        }
+        let transactions = system
+            .get_all_transactions()
+            .iter()
+            .filter(|tx| filter.matches(tx) && visible(key.as_ref(), tx))
+            .map(transaction_message)
+            .collect();
+        Ok(Response::new(proto::ListTransactionsResponse {
+            transactions,
+        }))
+    }
+
+    async fn freeze_wallet(
+        &self,
+        request: Request<proto::WalletStatusRequest>,
+    ) -> Result<Response<proto::Wallet>, Status> {
+        self.authorize(
+            &request,
+            ApiOperation::Manage,
+            &[&request.get_ref().wallet_id],
+        )?;
+        let request = request.into_inner();
+        self.mutate(|system| system.freeze_wallet(&request.wallet_id, &request.reason))?;
+        self.wallet_response(&request.wallet_id)
+    }
+
+    async fn unfreeze_wallet(
+        &self,
+        request: Request<proto::WalletStatusRequest>,
+    ) -> Result<Response<proto::Wallet>, Status> {
+        self.authorize(
+            &request,
+            ApiOperation::Manage,
+            &[&request.get_ref().wallet_id],
+        )?;
+        let request = request.into_inner();
+        self.mutate(|system| system.unfreeze_wallet(&request.wallet_id, &request.reason))?;
+        self.wallet_response(&request.wallet_id)
+    }
+
+    async fn close_wallet(
+        &self,
+        request: Request<proto::WalletStatusRequest>,
+    ) -> Result<Response<proto::Wallet>, Status> {
+        self.authorize(
+            &request,
+            ApiOperation::Manage,
+            &[&request.get_ref().wallet_id],
+        )?;
+        let request = request.into_inner();
+        self.mutate(|system| system.close_wallet(&request.wallet_id, &request.reason))?;
+        self.wallet_response(&request.wallet_id)
+    }
+
+    async fn request_withdrawal(
+        &self,
+        request: Request<proto::RequestWithdrawalRequest>,
+    ) -> Result<Response<proto::OperationResponse>, Status> {
+        self.authorize(
+            &request,
+            ApiOperation::Withdraw,
+            &[&request.get_ref().wallet_id],
+        )?;
+        let request = request.into_inner();
+        let amount = parse_amount(&request.amount)?;
+        let id = self.mutate(|system| {
+            system.request_withdrawal(
+                &request.wallet_id,
+                &request.requester,
+                amount,
+                request.destination.as_deref(),
+            )
+        })?;
+        Ok(operation(id, &OperationStatus::Pending))
+    }
+
+    async fn approve_withdrawal(
+        &self,
+        request: Request<proto::ApproveWithdrawalRequest>,
+    ) -> Result<Response<proto::OperationResponse>, Status> {
+        let wallet_id = self.pending_withdrawal_wallet(request.get_ref().withdrawal_id);
+        self.authorize(
+            &request,
+            ApiOperation::Manage,
+            wallet_id.as_deref().as_slice(),
+        )?;
+        let request = request.into_inner();
+        let id = request.withdrawal_id;
+        let state = self.mutate(|system| system.approve_withdrawal(id, &request.approver))?;
+        Ok(operation(id, &state))
+    }
+
+    async fn reject_withdrawal(
+        &self,
+        request: Request<proto::RejectWithdrawalRequest>,
+    ) -> Result<Response<proto::OperationResponse>, Status> {
+        let wallet_id = self.pending_withdrawal_wallet(request.get_ref().withdrawal_id);
+        self.authorize(
+            &request,
+            ApiOperation::Manage,
+            wallet_id.as_deref().as_slice(),
+        )?;
+        let request = request.into_inner();
+        let id = request.withdrawal_id;
+        self.mutate(|system| system.reject_withdrawal(id, &request.approver, &request.reason))?;
+        Ok(operation(id, &OperationStatus::Rejected))
+    }
+
+    async fn request_multisig_withdrawal(
+        &self,
+        request: Request<proto::RequestMultisigWithdrawalRequest>,
+    ) -> Result<Response<proto::OperationResponse>, Status> {
+        self.authorize(
+            &request,
+            ApiOperation::Withdraw,
+            &[&request.get_ref().wallet_id],
+        )?;
+        let request = request.into_inner();
+        let amount = parse_amount(&request.amount)?;
+        let (id, state) = self.mutate(|system| {
+            let id = system.request_multisig_withdrawal(
+                &request.wallet_id,
+                &request.signer,
+                amount,
+                request.destination.as_deref(),
+            )?;
+            let state = system
+                .get_multisig_withdrawal(id)
+                .map_or(OperationStatus::Pending, |op| op.status.clone());
+            Ok((id, state))
+        })?;
+        Ok(operation(id, &state))
+    }
+
+    async fn sign_withdrawal(
+        &self,
+        request: Request<proto::SignWithdrawalRequest>,
+    ) -> Result<Response<proto::OperationResponse>, Status> {
+        self.authorize(
+            &request,
+            ApiOperation::Withdraw,
+            &[&request.get_ref().wallet_id],
+        )?;
+        let request = request.into_inner();
+        let id = request.operation_id;
+        let state =
+            self.mutate(|system| system.sign_withdrawal(&request.wallet_id, id, &request.signer))?;
+        Ok(operation(id, &state))
+    }
+
+    type StreamTransactionsStream = Pin<Box<ReceiverStream<Result<proto::Transaction, Status>>>>;
+
+    async fn stream_transactions(
+        &self,
+        request: Request<proto::StreamTransactionsRequest>,
+    ) -> Result<Response<Self::StreamTransactionsStream>, Status> {
+        let wallet_id = request.get_ref().wallet_id.as_deref();
+        let key = self.authorize(&request, ApiOperation::Read, wallet_id.as_slice())?;
+        let request = request.into_inner();
+        if let Some(id) = &request.wallet_id {
+            if !read(&self.system).wallet_exists(id) {
+                return Err(status(CustodyError::WalletNotFound(id.clone())));
+            }
+        }
+        let (sender, receiver) = mpsc::channel(STREAM_BUFFER);
+        let system = self.system.clone();
+        let mut recorded = self.recorded.subscribe();
+        let poll_interval = self.poll_interval;
+
+        tokio::spawn(async move {
+            let mut cursor = request.from_sequence;
+            loop {
+                let batch: Vec<proto::Transaction> = {
+                    let system = read(&system);
+                    let batch = system
+                        .changes_since(cursor)
+                        .filter_map(|record| match &record.change {
+                            Change::TransactionAppend { transaction, .. }
+                                if request
+                                    .wallet_id
+                                    .as_deref()
+                                    .is_none_or(|id| transaction.involves(id))
+                                    && visible(key.as_ref(), transaction) =>
+                            {
+                                Some(transaction_message(transaction))
+                            }
+                            _ => None,
+                        })
+                        .collect();
+                    cursor = cursor.max(system.latest_sequence());
+                    batch
+                };
+                for transaction in batch {
+                    if sender.send(Ok(transaction)).await.is_err() {
+                        return;
+                    }
+                }
+                if sender.is_closed() {
+                    return;
+                }
+                // Woken early by operations made through the service
+                if let Ok(Err(_)) = tokio::time::timeout(poll_interval, recorded.changed()).await {
+                    return;
+                }
+            }
+        });
+        Ok(Response::new(Box::pin(ReceiverStream::new(receiver))))
+    }
+}
+
+impl CustodyService {
+    /// Returns the transaction just recorded, or the one recorded earlier
+    /// under `idempotency_key` for a replay
+    fn recorded_transaction(
+        &self,
+        idempotency_key: Option<&str>,
+    ) -> Result<Response<proto::Transaction>, Status> {
+        let system = read(&self.system);
+        let transaction = match idempotency_key {
+            Some(key) => system.transactions_by_idempotency_key(key).last().copied(),
+            None => system.get_all_transactions().last(),
+        };
+        transaction
+            .map(|tx| Response::new(transaction_message(tx)))
+            .ok_or_else(|| Status::internal("operation recorded no transaction"))
+    }
+
+    /// Returns the wallet of a withdrawal awaiting approval, if there is one
+    fn pending_withdrawal_wallet(&self, withdrawal_id: u64) -> Option<String> {
+        read(&self.system)
+            .get_pending_withdrawal(withdrawal_id)
+            .map(|withdrawal| withdrawal.wallet_id.clone())
+    }
+
+    fn wallet_response(&self, wallet_id: &str) -> Result<Response<proto::Wallet>, Status> {
+        read(&self.system)
+            .get_wallet(wallet_id)
+            .map(|wallet| Response::new(wallet_message(wallet)))
+            .ok_or_else(|| status(CustodyError::WalletNotFound(wallet_id.to_string())))
+    }
+}
+
+fn read(system: &RwLock<CustodySystem>) -> RwLockReadGuard<'_, CustodySystem> {
+    system
+        .read()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+fn write(system: &RwLock<CustodySystem>) -> RwLockWriteGuard<'_, CustodySystem> {
+    system
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Returns true if `tx` involves a wallet in the scope of `key`, or no key
+/// is required
+fn visible(key: Option<&ApiKey>, tx: &crate::Transaction) -> bool {
+    key.and_then(|key| key.scope.wallets.as_ref())
+        .is_none_or(|wallets| wallets.iter().any(|id| tx.involves(id)))
+}
+
+/// Maps a custody error to the closest gRPC status code
+fn status(err: CustodyError) -> Status {
+    use CustodyError::*;
+    let message = err.to_string();
+    match err {
+        WalletNotFound(_)
+        | SourceWalletNotFound(_)
+        | DestinationWalletNotFound(_)
+        | TemplateNotFound(_)
+        | OperationNotFound(_)
+        | TransactionNotFound(_)
+        | TransactionIdNotFound(_)
+        | QueuedWithdrawalNotFound(_)
+        | IdSchemeNotFound(_)
+        | ExtensionNotFound { .. }
+        | HoldNotFound(_)
+        | KeyNotFound(_)
+        | UnsignedTxNotFound(_)
+        | UnknownDepositAddress(_)
+        | DepositNotFound(_)
+        | AccountNotFound(_)
+        | OrganizationNotFound(_)
+        | CustomerNotFound(_)
+        | ApiKeyNotFound(_)
+        | TenantNotFound(_)
+        | AlertNotFound(_)
+        | CommitmentNotFound(_)
+        | BroadcastNotFound(_) => Status::not_found(message),
+        WalletAlreadyExists(_)
+        | AlreadyJoint(_)
+        | DuplicateReference(_)
+        | IdCollision(_)
+        | AccountAlreadyExists(_)
+        | OrganizationAlreadyExists(_)
+        | CustomerAlreadyExists(_)
+        | TenantAlreadyExists(_)
+        | AddressInUse(_) => Status::already_exists(message),
+        NonPositiveAmount(_)
+        | SameWallet
+        | AssetMismatch { .. }
+        | InvalidRate(_)
+        | InvalidDestination(_)
+        | InvalidThreshold { .. }
+        | InvalidUr(_)
+        | InvalidAddress { .. }
+        | InvalidXpub(_)
+        | InvalidInterval
+        | DepositMismatch(_)
+        | SignatureRejected(_)
+        | InvalidSnapshot(_)
+        | InvalidConfig(_)
+        | InvalidKeyShare(_)
+        | InvalidMnemonic(_)
+        | InvalidPsbt(_)
+        | InvalidMultisigKeys(_)
+        | UnsupportedSnapshotVersion(_)
+        | UnsupportedBackupVersion(_) => Status::invalid_argument(message),
+        NotAnOwner { .. }
+        | NotAnApprover { .. }
+        | NotASigner { .. }
+        | SelfApproval(_)
+        | InvalidPassphrase
+        | ScreeningBlocked(_)
+        | ScopeDenied { .. } => Status::permission_denied(message),
+        InvalidApiKey | ApiKeyExpired(_) | ApiKeyRevoked(_) => Status::unauthenticated(message),
+        IdempotencyConflict(_) | BatchAborted(_) => Status::aborted(message),
+        RateLimited { .. } => Status::resource_exhausted(message),
+        AmountOverflow => Status::out_of_range(message),
+        RateUnavailable { .. }
+        | GatewayError(_)
+        | SigningFailed(_)
+        | KeyGenerationFailed(_)
+        | NodeError(_)
+        | FeeUnavailable(_) => Status::unavailable(message),
+        StorageFailed(_)
+        | BackupFailed(_)
+        | KeyVaultFailed(_)
+        | AuditChainBroken(_)
+        | DigestMismatch(_)
+        | ClaimsMismatch { .. } => Status::internal(message),
+        _ => Status::failed_precondition(message),
+    }
+}
+
+fn parse_amount(amount: &str) -> Result<Amount, Status> {
+    amount
+        .parse()
+        .map_err(|err| Status::invalid_argument(format!("{}", err)))
+}
+
+fn parse_asset(symbol: &str) -> Result<Asset, Status> {
+    match symbol {
+        "" | "BTC" => Ok(Asset::Btc),
+        "ETH" => Ok(Asset::Eth),
+        code if code.len() == 3 && code.bytes().all(|b| b.is_ascii_uppercase()) => {
+            Ok(Asset::Fiat(code.to_string()))
+        }
+        other => Err(Status::invalid_argument(format!(
+            "unsupported asset '{}'",
+            other
+        ))),
+    }
+}
+
+fn parse_transaction_type(tag: &str) -> Result<TransactionType, Status> {
+    [
+        TransactionType::Deposit,
+        TransactionType::Withdrawal,
+        TransactionType::ConversionOut,
+        TransactionType::ConversionIn,
+        TransactionType::Transfer,
+        TransactionType::Reward,
+    ]
+    .into_iter()
+    .find(|kind| type_tag(kind) == tag)
+    .ok_or_else(|| Status::invalid_argument(format!("unknown transaction type '{}'", tag)))
+}
+
+fn wallet_message(wallet: &crate::Wallet) -> proto::Wallet {
+    let (kind, required, signers) = match &wallet.wallet_type {
+        WalletType::Hot => (proto::WalletKind::Hot, 0, Vec::new()),
+        WalletType::Cold => (proto::WalletKind::Cold, 0, Vec::new()),
+        WalletType::MultiSig { required, signers } => (
+            proto::WalletKind::Multisig,
+            *required as u32,
+            signers.iter().cloned().collect(),
+        ),
+    };
+    proto::Wallet {
+        id: wallet.id.clone(),
+        address: wallet.address.clone(),
+        kind: kind as i32,
+        required_signatures: required,
+        signers,
+        asset: wallet.asset.symbol().to_string(),
+        balance: wallet.balance.to_string(),
+        status: wallet.status.name().to_string(),
+        tags: wallet.tags.iter().cloned().collect(),
+        created_at: wallet.created_at.as_unix(),
+    }
+}
+
+fn transaction_message(tx: &crate::Transaction) -> proto::Transaction {
+    proto::Transaction {
+        id: tx.id,
+        uuid: tx.uuid.to_string(),
+        wallet_id: tx.wallet_id.clone(),
+        transaction_type: type_tag(&tx.transaction_type).to_string(),
+        amount: tx.amount.to_string(),
+        asset: tx.asset.symbol().to_string(),
+        timestamp: tx.timestamp.as_unix(),
+        counterparty: tx.counterparty.clone(),
+        idempotency_key: tx.idempotency_key.clone(),
+        chain_hash: tx.chain_hash.clone(),
+        memo: tx.memo.clone(),
+        tags: tx.tags.iter().cloned().collect(),
+        metadata: tx.metadata.clone(),
+    }
+}
+
+fn operation(operation_id: u64, state: &OperationStatus) -> Response<proto::OperationResponse> {
+    let status = match state {
+        OperationStatus::Pending => "pending",
+        OperationStatus::Executed => "executed",
+        OperationStatus::Rejected => "rejected",
+        OperationStatus::Expired => "expired",
+    };
+    Response::new(proto::OperationResponse {
+        operation_id,
+        status: status.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::future::Future;
+    use tokio_stream::StreamExt;
+    use tonic::Code;
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    fn service() -> CustodyService {
+        let service = CustodyService::new(Arc::new(RwLock::new(CustodySystem::new())))
+            .with_poll_interval(Duration::from_millis(10));
+        for id in ["a", "b"] {
+            write(&service.system)
+                .create_wallet(id.to_string(), format!("0x{}", id), WalletType::Hot)
+                .unwrap();
+        }
+        service
+    }
+
+    fn deposit(wallet_id: &str, amount: &str) -> Request<proto::DepositRequest> {
+        Request::new(proto::DepositRequest {
+            wallet_id: wallet_id.to_string(),
+            amount: amount.to_string(),
+            idempotency_key: None,
+        })
+    }
+
+    #[test]
+    fn test_rpc_round_trip() {
+        let service = service();
+        block_on(async {
+            let wallet = service
+                .create_wallet(Request::new(proto::CreateWalletRequest {
+                    id: "vault".to_string(),
+                    address: "0xvault".to_string(),
+                    kind: proto::WalletKind::Multisig as i32,
+                    required_signatures: 2,
+                    signers: vec!["alice".to_string(), "bob".to_string()],
+                    asset: String::new(),
+                }))
+                .await
+                .unwrap()
+                .into_inner();
+            assert_eq!(wallet.asset, "BTC");
+            assert_eq!(wallet.required_signatures, 2);
+
+            let tx = service
+                .deposit(deposit("a", "2.5"))
+                .await
+                .unwrap()
+                .into_inner();
+            assert_eq!(tx.transaction_type, "deposit");
+            assert_eq!(tx.amount, "2.5");
+            let tx = service
+                .transfer(Request::new(proto::TransferRequest {
+                    from_wallet_id: "a".to_string(),
+                    to_wallet_id: "b".to_string(),
+                    amount: "1".to_string(),
+                    idempotency_key: None,
+                }))
+                .await
+                .unwrap()
+                .into_inner();
+            assert_eq!(tx.counterparty.as_deref(), Some("b"));
+
+            let listed = service
+                .list_transactions(Request::new(proto::ListTransactionsRequest {
+                    wallet_id: Some("b".to_string()),
+                    ..Default::default()
+                }))
+                .await
+                .unwrap()
+                .into_inner();
+            assert_eq!(listed.transactions.len(), 1);
+
+            let wallets = service
+                .list_wallets(Request::new(proto::ListWalletsRequest {}))
+                .await
+                .unwrap()
+                .into_inner()
+                .wallets;
+            let ids: Vec<_> = wallets.iter().map(|w| w.id.as_str()).collect();
+            assert_eq!(ids, ["a", "b", "vault"]);
+            assert_eq!(wallets[0].balance, "1.5");
+
+            let frozen = service
+                .freeze_wallet(Request::new(proto::WalletStatusRequest {
+                    wallet_id: "a".to_string(),
+                    reason: "review".to_string(),
+                }))
+                .await
+                .unwrap()
+                .into_inner();
+            assert_eq!(frozen.status, "frozen");
+        });
+    }
+
+    #[test]
+    fn test_idempotent_deposit_returns_original_transaction() {
+        let service = service();
+        block_on(async {
+            let request = || {
+                let mut request = deposit("a", "1");
+                request.get_mut().idempotency_key = Some("key-1".to_string());
+                request
+            };
+            let first = service.deposit(request()).await.unwrap().into_inner();
+            service.deposit(deposit("b", "1")).await.unwrap();
+            let replay = service.deposit(request()).await.unwrap().into_inner();
+            assert_eq!(first, replay);
+        });
+    }
+
+    #[test]
+    fn test_errors_map_to_status_codes() {
+        let service = service();
+        block_on(async {
+            let code =
+                |result: Result<Response<proto::Transaction>, Status>| result.unwrap_err().code();
+            assert_eq!(
+                code(service.deposit(deposit("zz", "1")).await),
+                Code::NotFound
+            );
+            assert_eq!(
+                code(service.deposit(deposit("a", "abc")).await),
+                Code::InvalidArgument
+            );
+            assert_eq!(
+                code(service.deposit(deposit("a", "0")).await),
+                Code::InvalidArgument
+            );
+            let withdraw = service
+                .withdraw(Request::new(proto::WithdrawRequest {
+                    wallet_id: "a".to_string(),
+                    amount: "5".to_string(),
+                    idempotency_key: None,
+                }))
+                .await;
+            assert_eq!(code(withdraw), Code::FailedPrecondition);
+            let duplicate = service
+                .create_wallet(Request::new(proto::CreateWalletRequest {
+                    id: "a".to_string(),
+                    address: "0xa".to_string(),
+                    ..Default::default()
+                }))
+                .await;
+            assert_eq!(duplicate.unwrap_err().code(), Code::AlreadyExists);
+        });
+    }
+
+    #[test]
+    fn test_api_keys_are_enforced() {
+        let service = service().require_api_keys();
+        let (_, token) = write(&service.system)
+            .create_api_key(
+                "till",
+                crate::KeyScope::deposit_only().with_wallet("a"),
+                None,
+            )
+            .unwrap();
+        let with_key =
+            |mut request: Request<proto::DepositRequest>, header: &'static str, value: String| {
+                request
+                    .metadata_mut()
+                    .insert(header, value.parse().unwrap());
+                request
+            };
+        block_on(async {
+            let code =
+                |result: Result<Response<proto::Transaction>, Status>| result.unwrap_err().code();
+            assert_eq!(
+                code(service.deposit(deposit("a", "1")).await),
+                Code::Unauthenticated
+            );
+            assert_eq!(
+                code(
+                    service
+                        .deposit(with_key(
+                            deposit("a", "1"),
+                            "x-api-key",
+                            "svk_x.y".to_string()
+                        ))
+                        .await
+                ),
+                Code::Unauthenticated
+            );
+            service
+                .deposit(with_key(
+                    deposit("a", "1"),
+                    "authorization",
+                    format!("Bearer {}", token),
+                ))
+                .await
+                .unwrap();
+            assert_eq!(
+                code(
+                    service
+                        .deposit(with_key(deposit("b", "1"), "x-api-key", token.clone()))
+                        .await
+                ),
+                Code::PermissionDenied
+            );
+
+            let mut request = Request::new(proto::ListWalletsRequest {});
+            request
+                .metadata_mut()
+                .insert("x-api-key", token.parse().unwrap());
+            let wallets = service.list_wallets(request).await.unwrap().into_inner();
+            assert_eq!(wallets.wallets.len(), 1);
+            assert_eq!(wallets.wallets[0].id, "a");
+        });
+    }
+
+    #[test]
+    fn test_stream_sends_recorded_and_live_transactions() {
+        let service = service();
+        write(&service.system).deposit("a", amount!(1)).unwrap();
+        block_on(async {
+            let mut stream = service
+                .stream_transactions(Request::new(proto::StreamTransactionsRequest {
+                    from_sequence: 0,
+                    wallet_id: Some("a".to_string()),
+                }))
+                .await
+                .unwrap()
+                .into_inner();
+            let first = stream.next().await.unwrap().unwrap();
+            assert_eq!(first.amount, "1");
+
+            // Through the service, and straight on the shared system
+            service.deposit(deposit("b", "9")).await.unwrap();
+            service.deposit(deposit("a", "2")).await.unwrap();
+            assert_eq!(stream.next().await.unwrap().unwrap().amount, "2");
+            write(&service.system).deposit("a", amount!(3)).unwrap();
+            assert_eq!(stream.next().await.unwrap().unwrap().amount, "3");
+        });
+    }
+
+    #[test]
+    fn test_stream_of_unknown_wallet_is_not_found() {
+        let service = service();
+        let result = block_on(service.stream_transactions(Request::new(
+            proto::StreamTransactionsRequest {
+                from_sequence: 0,
+                wallet_id: Some("zz".to_string()),
+            },
+        )));
+        assert_eq!(
+            result.err().map(|status| status.code()),
+            Some(Code::NotFound)
+        );
+    }
+}
@@ -0,0 +1,204 @@
+//! Hierarchical deterministic (BIP-32/44) deposit addresses.
+//!
+//! A wallet created from an account-level extended public key
+//! (`m/44'/0'/account'`) derives its receive addresses on the external
+//! chain, `m/44'/0'/account'/0/i`, as P2PKH addresses. Its
+//! [`Wallet::address`] is the address at index 0, and
+//! [`CustodySystem::next_deposit_address`] hands out the following ones,
+//! never the same one twice. Only the public key is held, so the ledger
+//! can generate addresses but not spend from them.
+//!
+//! The account record is part of the wallet and persists with it;
+//! derivation needs the `hd` feature. `xpub` keys derive mainnet
+//! addresses, `tpub` keys testnet ones.
+
+use serde::{Deserialize, Serialize};
+
+/// Extended public key of an HD wallet and its derivation progress
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HdAccount {
+    /// Account-level extended public key
+    pub xpub: String,
+    /// Index of the next receive address to hand out
+    pub next_index: u32,
+}
+
+/// Derived addresses are only known with the `hd` feature
+#[cfg(not(feature = "hd"))]
+impl HdAccount {
+    pub(crate) fn has_issued(&self, _address: &str) -> bool {
+        false
+    }
+}
+
+#[cfg(feature = "hd")]
+mod derive {
+    use super::HdAccount;
+    use crate::{Asset, Chain, CustodyError, CustodySystem, Wallet, WalletType};
+    use bip32::{ChildNumber, PublicKey, XPub};
+    use ripemd::Ripemd160;
+    use sha2::{Digest, Sha256};
+
+    /// P2PKH version bytes
+    const MAINNET_P2PKH: u8 = 0x00;
+    const TESTNET_P2PKH: u8 = 0x6f;
+
+    impl HdAccount {
+        /// Derives the receive address at `index`
+        pub fn address_at(&self, index: u32) -> Result<String, CustodyError> {
+            let invalid = |err: bip32::Error| CustodyError::InvalidXpub(err.to_string());
+            let account: XPub = self.xpub.parse().map_err(invalid)?;
+            let key = [0, index]
+                .into_iter()
+                .try_fold(account, |key, index| {
+                    key.derive_child(ChildNumber::new(index, false)?)
+                })
+                .map_err(invalid)?;
+            let hash = Ripemd160::digest(Sha256::digest(key.public_key().to_bytes()));
+            let version = if self.xpub.starts_with("tpub") {
+                TESTNET_P2PKH
+            } else {
+                MAINNET_P2PKH
+            };
+            Ok(bs58::encode(hash).with_check_version(version).into_string())
+        }
+
+        /// Returns true if `address` is one of the receive addresses handed
+        /// out so far
+        pub(crate) fn has_issued(&self, address: &str) -> bool {
+            (0..self.next_index).any(|index| self.address_at(index).is_ok_and(|a| a == address))
+        }
+    }
+
+    impl CustodySystem {
+        /// Creates a Bitcoin wallet whose addresses are derived from an
+        /// account-level extended public key
+        ///
+        /// # Example
+        /// ```
+        /// use securevault::{CustodySystem, WalletType};
+        /// let xpub = "xpub6BosfCnifzxcFwrSzQiqu2DBVTshkCXacvNsWGYJVVhhawA7d4R5WSWGFNbi8Aw6ZRc1brxMyWMzG3DSSSSoekkudhUd9yLb6qx39T9nMdj";
+        /// let mut system = CustodySystem::new();
+        /// let wallet = system
+        ///     .create_wallet_from_xpub("hd".to_string(), xpub, WalletType::Hot)
+        ///     .unwrap();
+        /// assert_eq!(wallet.address, "1LqBGSKuX5yYUonjxT5qGfpUsXKYYWeabA");
+        /// assert_eq!(
+        ///     system.next_deposit_address("hd").unwrap(),
+        ///     "1Ak8PffB2meyfYnbXZR9EGfLfFZVpzJvQP"
+        /// );
+        /// ```
+        pub fn create_wallet_from_xpub(
+            &mut self,
+            id: String,
+            xpub: &str,
+            wallet_type: WalletType,
+        ) -> Result<Wallet, CustodyError> {
+            if self.wallet_id_taken(&id) {
+                return Err(CustodyError::WalletAlreadyExists(id));
+            }
+            let account = HdAccount {
+                xpub: xpub.to_string(),
+                next_index: 1,
+            };
+            let address = account.address_at(0)?;
+            self.insert_wallet(
+                id.clone(),
+                address,
+                wallet_type,
+                Asset::Btc,
+                Some(Chain::Bitcoin),
+            )?;
+            let mut wallet = self.wallets[&id].clone();
+            wallet.hd = Some(account);
+            self.commit_wallet(wallet.clone())?;
+            Ok(wallet)
+        }
+
+        /// Derives a fresh receive address for an HD wallet
+        pub fn next_deposit_address(&mut self, wallet_id: &str) -> Result<String, CustodyError> {
+            let mut wallet = self
+                .wallets
+                .get(wallet_id)
+                .cloned()
+                .ok_or_else(|| CustodyError::WalletNotFound(wallet_id.to_string()))?;
+            let account = wallet
+                .hd
+                .as_mut()
+                .ok_or_else(|| CustodyError::NotAnHdWallet(wallet_id.to_string()))?;
+            let address = account.address_at(account.next_index)?;
+            account.next_index += 1;
+            self.commit_wallet(wallet)?;
+            Ok(address)
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        const XPUB: &str = "xpub6BosfCnifzxcFwrSzQiqu2DBVTshkCXacvNsWGYJVVhhawA7d4R5WSWGFNbi8Aw6ZRc1brxMyWMzG3DSSSSoekkudhUd9yLb6qx39T9nMdj";
+
+        #[test]
+        fn test_deposit_addresses_advance_and_persist() {
+            let mut system = CustodySystem::new();
+            system
+                .create_wallet_from_xpub("hd".to_string(), XPUB, WalletType::Cold)
+                .unwrap();
+            let first = system.next_deposit_address("hd").unwrap();
+            let second = system.next_deposit_address("hd").unwrap();
+            assert_ne!(first, second);
+            assert_eq!(
+                system
+                    .get_wallet("hd")
+                    .unwrap()
+                    .hd
+                    .as_ref()
+                    .unwrap()
+                    .next_index,
+                3
+            );
+
+            let mut restored = CustodySystem::new();
+            restored.restore(system.snapshot()).unwrap();
+            let third = restored.next_deposit_address("hd").unwrap();
+            assert_ne!(third, second);
+            assert_eq!(third, system.next_deposit_address("hd").unwrap());
+        }
+
+        #[test]
+        fn test_issued_addresses_receive_deposits() {
+            let mut system = CustodySystem::new();
+            system
+                .create_wallet_from_xpub("hd".to_string(), XPUB, WalletType::Hot)
+                .unwrap();
+            let address = system.next_deposit_address("hd").unwrap();
+            assert_eq!(system.wallet_for_address(&address), Some("hd"));
+            let unissued = system
+                .get_wallet("hd")
+                .unwrap()
+                .hd
+                .as_ref()
+                .unwrap()
+                .address_at(5);
+            assert_eq!(system.wallet_for_address(&unissued.unwrap()), None);
+        }
+
+        #[test]
+        fn test_rejects_bad_keys_and_plain_wallets() {
+            let mut system = CustodySystem::new();
+            assert!(matches!(
+                system.create_wallet_from_xpub("hd".to_string(), "xpub123", WalletType::Hot),
+                Err(CustodyError::InvalidXpub(_))
+            ));
+            assert!(!system.wallet_exists("hd"));
+            system
+                .create_wallet("w".to_string(), "0x1".to_string(), WalletType::Hot)
+                .unwrap();
+            assert_eq!(
+                system.next_deposit_address("w"),
+                Err(CustodyError::NotAnHdWallet("w".to_string()))
+            );
+        }
+    }
+}
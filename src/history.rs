@@ -0,0 +1,267 @@
+//! Time-travel queries over system state.
+//!
+//! Balances are a pure function of the transaction log (see
+//! [`replay`](CustodySystem::replay)), so any past state can be rebuilt by
+//! replaying the log up to a point in time. This backs audits and incident
+//! forensics ("what did the books look like at 14:03 yesterday?").
+
+use crate::time::Timestamp;
+use crate::{Amount, CustodyError, CustodySystem};
+use std::ops::Deref;
+use std::time::Duration;
+
+/// A read-only view of the system as it was at a point in time
+///
+/// Dereferences to a [`CustodySystem`], so every read-only query (balances,
+/// reports, statements, audits) works on the historical view. Wallets,
+/// balances, and the transaction log are reconstructed; other state
+/// (templates, joint wallets, listeners) is not and reflects the present.
+#[derive(Debug, Clone)]
+pub struct HistoricalState {
+    at: Timestamp,
+    system: CustodySystem,
+}
+
+impl HistoricalState {
+    /// The instant this view reconstructs
+    pub fn at(&self) -> Timestamp {
+        self.at
+    }
+}
+
+/// A wallet's balance at one instant of a [`balance_series`](CustodySystem::balance_series)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BalancePoint {
+    pub at: Timestamp,
+    pub balance: Amount,
+}
+
+impl Deref for HistoricalState {
+    type Target = CustodySystem;
+
+    fn deref(&self) -> &CustodySystem {
+        &self.system
+    }
+}
+
+impl CustodySystem {
+    /// Returns the balance `wallet_id` had in its primary asset at `at`,
+    /// counting transactions recorded at or before that instant
+    pub fn balance_at(&self, wallet_id: &str, at: Timestamp) -> Result<Amount, CustodyError> {
+        let wallet = self
+            .get_wallet(wallet_id)
+            .ok_or_else(|| CustodyError::WalletNotFound(wallet_id.to_string()))?;
+        let balances = Self::replay_balances(
+            self.get_wallet_transactions(wallet_id)
+                .into_iter()
+                .filter(|tx| tx.asset == wallet.asset && tx.timestamp <= at),
+        );
+        Ok(balances
+            .get(&(wallet_id.to_string(), wallet.asset.clone()))
+            .copied()
+            .unwrap_or_default())
+    }
+
+    /// Samples the balance `wallet_id` had in its primary asset at `from`
+    /// and every `interval` after it, up to and including `to`
+    ///
+    /// Equivalent to calling [`balance_at`](Self::balance_at) at each
+    /// point, in a single pass over the log.
+    ///
+    /// # Example
+    /// ```
+    /// use securevault::{amount, CustodySystem, Timestamp, WalletType};
+    /// use std::time::Duration;
+    /// let mut system = CustodySystem::new();
+    /// system.create_wallet("w".to_string(), "0x1".to_string(), WalletType::Hot).unwrap();
+    /// system.deposit("w", amount!(5)).unwrap();
+    ///
+    /// let now = Timestamp::now().as_unix();
+    /// let series = system
+    ///     .balance_series(
+    ///         "w",
+    ///         Timestamp::from_unix(now - 3600),
+    ///         Timestamp::from_unix(now + 3600),
+    ///         Duration::from_secs(3600),
+    ///     )
+    ///     .unwrap();
+    /// let balances: Vec<_> = series.iter().map(|point| point.balance).collect();
+    /// assert_eq!(balances, [amount!(0), amount!(5), amount!(5)]);
+    /// ```
+    pub fn balance_series(
+        &self,
+        wallet_id: &str,
+        from: Timestamp,
+        to: Timestamp,
+        interval: Duration,
+    ) -> Result<Vec<BalancePoint>, CustodyError> {
+        let wallet = self
+            .get_wallet(wallet_id)
+            .ok_or_else(|| CustodyError::WalletNotFound(wallet_id.to_string()))?;
+        let step = interval.as_secs();
+        if step == 0 {
+            return Err(CustodyError::InvalidInterval);
+        }
+        let mut changes: Vec<(Timestamp, Amount)> = self
+            .transactions
+            .iter()
+            .filter(|tx| tx.involves(wallet_id) && tx.asset == wallet.asset)
+            .map(|tx| (tx.timestamp, tx.balance_change(wallet_id)))
+            .collect();
+        changes.sort_by_key(|(timestamp, _)| *timestamp);
+
+        let mut changes = changes.into_iter().peekable();
+        let mut balance = Amount::ZERO;
+        let mut series = Vec::new();
+        let mut at = from.as_unix();
+        while at <= to.as_unix() {
+            let point = Timestamp::from_unix(at);
+            while let Some((_, change)) = changes.next_if(|(timestamp, _)| *timestamp <= point) {
+                balance += change;
+            }
+            series.push(BalancePoint { at: point, balance });
+            at = match at.checked_add(step) {
+                Some(next) => next,
+                None => break,
+            };
+        }
+        Ok(series)
+    }
+
+    /// Reconstructs the wallets, balances, and transaction log as they were
+    /// at `at`
+    ///
+    /// Wallets created after `at` are absent; transactions recorded after
+    /// `at` are dropped and balances are replayed from what remains.
+    pub fn state_at(&self, at: Timestamp) -> HistoricalState {
+        let mut system = self.fork();
+        system.transactions.retain(|tx| tx.timestamp <= at);
+        system.next_transaction_id = system.transactions.last().map_or(0, |tx| tx.id) + 1;
+        system.wallets.retain(|_, wallet| wallet.created_at <= at);
+
+        let balances = Self::replay_balances(system.transactions.iter());
+        for (_, wallet) in system.wallets.iter_mut() {
+            wallet.balance = Amount::ZERO;
+            wallet.holdings.clear();
+        }
+        for ((id, asset), balance) in balances {
+            if let Some(wallet) = system.wallets.get_mut(&id) {
+                wallet.set_balance(&asset, balance);
+            }
+        }
+        HistoricalState { at, system }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Asset, Transaction, TransactionType, WalletType};
+
+    fn at(secs: u64) -> Timestamp {
+        Timestamp::from_unix(secs)
+    }
+
+    fn record(
+        system: &mut CustodySystem,
+        wallet: &str,
+        kind: TransactionType,
+        amount: Amount,
+        secs: u64,
+    ) {
+        let tx = Transaction::new(wallet, kind, amount, Asset::Btc, at(secs));
+        system.record_transaction(tx).unwrap();
+    }
+
+    fn system() -> CustodySystem {
+        let mut system = CustodySystem::new();
+        for (id, created) in [("a", 100), ("b", 250)] {
+            system
+                .create_wallet(id.to_string(), format!("0x{}", id), WalletType::Hot)
+                .unwrap();
+            system.wallets.get_mut(id).unwrap().created_at = at(created);
+        }
+        record(
+            &mut system,
+            "a",
+            TransactionType::Deposit,
+            amount!(10.0),
+            200,
+        );
+        record(
+            &mut system,
+            "a",
+            TransactionType::Withdrawal,
+            amount!(4.0),
+            300,
+        );
+        record(
+            &mut system,
+            "b",
+            TransactionType::Deposit,
+            amount!(1.0),
+            300,
+        );
+        system
+    }
+
+    #[test]
+    fn test_balance_at() {
+        let system = system();
+        assert_eq!(system.balance_at("a", at(150)).unwrap(), amount!(0.0));
+        assert_eq!(system.balance_at("a", at(200)).unwrap(), amount!(10.0));
+        assert_eq!(system.balance_at("a", at(300)).unwrap(), amount!(6.0));
+        assert!(system.balance_at("missing", at(300)).is_err());
+    }
+
+    #[test]
+    fn test_balance_series() {
+        let system = system();
+        let series = system
+            .balance_series("a", at(100), at(320), Duration::from_secs(100))
+            .unwrap();
+        assert_eq!(
+            series,
+            [
+                BalancePoint {
+                    at: at(100),
+                    balance: amount!(0.0)
+                },
+                BalancePoint {
+                    at: at(200),
+                    balance: amount!(10.0)
+                },
+                BalancePoint {
+                    at: at(300),
+                    balance: amount!(6.0)
+                },
+            ]
+        );
+        for point in &series {
+            assert_eq!(system.balance_at("a", point.at).unwrap(), point.balance);
+        }
+        assert!(system
+            .balance_series("a", at(400), at(300), Duration::from_secs(1))
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            system.balance_series("a", at(0), at(1), Duration::from_millis(10)),
+            Err(CustodyError::InvalidInterval)
+        );
+    }
+
+    #[test]
+    fn test_state_at_reconstructs_whole_system() {
+        let system = system();
+        let past = system.state_at(at(220));
+        assert_eq!(past.at(), at(220));
+        assert_eq!(past.wallet_count(), 1);
+        assert_eq!(past.get_wallet("a").unwrap().balance, amount!(10.0));
+        assert_eq!(past.get_total_balance(), amount!(10.0));
+        assert_eq!(past.get_all_transactions().len(), 1);
+        assert!(past.audit().passed());
+
+        let now = system.state_at(at(1000));
+        assert!(system.diff(&now).is_empty());
+    }
+}
@@ -0,0 +1,259 @@
+//! Balance holds.
+//!
+//! A hold earmarks part of a wallet's balance, e.g. for a withdrawal that
+//! is still being approved, without moving it. Held funds stay in the
+//! balance but cannot be withdrawn, transferred or converted until the
+//! hold is released, or captured, which withdraws them.
+
+use crate::precheck::Authorization;
+use crate::time::Timestamp;
+use crate::{Amount, Asset, CustodyError, CustodySystem, OperationKind, Wallet};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Identifier of a balance hold
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct HoldId(pub u64);
+
+impl fmt::Display for HoldId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Lifecycle of a hold
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HoldStatus {
+    /// The funds are earmarked
+    Active,
+    /// The hold was lifted and the funds are available again
+    Released,
+    /// The funds were withdrawn
+    Captured,
+}
+
+/// Funds earmarked in a wallet
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Hold {
+    pub id: HoldId,
+    pub wallet_id: String,
+    pub asset: Asset,
+    pub amount: Amount,
+    pub reason: String,
+    pub placed_at: Timestamp,
+    pub status: HoldStatus,
+}
+
+impl CustodySystem {
+    /// Earmarks `amount` of a wallet's primary asset
+    ///
+    /// # Example
+    /// ```
+    /// use securevault::{amount, CustodySystem, WalletType};
+    /// let mut system = CustodySystem::new();
+    /// system.create_wallet("w".to_string(), "0x1".to_string(), WalletType::Hot).unwrap();
+    /// system.deposit("w", amount!(10)).unwrap();
+    ///
+    /// let hold = system.place_hold("w", amount!(4), "payout #7").unwrap();
+    /// assert_eq!(system.available_balance("w"), Some(amount!(6)));
+    /// assert!(system.withdraw("w", amount!(7)).is_err());
+    ///
+    /// system.capture_hold(hold).unwrap();
+    /// assert_eq!(system.get_wallet("w").unwrap().balance, amount!(6));
+    /// ```
+    pub fn place_hold(
+        &mut self,
+        wallet_id: &str,
+        amount: Amount,
+        reason: &str,
+    ) -> Result<HoldId, CustodyError> {
+        if !amount.is_positive() {
+            return Err(CustodyError::NonPositiveAmount(OperationKind::Hold));
+        }
+        let wallet = self
+            .wallets
+            .get(wallet_id)
+            .ok_or_else(|| CustodyError::WalletNotFound(wallet_id.to_string()))?;
+        if let Some(reason) = self.debit_blockers(wallet).into_iter().next() {
+            return Err(reason);
+        }
+        let available = self.available_of(wallet, &wallet.asset);
+        if available < amount {
+            return Err(CustodyError::InsufficientBalance {
+                available,
+                requested: amount,
+            });
+        }
+        let asset = wallet.asset.clone();
+        let hold = Hold {
+            id: HoldId(self.allocate_operation_id()),
+            wallet_id: wallet_id.to_string(),
+            asset,
+            amount,
+            reason: reason.to_string(),
+            placed_at: self.current_timestamp(),
+            status: HoldStatus::Active,
+        };
+        let id = hold.id;
+        self.holds.insert(id, hold);
+        Ok(id)
+    }
+
+    /// Lifts an active hold, making the funds available again
+    pub fn release_hold(&mut self, id: HoldId) -> Result<(), CustodyError> {
+        self.active_hold(id)?;
+        self.set_hold_status(id, HoldStatus::Released);
+        Ok(())
+    }
+
+    /// Withdraws the held funds and closes the hold
+    ///
+    /// The withdrawal goes through the usual checks, so holds on wallets
+    /// that need approvals or signatures must be released before the
+    /// approved withdrawal executes. If the withdrawal fails the hold stays
+    /// active. A capture held for compliance review closes the hold; the
+    /// withdrawal executes if the review approves it.
+    pub fn capture_hold(&mut self, id: HoldId) -> Result<(), CustodyError> {
+        let hold = self.active_hold(id)?.clone();
+        self.set_hold_status(id, HoldStatus::Captured);
+        let result = self.execute_withdrawal(
+            &hold.wallet_id,
+            Some(&hold.asset),
+            hold.amount,
+            None,
+            Authorization::Direct,
+        );
+        if result.is_err() && !matches!(result, Err(CustodyError::FlaggedForReview(_))) {
+            self.set_hold_status(id, HoldStatus::Active);
+        }
+        result
+    }
+
+    /// Gets a hold by id
+    pub fn get_hold(&self, id: HoldId) -> Option<&Hold> {
+        self.holds.get(&id)
+    }
+
+    /// Returns the active holds on a wallet, oldest first
+    pub fn active_holds(&self, wallet_id: &str) -> Vec<&Hold> {
+        self.holds
+            .values()
+            .filter(|hold| hold.wallet_id == wallet_id && hold.status == HoldStatus::Active)
+            .collect()
+    }
+
+    /// Returns the wallet's primary-asset balance minus its active holds,
+    /// or `None` if the wallet does not exist
+    pub fn available_balance(&self, wallet_id: &str) -> Option<Amount> {
+        let wallet = self.wallets.get(wallet_id)?;
+        Some(self.available_of(wallet, &wallet.asset))
+    }
+
+    /// Balance of `asset` in `wallet` not earmarked by active holds
+    pub(crate) fn available_of(&self, wallet: &Wallet, asset: &Asset) -> Amount {
+        let held: Amount = self
+            .active_holds(&wallet.id)
+            .into_iter()
+            .filter(|hold| hold.asset == *asset)
+            .map(|hold| hold.amount)
+            .sum();
+        wallet.balance_of(asset) - held
+    }
+
+    fn active_hold(&self, id: HoldId) -> Result<&Hold, CustodyError> {
+        let hold = self.holds.get(&id).ok_or(CustodyError::HoldNotFound(id))?;
+        if hold.status != HoldStatus::Active {
+            return Err(CustodyError::HoldNotActive(id));
+        }
+        Ok(hold)
+    }
+
+    pub(crate) fn set_hold_status(&mut self, id: HoldId, status: HoldStatus) {
+        if let Some(hold) = self.holds.get_mut(&id) {
+            hold.status = status;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::WalletType;
+
+    fn system() -> CustodySystem {
+        let mut system = CustodySystem::new();
+        for id in ["a", "b"] {
+            system
+                .create_wallet(id.to_string(), format!("0x{}", id), WalletType::Hot)
+                .unwrap();
+        }
+        system.deposit("a", amount!(10)).unwrap();
+        system
+    }
+
+    #[test]
+    fn test_holds_reduce_available_balance() {
+        let mut system = system();
+        system.place_hold("a", amount!(3), "payout").unwrap();
+        let second = system.place_hold("a", amount!(5), "payout").unwrap();
+        assert_eq!(system.available_balance("a"), Some(amount!(2)));
+        assert_eq!(system.get_wallet("a").unwrap().balance, amount!(10));
+        assert_eq!(
+            system.place_hold("a", amount!(3), "payout"),
+            Err(CustodyError::InsufficientBalance {
+                available: amount!(2),
+                requested: amount!(3),
+            })
+        );
+
+        let blocked = Err(CustodyError::InsufficientBalance {
+            available: amount!(2),
+            requested: amount!(4),
+        });
+        assert_eq!(system.withdraw("a", amount!(4)), blocked);
+        assert_eq!(system.transfer("a", "b", amount!(4)), blocked);
+        system.withdraw("a", amount!(2)).unwrap();
+
+        system.release_hold(second).unwrap();
+        assert_eq!(system.available_balance("a"), Some(amount!(5)));
+        assert_eq!(active(&system), 1);
+    }
+
+    #[test]
+    fn test_capture_withdraws_held_funds() {
+        let mut system = system();
+        let hold = system.place_hold("a", amount!(4), "payout").unwrap();
+        system.capture_hold(hold).unwrap();
+        assert_eq!(system.get_wallet("a").unwrap().balance, amount!(6));
+        assert_eq!(system.available_balance("a"), Some(amount!(6)));
+        assert_eq!(system.get_hold(hold).unwrap().status, HoldStatus::Captured);
+        assert_eq!(
+            system.capture_hold(hold),
+            Err(CustodyError::HoldNotActive(hold))
+        );
+        assert_eq!(
+            system.release_hold(HoldId(99)),
+            Err(CustodyError::HoldNotFound(HoldId(99)))
+        );
+    }
+
+    #[test]
+    fn test_failed_capture_keeps_hold_active() {
+        let mut system = system();
+        let hold = system.place_hold("a", amount!(4), "payout").unwrap();
+        system.freeze_wallet("a", "review").unwrap();
+        assert_eq!(
+            system.capture_hold(hold),
+            Err(CustodyError::WalletFrozen("a".to_string()))
+        );
+        assert_eq!(active(&system), 1);
+        assert_eq!(
+            system.place_hold("b", amount!(0), "x"),
+            Err(CustodyError::NonPositiveAmount(OperationKind::Hold))
+        );
+    }
+
+    fn active(system: &CustodySystem) -> usize {
+        system.active_holds("a").len()
+    }
+}
@@ -0,0 +1,136 @@
+//! Minimal blocking HTTP/1.1 client for plain `http://` URLs.
+//!
+//! Shared by the webhook transport, the HTTP price oracle and the chain
+//! node clients so none of them pulls in a full client stack. One request
+//! per connection; TLS is left to transports built on a full HTTP client.
+
+use std::io::{BufRead, BufReader, Read, Write as _};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+/// Status code and body of a response
+pub(crate) struct Response {
+    pub status: u16,
+    /// Unused by webhooks, which only look at the status
+    #[cfg_attr(
+        not(any(feature = "price-http", feature = "bitcoin-rpc", feature = "eth-rpc")),
+        allow(dead_code)
+    )]
+    pub body: Vec<u8>,
+}
+
+/// Sends one request and reads the whole response
+pub(crate) fn send(
+    method: &str,
+    url: &str,
+    headers: &[(&str, String)],
+    body: &[u8],
+    timeout: Duration,
+) -> Result<Response, String> {
+    let rest = url
+        .strip_prefix("http://")
+        .ok_or_else(|| format!("unsupported URL '{}'", url))?;
+    let (authority, path) = match rest.find('/') {
+        Some(slash) => rest.split_at(slash),
+        None => (rest, "/"),
+    };
+    let address = if authority.contains(':') {
+        authority.to_string()
+    } else {
+        format!("{}:80", authority)
+    };
+    let address = address
+        .to_socket_addrs()
+        .map_err(|err| err.to_string())?
+        .next()
+        .ok_or_else(|| format!("cannot resolve '{}'", authority))?;
+    let stream = TcpStream::connect_timeout(&address, timeout).map_err(|err| err.to_string())?;
+    stream
+        .set_read_timeout(Some(timeout))
+        .map_err(|err| err.to_string())?;
+
+    let mut request = format!(
+        "{} {} HTTP/1.1\r\nHost: {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+        method,
+        path,
+        authority,
+        body.len()
+    );
+    for (name, value) in headers {
+        request.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    request.push_str("\r\n");
+    let mut writer = &stream;
+    writer
+        .write_all(request.as_bytes())
+        .and_then(|()| writer.write_all(body))
+        .and_then(|()| writer.flush())
+        .map_err(|err| err.to_string())?;
+
+    let mut reader = BufReader::new(&stream);
+    let mut status_line = String::new();
+    reader
+        .read_line(&mut status_line)
+        .map_err(|err| err.to_string())?;
+    let status = status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse().ok())
+        .ok_or_else(|| "malformed HTTP response".to_string())?;
+
+    let mut content_length = None;
+    let mut chunked = false;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).map_err(|err| err.to_string())? == 0 {
+            break;
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            let value = value.trim();
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.parse::<usize>().ok();
+            } else if name.eq_ignore_ascii_case("transfer-encoding") {
+                chunked = value.eq_ignore_ascii_case("chunked");
+            }
+        }
+    }
+
+    let body = if chunked {
+        read_chunked(&mut reader)?
+    } else if let Some(length) = content_length {
+        let mut body = vec![0; length];
+        reader
+            .read_exact(&mut body)
+            .map_err(|err| err.to_string())?;
+        body
+    } else {
+        let mut body = Vec::new();
+        reader
+            .read_to_end(&mut body)
+            .map_err(|err| err.to_string())?;
+        body
+    };
+    Ok(Response { status, body })
+}
+
+fn read_chunked(reader: &mut impl BufRead) -> Result<Vec<u8>, String> {
+    let mut body = Vec::new();
+    loop {
+        let mut size = String::new();
+        reader.read_line(&mut size).map_err(|err| err.to_string())?;
+        let size = size.trim().split(';').next().unwrap_or_default();
+        let size = usize::from_str_radix(size, 16).map_err(|_| "malformed chunk".to_string())?;
+        let mut chunk = vec![0; size + 2];
+        reader
+            .read_exact(&mut chunk)
+            .map_err(|err| err.to_string())?;
+        if size == 0 {
+            return Ok(body);
+        }
+        body.extend_from_slice(&chunk[..size]);
+    }
+}
@@ -0,0 +1,371 @@
+//! Hardware wallet signers (feature `hw-wallet`).
+//!
+//! Ledger and Trezor devices are reached through a [`DeviceTransport`],
+//! which lists the connected devices of one vendor and forwards signing
+//! requests to them. The transport blocks until the holder confirms or
+//! declines the transaction on the device's screen, so a signature from a
+//! [`HardwareWalletSigner`] proves someone physically approved it. USB/HID
+//! framing (Ledger APDUs, Trezor protobuf messages) is left to the vendor
+//! libraries the transports wrap, which keeps native dependencies out of
+//! this crate.
+//!
+//! [`CustodySystem::bind_device`] ties a wallet to one device key: from
+//! then on [`CustodySystem::submit_signed`] only accepts that key's
+//! signatures for the wallet, so its withdrawals cannot be approved
+//! without the device.
+
+use crate::signer::{Signature, Signer};
+use crate::{CustodyError, CustodySystem};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::Arc;
+
+/// Hardware wallet vendor
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum DeviceKind {
+    Ledger,
+    Trezor,
+}
+
+impl DeviceKind {
+    /// Returns a stable lower-case name for the vendor
+    pub fn name(&self) -> &'static str {
+        match self {
+            DeviceKind::Ledger => "ledger",
+            DeviceKind::Trezor => "trezor",
+        }
+    }
+}
+
+impl fmt::Display for DeviceKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// A connected hardware wallet
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HardwareDevice {
+    pub kind: DeviceKind,
+    /// Serial number or other identifier stable across connections
+    pub id: String,
+    /// Model name, e.g. `Nano X` or `Model T`
+    pub model: String,
+}
+
+/// What the device holder did with a signing request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeviceReply {
+    /// Confirmed on the device, which returned the signature
+    Signed(Vec<u8>),
+    /// Declined on the device
+    Declined,
+}
+
+/// Connection to the hardware wallets of one vendor
+pub trait DeviceTransport: fmt::Debug + Send + Sync {
+    /// Vendor of the devices this transport reaches
+    fn kind(&self) -> DeviceKind;
+
+    /// Lists the devices currently connected
+    fn devices(&self) -> Result<Vec<HardwareDevice>, String>;
+
+    /// Asks device `device_id` to sign `payload` with the key at
+    /// derivation `path`, waiting for the holder to confirm or decline
+    fn sign(&self, device_id: &str, path: &str, payload: &[u8]) -> Result<DeviceReply, String>;
+}
+
+/// Lists the devices connected through any of `transports`
+///
+/// Transports that fail to enumerate, e.g. because the vendor's bridge is
+/// not running, contribute no devices.
+pub fn detect_devices(transports: &[Arc<dyn DeviceTransport>]) -> Vec<HardwareDevice> {
+    transports
+        .iter()
+        .filter_map(|transport| transport.devices().ok())
+        .flatten()
+        .collect()
+}
+
+/// A key on a particular hardware wallet
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeviceBinding {
+    pub kind: DeviceKind,
+    pub device_id: String,
+    /// Derivation path of the key, e.g. `m/44'/0'/0'`
+    pub path: String,
+}
+
+impl DeviceBinding {
+    /// Returns the key id signatures made with the key carry
+    pub fn key_id(&self) -> String {
+        format!("{}:{}/{}", self.kind, self.device_id, self.path)
+    }
+}
+
+/// Signs on a hardware wallet, after the holder confirms on the device
+#[derive(Debug, Clone)]
+pub struct HardwareWalletSigner {
+    transport: Arc<dyn DeviceTransport>,
+    binding: DeviceBinding,
+}
+
+impl HardwareWalletSigner {
+    /// Signs with the key at `path` on device `device_id`
+    pub fn new(transport: Arc<dyn DeviceTransport>, device_id: &str, path: &str) -> Self {
+        let binding = DeviceBinding {
+            kind: transport.kind(),
+            device_id: device_id.to_string(),
+            path: path.to_string(),
+        };
+        Self { transport, binding }
+    }
+
+    /// Returns the device key the signer uses, for
+    /// [`CustodySystem::bind_device`]
+    pub fn binding(&self) -> &DeviceBinding {
+        &self.binding
+    }
+}
+
+impl Signer for HardwareWalletSigner {
+    fn key_id(&self) -> String {
+        self.binding.key_id()
+    }
+
+    fn sign(&self, payload: &[u8]) -> Result<Signature, CustodyError> {
+        let device = format!("{} device {}", self.binding.kind, self.binding.device_id);
+        let connected = self
+            .transport
+            .devices()
+            .map_err(CustodyError::SigningFailed)?
+            .iter()
+            .any(|found| found.id == self.binding.device_id);
+        if !connected {
+            return Err(CustodyError::SigningFailed(format!(
+                "{} is not connected",
+                device
+            )));
+        }
+        let reply = self
+            .transport
+            .sign(&self.binding.device_id, &self.binding.path, payload)
+            .map_err(CustodyError::SigningFailed)?;
+        match reply {
+            DeviceReply::Signed(bytes) => Ok(Signature {
+                key_id: self.key_id(),
+                bytes,
+            }),
+            DeviceReply::Declined => Err(CustodyError::SigningFailed(format!(
+                "declined on {}",
+                device
+            ))),
+        }
+    }
+}
+
+impl CustodySystem {
+    /// Requires a wallet's signed withdrawals to be signed by `binding`'s
+    /// device key, replacing any previous binding
+    ///
+    /// # Example
+    /// ```
+    /// use securevault::{amount, CallbackSigner, CustodySystem, DeviceBinding, DeviceKind, WalletType};
+    /// let mut system = CustodySystem::new();
+    /// system.create_wallet("cold".to_string(), "0x1".to_string(), WalletType::Cold).unwrap();
+    /// system.deposit("cold", amount!(10)).unwrap();
+    /// let ledger = DeviceBinding {
+    ///     kind: DeviceKind::Ledger,
+    ///     device_id: "0001".to_string(),
+    ///     path: "m/44'/60'/0'/0/0".to_string(),
+    /// };
+    /// system.bind_device("cold", ledger).unwrap();
+    ///
+    /// let software = CallbackSigner::new("software", |payload| Ok(payload.to_vec()));
+    /// assert!(system.withdraw_signed("cold", "0xdest", amount!(1), &software).is_err());
+    /// ```
+    pub fn bind_device(
+        &mut self,
+        wallet_id: &str,
+        binding: DeviceBinding,
+    ) -> Result<(), CustodyError> {
+        if !self.wallets.contains_key(wallet_id) {
+            return Err(CustodyError::WalletNotFound(wallet_id.to_string()));
+        }
+        self.device_bindings.insert(wallet_id.to_string(), binding);
+        Ok(())
+    }
+
+    /// Lifts a wallet's device binding, returning it if there was one
+    pub fn unbind_device(&mut self, wallet_id: &str) -> Option<DeviceBinding> {
+        self.device_bindings.remove(wallet_id)
+    }
+
+    /// Returns the device key a wallet is bound to
+    pub fn device_binding(&self, wallet_id: &str) -> Option<&DeviceBinding> {
+        self.device_bindings.get(wallet_id)
+    }
+
+    /// Refuses signatures by any other key than a bound wallet's device
+    pub(crate) fn check_device_binding(
+        &self,
+        wallet_id: &str,
+        signature: &Signature,
+    ) -> Result<(), CustodyError> {
+        match self.device_bindings.get(wallet_id) {
+            Some(binding) if signature.key_id != binding.key_id() => {
+                Err(CustodyError::SignatureRejected(format!(
+                    "{} must be signed on {} device {}",
+                    wallet_id, binding.kind, binding.device_id
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::WalletType;
+    use std::sync::Mutex;
+
+    /// A single device whose holder answers with `confirm`
+    #[derive(Debug)]
+    struct FakeLedger {
+        plugged_in: Mutex<bool>,
+        confirm: Mutex<bool>,
+    }
+
+    impl FakeLedger {
+        fn new() -> Arc<Self> {
+            Arc::new(Self {
+                plugged_in: Mutex::new(true),
+                confirm: Mutex::new(true),
+            })
+        }
+    }
+
+    impl DeviceTransport for FakeLedger {
+        fn kind(&self) -> DeviceKind {
+            DeviceKind::Ledger
+        }
+
+        fn devices(&self) -> Result<Vec<HardwareDevice>, String> {
+            let plugged_in = *self.plugged_in.lock().unwrap();
+            Ok(plugged_in
+                .then(|| HardwareDevice {
+                    kind: DeviceKind::Ledger,
+                    id: "0001".to_string(),
+                    model: "Nano X".to_string(),
+                })
+                .into_iter()
+                .collect())
+        }
+
+        fn sign(
+            &self,
+            _device_id: &str,
+            _path: &str,
+            payload: &[u8],
+        ) -> Result<DeviceReply, String> {
+            Ok(if *self.confirm.lock().unwrap() {
+                DeviceReply::Signed(payload[..4].to_vec())
+            } else {
+                DeviceReply::Declined
+            })
+        }
+    }
+
+    /// A vendor bridge that is not running
+    #[derive(Debug)]
+    struct NoBridge;
+
+    impl DeviceTransport for NoBridge {
+        fn kind(&self) -> DeviceKind {
+            DeviceKind::Trezor
+        }
+
+        fn devices(&self) -> Result<Vec<HardwareDevice>, String> {
+            Err("bridge unreachable".to_string())
+        }
+
+        fn sign(&self, _: &str, _: &str, _: &[u8]) -> Result<DeviceReply, String> {
+            Err("bridge unreachable".to_string())
+        }
+    }
+
+    fn system() -> CustodySystem {
+        let mut system = CustodySystem::new();
+        system
+            .create_wallet("cold".to_string(), "0x1".to_string(), WalletType::Cold)
+            .unwrap();
+        system.deposit("cold", amount!(10)).unwrap();
+        system
+    }
+
+    #[test]
+    fn test_detection_and_device_confirmation() {
+        let ledger = FakeLedger::new();
+        let transports: Vec<Arc<dyn DeviceTransport>> = vec![ledger.clone(), Arc::new(NoBridge)];
+        let devices = detect_devices(&transports);
+        assert_eq!(devices.len(), 1);
+        assert_eq!(
+            (devices[0].kind, devices[0].model.as_str()),
+            (DeviceKind::Ledger, "Nano X")
+        );
+
+        let mut system = system();
+        let signer = HardwareWalletSigner::new(ledger.clone(), "0001", "m/44'/0'/0'");
+        assert_eq!(signer.key_id(), "ledger:0001/m/44'/0'/0'");
+        *ledger.confirm.lock().unwrap() = false;
+        assert_eq!(
+            system.withdraw_signed("cold", "0xdest", amount!(1), &signer),
+            Err(CustodyError::SigningFailed(
+                "declined on ledger device 0001".to_string()
+            ))
+        );
+        *ledger.plugged_in.lock().unwrap() = false;
+        assert_eq!(
+            signer.sign(b"payload"),
+            Err(CustodyError::SigningFailed(
+                "ledger device 0001 is not connected".to_string()
+            ))
+        );
+
+        *ledger.plugged_in.lock().unwrap() = true;
+        *ledger.confirm.lock().unwrap() = true;
+        system
+            .withdraw_signed("cold", "0xdest", amount!(1), &signer)
+            .unwrap();
+        assert_eq!(system.get_wallet("cold").unwrap().balance, amount!(9));
+    }
+
+    #[test]
+    fn test_bound_wallets_need_their_device() {
+        let mut system = system();
+        let ledger = HardwareWalletSigner::new(FakeLedger::new(), "0001", "m/44'/0'/0'");
+        let other = HardwareWalletSigner::new(FakeLedger::new(), "0001", "m/44'/0'/1'");
+        system
+            .bind_device("cold", ledger.binding().clone())
+            .unwrap();
+        assert_eq!(
+            system.withdraw_signed("cold", "0xdest", amount!(1), &other),
+            Err(CustodyError::SignatureRejected(
+                "cold must be signed on ledger device 0001".to_string()
+            ))
+        );
+        assert!(system.unsigned_transactions().is_empty());
+        system
+            .withdraw_signed("cold", "0xdest", amount!(1), &ledger)
+            .unwrap();
+
+        assert_eq!(system.unbind_device("cold"), Some(ledger.binding().clone()));
+        system
+            .withdraw_signed("cold", "0xdest", amount!(1), &other)
+            .unwrap();
+        assert!(system
+            .bind_device("nope", ledger.binding().clone())
+            .is_err());
+    }
+}
@@ -0,0 +1,715 @@
+//! Localization of customer-facing text.
+//!
+//! Messages are looked up by key in a per-locale catalog and rendered by
+//! substituting positional `{0}`, `{1}`, ... placeholders. Keys missing from
+//! a catalog fall back to English so a partially translated locale never
+//! produces empty text.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Supported locales
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub enum Locale {
+    /// English
+    #[default]
+    En,
+    /// Brazilian Portuguese
+    PtBr,
+    /// Spanish
+    Es,
+}
+
+impl Locale {
+    /// All supported locales
+    pub const ALL: [Locale; 3] = [Locale::En, Locale::PtBr, Locale::Es];
+
+    /// Returns the BCP 47 language tag for the locale
+    pub fn tag(&self) -> &'static str {
+        match self {
+            Locale::En => "en",
+            Locale::PtBr => "pt-BR",
+            Locale::Es => "es",
+        }
+    }
+
+    /// Parses a BCP 47 language tag, matching on the primary language when
+    /// the exact region is not supported (e.g. `es-MX` maps to `es`)
+    pub fn from_tag(tag: &str) -> Option<Locale> {
+        let tag = tag.trim().replace('_', "-").to_ascii_lowercase();
+        let primary = tag.split('-').next().unwrap_or_default();
+        match primary {
+            "en" => Some(Locale::En),
+            "pt" => Some(Locale::PtBr),
+            "es" => Some(Locale::Es),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Locale {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.tag())
+    }
+}
+
+/// Labels and headings used in statements and reports
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Label {
+    Statement,
+    Report,
+    Wallet,
+    Date,
+    Description,
+    Amount,
+    Balance,
+    OpeningBalance,
+    ClosingBalance,
+    TotalBalance,
+    Deposit,
+    Withdrawal,
+    Transfer,
+    Conversion,
+    Reward,
+    Total,
+}
+
+impl Label {
+    fn key(&self) -> &'static str {
+        match self {
+            Label::Statement => "label.statement",
+            Label::Report => "label.report",
+            Label::Wallet => "label.wallet",
+            Label::Date => "label.date",
+            Label::Description => "label.description",
+            Label::Amount => "label.amount",
+            Label::Balance => "label.balance",
+            Label::OpeningBalance => "label.opening_balance",
+            Label::ClosingBalance => "label.closing_balance",
+            Label::TotalBalance => "label.total_balance",
+            Label::Deposit => "label.deposit",
+            Label::Withdrawal => "label.withdrawal",
+            Label::Transfer => "label.transfer",
+            Label::Conversion => "label.conversion",
+            Label::Reward => "label.reward",
+            Label::Total => "label.total",
+        }
+    }
+
+    /// Returns the label text in the given locale
+    pub fn text(&self, locale: Locale) -> &'static str {
+        lookup(locale, self.key())
+    }
+}
+
+/// Looks up the template for `key`, falling back to English
+pub fn lookup(locale: Locale, key: &str) -> &'static str {
+    let found = match locale {
+        Locale::En => en(key),
+        Locale::PtBr => pt_br(key),
+        Locale::Es => es(key),
+    };
+    found.or_else(|| en(key)).unwrap_or("")
+}
+
+/// Renders the message for `key` in `locale`, substituting `args` for the
+/// positional placeholders
+pub fn render(locale: Locale, key: &str, args: &[String]) -> String {
+    let mut message = lookup(locale, key).to_string();
+    for (i, arg) in args.iter().enumerate() {
+        message = message.replace(&format!("{{{}}}", i), arg);
+    }
+    message
+}
+
+fn en(key: &str) -> Option<&'static str> {
+    Some(match key {
+        "op.deposit" => "Deposit",
+        "op.withdrawal" => "Withdrawal",
+        "op.transfer" => "Transfer",
+        "op.conversion" => "Conversion",
+        "op.hold" => "Hold",
+        "error.wallet_already_exists" => "Wallet with id '{0}' already exists",
+        "error.wallet_not_found" => "Wallet '{0}' not found",
+        "error.source_wallet_not_found" => "Source wallet '{0}' not found",
+        "error.destination_wallet_not_found" => "Destination wallet '{0}' not found",
+        "error.non_positive_amount" => "{0} amount must be positive",
+        "error.insufficient_balance" => "Insufficient balance: {0} available, {1} requested",
+        "error.same_wallet" => "Cannot transfer to the same wallet",
+        "error.asset_mismatch" => "Asset mismatch: expected {0}, found {1}",
+        "error.rate_unavailable" => "No exchange rate available from {0} to {1}",
+        "error.invalid_rate" => "Invalid exchange rate: {0}",
+        "error.template_not_found" => "Wallet template '{0}' not found",
+        "error.invalid_destination" => "Invalid destination address '{0}'",
+        "error.cosignature_required" => "Withdrawals from joint wallet '{0}' must be co-signed",
+        "error.not_joint_wallet" => "Wallet '{0}' is not a joint wallet",
+        "error.already_joint" => "Wallet '{0}' is already a joint wallet",
+        "error.not_an_owner" => "'{0}' is not an owner of wallet '{1}'",
+        "error.invalid_threshold" => "Invalid threshold: {0} required but {1} signers available",
+        "error.operation_not_found" => "Operation {0} not found",
+        "error.operation_not_pending" => "Operation {0} is no longer pending",
+        "error.transaction_not_found" => "Transaction {0} not found",
+        "error.not_fiat_wallet" => "Wallet '{0}' is not a fiat wallet",
+        "error.gateway" => "Gateway error: {0}",
+        "error.duplicate_reference" => "Reference '{0}' was already booked",
+        "error.not_hot_wallet" => "Wallet '{0}' is not a hot wallet",
+        "error.not_cold_wallet" => "Wallet '{0}' is not a cold wallet",
+        "error.seed_export_not_confirmed" => "Seed export for wallet '{0}' was not confirmed",
+        "error.backup_failed" => "Backup failed: {0}",
+        "error.invalid_ur" => "Invalid UR data: {0}",
+        "error.id_scheme_not_found" => "Wallet id scheme '{0}' not found",
+        "error.id_collision" => "Derived wallet id '{0}' is already used by another wallet",
+        "error.extension_not_found" => "No {0} extension registered as '{1}'",
+        "error.extension_failed" => "Extension '{0}' could not be built: {1}",
+        "error.script_rejected" => "Blocked by script '{0}': {1}",
+        "error.script_error" => "Script '{0}' failed: {1}",
+        "error.transaction_id_not_found" => "No transaction with id {0}",
+        "error.digest_mismatch" => "Transaction {0} does not match its sealed digest",
+        "error.injected_fault" => "Injected fault: {0}",
+        "error.queued_withdrawal_not_found" => "No queued withdrawal with id {0}",
+        "error.amount_overflow" => "Amount out of range",
+        "error.storage_failed" => "Storage failed: {0}",
+        "error.approval_required" => "Withdrawals from cold wallet '{0}' require approval",
+        "error.no_approvers" => "No withdrawal approvers configured for wallet '{0}'",
+        "error.not_an_approver" => "'{0}' is not an approver for wallet '{1}'",
+        "error.self_approval" => "Withdrawal {0} cannot be approved by its requester",
+        "error.idempotency_conflict" => {
+            "Idempotency key {0} was already used for a different operation"
+        }
+        "error.audit_chain_broken" => "Audit chain broken at transaction {0}",
+        "error.policy_per_transaction" => "Withdrawal of {1} from wallet '{0}' exceeds the per-transaction maximum of {2}",
+        "error.policy_daily_limit" => "Withdrawal of {1} from wallet '{0}' would exceed the daily limit of {2} ({3} already withdrawn)",
+        "error.policy_weekly_limit" => "Withdrawal of {1} from wallet '{0}' would exceed the weekly limit of {2} ({3} already withdrawn)",
+        "error.policy_cooldown" => "Wallet '{0}' cannot withdraw for another {1} seconds",
+        "error.invalid_address" => "Invalid {1} address '{0}': {2}",
+        "error.no_address_validator" => "No address validator for chain '{0}'",
+        "error.invalid_snapshot" => "Invalid snapshot: {0}",
+        "error.unsupported_snapshot_version" => "Unsupported snapshot version {0}",
+        "error.signatures_required" => "Withdrawals from multi-signature wallet '{0}' must be signed",
+        "error.not_multisig_wallet" => "Wallet '{0}' is not a multi-signature wallet",
+        "error.not_a_signer" => "'{0}' is not a signer of wallet '{1}'",
+        "error.wallet_frozen" => "Wallet '{0}' is frozen",
+        "error.wallet_closed" => "Wallet '{0}' is closed",
+        "error.invalid_status_transition" => "Wallet '{0}' cannot go from {1} to {2}",
+        "error.hold_not_found" => "Hold {0} not found",
+        "error.hold_not_active" => "Hold {0} is no longer active",
+        "error.invalid_xpub" => "Invalid extended public key: {0}",
+        "error.not_an_hd_wallet" => "Wallet '{0}' does not derive addresses from an extended public key",
+        "error.vault_locked" => "The key vault is locked",
+        "error.invalid_passphrase" => "Invalid key vault passphrase",
+        "error.key_not_found" => "No signing key stored for wallet '{0}'",
+        "error.key_vault_failed" => "Key vault failure: {0}",
+        "error.signing_failed" => "Signing failed: {0}",
+        "error.signature_rejected" => "Signature rejected: {0}",
+        "error.unsigned_tx_not_found" => "No prepared withdrawal {0} awaits a signature",
+        "error.batch_aborted" => "Batch aborted: operation {0} failed",
+        "error.invalid_interval" => "The interval must be at least one second",
+        "error.screening_blocked" => "Blocked by compliance screening: {0}",
+        "error.flagged_for_review" => "Held for compliance review as {0}",
+        "error.unknown_deposit_address" => "No wallet receives deposits at address {0}",
+        "error.deposit_mismatch" => "Deposit {0} was already reported with a different amount",
+        "error.deposit_not_found" => "No deposit seen with transaction hash {0}",
+        "error.account_not_found" => "Account not found: {0}",
+        "error.account_already_exists" => "Account already exists: {0}",
+        "error.organization_not_found" => "Organization not found: {0}",
+        "error.organization_already_exists" => "Organization already exists: {0}",
+        "error.customer_not_found" => "Customer not found: {0}",
+        "error.customer_already_exists" => "Customer already exists: {0}",
+        "error.wallet_not_empty" => "Wallet {0} must be empty",
+        "error.not_customer_wallet" => "Wallet '{0}' does not hold funds of customer '{1}'",
+        "error.claims_mismatch" => "Customer claims on wallet '{0}' total {1} but its balance is {2}",
+        "error.already_reversed" => "Transaction {0} has already been reversed",
+        "error.invalid_config" => "Invalid configuration: {0}",
+        "error.event_out_of_order" => "Expected event {0}, got event {1}",
+        "error.event_rejected" => "Event {0} does not apply to the current state",
+        "error.dual_control_required" => "{0} needs confirmation by a second operator",
+        "error.action_expired" => "Proposed action {0} has expired",
+        "error.address_not_whitelisted" => "Address {0} is not whitelisted for wallet {1}",
+        "error.whitelist_not_active" => "Whitelisted address {0} becomes active at {1}",
+        "error.travel_rule_required" => "Withdrawal of {0} needs travel-rule information at or above {1}",
+        "error.incomplete_travel_rule" => "Travel-rule field {0} is required",
+        "error.invalid_api_key" => "Invalid API key",
+        "error.api_key_not_found" => "API key '{0}' not found",
+        "error.api_key_expired" => "API key '{0}' has expired",
+        "error.api_key_revoked" => "API key '{0}' has been revoked",
+        "error.scope_denied" => "API key '{0}' is not allowed to {1} here",
+        "error.rate_limited" => "Rate limit reached for {0}; retry in {1} s",
+        "error.invalid_batch_file" => "Invalid operations file: {0}",
+        "error.tenant_not_found" => "Tenant not found: {0}",
+        "error.tenant_already_exists" => "Tenant already exists: {0}",
+        "error.tenant_quota_exceeded" => "Tenant {0} has reached its quota of {1} wallets",
+        "error.period_closed" => "Accounting period closed up to {0}",
+        "error.period_not_ended" => "Cannot close a period ending at {0}, which has not passed yet",
+        "error.alert_not_found" => "Alert not found: {0}",
+        "error.alert_already_resolved" => "Alert {0} has already been resolved",
+        "error.commitment_not_found" => "State commitment not found: {0}",
+        "error.invalid_import_row" => "Invalid import row: {0}",
+        "error.invalid_import_file" => "Invalid import file: {0}",
+        "error.node" => "Chain node error: {0}",
+        "error.broadcast_not_found" => "No broadcast withdrawal {0} awaits confirmations",
+        "error.not_replaceable" => "Broadcast withdrawal {0} cannot be replaced",
+        "error.fee_unavailable" => "Network fee unavailable: {0}",
+        "error.key_generation_failed" => "Key generation failed: {0}",
+        "error.address_in_use" => "Address already in use: {0}",
+        "error.invalid_key_share" => "Invalid key share: {0}",
+        "error.invalid_mnemonic" => "Invalid mnemonic: {0}",
+        "error.invalid_psbt" => "Invalid PSBT: {0}",
+        "error.invalid_multisig_keys" => "Invalid multi-signature keys: {0}",
+        "error.unsupported_backup_version" => "Unsupported backup format version {0}",
+        "error.wallet_in_use" => "Wallet '{0}' has operations in progress",
+        "error.joint_not_allowed" => "Wallet '{0}' has its own withdrawal approvals and cannot be made joint",
+        "error.destination_required" => "Withdrawals from wallet '{0}' must name a whitelisted destination",
+        "error.approvers_already_set" => "Wallet '{0}' already has withdrawal approvers; propose a change instead",
+        "error.unpaired_conversion_leg" => "Conversion leg {0} has no matching opposite leg and cannot be reversed alone",
+        "notify.deposit_received.subject" => "Deposit received",
+        "notify.deposit_received.body" => "We received {amount} in wallet {wallet}.",
+        "notify.withdrawal_approved.subject" => "Withdrawal approved",
+        "notify.withdrawal_approved.body" => {
+            "Your withdrawal of {amount} from wallet {wallet} was approved."
+        }
+        "notify.withdrawal_settled.subject" => "Withdrawal settled",
+        "notify.withdrawal_settled.body" => {
+            "Your withdrawal of {amount} from wallet {wallet} was settled."
+        }
+        "notify.wallet_frozen.subject" => "Wallet frozen",
+        "notify.wallet_frozen.body" => "Wallet {wallet} was frozen: {reason}",
+        "label.statement" => "Statement",
+        "label.report" => "Report",
+        "label.wallet" => "Wallet",
+        "label.date" => "Date",
+        "label.description" => "Description",
+        "label.amount" => "Amount",
+        "label.balance" => "Balance",
+        "label.opening_balance" => "Opening balance",
+        "label.closing_balance" => "Closing balance",
+        "label.total_balance" => "Total balance",
+        "label.deposit" => "Deposit",
+        "label.withdrawal" => "Withdrawal",
+        "label.transfer" => "Transfer",
+        "label.conversion" => "Conversion",
+        "label.reward" => "Reward",
+        "label.total" => "Total",
+        _ => return None,
+    })
+}
+
+fn pt_br(key: &str) -> Option<&'static str> {
+    Some(match key {
+        "op.deposit" => "depósito",
+        "op.withdrawal" => "saque",
+        "op.transfer" => "transferência",
+        "op.conversion" => "conversão",
+        "op.hold" => "bloqueio",
+        "error.wallet_already_exists" => "Carteira com id '{0}' já existe",
+        "error.wallet_not_found" => "Carteira '{0}' não encontrada",
+        "error.source_wallet_not_found" => "Carteira de origem '{0}' não encontrada",
+        "error.destination_wallet_not_found" => "Carteira de destino '{0}' não encontrada",
+        "error.non_positive_amount" => "O valor de {0} deve ser positivo",
+        "error.insufficient_balance" => "Saldo insuficiente: {0} disponível, {1} solicitado",
+        "error.same_wallet" => "Não é possível transferir para a mesma carteira",
+        "error.asset_mismatch" => "Ativo incompatível: esperado {0}, encontrado {1}",
+        "error.rate_unavailable" => "Nenhuma taxa de câmbio disponível de {0} para {1}",
+        "error.invalid_rate" => "Taxa de câmbio inválida: {0}",
+        "error.template_not_found" => "Modelo de carteira '{0}' não encontrado",
+        "error.invalid_destination" => "Endereço de destino inválido '{0}'",
+        "error.cosignature_required" => "Saques da carteira conjunta '{0}' exigem coassinatura",
+        "error.not_joint_wallet" => "A carteira '{0}' não é uma carteira conjunta",
+        "error.already_joint" => "A carteira '{0}' já é uma carteira conjunta",
+        "error.not_an_owner" => "'{0}' não é titular da carteira '{1}'",
+        "error.invalid_threshold" => {
+            "Limite inválido: {0} exigidos, mas {1} signatários disponíveis"
+        }
+        "error.operation_not_found" => "Operação {0} não encontrada",
+        "error.operation_not_pending" => "A operação {0} não está mais pendente",
+        "error.transaction_not_found" => "Transação {0} não encontrada",
+        "error.not_fiat_wallet" => "A carteira '{0}' não é uma carteira fiduciária",
+        "error.gateway" => "Erro do gateway: {0}",
+        "error.duplicate_reference" => "A referência '{0}' já foi lançada",
+        "error.not_hot_wallet" => "A carteira '{0}' não é uma carteira quente",
+        "error.not_cold_wallet" => "A carteira '{0}' não é uma carteira fria",
+        "error.seed_export_not_confirmed" => {
+            "A exportação da semente da carteira '{0}' não foi confirmada"
+        }
+        "error.backup_failed" => "Falha no backup: {0}",
+        "error.invalid_ur" => "Dados UR inválidos: {0}",
+        "error.id_scheme_not_found" => "Esquema de id de carteira '{0}' não encontrado",
+        "error.id_collision" => "O id de carteira derivado '{0}' já é usado por outra carteira",
+        "error.extension_not_found" => "Nenhuma extensão {0} registrada como '{1}'",
+        "error.extension_failed" => "Não foi possível criar a extensão '{0}': {1}",
+        "error.script_rejected" => "Bloqueado pelo script '{0}': {1}",
+        "error.script_error" => "O script '{0}' falhou: {1}",
+        "error.transaction_id_not_found" => "Nenhuma transação com id {0}",
+        "error.digest_mismatch" => "A transação {0} não corresponde ao seu digest selado",
+        "error.injected_fault" => "Falha injetada: {0}",
+        "error.queued_withdrawal_not_found" => "Nenhum saque na fila com id {0}",
+        "error.amount_overflow" => "Valor fora do intervalo permitido",
+        "error.storage_failed" => "Falha no armazenamento: {0}",
+        "error.approval_required" => "Saques da carteira fria '{0}' exigem aprovação",
+        "error.no_approvers" => "Nenhum aprovador de saques configurado para a carteira '{0}'",
+        "error.not_an_approver" => "'{0}' não é aprovador da carteira '{1}'",
+        "error.self_approval" => "O saque {0} não pode ser aprovado por quem o solicitou",
+        "error.idempotency_conflict" => {
+            "A chave de idempotência {0} já foi usada em outra operação"
+        }
+        "error.audit_chain_broken" => "Cadeia de auditoria rompida na transação {0}",
+        "error.policy_per_transaction" => {
+            "O saque de {1} da carteira '{0}' excede o máximo por transação de {2}"
+        }
+        "error.policy_daily_limit" => {
+            "O saque de {1} da carteira '{0}' excederia o limite diário de {2} ({3} já sacados)"
+        }
+        "error.policy_weekly_limit" => {
+            "O saque de {1} da carteira '{0}' excederia o limite semanal de {2} ({3} já sacados)"
+        }
+        "error.policy_cooldown" => "A carteira '{0}' não pode sacar por mais {1} segundos",
+        "error.invalid_address" => "Endereço {1} inválido '{0}': {2}",
+        "error.no_address_validator" => "Nenhum validador de endereço para a rede '{0}'",
+        "error.invalid_snapshot" => "Snapshot inválido: {0}",
+        "error.unsupported_snapshot_version" => "Versão de snapshot não suportada: {0}",
+        "error.signatures_required" => {
+            "Saques da carteira multiassinatura '{0}' exigem assinaturas"
+        }
+        "error.not_multisig_wallet" => "A carteira '{0}' não é uma carteira multiassinatura",
+        "error.not_a_signer" => "'{0}' não é signatário da carteira '{1}'",
+        "error.wallet_frozen" => "A carteira '{0}' está bloqueada",
+        "error.wallet_closed" => "A carteira '{0}' está encerrada",
+        "error.invalid_status_transition" => "A carteira '{0}' não pode passar de {1} para {2}",
+        "error.hold_not_found" => "Bloqueio {0} não encontrado",
+        "error.hold_not_active" => "O bloqueio {0} não está mais ativo",
+        "error.invalid_xpub" => "Chave pública estendida inválida: {0}",
+        "error.not_an_hd_wallet" => {
+            "A carteira '{0}' não deriva endereços de uma chave pública estendida"
+        }
+        "error.vault_locked" => "O cofre de chaves está bloqueado",
+        "error.invalid_passphrase" => "Senha do cofre de chaves inválida",
+        "error.key_not_found" => "Nenhuma chave de assinatura armazenada para a carteira '{0}'",
+        "error.key_vault_failed" => "Falha no cofre de chaves: {0}",
+        "error.signing_failed" => "Falha na assinatura: {0}",
+        "error.signature_rejected" => "Assinatura rejeitada: {0}",
+        "error.unsigned_tx_not_found" => "Nenhum saque preparado {0} aguarda assinatura",
+        "error.batch_aborted" => "Lote cancelado: a operação {0} falhou",
+        "error.invalid_interval" => "O intervalo deve ser de pelo menos um segundo",
+        "error.screening_blocked" => "Bloqueado pela triagem de compliance: {0}",
+        "error.flagged_for_review" => "Retido para revisão de compliance como {0}",
+        "error.unknown_deposit_address" => "Nenhuma carteira recebe depósitos no endereço {0}",
+        "error.deposit_mismatch" => "O depósito {0} já foi informado com outro valor",
+        "error.deposit_not_found" => "Nenhum depósito encontrado com o hash de transação {0}",
+        "error.account_not_found" => "Conta não encontrada: {0}",
+        "error.account_already_exists" => "A conta já existe: {0}",
+        "error.organization_not_found" => "Organização não encontrada: {0}",
+        "error.organization_already_exists" => "A organização já existe: {0}",
+        "error.customer_not_found" => "Cliente não encontrado: {0}",
+        "error.customer_already_exists" => "O cliente já existe: {0}",
+        "error.wallet_not_empty" => "A carteira {0} deve estar vazia",
+        "error.not_customer_wallet" => "A carteira '{0}' não guarda fundos do cliente '{1}'",
+        "error.claims_mismatch" => {
+            "Os créditos de clientes na carteira '{0}' somam {1}, mas o saldo é {2}"
+        }
+        "error.already_reversed" => "A transação {0} já foi estornada",
+        "error.invalid_config" => "Configuração inválida: {0}",
+        "error.event_out_of_order" => "Esperava o evento {0}, recebeu o evento {1}",
+        "error.event_rejected" => "O evento {0} não se aplica ao estado atual",
+        "error.dual_control_required" => "{0} precisa da confirmação de um segundo operador",
+        "error.action_expired" => "A ação proposta {0} expirou",
+        "error.address_not_whitelisted" => {
+            "O endereço {0} não está na lista de permissões da carteira {1}"
+        }
+        "error.whitelist_not_active" => "O endereço {0} da lista de permissões fica ativo em {1}",
+        "error.travel_rule_required" => {
+            "O saque de {0} exige dados da regra de viagem a partir de {1}"
+        }
+        "error.incomplete_travel_rule" => "O campo {0} da regra de viagem é obrigatório",
+        "error.invalid_api_key" => "Chave de API inválida",
+        "error.api_key_not_found" => "Chave de API '{0}' não encontrada",
+        "error.api_key_expired" => "A chave de API '{0}' expirou",
+        "error.api_key_revoked" => "A chave de API '{0}' foi revogada",
+        "error.scope_denied" => "A chave de API '{0}' não tem permissão para {1} aqui",
+        "error.rate_limited" => "Limite de taxa atingido para {0}; tente novamente em {1} s",
+        "error.invalid_batch_file" => "Arquivo de operações inválido: {0}",
+        "error.tenant_not_found" => "Inquilino não encontrado: {0}",
+        "error.tenant_already_exists" => "O inquilino já existe: {0}",
+        "error.tenant_quota_exceeded" => "O inquilino {0} atingiu sua cota de {1} carteiras",
+        "error.period_closed" => "Período contábil fechado até {0}",
+        "error.period_not_ended" => {
+            "Não é possível fechar um período que termina em {0}, que ainda não passou"
+        }
+        "error.alert_not_found" => "Alerta não encontrado: {0}",
+        "error.alert_already_resolved" => "O alerta {0} já foi resolvido",
+        "error.commitment_not_found" => "Compromisso de estado não encontrado: {0}",
+        "error.invalid_import_row" => "Linha de importação inválida: {0}",
+        "error.invalid_import_file" => "Arquivo de importação inválido: {0}",
+        "error.node" => "Erro do nó da blockchain: {0}",
+        "error.broadcast_not_found" => "Nenhum saque transmitido {0} aguarda confirmações",
+        "error.not_replaceable" => "O saque transmitido {0} não pode ser substituído",
+        "error.fee_unavailable" => "Taxa de rede indisponível: {0}",
+        "error.key_generation_failed" => "Falha ao gerar chave: {0}",
+        "error.address_in_use" => "Endereço já em uso: {0}",
+        "error.invalid_key_share" => "Fragmento de chave inválido: {0}",
+        "error.invalid_mnemonic" => "Mnemônico inválido: {0}",
+        "error.invalid_psbt" => "PSBT inválida: {0}",
+        "error.invalid_multisig_keys" => "Chaves multiassinatura inválidas: {0}",
+        "error.unsupported_backup_version" => "Versão de formato de backup não suportada {0}",
+        "error.wallet_in_use" => "A carteira '{0}' tem operações em andamento",
+        "error.joint_not_allowed" => {
+            "A carteira '{0}' tem aprovações de saque próprias e não pode ser conjunta"
+        }
+        "error.destination_required" => {
+            "Saques da carteira '{0}' devem indicar um destino autorizado"
+        }
+        "error.approvers_already_set" => {
+            "A carteira '{0}' já tem aprovadores de saque; proponha uma alteração"
+        }
+        "error.unpaired_conversion_leg" => "A perna de conversão {0} não tem a perna oposta correspondente e não pode ser estornada sozinha",
+        "notify.deposit_received.subject" => "Depósito recebido",
+        "notify.deposit_received.body" => "Recebemos {amount} na carteira {wallet}.",
+        "notify.withdrawal_approved.subject" => "Saque aprovado",
+        "notify.withdrawal_approved.body" => {
+            "Seu saque de {amount} da carteira {wallet} foi aprovado."
+        }
+        "notify.withdrawal_settled.subject" => "Saque liquidado",
+        "notify.withdrawal_settled.body" => {
+            "Seu saque de {amount} da carteira {wallet} foi liquidado."
+        }
+        "notify.wallet_frozen.subject" => "Carteira bloqueada",
+        "notify.wallet_frozen.body" => "A carteira {wallet} foi bloqueada: {reason}",
+        "label.statement" => "Extrato",
+        "label.report" => "Relatório",
+        "label.wallet" => "Carteira",
+        "label.date" => "Data",
+        "label.description" => "Descrição",
+        "label.amount" => "Valor",
+        "label.balance" => "Saldo",
+        "label.opening_balance" => "Saldo inicial",
+        "label.closing_balance" => "Saldo final",
+        "label.total_balance" => "Saldo total",
+        "label.deposit" => "Depósito",
+        "label.withdrawal" => "Saque",
+        "label.transfer" => "Transferência",
+        "label.conversion" => "Conversão",
+        "label.reward" => "Recompensa",
+        "label.total" => "Total",
+        _ => return None,
+    })
+}
+
+fn es(key: &str) -> Option<&'static str> {
+    Some(match key {
+        "op.deposit" => "depósito",
+        "op.withdrawal" => "retiro",
+        "op.transfer" => "transferencia",
+        "op.conversion" => "conversión",
+        "op.hold" => "retención",
+        "error.wallet_already_exists" => "La billetera con id '{0}' ya existe",
+        "error.wallet_not_found" => "Billetera '{0}' no encontrada",
+        "error.source_wallet_not_found" => "Billetera de origen '{0}' no encontrada",
+        "error.destination_wallet_not_found" => "Billetera de destino '{0}' no encontrada",
+        "error.non_positive_amount" => "El monto de {0} debe ser positivo",
+        "error.insufficient_balance" => "Saldo insuficiente: {0} disponible, {1} solicitado",
+        "error.same_wallet" => "No se puede transferir a la misma billetera",
+        "error.asset_mismatch" => "Activo incompatible: se esperaba {0}, se encontró {1}",
+        "error.rate_unavailable" => "No hay tipo de cambio disponible de {0} a {1}",
+        "error.invalid_rate" => "Tipo de cambio inválido: {0}",
+        "error.template_not_found" => "Plantilla de billetera '{0}' no encontrada",
+        "error.invalid_destination" => "Dirección de destino inválida '{0}'",
+        "error.cosignature_required" => {
+            "Los retiros de la billetera conjunta '{0}' requieren cofirma"
+        }
+        "error.not_joint_wallet" => "La billetera '{0}' no es una billetera conjunta",
+        "error.already_joint" => "La billetera '{0}' ya es una billetera conjunta",
+        "error.not_an_owner" => "'{0}' no es titular de la billetera '{1}'",
+        "error.invalid_threshold" => "Umbral inválido: se requieren {0} pero hay {1} firmantes",
+        "error.operation_not_found" => "Operación {0} no encontrada",
+        "error.operation_not_pending" => "La operación {0} ya no está pendiente",
+        "error.transaction_not_found" => "Transacción {0} no encontrada",
+        "error.not_fiat_wallet" => "La billetera '{0}' no es una billetera fiduciaria",
+        "error.gateway" => "Error de la pasarela: {0}",
+        "error.duplicate_reference" => "La referencia '{0}' ya fue registrada",
+        "error.not_hot_wallet" => "La billetera '{0}' no es una billetera caliente",
+        "error.not_cold_wallet" => "La billetera '{0}' no es una billetera fría",
+        "error.seed_export_not_confirmed" => {
+            "La exportación de la semilla de la billetera '{0}' no fue confirmada"
+        }
+        "error.backup_failed" => "Error en la copia de seguridad: {0}",
+        "error.invalid_ur" => "Datos UR no válidos: {0}",
+        "error.id_scheme_not_found" => "Esquema de id de billetera '{0}' no encontrado",
+        "error.id_collision" => "El id de billetera derivado '{0}' ya lo usa otra billetera",
+        "error.extension_not_found" => "No hay ninguna extensión {0} registrada como '{1}'",
+        "error.extension_failed" => "No se pudo crear la extensión '{0}': {1}",
+        "error.script_rejected" => "Bloqueado por el script '{0}': {1}",
+        "error.script_error" => "El script '{0}' falló: {1}",
+        "error.transaction_id_not_found" => "Ninguna transacción con id {0}",
+        "error.digest_mismatch" => "La transacción {0} no coincide con su digest sellado",
+        "error.injected_fault" => "Fallo inyectado: {0}",
+        "error.queued_withdrawal_not_found" => "Ningún retiro en cola con id {0}",
+        "error.amount_overflow" => "Importe fuera de rango",
+        "error.storage_failed" => "Error de almacenamiento: {0}",
+        "error.approval_required" => "Los retiros de la billetera fría '{0}' requieren aprobación",
+        "error.no_approvers" => {
+            "No hay aprobadores de retiros configurados para la billetera '{0}'"
+        }
+        "error.not_an_approver" => "'{0}' no es aprobador de la billetera '{1}'",
+        "error.self_approval" => "El retiro {0} no puede ser aprobado por quien lo solicitó",
+        "error.idempotency_conflict" => "La clave de idempotencia {0} ya se usó en otra operación",
+        "error.audit_chain_broken" => "Cadena de auditoría rota en la transacción {0}",
+        "error.policy_per_transaction" => "El retiro de {1} de la billetera '{0}' supera el máximo por transacción de {2}",
+        "error.policy_daily_limit" => "El retiro de {1} de la billetera '{0}' superaría el límite diario de {2} ({3} ya retirados)",
+        "error.policy_weekly_limit" => "El retiro de {1} de la billetera '{0}' superaría el límite semanal de {2} ({3} ya retirados)",
+        "error.policy_cooldown" => "La billetera '{0}' no puede retirar durante otros {1} segundos",
+        "error.invalid_address" => "Dirección {1} no válida '{0}': {2}",
+        "error.no_address_validator" => "No hay validador de direcciones para la red '{0}'",
+        "error.invalid_snapshot" => "Instantánea no válida: {0}",
+        "error.unsupported_snapshot_version" => "Versión de instantánea no soportada: {0}",
+        "error.signatures_required" => "Los retiros de la billetera multifirma '{0}' requieren firmas",
+        "error.not_multisig_wallet" => "La billetera '{0}' no es una billetera multifirma",
+        "error.not_a_signer" => "'{0}' no es firmante de la billetera '{1}'",
+        "error.wallet_frozen" => "La billetera '{0}' está congelada",
+        "error.wallet_closed" => "La billetera '{0}' está cerrada",
+        "error.invalid_status_transition" => "La billetera '{0}' no puede pasar de {1} a {2}",
+        "error.hold_not_found" => "Retención {0} no encontrada",
+        "error.hold_not_active" => "La retención {0} ya no está activa",
+        "error.invalid_xpub" => "Clave pública extendida no válida: {0}",
+        "error.not_an_hd_wallet" => "La billetera '{0}' no deriva direcciones de una clave pública extendida",
+        "error.vault_locked" => "El almacén de claves está bloqueado",
+        "error.invalid_passphrase" => "Frase de contraseña del almacén de claves no válida",
+        "error.key_not_found" => "No hay clave de firma almacenada para la billetera '{0}'",
+        "error.key_vault_failed" => "Fallo del almacén de claves: {0}",
+        "error.signing_failed" => "Error al firmar: {0}",
+        "error.signature_rejected" => "Firma rechazada: {0}",
+        "error.unsigned_tx_not_found" => "Ningún retiro preparado {0} espera una firma",
+        "error.batch_aborted" => "Lote cancelado: la operación {0} falló",
+        "error.invalid_interval" => "El intervalo debe ser de al menos un segundo",
+        "error.screening_blocked" => "Bloqueado por el control de cumplimiento: {0}",
+        "error.flagged_for_review" => "Retenido para revisión de cumplimiento como {0}",
+        "error.unknown_deposit_address" => "Ninguna billetera recibe depósitos en la dirección {0}",
+        "error.deposit_mismatch" => "El depósito {0} ya se informó con otro importe",
+        "error.deposit_not_found" => "No se vio ningún depósito con el hash de transacción {0}",
+        "error.account_not_found" => "Cuenta no encontrada: {0}",
+        "error.account_already_exists" => "La cuenta ya existe: {0}",
+        "error.organization_not_found" => "Organización no encontrada: {0}",
+        "error.organization_already_exists" => "La organización ya existe: {0}",
+        "error.customer_not_found" => "Cliente no encontrado: {0}",
+        "error.customer_already_exists" => "El cliente ya existe: {0}",
+        "error.wallet_not_empty" => "La billetera {0} debe estar vacía",
+        "error.not_customer_wallet" => "La billetera '{0}' no custodia fondos del cliente '{1}'",
+        "error.claims_mismatch" => "Los saldos de clientes en la billetera '{0}' suman {1}, pero su saldo es {2}",
+        "error.already_reversed" => "La transacción {0} ya fue revertida",
+        "error.invalid_config" => "Configuración no válida: {0}",
+        "error.event_out_of_order" => "Se esperaba el evento {0}, se recibió el evento {1}",
+        "error.event_rejected" => "El evento {0} no se aplica al estado actual",
+        "error.dual_control_required" => "{0} necesita la confirmación de un segundo operador",
+        "error.action_expired" => "La acción propuesta {0} ha caducado",
+        "error.address_not_whitelisted" => "La dirección {0} no está en la lista blanca de la billetera {1}",
+        "error.whitelist_not_active" => "La dirección {0} de la lista blanca se activa el {1}",
+        "error.travel_rule_required" => "El retiro de {0} requiere datos de la regla de viaje a partir de {1}",
+        "error.incomplete_travel_rule" => "El campo {0} de la regla de viaje es obligatorio",
+        "error.invalid_api_key" => "Clave de API no válida",
+        "error.api_key_not_found" => "Clave de API '{0}' no encontrada",
+        "error.api_key_expired" => "La clave de API '{0}' ha caducado",
+        "error.api_key_revoked" => "La clave de API '{0}' ha sido revocada",
+        "error.scope_denied" => "La clave de API '{0}' no tiene permiso para {1} aquí",
+        "error.rate_limited" => "Límite de frecuencia alcanzado para {0}; reintente en {1} s",
+        "error.invalid_batch_file" => "Archivo de operaciones no válido: {0}",
+        "error.tenant_not_found" => "Inquilino no encontrado: {0}",
+        "error.tenant_already_exists" => "El inquilino ya existe: {0}",
+        "error.tenant_quota_exceeded" => "El inquilino {0} alcanzó su cuota de {1} billeteras",
+        "error.period_closed" => "Período contable cerrado hasta {0}",
+        "error.period_not_ended" => "No se puede cerrar un período que termina en {0}, que aún no ha pasado",
+        "error.alert_not_found" => "Alerta no encontrada: {0}",
+        "error.alert_already_resolved" => "La alerta {0} ya fue resuelta",
+        "error.commitment_not_found" => "Compromiso de estado no encontrado: {0}",
+        "error.invalid_import_row" => "Fila de importación no válida: {0}",
+        "error.invalid_import_file" => "Archivo de importación no válido: {0}",
+        "error.node" => "Error del nodo de la cadena: {0}",
+        "error.broadcast_not_found" => "Ningún retiro transmitido {0} espera confirmaciones",
+        "error.not_replaceable" => "El retiro transmitido {0} no se puede reemplazar",
+        "error.fee_unavailable" => "Comisión de red no disponible: {0}",
+        "error.key_generation_failed" => "Error al generar la clave: {0}",
+        "error.address_in_use" => "Dirección ya en uso: {0}",
+        "error.invalid_key_share" => "Fragmento de clave no válido: {0}",
+        "error.invalid_mnemonic" => "Mnemónico no válido: {0}",
+        "error.invalid_psbt" => "PSBT no válida: {0}",
+        "error.invalid_multisig_keys" => "Claves multifirma no válidas: {0}",
+        "error.unsupported_backup_version" => "Versión de formato de copia de seguridad no admitida {0}",
+        "error.wallet_in_use" => "La billetera '{0}' tiene operaciones en curso",
+        "error.joint_not_allowed" => "La billetera '{0}' tiene sus propias aprobaciones de retiro y no puede ser conjunta",
+        "error.destination_required" => "Los retiros de la billetera '{0}' deben indicar un destino autorizado",
+        "error.approvers_already_set" => "La billetera '{0}' ya tiene aprobadores de retiros; proponga un cambio",
+        "error.unpaired_conversion_leg" => "El tramo de conversión {0} no tiene el tramo opuesto correspondiente y no puede revertirse solo",
+        "notify.deposit_received.subject" => "Depósito recibido",
+        "notify.deposit_received.body" => "Recibimos {amount} en la billetera {wallet}.",
+        "notify.withdrawal_approved.subject" => "Retiro aprobado",
+        "notify.withdrawal_approved.body" => {
+            "Su retiro de {amount} de la billetera {wallet} fue aprobado."
+        }
+        "notify.withdrawal_settled.subject" => "Retiro liquidado",
+        "notify.withdrawal_settled.body" => {
+            "Su retiro de {amount} de la billetera {wallet} fue liquidado."
+        }
+        "notify.wallet_frozen.subject" => "Billetera congelada",
+        "notify.wallet_frozen.body" => "La billetera {wallet} fue congelada: {reason}",
+        "label.statement" => "Estado de cuenta",
+        "label.report" => "Informe",
+        "label.wallet" => "Billetera",
+        "label.date" => "Fecha",
+        "label.description" => "Descripción",
+        "label.amount" => "Monto",
+        "label.balance" => "Saldo",
+        "label.opening_balance" => "Saldo inicial",
+        "label.closing_balance" => "Saldo final",
+        "label.total_balance" => "Saldo total",
+        "label.deposit" => "Depósito",
+        "label.withdrawal" => "Retiro",
+        "label.transfer" => "Transferencia",
+        "label.conversion" => "Conversión",
+        "label.reward" => "Recompensa",
+        "label.total" => "Total",
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_locale_from_tag() {
+        assert_eq!(Locale::from_tag("en-US"), Some(Locale::En));
+        assert_eq!(Locale::from_tag("pt_BR"), Some(Locale::PtBr));
+        assert_eq!(Locale::from_tag("es-MX"), Some(Locale::Es));
+        assert_eq!(Locale::from_tag("fr"), None);
+    }
+
+    #[test]
+    fn test_labels_are_translated() {
+        assert_eq!(Label::OpeningBalance.text(Locale::En), "Opening balance");
+        assert_eq!(Label::OpeningBalance.text(Locale::PtBr), "Saldo inicial");
+        assert_eq!(Label::Withdrawal.text(Locale::Es), "Retiro");
+    }
+
+    #[test]
+    fn test_render_substitutes_arguments() {
+        let message = render(
+            Locale::En,
+            "error.insufficient_balance",
+            &["1".to_string(), "2".to_string()],
+        );
+        assert_eq!(message, "Insufficient balance: 1 available, 2 requested");
+    }
+
+    #[test]
+    fn test_every_english_key_is_translated() {
+        let keys = [
+            "error.wallet_already_exists",
+            "error.wallet_not_found",
+            "error.non_positive_amount",
+            "error.insufficient_balance",
+            "error.same_wallet",
+            "label.statement",
+            "label.closing_balance",
+        ];
+        for key in keys {
+            assert!(pt_br(key).is_some(), "missing pt-BR for {}", key);
+            assert!(es(key).is_some(), "missing es for {}", key);
+        }
+    }
+}
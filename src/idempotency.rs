@@ -0,0 +1,206 @@
+//! Idempotent deposits, withdrawals and transfers.
+//!
+//! A client that retries an operation after a timeout cannot tell whether
+//! the first attempt was applied. The `_idempotent` variants take a
+//! client-supplied key: the first successful call records its transactions
+//! under the key, and later calls with the same key and parameters succeed
+//! without touching the ledger again. Reusing a key for a different
+//! operation fails with [`CustodyError::IdempotencyConflict`].
+//!
+//! Keys are stamped on the transactions themselves
+//! ([`Transaction::idempotency_key`]), so they survive a restart from
+//! [`Storage`](crate::Storage). A failed call records nothing and may be
+//! retried with the same key.
+
+use crate::{Amount, CustodyError, CustodySystem, Transaction, TransactionType};
+
+/// The transaction an operation is expected to record: wallet, type,
+/// amount and counterparty
+type Leg<'a> = (&'a str, TransactionType, Amount, Option<&'a str>);
+
+impl CustodySystem {
+    /// Deposits funds unless a deposit with this key was already applied
+    ///
+    /// # Example
+    /// ```
+    /// use securevault::{amount, CustodySystem, WalletType};
+    /// let mut system = CustodySystem::new();
+    /// system.create_wallet("w".to_string(), "0x1".to_string(), WalletType::Hot).unwrap();
+    ///
+    /// system.deposit_idempotent("wire-42", "w", amount!(5)).unwrap();
+    /// // The client timed out and retries
+    /// system.deposit_idempotent("wire-42", "w", amount!(5)).unwrap();
+    ///
+    /// assert_eq!(system.get_wallet("w").unwrap().balance, amount!(5));
+    /// ```
+    pub fn deposit_idempotent(
+        &mut self,
+        idempotency_key: &str,
+        id: &str,
+        amount: Amount,
+    ) -> Result<(), CustodyError> {
+        let leg = (id, TransactionType::Deposit, amount, None);
+        self.run_idempotent(idempotency_key, leg, |system| system.deposit(id, amount))
+    }
+
+    /// Withdraws funds unless a withdrawal with this key was already
+    /// applied
+    pub fn withdraw_idempotent(
+        &mut self,
+        idempotency_key: &str,
+        id: &str,
+        amount: Amount,
+    ) -> Result<(), CustodyError> {
+        let leg = (id, TransactionType::Withdrawal, amount, None);
+        self.run_idempotent(idempotency_key, leg, |system| system.withdraw(id, amount))
+    }
+
+    /// Transfers funds unless a transfer with this key was already applied
+    pub fn transfer_idempotent(
+        &mut self,
+        idempotency_key: &str,
+        from_id: &str,
+        to_id: &str,
+        amount: Amount,
+    ) -> Result<(), CustodyError> {
+        let leg = (from_id, TransactionType::Transfer, amount, Some(to_id));
+        self.run_idempotent(idempotency_key, leg, |system| {
+            system.transfer(from_id, to_id, amount)
+        })
+    }
+
+    /// Gets the transactions recorded under an idempotency key, in ledger
+    /// order
+    pub fn transactions_by_idempotency_key(&self, idempotency_key: &str) -> Vec<&Transaction> {
+        self.transactions
+            .iter()
+            .filter(|tx| tx.idempotency_key.as_deref() == Some(idempotency_key))
+            .collect()
+    }
+
+    /// Runs `operation` with its transactions stamped with the key, or
+    /// checks a previous run against `leg` if the key was already used
+    fn run_idempotent(
+        &mut self,
+        idempotency_key: &str,
+        leg: Leg<'_>,
+        operation: impl FnOnce(&mut Self) -> Result<(), CustodyError>,
+    ) -> Result<(), CustodyError> {
+        let recorded = self.transactions_by_idempotency_key(idempotency_key);
+        if !recorded.is_empty() {
+            let (wallet_id, kind, amount, counterparty) = leg;
+            let same = matches!(recorded.as_slice(), [tx] if tx.wallet_id == wallet_id
+                && tx.transaction_type == kind
+                && tx.amount == amount
+                && tx.counterparty.as_deref() == counterparty);
+            return if same {
+                Ok(())
+            } else {
+                Err(CustodyError::IdempotencyConflict(
+                    idempotency_key.to_string(),
+                ))
+            };
+        }
+
+        self.idempotency_key = Some(idempotency_key.to_string());
+        let result = operation(self);
+        self.idempotency_key = None;
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{JsonFileStorage, WalletType};
+    use std::sync::Arc;
+
+    fn system() -> CustodySystem {
+        let mut system = CustodySystem::new();
+        system
+            .create_wallet("a".to_string(), "0x1".to_string(), WalletType::Hot)
+            .unwrap();
+        system
+            .create_wallet("b".to_string(), "0x2".to_string(), WalletType::Hot)
+            .unwrap();
+        system.deposit("a", amount!(10)).unwrap();
+        system
+    }
+
+    #[test]
+    fn test_retries_apply_once() {
+        let mut system = system();
+        for _ in 0..3 {
+            system.withdraw_idempotent("w-1", "a", amount!(2)).unwrap();
+            system
+                .transfer_idempotent("t-1", "a", "b", amount!(3))
+                .unwrap();
+        }
+
+        assert_eq!(system.get_wallet("a").unwrap().balance, amount!(5));
+        assert_eq!(system.get_wallet("b").unwrap().balance, amount!(3));
+        assert_eq!(system.get_all_transactions().len(), 3);
+        let recorded = system.transactions_by_idempotency_key("t-1");
+        assert_eq!(recorded.len(), 1);
+        assert!(system.verify_transaction(recorded[0].id).is_ok());
+        // Plain operations carry no key
+        assert_eq!(system.get_transaction(1).unwrap().idempotency_key, None);
+    }
+
+    #[test]
+    fn test_key_reuse_with_other_parameters_conflicts() {
+        let mut system = system();
+        system.deposit_idempotent("k", "a", amount!(1)).unwrap();
+
+        let conflict = Err(CustodyError::IdempotencyConflict("k".to_string()));
+        assert_eq!(system.deposit_idempotent("k", "a", amount!(2)), conflict);
+        assert_eq!(system.deposit_idempotent("k", "b", amount!(1)), conflict);
+        assert_eq!(system.withdraw_idempotent("k", "a", amount!(1)), conflict);
+        system
+            .transfer_idempotent("t", "a", "b", amount!(1))
+            .unwrap();
+        let conflict = Err(CustodyError::IdempotencyConflict("t".to_string()));
+        assert_eq!(
+            system.transfer_idempotent("t", "b", "a", amount!(1)),
+            conflict
+        );
+        assert_eq!(system.get_wallet("a").unwrap().balance, amount!(10));
+    }
+
+    #[test]
+    fn test_failed_attempt_can_be_retried() {
+        let mut system = system();
+        assert!(system.withdraw_idempotent("k", "a", amount!(50)).is_err());
+        assert!(system.transactions_by_idempotency_key("k").is_empty());
+
+        system.deposit("a", amount!(40)).unwrap();
+        system.withdraw_idempotent("k", "a", amount!(50)).unwrap();
+        assert_eq!(system.get_wallet("a").unwrap().balance, amount!(0));
+    }
+
+    #[test]
+    fn test_keys_survive_restart() {
+        let path = std::env::temp_dir().join(format!(
+            "securevault-idempotency-{}.json",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let storage = Arc::new(JsonFileStorage::new(&path));
+
+        let mut system = CustodySystem::with_storage(storage.clone()).unwrap();
+        system
+            .create_wallet("a".to_string(), "0x1".to_string(), WalletType::Hot)
+            .unwrap();
+        system
+            .deposit_idempotent("wire-1", "a", amount!(7))
+            .unwrap();
+        drop(system);
+
+        let mut restarted = CustodySystem::with_storage(storage).unwrap();
+        restarted
+            .deposit_idempotent("wire-1", "a", amount!(7))
+            .unwrap();
+        assert_eq!(restarted.get_wallet("a").unwrap().balance, amount!(7));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
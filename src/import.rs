@@ -0,0 +1,576 @@
+//! Bulk import of historical transactions from CSV.
+//!
+//! [`CustodySystem::import_transactions_csv`] loads deposits and
+//! withdrawals exported from a previous custody system, keeping their
+//! original timestamps. Each row is validated on its own: a bad row is
+//! reported in the [`ImportReport`] with its line number and the rest of
+//! the file still loads. Rows are applied oldest first, whatever the order
+//! of the file, so a withdrawal may follow the deposit that funds it.
+//!
+//! By default the imported rows make up the wallets' balances. When the
+//! source system declares what each wallet held before its first row,
+//! [`ImportBalances::Verify`] checks the wallets start from those balances
+//! and refuses the rows of any wallet that does not.
+//!
+//! Rows are recorded without the live checks on wallet status, policies
+//! or limits, but never into a closed accounting period, and a row whose
+//! reference is already in the log is refused so a file can be imported
+//! again after fixing its bad rows.
+
+use crate::extension::builtin_asset;
+use crate::replay::BalanceMismatch;
+use crate::time::Timestamp;
+use crate::{Amount, Asset, CustodyError, CustodySystem, Transaction, TransactionType};
+use std::collections::BTreeMap;
+use std::io::Read;
+
+/// How imported rows relate to wallet balances
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum ImportBalances {
+    /// Rows are applied to the wallets' current balances
+    #[default]
+    Recompute,
+    /// The primary-asset balance each wallet held before its first row;
+    /// a wallet listed here whose balance differs gets none of its rows
+    /// imported
+    Verify(BTreeMap<String, Amount>),
+}
+
+/// Names the CSV columns holding each field and says how balances are
+/// treated
+///
+/// The default matches the columns of
+/// [`ExportFormat::Csv`](crate::ExportFormat::Csv).
+#[derive(Debug, Clone, PartialEq)]
+pub struct CsvMapping {
+    pub wallet_id: String,
+    /// Column holding `deposit` or `withdrawal`, in any case
+    pub transaction_type: String,
+    pub amount: String,
+    /// Column holding Unix seconds or an RFC 3339 date and time
+    pub timestamp: String,
+    /// Column holding the asset symbol; the wallet's primary asset if
+    /// `None` or blank
+    pub asset: Option<String>,
+    /// Column holding the source system's reference
+    pub reference: Option<String>,
+    /// Column holding a free-text memo
+    pub memo: Option<String>,
+    pub balances: ImportBalances,
+}
+
+impl Default for CsvMapping {
+    fn default() -> Self {
+        Self {
+            wallet_id: "wallet_id".to_string(),
+            transaction_type: "type".to_string(),
+            amount: "amount".to_string(),
+            timestamp: "timestamp".to_string(),
+            asset: Some("asset".to_string()),
+            reference: Some("reference".to_string()),
+            memo: None,
+            balances: ImportBalances::Recompute,
+        }
+    }
+}
+
+/// A row that was not imported
+#[derive(Debug, Clone, PartialEq)]
+pub struct RowError {
+    /// Line the row starts on, the header being line 1
+    pub line: usize,
+    pub error: CustodyError,
+}
+
+/// Outcome of an import
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ImportReport {
+    /// Ids of the recorded transactions, oldest first
+    pub imported: Vec<u64>,
+    /// Rejected rows, by line
+    pub errors: Vec<RowError>,
+    /// Wallets whose balance did not match the declared opening balance
+    pub mismatches: Vec<BalanceMismatch>,
+}
+
+impl ImportReport {
+    /// Returns true if every row was imported
+    pub fn is_clean(&self) -> bool {
+        self.errors.is_empty() && self.mismatches.is_empty()
+    }
+}
+
+/// A parsed row, not yet applied
+struct Row {
+    line: usize,
+    wallet_id: String,
+    kind: TransactionType,
+    amount: Amount,
+    timestamp: Timestamp,
+    asset: Option<String>,
+    reference: Option<String>,
+    memo: Option<String>,
+}
+
+/// Splits CSV text into records, each with the line it starts on
+fn records(text: &str) -> Result<Vec<(usize, Vec<String>)>, CustodyError> {
+    let mut records = Vec::new();
+    let (mut record, mut field) = (Vec::new(), String::new());
+    let (mut line, mut start, mut quoted) = (1, 1, false);
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' if quoted => quoted = false,
+            '"' if field.is_empty() => quoted = true,
+            '\n' if quoted => {
+                line += 1;
+                field.push(c);
+            }
+            ',' if !quoted => record.push(std::mem::take(&mut field)),
+            '\r' if !quoted && chars.peek() == Some(&'\n') => {}
+            '\n' if !quoted => {
+                record.push(std::mem::take(&mut field));
+                records.push((start, std::mem::take(&mut record)));
+                line += 1;
+                start = line;
+            }
+            _ => field.push(c),
+        }
+    }
+    if quoted {
+        return Err(CustodyError::InvalidImportFile(format!(
+            "unterminated quoted field starting on line {}",
+            start
+        )));
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push((start, record));
+    }
+    // Blank lines carry no row
+    records.retain(|(_, record)| record.iter().any(|field| !field.trim().is_empty()));
+    Ok(records)
+}
+
+fn parse_timestamp(value: &str) -> Option<Timestamp> {
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Timestamp::from_unix(secs));
+    }
+    let parsed = chrono::DateTime::parse_from_rfc3339(value).ok()?;
+    u64::try_from(parsed.timestamp())
+        .ok()
+        .map(Timestamp::from_unix)
+}
+
+impl CsvMapping {
+    /// Returns the position of `column` in `header`
+    fn position(header: &[String], column: &str) -> Result<usize, CustodyError> {
+        header
+            .iter()
+            .position(|name| name.trim() == column)
+            .ok_or_else(|| CustodyError::InvalidImportFile(format!("no column named '{}'", column)))
+    }
+
+    fn parse(
+        &self,
+        header: &[String],
+        records: Vec<(usize, Vec<String>)>,
+    ) -> Result<(Vec<Row>, Vec<RowError>), CustodyError> {
+        let required = [
+            &self.wallet_id,
+            &self.transaction_type,
+            &self.amount,
+            &self.timestamp,
+        ]
+        .map(|column| Self::position(header, column));
+        let [wallet_id, kind, amount, timestamp] = required;
+        let (wallet_id, kind, amount, timestamp) = (wallet_id?, kind?, amount?, timestamp?);
+        let optional = |column: &Option<String>| {
+            column
+                .as_deref()
+                .map(|column| Self::position(header, column))
+                .transpose()
+        };
+        let (asset, reference, memo) = (
+            optional(&self.asset)?,
+            optional(&self.reference)?,
+            optional(&self.memo)?,
+        );
+
+        let (mut rows, mut errors) = (Vec::new(), Vec::new());
+        for (line, record) in records {
+            let field = |position: usize| record.get(position).map_or("", |f| f.trim());
+            let text = |position: Option<usize>| {
+                position
+                    .map(field)
+                    .filter(|value| !value.is_empty())
+                    .map(str::to_string)
+            };
+            let invalid = |reason: String| RowError {
+                line,
+                error: CustodyError::InvalidImportRow(reason),
+            };
+            let kind = match field(kind).to_lowercase().as_str() {
+                "deposit" => TransactionType::Deposit,
+                "withdrawal" => TransactionType::Withdrawal,
+                other => {
+                    errors.push(invalid(format!("unsupported type '{}'", other)));
+                    continue;
+                }
+            };
+            let Ok(amount) = field(amount).parse::<Amount>() else {
+                errors.push(invalid(format!("invalid amount '{}'", field(amount))));
+                continue;
+            };
+            let Some(timestamp) = parse_timestamp(field(timestamp)) else {
+                errors.push(invalid(format!("invalid timestamp '{}'", field(timestamp))));
+                continue;
+            };
+            rows.push(Row {
+                line,
+                wallet_id: field(wallet_id).to_string(),
+                kind,
+                amount,
+                timestamp,
+                asset: text(asset),
+                reference: text(reference),
+                memo: text(memo),
+            });
+        }
+        Ok((rows, errors))
+    }
+}
+
+impl CustodySystem {
+    /// Imports historical deposits and withdrawals from CSV with a header
+    /// row
+    ///
+    /// # Errors
+    /// [`CustodyError::InvalidImportFile`] if the input cannot be read or
+    /// lacks a mapped column; nothing is imported then. Problems with
+    /// single rows are reported in the [`ImportReport`] instead.
+    ///
+    /// # Example
+    /// ```
+    /// use securevault::{amount, CsvMapping, CustodySystem, WalletType};
+    /// let mut system = CustodySystem::new();
+    /// system.create_wallet("w".to_string(), "0x1".to_string(), WalletType::Hot).unwrap();
+    ///
+    /// let csv = "\
+    /// timestamp,wallet_id,type,amount
+    /// 2023-01-05T10:00:00Z,w,withdrawal,1.5
+    /// 2023-01-02T09:30:00Z,w,deposit,4
+    /// 2023-01-06T00:00:00Z,w,deposit,lots
+    /// ";
+    /// let mapping = CsvMapping { asset: None, reference: None, ..CsvMapping::default() };
+    /// let report = system.import_transactions_csv(csv.as_bytes(), &mapping).unwrap();
+    /// assert_eq!(report.imported.len(), 2);
+    /// assert_eq!(report.errors[0].line, 4);
+    /// assert_eq!(system.get_wallet("w").unwrap().balance, amount!(2.5));
+    /// ```
+    pub fn import_transactions_csv<R: Read>(
+        &mut self,
+        mut reader: R,
+        mapping: &CsvMapping,
+    ) -> Result<ImportReport, CustodyError> {
+        let mut text = String::new();
+        reader
+            .read_to_string(&mut text)
+            .map_err(|err| CustodyError::InvalidImportFile(err.to_string()))?;
+        let mut records = records(&text)?.into_iter();
+        let (_, header) = records
+            .next()
+            .ok_or_else(|| CustodyError::InvalidImportFile("no header row".to_string()))?;
+        let (mut rows, errors) = mapping.parse(&header, records.collect())?;
+        rows.sort_by_key(|row| row.timestamp);
+
+        let mut report = ImportReport {
+            errors,
+            ..ImportReport::default()
+        };
+        if let ImportBalances::Verify(opening) = &mapping.balances {
+            for (wallet_id, declared) in opening {
+                let Some(wallet) = self.wallets.get(wallet_id) else {
+                    continue;
+                };
+                if wallet.balance != *declared {
+                    report.mismatches.push(BalanceMismatch {
+                        wallet_id: wallet_id.clone(),
+                        asset: wallet.asset.clone(),
+                        stored: wallet.balance,
+                        replayed: *declared,
+                    });
+                }
+            }
+        }
+
+        let now = self.current_timestamp();
+        for row in rows {
+            let line = row.line;
+            let refused = report
+                .mismatches
+                .iter()
+                .any(|mismatch| mismatch.wallet_id == row.wallet_id);
+            let result = if refused {
+                Err(CustodyError::InvalidImportRow(format!(
+                    "wallet '{}' does not hold its declared opening balance",
+                    row.wallet_id
+                )))
+            } else {
+                self.import_row(row, now)
+            };
+            match result {
+                Ok(id) => report.imported.push(id),
+                Err(error) => report.errors.push(RowError { line, error }),
+            }
+        }
+        report.errors.sort_by_key(|error| error.line);
+        Ok(report)
+    }
+
+    /// Validates and records one row
+    fn import_row(&mut self, row: Row, now: Timestamp) -> Result<u64, CustodyError> {
+        let wallet = self
+            .wallets
+            .get(&row.wallet_id)
+            .ok_or_else(|| CustodyError::WalletNotFound(row.wallet_id.clone()))?;
+        if !row.amount.is_positive() {
+            return Err(CustodyError::NonPositiveAmount(match row.kind {
+                TransactionType::Deposit => crate::OperationKind::Deposit,
+                _ => crate::OperationKind::Withdrawal,
+            }));
+        }
+        if row.timestamp > now {
+            return Err(CustodyError::InvalidImportRow(format!(
+                "timestamp {} is in the future",
+                row.timestamp
+            )));
+        }
+        self.check_period_open(row.timestamp)?;
+        if let Some(reference) = &row.reference {
+            if self
+                .transactions
+                .iter()
+                .any(|tx| tx.reference.as_ref() == Some(reference))
+            {
+                return Err(CustodyError::DuplicateReference(reference.clone()));
+            }
+        }
+
+        let asset: Asset = match &row.asset {
+            None => wallet.asset.clone(),
+            Some(symbol) => match wallet
+                .balances()
+                .map(|(asset, _)| asset)
+                .find(|asset| asset.symbol().eq_ignore_ascii_case(symbol))
+            {
+                Some(asset) => asset.clone(),
+                None => known_asset(symbol).ok_or_else(|| {
+                    CustodyError::InvalidImportRow(format!("unknown asset '{}'", symbol))
+                })?,
+            },
+        };
+        match row.kind {
+            TransactionType::Deposit => wallet
+                .balance_of(&asset)
+                .checked_add(row.amount)
+                .ok_or(CustodyError::AmountOverflow)?,
+            _ => {
+                // A backdated debit must be covered when it happened and
+                // must not overdraw any later point of the history
+                let available = self.lowest_balance_from(&row.wallet_id, &asset, row.timestamp);
+                available
+                    .checked_sub(row.amount)
+                    .filter(|balance| !balance.is_negative())
+                    .ok_or(CustodyError::InsufficientBalance {
+                        available,
+                        requested: row.amount,
+                    })?
+            }
+        };
+
+        let mut tx = Transaction::new(&row.wallet_id, row.kind, row.amount, asset, row.timestamp);
+        tx.reference = row.reference;
+        tx.memo = row.memo;
+        let id = self.next_transaction_id;
+        self.record_transaction(tx)?;
+        Ok(id)
+    }
+
+    /// Returns the lowest balance `wallet_id` held in `asset` at `at` or
+    /// any time after it
+    fn lowest_balance_from(&self, wallet_id: &str, asset: &Asset, at: Timestamp) -> Amount {
+        let mut changes: Vec<(Timestamp, Amount)> = self
+            .transactions
+            .iter()
+            .filter(|tx| tx.asset == *asset && tx.involves(wallet_id))
+            .map(|tx| (tx.timestamp, tx.balance_change(wallet_id)))
+            .collect();
+        changes.sort_by_key(|(timestamp, _)| *timestamp);
+        let split = changes.partition_point(|(timestamp, _)| *timestamp <= at);
+
+        let mut balance: Amount = changes[..split].iter().map(|(_, change)| *change).sum();
+        let mut lowest = balance;
+        for (_, change) in &changes[split..] {
+            balance += *change;
+            lowest = lowest.min(balance);
+        }
+        lowest
+    }
+}
+
+/// Resolves a symbol the wallet does not hold yet: BTC, ETH or a
+/// three-letter fiat code
+fn known_asset(symbol: &str) -> Option<Asset> {
+    let symbol = symbol.to_uppercase();
+    match symbol.as_str() {
+        "BTC" | "ETH" => Some(builtin_asset(&symbol)),
+        code if code.len() == 3 && code.bytes().all(|b| b.is_ascii_alphabetic()) => {
+            Some(builtin_asset(code))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::time::ManualClock;
+    use crate::WalletType;
+    use std::sync::Arc;
+
+    fn system() -> CustodySystem {
+        let clock = Arc::new(ManualClock::new(Timestamp::from_unix(1_700_000_000)));
+        let mut system = CustodySystem::new();
+        system.set_clock(clock);
+        for id in ["a", "b"] {
+            system
+                .create_wallet(id.to_string(), id.to_string(), WalletType::Hot)
+                .unwrap();
+        }
+        system
+    }
+
+    fn messages(report: &ImportReport) -> Vec<(usize, String)> {
+        report
+            .errors
+            .iter()
+            .map(|e| (e.line, e.error.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_rows_are_validated_one_by_one() {
+        let mut system = system();
+        let csv = "wallet_id,type,amount,timestamp,asset,reference,note\r\n\
+            a,deposit,10,1600000000,BTC,R1,\"first, funded\"\r\n\
+            a,Withdrawal,4,1600000100,,R2,\r\n\
+            a,withdrawal,7,1600000200,,R3,\r\n\
+            zz,deposit,1,1600000000,,R4,\r\n\
+            a,deposit,1,1800000000,,R5,\r\n\
+            a,transfer,1,1600000000,,R6,\r\n\
+            \r\n\
+            b,deposit,2.5,2020-09-13T12:26:40Z,ETH,R7,\"multi\nline\"\r\n\
+            a,deposit,1,1600000300,,R1,\r\n";
+        let mapping = CsvMapping {
+            memo: Some("note".to_string()),
+            ..CsvMapping::default()
+        };
+        let report = system
+            .import_transactions_csv(csv.as_bytes(), &mapping)
+            .unwrap();
+        assert_eq!(report.imported.len(), 3);
+        let lines: Vec<usize> = report.errors.iter().map(|e| e.line).collect();
+        assert_eq!(lines, [4, 5, 6, 7, 11]);
+        assert!(matches!(
+            report.errors[0].error,
+            CustodyError::InsufficientBalance { .. }
+        ));
+        assert_eq!(
+            report.errors[4].error,
+            CustodyError::DuplicateReference("R1".to_string())
+        );
+        assert!(messages(&report)[2].1.contains("future"));
+
+        let a = system.get_wallet("a").unwrap();
+        assert_eq!(a.balance, amount!(6));
+        let b = system.get_wallet("b").unwrap();
+        assert_eq!(b.balance_of(&Asset::Eth), amount!(2.5));
+        let first = system.get_transaction(report.imported[0]).unwrap();
+        assert_eq!(first.timestamp, Timestamp::from_unix(1_600_000_000));
+        assert_eq!(first.memo.as_deref(), Some("first, funded"));
+        assert!(system.audit().passed());
+    }
+
+    #[test]
+    fn test_declared_opening_balances() {
+        let mut system = system();
+        system.deposit("a", amount!(5)).unwrap();
+        let csv = "wallet_id,type,amount,timestamp\n\
+            a,withdrawal,5,1600000000\n\
+            b,deposit,1,1600000000\n";
+        let mapping = CsvMapping {
+            asset: None,
+            reference: None,
+            balances: ImportBalances::Verify(BTreeMap::from([
+                ("a".to_string(), amount!(3)),
+                ("b".to_string(), Amount::ZERO),
+            ])),
+            ..CsvMapping::default()
+        };
+        let report = system
+            .import_transactions_csv(csv.as_bytes(), &mapping)
+            .unwrap();
+        assert!(!report.is_clean());
+        assert_eq!(report.mismatches.len(), 1);
+        assert_eq!(report.mismatches[0].stored, amount!(5));
+        assert_eq!(report.errors[0].line, 2);
+        assert_eq!(report.imported.len(), 1);
+        assert_eq!(system.get_wallet("a").unwrap().balance, amount!(5));
+        assert_eq!(system.get_wallet("b").unwrap().balance, amount!(1));
+
+        assert_eq!(
+            system.import_transactions_csv("wallet_id,amount\n".as_bytes(), &mapping),
+            Err(CustodyError::InvalidImportFile(
+                "no column named 'type'".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_withdrawals_need_funds_at_their_own_time() {
+        let mut system = system();
+        system.deposit("a", amount!(5)).unwrap();
+        let csv = "wallet_id,type,amount,timestamp,asset\n\
+            a,withdrawal,3,1600000000,\n\
+            a,withdrawal,3,1700000000,\n\
+            a,deposit,1,1600000000,doge\n\
+            a,deposit,1,1600000000,usd\n";
+        let mapping = CsvMapping {
+            reference: None,
+            ..CsvMapping::default()
+        };
+        let report = system
+            .import_transactions_csv(csv.as_bytes(), &mapping)
+            .unwrap();
+        assert_eq!(report.imported.len(), 2);
+        assert_eq!(
+            report.errors[0].error,
+            CustodyError::InsufficientBalance {
+                available: Amount::ZERO,
+                requested: amount!(3),
+            }
+        );
+        assert_eq!(
+            report.errors[1].error,
+            CustodyError::InvalidImportRow("unknown asset 'doge'".to_string())
+        );
+        let a = system.get_wallet("a").unwrap();
+        assert_eq!(a.balance, amount!(2));
+        assert_eq!(a.balance_of(&Asset::Fiat("USD".to_string())), amount!(1));
+    }
+}
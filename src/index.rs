@@ -0,0 +1,218 @@
+//! Secondary indexes over the transaction log.
+//!
+//! The log is append-only, so [`TransactionIndex`] maps transaction ids,
+//! wallets and timestamps to log positions and is extended as each entry is
+//! recorded. Per-wallet history, id lookups and time-range queries then
+//! touch only the matching entries instead of scanning the whole log. The
+//! index is rebuilt when a snapshot is restored; annotations edited in
+//! place do not affect it.
+//!
+//! [`CustodySystem::iter_transactions`] and
+//! [`CustodySystem::iter_wallet_transactions`] walk the log lazily, for
+//! callers that stream entries rather than collect them.
+
+use crate::time::Timestamp;
+use crate::{CustodySystem, Transaction, TransactionLog};
+
+/// Log positions keyed by id, wallet and timestamp. Persistent maps, so
+/// cloning the system stays O(1).
+#[derive(Debug, Clone, Default)]
+pub(crate) struct TransactionIndex {
+    by_id: im::HashMap<u64, usize>,
+    by_wallet: im::HashMap<String, im::Vector<usize>>,
+    /// Keyed by timestamp, then position, since timestamps need not grow
+    /// with the log
+    by_time: im::OrdSet<(Timestamp, usize)>,
+}
+
+impl TransactionIndex {
+    /// Indexes every entry of `log`
+    pub(crate) fn build(log: &TransactionLog) -> Self {
+        let mut index = Self::default();
+        for (position, tx) in log.iter().enumerate() {
+            index.insert(position, tx);
+        }
+        index
+    }
+
+    /// Indexes the entry recorded at `position`
+    pub(crate) fn insert(&mut self, position: usize, tx: &Transaction) {
+        self.by_id.insert(tx.id, position);
+        let mut wallets: Vec<&str> = tx.postings().map(|(id, _)| id).collect();
+        wallets.dedup();
+        for wallet_id in wallets {
+            self.by_wallet
+                .entry(wallet_id.to_string())
+                .or_default()
+                .push_back(position);
+        }
+        self.by_time.insert((tx.timestamp, position));
+    }
+
+    pub(crate) fn position(&self, id: u64) -> Option<usize> {
+        self.by_id.get(&id).copied()
+    }
+}
+
+impl CustodySystem {
+    /// Iterates over the whole transaction log in recording order
+    ///
+    /// # Example
+    /// ```
+    /// use securevault::{amount, CustodySystem, WalletType};
+    /// let mut system = CustodySystem::new();
+    /// system.create_wallet("w".to_string(), "0x1".to_string(), WalletType::Hot).unwrap();
+    /// system.deposit("w", amount!(5)).unwrap();
+    /// system.withdraw("w", amount!(2)).unwrap();
+    ///
+    /// let newest = system.iter_transactions().next_back().unwrap();
+    /// assert_eq!(newest.amount, amount!(2));
+    /// assert_eq!(system.iter_wallet_transactions("w").count(), 2);
+    /// ```
+    pub fn iter_transactions(
+        &self,
+    ) -> impl DoubleEndedIterator<Item = &Transaction> + ExactSizeIterator + '_ {
+        self.transactions.iter()
+    }
+
+    /// Iterates over the transactions touching a wallet, oldest first
+    pub fn iter_wallet_transactions<'a>(
+        &'a self,
+        wallet_id: &str,
+    ) -> impl DoubleEndedIterator<Item = &'a Transaction> + ExactSizeIterator + 'a {
+        // Cloning the persistent position list is O(1)
+        let positions = self.index.by_wallet.get(wallet_id).cloned();
+        positions
+            .unwrap_or_default()
+            .into_iter()
+            .map(|position| &self.transactions[position])
+    }
+
+    /// Gets transaction history for a specific wallet, oldest first
+    pub fn get_wallet_transactions(&self, wallet_id: &str) -> Vec<&Transaction> {
+        self.iter_wallet_transactions(wallet_id).collect()
+    }
+
+    /// Gets the transactions recorded between `from` and `to` inclusive,
+    /// ordered by timestamp
+    ///
+    /// # Example
+    /// ```
+    /// use securevault::time::Timestamp;
+    /// use securevault::{amount, CustodySystem, WalletType};
+    /// let mut system = CustodySystem::new();
+    /// system.create_wallet("w".to_string(), "0x1".to_string(), WalletType::Hot).unwrap();
+    /// system.deposit("w", amount!(5)).unwrap();
+    ///
+    /// let now = system.get_all_transactions()[0].timestamp;
+    /// assert_eq!(system.get_transactions_between(now, now).len(), 1);
+    /// ```
+    pub fn get_transactions_between(&self, from: Timestamp, to: Timestamp) -> Vec<&Transaction> {
+        if from > to {
+            return Vec::new();
+        }
+        self.index
+            .by_time
+            .range((from, 0)..=(to, usize::MAX))
+            .map(|(_, position)| &self.transactions[*position])
+            .collect()
+    }
+
+    /// Gets a wallet's transactions recorded between `from` and `to`
+    /// inclusive, ordered by timestamp
+    pub fn get_wallet_transactions_between(
+        &self,
+        wallet_id: &str,
+        from: Timestamp,
+        to: Timestamp,
+    ) -> Vec<&Transaction> {
+        let mut transactions: Vec<_> = self
+            .get_wallet_transactions(wallet_id)
+            .into_iter()
+            .filter(|tx| from <= tx.timestamp && tx.timestamp <= to)
+            .collect();
+        transactions.sort_by_key(|tx| tx.timestamp);
+        transactions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Asset, TransactionType, WalletType};
+
+    fn at(secs: u64) -> Timestamp {
+        Timestamp::from_unix(secs)
+    }
+
+    /// Records deposits at the given times, out of order on purpose
+    fn system() -> CustodySystem {
+        let mut system = CustodySystem::new();
+        for id in ["a", "b"] {
+            system
+                .create_wallet(id.to_string(), id.to_string(), WalletType::Hot)
+                .unwrap();
+        }
+        for (wallet_id, secs) in [("a", 100), ("b", 300), ("a", 200), ("b", 400)] {
+            let tx = Transaction::new(
+                wallet_id,
+                TransactionType::Deposit,
+                amount!(1),
+                Asset::Btc,
+                at(secs),
+            );
+            system.record_transaction(tx).unwrap();
+        }
+        system
+    }
+
+    fn times(transactions: Vec<&Transaction>) -> Vec<u64> {
+        transactions
+            .iter()
+            .map(|tx| tx.timestamp.as_unix())
+            .collect()
+    }
+
+    #[test]
+    fn test_wallet_history_matches_a_scan() {
+        let mut system = system();
+        system.deposit("a", amount!(10)).unwrap();
+        system.transfer("a", "b", amount!(4)).unwrap();
+        for wallet_id in ["a", "b", "missing"] {
+            let scanned: Vec<_> = system
+                .get_all_transactions()
+                .iter()
+                .filter(|tx| tx.involves(wallet_id))
+                .collect();
+            assert_eq!(system.get_wallet_transactions(wallet_id), scanned);
+        }
+
+        // Restoring a snapshot rebuilds the index
+        let backup = system.snapshot();
+        system.withdraw("b", amount!(1)).unwrap();
+        assert_eq!(system.get_wallet_transactions("b").len(), 4);
+        system.restore(backup).unwrap();
+        assert_eq!(system.get_wallet_transactions("b").len(), 3);
+        assert_eq!(system.get_transaction(6).unwrap().wallet_id, "a");
+        assert!(system.get_transaction(7).is_none());
+    }
+
+    #[test]
+    fn test_time_range_queries() {
+        let system = system();
+        assert_eq!(
+            times(system.get_transactions_between(at(150), at(400))),
+            [200, 300, 400]
+        );
+        assert_eq!(
+            times(system.get_wallet_transactions_between("a", at(0), at(1000))),
+            [100, 200]
+        );
+        assert_eq!(
+            times(system.get_transactions_between(at(300), at(300))),
+            [300]
+        );
+        assert!(system.get_transactions_between(at(400), at(100)).is_empty());
+        assert!(system.get_transactions_between(at(500), at(900)).is_empty());
+    }
+}
@@ -0,0 +1,488 @@
+//! ISO 20022-style account statements.
+//!
+//! Maps booked wallet activity onto a structure modelled on the ISO 20022
+//! `camt.053` bank-to-customer statement, so bank back offices can feed it
+//! into existing reconciliation pipelines. [`Camt053Statement::to_xml`]
+//! writes a `camt.053.001.08` document; the same structure serializes to
+//! JSON with serde for pipelines that prefer it.
+//!
+//! The mapping is "inspired by", not a certified implementation: crypto
+//! assets use their ticker as the currency code and amounts keep the
+//! asset's full precision. The account is identified by wallet id
+//! (`Othr/Id`) unless a [`StatementAccount`] is set for the wallet with
+//! [`CustodySystem::set_statement_account`], giving the identifier, and
+//! optionally the IBAN, the accounting software knows it by.
+//!
+//! | camt.053          | SecureVault                                       |
+//! |-------------------|---------------------------------------------------|
+//! | `Acct/Id`         | statement account id, or IBAN if set              |
+//! | `Acct/Ccy`        | wallet asset symbol                               |
+//! | `Bal` `OPBD`      | balance before `from`                             |
+//! | `Bal` `CLBD`      | balance before `to`                               |
+//! | `Ntry`            | one per transaction, status `BOOK`                |
+//! | `NtryRef`         | transaction id                                    |
+//! | `BkTxCd/Prtry`    | type code: `DEPOSIT`, `TRANSFER`, `REWARD`, ...   |
+//! | `RltdPties`       | counterparty wallet, for transfers                |
+//! | `Refs/EndToEndId` | external reference, if any                        |
+
+use crate::time::Timestamp;
+use crate::{Amount, CustodyError, CustodySystem, Transaction, TransactionType};
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+
+const NAMESPACE: &str = "urn:iso:std:iso:20022:tech:xsd:camt.053.001.08";
+
+/// Credit/debit indicator
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CreditDebit {
+    #[serde(rename = "CRDT")]
+    Credit,
+    #[serde(rename = "DBIT")]
+    Debit,
+}
+
+impl CreditDebit {
+    fn code(self) -> &'static str {
+        match self {
+            CreditDebit::Credit => "CRDT",
+            CreditDebit::Debit => "DBIT",
+        }
+    }
+}
+
+/// A statement balance (`Bal`)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StatementBalance {
+    /// `OPBD` (opening booked) or `CLBD` (closing booked)
+    pub code: String,
+    /// Absolute amount
+    pub amount: String,
+    pub credit_debit: CreditDebit,
+    pub at: Timestamp,
+}
+
+/// A booked entry (`Ntry`)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StatementEntry {
+    pub reference: String,
+    /// Absolute amount
+    pub amount: String,
+    pub credit_debit: CreditDebit,
+    pub booking_date: Timestamp,
+    /// Proprietary bank transaction code
+    pub transaction_code: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub end_to_end_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub counterparty: Option<String>,
+    /// Unstructured remittance information
+    pub remittance: String,
+}
+
+/// How a wallet is identified in exported bank statements
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StatementAccount {
+    /// Account number or ledger code
+    pub account_id: String,
+    /// IBAN, used in place of `account_id` where a format takes one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iban: Option<String>,
+    /// Bank or routing identifier, OFX `BANKID`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bank_id: Option<String>,
+}
+
+impl StatementAccount {
+    /// Identifies the wallet as `account_id`
+    pub fn new(account_id: &str) -> Self {
+        Self {
+            account_id: account_id.to_string(),
+            iban: None,
+            bank_id: None,
+        }
+    }
+
+    pub fn with_iban(mut self, iban: &str) -> Self {
+        self.iban = Some(iban.to_string());
+        self
+    }
+
+    pub fn with_bank_id(mut self, bank_id: &str) -> Self {
+        self.bank_id = Some(bank_id.to_string());
+        self
+    }
+}
+
+/// A camt.053-style statement for one wallet and period
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Camt053Statement {
+    pub message_id: String,
+    pub created_at: Timestamp,
+    pub account: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iban: Option<String>,
+    pub currency: String,
+    pub from: Timestamp,
+    pub to: Timestamp,
+    pub opening_balance: StatementBalance,
+    pub closing_balance: StatementBalance,
+    pub entries: Vec<StatementEntry>,
+}
+
+impl CustodySystem {
+    /// Sets how a wallet is identified in exported statements
+    ///
+    /// # Errors
+    /// [`CustodyError::WalletNotFound`]
+    pub fn set_statement_account(
+        &mut self,
+        wallet_id: &str,
+        account: StatementAccount,
+    ) -> Result<(), CustodyError> {
+        if !self.wallet_exists(wallet_id) {
+            return Err(CustodyError::WalletNotFound(wallet_id.to_string()));
+        }
+        self.statement_accounts
+            .insert(wallet_id.to_string(), account);
+        Ok(())
+    }
+
+    /// Returns how a wallet is identified in exported statements, by
+    /// default its wallet id
+    pub fn statement_account(&self, wallet_id: &str) -> StatementAccount {
+        self.statement_accounts
+            .get(wallet_id)
+            .cloned()
+            .unwrap_or_else(|| StatementAccount::new(wallet_id))
+    }
+
+    /// Builds a camt.053-style statement of `wallet_id` for transactions
+    /// with `from <= timestamp < to`
+    ///
+    /// # Example
+    /// ```
+    /// use securevault::{amount, CustodySystem, Timestamp, WalletType};
+    /// let mut system = CustodySystem::new();
+    /// system.create_wallet("w".to_string(), "0x1".to_string(), WalletType::Hot).unwrap();
+    /// system.deposit("w", amount!(1.5)).unwrap();
+    ///
+    /// let statement = system
+    ///     .camt053_statement("w", Timestamp::EPOCH, Timestamp::from_unix(u64::MAX))
+    ///     .unwrap();
+    /// assert_eq!(statement.entries.len(), 1);
+    /// assert!(statement.to_xml().contains("<Cd>CLBD</Cd>"));
+    /// ```
+    pub fn camt053_statement(
+        &self,
+        wallet_id: &str,
+        from: Timestamp,
+        to: Timestamp,
+    ) -> Result<Camt053Statement, CustodyError> {
+        let wallet = self
+            .get_wallet(wallet_id)
+            .ok_or_else(|| CustodyError::WalletNotFound(wallet_id.to_string()))?;
+        let decimals = usize::from(wallet.asset.decimals());
+        let amount = |value: Amount| format!("{:.*}", decimals, value.abs());
+        let balance = |code: &str, value: Amount, at: Timestamp| StatementBalance {
+            code: code.to_string(),
+            amount: amount(value),
+            credit_debit: indicator(value),
+            at,
+        };
+
+        let mut opening = Amount::ZERO;
+        let mut closing = Amount::ZERO;
+        let mut entries = Vec::new();
+        for tx in self
+            .get_wallet_transactions(wallet_id)
+            .into_iter()
+            .filter(|tx| tx.asset == wallet.asset)
+        {
+            if tx.timestamp >= to {
+                break;
+            }
+            let signed = tx.balance_change(wallet_id);
+            closing += signed;
+            if tx.timestamp < from {
+                opening += signed;
+                continue;
+            }
+            entries.push(StatementEntry {
+                reference: tx.id.to_string(),
+                amount: amount(signed),
+                credit_debit: indicator(signed),
+                booking_date: tx.timestamp,
+                transaction_code: transaction_code(tx).to_string(),
+                end_to_end_id: tx.reference.clone(),
+                counterparty: if tx.wallet_id == wallet_id {
+                    tx.counterparty.clone()
+                } else {
+                    Some(tx.wallet_id.clone())
+                },
+                remittance: remittance(tx),
+            });
+        }
+
+        let account = self.statement_account(wallet_id);
+        Ok(Camt053Statement {
+            message_id: format!("SV-{}-{}-{}", wallet_id, from.as_unix(), to.as_unix()),
+            created_at: self.current_timestamp(),
+            account: account.account_id,
+            iban: account.iban,
+            currency: wallet.asset.symbol().to_string(),
+            from,
+            to,
+            opening_balance: balance("OPBD", opening, from),
+            closing_balance: balance("CLBD", closing, to),
+            entries,
+        })
+    }
+}
+
+impl Camt053Statement {
+    /// Renders the statement as a `camt.053.001.08` XML document
+    pub fn to_xml(&self) -> String {
+        let mut xml = String::new();
+        let _ = self.write_xml(&mut xml);
+        xml
+    }
+
+    fn write_xml(&self, xml: &mut String) -> std::fmt::Result {
+        writeln!(xml, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+        writeln!(xml, r#"<Document xmlns="{}">"#, NAMESPACE)?;
+        writeln!(xml, "  <BkToCstmrStmt>")?;
+        writeln!(xml, "    <GrpHdr>")?;
+        writeln!(xml, "      <MsgId>{}</MsgId>", escape(&self.message_id))?;
+        writeln!(
+            xml,
+            "      <CreDtTm>{}</CreDtTm>",
+            datetime(self.created_at)
+        )?;
+        writeln!(xml, "    </GrpHdr>")?;
+        writeln!(xml, "    <Stmt>")?;
+        writeln!(xml, "      <Id>{}</Id>", escape(&self.message_id))?;
+        writeln!(
+            xml,
+            "      <CreDtTm>{}</CreDtTm>",
+            datetime(self.created_at)
+        )?;
+        writeln!(xml, "      <FrToDt>")?;
+        writeln!(xml, "        <FrDtTm>{}</FrDtTm>", datetime(self.from))?;
+        writeln!(xml, "        <ToDtTm>{}</ToDtTm>", datetime(self.to))?;
+        writeln!(xml, "      </FrToDt>")?;
+        writeln!(xml, "      <Acct>")?;
+        match &self.iban {
+            Some(iban) => writeln!(xml, "        <Id><IBAN>{}</IBAN></Id>", escape(iban))?,
+            None => writeln!(
+                xml,
+                "        <Id><Othr><Id>{}</Id></Othr></Id>",
+                escape(&self.account)
+            )?,
+        }
+        writeln!(xml, "        <Ccy>{}</Ccy>", escape(&self.currency))?;
+        writeln!(xml, "      </Acct>")?;
+        for balance in [&self.opening_balance, &self.closing_balance] {
+            writeln!(xml, "      <Bal>")?;
+            writeln!(
+                xml,
+                "        <Tp><CdOrPrtry><Cd>{}</Cd></CdOrPrtry></Tp>",
+                escape(&balance.code)
+            )?;
+            writeln!(
+                xml,
+                r#"        <Amt Ccy="{}">{}</Amt>"#,
+                escape(&self.currency),
+                balance.amount
+            )?;
+            writeln!(
+                xml,
+                "        <CdtDbtInd>{}</CdtDbtInd>",
+                balance.credit_debit.code()
+            )?;
+            writeln!(
+                xml,
+                "        <Dt><DtTm>{}</DtTm></Dt>",
+                datetime(balance.at)
+            )?;
+            writeln!(xml, "      </Bal>")?;
+        }
+        for entry in &self.entries {
+            writeln!(xml, "      <Ntry>")?;
+            writeln!(
+                xml,
+                "        <NtryRef>{}</NtryRef>",
+                escape(&entry.reference)
+            )?;
+            writeln!(
+                xml,
+                r#"        <Amt Ccy="{}">{}</Amt>"#,
+                escape(&self.currency),
+                entry.amount
+            )?;
+            writeln!(
+                xml,
+                "        <CdtDbtInd>{}</CdtDbtInd>",
+                entry.credit_debit.code()
+            )?;
+            writeln!(xml, "        <Sts><Cd>BOOK</Cd></Sts>")?;
+            let booked = datetime(entry.booking_date);
+            writeln!(xml, "        <BookgDt><DtTm>{}</DtTm></BookgDt>", booked)?;
+            writeln!(xml, "        <ValDt><DtTm>{}</DtTm></ValDt>", booked)?;
+            writeln!(
+                xml,
+                "        <BkTxCd><Prtry><Cd>{}</Cd></Prtry></BkTxCd>",
+                escape(&entry.transaction_code)
+            )?;
+            writeln!(xml, "        <NtryDtls><TxDtls>")?;
+            if let Some(id) = &entry.end_to_end_id {
+                writeln!(
+                    xml,
+                    "          <Refs><EndToEndId>{}</EndToEndId></Refs>",
+                    escape(id)
+                )?;
+            }
+            if let Some(counterparty) = &entry.counterparty {
+                let party = if entry.credit_debit == CreditDebit::Credit {
+                    "Dbtr"
+                } else {
+                    "Cdtr"
+                };
+                writeln!(
+                    xml,
+                    "          <RltdPties><{0}><Pty><Nm>{1}</Nm></Pty></{0}></RltdPties>",
+                    party,
+                    escape(counterparty)
+                )?;
+            }
+            writeln!(
+                xml,
+                "          <RmtInf><Ustrd>{}</Ustrd></RmtInf>",
+                escape(&entry.remittance)
+            )?;
+            writeln!(xml, "        </TxDtls></NtryDtls>")?;
+            writeln!(xml, "      </Ntry>")?;
+        }
+        writeln!(xml, "    </Stmt>")?;
+        writeln!(xml, "  </BkToCstmrStmt>")?;
+        writeln!(xml, "</Document>")
+    }
+}
+
+fn indicator(value: Amount) -> CreditDebit {
+    if value.is_negative() {
+        CreditDebit::Debit
+    } else {
+        CreditDebit::Credit
+    }
+}
+
+fn transaction_code(tx: &Transaction) -> &'static str {
+    match tx.transaction_type {
+        TransactionType::ConversionIn | TransactionType::ConversionOut => "CONVERSION",
+        TransactionType::Transfer => "TRANSFER",
+        _ if tx.counterparty.is_some() => "TRANSFER",
+        TransactionType::Deposit => "DEPOSIT",
+        TransactionType::Withdrawal => "WITHDRAWAL",
+        TransactionType::Reward => "REWARD",
+    }
+}
+
+fn remittance(tx: &Transaction) -> String {
+    let mut parts = vec![transaction_code(tx).to_lowercase()];
+    if let Some(category) = &tx.category {
+        parts.push(category.to_string());
+    }
+    if let Some(rate) = tx.rate {
+        parts.push(format!("rate {}", rate));
+    }
+    parts.join("; ")
+}
+
+fn datetime(at: Timestamp) -> String {
+    at.datetime().format("%Y-%m-%dT%H:%M:%SZ").to_string()
+}
+
+pub(crate) fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Asset, WalletType};
+
+    fn system() -> CustodySystem {
+        let mut system = CustodySystem::new();
+        system
+            .create_wallet("a".to_string(), "0x1".to_string(), WalletType::Hot)
+            .unwrap();
+        system
+            .create_wallet("b<&>".to_string(), "0x2".to_string(), WalletType::Cold)
+            .unwrap();
+        for (wallet, kind, amount, day) in [
+            ("a", TransactionType::Deposit, amount!(10.0), 1),
+            ("a", TransactionType::Withdrawal, amount!(2.5), 2),
+            ("a", TransactionType::Deposit, amount!(1.0), 3),
+        ] {
+            let at = Timestamp::from_unix(day * 86_400);
+            let mut tx = Transaction::new(wallet, kind, amount, Asset::Btc, at);
+            if day == 2 {
+                tx.counterparty = Some("b<&>".to_string());
+            }
+            system.record_transaction(tx).unwrap();
+        }
+        system
+    }
+
+    #[test]
+    fn test_statement_balances_and_entries() {
+        let system = system();
+        let statement = system
+            .camt053_statement(
+                "a",
+                Timestamp::from_unix(2 * 86_400),
+                Timestamp::from_unix(3 * 86_400),
+            )
+            .unwrap();
+        assert_eq!(statement.opening_balance.amount, "10.00000000");
+        assert_eq!(statement.closing_balance.amount, "7.50000000");
+        assert_eq!(statement.entries.len(), 1);
+        let entry = &statement.entries[0];
+        assert_eq!(entry.credit_debit, CreditDebit::Debit);
+        assert_eq!(entry.transaction_code, "TRANSFER");
+        assert_eq!(entry.reference, "2");
+    }
+
+    #[test]
+    fn test_xml_is_escaped_and_structured() {
+        let system = system();
+        let xml = system
+            .camt053_statement("a", Timestamp::EPOCH, Timestamp::from_unix(u64::MAX))
+            .unwrap()
+            .to_xml();
+        assert!(xml.contains(NAMESPACE));
+        assert_eq!(xml.matches("<Ntry>").count(), 3);
+        assert!(xml.contains("<Cdtr><Pty><Nm>b&lt;&amp;&gt;</Nm></Pty></Cdtr>"));
+        assert!(xml.contains("<BookgDt><DtTm>1970-01-02T00:00:00Z</DtTm></BookgDt>"));
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        let system = system();
+        let statement = system
+            .camt053_statement("a", Timestamp::EPOCH, Timestamp::from_unix(10 * 86_400))
+            .unwrap();
+        let json = serde_json::to_value(&statement).unwrap();
+        assert_eq!(json["entries"][1]["credit_debit"], "DBIT");
+        let parsed: Camt053Statement = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed, statement);
+        assert!(system
+            .camt053_statement("zz", Timestamp::EPOCH, Timestamp::EPOCH)
+            .is_err());
+    }
+}
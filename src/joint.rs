@@ -0,0 +1,503 @@
+//! Joint wallets with multiple owners.
+//!
+//! A joint wallet is owned by several users. Withdrawals are proposed by one
+//! owner and execute only once `required` owners have signed. Changes to the
+//! ownership itself are under dual control: they need at least two owners'
+//! signatures (or every owner, when there is only one), even if the wallet's
+//! withdrawal threshold is lower.
+//!
+//! Cold and multi-signature wallets have approvals of their own and cannot
+//! be made joint.
+
+use crate::precheck::Authorization;
+use crate::screening::Workflow;
+use crate::{AdminAction, Amount, CustodyError, CustodyEvent, CustodySystem, WalletType};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+
+/// The owners of a joint wallet and its signing threshold
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JointOwnership {
+    pub owners: BTreeSet<String>,
+    /// Number of owner signatures needed to execute a withdrawal
+    pub required: usize,
+}
+
+impl JointOwnership {
+    fn validate(&self) -> Result<(), CustodyError> {
+        if self.required == 0 || self.required > self.owners.len() {
+            return Err(CustodyError::InvalidThreshold {
+                required: self.required,
+                available: self.owners.len(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Signatures needed for an ownership change (dual control)
+    fn change_quorum(&self) -> usize {
+        self.required.max(2).min(self.owners.len())
+    }
+}
+
+/// A change to the ownership of a joint wallet
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum OwnershipChange {
+    AddOwner(String),
+    RemoveOwner(String),
+    SetThreshold(usize),
+}
+
+/// What a joint operation does once approved
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum JointOperationKind {
+    Withdrawal { amount: Amount },
+    OwnershipChange(OwnershipChange),
+}
+
+/// Lifecycle state of an operation awaiting approval
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum OperationStatus {
+    Pending,
+    Executed,
+    Rejected,
+    /// Not completed before its deadline
+    Expired,
+}
+
+/// An operation on a joint wallet collecting owner signatures
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JointOperation {
+    pub id: u64,
+    pub wallet_id: String,
+    pub kind: JointOperationKind,
+    /// Owners who have signed, including the proposer
+    pub signatures: BTreeSet<String>,
+    pub status: OperationStatus,
+}
+
+impl CustodySystem {
+    /// Turns a wallet into a joint wallet owned by `owners`, requiring
+    /// `required` signatures per withdrawal
+    ///
+    /// Only allowed once; later changes go through
+    /// [`propose_ownership_change`](Self::propose_ownership_change). Cold
+    /// and multi-signature wallets release withdrawals through their own
+    /// approvals and are refused with [`CustodyError::JointNotAllowed`].
+    ///
+    /// Under the two-person rule this fails with
+    /// [`CustodyError::DualControlRequired`]; propose
+    /// [`AdminAction::MakeJointWallet`] instead.
+    pub fn make_joint_wallet(
+        &mut self,
+        wallet_id: &str,
+        owners: &[&str],
+        required: usize,
+    ) -> Result<(), CustodyError> {
+        self.require_second_operator(AdminAction::MAKE_JOINT_WALLET)?;
+        self.make_joint(wallet_id, owners, required)
+    }
+
+    pub(crate) fn make_joint(
+        &mut self,
+        wallet_id: &str,
+        owners: &[&str],
+        required: usize,
+    ) -> Result<(), CustodyError> {
+        let wallet = self
+            .get_wallet(wallet_id)
+            .ok_or_else(|| CustodyError::WalletNotFound(wallet_id.to_string()))?;
+        if matches!(
+            wallet.wallet_type,
+            WalletType::Cold | WalletType::MultiSig { .. }
+        ) {
+            return Err(CustodyError::JointNotAllowed(wallet_id.to_string()));
+        }
+        if self.joint_ownership.contains_key(wallet_id) {
+            return Err(CustodyError::AlreadyJoint(wallet_id.to_string()));
+        }
+        let ownership = JointOwnership {
+            owners: owners.iter().map(|o| o.to_string()).collect(),
+            required,
+        };
+        ownership.validate()?;
+        self.joint_ownership
+            .insert(wallet_id.to_string(), ownership);
+        Ok(())
+    }
+
+    /// Gets the ownership of a joint wallet
+    pub fn joint_ownership(&self, wallet_id: &str) -> Option<&JointOwnership> {
+        self.joint_ownership.get(wallet_id)
+    }
+
+    /// Proposes a withdrawal from a joint wallet, signed by `owner`
+    ///
+    /// # Returns
+    /// The operation id other owners sign with
+    /// [`cosign`](Self::cosign). If one signature suffices, the
+    /// withdrawal executes immediately.
+    pub fn request_joint_withdrawal(
+        &mut self,
+        wallet_id: &str,
+        owner: &str,
+        amount: Amount,
+    ) -> Result<u64, CustodyError> {
+        self.check_joint_withdrawal(wallet_id, owner, amount)?;
+        let workflow = Workflow::Joint {
+            owner: owner.to_string(),
+        };
+        self.screen_withdrawal_request(wallet_id, amount, None, workflow)?;
+        self.open_joint_withdrawal(wallet_id, owner, amount)
+    }
+
+    /// Proposes a screened withdrawal, checking it again
+    pub(crate) fn open_joint_withdrawal(
+        &mut self,
+        wallet_id: &str,
+        owner: &str,
+        amount: Amount,
+    ) -> Result<u64, CustodyError> {
+        self.check_joint_withdrawal(wallet_id, owner, amount)?;
+        self.propose(wallet_id, owner, JointOperationKind::Withdrawal { amount })
+    }
+
+    fn check_joint_withdrawal(
+        &self,
+        wallet_id: &str,
+        owner: &str,
+        amount: Amount,
+    ) -> Result<(), CustodyError> {
+        self.ownership_for(wallet_id, owner)?;
+        let reasons =
+            self.withdrawal_blockers(wallet_id, None, amount, None, Authorization::Approved);
+        match reasons.into_iter().next() {
+            Some(reason) => Err(reason),
+            None => Ok(()),
+        }
+    }
+
+    /// Proposes an ownership change to a joint wallet, signed by `owner`
+    pub fn propose_ownership_change(
+        &mut self,
+        wallet_id: &str,
+        owner: &str,
+        change: OwnershipChange,
+    ) -> Result<u64, CustodyError> {
+        let ownership = self.ownership_for(wallet_id, owner)?;
+        apply_change(ownership, &change)?;
+        self.propose(
+            wallet_id,
+            owner,
+            JointOperationKind::OwnershipChange(change),
+        )
+    }
+
+    /// Adds `owner`'s signature to a pending joint operation, executing it
+    /// once enough owners have signed
+    ///
+    /// # Returns
+    /// The operation's status after signing
+    pub fn cosign(
+        &mut self,
+        operation_id: u64,
+        owner: &str,
+    ) -> Result<OperationStatus, CustodyError> {
+        let operation = self
+            .joint_operations
+            .get(&operation_id)
+            .ok_or(CustodyError::OperationNotFound(operation_id))?;
+        if operation.status != OperationStatus::Pending {
+            return Err(CustodyError::OperationNotPending(operation_id));
+        }
+        let wallet_id = operation.wallet_id.clone();
+        self.ownership_for(&wallet_id, owner)?;
+
+        if let Some(operation) = self.joint_operations.get_mut(&operation_id) {
+            operation.signatures.insert(owner.to_string());
+        }
+        self.try_execute(operation_id)
+    }
+
+    /// Gets a joint operation by id
+    pub fn get_joint_operation(&self, operation_id: u64) -> Option<&JointOperation> {
+        self.joint_operations.get(&operation_id)
+    }
+
+    /// Lists operations on `wallet_id` still awaiting signatures
+    pub fn pending_joint_operations(&self, wallet_id: &str) -> Vec<&JointOperation> {
+        self.joint_operations
+            .values()
+            .filter(|op| op.wallet_id == wallet_id && op.status == OperationStatus::Pending)
+            .collect()
+    }
+
+    fn ownership_for(&self, wallet_id: &str, owner: &str) -> Result<&JointOwnership, CustodyError> {
+        if !self.wallet_exists(wallet_id) {
+            return Err(CustodyError::WalletNotFound(wallet_id.to_string()));
+        }
+        let ownership = self
+            .joint_ownership
+            .get(wallet_id)
+            .ok_or_else(|| CustodyError::NotJointWallet(wallet_id.to_string()))?;
+        if !ownership.owners.contains(owner) {
+            return Err(CustodyError::NotAnOwner {
+                wallet_id: wallet_id.to_string(),
+                user: owner.to_string(),
+            });
+        }
+        Ok(ownership)
+    }
+
+    fn propose(
+        &mut self,
+        wallet_id: &str,
+        owner: &str,
+        kind: JointOperationKind,
+    ) -> Result<u64, CustodyError> {
+        let id = self.allocate_operation_id();
+        self.joint_operations.insert(
+            id,
+            JointOperation {
+                id,
+                wallet_id: wallet_id.to_string(),
+                kind,
+                signatures: BTreeSet::from([owner.to_string()]),
+                status: OperationStatus::Pending,
+            },
+        );
+        if let Err(err) = self.try_execute(id) {
+            self.joint_operations.remove(&id);
+            return Err(err);
+        }
+        Ok(id)
+    }
+
+    fn try_execute(&mut self, operation_id: u64) -> Result<OperationStatus, CustodyError> {
+        let operation = self.joint_operations[&operation_id].clone();
+        let ownership = self.joint_ownership[&operation.wallet_id].clone();
+
+        // Signatures from owners removed since signing no longer count
+        let valid = operation.signatures.intersection(&ownership.owners).count();
+        let needed = match operation.kind {
+            JointOperationKind::Withdrawal { .. } => ownership.required,
+            JointOperationKind::OwnershipChange(_) => ownership.change_quorum(),
+        };
+        if valid < needed {
+            return Ok(OperationStatus::Pending);
+        }
+
+        match &operation.kind {
+            JointOperationKind::Withdrawal { amount } => {
+                let wallet_id = &operation.wallet_id;
+                let blockers = self.withdrawal_blockers(
+                    wallet_id,
+                    None,
+                    *amount,
+                    None,
+                    Authorization::Approved,
+                );
+                if let Some(reason) = blockers.into_iter().next() {
+                    return Err(reason);
+                }
+                self.emit(CustodyEvent::WithdrawalApproved {
+                    wallet_id: wallet_id.clone(),
+                    operation_id,
+                    amount: *amount,
+                    asset: self.wallets[wallet_id].asset.clone(),
+                });
+                self.execute_withdrawal(wallet_id, None, *amount, None, Authorization::Approved)?;
+            }
+            JointOperationKind::OwnershipChange(change) => {
+                let updated = apply_change(&ownership, change)?;
+                self.joint_ownership
+                    .insert(operation.wallet_id.clone(), updated);
+            }
+        }
+        if let Some(operation) = self.joint_operations.get_mut(&operation_id) {
+            operation.status = OperationStatus::Executed;
+        }
+        Ok(OperationStatus::Executed)
+    }
+}
+
+fn apply_change(
+    ownership: &JointOwnership,
+    change: &OwnershipChange,
+) -> Result<JointOwnership, CustodyError> {
+    let mut updated = ownership.clone();
+    match change {
+        OwnershipChange::AddOwner(owner) => {
+            updated.owners.insert(owner.clone());
+        }
+        OwnershipChange::RemoveOwner(owner) => {
+            updated.owners.remove(owner);
+        }
+        OwnershipChange::SetThreshold(required) => updated.required = *required,
+    }
+    updated.validate()?;
+    Ok(updated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn system() -> CustodySystem {
+        let mut system = CustodySystem::new();
+        system
+            .create_wallet("treasury".to_string(), "0x1".to_string(), WalletType::Hot)
+            .unwrap();
+        system.deposit("treasury", amount!(100.0)).unwrap();
+        system
+            .make_joint_wallet("treasury", &["alice", "bob", "carol"], 2)
+            .unwrap();
+        system
+    }
+
+    #[test]
+    fn test_wallets_with_own_approvals_cannot_be_joint() {
+        let mut system = system();
+        system
+            .create_wallet("c".to_string(), "0xc".to_string(), WalletType::Cold)
+            .unwrap();
+        system
+            .create_wallet(
+                "ms".to_string(),
+                "0xms".to_string(),
+                WalletType::multisig(2, &["x", "y"]),
+            )
+            .unwrap();
+        for id in ["c", "ms"] {
+            assert_eq!(
+                system.make_joint_wallet(id, &["x", "y"], 1),
+                Err(CustodyError::JointNotAllowed(id.to_string()))
+            );
+            assert!(system.joint_ownership(id).is_none());
+        }
+    }
+
+    #[test]
+    fn test_making_a_wallet_joint_needs_two_operators() {
+        let mut system = CustodySystem::new();
+        system
+            .create_wallet("w".to_string(), "0xw".to_string(), WalletType::Hot)
+            .unwrap();
+        system
+            .set_four_eyes_rule(crate::FourEyesRule::default())
+            .unwrap();
+        assert_eq!(
+            system.make_joint_wallet("w", &["x", "y"], 1),
+            Err(CustodyError::DualControlRequired(
+                "make_joint_wallet".to_string()
+            ))
+        );
+
+        let action = AdminAction::MakeJointWallet {
+            wallet_id: "w".to_string(),
+            owners: vec!["x".to_string(), "y".to_string()],
+            required: 1,
+        };
+        let id = system.propose_action("alice", action).unwrap();
+        assert!(system.joint_ownership("w").is_none());
+        system.confirm_action(id, "bob").unwrap();
+        assert_eq!(system.joint_ownership("w").unwrap().required, 1);
+    }
+
+    #[test]
+    fn test_direct_withdrawal_requires_cosigning() {
+        let mut system = system();
+        let result = system.withdraw("treasury", amount!(10.0));
+        assert_eq!(
+            result,
+            Err(CustodyError::CoSignatureRequired("treasury".to_string()))
+        );
+        assert!(!system
+            .can_withdraw("treasury", amount!(10.0), None)
+            .is_allowed());
+    }
+
+    #[test]
+    fn test_withdrawal_executes_at_threshold() {
+        let mut system = system();
+        let op = system
+            .request_joint_withdrawal("treasury", "alice", amount!(30.0))
+            .unwrap();
+        assert_eq!(
+            system.get_wallet("treasury").unwrap().balance,
+            amount!(100.0)
+        );
+        assert_eq!(system.pending_joint_operations("treasury").len(), 1);
+
+        // Signing twice does not count twice
+        assert_eq!(
+            system.cosign(op, "alice").unwrap(),
+            OperationStatus::Pending
+        );
+        assert_eq!(system.cosign(op, "bob").unwrap(), OperationStatus::Executed);
+        assert_eq!(
+            system.get_wallet("treasury").unwrap().balance,
+            amount!(70.0)
+        );
+        assert!(system.pending_joint_operations("treasury").is_empty());
+        assert_eq!(
+            system.cosign(op, "carol"),
+            Err(CustodyError::OperationNotPending(op))
+        );
+    }
+
+    #[test]
+    fn test_outsider_cannot_sign() {
+        let mut system = system();
+        let op = system
+            .request_joint_withdrawal("treasury", "alice", amount!(30.0))
+            .unwrap();
+        assert!(matches!(
+            system.cosign(op, "mallory"),
+            Err(CustodyError::NotAnOwner { .. })
+        ));
+        assert!(matches!(
+            system.request_joint_withdrawal("treasury", "mallory", amount!(1.0)),
+            Err(CustodyError::NotAnOwner { .. })
+        ));
+    }
+
+    #[test]
+    fn test_ownership_change_needs_dual_control() {
+        let mut system = CustodySystem::new();
+        system
+            .create_wallet("w".to_string(), "0x1".to_string(), WalletType::Hot)
+            .unwrap();
+        system.make_joint_wallet("w", &["alice", "bob"], 1).unwrap();
+
+        let op = system
+            .propose_ownership_change("w", "alice", OwnershipChange::AddOwner("carol".to_string()))
+            .unwrap();
+        // A single signature is enough to withdraw but not to change owners
+        assert!(!system
+            .joint_ownership("w")
+            .unwrap()
+            .owners
+            .contains("carol"));
+        system.cosign(op, "bob").unwrap();
+        assert!(system
+            .joint_ownership("w")
+            .unwrap()
+            .owners
+            .contains("carol"));
+    }
+
+    #[test]
+    fn test_invalid_threshold_rejected() {
+        let mut system = system();
+        assert!(matches!(
+            system.propose_ownership_change("treasury", "alice", OwnershipChange::SetThreshold(4)),
+            Err(CustodyError::InvalidThreshold { .. })
+        ));
+        let mut other = CustodySystem::new();
+        other
+            .create_wallet("w".to_string(), "0x1".to_string(), WalletType::Hot)
+            .unwrap();
+        assert!(other.make_joint_wallet("w", &["alice"], 0).is_err());
+    }
+}
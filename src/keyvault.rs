@@ -0,0 +1,396 @@
+//! Encrypted-at-rest signing key material (feature `keyvault`).
+//!
+//! A [`KeyVault`] holds each wallet's signing key encrypted with
+//! ChaCha20-Poly1305 under a master key derived from a passphrase with
+//! Argon2id, whose cost parameters are stored with the vault. Each key is
+//! bound to its wallet id, so a key moved to another wallet's entry fails
+//! to decrypt. The master key only exists in memory between
+//! [`unlock`](KeyVault::unlock) and [`lock`](KeyVault::lock), and it and
+//! every decrypted key are wiped from memory when dropped.
+//!
+//! [`CustodySystem::with_signing_key`] is the gate signers go through: no
+//! wallet's key can be used while the vault is locked.
+
+use crate::{CustodyError, CustodySystem};
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use zeroize::Zeroizing;
+
+/// Plaintext sealed at creation to check passphrases against
+const VERIFIER: &[u8] = b"securevault/keyvault/v1";
+
+/// Minimum accepted passphrase length for a new vault
+const MIN_PASSPHRASE_CHARS: usize = 12;
+
+/// A ciphertext and the nonce it was sealed with, hex-encoded at rest
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Sealed {
+    nonce: String,
+    ciphertext: String,
+}
+
+/// Argon2id costs the master key is derived with
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+struct KdfParams {
+    m_cost: u32,
+    t_cost: u32,
+    p_cost: u32,
+}
+
+impl KdfParams {
+    fn argon2(self) -> Result<Argon2<'static>, CustodyError> {
+        // Stored costs come from the vault file; refuse ones that would
+        // make unlocking arbitrarily slow
+        if self.m_cost > Params::DEFAULT_M_COST
+            || self.t_cost > Params::DEFAULT_T_COST
+            || self.p_cost > Params::DEFAULT_P_COST
+        {
+            return Err(CustodyError::KeyVaultFailed(
+                "key derivation parameters exceed the supported maximum".to_string(),
+            ));
+        }
+        let params =
+            Params::new(self.m_cost, self.t_cost, self.p_cost, None).map_err(vault_failed)?;
+        Ok(Argon2::new(Algorithm::Argon2id, Version::V0x13, params))
+    }
+}
+
+impl Default for KdfParams {
+    fn default() -> Self {
+        Self {
+            m_cost: Params::DEFAULT_M_COST,
+            t_cost: Params::DEFAULT_T_COST,
+            p_cost: Params::DEFAULT_P_COST,
+        }
+    }
+}
+
+/// Passphrase-protected store of wallet signing keys
+#[derive(Serialize, Deserialize)]
+pub struct KeyVault {
+    salt: String,
+    kdf: KdfParams,
+    verifier: Sealed,
+    keys: BTreeMap<String, Sealed>,
+    #[serde(skip)]
+    master: Option<Zeroizing<[u8; 32]>>,
+}
+
+impl std::fmt::Debug for KeyVault {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("KeyVault")
+            .field("keys", &self.keys.keys().collect::<Vec<_>>())
+            .field("locked", &self.is_locked())
+            .finish()
+    }
+}
+
+impl KeyVault {
+    /// Creates an empty vault protected by `passphrase`, returned unlocked
+    ///
+    /// # Example
+    /// ```
+    /// use securevault::KeyVault;
+    /// let mut vault = KeyVault::create("correct horse battery").unwrap();
+    /// vault.store_key("cold-1", &[7u8; 32]).unwrap();
+    /// vault.lock();
+    /// assert!(vault.with_key("cold-1", |key| key.len()).is_err());
+    ///
+    /// vault.unlock("correct horse battery").unwrap();
+    /// assert_eq!(vault.with_key("cold-1", |key| key.len()), Ok(32));
+    /// ```
+    pub fn create(passphrase: &str) -> Result<Self, CustodyError> {
+        if passphrase.chars().count() < MIN_PASSPHRASE_CHARS {
+            return Err(CustodyError::KeyVaultFailed(format!(
+                "passphrase must be at least {} characters",
+                MIN_PASSPHRASE_CHARS
+            )));
+        }
+        let mut salt = [0u8; 16];
+        getrandom::getrandom(&mut salt).map_err(vault_failed)?;
+        let kdf = KdfParams::default();
+        let master = derive(passphrase, &salt, kdf)?;
+        let verifier = seal(&master, VERIFIER, VERIFIER)?;
+        Ok(Self {
+            salt: hex::encode(salt),
+            kdf,
+            verifier,
+            keys: BTreeMap::new(),
+            master: Some(master),
+        })
+    }
+
+    /// Derives the master key from `passphrase`, failing if it is wrong
+    pub fn unlock(&mut self, passphrase: &str) -> Result<(), CustodyError> {
+        let salt = hex::decode(&self.salt).map_err(vault_failed)?;
+        let master = derive(passphrase, &salt, self.kdf)?;
+        if !self.verifies(&master) {
+            return Err(CustodyError::InvalidPassphrase);
+        }
+        self.master = Some(master);
+        Ok(())
+    }
+
+    /// Returns true if `master` is the key the vault was sealed under
+    pub(crate) fn verifies(&self, master: &[u8; 32]) -> bool {
+        matches!(
+            open(master, VERIFIER, &self.verifier),
+            Ok(plaintext) if plaintext.as_slice() == VERIFIER
+        )
+    }
+
+    /// Unlocks with a master key recovered by other means than the
+    /// passphrase; the caller checks it with [`verifies`](Self::verifies)
+    #[cfg(feature = "key-shares")]
+    pub(crate) fn unlock_with(&mut self, master: Zeroizing<[u8; 32]>) {
+        self.master = Some(master);
+    }
+
+    /// Wipes the master key from memory
+    pub fn lock(&mut self) {
+        self.master = None;
+    }
+
+    /// Returns true if keys cannot currently be stored or used
+    pub fn is_locked(&self) -> bool {
+        self.master.is_none()
+    }
+
+    /// Encrypts and stores the signing key of a wallet, replacing any
+    /// previous one
+    pub fn store_key(&mut self, wallet_id: &str, secret: &[u8]) -> Result<(), CustodyError> {
+        let sealed = seal(self.master()?, wallet_id.as_bytes(), secret)?;
+        self.keys.insert(wallet_id.to_string(), sealed);
+        Ok(())
+    }
+
+    /// Deletes a wallet's key, returning whether there was one
+    pub fn remove_key(&mut self, wallet_id: &str) -> bool {
+        self.keys.remove(wallet_id).is_some()
+    }
+
+    /// Returns true if the vault holds a key for the wallet
+    pub fn contains_key(&self, wallet_id: &str) -> bool {
+        self.keys.contains_key(wallet_id)
+    }
+
+    /// Ids of the wallets with a stored key, sorted
+    pub fn wallet_ids(&self) -> Vec<&str> {
+        self.keys.keys().map(String::as_str).collect()
+    }
+
+    /// Decrypts a wallet's key for the duration of `f`
+    ///
+    /// The plaintext is wiped as soon as `f` returns.
+    pub fn with_key<T>(
+        &self,
+        wallet_id: &str,
+        f: impl FnOnce(&[u8]) -> T,
+    ) -> Result<T, CustodyError> {
+        let master = self.master()?;
+        let sealed = self
+            .keys
+            .get(wallet_id)
+            .ok_or_else(|| CustodyError::KeyNotFound(wallet_id.to_string()))?;
+        let secret = open(master, wallet_id.as_bytes(), sealed)?;
+        Ok(f(&secret))
+    }
+
+    /// Serializes the encrypted vault; no plaintext key material is
+    /// included
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("key vault serialization cannot fail")
+    }
+
+    /// Loads a vault written by [`to_json`](Self::to_json), locked
+    pub fn from_json(json: &str) -> Result<Self, CustodyError> {
+        serde_json::from_str(json).map_err(|err| CustodyError::KeyVaultFailed(err.to_string()))
+    }
+
+    pub(crate) fn master(&self) -> Result<&[u8; 32], CustodyError> {
+        self.master.as_deref().ok_or(CustodyError::VaultLocked)
+    }
+}
+
+impl CustodySystem {
+    /// Runs `f` with the signing key of a wallet from `vault`
+    ///
+    /// The wallet must exist. No wallet, hot or cold, can sign while the
+    /// vault is locked; keys are never available without the passphrase.
+    pub fn with_signing_key<T>(
+        &self,
+        vault: &KeyVault,
+        wallet_id: &str,
+        f: impl FnOnce(&[u8]) -> T,
+    ) -> Result<T, CustodyError> {
+        if self.get_wallet(wallet_id).is_none() {
+            return Err(CustodyError::WalletNotFound(wallet_id.to_string()));
+        }
+        vault.with_key(wallet_id, f)
+    }
+}
+
+fn derive(
+    passphrase: &str,
+    salt: &[u8],
+    kdf: KdfParams,
+) -> Result<Zeroizing<[u8; 32]>, CustodyError> {
+    let mut key = Zeroizing::new([0u8; 32]);
+    kdf.argon2()?
+        .hash_password_into(passphrase.as_bytes(), salt, key.as_mut())
+        .map_err(vault_failed)?;
+    Ok(key)
+}
+
+/// Encrypts `plaintext`, authenticating `aad` along with it
+fn seal(master: &[u8; 32], aad: &[u8], plaintext: &[u8]) -> Result<Sealed, CustodyError> {
+    let mut nonce = [0u8; 12];
+    getrandom::getrandom(&mut nonce).map_err(vault_failed)?;
+    let ciphertext = ChaCha20Poly1305::new(Key::from_slice(master))
+        .encrypt(
+            Nonce::from_slice(&nonce),
+            Payload {
+                msg: plaintext,
+                aad,
+            },
+        )
+        .map_err(vault_failed)?;
+    Ok(Sealed {
+        nonce: hex::encode(nonce),
+        ciphertext: hex::encode(ciphertext),
+    })
+}
+
+fn open(
+    master: &[u8; 32],
+    aad: &[u8],
+    sealed: &Sealed,
+) -> Result<Zeroizing<Vec<u8>>, CustodyError> {
+    let corrupted = || CustodyError::KeyVaultFailed("corrupted key material".to_string());
+    let nonce = hex::decode(&sealed.nonce).map_err(|_| corrupted())?;
+    let ciphertext = hex::decode(&sealed.ciphertext).map_err(|_| corrupted())?;
+    if nonce.len() != 12 {
+        return Err(corrupted());
+    }
+    ChaCha20Poly1305::new(Key::from_slice(master))
+        .decrypt(
+            Nonce::from_slice(&nonce),
+            Payload {
+                msg: &ciphertext,
+                aad,
+            },
+        )
+        .map(Zeroizing::new)
+        .map_err(|_| corrupted())
+}
+
+fn vault_failed(err: impl std::fmt::Display) -> CustodyError {
+    CustodyError::KeyVaultFailed(err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::WalletType;
+
+    const PASSPHRASE: &str = "correct horse battery";
+
+    #[test]
+    fn test_lock_and_unlock() {
+        let mut vault = KeyVault::create(PASSPHRASE).unwrap();
+        vault.store_key("w", b"secret key").unwrap();
+        vault.lock();
+        assert!(vault.is_locked());
+        assert_eq!(
+            vault.store_key("x", b"other"),
+            Err(CustodyError::VaultLocked)
+        );
+        assert_eq!(
+            vault.unlock("wrong passphrase!"),
+            Err(CustodyError::InvalidPassphrase)
+        );
+        assert!(vault.is_locked());
+        vault.unlock(PASSPHRASE).unwrap();
+        assert_eq!(
+            vault.with_key("w", |key| key.to_vec()),
+            Ok(b"secret key".to_vec())
+        );
+        assert_eq!(
+            vault.with_key("x", |_| ()),
+            Err(CustodyError::KeyNotFound("x".to_string()))
+        );
+        assert!(matches!(
+            KeyVault::create("short"),
+            Err(CustodyError::KeyVaultFailed(_))
+        ));
+    }
+
+    #[test]
+    fn test_persisted_vault_is_encrypted_and_locked() {
+        let mut vault = KeyVault::create(PASSPHRASE).unwrap();
+        vault.store_key("w", b"plaintext-key-material").unwrap();
+        let json = vault.to_json();
+        assert!(!json.contains(&hex::encode(b"plaintext-key-material")));
+
+        let mut loaded = KeyVault::from_json(&json).unwrap();
+        assert!(loaded.is_locked());
+        assert_eq!(loaded.wallet_ids(), ["w"]);
+        loaded.unlock(PASSPHRASE).unwrap();
+        assert_eq!(
+            loaded.with_key("w", |key| key.to_vec()),
+            Ok(b"plaintext-key-material".to_vec())
+        );
+        assert!(loaded.remove_key("w"));
+        assert!(!loaded.contains_key("w"));
+    }
+
+    #[test]
+    fn test_stored_keys_are_bound_to_their_wallet_and_costs() {
+        let mut vault = KeyVault::create(PASSPHRASE).unwrap();
+        vault.store_key("a", b"key of a").unwrap();
+        vault.store_key("b", b"key of b").unwrap();
+        let entry = vault.keys["a"].clone();
+        vault.keys.insert("b".to_string(), entry);
+        assert!(matches!(
+            vault.with_key("b", |_| ()),
+            Err(CustodyError::KeyVaultFailed(_))
+        ));
+
+        let json = vault.to_json();
+        assert!(json.contains(&format!("\"m_cost\": {}", Params::DEFAULT_M_COST)));
+        let mut costly = KeyVault::from_json(&json).unwrap();
+        costly.kdf.m_cost = u32::MAX;
+        assert!(matches!(
+            costly.unlock(PASSPHRASE),
+            Err(CustodyError::KeyVaultFailed(reason)) if reason.contains("maximum")
+        ));
+    }
+
+    #[test]
+    fn test_no_wallet_signs_while_locked() {
+        let mut system = CustodySystem::new();
+        let mut vault = KeyVault::create(PASSPHRASE).unwrap();
+        for (id, wallet_type) in [("cold", WalletType::Cold), ("hot", WalletType::Hot)] {
+            system
+                .create_wallet(id.to_string(), id.to_string(), wallet_type)
+                .unwrap();
+            vault.store_key(id, &[1; 32]).unwrap();
+        }
+        assert_eq!(system.with_signing_key(&vault, "cold", |key| key[0]), Ok(1));
+
+        vault.lock();
+        for id in ["cold", "hot"] {
+            assert_eq!(
+                system.with_signing_key(&vault, id, |key| key[0]),
+                Err(CustodyError::VaultLocked)
+            );
+        }
+        assert_eq!(
+            system.with_signing_key(&vault, "nope", |_| ()),
+            Err(CustodyError::WalletNotFound("nope".to_string()))
+        );
+    }
+}
@@ -3,56 +3,706 @@
 //! A cryptocurrency custody system with hot/cold wallet separation
 //! and transaction audit trails.
 //!
-//! ## Important Note on Precision
+//! ## Precision
 //!
-//! This implementation uses `f64` for balances and amounts. Floating-point
-//! arithmetic is not exact, so this is **not suitable for production financial
-//! systems** where precise decimal math is required. A production system should
-//! use integer arithmetic (e.g., satoshis/wei) or a fixed-precision decimal
-//! library.
+//! Balances and transaction amounts are [`Amount`]s: fixed-point decimals
+//! with 18 fractional digits, exact down to the satoshi and the wei, with
+//! overflow-checked arithmetic. Exchange rates and fiat valuations remain
+//! `f64`, since they are estimates to begin with.
+//!
+//! ## Feature Flags
+//!
+//! The default build contains only the in-memory ledger. Subsystems that need
+//! extra dependencies are opt-in:
+//!
+//! | Feature        | Enables                                      |
+//! |----------------|----------------------------------------------|
+//! | `cli`          | The `securevault` admin command-line tool    |
+//! | `config`       | Loading configuration from TOML files        |
+//! | `async`        | Tokio async API and broadcast event streams  |
+//! | `server`       | Network-facing servers                       |
+//! | `dashboard`    | Embedded web dashboard (implies `server`)    |
+//! | `grpc`         | tonic gRPC service (implies `server`)        |
+//! | `webhooks`     | HMAC-signed webhooks for custody events      |
+//! | `price-http`   | HTTP price oracle for portfolio valuation    |
+//! | `sqlite`       | SQLite-backed persistent storage             |
+//! | `bitcoin`      | Bitcoin chain integration                    |
+//! | `bitcoin-rpc`  | Deposit watcher for Bitcoin Core nodes       |
+//! | `ethereum`     | Ethereum chain integration                   |
+//! | `eth-rpc`      | ETH and ERC-20 deposit scanner               |
+//! | `hd`           | HD deposit addresses (implies `bitcoin`)     |
+//! | `mnemonic`     | BIP-39 seed phrases for cold wallet setup    |
+//! | `psbt`         | PSBTs for Bitcoin multisig withdrawals       |
+//! | `hsm`          | Hardware security module signers             |
+//! | `hw-wallet`    | Ledger and Trezor hardware wallet signers    |
+//! | `mpc`          | Threshold-signature signing ceremonies       |
+//! | `keyvault`     | Passphrase-encrypted signing key storage     |
+//! | `key-shares`   | Shamir shares of the vault master key        |
+//! | `signing`      | In-process secp256k1 software signers        |
+//! | `paper-backup` | Printable cold wallet backups with QR codes  |
+//! | `recovery`     | Encrypted disaster recovery archives         |
+//! | `airgap`       | BC-UR QR transport for offline signers       |
+//! | `scripting`    | Rhai pre-transaction validation hooks        |
+//! | `chaos`        | Fault injection harness for integrators      |
+//! | `wasm`         | wasm-bindgen JavaScript bindings             |
+//!
+//! ## WebAssembly
+//!
+//! The default build compiles for `wasm32-unknown-unknown`, taking the time
+//! from the JavaScript host. A simulation can drive a
+//! [`time::ManualClock`] through [`CustodySystem::set_clock`] instead.
+//! File-backed storage compiles but fails with
+//! [`CustodyError::StorageFailed`] in the browser, which has no file system.
+
+// Declared first so the `amount!` macro is in scope in every module
+#[macro_use]
+mod amount;
+mod accounts;
+mod accrual;
+mod address;
+#[cfg(feature = "airgap")]
+mod airgap;
+mod api_keys;
+mod approval;
+mod archive;
+mod asset;
+#[cfg(feature = "async")]
+mod async_api;
+mod audit;
+#[cfg(feature = "paper-backup")]
+mod backup;
+mod batch;
+#[cfg(feature = "bitcoin-rpc")]
+mod bitcoin_rpc;
+mod broadcast;
+mod category;
+mod cdc;
+mod chain;
+#[cfg(feature = "chaos")]
+mod chaos;
+mod commitments;
+mod config;
+mod conversion;
+mod customers;
+#[cfg(feature = "dashboard")]
+mod dashboard;
+mod deposits;
+mod diff;
+mod digest;
+mod error;
+#[cfg(feature = "eth-rpc")]
+mod eth_rpc;
+mod events;
+mod exchange;
+mod export;
+mod extension;
+mod fees;
+mod fiat;
+pub mod format;
+mod four_eyes;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+mod hd;
+mod history;
+mod holds;
+#[cfg(any(
+    feature = "webhooks",
+    feature = "price-http",
+    feature = "bitcoin-rpc",
+    feature = "eth-rpc"
+))]
+mod http;
+#[cfg(feature = "hw-wallet")]
+mod hw_wallet;
+pub mod i18n;
+mod idempotency;
+mod import;
+mod index;
+pub mod iso20022;
+mod joint;
+#[cfg(feature = "keyvault")]
+mod keyvault;
+mod lots;
+mod metadata;
+#[cfg(feature = "mnemonic")]
+mod mnemonic;
+mod multisig;
+mod nonces;
+pub mod notify;
+mod offline;
+mod ofx;
+mod oracle;
+mod periods;
+mod pnl;
+mod policy;
+mod portfolio;
+mod precheck;
+mod profile;
+#[cfg(feature = "psbt")]
+mod psbt;
+mod queue;
+mod quorum;
+mod rate_limit;
+mod reconcile;
+#[cfg(feature = "recovery")]
+mod recovery;
+mod replay;
+mod reversal;
+mod rotation;
+mod screening;
+#[cfg(feature = "scripting")]
+mod script;
+mod search;
+#[cfg(feature = "key-shares")]
+mod shamir;
+mod signer;
+pub mod statements;
+mod status;
+mod storage;
+mod surveillance;
+mod template;
+mod tenants;
+#[cfg(feature = "mpc")]
+mod threshold;
+pub mod time;
+mod travel_rule;
+mod wal;
+mod wallet_id;
+#[cfg(feature = "wasm")]
+mod wasm;
+#[cfg(feature = "webhooks")]
+mod webhook;
+mod whitelist;
+
+pub use accounts::{Account, Organization};
+pub use accrual::{Compounding, YieldEnrollment, YieldProgram, YieldReport};
+#[cfg(feature = "bitcoin")]
+pub use address::BitcoinAddressValidator;
+#[cfg(feature = "ethereum")]
+pub use address::EthereumAddressValidator;
+pub use address::{AddressValidator, Chain};
+#[cfg(feature = "airgap")]
+pub use airgap::{encode_ur, SignedWithdrawal, UnsignedWithdrawal, UrDecoder};
+pub use amount::{Amount, ParseAmountError};
+pub use api_keys::{ApiKey, ApiOperation, KeyScope, KeyedSystem, Permission, API_KEY_PREFIX};
+pub use approval::{ApprovalEntry, ApprovalVerdict, PendingWithdrawal};
+pub use asset::Asset;
+#[cfg(feature = "async")]
+pub use async_api::{
+    AsyncCustodySystem, AsyncStorage, BlockingStorage, TokioFileStorage, EVENT_CAPACITY,
+};
+pub use audit::{AuditCheck, AuditReport};
+#[cfg(feature = "paper-backup")]
+pub use backup::{EncryptedSeed, PaperBackup, SeedExport};
+pub use batch::{BatchMode, BatchResult, Operation};
+#[cfg(feature = "bitcoin-rpc")]
+pub use bitcoin_rpc::{
+    BitcoinDepositWatcher, BitcoinNode, BitcoinRpcClient, NodeTransaction, PollReport, SinceBlock,
+    FEE_TARGET_BLOCKS,
+};
+pub use broadcast::{BroadcastStatus, BroadcastTx, Broadcaster, TxHash};
+pub use category::{Category, CategoryFlow, CategoryReport};
+pub use cdc::{Change, ChangeRecord};
+#[cfg(feature = "chaos")]
+pub use chaos::{ChaosConfig, ChaosHarness, ChaosReport};
+pub use commitments::{ProofStep, Side, StateCommitment, WalletState, WalletStateProof};
+pub use config::{
+    Config, ConfirmationsConfig, StorageBackend, StorageConfig, WalletTypeDefaults, ENV_PREFIX,
+};
+pub use conversion::{Conversion, RateProvider, StaticRateProvider};
+pub use customers::{CustodyModel, Customer, CUSTOMER_METADATA_KEY};
+#[cfg(feature = "dashboard")]
+pub use dashboard::{render_dashboard, serve_dashboard};
+pub use deposits::{DepositStatus, ExternalDeposit, DEFAULT_REQUIRED_CONFIRMATIONS};
+pub use diff::{StateDiff, WalletDiff};
+pub use error::{CustodyError, OperationKind, PolicyViolation};
+#[cfg(feature = "eth-rpc")]
+pub use eth_rpc::{
+    EthBlock, EthTransaction, EthereumDepositScanner, EthereumNode, EthereumRpcClient, ScanReport,
+    TransferLog, TRANSFER_TOPIC,
+};
+pub use events::{CustodyEvent, EventListener};
+pub use exchange::{ExchangeConnector, ExchangeTrade, Fill, MarketOrder, SimulatedExchange};
+pub use export::{ExportFormat, TransactionFilter};
+pub use extension::{
+    ExtensionPoint, ExtensionRegistry, ExtensionSpec, ExtensionsConfig, Factory, Settings,
+};
+pub use fees::{
+    FeeEstimate, FeeEstimator, FeeRate, StaticFeeEstimator, ACTUAL_FEE_METADATA_KEY,
+    BTC_WITHDRAWAL_VBYTES, ERC20_TRANSFER_GAS, ESTIMATED_FEE_METADATA_KEY, ETH_TRANSFER_GAS,
+};
+pub use fiat::{FiatGateway, IncomingWire, PayoutRequest};
+pub use format::{format_amount, AmountFormatter, SymbolPosition};
+pub use four_eyes::{AdminAction, FourEyesRule, ProposedAction, DEFAULT_ACTION_EXPIRY_SECS};
+#[cfg(feature = "grpc")]
+pub use grpc::{serve_grpc, CustodyService};
+pub use hd::HdAccount;
+pub use history::{BalancePoint, HistoricalState};
+pub use holds::{Hold, HoldId, HoldStatus};
+#[cfg(feature = "hw-wallet")]
+pub use hw_wallet::{
+    detect_devices, DeviceBinding, DeviceKind, DeviceReply, DeviceTransport, HardwareDevice,
+    HardwareWalletSigner,
+};
+pub use i18n::{Label, Locale};
+pub use import::{CsvMapping, ImportBalances, ImportReport, RowError};
+use index::TransactionIndex;
+pub use joint::{
+    JointOperation, JointOperationKind, JointOwnership, OperationStatus, OwnershipChange,
+};
+#[cfg(feature = "keyvault")]
+pub use keyvault::KeyVault;
+pub use lots::{Disposal, Lot, LotMethod, LotReport};
+pub use metadata::TransactionMetadata;
+#[cfg(feature = "mnemonic")]
+pub use mnemonic::Mnemonic;
+pub use multisig::MultiSigWithdrawal;
+pub use nonces::{PendingNonce, DEFAULT_STUCK_AFTER_SECS};
+pub use offline::{SignedBatch, SignedOperation, UnsignedBatch, OFFLINE_BATCH_VERSION};
+#[cfg(feature = "price-http")]
+pub use oracle::HttpPriceOracle;
+pub use oracle::{AssetValue, PriceOracle, StaticPriceOracle, Valuation};
+pub use periods::{ClosedPeriod, PeriodStatement};
+pub use pnl::{FiatValue, PnlReport, WalletPnl};
+pub use policy::WithdrawalPolicy;
+pub use portfolio::{render_portfolio, sparkline};
+use precheck::Authorization;
+pub use precheck::Decision;
+pub use profile::{ProfileQuery, RiskTier, WalletProfile};
+#[cfg(feature = "psbt")]
+pub use psbt::{multisig_address, Psbt, Utxo, UtxoSource};
+pub use queue::{BusinessHours, QueuedWithdrawal, ReleaseRate, ReleasedWithdrawal};
+pub use quorum::{Quorum, QuorumChange};
+pub use rate_limit::RateLimit;
+pub use reconcile::{Discrepancy, ReconciledBalance, Reconciler, ReconciliationReport};
+#[cfg(feature = "recovery")]
+pub use recovery::{PendingOperations, PolicySet, RecoveryArchive, RECOVERY_FORMAT_VERSION};
+pub use replay::{BalanceMismatch, ReplayReport};
+pub use reversal::{REVERSED_BY_METADATA_KEY, REVERSES_METADATA_KEY};
+pub use rotation::{KeyGenerator, KeyRotation, SWEEP_FROM_METADATA_KEY, SWEEP_TO_METADATA_KEY};
+pub use screening::{
+    ComplianceScreener, FlaggedOperation, RuleScreener, ScreeningRequest, ScreeningVerdict,
+};
+pub use search::{MatchedField, SearchItem, SearchResult};
+#[cfg(feature = "key-shares")]
+pub use shamir::KeyShare;
+#[cfg(feature = "hsm")]
+pub use signer::HsmSigner;
+#[cfg(feature = "signing")]
+pub use signer::SoftwareSigner;
+pub use signer::{CallbackSigner, SignCallback, Signature, Signer, UnsignedTx};
+pub use status::{StatusChange, WalletStatus};
+#[cfg(feature = "sqlite")]
+pub use storage::SqliteStorage;
+pub use storage::{JsonFileStorage, Snapshot, Storage, TransactionChunks, SNAPSHOT_VERSION};
+pub use surveillance::{
+    Alert, AlertStatus, DetectionRule, DormancyRule, Finding, RapidMovementRule, Severity,
+    StructuringRule,
+};
+pub use template::WalletTemplate;
+pub use tenants::{Tenant, TenantSystem, TENANT_SEPARATOR};
+#[cfg(feature = "mpc")]
+pub use threshold::{
+    CeremonyStatus, LocalCeremony, MockParticipant, MockThresholdScheme, RoundMessage,
+    SigningCeremony, ThresholdParticipant, ThresholdSigner,
+};
+pub use time::Timestamp;
+use time::{Clock, SystemClock};
+pub use travel_rule::{Party, TravelRuleInfo, TRAVEL_RULE_METADATA_PREFIX};
+pub use uuid::Uuid;
+pub use wal::WalStorage;
+pub use wallet_id::{HashedIdScheme, IdInput, IdScheme, DEFAULT_ID_SCHEME};
+#[cfg(feature = "wasm")]
+pub use wasm::WasmCustodySystem;
+#[cfg(feature = "webhooks")]
+pub use webhook::{
+    EventFilter, HttpTransport, RetryPolicy, WebhookDelivery, WebhookDispatcher, WebhookTransport,
+    WebhookWorker,
+};
+pub use whitelist::{WhitelistEntry, DEFAULT_WHITELIST_DELAY_SECS};
 
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::sync::Arc;
+
+/// Wallets keyed by id. A persistent map, so cloning it is O(1) and clones
+/// share structure until modified.
+pub type WalletMap = im::HashMap<String, Wallet>;
+
+/// The ordered transaction log. A persistent vector, so cloning it is O(1).
+pub type TransactionLog = im::Vector<Transaction>;
 
 /// Represents a cryptocurrency wallet in the custody system
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct Wallet {
     pub id: String,
     pub address: String,
-    pub balance: f64,
+    /// Balance in the primary asset
+    pub balance: Amount,
     pub wallet_type: WalletType,
+    /// Primary asset, the one `balance` is denominated in
+    #[serde(default)]
+    pub asset: Asset,
+    /// Non-zero balances in assets other than the primary one
+    #[serde(
+        default,
+        skip_serializing_if = "BTreeMap::is_empty",
+        with = "asset::as_pairs"
+    )]
+    pub holdings: BTreeMap<Asset, Amount>,
+    /// Free-form tags used for grouping and reporting
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub tags: BTreeSet<String>,
+    /// Name of the template the wallet was created from, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template: Option<String>,
+    /// When the wallet was created
+    #[serde(default)]
+    pub created_at: Timestamp,
+    /// Signing quorum, if one applies
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quorum: Option<Quorum>,
+    /// Blockchain the address belongs to; addresses are only validated
+    /// for wallets that have one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chain: Option<Chain>,
+    /// Lifecycle status; only active wallets take every operation
+    #[serde(default)]
+    pub status: WalletStatus,
+    /// Free-text note
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memo: Option<String>,
+    /// Arbitrary key/value annotations
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub metadata: BTreeMap<String, String>,
+    /// Owner, contact, custody agreement and risk tier
+    #[serde(default, skip_serializing_if = "WalletProfile::is_empty")]
+    pub profile: WalletProfile,
+    /// Extended public key deposit addresses are derived from, for HD
+    /// wallets
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hd: Option<HdAccount>,
+    /// Former addresses, retired by key rotation but still watched for
+    /// deposits
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub deprecated_addresses: Vec<String>,
+    /// Client account the wallet belongs to, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub account: Option<String>,
+    /// How the wallet holds customer funds, for customer wallets
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub custody: Option<CustodyModel>,
+    /// When the wallet was archived, for wallets kept only for their
+    /// history
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub archived_at: Option<Timestamp>,
 }
 
-/// Represents the type of wallet: Hot (operational) or Cold (storage)
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+impl Wallet {
+    /// Returns the current address and every deprecated one
+    pub fn addresses(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(self.address.as_str())
+            .chain(self.deprecated_addresses.iter().map(String::as_str))
+    }
+
+    /// Returns the balance held in `asset`
+    pub fn balance_of(&self, asset: &Asset) -> Amount {
+        if *asset == self.asset {
+            self.balance
+        } else {
+            self.holdings.get(asset).copied().unwrap_or_default()
+        }
+    }
+
+    /// Returns the primary asset and every other holding with its balance
+    pub fn balances(&self) -> impl Iterator<Item = (&Asset, Amount)> {
+        std::iter::once((&self.asset, self.balance)).chain(
+            self.holdings
+                .iter()
+                .map(|(asset, balance)| (asset, *balance)),
+        )
+    }
+
+    /// Sets the balance held in `asset`; emptied holdings are dropped
+    fn set_balance(&mut self, asset: &Asset, balance: Amount) {
+        if *asset == self.asset {
+            self.balance = balance;
+        } else if balance.is_zero() {
+            self.holdings.remove(asset);
+        } else {
+            self.holdings.insert(asset.clone(), balance);
+        }
+    }
+}
+
+/// Represents the type of wallet: Hot (operational), Cold (storage) or
+/// MultiSig (shared control)
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub enum WalletType {
     /// Hot wallet for operational use with frequent transactions
     Hot,
     /// Cold wallet for long-term secure storage
     Cold,
+    /// Wallet whose withdrawals need `required` of `signers` to sign
+    MultiSig {
+        required: usize,
+        signers: BTreeSet<String>,
+    },
+}
+
+impl WalletType {
+    /// Creates a multi-signature type from a list of signer ids
+    pub fn multisig(required: usize, signers: &[&str]) -> Self {
+        WalletType::MultiSig {
+            required,
+            signers: signers.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+}
+
+impl fmt::Display for WalletType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WalletType::Hot => f.write_str("Hot"),
+            WalletType::Cold => f.write_str("Cold"),
+            WalletType::MultiSig { required, signers } => {
+                write!(f, "MultiSig({}/{})", required, signers.len())
+            }
+        }
+    }
 }
 
 /// Represents a transaction in the audit trail
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct Transaction {
+    /// Sequential id, assigned when the transaction is recorded
+    #[serde(default)]
+    pub id: u64,
+    /// Globally unique id, assigned when the transaction is recorded
+    #[serde(default)]
+    pub uuid: Uuid,
     pub wallet_id: String,
     pub transaction_type: TransactionType,
-    pub amount: f64,
-    pub timestamp: u64,
+    pub amount: Amount,
+    pub timestamp: Timestamp,
+    /// Asset the amount is denominated in
+    #[serde(default)]
+    pub asset: Asset,
+    /// Exchange rate applied, for conversion legs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rate: Option<f64>,
+    /// The other wallet involved, for operations spanning two wallets
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub counterparty: Option<String>,
+    /// Business purpose, if categorised
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub category: Option<Category>,
+    /// Fiat value at the time of the transaction (cost basis for credits,
+    /// proceeds for debits)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fiat_value: Option<FiatValue>,
+    /// External reference (bank wire, payout id, ...)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reference: Option<String>,
+    /// Client-supplied key of the idempotent operation that recorded it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idempotency_key: Option<String>,
+    /// Free-text note
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memo: Option<String>,
+    /// Free-form tags, e.g. ticket numbers
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub tags: BTreeSet<String>,
+    /// Arbitrary key/value annotations, e.g. customer references
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub metadata: BTreeMap<String, String>,
+    /// SHA-256 of the canonical encoding, sealed when recorded
+    #[serde(default)]
+    pub sealed_digest: String,
+    /// Chain hash of the entry recorded before this one
+    #[serde(default)]
+    pub previous_hash: String,
+    /// SHA-256 linking this entry to the previous one, over
+    /// `previous_hash` and the ledger fields
+    #[serde(default)]
+    pub chain_hash: String,
 }
 
-/// Type of transaction: Deposit or Withdrawal
+impl Transaction {
+    fn new(
+        wallet_id: &str,
+        transaction_type: TransactionType,
+        amount: Amount,
+        asset: Asset,
+        timestamp: Timestamp,
+    ) -> Self {
+        Self {
+            id: 0,
+            uuid: Uuid::nil(),
+            wallet_id: wallet_id.to_string(),
+            transaction_type,
+            amount,
+            timestamp,
+            asset,
+            rate: None,
+            counterparty: None,
+            category: None,
+            fiat_value: None,
+            reference: None,
+            idempotency_key: None,
+            memo: None,
+            tags: BTreeSet::new(),
+            metadata: BTreeMap::new(),
+            sealed_digest: String::new(),
+            previous_hash: String::new(),
+            chain_hash: String::new(),
+        }
+    }
+}
+
+/// Type of transaction
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub enum TransactionType {
     Deposit,
     Withdrawal,
+    /// Debit leg of a cross-asset conversion
+    ConversionOut,
+    /// Credit leg of a cross-asset conversion
+    ConversionIn,
+    /// Move between two custodied wallets, debiting `wallet_id` and
+    /// crediting `counterparty`
+    Transfer,
+    /// Interest or staking reward credited by a yield program
+    Reward,
+}
+
+impl TransactionType {
+    /// Returns true if this transaction type increases the wallet balance
+    pub fn is_credit(&self) -> bool {
+        matches!(
+            self,
+            TransactionType::Deposit | TransactionType::ConversionIn | TransactionType::Reward
+        )
+    }
+}
+
+impl Transaction {
+    /// Returns true if the transaction changes `wallet_id`'s balance
+    pub fn involves(&self, wallet_id: &str) -> bool {
+        self.postings().any(|(id, _)| id == wallet_id)
+    }
+
+    /// Returns true if the transaction increases `wallet_id`'s balance
+    pub fn is_credit_for(&self, wallet_id: &str) -> bool {
+        self.balance_change(wallet_id).is_positive()
+    }
+
+    /// Returns the signed change the transaction makes to `wallet_id`'s
+    /// balance, zero if it does not involve the wallet
+    pub fn balance_change(&self, wallet_id: &str) -> Amount {
+        self.postings()
+            .filter(|(id, _)| *id == wallet_id)
+            .map(|(_, change)| change)
+            .sum()
+    }
+
+    /// Returns each wallet whose balance the transaction changes, with the
+    /// signed change
+    ///
+    /// A transfer yields the debited source, then the credited destination.
+    pub fn postings(&self) -> impl Iterator<Item = (&str, Amount)> {
+        let (first, second) = match (&self.transaction_type, &self.counterparty) {
+            (TransactionType::Transfer, Some(destination)) => (
+                (self.wallet_id.as_str(), -self.amount),
+                Some((destination.as_str(), self.amount)),
+            ),
+            (kind, _) if kind.is_credit() => ((self.wallet_id.as_str(), self.amount), None),
+            _ => ((self.wallet_id.as_str(), -self.amount), None),
+        };
+        std::iter::once(first).chain(second)
+    }
 }
 
 /// Main custody system that manages wallets and transactions
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct CustodySystem {
-    wallets: HashMap<String, Wallet>,
-    transactions: Vec<Transaction>,
+    wallets: WalletMap,
+    archived_wallets: WalletMap,
+    transactions: TransactionLog,
+    index: TransactionIndex,
+    templates: im::HashMap<String, WalletTemplate>,
+    joint_ownership: im::HashMap<String, JointOwnership>,
+    accounts: im::HashMap<String, Account>,
+    organizations: im::HashMap<String, Organization>,
+    customers: im::HashMap<String, Customer>,
+    joint_operations: im::OrdMap<u64, JointOperation>,
+    multisig_withdrawals: im::OrdMap<u64, MultiSigWithdrawal>,
+    #[cfg(feature = "psbt")]
+    multisig_keys: im::HashMap<String, psbt::MultisigKeys>,
+    #[cfg(feature = "psbt")]
+    psbts: im::OrdMap<u64, psbt::Psbt>,
+    holds: im::OrdMap<HoldId, Hold>,
+    unsigned_txs: im::OrdMap<u64, UnsignedTx>,
+    broadcasts: im::OrdMap<u64, BroadcastTx>,
+    nonce_accounts: im::HashMap<String, nonces::NonceAccount>,
+    stuck_after_secs: u64,
+    price_oracle: Option<Arc<dyn PriceOracle>>,
+    compliance_screener: Option<Arc<dyn ComplianceScreener>>,
+    fee_estimator: Option<Arc<dyn FeeEstimator>>,
+    key_generator: Option<Arc<dyn KeyGenerator>>,
+    flagged_operations: im::OrdMap<u64, FlaggedOperation>,
+    required_confirmations: u32,
+    confirmation_policies: im::HashMap<Asset, u32>,
+    watched_addresses: im::HashMap<String, String>,
+    external_deposits: im::OrdMap<(String, String), ExternalDeposit>,
+    next_operation_id: u64,
+    next_transaction_id: u64,
+    trades: im::Vector<ExchangeTrade>,
+    id_schemes: im::HashMap<String, Arc<dyn IdScheme>>,
+    derived_ids: im::HashMap<String, IdInput>,
+    address_validators: im::HashMap<Chain, Arc<dyn AddressValidator>>,
+    changes: im::Vector<ChangeRecord>,
+    change_sequence: u64,
+    withdrawal_queue: queue::WithdrawalQueue,
+    default_quorums: im::HashMap<WalletType, Quorum>,
+    quorum_changes: im::OrdMap<u64, QuorumChange>,
+    withdrawal_approvers: im::HashMap<String, BTreeSet<String>>,
+    pending_withdrawals: im::OrdMap<u64, PendingWithdrawal>,
+    approval_log: im::Vector<ApprovalEntry>,
+    status_log: im::Vector<StatusChange>,
+    key_rotations: im::Vector<KeyRotation>,
+    frozen_deposits_allowed: bool,
+    default_policies: im::HashMap<WalletType, WithdrawalPolicy>,
+    wallet_policies: im::HashMap<String, WithdrawalPolicy>,
+    four_eyes: Option<FourEyesRule>,
+    admin_actions: im::OrdMap<u64, ProposedAction>,
+    whitelists: whitelist::Whitelists,
+    whitelist_delay_secs: u64,
+    travel_rule_threshold: Option<Amount>,
+    api_keys: im::HashMap<String, ApiKey>,
+    rate_limits: rate_limit::RateLimits,
+    offline_keys: im::HashMap<String, String>,
+    tenants: im::HashMap<String, Tenant>,
+    closed_periods: im::Vector<ClosedPeriod>,
+    yield_enrollments: im::HashMap<String, YieldEnrollment>,
+    detection_rules: im::Vector<Arc<dyn DetectionRule>>,
+    alerts: im::OrdMap<u64, Alert>,
+    state_commitments: im::Vector<StateCommitment>,
+    state_commitment_interval: Option<u64>,
+    statement_accounts: im::HashMap<String, iso20022::StatementAccount>,
+    /// Key of the idempotent operation in progress, stamped on the
+    /// transactions it records
+    idempotency_key: Option<String>,
+    #[cfg(feature = "scripting")]
+    script_hooks: im::Vector<Arc<script::ScriptHook>>,
+    #[cfg(feature = "hw-wallet")]
+    device_bindings: im::HashMap<String, hw_wallet::DeviceBinding>,
+    #[cfg(feature = "chaos")]
+    clock_skew: i64,
+    clock: Arc<dyn Clock>,
+    listeners: events::Listeners,
+    storage: storage::Attached,
 }
 
 impl Default for CustodySystem {
@@ -65,8 +715,83 @@ impl CustodySystem {
     /// Creates a new custody system
     pub fn new() -> Self {
         Self {
-            wallets: HashMap::new(),
-            transactions: Vec::new(),
+            wallets: WalletMap::new(),
+            archived_wallets: WalletMap::new(),
+            transactions: TransactionLog::new(),
+            index: TransactionIndex::default(),
+            templates: im::HashMap::new(),
+            joint_ownership: im::HashMap::new(),
+            accounts: im::HashMap::new(),
+            organizations: im::HashMap::new(),
+            customers: im::HashMap::new(),
+            joint_operations: im::OrdMap::new(),
+            multisig_withdrawals: im::OrdMap::new(),
+            #[cfg(feature = "psbt")]
+            multisig_keys: im::HashMap::new(),
+            #[cfg(feature = "psbt")]
+            psbts: im::OrdMap::new(),
+            holds: im::OrdMap::new(),
+            unsigned_txs: im::OrdMap::new(),
+            broadcasts: im::OrdMap::new(),
+            nonce_accounts: im::HashMap::new(),
+            stuck_after_secs: DEFAULT_STUCK_AFTER_SECS,
+            price_oracle: None,
+            compliance_screener: None,
+            fee_estimator: None,
+            key_generator: None,
+            flagged_operations: im::OrdMap::new(),
+            required_confirmations: DEFAULT_REQUIRED_CONFIRMATIONS,
+            confirmation_policies: im::HashMap::new(),
+            watched_addresses: im::HashMap::new(),
+            external_deposits: im::OrdMap::new(),
+            next_operation_id: 1,
+            next_transaction_id: 1,
+            trades: im::Vector::new(),
+            id_schemes: im::HashMap::unit(
+                DEFAULT_ID_SCHEME.to_string(),
+                Arc::new(HashedIdScheme::default()) as Arc<dyn IdScheme>,
+            ),
+            derived_ids: im::HashMap::new(),
+            address_validators: Self::builtin_validators(),
+            changes: im::Vector::new(),
+            change_sequence: 0,
+            withdrawal_queue: queue::WithdrawalQueue::default(),
+            default_quorums: im::HashMap::new(),
+            quorum_changes: im::OrdMap::new(),
+            withdrawal_approvers: im::HashMap::new(),
+            pending_withdrawals: im::OrdMap::new(),
+            approval_log: im::Vector::new(),
+            status_log: im::Vector::new(),
+            key_rotations: im::Vector::new(),
+            frozen_deposits_allowed: false,
+            default_policies: im::HashMap::new(),
+            wallet_policies: im::HashMap::new(),
+            four_eyes: None,
+            admin_actions: im::OrdMap::new(),
+            whitelists: whitelist::Whitelists::new(),
+            whitelist_delay_secs: DEFAULT_WHITELIST_DELAY_SECS,
+            travel_rule_threshold: None,
+            api_keys: im::HashMap::new(),
+            rate_limits: rate_limit::RateLimits::default(),
+            offline_keys: im::HashMap::new(),
+            tenants: im::HashMap::new(),
+            closed_periods: im::Vector::new(),
+            yield_enrollments: im::HashMap::new(),
+            detection_rules: im::Vector::new(),
+            alerts: im::OrdMap::new(),
+            state_commitments: im::Vector::new(),
+            state_commitment_interval: None,
+            statement_accounts: im::HashMap::new(),
+            idempotency_key: None,
+            #[cfg(feature = "scripting")]
+            script_hooks: im::Vector::new(),
+            #[cfg(feature = "hw-wallet")]
+            device_bindings: im::HashMap::new(),
+            #[cfg(feature = "chaos")]
+            clock_skew: 0,
+            clock: Arc::new(SystemClock),
+            listeners: events::Listeners::default(),
+            storage: storage::Attached::default(),
         }
     }
 
@@ -75,7 +800,7 @@ impl CustodySystem {
     /// # Arguments
     /// * `id` - Unique identifier for the wallet
     /// * `address` - Cryptocurrency address
-    /// * `wallet_type` - Type of wallet (Hot or Cold)
+    /// * `wallet_type` - Type of wallet (Hot, Cold or MultiSig)
     ///
     /// # Returns
     /// The created wallet
@@ -95,18 +820,65 @@ impl CustodySystem {
         id: String,
         address: String,
         wallet_type: WalletType,
-    ) -> Result<Wallet, String> {
-        if self.wallets.contains_key(&id) {
-            return Err(format!("Wallet with id '{}' already exists", id));
+    ) -> Result<Wallet, CustodyError> {
+        self.create_wallet_with_asset(id, address, wallet_type, Asset::Btc)
+    }
+
+    /// Creates a new wallet whose balance is denominated in `asset`
+    pub fn create_wallet_with_asset(
+        &mut self,
+        id: String,
+        address: String,
+        wallet_type: WalletType,
+        asset: Asset,
+    ) -> Result<Wallet, CustodyError> {
+        if self.wallet_id_taken(&id) {
+            return Err(CustodyError::WalletAlreadyExists(id));
         }
+        self.insert_wallet(id, address, wallet_type, asset, None)
+    }
 
+    /// Adds a wallet whose id is known to be free
+    fn insert_wallet(
+        &mut self,
+        id: String,
+        address: String,
+        wallet_type: WalletType,
+        asset: Asset,
+        chain: Option<Chain>,
+    ) -> Result<Wallet, CustodyError> {
+        if let WalletType::MultiSig { required, signers } = &wallet_type {
+            if *required == 0 || *required > signers.len() {
+                return Err(CustodyError::InvalidThreshold {
+                    required: *required,
+                    available: signers.len(),
+                });
+            }
+        }
+        let quorum = self.default_quorum(&wallet_type);
         let wallet = Wallet {
             id: id.clone(),
             address,
-            balance: 0.0,
+            balance: Amount::ZERO,
             wallet_type,
+            asset,
+            holdings: BTreeMap::new(),
+            tags: BTreeSet::new(),
+            template: None,
+            created_at: self.current_timestamp(),
+            quorum,
+            chain,
+            status: WalletStatus::Active,
+            memo: None,
+            metadata: BTreeMap::new(),
+            profile: WalletProfile::default(),
+            hd: None,
+            deprecated_addresses: Vec::new(),
+            account: None,
+            custody: None,
+            archived_at: None,
         };
-        self.wallets.insert(id, wallet.clone());
+        self.commit_wallet(wallet.clone())?;
         Ok(wallet)
     }
 
@@ -115,93 +887,217 @@ impl CustodySystem {
         self.wallets.get(id)
     }
 
-    /// Deposits funds to a wallet
+    /// Deposits funds to a wallet, in the wallet's primary asset
     ///
     /// # Arguments
     /// * `id` - Wallet identifier
     /// * `amount` - Amount to deposit
     ///
     /// # Returns
-    /// Ok(()) on success, Err with message on failure
-    pub fn deposit(&mut self, id: &str, amount: f64) -> Result<(), String> {
-        if amount <= 0.0 {
-            return Err("Deposit amount must be positive".to_string());
+    /// Ok(()) on success, Err describing the failure otherwise
+    pub fn deposit(&mut self, id: &str, amount: Amount) -> Result<(), CustodyError> {
+        self.execute_deposit(id, None, amount)
+    }
+
+    /// Deposits funds in `asset`, which need not be the wallet's primary
+    /// asset
+    ///
+    /// # Example
+    /// ```
+    /// use securevault::{amount, Asset, CustodySystem, WalletType};
+    /// let mut system = CustodySystem::new();
+    /// system.create_wallet("w".to_string(), "0x1".to_string(), WalletType::Hot).unwrap();
+    /// system.deposit("w", amount!(1.5)).unwrap();
+    /// system.deposit_asset("w", &Asset::Eth, amount!(20)).unwrap();
+    ///
+    /// let wallet = system.get_wallet("w").unwrap();
+    /// assert_eq!(wallet.balance_of(&Asset::Btc), amount!(1.5));
+    /// assert_eq!(wallet.balance_of(&Asset::Eth), amount!(20));
+    /// ```
+    pub fn deposit_asset(
+        &mut self,
+        id: &str,
+        asset: &Asset,
+        amount: Amount,
+    ) -> Result<(), CustodyError> {
+        self.execute_deposit(id, Some(asset), amount)
+    }
+
+    /// Credits a wallet in `asset`, or its primary asset if `None`
+    fn execute_deposit(
+        &mut self,
+        id: &str,
+        asset: Option<&Asset>,
+        amount: Amount,
+    ) -> Result<(), CustodyError> {
+        if !amount.is_positive() {
+            return Err(CustodyError::NonPositiveAmount(OperationKind::Deposit));
+        }
+
+        if let Some(reason) = self.wallets.get(id).and_then(|wallet| {
+            self.credit_blockers(wallet)
+                .into_iter()
+                .chain(self.hook_blockers(wallet, "deposit", amount, None))
+                .next()
+        }) {
+            return Err(reason);
         }
 
-        if let Some(wallet) = self.wallets.get_mut(id) {
-            wallet.balance += amount;
+        if let Some(wallet) = self.wallets.get(id) {
+            let asset = asset.unwrap_or(&wallet.asset).clone();
+            wallet
+                .balance_of(&asset)
+                .checked_add(amount)
+                .ok_or(CustodyError::AmountOverflow)?;
 
-            // Record transaction
-            self.transactions.push(Transaction {
+            let tx = Transaction::new(
+                id,
+                TransactionType::Deposit,
+                amount,
+                asset.clone(),
+                self.current_timestamp(),
+            );
+            self.record_transaction(tx)?;
+            self.emit(CustodyEvent::DepositReceived {
                 wallet_id: id.to_string(),
-                transaction_type: TransactionType::Deposit,
                 amount,
-                timestamp: Self::current_timestamp(),
+                asset,
             });
 
             Ok(())
         } else {
-            Err(format!("Wallet '{}' not found", id))
+            Err(CustodyError::WalletNotFound(id.to_string()))
         }
     }
 
-    /// Withdraws funds from a wallet
+    /// Withdraws funds from a wallet, in the wallet's primary asset
     ///
     /// # Arguments
     /// * `id` - Wallet identifier
     /// * `amount` - Amount to withdraw
     ///
     /// # Returns
-    /// Ok(()) on success, Err with message on failure
-    pub fn withdraw(&mut self, id: &str, amount: f64) -> Result<(), String> {
-        if amount <= 0.0 {
-            return Err("Withdrawal amount must be positive".to_string());
-        }
+    /// Ok(()) on success, Err describing the failure otherwise
+    pub fn withdraw(&mut self, id: &str, amount: Amount) -> Result<(), CustodyError> {
+        self.execute_withdrawal(id, None, amount, None, Authorization::Direct)
+    }
 
-        if let Some(wallet) = self.wallets.get_mut(id) {
-            if wallet.balance >= amount {
-                wallet.balance -= amount;
+    /// Withdraws funds from a wallet to an external `destination`, in the
+    /// wallet's primary asset
+    ///
+    /// The destination is checked against the wallet's chain and, if the
+    /// wallet has one, its whitelist. At or above the travel-rule threshold
+    /// use [`CustodySystem::withdraw_with_travel_rule`] instead.
+    pub fn withdraw_to(
+        &mut self,
+        id: &str,
+        amount: Amount,
+        destination: &str,
+    ) -> Result<(), CustodyError> {
+        if let Some(reason) = self.travel_rule_blockers(amount, None) {
+            return Err(reason);
+        }
+        self.execute_withdrawal(id, None, amount, Some(destination), Authorization::Direct)
+    }
 
-                // Record transaction
-                self.transactions.push(Transaction {
-                    wallet_id: id.to_string(),
-                    transaction_type: TransactionType::Withdrawal,
-                    amount,
-                    timestamp: Self::current_timestamp(),
-                });
+    /// Withdraws funds held in `asset`
+    pub fn withdraw_asset(
+        &mut self,
+        id: &str,
+        asset: &Asset,
+        amount: Amount,
+    ) -> Result<(), CustodyError> {
+        self.execute_withdrawal(id, Some(asset), amount, None, Authorization::Direct)
+    }
 
-                Ok(())
-            } else {
-                Err(format!(
-                    "Insufficient balance: {} available, {} requested",
-                    wallet.balance, amount
-                ))
-            }
-        } else {
-            Err(format!("Wallet '{}' not found", id))
+    /// Debits a wallet in `asset`, or its primary asset if `None`, once
+    /// every check for the given authorization and compliance screening
+    /// pass
+    pub(crate) fn execute_withdrawal(
+        &mut self,
+        id: &str,
+        asset: Option<&Asset>,
+        amount: Amount,
+        destination: Option<&str>,
+        authorization: Authorization,
+    ) -> Result<(), CustodyError> {
+        if let Some(reason) = self
+            .withdrawal_blockers(id, asset, amount, destination, authorization)
+            .into_iter()
+            .next()
+        {
+            return Err(reason);
+        }
+        let asset = asset.unwrap_or(&self.wallets[id].asset).clone();
+        // Withdrawals released by an approval workflow were screened when
+        // they were requested
+        if authorization != Authorization::Approved {
+            let request = ScreeningRequest {
+                kind: OperationKind::Withdrawal,
+                wallet_id: id.to_string(),
+                asset: asset.clone(),
+                amount,
+                destination: destination.map(str::to_string),
+                to_wallet_id: None,
+            };
+            self.screen(request, authorization)?;
         }
+        self.settle_withdrawal(id, asset, amount)
     }
 
-    /// Gets the total balance across all wallets
-    pub fn get_total_balance(&self) -> f64 {
-        self.wallets.values().map(|w| w.balance).sum()
+    /// Debits a wallet that passed every withdrawal check
+    pub(crate) fn settle_withdrawal(
+        &mut self,
+        id: &str,
+        asset: Asset,
+        amount: Amount,
+    ) -> Result<(), CustodyError> {
+        let tx = Transaction::new(
+            id,
+            TransactionType::Withdrawal,
+            amount,
+            asset.clone(),
+            self.current_timestamp(),
+        );
+        self.record_transaction(tx)?;
+        self.take_wallet_token(id);
+        self.emit(CustodyEvent::WithdrawalSettled {
+            wallet_id: id.to_string(),
+            amount,
+            asset,
+        });
+        Ok(())
     }
 
-    /// Gets all wallets in the system
-    pub fn get_all_wallets(&self) -> &HashMap<String, Wallet> {
-        &self.wallets
+    /// Gets the total balance across all wallets in the default asset
+    /// (BTC), counting primary balances and holdings alike
+    ///
+    /// Balances in other assets are left out, as amounts in different
+    /// assets cannot be added up; use
+    /// [`get_total_balance_of`](Self::get_total_balance_of) for them.
+    ///
+    /// # Panics
+    /// If the total overflows [`Amount`]
+    pub fn get_total_balance(&self) -> Amount {
+        self.get_total_balance_of(&Asset::default())
     }
 
-    /// Gets transaction history for a specific wallet
-    pub fn get_wallet_transactions(&self, wallet_id: &str) -> Vec<&Transaction> {
-        self.transactions
-            .iter()
-            .filter(|t| t.wallet_id == wallet_id)
-            .collect()
+    /// Gets the total balance in `asset` across all wallets, counting
+    /// primary balances and holdings alike
+    ///
+    /// # Panics
+    /// If the total overflows [`Amount`]
+    pub fn get_total_balance_of(&self, asset: &Asset) -> Amount {
+        self.wallets.values().map(|w| w.balance_of(asset)).sum()
+    }
+
+    /// Gets all wallets in the system
+    pub fn get_all_wallets(&self) -> &WalletMap {
+        &self.wallets
     }
 
     /// Gets all transactions in the system
-    pub fn get_all_transactions(&self) -> &[Transaction] {
+    pub fn get_all_transactions(&self) -> &TransactionLog {
         &self.transactions
     }
 
@@ -215,46 +1111,294 @@ impl CustodySystem {
         self.wallets.contains_key(id)
     }
 
-    /// Transfers funds between wallets
-    pub fn transfer(&mut self, from_id: &str, to_id: &str, amount: f64) -> Result<(), String> {
-        if amount <= 0.0 {
-            return Err("Transfer amount must be positive".to_string());
+    /// Transfers funds between wallets, in the source wallet's primary
+    /// asset
+    ///
+    /// Both wallets must have the same primary asset; use
+    /// [`transfer_asset`](Self::transfer_asset) to move other holdings.
+    pub fn transfer(
+        &mut self,
+        from_id: &str,
+        to_id: &str,
+        amount: Amount,
+    ) -> Result<(), CustodyError> {
+        self.execute_transfer(from_id, to_id, None, amount)
+    }
+
+    /// Transfers funds held in `asset` between wallets
+    ///
+    /// The destination is credited in `asset` whatever its primary asset.
+    pub fn transfer_asset(
+        &mut self,
+        from_id: &str,
+        to_id: &str,
+        asset: &Asset,
+        amount: Amount,
+    ) -> Result<(), CustodyError> {
+        self.execute_transfer(from_id, to_id, Some(asset), amount)
+    }
+
+    /// Moves funds in `asset`, or the source's primary asset if `None`
+    fn execute_transfer(
+        &mut self,
+        from_id: &str,
+        to_id: &str,
+        asset: Option<&Asset>,
+        amount: Amount,
+    ) -> Result<(), CustodyError> {
+        let asset = self.check_transfer(from_id, to_id, asset, amount)?;
+        let request = ScreeningRequest {
+            kind: OperationKind::Transfer,
+            wallet_id: from_id.to_string(),
+            asset: asset.clone(),
+            amount,
+            destination: Some(self.wallets[to_id].address.clone()),
+            to_wallet_id: Some(to_id.to_string()),
+        };
+        self.screen(request, Authorization::Direct)?;
+        self.settle_transfer(from_id, to_id, asset, amount)
+    }
+
+    /// Checks every condition of a transfer before either wallet is
+    /// touched, so that it applies in full or not at all
+    ///
+    /// # Returns
+    /// The asset the transfer moves
+    pub(crate) fn check_transfer(
+        &self,
+        from_id: &str,
+        to_id: &str,
+        asset: Option<&Asset>,
+        amount: Amount,
+    ) -> Result<Asset, CustodyError> {
+        if !amount.is_positive() {
+            return Err(CustodyError::NonPositiveAmount(OperationKind::Transfer));
         }
 
         if from_id == to_id {
-            return Err("Cannot transfer to the same wallet".to_string());
+            return Err(CustodyError::SameWallet);
         }
 
         // Validate both wallets exist first
-        if !self.wallet_exists(from_id) {
-            return Err(format!("Source wallet '{}' not found", from_id));
+        let source = self
+            .get_wallet(from_id)
+            .ok_or_else(|| CustodyError::SourceWalletNotFound(from_id.to_string()))?;
+        let destination = self
+            .get_wallet(to_id)
+            .ok_or_else(|| CustodyError::DestinationWalletNotFound(to_id.to_string()))?;
+
+        let asset = match asset {
+            Some(asset) => asset.clone(),
+            None if source.asset != destination.asset => {
+                return Err(CustodyError::AssetMismatch {
+                    expected: source.asset.symbol().to_string(),
+                    found: destination.asset.symbol().to_string(),
+                });
+            }
+            None => source.asset.clone(),
+        };
+
+        // Check source balance, net of holds
+        let available = self.available_of(source, &asset);
+        if available < amount {
+            return Err(CustodyError::InsufficientBalance {
+                available,
+                requested: amount,
+            });
         }
-        if !self.wallet_exists(to_id) {
-            return Err(format!("Destination wallet '{}' not found", to_id));
+
+        if let Some(reason) = self
+            .withdrawal_blockers(from_id, Some(&asset), amount, None, Authorization::Direct)
+            .into_iter()
+            // Transfers stay in custody and need no whitelisted destination
+            .filter(|reason| !matches!(reason, CustodyError::DestinationRequired(_)))
+            .chain(self.credit_blockers(destination))
+            .chain(self.hook_blockers(destination, "deposit", amount, None))
+            .next()
+        {
+            return Err(reason);
         }
+        destination
+            .balance_of(&asset)
+            .checked_add(amount)
+            .ok_or(CustodyError::AmountOverflow)?;
+        Ok(asset)
+    }
+
+    /// Moves funds between wallets that passed
+    /// [`check_transfer`](Self::check_transfer)
+    pub(crate) fn settle_transfer(
+        &mut self,
+        from_id: &str,
+        to_id: &str,
+        asset: Asset,
+        amount: Amount,
+    ) -> Result<(), CustodyError> {
+        let mut tx = Transaction::new(
+            from_id,
+            TransactionType::Transfer,
+            amount,
+            asset.clone(),
+            self.current_timestamp(),
+        );
+        tx.counterparty = Some(to_id.to_string());
+        self.record_transaction(tx)?;
+        self.take_wallet_token(from_id);
+        self.emit(CustodyEvent::WithdrawalSettled {
+            wallet_id: from_id.to_string(),
+            amount,
+            asset: asset.clone(),
+        });
+        self.emit(CustodyEvent::DepositReceived {
+            wallet_id: to_id.to_string(),
+            amount,
+            asset: asset.clone(),
+        });
+        self.emit(CustodyEvent::TransferCompleted {
+            wallet_id: from_id.to_string(),
+            to_wallet_id: to_id.to_string(),
+            amount,
+            asset,
+        });
+        Ok(())
+    }
+
+    /// Produces an independent copy of the system for what-if analysis
+    ///
+    /// The copy shares its wallets and transaction log with the original
+    /// until either side is modified, so forking is cheap even for large
+    /// systems. Operations applied to the fork never affect the original,
+    /// and neither event listeners nor storage are carried over.
+    ///
+    /// # Example
+    /// ```
+    /// use securevault::{amount, CustodySystem, WalletType};
+    /// let mut system = CustodySystem::new();
+    /// system.create_wallet("hot".to_string(), "0x1".to_string(), WalletType::Hot).unwrap();
+    /// system.create_wallet("cold".to_string(), "0x2".to_string(), WalletType::Cold).unwrap();
+    /// system.deposit("hot", amount!(10.0)).unwrap();
+    ///
+    /// let mut plan = system.fork();
+    /// plan.transfer("hot", "cold", amount!(8.0)).unwrap();
+    ///
+    /// assert_eq!(plan.get_wallet("hot").unwrap().balance, amount!(2.0));
+    /// assert_eq!(system.get_wallet("hot").unwrap().balance, amount!(10.0));
+    /// ```
+    pub fn fork(&self) -> CustodySystem {
+        let mut fork = self.clone();
+        // Hypothetical operations must not reach customers or downstream
+        // systems
+        fork.listeners = events::Listeners::default();
+        fork.detach_storage();
+        fork
+    }
+
+    /// Allocates an id for a pending operation
+    fn allocate_operation_id(&mut self) -> u64 {
+        let id = self.next_operation_id;
+        self.next_operation_id += 1;
+        id
+    }
 
-        // Check source balance
-        let source_balance = self.get_wallet(from_id).unwrap().balance;
-        if source_balance < amount {
-            return Err(format!(
-                "Insufficient balance in source wallet: {} available, {} requested",
-                source_balance, amount
-            ));
+    /// Appends a transaction to the audit trail and books its postings to
+    /// the wallets it names
+    ///
+    /// Callers check balances beforehand. Nothing changes if the write to
+    /// storage fails.
+    fn record_transaction(&mut self, tx: Transaction) -> Result<(), CustodyError> {
+        self.record_transactions(vec![tx])
+    }
+
+    /// Records several transactions as one operation; either all of them
+    /// are booked or none
+    fn record_transactions(&mut self, txs: Vec<Transaction>) -> Result<(), CustodyError> {
+        self.record_transactions_with(txs, Vec::new())
+    }
+
+    /// Records several transactions together with further changes to
+    /// persist in the same operation
+    fn record_transactions_with(
+        &mut self,
+        mut txs: Vec<Transaction>,
+        updates: Vec<cdc::Change>,
+    ) -> Result<(), CustodyError> {
+        for (offset, tx) in (0..).zip(txs.iter_mut()) {
+            #[cfg(feature = "chaos")]
+            {
+                tx.timestamp = chaos::skew(tx.timestamp, self.clock_skew);
+            }
+            tx.id = self.next_transaction_id + offset;
+            tx.uuid = Uuid::new_v4();
+            tx.idempotency_key = self.idempotency_key.clone();
+        }
+        self.chain(&mut txs);
+
+        let mut changes = Vec::new();
+        let mut wallets: Vec<Wallet> = Vec::new();
+        for (offset, mut tx) in txs.into_iter().enumerate() {
+            tx.seal();
+            for (id, change) in tx.postings() {
+                // A sweep to the same wallet posts to it twice
+                let position = match wallets.iter().position(|wallet| wallet.id == id) {
+                    Some(position) => position,
+                    None => match self.wallets.get(id) {
+                        Some(wallet) => {
+                            wallets.push(wallet.clone());
+                            wallets.len() - 1
+                        }
+                        None => continue,
+                    },
+                };
+                let wallet = &mut wallets[position];
+                wallet.set_balance(&tx.asset, wallet.balance_of(&tx.asset) + change);
+            }
+            changes.push(cdc::Change::TransactionAppend {
+                index: self.transactions.len() + offset,
+                transaction: tx,
+            });
         }
+        // Balances change together with the transactions that explain them
+        changes.extend(
+            wallets
+                .into_iter()
+                .map(|wallet| cdc::Change::WalletUpsert { wallet }),
+        );
+        changes.extend(updates);
+        self.commit(changes)?;
+        self.detect_suspicious_activity();
+        self.commit_state_if_due();
+        Ok(())
+    }
 
-        // Perform transfer
-        self.withdraw(from_id, amount)?;
-        self.deposit(to_id, amount)?;
+    /// Modifies the transaction at `index` in place, returning false if
+    /// there is none
+    fn update_transaction(
+        &mut self,
+        index: usize,
+        update: impl FnOnce(&mut Transaction),
+    ) -> Result<bool, CustodyError> {
+        let Some(mut transaction) = self.transactions.get(index).cloned() else {
+            return Ok(false);
+        };
+        update(&mut transaction);
+        transaction.seal();
+        self.commit(vec![cdc::Change::TransactionUpdate { index, transaction }])?;
+        Ok(true)
+    }
 
+    /// Modifies the most recently recorded transaction
+    fn update_last_transaction(
+        &mut self,
+        update: impl FnOnce(&mut Transaction),
+    ) -> Result<(), CustodyError> {
+        if let Some(index) = self.transactions.len().checked_sub(1) {
+            self.update_transaction(index, update)?;
+        }
         Ok(())
     }
 
-    fn current_timestamp() -> u64 {
-        use std::time::{SystemTime, UNIX_EPOCH};
-        SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap_or_default()
-            .as_secs()
+    fn current_timestamp(&self) -> Timestamp {
+        self.clock.now()
     }
 }
 
@@ -275,7 +1419,7 @@ mod tests {
 
         assert_eq!(wallet.id, "test_001");
         assert_eq!(wallet.address, "0x1234");
-        assert_eq!(wallet.balance, 0.0);
+        assert_eq!(wallet.balance, amount!(0.0));
     }
 
     #[test]
@@ -296,7 +1440,7 @@ mod tests {
         );
 
         assert!(result.is_err());
-        assert!(result.unwrap_err().contains("already exists"));
+        assert!(result.unwrap_err().to_string().contains("already exists"));
     }
 
     #[test]
@@ -310,11 +1454,11 @@ mod tests {
             )
             .unwrap();
 
-        let result = system.deposit("test_001", 10.5);
+        let result = system.deposit("test_001", amount!(10.5));
         assert!(result.is_ok());
 
         let wallet = system.get_wallet("test_001").unwrap();
-        assert_eq!(wallet.balance, 10.5);
+        assert_eq!(wallet.balance, amount!(10.5));
     }
 
     #[test]
@@ -328,9 +1472,9 @@ mod tests {
             )
             .unwrap();
 
-        let result = system.deposit("test_001", -10.0);
+        let result = system.deposit("test_001", amount!(-10.0));
         assert!(result.is_err());
-        assert!(result.unwrap_err().contains("positive"));
+        assert!(result.unwrap_err().to_string().contains("positive"));
     }
 
     #[test]
@@ -344,9 +1488,9 @@ mod tests {
             )
             .unwrap();
 
-        let result = system.deposit("test_001", 0.0);
+        let result = system.deposit("test_001", amount!(0.0));
         assert!(result.is_err());
-        assert!(result.unwrap_err().contains("positive"));
+        assert!(result.unwrap_err().to_string().contains("positive"));
     }
 
     #[test]
@@ -359,13 +1503,13 @@ mod tests {
                 WalletType::Hot,
             )
             .unwrap();
-        system.deposit("test_001", 10.0).unwrap();
+        system.deposit("test_001", amount!(10.0)).unwrap();
 
-        let result = system.withdraw("test_001", 5.0);
+        let result = system.withdraw("test_001", amount!(5.0));
         assert!(result.is_ok());
 
         let wallet = system.get_wallet("test_001").unwrap();
-        assert_eq!(wallet.balance, 5.0);
+        assert_eq!(wallet.balance, amount!(5.0));
     }
 
     #[test]
@@ -378,11 +1522,14 @@ mod tests {
                 WalletType::Hot,
             )
             .unwrap();
-        system.deposit("test_001", 5.0).unwrap();
+        system.deposit("test_001", amount!(5.0)).unwrap();
 
-        let result = system.withdraw("test_001", 10.0);
+        let result = system.withdraw("test_001", amount!(10.0));
         assert!(result.is_err());
-        assert!(result.unwrap_err().contains("Insufficient balance"));
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("Insufficient balance"));
     }
 
     #[test]
@@ -395,11 +1542,11 @@ mod tests {
                 WalletType::Hot,
             )
             .unwrap();
-        system.deposit("test_001", 10.0).unwrap();
+        system.deposit("test_001", amount!(10.0)).unwrap();
 
-        let result = system.withdraw("test_001", -5.0);
+        let result = system.withdraw("test_001", amount!(-5.0));
         assert!(result.is_err());
-        assert!(result.unwrap_err().contains("positive"));
+        assert!(result.unwrap_err().to_string().contains("positive"));
     }
 
     #[test]
@@ -416,28 +1563,56 @@ mod tests {
             )
             .unwrap();
 
-        system.deposit("hot_001", 10.5).unwrap();
-        system.deposit("cold_001", 100.0).unwrap();
+        system.deposit("hot_001", amount!(10.5)).unwrap();
+        system.deposit("cold_001", amount!(100.0)).unwrap();
+
+        assert_eq!(system.get_total_balance(), amount!(110.5));
+    }
+
+    #[test]
+    fn test_total_balance_is_per_asset() {
+        let mut system = CustodySystem::new();
+        system
+            .create_wallet("btc".to_string(), "0x1".to_string(), WalletType::Hot)
+            .unwrap();
+        system
+            .create_wallet_with_asset(
+                "eth".to_string(),
+                "0x2".to_string(),
+                WalletType::Hot,
+                Asset::Eth,
+            )
+            .unwrap();
+        system.deposit("btc", amount!(1.5)).unwrap();
+        system
+            .deposit_asset("btc", &Asset::Eth, amount!(3))
+            .unwrap();
+        system.deposit("eth", amount!(20)).unwrap();
+        system
+            .deposit_asset("eth", &Asset::Btc, amount!(0.5))
+            .unwrap();
 
-        assert_eq!(system.get_total_balance(), 110.5);
+        assert_eq!(system.get_total_balance_of(&Asset::Eth), amount!(23));
+        assert_eq!(system.get_total_balance_of(&Asset::Btc), amount!(2));
+        assert_eq!(system.get_total_balance(), amount!(2));
     }
 
     #[test]
     fn test_withdraw_from_nonexistent_wallet() {
         let mut system = CustodySystem::new();
 
-        let result = system.withdraw("nonexistent", 10.0);
+        let result = system.withdraw("nonexistent", amount!(10.0));
         assert!(result.is_err());
-        assert!(result.unwrap_err().contains("not found"));
+        assert!(result.unwrap_err().to_string().contains("not found"));
     }
 
     #[test]
     fn test_deposit_to_nonexistent_wallet() {
         let mut system = CustodySystem::new();
 
-        let result = system.deposit("nonexistent", 10.0);
+        let result = system.deposit("nonexistent", amount!(10.0));
         assert!(result.is_err());
-        assert!(result.unwrap_err().contains("not found"));
+        assert!(result.unwrap_err().to_string().contains("not found"));
     }
 
     #[test]
@@ -451,15 +1626,15 @@ mod tests {
             )
             .unwrap();
 
-        system.deposit("test_001", 10.0).unwrap();
-        system.withdraw("test_001", 3.0).unwrap();
-        system.deposit("test_001", 5.0).unwrap();
+        system.deposit("test_001", amount!(10.0)).unwrap();
+        system.withdraw("test_001", amount!(3.0)).unwrap();
+        system.deposit("test_001", amount!(5.0)).unwrap();
 
         let transactions = system.get_wallet_transactions("test_001");
         assert_eq!(transactions.len(), 3);
-        assert_eq!(transactions[0].amount, 10.0);
-        assert_eq!(transactions[1].amount, 3.0);
-        assert_eq!(transactions[2].amount, 5.0);
+        assert_eq!(transactions[0].amount, amount!(10.0));
+        assert_eq!(transactions[1].amount, amount!(3.0));
+        assert_eq!(transactions[2].amount, amount!(5.0));
     }
 
     #[test]
@@ -519,12 +1694,18 @@ mod tests {
             )
             .unwrap();
 
-        system.deposit("wallet_1", 100.0).unwrap();
-        let result = system.transfer("wallet_1", "wallet_2", 30.0);
+        system.deposit("wallet_1", amount!(100.0)).unwrap();
+        let result = system.transfer("wallet_1", "wallet_2", amount!(30.0));
         assert!(result.is_ok());
 
-        assert_eq!(system.get_wallet("wallet_1").unwrap().balance, 70.0);
-        assert_eq!(system.get_wallet("wallet_2").unwrap().balance, 30.0);
+        assert_eq!(
+            system.get_wallet("wallet_1").unwrap().balance,
+            amount!(70.0)
+        );
+        assert_eq!(
+            system.get_wallet("wallet_2").unwrap().balance,
+            amount!(30.0)
+        );
     }
 
     #[test]
@@ -545,10 +1726,13 @@ mod tests {
             )
             .unwrap();
 
-        system.deposit("wallet_1", 10.0).unwrap();
-        let result = system.transfer("wallet_1", "wallet_2", 30.0);
+        system.deposit("wallet_1", amount!(10.0)).unwrap();
+        let result = system.transfer("wallet_1", "wallet_2", amount!(30.0));
         assert!(result.is_err());
-        assert!(result.unwrap_err().contains("Insufficient balance"));
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("Insufficient balance"));
     }
 
     #[test]
@@ -562,9 +1746,9 @@ mod tests {
             )
             .unwrap();
 
-        let result = system.transfer("wallet_1", "wallet_2", 30.0);
+        let result = system.transfer("wallet_1", "wallet_2", amount!(30.0));
         assert!(result.is_err());
-        assert!(result.unwrap_err().contains("not found"));
+        assert!(result.unwrap_err().to_string().contains("not found"));
     }
 
     #[test]
@@ -578,10 +1762,10 @@ mod tests {
             )
             .unwrap();
 
-        system.deposit("wallet_1", 100.0).unwrap();
-        let result = system.transfer("wallet_1", "wallet_2", 30.0);
+        system.deposit("wallet_1", amount!(100.0)).unwrap();
+        let result = system.transfer("wallet_1", "wallet_2", amount!(30.0));
         assert!(result.is_err());
-        assert!(result.unwrap_err().contains("not found"));
+        assert!(result.unwrap_err().to_string().contains("not found"));
     }
 
     #[test]
@@ -602,10 +1786,10 @@ mod tests {
             )
             .unwrap();
 
-        system.deposit("wallet_1", 100.0).unwrap();
-        let result = system.transfer("wallet_1", "wallet_2", -30.0);
+        system.deposit("wallet_1", amount!(100.0)).unwrap();
+        let result = system.transfer("wallet_1", "wallet_2", amount!(-30.0));
         assert!(result.is_err());
-        assert!(result.unwrap_err().contains("positive"));
+        assert!(result.unwrap_err().to_string().contains("positive"));
     }
 
     #[test]
@@ -619,8 +1803,8 @@ mod tests {
             )
             .unwrap();
 
-        system.deposit("wallet_1", 10.0).unwrap();
-        system.withdraw("wallet_1", 3.0).unwrap();
+        system.deposit("wallet_1", amount!(10.0)).unwrap();
+        system.withdraw("wallet_1", amount!(3.0)).unwrap();
 
         let transactions = system.get_all_transactions();
         assert_eq!(transactions.len(), 2);
@@ -630,7 +1814,7 @@ mod tests {
     fn test_default_implementation() {
         let system = CustodySystem::default();
         assert_eq!(system.wallet_count(), 0);
-        assert_eq!(system.get_total_balance(), 0.0);
+        assert_eq!(system.get_total_balance(), amount!(0.0));
     }
 
     #[test]
@@ -674,12 +1858,12 @@ mod tests {
             )
             .unwrap();
 
-        system.deposit("test_001", 10.0).unwrap();
-        system.deposit("test_001", 20.0).unwrap();
-        system.deposit("test_001", 15.5).unwrap();
+        system.deposit("test_001", amount!(10.0)).unwrap();
+        system.deposit("test_001", amount!(20.0)).unwrap();
+        system.deposit("test_001", amount!(15.5)).unwrap();
 
         let wallet = system.get_wallet("test_001").unwrap();
-        assert_eq!(wallet.balance, 45.5);
+        assert_eq!(wallet.balance, amount!(45.5));
     }
 
     #[test]
@@ -693,13 +1877,13 @@ mod tests {
             )
             .unwrap();
 
-        system.deposit("test_001", 100.0).unwrap();
-        system.withdraw("test_001", 10.0).unwrap();
-        system.withdraw("test_001", 20.0).unwrap();
-        system.withdraw("test_001", 15.5).unwrap();
+        system.deposit("test_001", amount!(100.0)).unwrap();
+        system.withdraw("test_001", amount!(10.0)).unwrap();
+        system.withdraw("test_001", amount!(20.0)).unwrap();
+        system.withdraw("test_001", amount!(15.5)).unwrap();
 
         let wallet = system.get_wallet("test_001").unwrap();
-        assert_eq!(wallet.balance, 54.5);
+        assert_eq!(wallet.balance, amount!(54.5));
     }
 
     #[test]
@@ -713,12 +1897,12 @@ mod tests {
             )
             .unwrap();
 
-        system.deposit("test_001", 10.0).unwrap();
+        system.deposit("test_001", amount!(10.0)).unwrap();
 
         let transactions = system.get_wallet_transactions("test_001");
         assert_eq!(transactions.len(), 1);
         assert_eq!(transactions[0].transaction_type, TransactionType::Deposit);
-        assert_eq!(transactions[0].amount, 10.0);
+        assert_eq!(transactions[0].amount, amount!(10.0));
     }
 
     #[test]
@@ -732,8 +1916,8 @@ mod tests {
             )
             .unwrap();
 
-        system.deposit("test_001", 20.0).unwrap();
-        system.withdraw("test_001", 5.0).unwrap();
+        system.deposit("test_001", amount!(20.0)).unwrap();
+        system.withdraw("test_001", amount!(5.0)).unwrap();
 
         let transactions = system.get_wallet_transactions("test_001");
         assert_eq!(transactions.len(), 2);
@@ -741,7 +1925,7 @@ mod tests {
             transactions[1].transaction_type,
             TransactionType::Withdrawal
         );
-        assert_eq!(transactions[1].amount, 5.0);
+        assert_eq!(transactions[1].amount, amount!(5.0));
     }
 
     #[test]
@@ -755,11 +1939,11 @@ mod tests {
             )
             .unwrap();
 
-        system.deposit("test_001", 10.0).unwrap();
+        system.deposit("test_001", amount!(10.0)).unwrap();
 
         let transactions = system.get_wallet_transactions("test_001");
         assert_eq!(transactions.len(), 1);
-        assert!(transactions[0].timestamp > 0);
+        assert!(transactions[0].timestamp > Timestamp::EPOCH);
     }
 
     #[test]
@@ -804,10 +1988,10 @@ mod tests {
             )
             .unwrap();
 
-        system.deposit("wallet_1", 100.0).unwrap();
-        let result = system.transfer("wallet_1", "wallet_2", 0.0);
+        system.deposit("wallet_1", amount!(100.0)).unwrap();
+        let result = system.transfer("wallet_1", "wallet_2", amount!(0.0));
         assert!(result.is_err());
-        assert!(result.unwrap_err().contains("positive"));
+        assert!(result.unwrap_err().to_string().contains("positive"));
     }
 
     #[test]
@@ -843,9 +2027,9 @@ mod tests {
             )
             .unwrap();
 
-        system.deposit("wallet_1", 10.0).unwrap();
-        system.deposit("wallet_2", 20.0).unwrap();
-        system.withdraw("wallet_1", 5.0).unwrap();
+        system.deposit("wallet_1", amount!(10.0)).unwrap();
+        system.deposit("wallet_2", amount!(20.0)).unwrap();
+        system.withdraw("wallet_1", amount!(5.0)).unwrap();
 
         let wallet_1_txs = system.get_wallet_transactions("wallet_1");
         let wallet_2_txs = system.get_wallet_transactions("wallet_2");
@@ -872,29 +2056,73 @@ mod tests {
             )
             .unwrap();
 
-        system.deposit("wallet_1", 100.0).unwrap();
-        system.transfer("wallet_1", "wallet_2", 30.0).unwrap();
+        system.deposit("wallet_1", amount!(100.0)).unwrap();
+        system
+            .transfer("wallet_1", "wallet_2", amount!(30.0))
+            .unwrap();
 
         let wallet_1_txs = system.get_wallet_transactions("wallet_1");
         let wallet_2_txs = system.get_wallet_transactions("wallet_2");
 
-        // wallet_1 should have 1 deposit + 1 withdrawal
+        // wallet_1 should have 1 deposit + 1 transfer
         assert_eq!(wallet_1_txs.len(), 2);
         assert_eq!(wallet_1_txs[0].transaction_type, TransactionType::Deposit);
+        assert_eq!(wallet_1_txs[1].transaction_type, TransactionType::Transfer);
+
+        // wallet_2 should see the same transfer as a credit
+        assert_eq!(wallet_2_txs.len(), 1);
+        assert_eq!(wallet_2_txs[0].id, wallet_1_txs[1].id);
+        assert_eq!(wallet_2_txs[0].counterparty.as_deref(), Some("wallet_2"));
+        assert_eq!(wallet_2_txs[0].balance_change("wallet_1"), amount!(-30.0));
+        assert_eq!(wallet_2_txs[0].balance_change("wallet_2"), amount!(30.0));
+    }
+
+    #[test]
+    fn test_transfer_is_atomic_when_credit_fails() {
+        let mut system = CustodySystem::new();
+        for id in ["a", "b"] {
+            system
+                .create_wallet(id.to_string(), format!("0x{}", id), WalletType::Hot)
+                .unwrap();
+        }
+        system.deposit("a", amount!(10.0)).unwrap();
+        // Inject a failure into the credit leg
+        system.wallets.get_mut("b").unwrap().balance = Amount::MAX;
+        let changes = system.latest_sequence();
+
         assert_eq!(
-            wallet_1_txs[1].transaction_type,
-            TransactionType::Withdrawal
+            system.transfer("a", "b", amount!(4.0)),
+            Err(CustodyError::AmountOverflow)
         );
+        assert_eq!(system.get_wallet("a").unwrap().balance, amount!(10.0));
+        assert_eq!(system.get_wallet("b").unwrap().balance, Amount::MAX);
+        assert_eq!(system.get_all_transactions().len(), 1);
+        assert_eq!(system.latest_sequence(), changes);
+    }
 
-        // wallet_2 should have 1 deposit
-        assert_eq!(wallet_2_txs.len(), 1);
-        assert_eq!(wallet_2_txs[0].transaction_type, TransactionType::Deposit);
-        assert_eq!(wallet_2_txs[0].amount, 30.0);
+    #[test]
+    fn test_transfer_blocked_at_source_changes_nothing() {
+        let mut system = CustodySystem::new();
+        system
+            .create_wallet("cold".to_string(), "0x1".to_string(), WalletType::Cold)
+            .unwrap();
+        system
+            .create_wallet("hot".to_string(), "0x2".to_string(), WalletType::Hot)
+            .unwrap();
+        system.deposit("cold", amount!(10.0)).unwrap();
+
+        assert_eq!(
+            system.transfer("cold", "hot", amount!(4.0)),
+            Err(CustodyError::ApprovalRequired("cold".to_string()))
+        );
+        assert_eq!(system.get_wallet("cold").unwrap().balance, amount!(10.0));
+        assert_eq!(system.get_wallet("hot").unwrap().balance, amount!(0.0));
+        assert!(system.audit().passed());
     }
 
     #[test]
     fn test_large_amounts() {
-        const LARGE_AMOUNT: f64 = 1_000_000_000.0;
+        let large_amount = amount!(1_000_000_000.0);
 
         let mut system = CustodySystem::new();
         system
@@ -905,16 +2133,14 @@ mod tests {
             )
             .unwrap();
 
-        system.deposit("test_001", LARGE_AMOUNT).unwrap();
+        system.deposit("test_001", large_amount).unwrap();
 
         let wallet = system.get_wallet("test_001").unwrap();
-        assert_eq!(wallet.balance, LARGE_AMOUNT);
+        assert_eq!(wallet.balance, large_amount);
     }
 
     #[test]
     fn test_decimal_precision() {
-        const EPSILON: f64 = 1e-5;
-
         let mut system = CustodySystem::new();
         system
             .create_wallet(
@@ -924,11 +2150,11 @@ mod tests {
             )
             .unwrap();
 
-        system.deposit("test_001", 0.12345678).unwrap();
-        system.deposit("test_001", 0.87654322).unwrap();
+        system.deposit("test_001", amount!(0.12345678)).unwrap();
+        system.deposit("test_001", amount!(0.87654322)).unwrap();
 
         let wallet = system.get_wallet("test_001").unwrap();
-        assert!((wallet.balance - 1.0).abs() < EPSILON);
+        assert_eq!(wallet.balance, amount!(1.0));
     }
 
     #[test]
@@ -942,12 +2168,12 @@ mod tests {
             )
             .unwrap();
 
-        system.deposit("test_001", 42.5).unwrap();
+        system.deposit("test_001", amount!(42.5)).unwrap();
 
         let wallet = system.get_wallet("test_001").unwrap();
         assert_eq!(wallet.id, "test_001");
         assert_eq!(wallet.address, "0xABCDEF1234567890");
-        assert_eq!(wallet.balance, 42.5);
+        assert_eq!(wallet.balance, amount!(42.5));
         assert_eq!(wallet.wallet_type, WalletType::Cold);
     }
 
@@ -969,13 +2195,13 @@ mod tests {
             )
             .unwrap();
 
-        system.deposit("test_001", 100.0).unwrap();
-        system.withdraw("test_001", 30.0).unwrap();
-        system.deposit("test_001", 50.0).unwrap();
-        system.withdraw("test_001", 20.0).unwrap();
+        system.deposit("test_001", amount!(100.0)).unwrap();
+        system.withdraw("test_001", amount!(30.0)).unwrap();
+        system.deposit("test_001", amount!(50.0)).unwrap();
+        system.withdraw("test_001", amount!(20.0)).unwrap();
 
         let wallet = system.get_wallet("test_001").unwrap();
-        assert_eq!(wallet.balance, 100.0);
+        assert_eq!(wallet.balance, amount!(100.0));
     }
 
     #[test]
@@ -1003,11 +2229,11 @@ mod tests {
             )
             .unwrap();
 
-        system.deposit("wallet_1", 25.0).unwrap();
-        system.deposit("wallet_2", 50.0).unwrap();
-        system.deposit("wallet_3", 75.0).unwrap();
+        system.deposit("wallet_1", amount!(25.0)).unwrap();
+        system.deposit("wallet_2", amount!(50.0)).unwrap();
+        system.deposit("wallet_3", amount!(75.0)).unwrap();
 
-        assert_eq!(system.get_total_balance(), 150.0);
+        assert_eq!(system.get_total_balance(), amount!(150.0));
     }
 
     #[test]
@@ -1021,7 +2247,7 @@ mod tests {
             )
             .unwrap();
 
-        system.deposit("test_wallet", 10.0).unwrap();
+        system.deposit("test_wallet", amount!(10.0)).unwrap();
 
         let transactions = system.get_wallet_transactions("test_wallet");
         assert_eq!(transactions.len(), 1);
@@ -1039,9 +2265,96 @@ mod tests {
             )
             .unwrap();
 
-        system.deposit("wallet_1", 100.0).unwrap();
-        let result = system.transfer("wallet_1", "wallet_1", 10.0);
+        system.deposit("wallet_1", amount!(100.0)).unwrap();
+        let result = system.transfer("wallet_1", "wallet_1", amount!(10.0));
         assert!(result.is_err());
-        assert!(result.unwrap_err().contains("same wallet"));
+        assert!(result.unwrap_err().to_string().contains("same wallet"));
+    }
+
+    #[test]
+    fn test_transfer_between_assets_rejected() {
+        let mut system = CustodySystem::new();
+        system
+            .create_wallet("btc".to_string(), "0x1234".to_string(), WalletType::Hot)
+            .unwrap();
+        system
+            .create_wallet_with_asset(
+                "eth".to_string(),
+                "0x5678".to_string(),
+                WalletType::Hot,
+                Asset::Eth,
+            )
+            .unwrap();
+
+        system.deposit("btc", amount!(1.0)).unwrap();
+        let result = system.transfer("btc", "eth", amount!(1.0));
+        assert!(matches!(result, Err(CustodyError::AssetMismatch { .. })));
+    }
+
+    #[test]
+    fn test_wallet_holds_several_assets() {
+        let usdc = Asset::Erc20 {
+            symbol: "USDC".to_string(),
+            contract: "0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48".to_string(),
+            decimals: 6,
+        };
+        let mut system = CustodySystem::new();
+        system
+            .create_wallet("btc".to_string(), "0x1234".to_string(), WalletType::Hot)
+            .unwrap();
+        system
+            .create_wallet_with_asset(
+                "eth".to_string(),
+                "0x5678".to_string(),
+                WalletType::Hot,
+                Asset::Eth,
+            )
+            .unwrap();
+
+        system.deposit("btc", amount!(1.0)).unwrap();
+        system.deposit_asset("btc", &usdc, amount!(250)).unwrap();
+        system
+            .transfer_asset("btc", "eth", &usdc, amount!(100))
+            .unwrap();
+        assert!(matches!(
+            system.withdraw_asset("eth", &usdc, amount!(101)),
+            Err(CustodyError::InsufficientBalance { .. })
+        ));
+        system.withdraw_asset("eth", &usdc, amount!(100)).unwrap();
+
+        let btc = system.get_wallet("btc").unwrap();
+        assert_eq!(btc.balance, amount!(1.0));
+        assert_eq!(btc.balance_of(&usdc), amount!(150));
+        assert_eq!(btc.balances().count(), 2);
+        let eth = system.get_wallet("eth").unwrap();
+        assert!(eth.holdings.is_empty());
+        assert_eq!(system.get_all_transactions()[1].asset, usdc);
+        assert!(system.audit().passed());
+
+        let json = serde_json::to_string(btc).unwrap();
+        assert_eq!(&serde_json::from_str::<Wallet>(&json).unwrap(), btc);
+    }
+
+    #[test]
+    fn test_fork_is_independent() {
+        let mut system = CustodySystem::new();
+        system
+            .create_wallet("hot".to_string(), "0x1234".to_string(), WalletType::Hot)
+            .unwrap();
+        system.deposit("hot", amount!(10.0)).unwrap();
+
+        let mut fork = system.fork();
+        fork.withdraw("hot", amount!(4.0)).unwrap();
+        fork.create_wallet("new".to_string(), "0x5678".to_string(), WalletType::Cold)
+            .unwrap();
+        system.deposit("hot", amount!(1.0)).unwrap();
+
+        assert_eq!(fork.get_wallet("hot").unwrap().balance, amount!(6.0));
+        assert_eq!(fork.wallet_count(), 2);
+        assert_eq!(fork.get_all_transactions().len(), 2);
+
+        assert_eq!(system.get_wallet("hot").unwrap().balance, amount!(11.0));
+        assert_eq!(system.wallet_count(), 1);
+        assert_eq!(system.get_all_transactions().len(), 2);
     }
 }
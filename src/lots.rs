@@ -0,0 +1,317 @@
+//! Tax lot accounting.
+//!
+//! Every credit to a wallet opens an acquisition lot at its recorded fiat
+//! value (see [`CustodySystem::deposit_priced`]). Every debit is matched
+//! against open lots using a [`LotMethod`], producing one [`Disposal`] per
+//! lot consumed. The resulting report can be written as CSV for tax filing.
+
+use crate::time::Timestamp;
+use crate::{Amount, Asset, CustodyError, CustodySystem, Transaction};
+use std::io::{self, Write};
+
+/// How debits are matched against open lots
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LotMethod {
+    /// First in, first out
+    #[default]
+    Fifo,
+    /// Last in, first out
+    Lifo,
+    /// Highest unit cost first
+    Hifo,
+}
+
+/// Units acquired in a single credit
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lot {
+    pub acquired: Timestamp,
+    /// Units still open in this lot
+    pub units: Amount,
+    /// Fiat cost per unit; zero if the credit had no recorded value
+    pub unit_cost: f64,
+}
+
+/// Units of one lot removed by a debit
+#[derive(Debug, Clone, PartialEq)]
+pub struct Disposal {
+    pub wallet_id: String,
+    pub asset: Asset,
+    /// Acquisition time of the matched lot, or `None` if the debit exceeded
+    /// the open lots
+    pub acquired: Option<Timestamp>,
+    pub disposed: Timestamp,
+    pub units: Amount,
+    pub cost_basis: f64,
+    /// Fiat proceeds, or `None` if the debit had no recorded value
+    pub proceeds: Option<f64>,
+}
+
+impl Disposal {
+    /// Proceeds minus cost basis; unpriced disposals realise nothing
+    pub fn gain(&self) -> f64 {
+        self.proceeds
+            .map_or(0.0, |proceeds| proceeds - self.cost_basis)
+    }
+
+    /// Whole days between acquisition and disposal
+    pub fn holding_days(&self) -> Option<i64> {
+        self.acquired
+            .map(|acquired| (self.disposed.datetime() - acquired.datetime()).num_days())
+    }
+}
+
+/// Disposals and remaining lots for a set of wallets
+#[derive(Debug, Clone, PartialEq)]
+pub struct LotReport {
+    pub method: LotMethod,
+    pub currency: Asset,
+    /// Disposals in chronological order
+    pub disposals: Vec<Disposal>,
+    /// Lots still open, by wallet id
+    pub open_lots: Vec<(String, Lot)>,
+}
+
+impl LotReport {
+    /// Total realised gain across disposals
+    pub fn total_gain(&self) -> f64 {
+        self.disposals.iter().map(Disposal::gain).sum()
+    }
+
+    /// Writes disposals as CSV, one row per matched lot
+    pub fn write_csv<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(
+            writer,
+            "wallet,asset,units,acquired,disposed,proceeds,cost_basis,gain,currency"
+        )?;
+        for disposal in &self.disposals {
+            writeln!(
+                writer,
+                "{},{},{},{},{},{},{:.2},{:.2},{}",
+                disposal.wallet_id,
+                disposal.asset.symbol(),
+                disposal.units,
+                disposal
+                    .acquired
+                    .map(|t| t.datetime().format("%Y-%m-%d").to_string())
+                    .unwrap_or_else(|| "unknown".to_string()),
+                disposal.disposed.datetime().format("%Y-%m-%d"),
+                disposal
+                    .proceeds
+                    .map(|p| format!("{:.2}", p))
+                    .unwrap_or_default(),
+                disposal.cost_basis,
+                disposal.gain(),
+                self.currency.symbol()
+            )?;
+        }
+        writer.flush()
+    }
+}
+
+impl CustodySystem {
+    /// Matches the debits of one wallet against its lots
+    ///
+    /// Only fiat values recorded in `currency` are used; other credits open
+    /// zero-cost lots and other debits have no proceeds.
+    pub fn wallet_lots(
+        &self,
+        wallet_id: &str,
+        method: LotMethod,
+        currency: &Asset,
+    ) -> Result<LotReport, CustodyError> {
+        let wallet = self
+            .get_wallet(wallet_id)
+            .ok_or_else(|| CustodyError::WalletNotFound(wallet_id.to_string()))?;
+        let mut report = LotReport {
+            method,
+            currency: currency.clone(),
+            disposals: Vec::new(),
+            open_lots: Vec::new(),
+        };
+        let mut lots = Vec::new();
+        for tx in self.get_wallet_transactions(wallet_id) {
+            let value = fiat_amount(tx, currency);
+            if tx.is_credit_for(wallet_id) {
+                lots.push(Lot {
+                    acquired: tx.timestamp,
+                    units: tx.amount,
+                    unit_cost: value.map_or(0.0, |v| v / tx.amount.to_f64()),
+                });
+            } else {
+                let unit_proceeds = value.map(|v| v / tx.amount.to_f64());
+                dispose(
+                    &mut lots,
+                    &mut report.disposals,
+                    method,
+                    tx,
+                    &wallet.asset,
+                    unit_proceeds,
+                );
+            }
+        }
+        report.open_lots = lots
+            .into_iter()
+            .map(|lot| (wallet_id.to_string(), lot))
+            .collect();
+        Ok(report)
+    }
+
+    /// Matches debits of every wallet and keeps disposals within
+    /// `from <= disposed < to`, ready for a tax filing export
+    pub fn disposal_report(
+        &self,
+        from: Timestamp,
+        to: Timestamp,
+        method: LotMethod,
+        currency: &Asset,
+    ) -> LotReport {
+        let mut ids: Vec<&String> = self.wallets.keys().collect();
+        ids.sort();
+        let mut report = LotReport {
+            method,
+            currency: currency.clone(),
+            disposals: Vec::new(),
+            open_lots: Vec::new(),
+        };
+        for id in ids {
+            if let Ok(mut wallet) = self.wallet_lots(id, method, currency) {
+                report.disposals.extend(
+                    wallet
+                        .disposals
+                        .into_iter()
+                        .filter(|d| d.disposed >= from && d.disposed < to),
+                );
+                report.open_lots.append(&mut wallet.open_lots);
+            }
+        }
+        report.disposals.sort_by_key(|d| d.disposed);
+        report
+    }
+}
+
+fn fiat_amount(tx: &Transaction, currency: &Asset) -> Option<f64> {
+    tx.fiat_value
+        .as_ref()
+        .filter(|value| &value.currency == currency)
+        .map(|value| value.amount)
+}
+
+fn dispose(
+    lots: &mut Vec<Lot>,
+    disposals: &mut Vec<Disposal>,
+    method: LotMethod,
+    tx: &Transaction,
+    asset: &Asset,
+    unit_proceeds: Option<f64>,
+) {
+    let mut remaining = tx.amount;
+    while remaining.is_positive() {
+        let index = match method {
+            LotMethod::Fifo => (!lots.is_empty()).then_some(0),
+            LotMethod::Lifo => lots.len().checked_sub(1),
+            LotMethod::Hifo => lots
+                .iter()
+                .enumerate()
+                .max_by(|(_, a), (_, b)| a.unit_cost.total_cmp(&b.unit_cost))
+                .map(|(i, _)| i),
+        };
+        let (acquired, units, unit_cost) = match index {
+            Some(i) => {
+                let lot = &mut lots[i];
+                let units = lot.units.min(remaining);
+                lot.units -= units;
+                let taken = (Some(lot.acquired), units, lot.unit_cost);
+                if !lot.units.is_positive() {
+                    lots.remove(i);
+                }
+                taken
+            }
+            // More was debited than the lots hold; dispose at zero cost
+            None => (None, remaining, 0.0),
+        };
+        remaining -= units;
+        disposals.push(Disposal {
+            wallet_id: tx.wallet_id.clone(),
+            asset: asset.clone(),
+            acquired,
+            disposed: tx.timestamp,
+            units,
+            cost_basis: units.to_f64() * unit_cost,
+            proceeds: unit_proceeds.map(|p| p * units.to_f64()),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{StaticRateProvider, WalletType};
+
+    fn usd() -> Asset {
+        Asset::Fiat("USD".to_string())
+    }
+
+    fn prices(btc: f64) -> StaticRateProvider {
+        let mut prices = StaticRateProvider::new();
+        prices.set_rate(Asset::Btc, usd(), btc);
+        prices
+    }
+
+    fn system() -> CustodySystem {
+        let mut system = CustodySystem::new();
+        system
+            .create_wallet("w".to_string(), "bc1".to_string(), WalletType::Hot)
+            .unwrap();
+        for price in [10.0, 30.0, 20.0] {
+            system
+                .deposit_priced("w", amount!(1.0), &prices(price), &usd())
+                .unwrap();
+        }
+        system
+            .withdraw_priced("w", amount!(1.5), &prices(40.0), &usd())
+            .unwrap();
+        system
+    }
+
+    fn gain(method: LotMethod) -> f64 {
+        system()
+            .wallet_lots("w", method, &usd())
+            .unwrap()
+            .total_gain()
+    }
+
+    #[test]
+    fn test_lot_methods() {
+        // Proceeds are 60 for 1.5 units
+        assert_eq!(gain(LotMethod::Fifo), 60.0 - (10.0 + 15.0));
+        assert_eq!(gain(LotMethod::Lifo), 60.0 - (20.0 + 15.0));
+        assert_eq!(gain(LotMethod::Hifo), 60.0 - (30.0 + 10.0));
+    }
+
+    #[test]
+    fn test_open_lots_after_partial_match() {
+        let report = system().wallet_lots("w", LotMethod::Fifo, &usd()).unwrap();
+        assert_eq!(report.disposals.len(), 2);
+        assert_eq!(report.open_lots.len(), 2);
+        assert_eq!(report.open_lots[0].1.units, amount!(0.5));
+        assert_eq!(report.open_lots[0].1.unit_cost, 30.0);
+    }
+
+    #[test]
+    fn test_disposal_report_csv() {
+        let report = system().disposal_report(
+            Timestamp::EPOCH,
+            Timestamp::from_unix(u32::MAX as u64),
+            LotMethod::Fifo,
+            &usd(),
+        );
+        let mut csv = Vec::new();
+        report.write_csv(&mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let lines: Vec<_> = csv.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[1].starts_with("w,BTC,1,"));
+        assert!(lines[1].ends_with(",40.00,10.00,30.00,USD"));
+        assert_eq!(report.disposals[0].holding_days(), Some(0));
+    }
+}
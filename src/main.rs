@@ -1,79 +1,421 @@
-use securevault::{CustodySystem, WalletType};
+//! `securevault` administration tool.
+//!
+//! Every command opens the ledger kept in `--data-dir` (created on first
+//! use), applies the operation and persists the result.
 
-fn main() {
-    println!("🔐 SecureVault - Cryptocurrency Custody System");
-    println!("==============================================\n");
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use securevault::time::{FixedOffset, NaiveDate, Timestamp};
+use securevault::{
+    format_amount, render_portfolio, Amount, Asset, CustodySystem, ExportFormat, JsonFileStorage,
+    StaticRateProvider, TransactionFilter, WalletType,
+};
+use std::error::Error;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::Arc;
 
-    let mut system = CustodySystem::new();
+/// Name of the ledger document inside the data directory
+const LEDGER_FILE: &str = "ledger.json";
 
-    let hot_wallet = system
-        .create_wallet(
-            "hot_001".to_string(),
-            "0x1234567890abcdef".to_string(),
-            WalletType::Hot,
-        )
-        .expect("Failed to create hot wallet");
-    println!(
-        "✓ Created hot wallet: {} ({})",
-        hot_wallet.id, hot_wallet.address
-    );
-
-    let cold_wallet = system
-        .create_wallet(
-            "cold_001".to_string(),
-            "0xfedcba0987654321".to_string(),
-            WalletType::Cold,
-        )
-        .expect("Failed to create cold wallet");
-    println!(
-        "✓ Created cold wallet: {} ({})",
-        cold_wallet.id, cold_wallet.address
-    );
-
-    system.deposit("hot_001", 10.5).unwrap();
-    println!("\n✓ Deposited 10.5 BTC to hot wallet");
-
-    system.deposit("cold_001", 100.0).unwrap();
-    println!("✓ Deposited 100.0 BTC to cold wallet");
-
-    println!("\n📊 Wallet Balances:");
-    for (id, wallet) in system.get_all_wallets() {
-        println!(
-            "  {} ({:?}): {} BTC",
-            id, wallet.wallet_type, wallet.balance
-        );
+#[derive(Debug, Parser)]
+#[command(
+    name = "securevault",
+    version,
+    about = "Administer a securevault custody ledger"
+)]
+struct Cli {
+    /// Directory holding the ledger; created if missing
+    #[arg(long, global = true, default_value = "securevault-data")]
+    data_dir: PathBuf,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Create and inspect wallets
+    #[command(subcommand)]
+    Wallet(WalletCommand),
+    /// Credit a wallet
+    Deposit {
+        wallet: String,
+        amount: Amount,
+        /// Ignore the deposit if one with this key was already recorded
+        #[arg(long)]
+        idempotency_key: Option<String>,
+    },
+    /// Debit a hot wallet
+    Withdraw {
+        wallet: String,
+        amount: Amount,
+        /// Ignore the withdrawal if one with this key was already recorded
+        #[arg(long)]
+        idempotency_key: Option<String>,
+    },
+    /// Move funds between two wallets
+    Transfer {
+        from: String,
+        to: String,
+        amount: Amount,
+        /// Ignore the transfer if one with this key was already recorded
+        #[arg(long)]
+        idempotency_key: Option<String>,
+    },
+    /// Inspect the transaction log
+    #[command(subcommand)]
+    Tx(TxCommand),
+    /// Write transactions as CSV or JSON Lines
+    Export {
+        #[arg(long, value_enum, default_value_t = Format::Csv)]
+        format: Format,
+        /// File to write; standard output when omitted
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+        #[command(flatten)]
+        filter: FilterArgs,
+    },
+    /// Print balances valued in a fiat currency
+    Portfolio {
+        /// Currency to value holdings in
+        #[arg(long, default_value = "USD")]
+        currency: String,
+        /// Price of one unit of an asset, as ASSET=PRICE (repeatable)
+        #[arg(long = "rate", value_parser = parse_rate)]
+        rates: Vec<(Asset, f64)>,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum WalletCommand {
+    /// Create a wallet
+    Create {
+        id: String,
+        address: String,
+        #[arg(long, value_enum, default_value_t = Kind::Hot)]
+        kind: Kind,
+        /// Asset held: BTC, ETH or an ISO 4217 code
+        #[arg(long, default_value = "BTC", value_parser = parse_asset)]
+        asset: Asset,
+        /// Signatures needed to withdraw (multisig only)
+        #[arg(long, required_if_eq("kind", "multisig"))]
+        required: Option<usize>,
+        /// Authorized signer (multisig only, repeatable)
+        #[arg(long = "signer", required_if_eq("kind", "multisig"))]
+        signers: Vec<String>,
+    },
+    /// List all wallets
+    List,
+    /// Show one wallet
+    Show { id: String },
+}
+
+#[derive(Debug, Subcommand)]
+enum TxCommand {
+    /// List transactions, oldest first
+    List {
+        #[command(flatten)]
+        filter: FilterArgs,
+    },
+}
+
+#[derive(Debug, Args)]
+struct FilterArgs {
+    /// Only transactions involving this wallet
+    #[arg(long)]
+    wallet: Option<String>,
+    /// Only transactions at or after this date (YYYY-MM-DD, UTC) or Unix time
+    #[arg(long, value_parser = parse_time)]
+    since: Option<Timestamp>,
+    /// Only transactions before this date (YYYY-MM-DD, UTC) or Unix time
+    #[arg(long, value_parser = parse_time)]
+    until: Option<Timestamp>,
+    /// Only transactions carrying this tag
+    #[arg(long)]
+    tag: Option<String>,
+}
+
+impl From<FilterArgs> for TransactionFilter {
+    fn from(args: FilterArgs) -> Self {
+        TransactionFilter {
+            wallet_id: args.wallet,
+            from: args.since,
+            until: args.until,
+            tag: args.tag,
+            ..Default::default()
+        }
     }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Kind {
+    Hot,
+    Cold,
+    Multisig,
+}
 
-    println!("\n💰 Total Balance: {} BTC", system.get_total_balance());
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Format {
+    Csv,
+    Jsonl,
+}
 
-    match system.withdraw("hot_001", 5.0) {
-        Ok(_) => println!("\n✓ Withdrew 5.0 BTC from hot wallet"),
-        Err(e) => println!("\n✗ Withdrawal failed: {}", e),
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    match run(cli) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            ExitCode::FAILURE
+        }
     }
+}
 
-    println!(
-        "\n📊 Final Total Balance: {} BTC",
-        system.get_total_balance()
-    );
+fn run(cli: Cli) -> Result<(), Box<dyn Error>> {
+    let mut system = open(&cli.data_dir)?;
+    let mut out = io::stdout().lock();
+    match cli.command {
+        Command::Wallet(WalletCommand::Create {
+            id,
+            address,
+            kind,
+            asset,
+            required,
+            signers,
+        }) => {
+            let wallet_type = match kind {
+                Kind::Hot => WalletType::Hot,
+                Kind::Cold => WalletType::Cold,
+                Kind::Multisig => WalletType::MultiSig {
+                    required: required.unwrap_or_default(),
+                    signers: signers.into_iter().collect(),
+                },
+            };
+            let wallet = system.create_wallet_with_asset(id, address, wallet_type, asset)?;
+            writeln!(out, "Created {} wallet {}", wallet.wallet_type, wallet.id)?;
+        }
+        Command::Wallet(WalletCommand::List) => {
+            let mut wallets: Vec<_> = system.get_all_wallets().values().collect();
+            wallets.sort_by(|a, b| a.id.cmp(&b.id));
+            for wallet in wallets {
+                writeln!(
+                    out,
+                    "{}\t{}\t{}\t{}",
+                    wallet.id,
+                    wallet.wallet_type,
+                    wallet.status,
+                    format_amount(wallet.balance, &wallet.asset)
+                )?;
+            }
+        }
+        Command::Wallet(WalletCommand::Show { id }) => {
+            let wallet = system
+                .get_wallet(&id)
+                .ok_or_else(|| format!("wallet '{}' not found", id))?;
+            writeln!(out, "id:       {}", wallet.id)?;
+            writeln!(out, "address:  {}", wallet.address)?;
+            writeln!(out, "type:     {}", wallet.wallet_type)?;
+            writeln!(out, "status:   {}", wallet.status)?;
+            writeln!(
+                out,
+                "balance:  {}",
+                format_amount(wallet.balance, &wallet.asset)
+            )?;
+            writeln!(out, "created:  {}", wallet.created_at)?;
+            writeln!(
+                out,
+                "activity: {} transactions",
+                system.get_wallet_transactions(&id).len()
+            )?;
+        }
+        Command::Deposit {
+            wallet,
+            amount,
+            idempotency_key,
+        } => {
+            match idempotency_key {
+                Some(key) => system.deposit_idempotent(&key, &wallet, amount)?,
+                None => system.deposit(&wallet, amount)?,
+            }
+            writeln!(out, "Deposited {} to {}", amount, wallet)?;
+        }
+        Command::Withdraw {
+            wallet,
+            amount,
+            idempotency_key,
+        } => {
+            match idempotency_key {
+                Some(key) => system.withdraw_idempotent(&key, &wallet, amount)?,
+                None => system.withdraw(&wallet, amount)?,
+            }
+            writeln!(out, "Withdrew {} from {}", amount, wallet)?;
+        }
+        Command::Transfer {
+            from,
+            to,
+            amount,
+            idempotency_key,
+        } => {
+            match idempotency_key {
+                Some(key) => system.transfer_idempotent(&key, &from, &to, amount)?,
+                None => system.transfer(&from, &to, amount)?,
+            }
+            writeln!(out, "Transferred {} from {} to {}", amount, from, to)?;
+        }
+        Command::Tx(TxCommand::List { filter }) => {
+            let filter = checked_filter(&system, filter)?;
+            for tx in system
+                .get_all_transactions()
+                .iter()
+                .filter(|tx| filter.matches(tx))
+            {
+                writeln!(
+                    out,
+                    "{}\t{}\t{}\t{:?}\t{}\t{}",
+                    tx.id,
+                    tx.timestamp,
+                    tx.wallet_id,
+                    tx.transaction_type,
+                    format_amount(tx.amount, &tx.asset),
+                    tx.counterparty.as_deref().unwrap_or("-")
+                )?;
+            }
+        }
+        Command::Export {
+            format,
+            output,
+            filter,
+        } => {
+            let format = match format {
+                Format::Csv => ExportFormat::Csv,
+                Format::Jsonl => ExportFormat::JsonLines,
+            };
+            let filter = TransactionFilter::from(filter);
+            let count = match output {
+                Some(path) => {
+                    let mut file = io::BufWriter::new(fs::File::create(&path)?);
+                    let count = system.export_transactions(format, &filter, &mut file)?;
+                    file.flush()?;
+                    count
+                }
+                None => system.export_transactions(format, &filter, &mut out)?,
+            };
+            eprintln!("Exported {} transactions", count);
+        }
+        Command::Portfolio { currency, rates } => {
+            let currency = Asset::Fiat(currency.to_uppercase());
+            let mut provider = StaticRateProvider::new();
+            for (asset, price) in rates {
+                provider.set_rate(asset, currency.clone(), price);
+            }
+            write!(out, "{}", render_portfolio(&system, &provider, &currency))?;
+        }
+    }
+    if let Some(err) = system.storage_error() {
+        return Err(format!("ledger not saved: {}", err).into());
+    }
+    Ok(())
+}
+
+/// Opens the ledger in `data_dir`, creating the directory and an empty
+/// ledger on first use
+fn open(data_dir: &Path) -> Result<CustodySystem, Box<dyn Error>> {
+    fs::create_dir_all(data_dir)?;
+    let storage = JsonFileStorage::new(data_dir.join(LEDGER_FILE));
+    Ok(CustodySystem::with_storage(Arc::new(storage))?)
+}
+
+/// Converts filter arguments, rejecting wallets the ledger does not know
+fn checked_filter(
+    system: &CustodySystem,
+    args: FilterArgs,
+) -> Result<TransactionFilter, Box<dyn Error>> {
+    if let Some(id) = &args.wallet {
+        if !system.wallet_exists(id) {
+            return Err(format!("wallet '{}' not found", id).into());
+        }
+    }
+    Ok(args.into())
+}
+
+fn parse_asset(symbol: &str) -> Result<Asset, String> {
+    match symbol.to_uppercase().as_str() {
+        "BTC" => Ok(Asset::Btc),
+        "ETH" => Ok(Asset::Eth),
+        code if code.len() == 3 && code.bytes().all(|b| b.is_ascii_alphabetic()) => {
+            Ok(Asset::Fiat(code.to_string()))
+        }
+        _ => Err(format!("unsupported asset '{}'", symbol)),
+    }
+}
+
+fn parse_rate(rate: &str) -> Result<(Asset, f64), String> {
+    let (asset, price) = rate
+        .split_once('=')
+        .ok_or_else(|| format!("expected ASSET=PRICE, got '{}'", rate))?;
+    let price = price
+        .parse()
+        .map_err(|_| format!("invalid price '{}'", price))?;
+    Ok((parse_asset(asset)?, price))
+}
+
+fn parse_time(time: &str) -> Result<Timestamp, String> {
+    if let Ok(secs) = time.parse::<u64>() {
+        return Ok(Timestamp::from_unix(secs));
+    }
+    NaiveDate::parse_from_str(time, "%Y-%m-%d")
+        .map(|date| Timestamp::start_of_day(date, FixedOffset::east_opt(0).unwrap()))
+        .map_err(|_| format!("expected YYYY-MM-DD or Unix seconds, got '{}'", time))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
 
-    // Demonstrate transfer functionality
-    println!("\n🔄 Transferring 2.0 BTC from hot to cold wallet...");
-    match system.transfer("hot_001", "cold_001", 2.0) {
-        Ok(_) => println!("✓ Transfer successful"),
-        Err(e) => println!("✗ Transfer failed: {}", e),
+    fn run_args(data_dir: &Path, args: &[&str]) -> Result<(), Box<dyn Error>> {
+        let data_dir = data_dir.to_str().unwrap();
+        let prefix = ["securevault", "--data-dir", data_dir];
+        run(Cli::try_parse_from(prefix.iter().chain(args))?)
     }
 
-    println!("\n📊 Final Wallet Balances:");
-    for (id, wallet) in system.get_all_wallets() {
-        println!(
-            "  {} ({:?}): {} BTC",
-            id, wallet.wallet_type, wallet.balance
+    #[test]
+    fn test_cli_definition() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn test_commands_persist_to_data_dir() {
+        let dir = std::env::temp_dir().join(format!("securevault-cli-{}", std::process::id()));
+        run_args(&dir, &["wallet", "create", "hot", "0xabc"]).unwrap();
+        run_args(
+            &dir,
+            &["wallet", "create", "cold", "0xdef", "--kind", "cold"],
+        )
+        .unwrap();
+        run_args(&dir, &["deposit", "hot", "2.5"]).unwrap();
+        run_args(&dir, &["transfer", "hot", "cold", "1"]).unwrap();
+        assert!(run_args(&dir, &["withdraw", "hot", "5"]).is_err());
+        assert!(run_args(&dir, &["tx", "list", "--wallet", "nope"]).is_err());
+
+        let system = open(&dir).unwrap();
+        assert_eq!(
+            system.get_wallet("hot").unwrap().balance,
+            "1.5".parse().unwrap()
+        );
+        assert_eq!(
+            system.get_wallet("cold").unwrap().balance,
+            "1".parse().unwrap()
         );
+        fs::remove_dir_all(&dir).unwrap();
     }
 
-    // Show transaction history
-    println!("\n📜 Transaction History for hot_001:");
-    for (i, tx) in system.get_wallet_transactions("hot_001").iter().enumerate() {
-        println!("  {}. {:?}: {} BTC", i + 1, tx.transaction_type, tx.amount);
+    #[test]
+    fn test_parse_time() {
+        assert_eq!(parse_time("86400"), Ok(Timestamp::from_unix(86_400)));
+        assert_eq!(parse_time("1970-01-02"), Ok(Timestamp::from_unix(86_400)));
+        assert!(parse_time("yesterday").is_err());
     }
 }
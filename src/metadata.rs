@@ -0,0 +1,291 @@
+//! Memos, tags and key/value metadata on transactions and wallets.
+//!
+//! Annotations carry internal ticket numbers, customer references and
+//! similar bookkeeping. Like categories they can be added when a
+//! transaction is recorded or afterwards; the sealed digest covers them,
+//! the audit chain does not.
+
+use crate::{Amount, CustodyError, CustodySystem, Transaction, Wallet};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Annotations to attach to a transaction
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TransactionMetadata {
+    pub memo: Option<String>,
+    pub tags: BTreeSet<String>,
+    pub fields: BTreeMap<String, String>,
+}
+
+impl TransactionMetadata {
+    /// Creates empty metadata
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the free-text memo
+    pub fn with_memo(mut self, memo: &str) -> Self {
+        self.memo = Some(memo.to_string());
+        self
+    }
+
+    /// Adds a tag
+    pub fn with_tag(mut self, tag: &str) -> Self {
+        self.tags.insert(tag.to_string());
+        self
+    }
+
+    /// Sets a key/value entry
+    pub fn with_field(mut self, key: &str, value: &str) -> Self {
+        self.fields.insert(key.to_string(), value.to_string());
+        self
+    }
+
+    /// Returns true if there is nothing to attach
+    pub fn is_empty(&self) -> bool {
+        self.memo.is_none() && self.tags.is_empty() && self.fields.is_empty()
+    }
+
+    /// Merges into `tx`: replaces the memo if one is set, adds the tags and
+    /// overwrites entries with the same key
+    fn apply(self, tx: &mut Transaction) {
+        if self.memo.is_some() {
+            tx.memo = self.memo;
+        }
+        tx.tags.extend(self.tags);
+        tx.metadata.extend(self.fields);
+    }
+}
+
+impl CustodySystem {
+    /// Deposits funds and attaches `metadata` to the transaction
+    ///
+    /// # Example
+    /// ```
+    /// use securevault::{amount, CustodySystem, TransactionMetadata, WalletType};
+    /// let mut system = CustodySystem::new();
+    /// system.create_wallet("w".to_string(), "0x1".to_string(), WalletType::Hot).unwrap();
+    /// let metadata = TransactionMetadata::new()
+    ///     .with_memo("wire from ACME")
+    ///     .with_tag("TICKET-42")
+    ///     .with_field("customer", "C-1001");
+    /// system.deposit_with_metadata("w", amount!(5), metadata).unwrap();
+    ///
+    /// let tagged = system.transactions_tagged("TICKET-42");
+    /// assert_eq!(tagged[0].metadata["customer"], "C-1001");
+    /// ```
+    pub fn deposit_with_metadata(
+        &mut self,
+        id: &str,
+        amount: Amount,
+        metadata: TransactionMetadata,
+    ) -> Result<(), CustodyError> {
+        self.deposit(id, amount)?;
+        self.annotate_last(metadata)
+    }
+
+    /// Withdraws funds and attaches `metadata` to the transaction
+    pub fn withdraw_with_metadata(
+        &mut self,
+        id: &str,
+        amount: Amount,
+        metadata: TransactionMetadata,
+    ) -> Result<(), CustodyError> {
+        self.withdraw(id, amount)?;
+        self.annotate_last(metadata)
+    }
+
+    /// Merges `metadata` into the transaction at `index` in the log
+    pub fn annotate_transaction(
+        &mut self,
+        index: usize,
+        metadata: TransactionMetadata,
+    ) -> Result<(), CustodyError> {
+        self.check_transaction_open(index)?;
+        if self.update_transaction(index, |tx| metadata.apply(tx))? {
+            Ok(())
+        } else {
+            Err(CustodyError::TransactionNotFound(index))
+        }
+    }
+
+    /// Returns the transactions carrying `tag`, oldest first
+    pub fn transactions_tagged(&self, tag: &str) -> Vec<&Transaction> {
+        self.transactions
+            .iter()
+            .filter(|tx| tx.tags.contains(tag))
+            .collect()
+    }
+
+    /// Returns the transactions whose metadata maps `key` to `value`,
+    /// oldest first
+    pub fn transactions_with_metadata(&self, key: &str, value: &str) -> Vec<&Transaction> {
+        self.transactions
+            .iter()
+            .filter(|tx| tx.metadata.get(key).is_some_and(|v| v == value))
+            .collect()
+    }
+
+    /// Adds a tag to a wallet
+    pub fn tag_wallet(&mut self, wallet_id: &str, tag: &str) -> Result<(), CustodyError> {
+        self.update_wallet_annotations(wallet_id, |wallet| {
+            wallet.tags.insert(tag.to_string());
+        })
+    }
+
+    /// Removes a tag from a wallet, returning whether it had it
+    pub fn untag_wallet(&mut self, wallet_id: &str, tag: &str) -> Result<bool, CustodyError> {
+        let mut removed = false;
+        self.update_wallet_annotations(wallet_id, |wallet| removed = wallet.tags.remove(tag))?;
+        Ok(removed)
+    }
+
+    /// Sets or clears a wallet's memo
+    pub fn set_wallet_memo(
+        &mut self,
+        wallet_id: &str,
+        memo: Option<&str>,
+    ) -> Result<(), CustodyError> {
+        self.update_wallet_annotations(wallet_id, |wallet| wallet.memo = memo.map(str::to_string))
+    }
+
+    /// Sets a key/value entry on a wallet, or removes it when `value` is
+    /// `None`
+    pub fn set_wallet_metadata(
+        &mut self,
+        wallet_id: &str,
+        key: &str,
+        value: Option<&str>,
+    ) -> Result<(), CustodyError> {
+        self.update_wallet_annotations(wallet_id, |wallet| match value {
+            Some(value) => {
+                wallet.metadata.insert(key.to_string(), value.to_string());
+            }
+            None => {
+                wallet.metadata.remove(key);
+            }
+        })
+    }
+
+    pub(crate) fn update_wallet_annotations(
+        &mut self,
+        wallet_id: &str,
+        update: impl FnOnce(&mut Wallet),
+    ) -> Result<(), CustodyError> {
+        let mut wallet = self
+            .wallets
+            .get(wallet_id)
+            .cloned()
+            .ok_or_else(|| CustodyError::WalletNotFound(wallet_id.to_string()))?;
+        update(&mut wallet);
+        self.commit_wallet(wallet)
+    }
+
+    fn annotate_last(&mut self, metadata: TransactionMetadata) -> Result<(), CustodyError> {
+        if metadata.is_empty() {
+            return Ok(());
+        }
+        self.update_last_transaction(|tx| metadata.apply(tx))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{TransactionFilter, WalletType};
+
+    fn system() -> CustodySystem {
+        let mut system = CustodySystem::new();
+        system
+            .create_wallet("w".to_string(), "0x1".to_string(), WalletType::Hot)
+            .unwrap();
+        system
+            .deposit_with_metadata(
+                "w",
+                amount!(10),
+                TransactionMetadata::new()
+                    .with_tag("T-1")
+                    .with_field("customer", "C-1"),
+            )
+            .unwrap();
+        system
+            .withdraw_with_metadata("w", amount!(2), TransactionMetadata::new().with_tag("T-2"))
+            .unwrap();
+        system.deposit("w", amount!(1)).unwrap();
+        system
+    }
+
+    #[test]
+    fn test_query_by_tag_and_metadata() {
+        let system = system();
+        assert_eq!(system.transactions_tagged("T-1").len(), 1);
+        assert_eq!(system.transactions_tagged("T-2")[0].amount, amount!(2));
+        assert!(system.transactions_tagged("T-3").is_empty());
+        assert_eq!(
+            system.transactions_with_metadata("customer", "C-1").len(),
+            1
+        );
+        assert!(system
+            .transactions_with_metadata("customer", "C-2")
+            .is_empty());
+
+        let filter = TransactionFilter {
+            tag: Some("T-2".to_string()),
+            ..Default::default()
+        };
+        let matched: Vec<_> = system
+            .get_all_transactions()
+            .iter()
+            .filter(|tx| filter.matches(tx))
+            .collect();
+        assert_eq!(matched.len(), 1);
+    }
+
+    #[test]
+    fn test_annotate_merges_and_reseals() {
+        let mut system = system();
+        system
+            .annotate_transaction(
+                0,
+                TransactionMetadata::new()
+                    .with_memo("confirmed by phone")
+                    .with_tag("T-3")
+                    .with_field("customer", "C-9"),
+            )
+            .unwrap();
+        let tx = &system.get_all_transactions()[0];
+        assert_eq!(tx.memo.as_deref(), Some("confirmed by phone"));
+        assert_eq!(tx.tags.len(), 2);
+        assert_eq!(tx.metadata["customer"], "C-9");
+        assert!(tx.verify_digest());
+        assert!(system.verify_audit_chain().is_ok());
+        assert_eq!(
+            system.annotate_transaction(9, TransactionMetadata::new()),
+            Err(CustodyError::TransactionNotFound(9))
+        );
+    }
+
+    #[test]
+    fn test_wallet_annotations() {
+        let mut system = system();
+        system.tag_wallet("w", "treasury").unwrap();
+        system.set_wallet_memo("w", Some("main float")).unwrap();
+        system
+            .set_wallet_metadata("w", "cost_center", Some("CC-7"))
+            .unwrap();
+        let wallet = system.get_wallet("w").unwrap();
+        assert!(wallet.tags.contains("treasury"));
+        assert_eq!(wallet.memo.as_deref(), Some("main float"));
+        assert_eq!(wallet.metadata["cost_center"], "CC-7");
+
+        assert_eq!(system.untag_wallet("w", "treasury"), Ok(true));
+        assert_eq!(system.untag_wallet("w", "treasury"), Ok(false));
+        system
+            .set_wallet_metadata("w", "cost_center", None)
+            .unwrap();
+        assert!(system.get_wallet("w").unwrap().metadata.is_empty());
+        assert_eq!(
+            system.tag_wallet("nope", "x"),
+            Err(CustodyError::WalletNotFound("nope".to_string()))
+        );
+    }
+}
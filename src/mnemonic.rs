@@ -0,0 +1,235 @@
+//! BIP-39 seed phrases (feature `mnemonic`).
+//!
+//! A [`Mnemonic`] is generated from OS randomness or imported from an
+//! existing phrase, whose checksum is verified. Together with an optional
+//! passphrase it yields the BIP-39 seed, from which the BIP-44 Bitcoin
+//! account `m/44'/0'/account'` is derived: its extended public key backs an
+//! HD wallet through [`CustodySystem::create_wallet_from_mnemonic`], and
+//! the private key of each receive address is available for a signer or
+//! a [`KeyVault`](crate::KeyVault). A cold wallet can thus be set up
+//! offline with this crate alone.
+//!
+//! The phrase, the seed and derived private keys are wiped from memory
+//! when dropped.
+
+use crate::{CustodyError, CustodySystem, Wallet, WalletType};
+use bip32::{ChildNumber, DerivationPath, Prefix, XPrv};
+use bip39::Language;
+use std::fmt;
+use zeroize::Zeroizing;
+
+/// BIP-44 purpose
+const PURPOSE: u32 = 44;
+
+/// BIP-44 coin type of Bitcoin
+const BITCOIN_COIN_TYPE: u32 = 0;
+
+/// A BIP-39 English seed phrase
+#[derive(Clone)]
+pub struct Mnemonic {
+    phrase: Zeroizing<String>,
+}
+
+impl fmt::Debug for Mnemonic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Mnemonic")
+            .field("words", &self.word_count())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Mnemonic {
+    /// Generates a phrase of 12 or 24 words from OS randomness
+    ///
+    /// # Example
+    /// ```
+    /// use securevault::Mnemonic;
+    /// let mnemonic = Mnemonic::generate(24).unwrap();
+    /// assert_eq!(mnemonic.word_count(), 24);
+    /// assert!(Mnemonic::parse(&mnemonic.phrase()).is_ok());
+    /// assert!(Mnemonic::generate(13).is_err());
+    /// ```
+    pub fn generate(words: usize) -> Result<Self, CustodyError> {
+        let length = match words {
+            12 => 16,
+            24 => 32,
+            _ => {
+                return Err(CustodyError::InvalidMnemonic(format!(
+                    "cannot generate {} words, only 12 or 24",
+                    words
+                )))
+            }
+        };
+        let mut entropy = Zeroizing::new([0u8; 32]);
+        getrandom::getrandom(&mut entropy[..length])
+            .map_err(|err| CustodyError::InvalidMnemonic(err.to_string()))?;
+        let mnemonic = bip39::Mnemonic::from_entropy(&entropy[..length])
+            .expect("16 and 32 bytes are valid BIP-39 entropy lengths");
+        Ok(Self::from_bip39(mnemonic))
+    }
+
+    /// Imports an existing phrase, verifying its words and checksum
+    pub fn parse(phrase: &str) -> Result<Self, CustodyError> {
+        let mnemonic = bip39::Mnemonic::parse_in(Language::English, phrase)
+            .map_err(|err| CustodyError::InvalidMnemonic(err.to_string()))?;
+        Ok(Self::from_bip39(mnemonic))
+    }
+
+    fn from_bip39(mnemonic: bip39::Mnemonic) -> Self {
+        Self {
+            phrase: Zeroizing::new(mnemonic.to_string()),
+        }
+    }
+
+    /// Returns the words, separated by single spaces
+    pub fn phrase(&self) -> Zeroizing<String> {
+        self.phrase.clone()
+    }
+
+    /// Number of words
+    pub fn word_count(&self) -> usize {
+        self.phrase.split(' ').count()
+    }
+
+    /// Returns the BIP-39 seed, protected by `passphrase` (empty for none)
+    pub fn to_seed(&self, passphrase: &str) -> Zeroizing<[u8; 64]> {
+        let mnemonic = bip39::Mnemonic::parse_in(Language::English, self.phrase.as_str())
+            .expect("the phrase was validated when created");
+        Zeroizing::new(mnemonic.to_seed(passphrase))
+    }
+
+    /// Returns the extended public key of the BIP-44 Bitcoin account
+    /// `m/44'/0'/account'`
+    pub fn account_xpub(&self, passphrase: &str, account: u32) -> Result<String, CustodyError> {
+        let key = self.derive(passphrase, &account_path(account)?)?;
+        Ok(key.public_key().to_string(Prefix::XPUB))
+    }
+
+    /// Returns the private key of receive address `index` of the account,
+    /// `m/44'/0'/account'/0/index`
+    pub fn signing_key(
+        &self,
+        passphrase: &str,
+        account: u32,
+        index: u32,
+    ) -> Result<Zeroizing<[u8; 32]>, CustodyError> {
+        let mut path = account_path(account)?;
+        path.push(child(0, false)?);
+        path.push(child(index, false)?);
+        let key = self.derive(passphrase, &path)?;
+        Ok(Zeroizing::new(key.to_bytes()))
+    }
+
+    fn derive(&self, passphrase: &str, path: &DerivationPath) -> Result<XPrv, CustodyError> {
+        XPrv::derive_from_path(self.to_seed(passphrase).as_slice(), path)
+            .map_err(|err| CustodyError::InvalidMnemonic(err.to_string()))
+    }
+}
+
+fn account_path(account: u32) -> Result<DerivationPath, CustodyError> {
+    let mut path = DerivationPath::default();
+    path.push(child(PURPOSE, true)?);
+    path.push(child(BITCOIN_COIN_TYPE, true)?);
+    path.push(child(account, true)?);
+    Ok(path)
+}
+
+fn child(index: u32, hardened: bool) -> Result<ChildNumber, CustodyError> {
+    ChildNumber::new(index, hardened)
+        .map_err(|_| CustodyError::InvalidMnemonic(format!("index {} out of range", index)))
+}
+
+impl CustodySystem {
+    /// Creates an HD Bitcoin wallet for the BIP-44 account `account` of a
+    /// seed phrase
+    ///
+    /// Only the account's extended public key is kept; the phrase is not
+    /// stored.
+    ///
+    /// # Example
+    /// ```
+    /// use securevault::{CustodySystem, Mnemonic, WalletType};
+    /// let mnemonic = Mnemonic::parse(
+    ///     "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about",
+    /// )
+    /// .unwrap();
+    /// let mut system = CustodySystem::new();
+    /// let wallet = system
+    ///     .create_wallet_from_mnemonic("cold".to_string(), &mnemonic, "", 0, WalletType::Cold)
+    ///     .unwrap();
+    /// assert_eq!(wallet.address, "1LqBGSKuX5yYUonjxT5qGfpUsXKYYWeabA");
+    /// ```
+    pub fn create_wallet_from_mnemonic(
+        &mut self,
+        id: String,
+        mnemonic: &Mnemonic,
+        passphrase: &str,
+        account: u32,
+        wallet_type: WalletType,
+    ) -> Result<Wallet, CustodyError> {
+        let xpub = mnemonic.account_xpub(passphrase, account)?;
+        self.create_wallet_from_xpub(id, &xpub, wallet_type)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ABANDON: &str = "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about";
+
+    #[test]
+    fn test_bip39_vectors() {
+        let mnemonic = Mnemonic::parse(ABANDON).unwrap();
+        assert_eq!(mnemonic.word_count(), 12);
+        assert_eq!(
+            mnemonic
+                .to_seed("TREZOR")
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect::<String>(),
+            "c55257c360c07c72029aebc1b53c05ed0362ada38ead3e3e9efa3708e53495531f09a6987599d18264c1e1c92f2cf141630c7a3c4ab7c81b2f001698e7463b04"
+        );
+        // Same words, broken checksum
+        let broken = ABANDON.replace("about", "abandon");
+        assert!(matches!(
+            Mnemonic::parse(&broken),
+            Err(CustodyError::InvalidMnemonic(_))
+        ));
+        assert!(Mnemonic::parse("abandon abandon notaword").is_err());
+        assert!(!format!("{:?}", mnemonic).contains("abandon"));
+    }
+
+    #[test]
+    fn test_keys_match_the_wallet_addresses() {
+        let mnemonic = Mnemonic::parse(ABANDON).unwrap();
+        assert_eq!(
+            mnemonic.account_xpub("", 0).unwrap(),
+            "xpub6BosfCnifzxcFwrSzQiqu2DBVTshkCXacvNsWGYJVVhhawA7d4R5WSWGFNbi8Aw6ZRc1brxMyWMzG3DSSSSoekkudhUd9yLb6qx39T9nMdj"
+        );
+        // WIF of m/44'/0'/0'/0/0: version, key, compression flag
+        let wif = bs58::decode("L4p2b9VAf8k5aUahF1JCJUzZkgNEAqLfq8DDdQiyAprQAKSbu8hf")
+            .with_check(Some(0x80))
+            .into_vec()
+            .unwrap();
+        assert_eq!(
+            mnemonic.signing_key("", 0, 0).unwrap().as_slice(),
+            &wif[1..33]
+        );
+
+        let mut system = CustodySystem::new();
+        system
+            .create_wallet_from_mnemonic("a".to_string(), &mnemonic, "", 0, WalletType::Cold)
+            .unwrap();
+        let other = system
+            .create_wallet_from_mnemonic(
+                "b".to_string(),
+                &mnemonic,
+                "extra words",
+                0,
+                WalletType::Cold,
+            )
+            .unwrap();
+        assert_ne!(other.address, system.get_wallet("a").unwrap().address);
+    }
+}
@@ -0,0 +1,342 @@
+//! Multi-signature wallets.
+//!
+//! A [`WalletType::MultiSig`] wallet names its signers and how many of
+//! them must sign a withdrawal. Withdrawals cannot be made directly: one
+//! signer requests a [`MultiSigWithdrawal`], which carries their
+//! signature, and others add theirs with
+//! [`CustodySystem::sign_withdrawal`]. The withdrawal executes as soon as
+//! `required` signers have signed, and is checked again at that point.
+
+use crate::precheck::Authorization;
+use crate::screening::Workflow;
+use crate::{Amount, CustodyError, CustodyEvent, CustodySystem, OperationStatus, WalletType};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+
+/// A withdrawal from a multi-signature wallet collecting signatures
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MultiSigWithdrawal {
+    pub id: u64,
+    pub wallet_id: String,
+    pub amount: Amount,
+    pub destination: Option<String>,
+    /// Signers who have signed, including the requester
+    pub signatures: BTreeSet<String>,
+    pub status: OperationStatus,
+}
+
+impl CustodySystem {
+    /// Requests a withdrawal from a multi-signature wallet, signed by
+    /// `signer`
+    ///
+    /// # Returns
+    /// The operation id other signers sign with
+    /// [`sign_withdrawal`](Self::sign_withdrawal). If one signature
+    /// suffices, the withdrawal executes immediately.
+    ///
+    /// # Example
+    /// ```
+    /// use securevault::{amount, CustodySystem, OperationStatus, WalletType};
+    /// let mut system = CustodySystem::new();
+    /// let wallet_type = WalletType::multisig(2, &["alice", "bob", "carol"]);
+    /// system.create_wallet("treasury".to_string(), "bc1q...".to_string(), wallet_type).unwrap();
+    /// system.deposit("treasury", amount!(10.0)).unwrap();
+    ///
+    /// let op = system
+    ///     .request_multisig_withdrawal("treasury", "alice", amount!(4.0), None)
+    ///     .unwrap();
+    /// assert_eq!(system.get_wallet("treasury").unwrap().balance, amount!(10.0));
+    /// assert_eq!(
+    ///     system.sign_withdrawal("treasury", op, "carol").unwrap(),
+    ///     OperationStatus::Executed
+    /// );
+    /// assert_eq!(system.get_wallet("treasury").unwrap().balance, amount!(6.0));
+    /// ```
+    pub fn request_multisig_withdrawal(
+        &mut self,
+        wallet_id: &str,
+        signer: &str,
+        amount: Amount,
+        destination: Option<&str>,
+    ) -> Result<u64, CustodyError> {
+        self.check_multisig_request(wallet_id, signer, amount, destination)?;
+        let workflow = Workflow::MultiSig {
+            signer: signer.to_string(),
+        };
+        self.screen_withdrawal_request(wallet_id, amount, destination, workflow)?;
+        self.open_multisig_withdrawal(wallet_id, signer, amount, destination)
+    }
+
+    /// Opens a screened withdrawal request, checking it again
+    pub(crate) fn open_multisig_withdrawal(
+        &mut self,
+        wallet_id: &str,
+        signer: &str,
+        amount: Amount,
+        destination: Option<&str>,
+    ) -> Result<u64, CustodyError> {
+        self.check_multisig_request(wallet_id, signer, amount, destination)?;
+        let id = self.allocate_operation_id();
+        self.multisig_withdrawals.insert(
+            id,
+            MultiSigWithdrawal {
+                id,
+                wallet_id: wallet_id.to_string(),
+                amount,
+                destination: destination.map(str::to_string),
+                signatures: BTreeSet::from([signer.to_string()]),
+                status: OperationStatus::Pending,
+            },
+        );
+        if let Err(err) = self.try_sign_off(id) {
+            self.multisig_withdrawals.remove(&id);
+            return Err(err);
+        }
+        Ok(id)
+    }
+
+    /// Adds `signer`'s signature to a pending withdrawal from `wallet_id`,
+    /// executing it once the threshold is met
+    ///
+    /// Signing twice has no further effect.
+    ///
+    /// # Returns
+    /// The withdrawal's status after signing
+    pub fn sign_withdrawal(
+        &mut self,
+        wallet_id: &str,
+        operation_id: u64,
+        signer: &str,
+    ) -> Result<OperationStatus, CustodyError> {
+        self.signers_for(wallet_id, signer)?;
+        let operation = self
+            .multisig_withdrawals
+            .get_mut(&operation_id)
+            .filter(|op| op.wallet_id == wallet_id)
+            .ok_or(CustodyError::OperationNotFound(operation_id))?;
+        if operation.status != OperationStatus::Pending {
+            return Err(CustodyError::OperationNotPending(operation_id));
+        }
+        operation.signatures.insert(signer.to_string());
+        self.try_sign_off(operation_id)
+    }
+
+    /// Gets a multi-signature withdrawal by id
+    pub fn get_multisig_withdrawal(&self, operation_id: u64) -> Option<&MultiSigWithdrawal> {
+        self.multisig_withdrawals.get(&operation_id)
+    }
+
+    /// Lists withdrawals from `wallet_id` still awaiting signatures
+    pub fn pending_multisig_withdrawals(&self, wallet_id: &str) -> Vec<&MultiSigWithdrawal> {
+        self.multisig_withdrawals
+            .values()
+            .filter(|op| op.wallet_id == wallet_id && op.status == OperationStatus::Pending)
+            .collect()
+    }
+
+    /// Returns the wallet's threshold and signers, if `signer` is one of
+    /// them
+    fn signers_for(
+        &self,
+        wallet_id: &str,
+        signer: &str,
+    ) -> Result<(usize, &BTreeSet<String>), CustodyError> {
+        let wallet = self
+            .wallets
+            .get(wallet_id)
+            .ok_or_else(|| CustodyError::WalletNotFound(wallet_id.to_string()))?;
+        let WalletType::MultiSig { required, signers } = &wallet.wallet_type else {
+            return Err(CustodyError::NotMultiSigWallet(wallet_id.to_string()));
+        };
+        if !signers.contains(signer) {
+            return Err(CustodyError::NotASigner {
+                wallet_id: wallet_id.to_string(),
+                user: signer.to_string(),
+            });
+        }
+        Ok((*required, signers))
+    }
+
+    fn check_multisig_request(
+        &self,
+        wallet_id: &str,
+        signer: &str,
+        amount: Amount,
+        destination: Option<&str>,
+    ) -> Result<(), CustodyError> {
+        self.signers_for(wallet_id, signer)?;
+        let reasons = self.withdrawal_blockers(
+            wallet_id,
+            None,
+            amount,
+            destination,
+            Authorization::Approved,
+        );
+        match reasons.into_iter().next() {
+            Some(reason) => Err(reason),
+            None => Ok(()),
+        }
+    }
+
+    fn try_sign_off(&mut self, operation_id: u64) -> Result<OperationStatus, CustodyError> {
+        let operation = self.multisig_withdrawals[&operation_id].clone();
+        let wallet_id = &operation.wallet_id;
+        let WalletType::MultiSig { required, .. } = self.wallets[wallet_id].wallet_type else {
+            return Err(CustodyError::NotMultiSigWallet(wallet_id.clone()));
+        };
+        if operation.signatures.len() < required {
+            return Ok(OperationStatus::Pending);
+        }
+
+        let reasons = self.withdrawal_blockers(
+            wallet_id,
+            None,
+            operation.amount,
+            operation.destination.as_deref(),
+            Authorization::Approved,
+        );
+        if let Some(reason) = reasons.into_iter().next() {
+            return Err(reason);
+        }
+        self.emit(CustodyEvent::WithdrawalApproved {
+            wallet_id: wallet_id.clone(),
+            operation_id,
+            amount: operation.amount,
+            asset: self.wallets[wallet_id].asset.clone(),
+        });
+        self.execute_withdrawal(
+            wallet_id,
+            None,
+            operation.amount,
+            operation.destination.as_deref(),
+            Authorization::Approved,
+        )?;
+        if let Some(operation) = self.multisig_withdrawals.get_mut(&operation_id) {
+            operation.status = OperationStatus::Executed;
+        }
+        Ok(OperationStatus::Executed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn system() -> CustodySystem {
+        let mut system = CustodySystem::new();
+        system
+            .create_wallet(
+                "ms".to_string(),
+                "0xms".to_string(),
+                WalletType::multisig(2, &["alice", "bob", "carol"]),
+            )
+            .unwrap();
+        system.deposit("ms", amount!(10.0)).unwrap();
+        system
+    }
+
+    #[test]
+    fn test_executes_at_threshold() {
+        let mut system = system();
+        let op = system
+            .request_multisig_withdrawal("ms", "alice", amount!(3.0), Some("bc1qdest"))
+            .unwrap();
+        // Signing twice does not count twice
+        assert_eq!(
+            system.sign_withdrawal("ms", op, "alice").unwrap(),
+            OperationStatus::Pending
+        );
+        assert_eq!(system.pending_multisig_withdrawals("ms").len(), 1);
+        assert_eq!(system.get_wallet("ms").unwrap().balance, amount!(10.0));
+
+        assert_eq!(
+            system.sign_withdrawal("ms", op, "bob").unwrap(),
+            OperationStatus::Executed
+        );
+        assert_eq!(system.get_wallet("ms").unwrap().balance, amount!(7.0));
+        assert!(system.pending_multisig_withdrawals("ms").is_empty());
+        assert_eq!(
+            system.sign_withdrawal("ms", op, "carol"),
+            Err(CustodyError::OperationNotPending(op))
+        );
+    }
+
+    #[test]
+    fn test_direct_debits_are_blocked() {
+        let mut system = system();
+        assert_eq!(
+            system.withdraw("ms", amount!(1.0)),
+            Err(CustodyError::SignaturesRequired("ms".to_string()))
+        );
+        system
+            .create_wallet("hot".to_string(), "0xh".to_string(), WalletType::Hot)
+            .unwrap();
+        assert!(system.transfer("ms", "hot", amount!(1.0)).is_err());
+        assert_eq!(system.get_wallet("ms").unwrap().balance, amount!(10.0));
+    }
+
+    #[test]
+    fn test_only_signers_may_sign() {
+        let mut system = system();
+        assert_eq!(
+            system.request_multisig_withdrawal("ms", "mallory", amount!(1.0), None),
+            Err(CustodyError::NotASigner {
+                wallet_id: "ms".to_string(),
+                user: "mallory".to_string(),
+            })
+        );
+        let op = system
+            .request_multisig_withdrawal("ms", "alice", amount!(1.0), None)
+            .unwrap();
+        assert!(matches!(
+            system.sign_withdrawal("ms", op, "mallory"),
+            Err(CustodyError::NotASigner { .. })
+        ));
+        system
+            .create_wallet("hot".to_string(), "0xh".to_string(), WalletType::Hot)
+            .unwrap();
+        assert_eq!(
+            system.sign_withdrawal("hot", op, "bob"),
+            Err(CustodyError::NotMultiSigWallet("hot".to_string()))
+        );
+        assert_eq!(
+            system.sign_withdrawal("ms", op + 1, "bob"),
+            Err(CustodyError::OperationNotFound(op + 1))
+        );
+    }
+
+    #[test]
+    fn test_invalid_threshold_is_rejected() {
+        let mut system = CustodySystem::new();
+        assert_eq!(
+            system.create_wallet(
+                "ms".to_string(),
+                "0xms".to_string(),
+                WalletType::multisig(3, &["alice", "bob"]),
+            ),
+            Err(CustodyError::InvalidThreshold {
+                required: 3,
+                available: 2,
+            })
+        );
+        assert!(!system.wallet_exists("ms"));
+
+        // A 1-of-n wallet pays out on request
+        system
+            .create_wallet(
+                "solo".to_string(),
+                "0xs".to_string(),
+                WalletType::multisig(1, &["alice", "bob"]),
+            )
+            .unwrap();
+        system.deposit("solo", amount!(2.0)).unwrap();
+        let op = system
+            .request_multisig_withdrawal("solo", "bob", amount!(2.0), None)
+            .unwrap();
+        assert_eq!(
+            system.get_multisig_withdrawal(op).unwrap().status,
+            OperationStatus::Executed
+        );
+        assert_eq!(system.get_wallet("solo").unwrap().balance, Amount::ZERO);
+    }
+}
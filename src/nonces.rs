@@ -0,0 +1,476 @@
+//! Account nonces for Ethereum withdrawals.
+//!
+//! Every transaction from an Ethereum account carries the account's next
+//! nonce, and the chain mines them strictly in order. Withdrawals from
+//! wallets holding ETH or an ERC-20 token are given a nonce when they are
+//! prepared, so concurrent withdrawals from one hot wallet never reuse
+//! one. A nonce stays pending until its transaction is mined or the
+//! withdrawal is given up; a given-up nonce below other pending ones is a
+//! gap that stalls them, and is handed out first to close it.
+//!
+//! A stuck broadcast can be replaced under the same nonce, either by
+//! sending the payout again ([`CustodySystem::speed_up_broadcast`]) or by
+//! sending nothing to the wallet itself
+//! ([`CustodySystem::cancel_broadcast`]). Broadcasts left unmined for
+//! longer than [`CustodySystem::set_stuck_after`] raise
+//! [`CustodyEvent::TransactionStuck`].
+
+use crate::signer::{Signer, UnsignedTx};
+use crate::time::Timestamp;
+use crate::{
+    Amount, Asset, BroadcastStatus, BroadcastTx, Broadcaster, CustodyError, CustodyEvent,
+    CustodySystem, TxHash,
+};
+use std::collections::BTreeMap;
+
+/// How long a broadcast may stay unmined before it is reported, unless
+/// configured
+pub const DEFAULT_STUCK_AFTER_SECS: u64 = 30 * 60;
+
+/// A nonce handed out and not yet mined
+#[derive(Debug, Clone, PartialEq)]
+pub struct PendingNonce {
+    pub nonce: u64,
+    /// Prepared withdrawal it was given to
+    pub withdrawal_id: u64,
+    pub assigned_at: Timestamp,
+    /// Latest transaction sent with it, once broadcast
+    pub tx_hash: Option<TxHash>,
+    /// When that transaction was sent
+    pub broadcast_at: Option<Timestamp>,
+    /// Earlier transactions sent with it
+    pub replaced: Vec<TxHash>,
+    /// Whether the latest transaction was reported as stuck
+    pub stuck: bool,
+}
+
+/// Nonce bookkeeping of one wallet
+#[derive(Debug, Clone, Default)]
+pub(crate) struct NonceAccount {
+    /// Nonce the chain expects next, as far as the ledger knows
+    next: u64,
+    pending: BTreeMap<u64, PendingNonce>,
+}
+
+impl NonceAccount {
+    /// Nonces below the highest pending one that nothing holds
+    fn gaps(&self) -> impl Iterator<Item = u64> + '_ {
+        let end = self
+            .pending
+            .keys()
+            .next_back()
+            .copied()
+            .unwrap_or(self.next);
+        (self.next..end).filter(|nonce| !self.pending.contains_key(nonce))
+    }
+}
+
+/// Returns true if withdrawals of `asset` are sent from an Ethereum
+/// account
+pub(crate) fn uses_nonces(asset: &Asset) -> bool {
+    matches!(asset, Asset::Eth | Asset::Erc20 { .. })
+}
+
+impl CustodySystem {
+    /// Tells the ledger how many transactions the wallet's account has
+    /// mined, e.g. from `eth_getTransactionCount`
+    ///
+    /// Pending nonces below it are considered mined.
+    pub fn sync_nonce(&mut self, wallet_id: &str, mined: u64) -> Result<(), CustodyError> {
+        if !self.wallets.contains_key(wallet_id) {
+            return Err(CustodyError::WalletNotFound(wallet_id.to_string()));
+        }
+        let account = self
+            .nonce_accounts
+            .entry(wallet_id.to_string())
+            .or_default();
+        account.next = account.next.max(mined);
+        account.pending = account.pending.split_off(&mined);
+        Ok(())
+    }
+
+    /// Returns the nonces handed out to the wallet's withdrawals and not
+    /// yet mined, lowest first
+    pub fn pending_nonces(&self, wallet_id: &str) -> Vec<&PendingNonce> {
+        self.nonce_accounts
+            .get(wallet_id)
+            .map(|account| account.pending.values().collect())
+            .unwrap_or_default()
+    }
+
+    /// Returns the unused nonces below the wallet's highest pending one;
+    /// until they are used, the transactions above them cannot be mined
+    pub fn nonce_gaps(&self, wallet_id: &str) -> Vec<u64> {
+        self.nonce_accounts
+            .get(wallet_id)
+            .map(|account| account.gaps().collect())
+            .unwrap_or_default()
+    }
+
+    /// Sets how long a broadcast may stay unmined before it is reported as
+    /// stuck
+    pub fn set_stuck_after(&mut self, secs: u64) {
+        self.stuck_after_secs = secs;
+    }
+
+    /// Reports broadcasts that have waited too long to be mined, emitting
+    /// [`CustodyEvent::TransactionStuck`] once for each
+    ///
+    /// Called by [`track_broadcasts`](Self::track_broadcasts).
+    ///
+    /// # Returns
+    /// The newly stuck nonces
+    pub fn check_stuck_transactions(&mut self) -> Vec<PendingNonce> {
+        let now = self.current_timestamp();
+        let mut stuck = Vec::new();
+        for (wallet_id, account) in self.nonce_accounts.iter_mut() {
+            for pending in account.pending.values_mut() {
+                let (Some(tx_hash), Some(broadcast_at)) = (&pending.tx_hash, pending.broadcast_at)
+                else {
+                    continue;
+                };
+                let waited = now.as_unix().saturating_sub(broadcast_at.as_unix());
+                if pending.stuck || waited < self.stuck_after_secs {
+                    continue;
+                }
+                pending.stuck = true;
+                stuck.push((wallet_id.clone(), tx_hash.clone(), pending.clone(), waited));
+            }
+        }
+        stuck
+            .into_iter()
+            .map(|(wallet_id, tx_hash, pending, pending_secs)| {
+                self.emit(CustodyEvent::TransactionStuck {
+                    wallet_id,
+                    tx_hash,
+                    nonce: pending.nonce,
+                    pending_secs,
+                });
+                pending
+            })
+            .collect()
+    }
+
+    /// Sends a broadcast withdrawal again under its nonce, for a signer
+    /// that signs the replacement with a higher fee
+    ///
+    /// Whichever of the payouts is mined books the withdrawal.
+    pub fn speed_up_broadcast(
+        &mut self,
+        id: u64,
+        signer: &dyn Signer,
+        broadcaster: &dyn Broadcaster,
+    ) -> Result<BroadcastTx, CustodyError> {
+        self.replace_broadcast(id, false, signer, broadcaster)
+    }
+
+    /// Sends an empty transaction to the wallet itself under a broadcast
+    /// withdrawal's nonce
+    ///
+    /// If the cancellation is mined the withdrawal is abandoned and its
+    /// funds released; if the payout wins the race it is booked as usual.
+    pub fn cancel_broadcast(
+        &mut self,
+        id: u64,
+        signer: &dyn Signer,
+        broadcaster: &dyn Broadcaster,
+    ) -> Result<BroadcastTx, CustodyError> {
+        self.replace_broadcast(id, true, signer, broadcaster)
+    }
+
+    fn replace_broadcast(
+        &mut self,
+        id: u64,
+        cancel: bool,
+        signer: &dyn Signer,
+        broadcaster: &dyn Broadcaster,
+    ) -> Result<BroadcastTx, CustodyError> {
+        let sent = self
+            .broadcasts
+            .get(&id)
+            .filter(|sent| sent.status == BroadcastStatus::Broadcast)
+            .ok_or(CustodyError::BroadcastNotFound(id))?
+            .clone();
+        let nonce = match sent.nonce {
+            Some(nonce) if sent.cancellation.is_none() => nonce,
+            _ => return Err(CustodyError::NotReplaceable(id)),
+        };
+        let (destination, amount) = if cancel {
+            (self.wallets[&sent.wallet_id].address.clone(), Amount::ZERO)
+        } else {
+            (sent.destination.clone(), sent.amount)
+        };
+        let replacement = UnsignedTx {
+            id: self.allocate_operation_id(),
+            wallet_id: sent.wallet_id.clone(),
+            asset: sent.asset.clone(),
+            destination,
+            amount,
+            prepared_at: self.current_timestamp(),
+            nonce: Some(nonce),
+            fee: self.estimate_fee(&sent.asset)?,
+        };
+        let signature = signer.sign(&replacement.payload())?;
+        if signature.bytes.is_empty() {
+            return Err(CustodyError::SignatureRejected(
+                "signature is empty".to_string(),
+            ));
+        }
+        let tx_hash = broadcaster
+            .broadcast(&signature.bytes)
+            .map_err(CustodyError::NodeError)?;
+        self.nonce_broadcast(&sent.wallet_id, nonce, &tx_hash);
+
+        let sent = self.broadcasts.get_mut(&id).expect("checked above");
+        if cancel {
+            sent.cancellation = Some(tx_hash);
+        } else {
+            let replaced = std::mem::replace(&mut sent.tx_hash, tx_hash);
+            sent.replaced.push(replaced);
+        }
+        Ok(sent.clone())
+    }
+
+    /// Hands out the wallet's next nonce, filling the lowest gap first
+    pub(crate) fn assign_nonce(&mut self, wallet_id: &str, withdrawal_id: u64) -> u64 {
+        let assigned_at = self.current_timestamp();
+        let account = self
+            .nonce_accounts
+            .entry(wallet_id.to_string())
+            .or_default();
+        let nonce = account.gaps().next().unwrap_or_else(|| {
+            account
+                .pending
+                .keys()
+                .next_back()
+                .map_or(account.next, |highest| highest + 1)
+        });
+        account.pending.insert(
+            nonce,
+            PendingNonce {
+                nonce,
+                withdrawal_id,
+                assigned_at,
+                tx_hash: None,
+                broadcast_at: None,
+                replaced: Vec::new(),
+                stuck: false,
+            },
+        );
+        nonce
+    }
+
+    /// Frees a nonce whose withdrawal was given up
+    pub(crate) fn release_nonce(&mut self, wallet_id: &str, nonce: u64) {
+        if let Some(account) = self.nonce_accounts.get_mut(wallet_id) {
+            account.pending.remove(&nonce);
+        }
+    }
+
+    /// Records a transaction sent with a pending nonce
+    pub(crate) fn nonce_broadcast(&mut self, wallet_id: &str, nonce: u64, tx_hash: &str) {
+        let now = self.current_timestamp();
+        let pending = self
+            .nonce_accounts
+            .get_mut(wallet_id)
+            .and_then(|account| account.pending.get_mut(&nonce));
+        if let Some(pending) = pending {
+            if let Some(previous) = pending.tx_hash.replace(tx_hash.to_string()) {
+                pending.replaced.push(previous);
+            }
+            pending.broadcast_at = Some(now);
+            pending.stuck = false;
+        }
+    }
+
+    /// Records that a transaction with `nonce` was mined
+    pub(crate) fn nonce_mined(&mut self, wallet_id: &str, nonce: u64) {
+        if let Some(account) = self.nonce_accounts.get_mut(wallet_id) {
+            account.pending.remove(&nonce);
+            account.next = account.next.max(nonce + 1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::time::ManualClock;
+    use crate::{CallbackSigner, EventListener, WalletType};
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    /// Names transactions after their signature and mines what tests say
+    #[derive(Default)]
+    struct FakeChain {
+        mined: Mutex<HashMap<String, u32>>,
+    }
+
+    impl Broadcaster for FakeChain {
+        fn broadcast(&self, raw_tx: &[u8]) -> Result<TxHash, String> {
+            Ok(String::from_utf8_lossy(raw_tx).into_owned())
+        }
+
+        fn confirmations(&self, tx_hash: &str) -> Result<u32, String> {
+            Ok(self
+                .mined
+                .lock()
+                .unwrap()
+                .get(tx_hash)
+                .copied()
+                .unwrap_or(0))
+        }
+    }
+
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<CustodyEvent>>);
+
+    impl EventListener for Recorder {
+        fn on_event(&self, event: &CustodyEvent) {
+            self.0.lock().unwrap().push(event.clone());
+        }
+    }
+
+    /// Signs with the payload's nonce and amount lines, so replacements
+    /// get their own hashes
+    fn signer() -> CallbackSigner {
+        CallbackSigner::new("k", |payload| {
+            let text = String::from_utf8_lossy(payload);
+            let field = |name: &str| {
+                text.lines()
+                    .find_map(|line| line.strip_prefix(name))
+                    .unwrap_or("")
+                    .to_string()
+            };
+            Ok(format!("n{}-{}", field("nonce="), field("amount=")).into_bytes())
+        })
+    }
+
+    fn setup() -> (CustodySystem, Arc<ManualClock>) {
+        let clock = Arc::new(ManualClock::new(Timestamp::from_unix(1_700_000_000)));
+        let mut system = CustodySystem::new();
+        system.set_clock(clock.clone());
+        system
+            .create_wallet_with_asset(
+                "eth".to_string(),
+                "0xhot".to_string(),
+                WalletType::Hot,
+                Asset::Eth,
+            )
+            .unwrap();
+        system.deposit("eth", amount!(10)).unwrap();
+        system.set_confirmation_policy(Asset::Eth, 1);
+        (system, clock)
+    }
+
+    #[test]
+    fn test_concurrent_withdrawals_get_distinct_nonces() {
+        let (mut system, _) = setup();
+        system.sync_nonce("eth", 7).unwrap();
+        let a = system.prepare_withdrawal("eth", "0xa", amount!(1)).unwrap();
+        let b = system.prepare_withdrawal("eth", "0xb", amount!(1)).unwrap();
+        let c = system.prepare_withdrawal("eth", "0xc", amount!(1)).unwrap();
+        assert_eq!([a.nonce, b.nonce, c.nonce], [Some(7), Some(8), Some(9)]);
+        assert!(String::from_utf8(b.payload())
+            .unwrap()
+            .ends_with("nonce=8\n"));
+
+        system.cancel_unsigned(b.id);
+        assert_eq!(system.nonce_gaps("eth"), [8]);
+        let d = system.prepare_withdrawal("eth", "0xd", amount!(1)).unwrap();
+        assert_eq!(d.nonce, Some(8));
+        assert!(system.nonce_gaps("eth").is_empty());
+
+        system.sync_nonce("eth", 9).unwrap();
+        let pending: Vec<u64> = system
+            .pending_nonces("eth")
+            .iter()
+            .map(|p| p.nonce)
+            .collect();
+        assert_eq!(pending, [9]);
+        assert_eq!(
+            system.sync_nonce("nope", 1),
+            Err(CustodyError::WalletNotFound("nope".to_string()))
+        );
+
+        system
+            .create_wallet("btc".to_string(), "bc1q".to_string(), WalletType::Hot)
+            .unwrap();
+        system.deposit("btc", amount!(1)).unwrap();
+        let btc = system
+            .prepare_withdrawal("btc", "bc1qdest", amount!(1))
+            .unwrap();
+        assert_eq!(btc.nonce, None);
+    }
+
+    #[test]
+    fn test_stuck_transaction_replaced() {
+        let (mut system, clock) = setup();
+        let recorder = Arc::new(Recorder::default());
+        system.subscribe(recorder.clone());
+        system.set_stuck_after(600);
+        let chain = FakeChain::default();
+
+        let sent = system
+            .broadcast_withdrawal("eth", "0xdest", amount!(2), &signer(), &chain)
+            .unwrap();
+        assert_eq!(sent.tx_hash, "n0-2");
+        clock.advance(601);
+        assert!(system.track_broadcasts(&chain).unwrap().is_empty());
+        assert!(system.pending_nonces("eth")[0].stuck);
+        assert!(recorder
+            .0
+            .lock()
+            .unwrap()
+            .contains(&CustodyEvent::TransactionStuck {
+                wallet_id: "eth".to_string(),
+                tx_hash: "n0-2".to_string(),
+                nonce: 0,
+                pending_secs: 601,
+            }));
+        assert!(system.check_stuck_transactions().is_empty());
+
+        let faster = CallbackSigner::new("k", |_| Ok(b"n0-2-fast".to_vec()));
+        let sent = system.speed_up_broadcast(sent.id, &faster, &chain).unwrap();
+        assert_eq!(
+            (sent.tx_hash.as_str(), sent.replaced.as_slice()),
+            ("n0-2-fast", &["n0-2".to_string()][..])
+        );
+        assert!(!system.pending_nonces("eth")[0].stuck);
+
+        // The original payout is mined after all
+        chain.mined.lock().unwrap().insert("n0-2".to_string(), 1);
+        assert_eq!(system.track_broadcasts(&chain).unwrap(), [sent.id]);
+        let booked = system.get_broadcast(sent.id).unwrap();
+        assert_eq!(booked.tx_hash, "n0-2");
+        assert_eq!(system.get_wallet("eth").unwrap().balance, amount!(8));
+        assert!(system.pending_nonces("eth").is_empty());
+    }
+
+    #[test]
+    fn test_cancelled_broadcast_releases_funds() {
+        let (mut system, _) = setup();
+        let chain = FakeChain::default();
+        let sent = system
+            .broadcast_withdrawal("eth", "0xdest", amount!(2), &signer(), &chain)
+            .unwrap();
+        let cancelled = system.cancel_broadcast(sent.id, &signer(), &chain).unwrap();
+        assert_eq!(cancelled.cancellation.as_deref(), Some("n0-0"));
+        assert_eq!(
+            system.cancel_broadcast(sent.id, &signer(), &chain),
+            Err(CustodyError::NotReplaceable(sent.id))
+        );
+
+        chain.mined.lock().unwrap().insert("n0-0".to_string(), 1);
+        assert!(system.track_broadcasts(&chain).unwrap().is_empty());
+        assert_eq!(
+            system.get_broadcast(sent.id).unwrap().status,
+            BroadcastStatus::Abandoned
+        );
+        assert_eq!(system.available_balance("eth"), Some(amount!(10)));
+        let next = system
+            .prepare_withdrawal("eth", "0xdest", amount!(1))
+            .unwrap();
+        assert_eq!(next.nonce, Some(1));
+    }
+}
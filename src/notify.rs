@@ -0,0 +1,351 @@
+//! Templated customer notifications.
+//!
+//! [`NotificationDispatcher`] listens to custody events, looks up who owns
+//! the affected wallet and how they want to be contacted, renders the
+//! matching template in the customer's locale, and hands the result to a
+//! [`Notifier`] for delivery.
+//!
+//! Templates use named placeholders: `{amount}` (formatted with the asset's
+//! conventions), `{wallet}`, and `{reason}`. Built-in templates come from
+//! the [`i18n`](crate::i18n) catalogs and can be overridden per event kind
+//! and locale.
+
+use crate::format::AmountFormatter;
+use crate::i18n::{self, Locale};
+use crate::{CustodyEvent, EventListener};
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{Arc, Mutex};
+
+/// Maximum length of a single SMS message
+const SMS_MAX_CHARS: usize = 160;
+
+/// Delivery channel
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Channel {
+    Email,
+    Sms,
+}
+
+/// A rendered message ready for delivery
+#[derive(Debug, Clone, PartialEq)]
+pub struct Notification {
+    pub channel: Channel,
+    /// Email address or phone number
+    pub to: String,
+    /// Subject line; `None` for SMS
+    pub subject: Option<String>,
+    pub body: String,
+}
+
+/// Delivers rendered notifications (SMTP relay, SMS gateway, ...)
+pub trait Notifier: Send + Sync {
+    fn send(&self, notification: &Notification) -> Result<(), String>;
+}
+
+/// How a customer wants to be contacted
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ContactPreferences {
+    pub email: Option<String>,
+    pub phone: Option<String>,
+    /// Channels the customer opted into
+    pub channels: BTreeSet<Channel>,
+    pub locale: Locale,
+}
+
+/// Subject and body template for one event kind
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    pub subject: String,
+    pub body: String,
+}
+
+/// Renders custody events into notifications
+#[derive(Debug, Clone, Default)]
+pub struct NotificationTemplates {
+    overrides: HashMap<(&'static str, Locale), Template>,
+}
+
+impl NotificationTemplates {
+    /// Creates a template set using the built-in catalog texts
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Overrides the template for an event kind (see
+    /// [`CustodyEvent::kind`]) in one locale
+    pub fn set(&mut self, kind: &'static str, locale: Locale, template: Template) {
+        self.overrides.insert((kind, locale), template);
+    }
+
+    /// Returns the template used for an event kind in a locale
+    pub fn template(&self, kind: &'static str, locale: Locale) -> Template {
+        if let Some(template) = self.overrides.get(&(kind, locale)) {
+            return template.clone();
+        }
+        Template {
+            subject: i18n::lookup(locale, &format!("notify.{}.subject", kind)).to_string(),
+            body: i18n::lookup(locale, &format!("notify.{}.body", kind)).to_string(),
+        }
+    }
+
+    /// Renders the notification for `event` on `channel`
+    pub fn render(
+        &self,
+        event: &CustodyEvent,
+        channel: Channel,
+        locale: Locale,
+        to: &str,
+    ) -> Notification {
+        let template = self.template(event.kind(), locale);
+        let formatter = AmountFormatter::for_locale(locale);
+        let (amount, reason) = match event {
+            CustodyEvent::DepositReceived { amount, asset, .. }
+            | CustodyEvent::WithdrawalApproved { amount, asset, .. }
+            | CustodyEvent::WithdrawalSettled { amount, asset, .. }
+            | CustodyEvent::TransferCompleted { amount, asset, .. }
+            | CustodyEvent::DepositFinalized { amount, asset, .. } => {
+                (formatter.format(*amount, asset), String::new())
+            }
+            CustodyEvent::WalletFrozen { reason, .. }
+            | CustodyEvent::OperationFlagged { reason, .. } => (String::new(), reason.clone()),
+            CustodyEvent::TransactionStuck { .. } => (String::new(), String::new()),
+        };
+        let fill = |text: &str| {
+            text.replace("{amount}", &amount)
+                .replace("{wallet}", event.wallet_id())
+                .replace("{reason}", &reason)
+        };
+
+        match channel {
+            Channel::Email => Notification {
+                channel,
+                to: to.to_string(),
+                subject: Some(fill(&template.subject)),
+                body: fill(&template.body),
+            },
+            Channel::Sms => {
+                let body: String = format!("SecureVault: {}", fill(&template.body))
+                    .chars()
+                    .take(SMS_MAX_CHARS)
+                    .collect();
+                Notification {
+                    channel,
+                    to: to.to_string(),
+                    subject: None,
+                    body,
+                }
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct Directory {
+    customers: HashMap<String, ContactPreferences>,
+    wallet_owners: HashMap<String, String>,
+}
+
+/// Event listener that notifies wallet owners through their preferred
+/// channels
+pub struct NotificationDispatcher {
+    notifier: Arc<dyn Notifier>,
+    templates: NotificationTemplates,
+    directory: Mutex<Directory>,
+    failures: Mutex<Vec<(Notification, String)>>,
+}
+
+impl NotificationDispatcher {
+    /// Creates a dispatcher delivering through `notifier`
+    pub fn new(notifier: Arc<dyn Notifier>, templates: NotificationTemplates) -> Self {
+        Self {
+            notifier,
+            templates,
+            directory: Mutex::new(Directory::default()),
+            failures: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Sets a customer's contact preferences
+    pub fn set_preferences(&self, customer_id: &str, preferences: ContactPreferences) {
+        let mut directory = self.directory.lock().expect("directory lock poisoned");
+        directory
+            .customers
+            .insert(customer_id.to_string(), preferences);
+    }
+
+    /// Records that `customer_id` should be notified about `wallet_id`
+    pub fn assign_wallet(&self, wallet_id: &str, customer_id: &str) {
+        let mut directory = self.directory.lock().expect("directory lock poisoned");
+        directory
+            .wallet_owners
+            .insert(wallet_id.to_string(), customer_id.to_string());
+    }
+
+    /// Returns notifications the notifier failed to deliver, with the error
+    pub fn failures(&self) -> Vec<(Notification, String)> {
+        self.failures
+            .lock()
+            .expect("failures lock poisoned")
+            .clone()
+    }
+
+    fn notifications_for(&self, event: &CustodyEvent) -> Vec<Notification> {
+        // Owners hear about a transfer through its two legs and about an
+        // on-chain deposit when it is received, and are never told that
+        // compliance is reviewing them; stuck transactions are for operators
+        if matches!(
+            event,
+            CustodyEvent::TransferCompleted { .. }
+                | CustodyEvent::DepositFinalized { .. }
+                | CustodyEvent::OperationFlagged { .. }
+                | CustodyEvent::TransactionStuck { .. }
+        ) {
+            return Vec::new();
+        }
+        let directory = self.directory.lock().expect("directory lock poisoned");
+        let preferences = match directory
+            .wallet_owners
+            .get(event.wallet_id())
+            .and_then(|customer| directory.customers.get(customer))
+        {
+            Some(preferences) => preferences,
+            None => return Vec::new(),
+        };
+
+        preferences
+            .channels
+            .iter()
+            .filter_map(|channel| {
+                let to = match channel {
+                    Channel::Email => preferences.email.as_deref(),
+                    Channel::Sms => preferences.phone.as_deref(),
+                }?;
+                Some(
+                    self.templates
+                        .render(event, *channel, preferences.locale, to),
+                )
+            })
+            .collect()
+    }
+}
+
+impl EventListener for NotificationDispatcher {
+    fn on_event(&self, event: &CustodyEvent) {
+        for notification in self.notifications_for(event) {
+            if let Err(err) = self.notifier.send(&notification) {
+                self.failures
+                    .lock()
+                    .expect("failures lock poisoned")
+                    .push((notification, err));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Asset, CustodySystem, WalletType};
+
+    #[derive(Default)]
+    struct Outbox(Mutex<Vec<Notification>>);
+
+    impl Notifier for Outbox {
+        fn send(&self, notification: &Notification) -> Result<(), String> {
+            if notification.to.is_empty() {
+                return Err("no recipient".to_string());
+            }
+            self.0.lock().unwrap().push(notification.clone());
+            Ok(())
+        }
+    }
+
+    fn deposit_event() -> CustodyEvent {
+        CustodyEvent::DepositReceived {
+            wallet_id: "w1".to_string(),
+            amount: amount!(1.5),
+            asset: Asset::Btc,
+        }
+    }
+
+    #[test]
+    fn test_render_email_in_locale() {
+        let templates = NotificationTemplates::new();
+        let email = templates.render(&deposit_event(), Channel::Email, Locale::PtBr, "a@b.c");
+        assert_eq!(email.subject.as_deref(), Some("Depósito recebido"));
+        assert_eq!(email.body, "Recebemos 1,50000000 BTC na carteira w1.");
+    }
+
+    #[test]
+    fn test_render_sms_is_short() {
+        let mut templates = NotificationTemplates::new();
+        templates.set(
+            "deposit_received",
+            Locale::En,
+            Template {
+                subject: String::new(),
+                body: "x".repeat(500),
+            },
+        );
+        let sms = templates.render(&deposit_event(), Channel::Sms, Locale::En, "+15550100");
+        assert!(sms.subject.is_none());
+        assert_eq!(sms.body.chars().count(), SMS_MAX_CHARS);
+    }
+
+    #[test]
+    fn test_template_override() {
+        let mut templates = NotificationTemplates::new();
+        templates.set(
+            "wallet_frozen",
+            Locale::En,
+            Template {
+                subject: "Action needed on {wallet}".to_string(),
+                body: "Frozen because: {reason}".to_string(),
+            },
+        );
+        let event = CustodyEvent::WalletFrozen {
+            wallet_id: "w9".to_string(),
+            reason: "court order".to_string(),
+        };
+        let email = templates.render(&event, Channel::Email, Locale::En, "a@b.c");
+        assert_eq!(email.subject.as_deref(), Some("Action needed on w9"));
+        assert_eq!(email.body, "Frozen because: court order");
+    }
+
+    #[test]
+    fn test_dispatcher_follows_preferences() {
+        let outbox = Arc::new(Outbox::default());
+        let dispatcher = Arc::new(NotificationDispatcher::new(
+            outbox.clone(),
+            NotificationTemplates::new(),
+        ));
+        dispatcher.set_preferences(
+            "cust_1",
+            ContactPreferences {
+                email: Some("ana@example.com".to_string()),
+                phone: None,
+                channels: BTreeSet::from([Channel::Email, Channel::Sms]),
+                locale: Locale::Es,
+            },
+        );
+        dispatcher.assign_wallet("w1", "cust_1");
+
+        let mut system = CustodySystem::new();
+        system.subscribe(dispatcher.clone());
+        system
+            .create_wallet("w1".to_string(), "0x1".to_string(), WalletType::Hot)
+            .unwrap();
+        system
+            .create_wallet("w2".to_string(), "0x2".to_string(), WalletType::Hot)
+            .unwrap();
+        system.deposit("w1", amount!(2.0)).unwrap();
+        system.deposit("w2", amount!(2.0)).unwrap();
+
+        let sent = outbox.0.lock().unwrap();
+        // Only the email channel has an address, and w2 has no owner
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].to, "ana@example.com");
+        assert_eq!(sent[0].subject.as_deref(), Some("Depósito recibido"));
+        assert!(dispatcher.failures().is_empty());
+    }
+}
@@ -0,0 +1,351 @@
+//! File-based signing of cold wallet withdrawals on an air-gapped machine.
+//!
+//! [`CustodySystem::export_unsigned_operations`] writes every prepared
+//! withdrawal from a cold wallet (see
+//! [`CustodySystem::prepare_withdrawal`]) to an [`UnsignedBatch`], a small
+//! JSON file to carry to the offline machine on removable media. There
+//! [`UnsignedBatch::sign`] signs each operation, and the resulting
+//! [`SignedBatch`] is carried back and handed to
+//! [`CustodySystem::import_signed_operations`].
+//!
+//! Import checks each signature against the key registered for its wallet
+//! with [`CustodySystem::register_offline_key`]: the key ids must match,
+//! and with the `signing` feature a key id that is a secp256k1 public key
+//! (as reported by `SoftwareSigner`) has the ECDSA signature verified over
+//! the operation's payload. Operations that pass are booked as
+//! [`CustodySystem::submit_signed`] would.
+
+use crate::time::Timestamp;
+use crate::{CustodyError, CustodySystem, Signature, Signer, UnsignedTx, WalletType};
+use serde::{Deserialize, Serialize};
+
+/// Format version of batch files
+pub const OFFLINE_BATCH_VERSION: u32 = 1;
+
+/// Withdrawals awaiting an offline signature
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UnsignedBatch {
+    pub version: u32,
+    pub exported_at: Timestamp,
+    pub operations: Vec<UnsignedTx>,
+}
+
+/// One signed withdrawal; the signature is hex-encoded
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignedOperation {
+    pub operation: UnsignedTx,
+    pub key_id: String,
+    pub signature: String,
+}
+
+/// Signed withdrawals coming back from the offline machine
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignedBatch {
+    pub version: u32,
+    pub operations: Vec<SignedOperation>,
+}
+
+impl UnsignedBatch {
+    /// Serializes the batch as compact JSON
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("batch serialization cannot fail")
+    }
+
+    /// Parses a batch written by [`UnsignedBatch::to_json`]
+    pub fn from_json(json: &str) -> Result<Self, CustodyError> {
+        let batch: Self = serde_json::from_str(json)
+            .map_err(|err| CustodyError::InvalidBatchFile(err.to_string()))?;
+        check_version(batch.version)?;
+        Ok(batch)
+    }
+
+    /// Signs every operation with `signer`, on the offline machine
+    pub fn sign(&self, signer: &dyn Signer) -> Result<SignedBatch, CustodyError> {
+        let operations = self
+            .operations
+            .iter()
+            .map(|operation| {
+                let signature = signer.sign(&operation.payload())?;
+                Ok(SignedOperation {
+                    operation: operation.clone(),
+                    key_id: signature.key_id,
+                    signature: to_hex(&signature.bytes),
+                })
+            })
+            .collect::<Result<_, CustodyError>>()?;
+        Ok(SignedBatch {
+            version: OFFLINE_BATCH_VERSION,
+            operations,
+        })
+    }
+}
+
+impl SignedBatch {
+    /// Serializes the batch as compact JSON
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("batch serialization cannot fail")
+    }
+
+    /// Parses a batch written by [`SignedBatch::to_json`]
+    pub fn from_json(json: &str) -> Result<Self, CustodyError> {
+        let batch: Self = serde_json::from_str(json)
+            .map_err(|err| CustodyError::InvalidBatchFile(err.to_string()))?;
+        check_version(batch.version)?;
+        Ok(batch)
+    }
+}
+
+impl CustodySystem {
+    /// Registers the key whose signatures are accepted for a wallet's
+    /// imported operations
+    pub fn register_offline_key(
+        &mut self,
+        wallet_id: &str,
+        key_id: &str,
+    ) -> Result<(), CustodyError> {
+        if !self.wallet_exists(wallet_id) {
+            return Err(CustodyError::WalletNotFound(wallet_id.to_string()));
+        }
+        self.offline_keys
+            .insert(wallet_id.to_string(), key_id.to_string());
+        Ok(())
+    }
+
+    /// Returns the key registered for a wallet's imported operations
+    pub fn offline_key(&self, wallet_id: &str) -> Option<&str> {
+        self.offline_keys.get(wallet_id).map(String::as_str)
+    }
+
+    /// Collects the prepared withdrawals from cold wallets, oldest first,
+    /// for signing offline
+    ///
+    /// # Example
+    /// ```
+    /// use securevault::{amount, CallbackSigner, CustodySystem, UnsignedBatch, WalletType};
+    /// let mut system = CustodySystem::new();
+    /// system.create_wallet("cold".to_string(), "0x1".to_string(), WalletType::Cold).unwrap();
+    /// system.deposit("cold", amount!(10)).unwrap();
+    /// system.register_offline_key("cold", "offline-1").unwrap();
+    /// system.prepare_withdrawal("cold", "0xdest", amount!(4)).unwrap();
+    ///
+    /// let file = system.export_unsigned_operations().to_json();
+    /// // ... carried to the offline machine ...
+    /// let signer = CallbackSigner::new("offline-1", |payload| Ok(payload[..8].to_vec()));
+    /// let signed = UnsignedBatch::from_json(&file).unwrap().sign(&signer).unwrap().to_json();
+    /// // ... and back
+    /// let results = system.import_signed_operations(&signed).unwrap();
+    /// assert_eq!(results, [Ok(())]);
+    /// assert_eq!(system.get_wallet("cold").unwrap().balance, amount!(6));
+    /// ```
+    pub fn export_unsigned_operations(&self) -> UnsignedBatch {
+        let operations = self
+            .unsigned_txs
+            .values()
+            .filter(|tx| {
+                self.wallets
+                    .get(&tx.wallet_id)
+                    .is_some_and(|wallet| wallet.wallet_type == WalletType::Cold)
+            })
+            .cloned()
+            .collect();
+        UnsignedBatch {
+            version: OFFLINE_BATCH_VERSION,
+            exported_at: self.current_timestamp(),
+            operations,
+        }
+    }
+
+    /// Validates the signatures in a signed batch file and books the
+    /// withdrawals they authorize
+    ///
+    /// # Returns
+    /// The result of each operation, in file order. Operations are
+    /// independent: one that fails stays prepared and the others are still
+    /// booked.
+    ///
+    /// # Errors
+    /// [`CustodyError::InvalidBatchFile`] if the file cannot be read; no
+    /// operation is booked then
+    pub fn import_signed_operations(
+        &mut self,
+        json: &str,
+    ) -> Result<Vec<Result<(), CustodyError>>, CustodyError> {
+        let batch = SignedBatch::from_json(json)?;
+        Ok(batch
+            .operations
+            .iter()
+            .map(|signed| {
+                let signature = self.verify_offline_signature(signed)?;
+                self.submit_signed(&signed.operation, &signature)
+            })
+            .collect())
+    }
+
+    fn verify_offline_signature(
+        &self,
+        signed: &SignedOperation,
+    ) -> Result<Signature, CustodyError> {
+        let rejected = |reason: &str| Err(CustodyError::SignatureRejected(reason.to_string()));
+        let tx = &signed.operation;
+        let Some(key_id) = self.offline_key(&tx.wallet_id) else {
+            return rejected(&format!(
+                "no offline key registered for wallet '{}'",
+                tx.wallet_id
+            ));
+        };
+        if signed.key_id != key_id {
+            return rejected(&format!("signed by unexpected key '{}'", signed.key_id));
+        }
+        let Some(bytes) = from_hex(&signed.signature) else {
+            return rejected("signature is not hex");
+        };
+        #[cfg(feature = "signing")]
+        if let Some(public_key) = from_hex(key_id).filter(|key| key.len() == 33) {
+            if !crate::SoftwareSigner::verify(&public_key, &tx.payload(), &bytes) {
+                return rejected("signature does not verify");
+            }
+        }
+        Ok(Signature {
+            key_id: signed.key_id.clone(),
+            bytes,
+        })
+    }
+}
+
+fn check_version(version: u32) -> Result<(), CustodyError> {
+    if version == 0 || version > OFFLINE_BATCH_VERSION {
+        return Err(CustodyError::InvalidBatchFile(format!(
+            "unsupported version {}",
+            version
+        )));
+    }
+    Ok(())
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CallbackSigner;
+
+    fn system() -> CustodySystem {
+        let mut system = CustodySystem::new();
+        for (id, wallet_type) in [("hot", WalletType::Hot), ("cold", WalletType::Cold)] {
+            system
+                .create_wallet(id.to_string(), format!("0x{}", id), wallet_type)
+                .unwrap();
+            system.deposit(id, amount!(10)).unwrap();
+        }
+        system.register_offline_key("cold", "offline-1").unwrap();
+        system
+    }
+
+    fn signer(key_id: &str) -> CallbackSigner {
+        CallbackSigner::new(key_id, |payload| Ok(payload[..8].to_vec()))
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut system = system();
+        system
+            .prepare_withdrawal("cold", "0xdest", amount!(1))
+            .unwrap();
+        system
+            .prepare_withdrawal("hot", "0xdest", amount!(1))
+            .unwrap();
+        system
+            .prepare_withdrawal("cold", "0xdest", amount!(2))
+            .unwrap();
+
+        let exported = system.export_unsigned_operations();
+        assert_eq!(exported.operations.len(), 2);
+        let file = exported.to_json();
+        let signed = UnsignedBatch::from_json(&file)
+            .unwrap()
+            .sign(&signer("offline-1"))
+            .unwrap();
+        assert_eq!(
+            system.import_signed_operations(&signed.to_json()).unwrap(),
+            [Ok(()), Ok(())]
+        );
+        assert_eq!(system.get_wallet("cold").unwrap().balance, amount!(7));
+        // Replaying the file books nothing
+        assert!(matches!(
+            system.import_signed_operations(&signed.to_json()).unwrap()[..],
+            [
+                Err(CustodyError::UnsignedTxNotFound(_)),
+                Err(CustodyError::UnsignedTxNotFound(_))
+            ]
+        ));
+        assert_eq!(system.unsigned_transactions().len(), 1);
+    }
+
+    #[test]
+    fn test_bad_signatures_are_rejected() {
+        let mut system = system();
+        system
+            .prepare_withdrawal("cold", "0xdest", amount!(1))
+            .unwrap();
+        let batch = system.export_unsigned_operations();
+
+        let wrong_key = batch.sign(&signer("laptop")).unwrap();
+        assert!(matches!(
+            system
+                .import_signed_operations(&wrong_key.to_json())
+                .unwrap()[..],
+            [Err(CustodyError::SignatureRejected(_))]
+        ));
+        let mut tampered = batch.sign(&signer("offline-1")).unwrap();
+        tampered.operations[0].operation.amount = amount!(9);
+        assert!(matches!(
+            system
+                .import_signed_operations(&tampered.to_json())
+                .unwrap()[..],
+            [Err(CustodyError::SignatureRejected(_))]
+        ));
+        assert!(matches!(
+            system.import_signed_operations("{\"version\":2,\"operations\":[]}"),
+            Err(CustodyError::InvalidBatchFile(_))
+        ));
+        assert_eq!(system.get_wallet("cold").unwrap().balance, amount!(10));
+    }
+
+    #[cfg(feature = "signing")]
+    #[test]
+    fn test_ecdsa_signatures_are_verified() {
+        let mut system = system();
+        let key = crate::SoftwareSigner::from_bytes(&[7; 32]).unwrap();
+        system.register_offline_key("cold", &key.key_id()).unwrap();
+        system
+            .prepare_withdrawal("cold", "0xdest", amount!(1))
+            .unwrap();
+        let mut signed = system.export_unsigned_operations().sign(&key).unwrap();
+        let valid = signed.clone();
+        // Flip a bit of the signature
+        let last = signed.operations[0].signature.pop().unwrap();
+        signed.operations[0]
+            .signature
+            .push(if last == '0' { '1' } else { '0' });
+        assert!(matches!(
+            system.import_signed_operations(&signed.to_json()).unwrap()[..],
+            [Err(CustodyError::SignatureRejected(_))]
+        ));
+        assert_eq!(
+            system.import_signed_operations(&valid.to_json()).unwrap(),
+            [Ok(())]
+        );
+    }
+}
@@ -0,0 +1,342 @@
+//! OFX and QIF statement exports.
+//!
+//! Desktop and SaaS accounting packages import bank statements as OFX, and
+//! older ones as QIF. Both exports carry the same entries and balances as
+//! the [camt.053 statement](crate::iso20022), one per transaction, and
+//! identify the account by the wallet's
+//! [`StatementAccount`](crate::iso20022::StatementAccount).
+//!
+//! | OFX / QIF              | SecureVault                                  |
+//! |------------------------|----------------------------------------------|
+//! | `BANKID`               | statement account bank id, or `SECUREVAULT`  |
+//! | `ACCTID` / `!Account N`| statement account id                         |
+//! | `CURDEF`               | wallet asset symbol                          |
+//! | `FITID` / `N`          | transaction id                               |
+//! | `TRNTYPE`              | `DEP`, `DEBIT`, `XFER`, `INT` or `OTHER`     |
+//! | `TRNAMT` / `T`         | signed amount, credits positive              |
+//! | `NAME` / `P`           | counterparty wallet, for transfers           |
+//! | `MEMO` / `M`           | camt.053 remittance information              |
+//! | `LEDGERBAL`            | balance before `to`                          |
+
+use crate::iso20022::{escape, Camt053Statement, CreditDebit, StatementBalance};
+use crate::time::Timestamp;
+use crate::{CustodyError, CustodySystem};
+use std::fmt::Write;
+
+/// OFX `BANKID` of wallets whose statement account names no bank
+const DEFAULT_BANK_ID: &str = "SECUREVAULT";
+
+/// Longest `NAME` OFX allows
+const OFX_NAME_LEN: usize = 32;
+
+fn signed(amount: &str, credit_debit: CreditDebit) -> String {
+    match credit_debit {
+        CreditDebit::Credit => amount.to_string(),
+        CreditDebit::Debit => format!("-{}", amount),
+    }
+}
+
+fn balance_amount(balance: &StatementBalance) -> String {
+    signed(&balance.amount, balance.credit_debit)
+}
+
+fn ofx_datetime(at: Timestamp) -> String {
+    at.datetime().format("%Y%m%d%H%M%S").to_string()
+}
+
+fn ofx_type(transaction_code: &str) -> &'static str {
+    match transaction_code {
+        "DEPOSIT" => "DEP",
+        "WITHDRAWAL" => "DEBIT",
+        "TRANSFER" => "XFER",
+        "REWARD" => "INT",
+        _ => "OTHER",
+    }
+}
+
+/// Strips the characters QIF uses as line and record delimiters
+fn qif_text(value: &str) -> String {
+    value.replace(['\n', '\r', '^'], " ")
+}
+
+impl CustodySystem {
+    /// Builds an OFX 2.2 bank statement of `wallet_id` for transactions
+    /// with `from <= timestamp < to`
+    ///
+    /// # Example
+    /// ```
+    /// use securevault::iso20022::StatementAccount;
+    /// use securevault::{amount, CustodySystem, Timestamp, WalletType};
+    /// let mut system = CustodySystem::new();
+    /// system.create_wallet("w".to_string(), "0x1".to_string(), WalletType::Hot).unwrap();
+    /// system.set_statement_account("w", StatementAccount::new("1010-BTC")).unwrap();
+    /// system.deposit("w", amount!(1.5)).unwrap();
+    ///
+    /// let ofx = system.ofx_statement("w", Timestamp::EPOCH, Timestamp::from_unix(u64::MAX)).unwrap();
+    /// assert!(ofx.contains("<ACCTID>1010-BTC</ACCTID>"));
+    /// assert!(ofx.contains("<TRNAMT>1.50000000</TRNAMT>"));
+    /// ```
+    pub fn ofx_statement(
+        &self,
+        wallet_id: &str,
+        from: Timestamp,
+        to: Timestamp,
+    ) -> Result<String, CustodyError> {
+        let statement = self.camt053_statement(wallet_id, from, to)?;
+        let bank_id = self
+            .statement_account(wallet_id)
+            .bank_id
+            .unwrap_or_else(|| DEFAULT_BANK_ID.to_string());
+        let mut ofx = String::new();
+        let _ = write_ofx(&statement, &bank_id, &mut ofx);
+        Ok(ofx)
+    }
+
+    /// Builds a QIF bank statement of `wallet_id` for transactions with
+    /// `from <= timestamp < to`
+    ///
+    /// QIF has no balances; the opening balance is the first record,
+    /// dated `from`, as Quicken writes it.
+    pub fn qif_statement(
+        &self,
+        wallet_id: &str,
+        from: Timestamp,
+        to: Timestamp,
+    ) -> Result<String, CustodyError> {
+        let statement = self.camt053_statement(wallet_id, from, to)?;
+        let mut qif = String::new();
+        let _ = write_qif(&statement, &mut qif);
+        Ok(qif)
+    }
+}
+
+fn write_ofx(statement: &Camt053Statement, bank_id: &str, ofx: &mut String) -> std::fmt::Result {
+    let status = "<STATUS><CODE>0</CODE><SEVERITY>INFO</SEVERITY></STATUS>";
+    writeln!(ofx, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        ofx,
+        r#"<?OFX OFXHEADER="200" VERSION="220" SECURITY="NONE" OLDFILEUID="NONE" NEWFILEUID="NONE"?>"#
+    )?;
+    writeln!(ofx, "<OFX>")?;
+    writeln!(ofx, "  <SIGNONMSGSRSV1>")?;
+    writeln!(ofx, "    <SONRS>")?;
+    writeln!(ofx, "      {}", status)?;
+    writeln!(
+        ofx,
+        "      <DTSERVER>{}</DTSERVER>",
+        ofx_datetime(statement.created_at)
+    )?;
+    writeln!(ofx, "      <LANGUAGE>ENG</LANGUAGE>")?;
+    writeln!(ofx, "    </SONRS>")?;
+    writeln!(ofx, "  </SIGNONMSGSRSV1>")?;
+    writeln!(ofx, "  <BANKMSGSRSV1>")?;
+    writeln!(ofx, "    <STMTTRNRS>")?;
+    writeln!(
+        ofx,
+        "      <TRNUID>{}</TRNUID>",
+        escape(&statement.message_id)
+    )?;
+    writeln!(ofx, "      {}", status)?;
+    writeln!(ofx, "      <STMTRS>")?;
+    writeln!(
+        ofx,
+        "        <CURDEF>{}</CURDEF>",
+        escape(&statement.currency)
+    )?;
+    writeln!(ofx, "        <BANKACCTFROM>")?;
+    writeln!(ofx, "          <BANKID>{}</BANKID>", escape(bank_id))?;
+    writeln!(
+        ofx,
+        "          <ACCTID>{}</ACCTID>",
+        escape(&statement.account)
+    )?;
+    writeln!(ofx, "          <ACCTTYPE>CHECKING</ACCTTYPE>")?;
+    writeln!(ofx, "        </BANKACCTFROM>")?;
+    writeln!(ofx, "        <BANKTRANLIST>")?;
+    writeln!(
+        ofx,
+        "          <DTSTART>{}</DTSTART>",
+        ofx_datetime(statement.from)
+    )?;
+    writeln!(
+        ofx,
+        "          <DTEND>{}</DTEND>",
+        ofx_datetime(statement.to)
+    )?;
+    for entry in &statement.entries {
+        writeln!(ofx, "          <STMTTRN>")?;
+        writeln!(
+            ofx,
+            "            <TRNTYPE>{}</TRNTYPE>",
+            ofx_type(&entry.transaction_code)
+        )?;
+        writeln!(
+            ofx,
+            "            <DTPOSTED>{}</DTPOSTED>",
+            ofx_datetime(entry.booking_date)
+        )?;
+        writeln!(
+            ofx,
+            "            <TRNAMT>{}</TRNAMT>",
+            signed(&entry.amount, entry.credit_debit)
+        )?;
+        writeln!(
+            ofx,
+            "            <FITID>{}</FITID>",
+            escape(&entry.reference)
+        )?;
+        if let Some(counterparty) = &entry.counterparty {
+            let name: String = counterparty.chars().take(OFX_NAME_LEN).collect();
+            writeln!(ofx, "            <NAME>{}</NAME>", escape(&name))?;
+        }
+        writeln!(
+            ofx,
+            "            <MEMO>{}</MEMO>",
+            escape(&entry.remittance)
+        )?;
+        writeln!(ofx, "          </STMTTRN>")?;
+    }
+    writeln!(ofx, "        </BANKTRANLIST>")?;
+    writeln!(ofx, "        <LEDGERBAL>")?;
+    writeln!(
+        ofx,
+        "          <BALAMT>{}</BALAMT>",
+        balance_amount(&statement.closing_balance)
+    )?;
+    writeln!(
+        ofx,
+        "          <DTASOF>{}</DTASOF>",
+        ofx_datetime(statement.closing_balance.at)
+    )?;
+    writeln!(ofx, "        </LEDGERBAL>")?;
+    writeln!(ofx, "      </STMTRS>")?;
+    writeln!(ofx, "    </STMTTRNRS>")?;
+    writeln!(ofx, "  </BANKMSGSRSV1>")?;
+    writeln!(ofx, "</OFX>")
+}
+
+fn write_qif(statement: &Camt053Statement, qif: &mut String) -> std::fmt::Result {
+    let date = |at: Timestamp| at.datetime().format("%m/%d/%Y").to_string();
+    writeln!(qif, "!Account")?;
+    writeln!(qif, "N{}", qif_text(&statement.account))?;
+    writeln!(qif, "TBank")?;
+    writeln!(qif, "^")?;
+    writeln!(qif, "!Type:Bank")?;
+    writeln!(qif, "D{}", date(statement.from))?;
+    writeln!(qif, "T{}", balance_amount(&statement.opening_balance))?;
+    writeln!(qif, "POpening Balance")?;
+    writeln!(qif, "L[{}]", qif_text(&statement.account))?;
+    writeln!(qif, "^")?;
+    for entry in &statement.entries {
+        writeln!(qif, "D{}", date(entry.booking_date))?;
+        writeln!(qif, "T{}", signed(&entry.amount, entry.credit_debit))?;
+        writeln!(qif, "N{}", qif_text(&entry.reference))?;
+        if let Some(counterparty) = &entry.counterparty {
+            writeln!(qif, "P{}", qif_text(counterparty))?;
+        }
+        writeln!(qif, "M{}", qif_text(&entry.remittance))?;
+        writeln!(qif, "^")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::iso20022::StatementAccount;
+    use crate::time::ManualClock;
+    use crate::WalletType;
+    use std::sync::Arc;
+
+    const DAY: u64 = 86_400;
+
+    fn system() -> CustodySystem {
+        let clock = Arc::new(ManualClock::new(Timestamp::from_unix(1_700_000_000)));
+        let mut system = CustodySystem::new();
+        system.set_clock(clock.clone());
+        for id in ["a", "b&co"] {
+            system
+                .create_wallet(id.to_string(), id.to_string(), WalletType::Hot)
+                .unwrap();
+        }
+        system.deposit("a", amount!(10)).unwrap();
+        clock.advance(DAY);
+        system.transfer("a", "b&co", amount!(2.5)).unwrap();
+        clock.advance(DAY);
+        system.withdraw("a", amount!(1)).unwrap();
+        system
+    }
+
+    fn period() -> (Timestamp, Timestamp) {
+        (
+            Timestamp::from_unix(1_700_000_000 + DAY),
+            Timestamp::from_unix(1_700_000_000 + 3 * DAY),
+        )
+    }
+
+    #[test]
+    fn test_ofx_entries_and_balance() {
+        let mut system = system();
+        system
+            .set_statement_account(
+                "a",
+                StatementAccount::new("1010-BTC").with_bank_id("CUSTODY01"),
+            )
+            .unwrap();
+        let (from, to) = period();
+        let ofx = system.ofx_statement("a", from, to).unwrap();
+        assert!(ofx.contains("<BANKID>CUSTODY01</BANKID>"));
+        assert!(ofx.contains("<ACCTID>1010-BTC</ACCTID>"));
+        assert!(ofx.contains("<CURDEF>BTC</CURDEF>"));
+        assert_eq!(ofx.matches("<STMTTRN>").count(), 2);
+        assert!(ofx.contains("<TRNTYPE>XFER</TRNTYPE>"));
+        assert!(ofx.contains("<TRNAMT>-2.50000000</TRNAMT>"));
+        assert!(ofx.contains("<NAME>b&amp;co</NAME>"));
+        assert!(ofx.contains("<DTPOSTED>20231116221320</DTPOSTED>"));
+        assert!(ofx.contains("<BALAMT>6.50000000</BALAMT>"));
+
+        assert_eq!(
+            system.ofx_statement("zz", from, to),
+            Err(CustodyError::WalletNotFound("zz".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_qif_records() {
+        let system = system();
+        let (from, to) = period();
+        let qif = system.qif_statement("b&co", from, to).unwrap();
+        let records: Vec<&str> = qif.split("^\n").collect();
+        assert_eq!(records[0], "!Account\nNb&co\nTBank\n");
+        assert!(records[1].contains("T0.00000000\nPOpening Balance"));
+        assert_eq!(
+            records[2],
+            format!(
+                "D11/15/2023\nT2.50000000\nN{}\nPa\nMtransfer\n",
+                system.get_all_transactions()[1].id
+            )
+        );
+        assert_eq!(records.len(), 4);
+    }
+
+    #[test]
+    fn test_camt053_uses_the_statement_account() {
+        let mut system = system();
+        system
+            .set_statement_account(
+                "a",
+                StatementAccount::new("1010").with_iban("DE89370400440532013000"),
+            )
+            .unwrap();
+        let (from, to) = period();
+        let statement = system.camt053_statement("a", from, to).unwrap();
+        assert_eq!(statement.account, "1010");
+        assert!(statement
+            .to_xml()
+            .contains("<Id><IBAN>DE89370400440532013000</IBAN></Id>"));
+        assert_eq!(system.statement_account("b&co").account_id, "b&co");
+        assert!(system
+            .set_statement_account("zz", StatementAccount::new("x"))
+            .is_err());
+    }
+}
@@ -0,0 +1,354 @@
+//! Portfolio valuation in a quote currency.
+//!
+//! A [`PriceOracle`] quotes assets in a fiat currency.
+//! [`CustodySystem::get_total_value`] values every balance, including
+//! secondary holdings, with the attached oracle. [`StaticPriceOracle`]
+//! serves a fixed price table; `HttpPriceOracle` (feature `price-http`)
+//! fetches prices from an HTTP endpoint.
+
+use crate::{Amount, Asset, CustodyError, CustodySystem};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::Arc;
+
+/// Source of asset prices
+pub trait PriceOracle: fmt::Debug + Send + Sync {
+    /// Returns the price of one unit of `asset` in `quote`, or `None` if it
+    /// is not available
+    fn price(&self, asset: &Asset, quote: &Asset) -> Option<f64>;
+}
+
+/// A price oracle backed by a fixed price table
+#[derive(Debug, Clone, Default)]
+pub struct StaticPriceOracle {
+    prices: HashMap<(Asset, Asset), f64>,
+}
+
+impl StaticPriceOracle {
+    /// Creates an empty price table
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the price of one unit of `asset` in `quote`. The inverse
+    /// price is derived automatically unless set explicitly.
+    pub fn set_price(&mut self, asset: Asset, quote: Asset, price: f64) {
+        self.prices.insert((asset, quote), price);
+    }
+}
+
+impl PriceOracle for StaticPriceOracle {
+    fn price(&self, asset: &Asset, quote: &Asset) -> Option<f64> {
+        if asset == quote {
+            return Some(1.0);
+        }
+        if let Some(price) = self.prices.get(&(asset.clone(), quote.clone())) {
+            return Some(*price);
+        }
+        self.prices
+            .get(&(quote.clone(), asset.clone()))
+            .filter(|price| **price > 0.0)
+            .map(|price| 1.0 / price)
+    }
+}
+
+/// Value of the balances held in one asset
+#[derive(Debug, Clone, PartialEq)]
+pub struct AssetValue {
+    pub asset: Asset,
+    /// Balance summed over all wallets
+    pub amount: Amount,
+    pub price: f64,
+    pub value: f64,
+}
+
+/// Balances valued in a quote currency
+#[derive(Debug, Clone, PartialEq)]
+pub struct Valuation {
+    pub quote: Asset,
+    /// One entry per asset held, ordered by asset
+    pub assets: Vec<AssetValue>,
+    pub total: f64,
+}
+
+impl CustodySystem {
+    /// Attaches the oracle used by [`get_total_value`](Self::get_total_value)
+    pub fn set_price_oracle(&mut self, oracle: Arc<dyn PriceOracle>) {
+        self.price_oracle = Some(oracle);
+    }
+
+    /// Values every balance in `quote`, per asset
+    ///
+    /// Fails with [`CustodyError::RateUnavailable`] for the first asset the
+    /// oracle has no price for; without an oracle only balances already in
+    /// `quote` can be valued.
+    pub fn valuation(&self, quote: &Asset) -> Result<Valuation, CustodyError> {
+        let mut amounts: BTreeMap<&Asset, Amount> = BTreeMap::new();
+        for wallet in self.wallets.values() {
+            let balances =
+                std::iter::once((&wallet.asset, &wallet.balance)).chain(&wallet.holdings);
+            for (asset, balance) in balances {
+                let total = amounts.entry(asset).or_default();
+                *total = total
+                    .checked_add(*balance)
+                    .ok_or(CustodyError::AmountOverflow)?;
+            }
+        }
+
+        let mut assets = Vec::with_capacity(amounts.len());
+        for (asset, amount) in amounts {
+            let price = match &self.price_oracle {
+                _ if asset == quote => Some(1.0),
+                Some(oracle) => oracle.price(asset, quote),
+                None => None,
+            }
+            .filter(|price| price.is_finite() && *price >= 0.0)
+            .ok_or_else(|| CustodyError::RateUnavailable {
+                from: asset.symbol().to_string(),
+                to: quote.symbol().to_string(),
+            })?;
+            assets.push(AssetValue {
+                asset: asset.clone(),
+                amount,
+                price,
+                value: amount.to_f64() * price,
+            });
+        }
+        let total = assets.iter().map(|asset| asset.value).sum();
+        Ok(Valuation {
+            quote: quote.clone(),
+            assets,
+            total,
+        })
+    }
+
+    /// Returns the value of all balances in `quote`
+    ///
+    /// # Example
+    /// ```
+    /// use securevault::{amount, Asset, CustodySystem, StaticPriceOracle, WalletType};
+    /// use std::sync::Arc;
+    /// let mut system = CustodySystem::new();
+    /// system.create_wallet("btc".to_string(), "0x1".to_string(), WalletType::Hot).unwrap();
+    /// system.deposit("btc", amount!(2)).unwrap();
+    ///
+    /// let usd = Asset::Fiat("USD".to_string());
+    /// let mut oracle = StaticPriceOracle::new();
+    /// oracle.set_price(Asset::Btc, usd.clone(), 60_000.0);
+    /// system.set_price_oracle(Arc::new(oracle));
+    /// assert_eq!(system.get_total_value(&usd).unwrap(), 120_000.0);
+    /// ```
+    pub fn get_total_value(&self, quote: &Asset) -> Result<f64, CustodyError> {
+        self.valuation(quote).map(|valuation| valuation.total)
+    }
+}
+
+#[cfg(feature = "price-http")]
+mod http_oracle {
+    use super::PriceOracle;
+    use crate::{http, Asset};
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+    use std::time::{Duration, Instant};
+
+    /// Fetches prices from an HTTP endpoint (feature `price-http`)
+    ///
+    /// The URL template's `{asset}` and `{quote}` placeholders are replaced
+    /// with the asset symbols; the price is read from the JSON response at
+    /// a JSON pointer, `/price` by default. Prices are cached for the
+    /// configured time to live. Failed lookups return no price.
+    #[derive(Debug)]
+    pub struct HttpPriceOracle {
+        url_template: String,
+        pointer: String,
+        timeout: Duration,
+        ttl: Duration,
+        cache: Mutex<HashMap<(Asset, Asset), (Instant, f64)>>,
+    }
+
+    impl HttpPriceOracle {
+        /// Queries `url_template`, e.g.
+        /// `http://prices.internal/v1/price?base={asset}&quote={quote}`
+        pub fn new(url_template: &str) -> Self {
+            Self {
+                url_template: url_template.to_string(),
+                pointer: "/price".to_string(),
+                timeout: Duration::from_secs(5),
+                ttl: Duration::from_secs(60),
+                cache: Mutex::new(HashMap::new()),
+            }
+        }
+
+        /// Reads the price at this JSON pointer instead of `/price`
+        pub fn with_pointer(mut self, pointer: &str) -> Self {
+            self.pointer = pointer.to_string();
+            self
+        }
+
+        /// Sets the request timeout
+        pub fn with_timeout(mut self, timeout: Duration) -> Self {
+            self.timeout = timeout;
+            self
+        }
+
+        /// Sets how long fetched prices are reused
+        pub fn with_ttl(mut self, ttl: Duration) -> Self {
+            self.ttl = ttl;
+            self
+        }
+
+        fn fetch(&self, asset: &Asset, quote: &Asset) -> Option<f64> {
+            let url = self
+                .url_template
+                .replace("{asset}", asset.symbol())
+                .replace("{quote}", quote.symbol());
+            let response = http::send("GET", &url, &[], &[], self.timeout).ok()?;
+            if response.status != 200 {
+                return None;
+            }
+            let json: serde_json::Value = serde_json::from_slice(&response.body).ok()?;
+            let price = json.pointer(&self.pointer)?;
+            price
+                .as_f64()
+                .or_else(|| price.as_str().and_then(|price| price.parse().ok()))
+        }
+    }
+
+    impl PriceOracle for HttpPriceOracle {
+        fn price(&self, asset: &Asset, quote: &Asset) -> Option<f64> {
+            let key = (asset.clone(), quote.clone());
+            let mut cache = self.cache.lock().unwrap_or_else(|e| e.into_inner());
+            if let Some((fetched, price)) = cache.get(&key) {
+                if fetched.elapsed() < self.ttl {
+                    return Some(*price);
+                }
+            }
+            let price = self.fetch(asset, quote)?;
+            cache.insert(key, (Instant::now(), price));
+            Some(price)
+        }
+    }
+}
+
+#[cfg(feature = "price-http")]
+pub use http_oracle::HttpPriceOracle;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::WalletType;
+
+    fn usd() -> Asset {
+        Asset::Fiat("USD".to_string())
+    }
+
+    fn system() -> CustodySystem {
+        let mut system = CustodySystem::new();
+        system
+            .create_wallet("btc".to_string(), "0x1".to_string(), WalletType::Hot)
+            .unwrap();
+        system
+            .create_wallet_with_asset(
+                "eth".to_string(),
+                "0x2".to_string(),
+                WalletType::Hot,
+                Asset::Eth,
+            )
+            .unwrap();
+        system.deposit("btc", amount!(0.5)).unwrap();
+        system.deposit("eth", amount!(10)).unwrap();
+        system
+    }
+
+    #[test]
+    fn test_total_value_across_assets() {
+        let mut system = system();
+        let mut oracle = StaticPriceOracle::new();
+        oracle.set_price(Asset::Btc, usd(), 60_000.0);
+        oracle.set_price(Asset::Eth, usd(), 3_000.0);
+        system.set_price_oracle(Arc::new(oracle));
+
+        let valuation = system.valuation(&usd()).unwrap();
+        assert_eq!(valuation.total, 60_000.0);
+        let values: Vec<_> = valuation
+            .assets
+            .iter()
+            .map(|a| (a.asset.clone(), a.value))
+            .collect();
+        assert_eq!(values, [(Asset::Btc, 30_000.0), (Asset::Eth, 30_000.0)]);
+
+        // EUR is valued through the inverse of the EUR/USD quote
+        let eur = Asset::Fiat("EUR".to_string());
+        let mut oracle = StaticPriceOracle::new();
+        oracle.set_price(Asset::Btc, eur.clone(), 50_000.0);
+        oracle.set_price(Asset::Eth, eur.clone(), 2_500.0);
+        oracle.set_price(eur.clone(), usd(), 1.25);
+        system.set_price_oracle(Arc::new(oracle));
+        system.deposit_asset("btc", &eur, amount!(100)).unwrap();
+        assert_eq!(system.get_total_value(&eur).unwrap(), 50_100.0);
+        assert_eq!(
+            system.get_total_value(&usd()),
+            Err(CustodyError::RateUnavailable {
+                from: "BTC".to_string(),
+                to: "USD".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_missing_price_is_an_error() {
+        let mut system = system();
+        assert!(matches!(
+            system.get_total_value(&usd()),
+            Err(CustodyError::RateUnavailable { .. })
+        ));
+        let mut oracle = StaticPriceOracle::new();
+        oracle.set_price(Asset::Btc, usd(), 60_000.0);
+        system.set_price_oracle(Arc::new(oracle));
+        assert_eq!(
+            system.get_total_value(&usd()),
+            Err(CustodyError::RateUnavailable {
+                from: "ETH".to_string(),
+                to: "USD".to_string(),
+            })
+        );
+    }
+
+    #[cfg(feature = "price-http")]
+    #[test]
+    fn test_http_oracle_fetches_and_caches() {
+        use std::io::{BufRead, BufReader, Write as _};
+        use std::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let template = format!(
+            "http://{}/price?base={{asset}}&quote={{quote}}",
+            listener.local_addr().unwrap()
+        );
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut request_line = String::new();
+            BufReader::new(&stream)
+                .read_line(&mut request_line)
+                .unwrap();
+            let body = r#"{"data":{"amount":"61000.5"}}"#;
+            write!(
+                &stream,
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}",
+                body.len(),
+                body
+            )
+            .unwrap();
+            request_line
+        });
+
+        let oracle = HttpPriceOracle::new(&template).with_pointer("/data/amount");
+        assert_eq!(oracle.price(&Asset::Btc, &usd()), Some(61_000.5));
+        // Served from the cache; the server only answers once
+        assert_eq!(oracle.price(&Asset::Btc, &usd()), Some(61_000.5));
+        assert!(server
+            .join()
+            .unwrap()
+            .starts_with("GET /price?base=BTC&quote=USD HTTP/1.1"));
+    }
+}
@@ -0,0 +1,308 @@
+//! Accounting period close.
+//!
+//! [`CustodySystem::close_period`] draws a line under every transaction up
+//! to a moment in the past: it records per-wallet and system-wide
+//! statements for the period since the previous close, and from then on
+//! nothing at or before that moment may change. Deposits, withdrawals and
+//! transfers are refused with [`CustodyError::PeriodClosed`] while the
+//! clock still reads a closed moment, and transactions in a closed period
+//! can no longer be annotated, recategorized or reversed. Corrections are
+//! posted as new entries in the open period instead.
+
+use crate::time::Timestamp;
+use crate::{Amount, Asset, CustodyError, CustodySystem};
+use std::collections::BTreeMap;
+
+/// Movements of one asset over a period
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PeriodStatement {
+    /// Balance at the start of the period
+    pub opening: Amount,
+    /// Sum of the credits in the period
+    pub credits: Amount,
+    /// Sum of the debits in the period, as a positive amount
+    pub debits: Amount,
+    /// Balance at the end of the period
+    pub closing: Amount,
+    /// Number of postings in the period
+    pub entries: usize,
+}
+
+impl PeriodStatement {
+    fn post(&mut self, change: Amount, in_period: bool) {
+        if !in_period {
+            self.opening += change;
+        } else if change.is_negative() {
+            self.debits += -change;
+            self.entries += 1;
+        } else {
+            self.credits += change;
+            self.entries += 1;
+        }
+        self.closing += change;
+    }
+
+    fn add(&mut self, other: &PeriodStatement) {
+        self.opening += other.opening;
+        self.credits += other.credits;
+        self.debits += other.debits;
+        self.closing += other.closing;
+        self.entries += other.entries;
+    }
+}
+
+/// A closed accounting period and its statements
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClosedPeriod {
+    /// Sequence number, starting at 1
+    pub id: u64,
+    /// End of the previous period, exclusive; `None` for the first period
+    pub from: Option<Timestamp>,
+    /// End of this period, inclusive
+    pub until: Timestamp,
+    /// When the period was closed
+    pub closed_at: Timestamp,
+    /// Statements by wallet and asset, for every wallet with a balance or
+    /// movements
+    pub wallets: BTreeMap<String, BTreeMap<Asset, PeriodStatement>>,
+    /// Statements by asset across all wallets
+    pub totals: BTreeMap<Asset, PeriodStatement>,
+    /// Chain hash of the last transaction in the period, if any
+    pub chain_head: Option<String>,
+}
+
+impl ClosedPeriod {
+    /// Returns the statement of `wallet_id` for `asset`, if it had a balance
+    /// or movements
+    pub fn statement(&self, wallet_id: &str, asset: &Asset) -> Option<&PeriodStatement> {
+        self.wallets.get(wallet_id)?.get(asset)
+    }
+}
+
+impl CustodySystem {
+    /// Closes the accounting period ending at `until`, inclusive
+    ///
+    /// The period starts after the previous close, or at the beginning of
+    /// the log. The returned statements are kept in
+    /// [`closed_periods`](Self::closed_periods).
+    ///
+    /// # Errors
+    /// [`CustodyError::PeriodNotEnded`] unless `until` is in the past, and
+    /// [`CustodyError::PeriodClosed`] if it is not after the previous close
+    ///
+    /// # Example
+    /// ```
+    /// use securevault::time::{ManualClock, Timestamp};
+    /// use securevault::{amount, Asset, CustodySystem, WalletType};
+    /// use std::sync::Arc;
+    ///
+    /// let clock = Arc::new(ManualClock::new(Timestamp::from_unix(1_700_000_000)));
+    /// let mut system = CustodySystem::new();
+    /// system.set_clock(clock.clone());
+    /// system.create_wallet("w".to_string(), "0x1".to_string(), WalletType::Hot).unwrap();
+    /// system.deposit("w", amount!(10)).unwrap();
+    /// clock.advance(60);
+    ///
+    /// let period = system.close_period(Timestamp::from_unix(1_700_000_059)).unwrap();
+    /// let statement = period.statement("w", &Asset::default()).unwrap();
+    /// assert_eq!(statement.closing, amount!(10));
+    /// ```
+    pub fn close_period(&mut self, until: Timestamp) -> Result<ClosedPeriod, CustodyError> {
+        let closed_at = self.current_timestamp();
+        if until >= closed_at {
+            return Err(CustodyError::PeriodNotEnded { until });
+        }
+        let from = self.closed_until();
+        if let Some(closed_until) = from.filter(|closed_until| until <= *closed_until) {
+            return Err(CustodyError::PeriodClosed { closed_until });
+        }
+
+        let mut wallets: BTreeMap<String, BTreeMap<Asset, PeriodStatement>> = BTreeMap::new();
+        let mut chain_head = None;
+        for tx in self.transactions.iter().filter(|tx| tx.timestamp <= until) {
+            let in_period = from.is_none_or(|from| tx.timestamp > from);
+            for (wallet_id, change) in tx.postings() {
+                wallets
+                    .entry(wallet_id.to_string())
+                    .or_default()
+                    .entry(tx.asset.clone())
+                    .or_default()
+                    .post(change, in_period);
+            }
+            chain_head = Some(tx.chain_hash.clone());
+        }
+        for statements in wallets.values_mut() {
+            statements.retain(|_, s| s.entries > 0 || s.opening != Amount::ZERO);
+        }
+        wallets.retain(|_, statements| !statements.is_empty());
+
+        let mut totals: BTreeMap<Asset, PeriodStatement> = BTreeMap::new();
+        for (asset, statement) in wallets.values().flatten() {
+            totals.entry(asset.clone()).or_default().add(statement);
+        }
+
+        let period = ClosedPeriod {
+            id: self.closed_periods.len() as u64 + 1,
+            from,
+            until,
+            closed_at,
+            wallets,
+            totals,
+            chain_head,
+        };
+        self.closed_periods.push_back(period.clone());
+        Ok(period)
+    }
+
+    /// Returns the closed periods, oldest first
+    pub fn closed_periods(&self) -> &im::Vector<ClosedPeriod> {
+        &self.closed_periods
+    }
+
+    /// Returns the end of the latest closed period, if any
+    pub fn closed_until(&self) -> Option<Timestamp> {
+        self.closed_periods.last().map(|period| period.until)
+    }
+
+    /// Fails if `timestamp` falls in a closed period
+    pub(crate) fn check_period_open(&self, timestamp: Timestamp) -> Result<(), CustodyError> {
+        match self.closed_until() {
+            Some(closed_until) if timestamp <= closed_until => {
+                Err(CustodyError::PeriodClosed { closed_until })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Fails if the transaction at `index` in the log falls in a closed
+    /// period
+    pub(crate) fn check_transaction_open(&self, index: usize) -> Result<(), CustodyError> {
+        match self.transactions.get(index) {
+            Some(tx) => self.check_period_open(tx.timestamp),
+            None => Ok(()),
+        }
+    }
+
+    /// Checks that entries recorded now would not be backdated into a
+    /// closed period
+    pub(crate) fn period_blockers(&self) -> Option<CustodyError> {
+        self.check_period_open(self.current_timestamp()).err()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::time::ManualClock;
+    use crate::{TransactionMetadata, WalletType};
+    use std::sync::Arc;
+
+    const START: u64 = 1_700_000_000;
+
+    fn system() -> (CustodySystem, Arc<ManualClock>) {
+        let clock = Arc::new(ManualClock::new(Timestamp::from_unix(START)));
+        let mut system = CustodySystem::new();
+        system.set_clock(clock.clone());
+        for id in ["a", "b"] {
+            system
+                .create_wallet(id.to_string(), id.to_string(), WalletType::Hot)
+                .unwrap();
+        }
+        (system, clock)
+    }
+
+    #[test]
+    fn test_statements_roll_forward() {
+        let (mut system, clock) = system();
+        system.deposit("a", amount!(100)).unwrap();
+        system.transfer("a", "b", amount!(30)).unwrap();
+        clock.advance(100);
+        let first = system
+            .close_period(Timestamp::from_unix(START + 50))
+            .unwrap();
+        assert_eq!(first.id, 1);
+        assert_eq!(first.from, None);
+        let asset = Asset::default();
+        assert_eq!(
+            first.statement("a", &asset),
+            Some(&PeriodStatement {
+                opening: Amount::ZERO,
+                credits: amount!(100),
+                debits: amount!(30),
+                closing: amount!(70),
+                entries: 2,
+            })
+        );
+        assert_eq!(first.totals[&asset].closing, amount!(100));
+        assert_eq!(
+            first.chain_head.as_deref(),
+            Some(system.get_all_transactions()[1].chain_hash.as_str())
+        );
+
+        system.withdraw("b", amount!(5)).unwrap();
+        clock.advance(100);
+        let second = system
+            .close_period(Timestamp::from_unix(START + 150))
+            .unwrap();
+        assert_eq!(second.from, Some(Timestamp::from_unix(START + 50)));
+        // Idle wallets carry their balance forward
+        let a = second.statement("a", &asset).unwrap();
+        assert_eq!(
+            (a.opening, a.closing, a.entries),
+            (amount!(70), amount!(70), 0)
+        );
+        let b = second.statement("b", &asset).unwrap();
+        assert_eq!(
+            (b.opening, b.debits, b.closing),
+            (amount!(30), amount!(5), amount!(25))
+        );
+        assert_eq!(system.closed_periods().len(), 2);
+        assert_eq!(
+            system.closed_until(),
+            Some(Timestamp::from_unix(START + 150))
+        );
+    }
+
+    #[test]
+    fn test_closed_period_is_frozen() {
+        let (mut system, clock) = system();
+        system.deposit("a", amount!(10)).unwrap();
+        assert_eq!(
+            system.close_period(Timestamp::from_unix(START)),
+            Err(CustodyError::PeriodNotEnded {
+                until: Timestamp::from_unix(START)
+            })
+        );
+        clock.advance(10);
+        system
+            .close_period(Timestamp::from_unix(START + 5))
+            .unwrap();
+        let closed = CustodyError::PeriodClosed {
+            closed_until: Timestamp::from_unix(START + 5),
+        };
+        assert_eq!(
+            system.close_period(Timestamp::from_unix(START + 5)),
+            Err(closed.clone())
+        );
+
+        // Existing entries cannot change
+        assert_eq!(
+            system.annotate_transaction(0, TransactionMetadata::new().with_memo("late")),
+            Err(closed.clone())
+        );
+        assert_eq!(system.categorize_transaction(0, None), Err(closed.clone()));
+        let id = system.get_all_transactions()[0].id;
+        assert_eq!(system.reverse_transaction(id, "late"), Err(closed.clone()));
+
+        // Nor can new ones be backdated by a clock set into the period
+        clock.set(Timestamp::from_unix(START + 5));
+        assert_eq!(system.deposit("a", amount!(1)), Err(closed.clone()));
+        assert_eq!(system.withdraw("a", amount!(1)), Err(closed.clone()));
+        assert_eq!(system.transfer("a", "b", amount!(1)), Err(closed));
+        clock.set(Timestamp::from_unix(START + 6));
+        system.deposit("a", amount!(1)).unwrap();
+        system
+            .reverse_transaction(system.get_all_transactions()[1].id, "fix")
+            .unwrap();
+    }
+}
@@ -0,0 +1,311 @@
+//! Cost basis and profit & loss tracking.
+//!
+//! Deposits made through [`CustodySystem::deposit_priced`] record their fiat
+//! value at the time of deposit, which becomes their cost basis. Withdrawals
+//! made through [`CustodySystem::withdraw_priced`] record their fiat
+//! proceeds. P&L uses the average-cost method: each debit realises the
+//! difference between its proceeds and the average cost of the units it
+//! removes.
+
+use crate::time::Timestamp;
+use crate::{Amount, Asset, CustodyError, CustodySystem, RateProvider, Transaction};
+use serde::{Deserialize, Serialize};
+
+/// Fiat value of a transaction at the time it was recorded
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FiatValue {
+    pub currency: Asset,
+    pub amount: f64,
+}
+
+/// Profit and loss of a single wallet
+#[derive(Debug, Clone, PartialEq)]
+pub struct WalletPnl {
+    pub wallet_id: String,
+    pub asset: Asset,
+    /// Units held at the end of the period
+    pub holdings: Amount,
+    /// Cost basis of the holdings at the end of the period
+    pub cost_basis: f64,
+    /// Current value of the holdings, if a price is available
+    pub market_value: Option<f64>,
+    /// Gains realised by debits within the period
+    pub realized: f64,
+    /// `market_value - cost_basis`, if a price is available
+    pub unrealized: Option<f64>,
+    /// Transactions without a fiat value in the report currency; their
+    /// units carry zero cost (credits) or realise nothing (debits)
+    pub unpriced_transactions: usize,
+}
+
+/// P&L of all wallets over a period
+#[derive(Debug, Clone, PartialEq)]
+pub struct PnlReport {
+    /// Start of the period (inclusive)
+    pub from: Timestamp,
+    /// End of the period (exclusive)
+    pub to: Timestamp,
+    /// Currency all values are expressed in
+    pub currency: Asset,
+    /// Per-wallet results, sorted by wallet id
+    pub wallets: Vec<WalletPnl>,
+}
+
+impl PnlReport {
+    /// Total realised P&L across wallets
+    pub fn realized(&self) -> f64 {
+        self.wallets.iter().map(|w| w.realized).sum()
+    }
+
+    /// Total unrealised P&L across wallets that have a price
+    pub fn unrealized(&self) -> f64 {
+        self.wallets.iter().filter_map(|w| w.unrealized).sum()
+    }
+
+    /// Total cost basis across wallets
+    pub fn cost_basis(&self) -> f64 {
+        self.wallets.iter().map(|w| w.cost_basis).sum()
+    }
+}
+
+impl CustodySystem {
+    /// Deposits funds and records their fiat value as cost basis
+    ///
+    /// Fails with [`CustodyError::RateUnavailable`] (without depositing) if
+    /// `prices` has no rate from the wallet's asset to `currency`.
+    pub fn deposit_priced(
+        &mut self,
+        id: &str,
+        amount: Amount,
+        prices: &dyn RateProvider,
+        currency: &Asset,
+    ) -> Result<(), CustodyError> {
+        let value = self.fiat_value(id, amount, prices, currency)?;
+        self.deposit(id, amount)?;
+        self.price_last(value)
+    }
+
+    /// Withdraws funds and records their fiat proceeds
+    pub fn withdraw_priced(
+        &mut self,
+        id: &str,
+        amount: Amount,
+        prices: &dyn RateProvider,
+        currency: &Asset,
+    ) -> Result<(), CustodyError> {
+        let value = self.fiat_value(id, amount, prices, currency)?;
+        self.withdraw(id, amount)?;
+        self.price_last(value)
+    }
+
+    /// Computes P&L for one wallet over `from <= timestamp < to`, valuing
+    /// the remaining holdings at current `prices`
+    pub fn wallet_pnl(
+        &self,
+        wallet_id: &str,
+        from: Timestamp,
+        to: Timestamp,
+        prices: &dyn RateProvider,
+        currency: &Asset,
+    ) -> Result<WalletPnl, CustodyError> {
+        let wallet = self
+            .get_wallet(wallet_id)
+            .ok_or_else(|| CustodyError::WalletNotFound(wallet_id.to_string()))?;
+
+        let mut pnl = WalletPnl {
+            wallet_id: wallet.id.clone(),
+            asset: wallet.asset.clone(),
+            holdings: Amount::ZERO,
+            cost_basis: 0.0,
+            market_value: None,
+            realized: 0.0,
+            unrealized: None,
+            unpriced_transactions: 0,
+        };
+        for tx in self
+            .get_wallet_transactions(wallet_id)
+            .into_iter()
+            .filter(|tx| tx.timestamp < to)
+        {
+            apply(&mut pnl, tx, tx.timestamp >= from, currency);
+        }
+
+        pnl.market_value = prices
+            .rate(&wallet.asset, currency)
+            .map(|rate| pnl.holdings.to_f64() * rate);
+        pnl.unrealized = pnl.market_value.map(|value| value - pnl.cost_basis);
+        Ok(pnl)
+    }
+
+    /// Computes P&L for every wallet over `from <= timestamp < to`
+    pub fn pnl_report(
+        &self,
+        from: Timestamp,
+        to: Timestamp,
+        prices: &dyn RateProvider,
+        currency: &Asset,
+    ) -> PnlReport {
+        let mut ids: Vec<&String> = self.wallets.keys().collect();
+        ids.sort();
+        let wallets = ids
+            .into_iter()
+            .filter_map(|id| self.wallet_pnl(id, from, to, prices, currency).ok())
+            .collect();
+        PnlReport {
+            from,
+            to,
+            currency: currency.clone(),
+            wallets,
+        }
+    }
+
+    fn fiat_value(
+        &self,
+        id: &str,
+        amount: Amount,
+        prices: &dyn RateProvider,
+        currency: &Asset,
+    ) -> Result<FiatValue, CustodyError> {
+        let asset = &self
+            .get_wallet(id)
+            .ok_or_else(|| CustodyError::WalletNotFound(id.to_string()))?
+            .asset;
+        let rate = prices
+            .rate(asset, currency)
+            .ok_or_else(|| CustodyError::RateUnavailable {
+                from: asset.symbol().to_string(),
+                to: currency.symbol().to_string(),
+            })?;
+        if !rate.is_finite() || rate <= 0.0 {
+            return Err(CustodyError::InvalidRate(rate));
+        }
+        Ok(FiatValue {
+            currency: currency.clone(),
+            amount: amount.to_f64() * rate,
+        })
+    }
+
+    fn price_last(&mut self, value: FiatValue) -> Result<(), CustodyError> {
+        self.update_last_transaction(|tx| tx.fiat_value = Some(value))
+    }
+}
+
+/// Applies one transaction to a running average-cost position
+fn apply(pnl: &mut WalletPnl, tx: &Transaction, in_period: bool, currency: &Asset) {
+    let value = tx
+        .fiat_value
+        .as_ref()
+        .filter(|value| &value.currency == currency)
+        .map(|value| value.amount);
+    if value.is_none() && in_period {
+        pnl.unpriced_transactions += 1;
+    }
+
+    if tx.is_credit_for(&pnl.wallet_id) {
+        pnl.holdings += tx.amount;
+        pnl.cost_basis += value.unwrap_or(0.0);
+        return;
+    }
+
+    let average_cost = if pnl.holdings.is_positive() {
+        pnl.cost_basis / pnl.holdings.to_f64()
+    } else {
+        0.0
+    };
+    let removed_cost = average_cost * tx.amount.to_f64();
+    if in_period {
+        pnl.realized += value.map_or(0.0, |proceeds| proceeds - removed_cost);
+    }
+    pnl.holdings -= tx.amount;
+    pnl.cost_basis -= removed_cost;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{StaticRateProvider, WalletType};
+
+    fn usd() -> Asset {
+        Asset::Fiat("USD".to_string())
+    }
+
+    fn prices(btc: f64) -> StaticRateProvider {
+        let mut prices = StaticRateProvider::new();
+        prices.set_rate(Asset::Btc, usd(), btc);
+        prices
+    }
+
+    fn far_future() -> Timestamp {
+        Timestamp::from_unix(u32::MAX as u64)
+    }
+
+    fn system() -> CustodySystem {
+        let mut system = CustodySystem::new();
+        system
+            .create_wallet("w".to_string(), "bc1".to_string(), WalletType::Hot)
+            .unwrap();
+        system
+            .deposit_priced("w", amount!(1.0), &prices(20000.0), &usd())
+            .unwrap();
+        system
+            .deposit_priced("w", amount!(1.0), &prices(40000.0), &usd())
+            .unwrap();
+        system
+            .withdraw_priced("w", amount!(0.5), &prices(50000.0), &usd())
+            .unwrap();
+        system
+    }
+
+    #[test]
+    fn test_average_cost_pnl() {
+        let system = system();
+        let pnl = system
+            .wallet_pnl(
+                "w",
+                Timestamp::EPOCH,
+                far_future(),
+                &prices(60000.0),
+                &usd(),
+            )
+            .unwrap();
+        // Average cost 30,000; 0.5 sold at 50,000 realises 10,000
+        assert_eq!(pnl.realized, 10000.0);
+        assert_eq!(pnl.holdings, amount!(1.5));
+        assert_eq!(pnl.cost_basis, 45000.0);
+        assert_eq!(pnl.market_value, Some(90000.0));
+        assert_eq!(pnl.unrealized, Some(45000.0));
+        assert_eq!(pnl.unpriced_transactions, 0);
+    }
+
+    #[test]
+    fn test_period_excludes_earlier_realizations() {
+        let system = system();
+        let pnl = system
+            .wallet_pnl("w", far_future(), far_future(), &prices(60000.0), &usd())
+            .unwrap();
+        assert_eq!(pnl.realized, 0.0);
+        // Holdings are still built from the full history
+        assert_eq!(pnl.holdings, amount!(1.5));
+    }
+
+    #[test]
+    fn test_unpriced_and_missing_rates() {
+        let mut system = system();
+        system.deposit("w", amount!(0.5)).unwrap();
+        let report = system.pnl_report(
+            Timestamp::EPOCH,
+            far_future(),
+            &StaticRateProvider::new(),
+            &usd(),
+        );
+        assert_eq!(report.wallets.len(), 1);
+        assert_eq!(report.wallets[0].unpriced_transactions, 1);
+        assert_eq!(report.wallets[0].unrealized, None);
+        assert_eq!(report.cost_basis(), 45000.0);
+        assert_eq!(report.realized(), 10000.0);
+
+        let result = system.deposit_priced("w", amount!(1.0), &StaticRateProvider::new(), &usd());
+        assert!(matches!(result, Err(CustodyError::RateUnavailable { .. })));
+        assert_eq!(system.get_wallet("w").unwrap().balance, amount!(2.0));
+    }
+}
@@ -0,0 +1,373 @@
+//! Withdrawal limits and velocity policies.
+//!
+//! A [`WithdrawalPolicy`] caps single withdrawals, totals over rolling
+//! 24-hour and 7-day windows, and how soon a wallet may withdraw again.
+//! Policies are set per wallet type and may be overridden per wallet; the
+//! wallet's own policy replaces the type's entirely rather than merging
+//! with it. A tenant's policy for a type replaces the system's for the
+//! tenant's wallets.
+//!
+//! Limits are counted in the asset being withdrawn, over the wallet's
+//! withdrawals and outgoing transfers in that asset. Every path that
+//! debits a wallet checks them, and a breach fails with
+//! [`CustodyError::PolicyViolation`].
+
+use crate::time::Timestamp;
+use crate::{
+    AdminAction, Amount, Asset, CustodyError, CustodySystem, PolicyViolation, Transaction,
+    TransactionType, Wallet, WalletType,
+};
+use serde::{Deserialize, Serialize};
+
+const DAY_SECS: u64 = 24 * 60 * 60;
+const WEEK_SECS: u64 = 7 * DAY_SECS;
+
+/// Limits on the withdrawals of a wallet; `None` leaves a rule unset
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct WithdrawalPolicy {
+    /// Largest single withdrawal
+    pub max_per_transaction: Option<Amount>,
+    /// Total withdrawn over any 24 hours
+    pub daily_limit: Option<Amount>,
+    /// Total withdrawn over any 7 days
+    pub weekly_limit: Option<Amount>,
+    /// Seconds that must pass after a withdrawal before the next one
+    pub cooldown_secs: Option<u64>,
+}
+
+impl CustodySystem {
+    /// Sets the policy of every `wallet_type` wallet without a policy of
+    /// its own
+    pub fn set_default_withdrawal_policy(
+        &mut self,
+        wallet_type: WalletType,
+        policy: WithdrawalPolicy,
+    ) -> Result<(), CustodyError> {
+        self.require_second_operator(AdminAction::SET_DEFAULT_WITHDRAWAL_POLICY)?;
+        self.replace_default_withdrawal_policy(wallet_type, policy);
+        Ok(())
+    }
+
+    pub(crate) fn replace_default_withdrawal_policy(
+        &mut self,
+        wallet_type: WalletType,
+        policy: WithdrawalPolicy,
+    ) {
+        self.default_policies.insert(wallet_type, policy);
+    }
+
+    /// Sets a wallet's own policy, replacing its type's
+    ///
+    /// # Example
+    /// ```
+    /// use securevault::{amount, CustodyError, CustodySystem, WalletType, WithdrawalPolicy};
+    /// let mut system = CustodySystem::new();
+    /// system.create_wallet("w".to_string(), "0x1".to_string(), WalletType::Hot).unwrap();
+    /// system.deposit("w", amount!(100)).unwrap();
+    /// system
+    ///     .set_withdrawal_policy("w", WithdrawalPolicy {
+    ///         daily_limit: Some(amount!(10)),
+    ///         ..WithdrawalPolicy::default()
+    ///     })
+    ///     .unwrap();
+    ///
+    /// system.withdraw("w", amount!(6)).unwrap();
+    /// assert!(matches!(
+    ///     system.withdraw("w", amount!(6)),
+    ///     Err(CustodyError::PolicyViolation { .. })
+    /// ));
+    /// ```
+    pub fn set_withdrawal_policy(
+        &mut self,
+        wallet_id: &str,
+        policy: WithdrawalPolicy,
+    ) -> Result<(), CustodyError> {
+        self.require_second_operator(AdminAction::SET_WITHDRAWAL_POLICY)?;
+        self.replace_withdrawal_policy(wallet_id, Some(policy))?;
+        Ok(())
+    }
+
+    /// Removes a wallet's own policy, so that its type's applies again
+    ///
+    /// # Returns
+    /// The policy removed, if the wallet had one
+    pub fn clear_withdrawal_policy(
+        &mut self,
+        wallet_id: &str,
+    ) -> Result<Option<WithdrawalPolicy>, CustodyError> {
+        self.require_second_operator(AdminAction::SET_WITHDRAWAL_POLICY)?;
+        self.replace_withdrawal_policy(wallet_id, None)
+    }
+
+    pub(crate) fn replace_withdrawal_policy(
+        &mut self,
+        wallet_id: &str,
+        policy: Option<WithdrawalPolicy>,
+    ) -> Result<Option<WithdrawalPolicy>, CustodyError> {
+        if !self.wallet_exists(wallet_id) {
+            return Err(CustodyError::WalletNotFound(wallet_id.to_string()));
+        }
+        Ok(match policy {
+            Some(policy) => self.wallet_policies.insert(wallet_id.to_string(), policy),
+            None => self.wallet_policies.remove(wallet_id),
+        })
+    }
+
+    /// Gets the policy in force for a wallet, if any
+    pub fn withdrawal_policy(&self, wallet_id: &str) -> Option<&WithdrawalPolicy> {
+        self.wallet_policies.get(wallet_id).or_else(|| {
+            let wallet = self.wallets.get(wallet_id)?;
+            self.tenant_withdrawal_policy(wallet)
+                .or_else(|| self.default_policies.get(&wallet.wallet_type))
+        })
+    }
+
+    /// Sums the withdrawals and outgoing transfers of `asset` from a
+    /// wallet recorded at or after `since`
+    pub fn withdrawn_since(&self, wallet_id: &str, asset: &Asset, since: Timestamp) -> Amount {
+        self.transactions
+            .iter()
+            .filter(|tx| is_outflow(tx, wallet_id) && &tx.asset == asset && tx.timestamp >= since)
+            .map(|tx| tx.amount)
+            .sum()
+    }
+
+    /// Checks a debit of `amount` in `asset` against the wallet's policy
+    pub(crate) fn policy_blockers(
+        &self,
+        wallet: &Wallet,
+        asset: &Asset,
+        amount: Amount,
+    ) -> Vec<CustodyError> {
+        let Some(policy) = self.withdrawal_policy(&wallet.id) else {
+            return Vec::new();
+        };
+        let now = self.current_timestamp();
+        let mut violations = Vec::new();
+
+        if let Some(limit) = policy.max_per_transaction {
+            if amount > limit {
+                violations.push(PolicyViolation::PerTransactionLimit {
+                    limit,
+                    requested: amount,
+                });
+            }
+        }
+        let windows = [
+            (policy.daily_limit, DAY_SECS, true),
+            (policy.weekly_limit, WEEK_SECS, false),
+        ];
+        for (limit, secs, daily) in windows {
+            let Some(limit) = limit else { continue };
+            let since = Timestamp::from_unix(now.as_unix().saturating_sub(secs));
+            let used = self.withdrawn_since(&wallet.id, asset, since);
+            if used.checked_add(amount).is_none_or(|total| total > limit) {
+                violations.push(if daily {
+                    PolicyViolation::DailyLimit {
+                        limit,
+                        used,
+                        requested: amount,
+                    }
+                } else {
+                    PolicyViolation::WeeklyLimit {
+                        limit,
+                        used,
+                        requested: amount,
+                    }
+                });
+            }
+        }
+        if let Some(cooldown) = policy.cooldown_secs {
+            let last = self
+                .transactions
+                .iter()
+                .rev()
+                .find(|tx| is_outflow(tx, &wallet.id));
+            if let Some(last) = last {
+                let ready = last.timestamp.as_unix().saturating_add(cooldown);
+                if ready > now.as_unix() {
+                    violations.push(PolicyViolation::Cooldown {
+                        remaining_secs: ready - now.as_unix(),
+                    });
+                }
+            }
+        }
+
+        violations
+            .into_iter()
+            .map(|violation| CustodyError::PolicyViolation {
+                wallet_id: wallet.id.clone(),
+                violation,
+            })
+            .collect()
+    }
+}
+
+/// Returns true for withdrawals from, and transfers out of, the wallet
+fn is_outflow(tx: &Transaction, wallet_id: &str) -> bool {
+    tx.wallet_id == wallet_id
+        && matches!(
+            tx.transaction_type,
+            TransactionType::Withdrawal | TransactionType::Transfer
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn system() -> CustodySystem {
+        let mut system = CustodySystem::new();
+        for id in ["a", "b"] {
+            system
+                .create_wallet(id.to_string(), format!("0x{}", id), WalletType::Hot)
+                .unwrap();
+        }
+        system.deposit("a", amount!(100)).unwrap();
+        system
+    }
+
+    fn violation(result: Result<(), CustodyError>) -> PolicyViolation {
+        match result {
+            Err(CustodyError::PolicyViolation { violation, .. }) => violation,
+            other => panic!("expected a policy violation, got {:?}", other),
+        }
+    }
+
+    /// Records a withdrawal from `a` that happened `secs_ago`
+    fn backdate_withdrawal(system: &mut CustodySystem, amount: Amount, secs_ago: u64) {
+        let at = Timestamp::from_unix(system.current_timestamp().as_unix() - secs_ago);
+        let tx = Transaction::new("a", TransactionType::Withdrawal, amount, Asset::Btc, at);
+        system.record_transaction(tx).unwrap();
+    }
+
+    #[test]
+    fn test_per_transaction_maximum() {
+        let mut system = system();
+        system
+            .set_default_withdrawal_policy(
+                WalletType::Hot,
+                WithdrawalPolicy {
+                    max_per_transaction: Some(amount!(5)),
+                    ..WithdrawalPolicy::default()
+                },
+            )
+            .unwrap();
+        assert_eq!(
+            violation(system.withdraw("a", amount!(6))),
+            PolicyViolation::PerTransactionLimit {
+                limit: amount!(5),
+                requested: amount!(6),
+            }
+        );
+        // Transfers are withdrawals from the source too
+        assert!(system.transfer("a", "b", amount!(6)).is_err());
+        system.transfer("a", "b", amount!(5)).unwrap();
+        assert!(!system.can_withdraw("a", amount!(6), None).is_allowed());
+    }
+
+    #[test]
+    fn test_rolling_windows() {
+        let mut system = system();
+        system
+            .set_withdrawal_policy(
+                "a",
+                WithdrawalPolicy {
+                    daily_limit: Some(amount!(10)),
+                    weekly_limit: Some(amount!(25)),
+                    ..WithdrawalPolicy::default()
+                },
+            )
+            .unwrap();
+        // Outside the daily window but inside the weekly one
+        backdate_withdrawal(&mut system, amount!(12), 2 * DAY_SECS);
+        backdate_withdrawal(&mut system, amount!(8), DAY_SECS / 2);
+
+        assert_eq!(
+            violation(system.withdraw("a", amount!(3))),
+            PolicyViolation::DailyLimit {
+                limit: amount!(10),
+                used: amount!(8),
+                requested: amount!(3),
+            }
+        );
+        system.withdraw("a", amount!(2)).unwrap();
+        assert_eq!(
+            system.withdrawn_since("a", &Asset::Btc, Timestamp::EPOCH),
+            amount!(22)
+        );
+
+        // Deposits do not free up allowance
+        system.deposit("a", amount!(50)).unwrap();
+        system.clear_withdrawal_policy("a").unwrap();
+        system
+            .set_withdrawal_policy(
+                "a",
+                WithdrawalPolicy {
+                    weekly_limit: Some(amount!(25)),
+                    ..WithdrawalPolicy::default()
+                },
+            )
+            .unwrap();
+        assert!(matches!(
+            violation(system.withdraw("a", amount!(4))),
+            PolicyViolation::WeeklyLimit { .. }
+        ));
+    }
+
+    #[test]
+    fn test_cooldown() {
+        let mut system = system();
+        system
+            .set_withdrawal_policy(
+                "a",
+                WithdrawalPolicy {
+                    cooldown_secs: Some(3600),
+                    ..WithdrawalPolicy::default()
+                },
+            )
+            .unwrap();
+        backdate_withdrawal(&mut system, amount!(1), 7200);
+        system.withdraw("a", amount!(1)).unwrap();
+
+        match violation(system.withdraw("a", amount!(1))) {
+            PolicyViolation::Cooldown { remaining_secs } => {
+                assert!(remaining_secs > 3590 && remaining_secs <= 3600)
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        // Other wallets are unaffected
+        system.deposit("b", amount!(1)).unwrap();
+        system.withdraw("b", amount!(1)).unwrap();
+    }
+
+    #[test]
+    fn test_wallet_policy_overrides_type() {
+        let mut system = system();
+        let strict = WithdrawalPolicy {
+            max_per_transaction: Some(amount!(1)),
+            ..WithdrawalPolicy::default()
+        };
+        system
+            .set_default_withdrawal_policy(WalletType::Hot, strict.clone())
+            .unwrap();
+        system
+            .set_withdrawal_policy("a", WithdrawalPolicy::default())
+            .unwrap();
+        system.withdraw("a", amount!(50)).unwrap();
+        assert_eq!(system.withdrawal_policy("b"), Some(&strict));
+
+        assert_eq!(
+            system.set_withdrawal_policy("ghost", strict),
+            Err(CustodyError::WalletNotFound("ghost".to_string()))
+        );
+        let err = CustodyError::PolicyViolation {
+            wallet_id: "a".to_string(),
+            violation: PolicyViolation::Cooldown { remaining_secs: 30 },
+        };
+        assert_eq!(
+            err.to_string(),
+            "Wallet 'a' cannot withdraw for another 30 seconds"
+        );
+    }
+}
@@ -0,0 +1,205 @@
+//! Terminal portfolio view.
+//!
+//! Renders wallets grouped into portfolios (one per wallet tag) with their
+//! balances, fiat valuations from a [`RateProvider`], and a sparkline of
+//! each wallet's balance history. Backs the `securevault portfolio` command.
+
+use crate::format::AmountFormatter;
+use crate::{Amount, Asset, CustodySystem, RateProvider, Wallet};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+
+/// Portfolio heading for wallets without tags
+const UNTAGGED: &str = "untagged";
+
+/// Number of history points shown in each sparkline
+const SPARKLINE_WIDTH: usize = 16;
+
+const SPARK_LEVELS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+impl CustodySystem {
+    /// Returns the wallet's primary asset balance after each of its
+    /// transactions in that asset, oldest first
+    pub fn balance_history(&self, wallet_id: &str) -> Vec<Amount> {
+        let Some(wallet) = self.get_wallet(wallet_id) else {
+            return Vec::new();
+        };
+        let mut balance = Amount::ZERO;
+        self.get_wallet_transactions(wallet_id)
+            .into_iter()
+            .filter(|tx| tx.asset == wallet.asset)
+            .map(|tx| {
+                balance += tx.balance_change(wallet_id);
+                balance
+            })
+            .collect()
+    }
+}
+
+/// Renders `values` as a single-line sparkline, scaled between their
+/// minimum and maximum
+pub fn sparkline(values: &[f64]) -> String {
+    let min = values.iter().cloned().fold(f64::INFINITY, f64::min);
+    let max = values.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+    let range = max - min;
+    values
+        .iter()
+        .map(|value| {
+            if range <= 0.0 {
+                return SPARK_LEVELS[SPARK_LEVELS.len() / 2];
+            }
+            let level = ((value - min) / range * (SPARK_LEVELS.len() - 1) as f64).round();
+            SPARK_LEVELS[level as usize]
+        })
+        .collect()
+}
+
+/// Renders the portfolio view as plain text
+///
+/// # Arguments
+/// * `system` - Custody system to render
+/// * `rates` - Source of fiat valuations
+/// * `fiat` - Currency valuations are expressed in
+pub fn render_portfolio(system: &CustodySystem, rates: &dyn RateProvider, fiat: &Asset) -> String {
+    let formatter = AmountFormatter::default();
+    let mut portfolios: BTreeMap<&str, Vec<&Wallet>> = BTreeMap::new();
+    for wallet in system.get_all_wallets().values() {
+        if wallet.tags.is_empty() {
+            portfolios.entry(UNTAGGED).or_default().push(wallet);
+        }
+        for tag in &wallet.tags {
+            portfolios.entry(tag).or_default().push(wallet);
+        }
+    }
+
+    let mut out = String::new();
+    let mut unpriced = false;
+    for (name, mut wallets) in portfolios {
+        wallets.sort_by(|a, b| a.id.cmp(&b.id));
+        let _ = writeln!(out, "== {} ==", name);
+        let mut portfolio_total = Amount::ZERO;
+        for wallet in wallets {
+            let valuation = valuation(wallet, rates, fiat);
+            let value = match valuation {
+                Some(value) => {
+                    portfolio_total += value;
+                    formatter.format(value, fiat)
+                }
+                None => {
+                    unpriced = true;
+                    "n/a".to_string()
+                }
+            };
+            let history = system.balance_history(&wallet.id);
+            let recent: Vec<f64> = history[history.len().saturating_sub(SPARKLINE_WIDTH)..]
+                .iter()
+                .map(|balance| balance.to_f64())
+                .collect();
+            let _ = writeln!(
+                out,
+                "  {:<12} {:<5} {:>24} {:>16}  {}",
+                wallet.id,
+                wallet.wallet_type.to_string(),
+                formatter.format(wallet.balance, &wallet.asset),
+                value,
+                sparkline(&recent)
+            );
+        }
+        let _ = writeln!(
+            out,
+            "  {:<12} {:>47}",
+            "total",
+            formatter.format(portfolio_total, fiat)
+        );
+    }
+
+    // Wallets in several portfolios are counted once in the grand total
+    let distinct_total: Amount = system
+        .get_all_wallets()
+        .values()
+        .filter_map(|wallet| valuation(wallet, rates, fiat))
+        .sum();
+    let _ = writeln!(
+        out,
+        "\nTotal value: {}",
+        formatter.format(distinct_total, fiat)
+    );
+    if unpriced {
+        out.push_str("Some wallets have no price and are excluded from totals.\n");
+    }
+    out
+}
+
+/// Values every balance of a wallet in `fiat`, rounded to the currency's
+/// precision; `None` if any held asset has no price
+fn valuation(wallet: &Wallet, rates: &dyn RateProvider, fiat: &Asset) -> Option<Amount> {
+    wallet
+        .balances()
+        .map(|(asset, balance)| {
+            let rate = rates.rate(asset, fiat)?;
+            Some(balance.checked_mul_f64(rate)?.round_dp(fiat.decimals()))
+        })
+        .try_fold(Amount::ZERO, |total, value| total.checked_add(value?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{StaticRateProvider, WalletType};
+
+    fn usd() -> Asset {
+        Asset::Fiat("USD".to_string())
+    }
+
+    #[test]
+    fn test_balance_history_and_sparkline() {
+        let mut system = CustodySystem::new();
+        system
+            .create_wallet("w".to_string(), "0x1".to_string(), WalletType::Hot)
+            .unwrap();
+        system.deposit("w", amount!(4.0)).unwrap();
+        system.withdraw("w", amount!(3.0)).unwrap();
+        system.deposit("w", amount!(6.0)).unwrap();
+
+        let history = system.balance_history("w");
+        assert_eq!(history, vec![amount!(4.0), amount!(1.0), amount!(7.0)]);
+        let points: Vec<f64> = history.iter().map(|balance| balance.to_f64()).collect();
+        assert_eq!(sparkline(&points), "▅▁█");
+        assert_eq!(sparkline(&[2.0, 2.0]), "▅▅");
+        assert_eq!(sparkline(&[]), "");
+    }
+
+    #[test]
+    fn test_render_portfolio_groups_by_tag() {
+        let mut system = CustodySystem::new();
+        system.register_template(
+            "ops",
+            crate::WalletTemplate::hot(Asset::Btc).with_tag("ops"),
+        );
+        system
+            .create_wallet_from_template("ops", "ops1".to_string(), "0x1".to_string())
+            .unwrap();
+        system
+            .create_wallet_with_asset(
+                "e1".to_string(),
+                "0x2".to_string(),
+                WalletType::Cold,
+                Asset::Eth,
+            )
+            .unwrap();
+        system.deposit("ops1", amount!(2.0)).unwrap();
+        system.deposit("e1", amount!(1.0)).unwrap();
+
+        let mut rates = StaticRateProvider::new();
+        rates.set_rate(Asset::Btc, usd(), 30000.0);
+
+        let view = render_portfolio(&system, &rates, &usd());
+        assert!(view.contains("== hot =="));
+        assert!(view.contains("== ops =="));
+        assert!(view.contains("== untagged =="));
+        assert!(view.contains("$60,000.00"));
+        assert!(view.contains("n/a"));
+        assert!(view.contains("Total value: $60,000.00"));
+        assert!(view.contains("excluded from totals"));
+    }
+}
@@ -0,0 +1,221 @@
+//! Side-effect free withdrawal pre-checks.
+//!
+//! [`CustodySystem::can_withdraw`] runs the same checks as
+//! [`CustodySystem::withdraw`] without mutating anything and reports every
+//! reason a withdrawal would be blocked, so a UI can explain the outcome
+//! before the user submits.
+
+use crate::{Amount, Asset, CustodyError, CustodySystem, OperationKind, WalletType};
+use serde::{Deserialize, Serialize};
+
+/// How a withdrawal reached execution. Direct requests are subject to
+/// every approval requirement; approved ones have already collected the
+/// signatures or approvals their wallet needs. Signed ones carry the
+/// wallet's own signature, which stands in for a cold wallet's approval
+/// but not for co-owners or multisig signers. Confirmed ones passed the
+/// two-person rule and are otherwise treated as direct.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) enum Authorization {
+    Direct,
+    Approved,
+    Signed,
+    Confirmed,
+}
+
+/// Outcome of a withdrawal pre-check
+#[derive(Debug, Clone, PartialEq)]
+pub enum Decision {
+    /// The withdrawal would be accepted
+    Allow,
+    /// The withdrawal would be rejected for the listed reasons
+    Block(Vec<CustodyError>),
+}
+
+impl Decision {
+    /// Returns true if the withdrawal would be accepted
+    pub fn is_allowed(&self) -> bool {
+        matches!(self, Decision::Allow)
+    }
+
+    /// Returns the reasons the withdrawal would be blocked
+    pub fn reasons(&self) -> &[CustodyError] {
+        match self {
+            Decision::Allow => &[],
+            Decision::Block(reasons) => reasons,
+        }
+    }
+}
+
+impl CustodySystem {
+    /// Evaluates whether a withdrawal would succeed, without executing it
+    ///
+    /// # Arguments
+    /// * `wallet_id` - Wallet to withdraw from
+    /// * `amount` - Amount to withdraw
+    /// * `destination` - External destination address, if known
+    ///
+    /// # Example
+    /// ```
+    /// use securevault::{amount, CustodySystem, WalletType};
+    /// let mut system = CustodySystem::new();
+    /// system.create_wallet("w".to_string(), "0x1".to_string(), WalletType::Hot).unwrap();
+    ///
+    /// let decision = system.can_withdraw("w", amount!(5.0), None);
+    /// assert!(!decision.is_allowed());
+    /// assert!(decision.reasons()[0].to_string().contains("Insufficient balance"));
+    /// ```
+    pub fn can_withdraw(
+        &self,
+        wallet_id: &str,
+        amount: Amount,
+        destination: Option<&str>,
+    ) -> Decision {
+        let reasons =
+            self.withdrawal_blockers(wallet_id, None, amount, destination, Authorization::Direct);
+        if reasons.is_empty() {
+            Decision::Allow
+        } else {
+            Decision::Block(reasons)
+        }
+    }
+
+    /// Collects every reason a withdrawal in `asset` (the wallet's primary
+    /// asset if `None`) would be rejected, most fundamental first
+    pub(crate) fn withdrawal_blockers(
+        &self,
+        wallet_id: &str,
+        asset: Option<&Asset>,
+        amount: Amount,
+        destination: Option<&str>,
+        authorization: Authorization,
+    ) -> Vec<CustodyError> {
+        let mut reasons = Vec::new();
+        if !amount.is_positive() {
+            reasons.push(CustodyError::NonPositiveAmount(OperationKind::Withdrawal));
+        }
+        if destination.is_some_and(|d| d.trim().is_empty()) {
+            reasons.push(CustodyError::InvalidDestination(
+                destination.unwrap_or_default().to_string(),
+            ));
+        }
+
+        let wallet = match self.wallets.get(wallet_id) {
+            Some(wallet) => wallet,
+            None => {
+                reasons.push(CustodyError::WalletNotFound(wallet_id.to_string()));
+                return reasons;
+            }
+        };
+
+        reasons.extend(self.debit_blockers(wallet));
+        if authorization != Authorization::Approved && self.joint_ownership.contains_key(wallet_id)
+        {
+            reasons.push(CustodyError::CoSignatureRequired(wallet_id.to_string()));
+        }
+        if matches!(
+            authorization,
+            Authorization::Direct | Authorization::Confirmed
+        ) && wallet.wallet_type == WalletType::Cold
+        {
+            reasons.push(CustodyError::ApprovalRequired(wallet_id.to_string()));
+        }
+        if authorization != Authorization::Approved
+            && matches!(wallet.wallet_type, WalletType::MultiSig { .. })
+        {
+            reasons.push(CustodyError::SignaturesRequired(wallet_id.to_string()));
+        }
+        let asset = asset.unwrap_or(&wallet.asset);
+        let available = self.available_of(wallet, asset);
+        if amount.is_positive() && available < amount {
+            reasons.push(CustodyError::InsufficientBalance {
+                available,
+                requested: amount,
+            });
+        }
+        if matches!(authorization, Authorization::Direct | Authorization::Signed)
+            && self.needs_second_operator(amount)
+        {
+            reasons.push(CustodyError::DualControlRequired(
+                crate::AdminAction::WITHDRAWAL.to_string(),
+            ));
+        }
+        reasons.extend(self.policy_blockers(wallet, asset, amount));
+        reasons.extend(self.rate_limit_blockers(wallet_id));
+        reasons.extend(self.destination_blockers(wallet, destination));
+        reasons.extend(self.whitelist_blockers(wallet_id, destination));
+        reasons.extend(self.hook_blockers(wallet, "withdrawal", amount, destination));
+        reasons
+    }
+
+    /// Validation hooks are only available with the `scripting` feature
+    #[cfg(not(feature = "scripting"))]
+    pub(crate) fn hook_blockers(
+        &self,
+        _wallet: &crate::Wallet,
+        _kind: &str,
+        _amount: Amount,
+        _destination: Option<&str>,
+    ) -> Vec<CustodyError> {
+        Vec::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::WalletType;
+
+    fn system() -> CustodySystem {
+        let mut system = CustodySystem::new();
+        system
+            .create_wallet("w".to_string(), "0x1".to_string(), WalletType::Hot)
+            .unwrap();
+        system.deposit("w", amount!(10.0)).unwrap();
+        system
+    }
+
+    #[test]
+    fn test_can_withdraw_allows_valid_withdrawal() {
+        let system = system();
+        assert_eq!(
+            system.can_withdraw("w", amount!(10.0), Some("bc1qdest")),
+            Decision::Allow
+        );
+    }
+
+    #[test]
+    fn test_can_withdraw_does_not_mutate() {
+        let system = system();
+        let before = system.fork();
+        system.can_withdraw("w", amount!(4.0), None);
+        assert!(system.diff(&before).is_empty());
+    }
+
+    #[test]
+    fn test_can_withdraw_reports_all_reasons() {
+        let system = system();
+        assert_eq!(
+            system.can_withdraw("missing", amount!(1.0), None).reasons(),
+            &[CustodyError::WalletNotFound("missing".to_string())]
+        );
+
+        let decision = system.can_withdraw("w", amount!(-1.0), Some(" "));
+        assert_eq!(decision.reasons().len(), 2);
+        assert!(matches!(
+            decision.reasons()[0],
+            CustodyError::NonPositiveAmount(OperationKind::Withdrawal)
+        ));
+        assert!(matches!(
+            decision.reasons()[1],
+            CustodyError::InvalidDestination(_)
+        ));
+    }
+
+    #[test]
+    fn test_can_withdraw_matches_withdraw_error() {
+        let mut system = system();
+        let decision = system.can_withdraw("w", amount!(11.0), None);
+        let error = system.withdraw("w", amount!(11.0)).unwrap_err();
+        assert_eq!(decision.reasons(), &[error]);
+    }
+}
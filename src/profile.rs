@@ -0,0 +1,297 @@
+//! Owner and agreement details of wallets.
+//!
+//! A [`WalletProfile`] records who a wallet is held for: the owner's name
+//! and contact, the custody agreement it is held under and the risk tier
+//! compliance assigned. [`CustodySystem::find_wallets`] looks wallets up by
+//! any of them.
+
+use crate::{CustodyError, CustodySystem, Wallet};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Risk rating of a wallet's owner
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum RiskTier {
+    Low,
+    Medium,
+    High,
+}
+
+impl RiskTier {
+    /// Returns a stable lower-case name for the tier
+    pub fn name(&self) -> &'static str {
+        match self {
+            RiskTier::Low => "low",
+            RiskTier::Medium => "medium",
+            RiskTier::High => "high",
+        }
+    }
+}
+
+impl fmt::Display for RiskTier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Who a wallet is held for and under which agreement
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WalletProfile {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner_name: Option<String>,
+    /// E-mail address, phone number or similar
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub contact: Option<String>,
+    /// Reference of the custody agreement the wallet is held under
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub agreement_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub risk_tier: Option<RiskTier>,
+}
+
+impl WalletProfile {
+    /// Creates an empty profile
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the owner's name
+    pub fn with_owner(mut self, name: &str) -> Self {
+        self.owner_name = Some(name.to_string());
+        self
+    }
+
+    /// Sets the owner's contact
+    pub fn with_contact(mut self, contact: &str) -> Self {
+        self.contact = Some(contact.to_string());
+        self
+    }
+
+    /// Sets the custody agreement reference
+    pub fn with_agreement(mut self, agreement_id: &str) -> Self {
+        self.agreement_id = Some(agreement_id.to_string());
+        self
+    }
+
+    /// Sets the risk tier
+    pub fn with_risk_tier(mut self, tier: RiskTier) -> Self {
+        self.risk_tier = Some(tier);
+        self
+    }
+
+    /// Returns true if no field is set
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Criteria for [`CustodySystem::find_wallets`]; a wallet must meet all
+/// that are set
+///
+/// Names and contacts match case-insensitively on any part, agreement
+/// references and tiers exactly.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProfileQuery {
+    pub owner_name: Option<String>,
+    pub contact: Option<String>,
+    pub agreement_id: Option<String>,
+    pub risk_tier: Option<RiskTier>,
+}
+
+impl ProfileQuery {
+    /// Creates a query every wallet matches
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Matches owners whose name contains `name`
+    pub fn owner(mut self, name: &str) -> Self {
+        self.owner_name = Some(name.to_string());
+        self
+    }
+
+    /// Matches contacts containing `contact`
+    pub fn contact(mut self, contact: &str) -> Self {
+        self.contact = Some(contact.to_string());
+        self
+    }
+
+    /// Matches wallets held under agreement `agreement_id`
+    pub fn agreement(mut self, agreement_id: &str) -> Self {
+        self.agreement_id = Some(agreement_id.to_string());
+        self
+    }
+
+    /// Matches wallets of risk tier `tier`
+    pub fn risk_tier(mut self, tier: RiskTier) -> Self {
+        self.risk_tier = Some(tier);
+        self
+    }
+
+    /// Checks whether a profile meets every criterion
+    pub fn matches(&self, profile: &WalletProfile) -> bool {
+        let contains = |field: &Option<String>, wanted: &Option<String>| match wanted {
+            None => true,
+            Some(wanted) => field
+                .as_ref()
+                .is_some_and(|value| value.to_lowercase().contains(&wanted.to_lowercase())),
+        };
+        contains(&profile.owner_name, &self.owner_name)
+            && contains(&profile.contact, &self.contact)
+            && (self.agreement_id.is_none() || profile.agreement_id == self.agreement_id)
+            && (self.risk_tier.is_none() || profile.risk_tier == self.risk_tier)
+    }
+}
+
+impl CustodySystem {
+    /// Replaces a wallet's profile
+    ///
+    /// # Example
+    /// ```
+    /// use securevault::{CustodySystem, ProfileQuery, RiskTier, WalletProfile, WalletType};
+    /// let mut system = CustodySystem::new();
+    /// system.create_wallet("w".to_string(), "bc1q...".to_string(), WalletType::Cold).unwrap();
+    /// let profile = WalletProfile::new()
+    ///     .with_owner("Acme Treasury")
+    ///     .with_contact("ops@acme.example")
+    ///     .with_agreement("CA-2024-017")
+    ///     .with_risk_tier(RiskTier::Medium);
+    /// system.set_wallet_profile("w", profile).unwrap();
+    ///
+    /// let found = system.find_wallets(&ProfileQuery::new().owner("acme"));
+    /// assert_eq!(found[0].id, "w");
+    /// ```
+    pub fn set_wallet_profile(
+        &mut self,
+        wallet_id: &str,
+        profile: WalletProfile,
+    ) -> Result<(), CustodyError> {
+        self.update_wallet_annotations(wallet_id, |wallet| wallet.profile = profile)
+    }
+
+    /// Sets or clears a wallet owner's name and contact
+    pub fn set_wallet_owner(
+        &mut self,
+        wallet_id: &str,
+        name: Option<&str>,
+        contact: Option<&str>,
+    ) -> Result<(), CustodyError> {
+        self.update_wallet_annotations(wallet_id, |wallet| {
+            wallet.profile.owner_name = name.map(str::to_string);
+            wallet.profile.contact = contact.map(str::to_string);
+        })
+    }
+
+    /// Sets or clears the custody agreement a wallet is held under
+    pub fn set_wallet_agreement(
+        &mut self,
+        wallet_id: &str,
+        agreement_id: Option<&str>,
+    ) -> Result<(), CustodyError> {
+        self.update_wallet_annotations(wallet_id, |wallet| {
+            wallet.profile.agreement_id = agreement_id.map(str::to_string)
+        })
+    }
+
+    /// Sets or clears a wallet's risk tier
+    pub fn set_wallet_risk_tier(
+        &mut self,
+        wallet_id: &str,
+        tier: Option<RiskTier>,
+    ) -> Result<(), CustodyError> {
+        self.update_wallet_annotations(wallet_id, |wallet| wallet.profile.risk_tier = tier)
+    }
+
+    /// Returns the active wallets whose profile matches `query`, ordered
+    /// by id
+    pub fn find_wallets(&self, query: &ProfileQuery) -> Vec<&Wallet> {
+        let mut wallets: Vec<_> = self
+            .wallets
+            .values()
+            .filter(|wallet| query.matches(&wallet.profile))
+            .collect();
+        wallets.sort_by(|a, b| a.id.cmp(&b.id));
+        wallets
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::WalletType;
+
+    fn system() -> CustodySystem {
+        let mut system = CustodySystem::new();
+        for id in ["a", "b", "c"] {
+            system
+                .create_wallet(id.to_string(), format!("0x{}", id), WalletType::Cold)
+                .unwrap();
+        }
+        system
+            .set_wallet_owner("a", Some("Alice Martin"), Some("alice@example.com"))
+            .unwrap();
+        system.set_wallet_agreement("a", Some("CA-1")).unwrap();
+        system
+            .set_wallet_risk_tier("a", Some(RiskTier::High))
+            .unwrap();
+        system
+            .set_wallet_profile(
+                "b",
+                WalletProfile::new()
+                    .with_owner("Bob Martin")
+                    .with_agreement("CA-2")
+                    .with_risk_tier(RiskTier::Low),
+            )
+            .unwrap();
+        system
+    }
+
+    fn ids(wallets: Vec<&Wallet>) -> Vec<&str> {
+        wallets.iter().map(|wallet| wallet.id.as_str()).collect()
+    }
+
+    #[test]
+    fn test_find_wallets_by_profile() {
+        let system = system();
+        assert_eq!(
+            ids(system.find_wallets(&ProfileQuery::new())),
+            ["a", "b", "c"]
+        );
+        assert_eq!(
+            ids(system.find_wallets(&ProfileQuery::new().owner("martin"))),
+            ["a", "b"]
+        );
+        assert_eq!(
+            ids(system.find_wallets(&ProfileQuery::new().owner("martin").risk_tier(RiskTier::Low))),
+            ["b"]
+        );
+        assert_eq!(
+            ids(system.find_wallets(&ProfileQuery::new().contact("EXAMPLE.COM"))),
+            ["a"]
+        );
+        // Agreement references match exactly
+        assert!(system
+            .find_wallets(&ProfileQuery::new().agreement("CA"))
+            .is_empty());
+        assert_eq!(
+            ids(system.find_wallets(&ProfileQuery::new().agreement("CA-2"))),
+            ["b"]
+        );
+    }
+
+    #[test]
+    fn test_profile_updates_and_serialization() {
+        let mut system = system();
+        system.set_wallet_owner("a", None, None).unwrap();
+        let profile = &system.get_wallet("a").unwrap().profile;
+        assert_eq!(profile.owner_name, None);
+        assert_eq!(profile.risk_tier, Some(RiskTier::High));
+        assert!(system.set_wallet_agreement("nope", Some("CA-3")).is_err());
+
+        let json = serde_json::to_string(system.get_wallet("c").unwrap()).unwrap();
+        assert!(!json.contains("profile"));
+        let json = serde_json::to_string(system.get_wallet("b").unwrap()).unwrap();
+        let wallet: Wallet = serde_json::from_str(&json).unwrap();
+        assert_eq!(wallet.profile.owner_name.as_deref(), Some("Bob Martin"));
+    }
+}
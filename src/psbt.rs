@@ -0,0 +1,1418 @@
+//! Partially signed Bitcoin transactions for multi-signature wallets
+//! (feature `psbt`).
+//!
+//! A Bitcoin multi-signature wallet pays to a P2WSH `sortedmulti` script
+//! over its signers' public keys, registered with
+//! [`CustodySystem::set_multisig_keys`]. Once a
+//! [`MultiSigWithdrawal`](crate::MultiSigWithdrawal) is requested,
+//! [`CustodySystem::build_psbt`] selects unspent outputs from a
+//! [`UtxoSource`], pays the destination, returns the change to the wallet
+//! and hands out a BIP-174 [`Psbt`] for the signers' offline or hardware
+//! wallets. Each signer returns a copy carrying their partial signatures;
+//! [`CustodySystem::merge_signatures`] combines them, checking every
+//! signature against the transaction. As soon as `required` signers have
+//! signed every input, the PSBT is finalized and the withdrawal signed off
+//! in their names, and [`Psbt::extract_tx`] yields the network transaction
+//! for a [`Broadcaster`](crate::Broadcaster).
+//!
+//! Only what this flow needs is interpreted: witness UTXOs, witness
+//! scripts, partial signatures, sighash types and final witnesses. Other
+//! entries are carried through untouched, so PSBTs round-trip through
+//! signers that add their own.
+
+use crate::{
+    Amount, Asset, BitcoinAddressValidator, CustodyError, CustodySystem, FeeRate, OperationStatus,
+    WalletType,
+};
+use base64::Engine;
+use k256::ecdsa::signature::hazmat::PrehashVerifier;
+use k256::ecdsa::{Signature, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+/// Bytes every PSBT starts with
+const MAGIC: &[u8] = b"psbt\xff";
+
+/// The only sighash type signatures may use
+const SIGHASH_ALL: u8 = 0x01;
+
+/// Input sequence number signalling replace-by-fee (BIP-125)
+const SEQUENCE_RBF: u32 = 0xffff_fffd;
+
+/// Change below this is left to the miners rather than paid into an
+/// output worth less than spending it
+const DUST_LIMIT: u64 = 330;
+
+/// Most keys a script can name with a single `OP_n`
+const MAX_KEYS: usize = 16;
+
+/// Serialized size of a DER signature with its sighash byte, at most
+const MAX_SIGNATURE_LEN: usize = 73;
+
+const GLOBAL_UNSIGNED_TX: u8 = 0x00;
+const IN_WITNESS_UTXO: u8 = 0x01;
+const IN_PARTIAL_SIG: u8 = 0x02;
+const IN_SIGHASH_TYPE: u8 = 0x03;
+const IN_WITNESS_SCRIPT: u8 = 0x05;
+const IN_FINAL_SCRIPTWITNESS: u8 = 0x08;
+
+/// An unspent output a wallet can spend
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Utxo {
+    /// Transaction id, in the usual byte-reversed hex
+    pub txid: String,
+    pub vout: u32,
+    pub amount: Amount,
+}
+
+/// Lists the unspent outputs of an address, e.g. from a node or an indexer
+pub trait UtxoSource: fmt::Debug + Send + Sync {
+    /// Returns the outputs paying to `address` that are not yet spent
+    fn unspent(&self, address: &str) -> Result<Vec<Utxo>, String>;
+}
+
+/// Returns the P2WSH address of a `sortedmulti` script over
+/// `public_keys`, hex-encoded compressed keys, of which `required` must
+/// sign
+///
+/// # Example
+/// ```
+/// use securevault::{multisig_address, BitcoinAddressValidator};
+/// let keys = [
+///     "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+///     "02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5",
+/// ];
+/// let address = multisig_address(2, &keys, &BitcoinAddressValidator::mainnet()).unwrap();
+/// assert!(address.starts_with("bc1q"));
+/// assert!(multisig_address(3, &keys, &BitcoinAddressValidator::mainnet()).is_err());
+/// ```
+pub fn multisig_address(
+    required: usize,
+    public_keys: &[&str],
+    network: &BitcoinAddressValidator,
+) -> Result<String, CustodyError> {
+    let keys = public_keys
+        .iter()
+        .map(|key| {
+            parse_public_key(key).ok_or_else(|| {
+                CustodyError::InvalidMultisigKeys(format!("invalid public key {}", key))
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let script = multisig_script(required, keys)?;
+    Ok(network.segwit_address(0, &Sha256::digest(&script)))
+}
+
+/// A Bitcoin multi-signature wallet's keys and the script they form
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct MultisigKeys {
+    /// Compressed public key of each signer
+    keys: BTreeMap<String, [u8; 33]>,
+    witness_script: Vec<u8>,
+    script_pubkey: Vec<u8>,
+}
+
+impl MultisigKeys {
+    fn signer_of(&self, public_key: &[u8]) -> Option<&str> {
+        self.keys
+            .iter()
+            .find(|(_, key)| key.as_slice() == public_key)
+            .map(|(signer, _)| signer.as_str())
+    }
+}
+
+/// A transaction output
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct TxOut {
+    value: u64,
+    script_pubkey: Vec<u8>,
+}
+
+impl TxOut {
+    fn write(&self, out: &mut Vec<u8>) {
+        out.extend(self.value.to_le_bytes());
+        write_bytes(out, &self.script_pubkey);
+    }
+
+    fn read(reader: &mut Reader<'_>) -> Result<Self, String> {
+        Ok(Self {
+            value: reader.u64()?,
+            script_pubkey: reader.bytes()?.to_vec(),
+        })
+    }
+}
+
+/// A transaction input, spending output `vout` of `txid`
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct TxIn {
+    /// In internal byte order, the reverse of the displayed txid
+    txid: [u8; 32],
+    vout: u32,
+    sequence: u32,
+}
+
+impl TxIn {
+    fn write_outpoint(&self, out: &mut Vec<u8>) {
+        out.extend(self.txid);
+        out.extend(self.vout.to_le_bytes());
+    }
+}
+
+/// A transaction without signatures
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct UnsignedTx {
+    version: u32,
+    inputs: Vec<TxIn>,
+    outputs: Vec<TxOut>,
+    lock_time: u32,
+}
+
+impl UnsignedTx {
+    /// Serializes the transaction, in the segwit format if `witnesses`
+    /// are given
+    fn serialize(&self, witnesses: Option<&[Vec<Vec<u8>>]>) -> Vec<u8> {
+        let mut out = self.version.to_le_bytes().to_vec();
+        if witnesses.is_some() {
+            out.extend([0x00, 0x01]);
+        }
+        write_compact(&mut out, self.inputs.len());
+        for input in &self.inputs {
+            input.write_outpoint(&mut out);
+            // Empty scriptSig
+            out.push(0);
+            out.extend(input.sequence.to_le_bytes());
+        }
+        write_compact(&mut out, self.outputs.len());
+        for output in &self.outputs {
+            output.write(&mut out);
+        }
+        for witness in witnesses.into_iter().flatten() {
+            write_compact(&mut out, witness.len());
+            for item in witness {
+                write_bytes(&mut out, item);
+            }
+        }
+        out.extend(self.lock_time.to_le_bytes());
+        out
+    }
+
+    fn parse(bytes: &[u8]) -> Result<Self, String> {
+        let mut reader = Reader::new(bytes);
+        let version = reader.u32()?;
+        let count = reader.compact()?;
+        if count == 0 {
+            return Err("transaction has no inputs".to_string());
+        }
+        let mut inputs = Vec::with_capacity(count);
+        for _ in 0..count {
+            let txid = reader.array()?;
+            let vout = reader.u32()?;
+            if !reader.bytes()?.is_empty() {
+                return Err("unsigned transaction has a scriptSig".to_string());
+            }
+            inputs.push(TxIn {
+                txid,
+                vout,
+                sequence: reader.u32()?,
+            });
+        }
+        let count = reader.compact()?;
+        let outputs = (0..count)
+            .map(|_| TxOut::read(&mut reader))
+            .collect::<Result<_, _>>()?;
+        let tx = Self {
+            version,
+            inputs,
+            outputs,
+            lock_time: reader.u32()?,
+        };
+        reader.finish()?;
+        Ok(tx)
+    }
+
+    fn txid(&self) -> String {
+        let mut hash = double_sha256(&self.serialize(None));
+        hash.reverse();
+        hex::encode(hash)
+    }
+
+    /// BIP-143 signature hash of input `index`, spending `value` locked by
+    /// `script_code`, for `SIGHASH_ALL`
+    fn sighash(&self, index: usize, script_code: &[u8], value: u64) -> [u8; 32] {
+        let mut prevouts = Vec::new();
+        let mut sequences = Vec::new();
+        for input in &self.inputs {
+            input.write_outpoint(&mut prevouts);
+            sequences.extend(input.sequence.to_le_bytes());
+        }
+        let mut outputs = Vec::new();
+        for output in &self.outputs {
+            output.write(&mut outputs);
+        }
+
+        let input = &self.inputs[index];
+        let mut preimage = self.version.to_le_bytes().to_vec();
+        preimage.extend(double_sha256(&prevouts));
+        preimage.extend(double_sha256(&sequences));
+        input.write_outpoint(&mut preimage);
+        write_bytes(&mut preimage, script_code);
+        preimage.extend(value.to_le_bytes());
+        preimage.extend(input.sequence.to_le_bytes());
+        preimage.extend(double_sha256(&outputs));
+        preimage.extend(self.lock_time.to_le_bytes());
+        preimage.extend(u32::from(SIGHASH_ALL).to_le_bytes());
+        double_sha256(&preimage)
+    }
+}
+
+/// What a PSBT records about one input
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct PsbtInput {
+    witness_utxo: Option<TxOut>,
+    /// Signatures by compressed public key, DER with the sighash byte
+    partial_sigs: BTreeMap<Vec<u8>, Vec<u8>>,
+    sighash_type: Option<u32>,
+    witness_script: Option<Vec<u8>>,
+    final_script_witness: Option<Vec<Vec<u8>>>,
+    unknown: BTreeMap<Vec<u8>, Vec<u8>>,
+}
+
+impl PsbtInput {
+    fn from_map(map: BTreeMap<Vec<u8>, &[u8]>) -> Result<Self, String> {
+        let mut input = Self::default();
+        for (key, value) in map {
+            match (key[0], key.len()) {
+                (IN_WITNESS_UTXO, 1) => {
+                    let mut reader = Reader::new(value);
+                    input.witness_utxo = Some(TxOut::read(&mut reader)?);
+                    reader.finish()?;
+                }
+                (IN_PARTIAL_SIG, 34) => {
+                    input.partial_sigs.insert(key[1..].to_vec(), value.to_vec());
+                }
+                (IN_SIGHASH_TYPE, 1) => {
+                    let sighash_type = value
+                        .try_into()
+                        .map_err(|_| "sighash type is not 4 bytes".to_string())?;
+                    input.sighash_type = Some(u32::from_le_bytes(sighash_type));
+                }
+                (IN_WITNESS_SCRIPT, 1) => input.witness_script = Some(value.to_vec()),
+                (IN_FINAL_SCRIPTWITNESS, 1) => {
+                    let mut reader = Reader::new(value);
+                    let count = reader.compact()?;
+                    let witness = (0..count)
+                        .map(|_| reader.bytes().map(<[u8]>::to_vec))
+                        .collect::<Result<_, _>>()?;
+                    reader.finish()?;
+                    input.final_script_witness = Some(witness);
+                }
+                _ => {
+                    input.unknown.insert(key, value.to_vec());
+                }
+            }
+        }
+        Ok(input)
+    }
+
+    fn write(&self, out: &mut Vec<u8>) {
+        if let Some(utxo) = &self.witness_utxo {
+            let mut value = Vec::new();
+            utxo.write(&mut value);
+            write_entry(out, &[IN_WITNESS_UTXO], &value);
+        }
+        for (public_key, signature) in &self.partial_sigs {
+            write_entry(
+                out,
+                &[&[IN_PARTIAL_SIG], public_key.as_slice()].concat(),
+                signature,
+            );
+        }
+        if let Some(sighash_type) = self.sighash_type {
+            write_entry(out, &[IN_SIGHASH_TYPE], &sighash_type.to_le_bytes());
+        }
+        if let Some(script) = &self.witness_script {
+            write_entry(out, &[IN_WITNESS_SCRIPT], script);
+        }
+        if let Some(witness) = &self.final_script_witness {
+            let mut value = Vec::new();
+            write_compact(&mut value, witness.len());
+            for item in witness {
+                write_bytes(&mut value, item);
+            }
+            write_entry(out, &[IN_FINAL_SCRIPTWITNESS], &value);
+        }
+        for (key, value) in &self.unknown {
+            write_entry(out, key, value);
+        }
+        out.push(0);
+    }
+
+    /// Adds what `other` knows and this input does not
+    fn combine(&mut self, other: &PsbtInput) {
+        if self.final_script_witness.is_some() {
+            return;
+        }
+        if other.final_script_witness.is_some() {
+            *self = other.clone();
+            return;
+        }
+        for (public_key, signature) in &other.partial_sigs {
+            self.partial_sigs
+                .entry(public_key.clone())
+                .or_insert_with(|| signature.clone());
+        }
+        self.witness_utxo = self.witness_utxo.take().or(other.witness_utxo.clone());
+        self.sighash_type = self.sighash_type.or(other.sighash_type);
+        self.witness_script = self.witness_script.take().or(other.witness_script.clone());
+        for (key, value) in &other.unknown {
+            self.unknown
+                .entry(key.clone())
+                .or_insert_with(|| value.clone());
+        }
+    }
+}
+
+/// A partially signed Bitcoin transaction (BIP-174, version 0)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Psbt {
+    tx: UnsignedTx,
+    unknown: BTreeMap<Vec<u8>, Vec<u8>>,
+    inputs: Vec<PsbtInput>,
+    /// Output maps, none of which this crate interprets
+    outputs: Vec<BTreeMap<Vec<u8>, Vec<u8>>>,
+}
+
+impl Psbt {
+    /// Parses the binary format
+    pub fn parse(bytes: &[u8]) -> Result<Self, CustodyError> {
+        Self::parse_inner(bytes).map_err(CustodyError::InvalidPsbt)
+    }
+
+    fn parse_inner(bytes: &[u8]) -> Result<Self, String> {
+        let mut reader = Reader::new(bytes);
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err("missing PSBT magic bytes".to_string());
+        }
+        let mut global = read_map(&mut reader)?;
+        let tx = global
+            .remove([GLOBAL_UNSIGNED_TX].as_slice())
+            .ok_or("missing unsigned transaction")?;
+        let tx = UnsignedTx::parse(tx)?;
+        let inputs = (0..tx.inputs.len())
+            .map(|_| read_map(&mut reader).and_then(PsbtInput::from_map))
+            .collect::<Result<_, _>>()?;
+        let outputs = (0..tx.outputs.len())
+            .map(|_| {
+                read_map(&mut reader).map(|map| {
+                    map.into_iter()
+                        .map(|(key, value)| (key, value.to_vec()))
+                        .collect()
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        reader.finish()?;
+        Ok(Self {
+            tx,
+            unknown: global
+                .into_iter()
+                .map(|(key, value)| (key, value.to_vec()))
+                .collect(),
+            inputs,
+            outputs,
+        })
+    }
+
+    /// Serializes to the binary format
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        write_entry(&mut out, &[GLOBAL_UNSIGNED_TX], &self.tx.serialize(None));
+        for (key, value) in &self.unknown {
+            write_entry(&mut out, key, value);
+        }
+        out.push(0);
+        for input in &self.inputs {
+            input.write(&mut out);
+        }
+        for output in &self.outputs {
+            for (key, value) in output {
+                write_entry(&mut out, key, value);
+            }
+            out.push(0);
+        }
+        out
+    }
+
+    /// Parses the base64 encoding signers exchange
+    pub fn from_base64(encoded: &str) -> Result<Self, CustodyError> {
+        let bytes = base64::engine::general_purpose::STANDARD
+            .decode(encoded.trim())
+            .map_err(|err| CustodyError::InvalidPsbt(err.to_string()))?;
+        Self::parse(&bytes)
+    }
+
+    /// Encodes in base64
+    pub fn to_base64(&self) -> String {
+        base64::engine::general_purpose::STANDARD.encode(self.serialize())
+    }
+
+    /// Id of the transaction being signed
+    pub fn txid(&self) -> String {
+        self.tx.txid()
+    }
+
+    /// Number of inputs, each of which must be signed
+    pub fn input_count(&self) -> usize {
+        self.inputs.len()
+    }
+
+    /// Returns the hash a signer signs input `index` with
+    pub fn sighash(&self, index: usize) -> Result<[u8; 32], CustodyError> {
+        let input = self
+            .inputs
+            .get(index)
+            .ok_or_else(|| CustodyError::InvalidPsbt(format!("no input {}", index)))?;
+        let (Some(utxo), Some(script)) = (&input.witness_utxo, &input.witness_script) else {
+            return Err(CustodyError::InvalidPsbt(format!(
+                "input {} lacks its witness UTXO or script",
+                index
+            )));
+        };
+        Ok(self.tx.sighash(index, script, utxo.value))
+    }
+
+    /// Adds `public_key`'s signature of input `index`: DER followed by the
+    /// `SIGHASH_ALL` byte
+    ///
+    /// The signature is checked when the PSBT is merged.
+    pub fn add_signature(
+        &mut self,
+        index: usize,
+        public_key: &[u8],
+        signature: &[u8],
+    ) -> Result<(), CustodyError> {
+        let input = self
+            .inputs
+            .get_mut(index)
+            .ok_or_else(|| CustodyError::InvalidPsbt(format!("no input {}", index)))?;
+        if public_key.len() != 33 {
+            return Err(CustodyError::InvalidPsbt(
+                "public key is not compressed".to_string(),
+            ));
+        }
+        input
+            .partial_sigs
+            .insert(public_key.to_vec(), signature.to_vec());
+        Ok(())
+    }
+
+    /// Whether every input has its final witness
+    pub fn is_finalized(&self) -> bool {
+        self.inputs
+            .iter()
+            .all(|input| input.final_script_witness.is_some())
+    }
+
+    /// Returns the signed transaction, ready to broadcast
+    pub fn extract_tx(&self) -> Result<Vec<u8>, CustodyError> {
+        let witnesses = self
+            .inputs
+            .iter()
+            .enumerate()
+            .map(|(index, input)| {
+                input.final_script_witness.clone().ok_or_else(|| {
+                    CustodyError::InvalidPsbt(format!("input {} is not finalized", index))
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(self.tx.serialize(Some(&witnesses)))
+    }
+
+    /// Adds the signatures and other fields of `other`, a copy of the same
+    /// transaction
+    pub fn combine(&mut self, other: &Psbt) -> Result<(), CustodyError> {
+        if self.tx != other.tx {
+            return Err(CustodyError::InvalidPsbt(format!(
+                "{} and {} are different transactions",
+                self.txid(),
+                other.txid()
+            )));
+        }
+        for (input, theirs) in self.inputs.iter_mut().zip(&other.inputs) {
+            input.combine(theirs);
+        }
+        for (output, theirs) in self.outputs.iter_mut().zip(&other.outputs) {
+            for (key, value) in theirs {
+                output.entry(key.clone()).or_insert_with(|| value.clone());
+            }
+        }
+        for (key, value) in &other.unknown {
+            self.unknown
+                .entry(key.clone())
+                .or_insert_with(|| value.clone());
+        }
+        Ok(())
+    }
+
+    /// Checks every partial signature against `keys`, returning the
+    /// signers who signed all inputs
+    fn verify(&self, keys: &MultisigKeys) -> Result<BTreeSet<String>, CustodyError> {
+        let mut signed_all: Option<BTreeSet<String>> = None;
+        for (index, input) in self.inputs.iter().enumerate() {
+            if input.witness_script.as_ref() != Some(&keys.witness_script) {
+                return Err(CustodyError::InvalidPsbt(format!(
+                    "input {} is not locked by the wallet's script",
+                    index
+                )));
+            }
+            let sighash = self.sighash(index)?;
+            let mut signers = BTreeSet::new();
+            for (public_key, signature) in &input.partial_sigs {
+                let signer = keys.signer_of(public_key).ok_or_else(|| {
+                    CustodyError::SignatureRejected(format!(
+                        "input {} is signed by unknown key {}",
+                        index,
+                        hex::encode(public_key)
+                    ))
+                })?;
+                check_signature(public_key, signature, &sighash).map_err(|reason| {
+                    CustodyError::SignatureRejected(format!(
+                        "{}'s signature of input {}: {}",
+                        signer, index, reason
+                    ))
+                })?;
+                signers.insert(signer.to_string());
+            }
+            signed_all = Some(match signed_all {
+                Some(previous) => previous.intersection(&signers).cloned().collect(),
+                None => signers,
+            });
+        }
+        Ok(signed_all.unwrap_or_default())
+    }
+
+    /// Builds the final witnesses from `required` signatures per input, if
+    /// every input has them
+    fn finalize(&mut self, required: usize) -> bool {
+        let mut witnesses = Vec::with_capacity(self.inputs.len());
+        for input in &self.inputs {
+            let Some(script) = &input.witness_script else {
+                return false;
+            };
+            // CHECKMULTISIG wants the signatures in the script's key order,
+            // after a dummy element it pops by mistake
+            let mut witness = vec![Vec::new()];
+            witness.extend(
+                script_keys(script)
+                    .filter_map(|key| input.partial_sigs.get(key).cloned())
+                    .take(required),
+            );
+            if witness.len() <= required {
+                return false;
+            }
+            witness.push(script.clone());
+            witnesses.push(witness);
+        }
+        for (input, witness) in self.inputs.iter_mut().zip(witnesses) {
+            *input = PsbtInput {
+                witness_utxo: input.witness_utxo.take(),
+                final_script_witness: Some(witness),
+                unknown: std::mem::take(&mut input.unknown),
+                ..PsbtInput::default()
+            };
+        }
+        true
+    }
+}
+
+impl CustodySystem {
+    /// Registers the public keys of a Bitcoin multi-signature wallet's
+    /// signers: `(signer, compressed public key in hex)` for each
+    ///
+    /// The wallet's address must be the P2WSH address of the keys'
+    /// `sortedmulti` script, see [`multisig_address`].
+    ///
+    /// # Errors
+    /// * [`CustodyError::NotMultiSigWallet`] for other wallets
+    /// * [`CustodyError::NotASigner`] for keys of anyone else
+    /// * [`CustodyError::InvalidMultisigKeys`] if a signer has no key, a
+    ///   key is malformed, or the keys do not match the address
+    pub fn set_multisig_keys(
+        &mut self,
+        wallet_id: &str,
+        keys: &[(&str, &str)],
+    ) -> Result<(), CustodyError> {
+        let wallet = self
+            .wallets
+            .get(wallet_id)
+            .ok_or_else(|| CustodyError::WalletNotFound(wallet_id.to_string()))?;
+        let WalletType::MultiSig { required, signers } = &wallet.wallet_type else {
+            return Err(CustodyError::NotMultiSigWallet(wallet_id.to_string()));
+        };
+        let mut by_signer = BTreeMap::new();
+        for (signer, key) in keys {
+            if !signers.contains(*signer) {
+                return Err(CustodyError::NotASigner {
+                    wallet_id: wallet_id.to_string(),
+                    user: signer.to_string(),
+                });
+            }
+            let key = parse_public_key(key).ok_or_else(|| {
+                CustodyError::InvalidMultisigKeys(format!("invalid public key for {}", signer))
+            })?;
+            by_signer.insert(signer.to_string(), key);
+        }
+        if let Some(missing) = signers
+            .iter()
+            .find(|signer| !by_signer.contains_key(*signer))
+        {
+            return Err(CustodyError::InvalidMultisigKeys(format!(
+                "no key for {}",
+                missing
+            )));
+        }
+
+        let witness_script = multisig_script(*required, by_signer.values().copied().collect())?;
+        let script_pubkey = p2wsh(&witness_script);
+        let networks = [
+            BitcoinAddressValidator::mainnet(),
+            BitcoinAddressValidator::testnet(),
+        ];
+        let network = networks
+            .iter()
+            .find(|network| network.script_pubkey(&wallet.address).is_ok())
+            .unwrap_or(&networks[0]);
+        if network.script_pubkey(&wallet.address).ok() != Some(script_pubkey.clone()) {
+            return Err(CustodyError::InvalidMultisigKeys(format!(
+                "the keys pay to {}, not {}",
+                network.segwit_address(0, &script_pubkey[2..]),
+                wallet.address
+            )));
+        }
+        self.multisig_keys.insert(
+            wallet_id.to_string(),
+            MultisigKeys {
+                keys: by_signer,
+                witness_script,
+                script_pubkey,
+            },
+        );
+        Ok(())
+    }
+
+    /// Builds the transaction paying out a multi-signature withdrawal, as a
+    /// PSBT for its signers
+    ///
+    /// Unspent outputs of the wallet's address are spent largest first
+    /// until they cover the amount and the fee at the rate of the
+    /// [`FeeEstimator`](crate::FeeEstimator); change goes back to the
+    /// wallet. Building again replaces the previous PSBT, discarding its
+    /// signatures.
+    ///
+    /// # Errors
+    /// * [`CustodyError::OperationNotFound`] or
+    ///   [`CustodyError::OperationNotPending`] unless the withdrawal is
+    ///   pending or executed
+    /// * [`CustodyError::InvalidMultisigKeys`] before
+    ///   [`set_multisig_keys`](Self::set_multisig_keys)
+    /// * [`CustodyError::FeeUnavailable`] without a fee rate in sat/vB
+    /// * [`CustodyError::NodeError`] if `source` fails
+    /// * [`CustodyError::InvalidPsbt`] if the withdrawal has no Bitcoin
+    ///   destination or the outputs do not cover it
+    pub fn build_psbt(
+        &mut self,
+        operation_id: u64,
+        source: &dyn UtxoSource,
+    ) -> Result<Psbt, CustodyError> {
+        let operation = self.payable_withdrawal(operation_id)?;
+        let wallet = &self.wallets[&operation.wallet_id];
+        if wallet.asset != Asset::Btc {
+            return Err(CustodyError::InvalidPsbt(format!(
+                "{} holds {}, not BTC",
+                wallet.id, wallet.asset
+            )));
+        }
+        let keys = self.multisig_keys_of(&operation.wallet_id)?;
+        let destination = operation.destination.as_deref().ok_or_else(|| {
+            CustodyError::InvalidPsbt(format!("withdrawal {} has no destination", operation_id))
+        })?;
+        let destination = script_pubkey(destination)?;
+        let amount = satoshis(operation.amount)?;
+        let rate = self.sat_per_vbyte()?;
+
+        let mut utxos = source
+            .unspent(&wallet.address)
+            .map_err(CustodyError::NodeError)?
+            .into_iter()
+            .map(|utxo| Ok((txid_bytes(&utxo.txid)?, utxo.vout, satoshis(utxo.amount)?)))
+            .collect::<Result<Vec<_>, CustodyError>>()?;
+        utxos.sort_by_key(|utxo| std::cmp::Reverse(utxo.2));
+
+        let required = match &wallet.wallet_type {
+            WalletType::MultiSig { required, .. } => *required,
+            _ => return Err(CustodyError::NotMultiSigWallet(wallet.id.clone())),
+        };
+        let input_weight = input_weight(required, keys.witness_script.len());
+        let fee = |inputs: usize, outputs: &[&[u8]]| -> Result<u64, CustodyError> {
+            let weight = tx_weight(inputs, input_weight, outputs);
+            weight
+                .div_ceil(4)
+                .checked_mul(rate)
+                .ok_or(CustodyError::AmountOverflow)
+        };
+        let without_change = [destination.as_slice()];
+        let with_change = [destination.as_slice(), keys.script_pubkey.as_slice()];
+
+        let mut selected = Vec::new();
+        let mut total = 0u64;
+        for utxo in utxos {
+            if total >= amount.saturating_add(fee(selected.len(), &without_change)?) {
+                break;
+            }
+            total = total.saturating_add(utxo.2);
+            selected.push(utxo);
+        }
+        let needed = amount.saturating_add(fee(selected.len(), &without_change)?);
+        if selected.is_empty() || total < needed {
+            return Err(CustodyError::InvalidPsbt(format!(
+                "unspent outputs hold {} sat, {} sat needed",
+                total, needed
+            )));
+        }
+        let mut outputs = vec![TxOut {
+            value: amount,
+            script_pubkey: destination.clone(),
+        }];
+        let change = total
+            .saturating_sub(amount)
+            .saturating_sub(fee(selected.len(), &with_change)?);
+        if change >= DUST_LIMIT {
+            outputs.push(TxOut {
+                value: change,
+                script_pubkey: keys.script_pubkey.clone(),
+            });
+        }
+
+        let tx = UnsignedTx {
+            version: 2,
+            inputs: selected
+                .iter()
+                .map(|&(txid, vout, _)| TxIn {
+                    txid,
+                    vout,
+                    sequence: SEQUENCE_RBF,
+                })
+                .collect(),
+            outputs,
+            lock_time: 0,
+        };
+        let psbt = Psbt {
+            inputs: selected
+                .iter()
+                .map(|&(_, _, value)| PsbtInput {
+                    witness_utxo: Some(TxOut {
+                        value,
+                        script_pubkey: keys.script_pubkey.clone(),
+                    }),
+                    sighash_type: Some(u32::from(SIGHASH_ALL)),
+                    witness_script: Some(keys.witness_script.clone()),
+                    ..PsbtInput::default()
+                })
+                .collect(),
+            outputs: vec![BTreeMap::new(); tx.outputs.len()],
+            unknown: BTreeMap::new(),
+            tx,
+        };
+        self.psbts.insert(operation_id, psbt.clone());
+        Ok(psbt)
+    }
+
+    /// Merges signed copies of a withdrawal's PSBT into the one
+    /// [`build_psbt`](Self::build_psbt) made
+    ///
+    /// Every partial signature must be a valid `SIGHASH_ALL` signature by
+    /// one of the wallet's keys. Once `required` signers have signed every
+    /// input, the PSBT is finalized and the withdrawal signed in their
+    /// names, executing it if it is still pending.
+    ///
+    /// # Returns
+    /// The merged PSBT; [`Psbt::is_finalized`] tells whether it is ready
+    /// for [`Psbt::extract_tx`]
+    ///
+    /// # Errors
+    /// * [`CustodyError::InvalidPsbt`] if the parts are of a transaction
+    ///   no withdrawal is paid by
+    /// * [`CustodyError::SignatureRejected`] for signatures by unknown
+    ///   keys or that do not verify; nothing is merged then
+    pub fn merge_signatures(&mut self, parts: &[Psbt]) -> Result<Psbt, CustodyError> {
+        let first = parts
+            .first()
+            .ok_or_else(|| CustodyError::InvalidPsbt("no PSBTs to merge".to_string()))?;
+        let txid = first.txid();
+        let (operation_id, mut merged) = self
+            .psbts
+            .iter()
+            .find(|(_, psbt)| psbt.txid() == txid)
+            .map(|(id, psbt)| (*id, psbt.clone()))
+            .ok_or_else(|| CustodyError::InvalidPsbt(format!("{} pays out no withdrawal", txid)))?;
+        let operation = self.payable_withdrawal(operation_id)?;
+        if merged.is_finalized() {
+            return Ok(merged);
+        }
+        for part in parts {
+            if part
+                .inputs
+                .iter()
+                .any(|input| input.final_script_witness.is_some())
+            {
+                return Err(CustodyError::InvalidPsbt(format!(
+                    "{} was finalized elsewhere",
+                    txid
+                )));
+            }
+            merged.combine(part)?;
+        }
+        let keys = self.multisig_keys_of(&operation.wallet_id)?;
+        let signers = merged.verify(keys)?;
+        let required = match &self.wallets[&operation.wallet_id].wallet_type {
+            WalletType::MultiSig { required, .. } => *required,
+            _ => return Err(CustodyError::NotMultiSigWallet(operation.wallet_id.clone())),
+        };
+
+        let mut finalized = merged.clone();
+        self.psbts.insert(operation_id, merged.clone());
+        if !finalized.finalize(required) {
+            return Ok(merged);
+        }
+        for signer in &signers {
+            let pending = &self.multisig_withdrawals[&operation_id];
+            if pending.status == OperationStatus::Pending && !pending.signatures.contains(signer) {
+                self.sign_withdrawal(&operation.wallet_id, operation_id, signer)?;
+            }
+        }
+        self.psbts.insert(operation_id, finalized.clone());
+        Ok(finalized)
+    }
+
+    /// Returns the latest PSBT of a withdrawal
+    pub fn get_psbt(&self, operation_id: u64) -> Option<&Psbt> {
+        self.psbts.get(&operation_id)
+    }
+
+    /// Returns a multi-signature withdrawal that may still be paid out
+    fn payable_withdrawal(
+        &self,
+        operation_id: u64,
+    ) -> Result<crate::MultiSigWithdrawal, CustodyError> {
+        let operation = self
+            .multisig_withdrawals
+            .get(&operation_id)
+            .ok_or(CustodyError::OperationNotFound(operation_id))?;
+        match operation.status {
+            OperationStatus::Pending | OperationStatus::Executed => Ok(operation.clone()),
+            OperationStatus::Rejected | OperationStatus::Expired => {
+                Err(CustodyError::OperationNotPending(operation_id))
+            }
+        }
+    }
+
+    fn multisig_keys_of(&self, wallet_id: &str) -> Result<&MultisigKeys, CustodyError> {
+        self.multisig_keys.get(wallet_id).ok_or_else(|| {
+            CustodyError::InvalidMultisigKeys(format!("no keys set for {}", wallet_id))
+        })
+    }
+
+    fn sat_per_vbyte(&self) -> Result<u64, CustodyError> {
+        let estimator = self
+            .fee_estimator
+            .as_ref()
+            .ok_or_else(|| CustodyError::FeeUnavailable("no fee estimator set".to_string()))?;
+        match estimator
+            .fee_rate(&Asset::Btc)
+            .map_err(CustodyError::FeeUnavailable)?
+        {
+            FeeRate::SatPerVbyte(rate) => Ok(rate),
+            other => Err(CustodyError::FeeUnavailable(format!(
+                "expected a rate in sat/vB, got {:?}",
+                other
+            ))),
+        }
+    }
+}
+
+/// Weight of one P2WSH multisig input with its witness
+fn input_weight(required: usize, script_len: usize) -> u64 {
+    // Outpoint, empty scriptSig and sequence, at four weight units a byte
+    let base = (36 + 1 + 4) * 4;
+    // Item count, the dummy element, the signatures and the script
+    let witness = 1 + 1 + required * (1 + MAX_SIGNATURE_LEN) + compact_len(script_len) + script_len;
+    (base + witness) as u64
+}
+
+/// Weight of a transaction spending `inputs` inputs to `outputs`
+fn tx_weight(inputs: usize, input_weight: u64, outputs: &[&[u8]]) -> u64 {
+    // Version, lock time and the counts; the segwit marker and flag
+    let base = (4 + 4 + compact_len(inputs) + compact_len(outputs.len())) * 4 + 2;
+    let outputs: usize = outputs
+        .iter()
+        .map(|script| (8 + compact_len(script.len()) + script.len()) * 4)
+        .sum();
+    (base + outputs) as u64 + inputs as u64 * input_weight
+}
+
+/// Builds `OP_required <keys in byte order> OP_n OP_CHECKMULTISIG`
+fn multisig_script(required: usize, mut keys: Vec<[u8; 33]>) -> Result<Vec<u8>, CustodyError> {
+    if keys.len() > MAX_KEYS {
+        return Err(CustodyError::InvalidMultisigKeys(format!(
+            "{} keys, at most {} are supported",
+            keys.len(),
+            MAX_KEYS
+        )));
+    }
+    if required == 0 || required > keys.len() {
+        return Err(CustodyError::InvalidMultisigKeys(format!(
+            "{} of {} keys cannot sign",
+            required,
+            keys.len()
+        )));
+    }
+    keys.sort_unstable();
+    if keys.windows(2).any(|pair| pair[0] == pair[1]) {
+        return Err(CustodyError::InvalidMultisigKeys(
+            "signers share a key".to_string(),
+        ));
+    }
+    let mut script = vec![0x50 + required as u8];
+    for key in &keys {
+        script.push(33);
+        script.extend(key);
+    }
+    script.extend([0x50 + keys.len() as u8, 0xae]);
+    Ok(script)
+}
+
+/// Public keys of a script built by [`multisig_script`], in order
+fn script_keys(script: &[u8]) -> impl Iterator<Item = &[u8]> {
+    script
+        .get(1..script.len().saturating_sub(2))
+        .unwrap_or_default()
+        .chunks(34)
+        .filter(|push| push.len() == 34 && push[0] == 33)
+        .map(|push| &push[1..])
+}
+
+/// `OP_0 <SHA-256 of the script>`
+fn p2wsh(witness_script: &[u8]) -> Vec<u8> {
+    [&[0x00, 0x20], Sha256::digest(witness_script).as_slice()].concat()
+}
+
+/// Output script of a mainnet or testnet address
+fn script_pubkey(address: &str) -> Result<Vec<u8>, CustodyError> {
+    let mainnet = BitcoinAddressValidator::mainnet().script_pubkey(address);
+    mainnet
+        .clone()
+        .or_else(|_| BitcoinAddressValidator::testnet().script_pubkey(address))
+        .map_err(|_| CustodyError::InvalidAddress {
+            chain: "bitcoin".to_string(),
+            address: address.to_string(),
+            reason: mainnet.unwrap_err(),
+        })
+}
+
+fn parse_public_key(key: &str) -> Option<[u8; 33]> {
+    let key: [u8; 33] = hex::decode(key).ok()?.try_into().ok()?;
+    VerifyingKey::from_sec1_bytes(&key).ok()?;
+    Some(key)
+}
+
+fn satoshis(amount: Amount) -> Result<u64, CustodyError> {
+    amount
+        .to_minor_units(Asset::Btc.decimals())
+        .and_then(|sats| u64::try_from(sats).ok())
+        .ok_or_else(|| {
+            CustodyError::InvalidPsbt(format!("{} is not a whole number of satoshis", amount))
+        })
+}
+
+/// Converts a displayed txid to internal byte order
+fn txid_bytes(txid: &str) -> Result<[u8; 32], CustodyError> {
+    let mut bytes: [u8; 32] = hex::decode(txid)
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| CustodyError::InvalidPsbt(format!("malformed txid {}", txid)))?;
+    bytes.reverse();
+    Ok(bytes)
+}
+
+/// Verifies a DER signature with its sighash byte; Bitcoin relays only
+/// low-S signatures, so high-S ones are refused
+fn check_signature(public_key: &[u8], signature: &[u8], sighash: &[u8; 32]) -> Result<(), String> {
+    let (&sighash_type, der) = signature.split_last().ok_or("empty signature")?;
+    if sighash_type != SIGHASH_ALL {
+        return Err(format!(
+            "sighash type {:#04x} is not SIGHASH_ALL",
+            sighash_type
+        ));
+    }
+    let signature = parse_der(der).ok_or("malformed DER signature")?;
+    if signature.normalize_s().is_some() {
+        return Err("high-S signature".to_string());
+    }
+    let key = VerifyingKey::from_sec1_bytes(public_key).map_err(|_| "invalid public key")?;
+    key.verify_prehash(sighash, &signature)
+        .map_err(|_| "does not verify".to_string())
+}
+
+/// Parses `30 len 02 rlen r 02 slen s`
+fn parse_der(der: &[u8]) -> Option<Signature> {
+    let mut reader = Reader::new(der);
+    let [0x30, len] = reader.array().ok()? else {
+        return None;
+    };
+    if usize::from(len) != der.len() - 2 {
+        return None;
+    }
+    let mut scalar = || -> Option<[u8; 32]> {
+        let [0x02, len] = reader.array().ok()? else {
+            return None;
+        };
+        let value = reader.take(usize::from(len)).ok()?;
+        let value = value.strip_prefix(&[0]).unwrap_or(value);
+        let mut padded = [0; 32];
+        padded
+            .get_mut(32usize.checked_sub(value.len())?..)?
+            .copy_from_slice(value);
+        Some(padded)
+    };
+    let (r, s) = (scalar()?, scalar()?);
+    reader.finish().ok()?;
+    Signature::from_scalars(r, s).ok()
+}
+
+fn double_sha256(bytes: &[u8]) -> [u8; 32] {
+    Sha256::digest(Sha256::digest(bytes)).into()
+}
+
+fn compact_len(len: usize) -> usize {
+    match len {
+        0..=0xfc => 1,
+        0xfd..=0xffff => 3,
+        _ => 5,
+    }
+}
+
+fn write_compact(out: &mut Vec<u8>, len: usize) {
+    match len {
+        0..=0xfc => out.push(len as u8),
+        0xfd..=0xffff => {
+            out.push(0xfd);
+            out.extend((len as u16).to_le_bytes());
+        }
+        _ => {
+            out.push(0xfe);
+            out.extend((len as u32).to_le_bytes());
+        }
+    }
+}
+
+fn write_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    write_compact(out, bytes.len());
+    out.extend(bytes);
+}
+
+fn write_entry(out: &mut Vec<u8>, key: &[u8], value: &[u8]) {
+    write_bytes(out, key);
+    write_bytes(out, value);
+}
+
+/// Reads a PSBT key-value map up to its terminating zero byte
+fn read_map<'a>(reader: &mut Reader<'a>) -> Result<BTreeMap<Vec<u8>, &'a [u8]>, String> {
+    let mut map = BTreeMap::new();
+    loop {
+        let key = reader.bytes()?;
+        if key.is_empty() {
+            return Ok(map);
+        }
+        let value = reader.bytes()?;
+        if map.insert(key.to_vec(), value).is_some() {
+            return Err(format!("duplicate key {}", hex::encode(key)));
+        }
+    }
+}
+
+/// Cursor over serialized data
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, position: 0 }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let end = self
+            .position
+            .checked_add(len)
+            .filter(|&end| end <= self.bytes.len())
+            .ok_or("unexpected end of data")?;
+        let taken = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        Ok(self.take(N)?.try_into().expect("took N bytes"))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        self.array().map(u32::from_le_bytes)
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        self.array().map(u64::from_le_bytes)
+    }
+
+    /// Reads a compact size, which as a length or count cannot exceed the
+    /// data left
+    fn compact(&mut self) -> Result<usize, String> {
+        let value = match self.array::<1>()?[0] {
+            0xfd => u64::from(u16::from_le_bytes(self.array()?)),
+            0xfe => u64::from(self.u32()?),
+            0xff => self.u64()?,
+            byte => u64::from(byte),
+        };
+        usize::try_from(value)
+            .ok()
+            .filter(|&value| value <= self.bytes.len() - self.position)
+            .ok_or_else(|| format!("length {} exceeds the data", value))
+    }
+
+    fn bytes(&mut self) -> Result<&'a [u8], String> {
+        let len = self.compact()?;
+        self.take(len)
+    }
+
+    fn finish(&self) -> Result<(), String> {
+        if self.position == self.bytes.len() {
+            Ok(())
+        } else {
+            Err("trailing data".to_string())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FeeEstimator;
+    use k256::ecdsa::signature::hazmat::PrehashSigner;
+    use k256::ecdsa::SigningKey;
+    use std::sync::Arc;
+
+    const DESTINATION: &str = "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4";
+
+    #[derive(Debug)]
+    struct TenSatPerVbyte;
+
+    impl FeeEstimator for TenSatPerVbyte {
+        fn fee_rate(&self, _asset: &Asset) -> Result<FeeRate, String> {
+            Ok(FeeRate::SatPerVbyte(10))
+        }
+    }
+
+    #[derive(Debug)]
+    struct Unspent(Vec<Utxo>);
+
+    impl UtxoSource for Unspent {
+        fn unspent(&self, _address: &str) -> Result<Vec<Utxo>, String> {
+            Ok(self.0.clone())
+        }
+    }
+
+    fn utxo(txid_byte: char, amount: Amount) -> Utxo {
+        Utxo {
+            txid: txid_byte.to_string().repeat(64),
+            vout: 0,
+            amount,
+        }
+    }
+
+    fn key(seed: u8) -> SigningKey {
+        SigningKey::from_bytes(&[seed; 32].into()).unwrap()
+    }
+
+    fn public_key(key: &SigningKey) -> Vec<u8> {
+        key.verifying_key()
+            .to_encoded_point(true)
+            .as_bytes()
+            .to_vec()
+    }
+
+    /// Signs every input as `key`, encoding the signature in DER
+    fn sign(psbt: &Psbt, key: &SigningKey) -> Psbt {
+        let mut signed = psbt.clone();
+        for index in 0..psbt.input_count() {
+            let signature: Signature = key.sign_prehash(&psbt.sighash(index).unwrap()).unwrap();
+            let signature = signature.normalize_s().unwrap_or(signature);
+            let mut der = Vec::new();
+            for scalar in [signature.r().to_bytes(), signature.s().to_bytes()] {
+                let trimmed: Vec<u8> = scalar.iter().copied().skip_while(|&b| b == 0).collect();
+                let padded = if trimmed[0] & 0x80 != 0 {
+                    [&[0], trimmed.as_slice()].concat()
+                } else {
+                    trimmed
+                };
+                der.extend([0x02, padded.len() as u8]);
+                der.extend(padded);
+            }
+            let mut encoded = vec![0x30, der.len() as u8];
+            encoded.extend(der);
+            encoded.push(SIGHASH_ALL);
+            signed
+                .add_signature(index, &public_key(key), &encoded)
+                .unwrap();
+        }
+        signed
+    }
+
+    /// A 2-of-3 treasury holding 1 BTC, with a withdrawal of 0.6 BTC
+    /// requested by alice
+    fn treasury() -> (CustodySystem, u64) {
+        let keys: Vec<String> = (1..=3)
+            .map(|seed| hex::encode(public_key(&key(seed))))
+            .collect();
+        let keys: Vec<&str> = keys.iter().map(String::as_str).collect();
+        let address = multisig_address(2, &keys, &BitcoinAddressValidator::mainnet()).unwrap();
+        let mut system = CustodySystem::new();
+        system
+            .create_wallet(
+                "treasury".to_string(),
+                address,
+                WalletType::multisig(2, &["alice", "bob", "carol"]),
+            )
+            .unwrap();
+        system.deposit("treasury", amount!(1)).unwrap();
+        system
+            .set_multisig_keys(
+                "treasury",
+                &[("alice", keys[0]), ("bob", keys[1]), ("carol", keys[2])],
+            )
+            .unwrap();
+        system.set_fee_estimator(Arc::new(TenSatPerVbyte));
+        let op = system
+            .request_multisig_withdrawal("treasury", "alice", amount!(0.6), Some(DESTINATION))
+            .unwrap();
+        (system, op)
+    }
+
+    #[test]
+    fn test_build_sign_merge_and_finalize() {
+        let (mut system, op) = treasury();
+        let source = Unspent(vec![utxo('a', amount!(0.3)), utxo('b', amount!(0.5))]);
+        let psbt = system.build_psbt(op, &source).unwrap();
+        assert_eq!(psbt.input_count(), 2);
+        // Largest output first
+        assert_eq!(psbt.tx.inputs[0].txid, [0xbb; 32]);
+        assert_eq!(psbt.tx.outputs[0].value, 60_000_000);
+        let change = psbt.tx.outputs[1].value;
+        // 2 inputs of ~104 vB each plus ~94 vB of outputs and overhead at 10 sat/vB
+        assert!((20_000_000 - 3_200..20_000_000 - 2_500).contains(&change));
+        assert_eq!(Psbt::from_base64(&psbt.to_base64()).unwrap(), psbt);
+
+        let alice = system.merge_signatures(&[sign(&psbt, &key(1))]).unwrap();
+        assert!(!alice.is_finalized());
+        assert!(alice.extract_tx().is_err());
+        assert_eq!(system.get_wallet("treasury").unwrap().balance, amount!(1));
+
+        // Carol signs a copy that went out before alice signed
+        let merged = system.merge_signatures(&[sign(&psbt, &key(3))]).unwrap();
+        assert!(merged.is_finalized());
+        assert_eq!(system.get_psbt(op), Some(&merged));
+        let status = &system.get_multisig_withdrawal(op).unwrap();
+        assert_eq!(status.status, OperationStatus::Executed);
+        assert!(status.signatures.contains("carol"));
+        assert_eq!(system.get_wallet("treasury").unwrap().balance, amount!(0.4));
+
+        let tx = merged.extract_tx().unwrap();
+        assert_eq!(tx[..6], [0x02, 0, 0, 0, 0x00, 0x01]);
+        let witness = merged.inputs[0].final_script_witness.as_ref().unwrap();
+        // Dummy, two signatures, witness script
+        assert_eq!(witness.len(), 4);
+        assert!(witness[0].is_empty());
+    }
+
+    #[test]
+    fn test_merge_rejections() {
+        let (mut system, op) = treasury();
+        let source = Unspent(vec![utxo('a', amount!(1))]);
+        let psbt = system.build_psbt(op, &source).unwrap();
+
+        let outsider = sign(&psbt, &key(9));
+        assert!(matches!(
+            system.merge_signatures(&[outsider]),
+            Err(CustodyError::SignatureRejected(_))
+        ));
+        // bob's signature filed under alice's key
+        let mut forged = sign(&psbt, &key(2));
+        let signature = forged.inputs[0].partial_sigs.pop_first().unwrap().1;
+        forged
+            .add_signature(0, &public_key(&key(1)), &signature)
+            .unwrap();
+        assert!(matches!(
+            system.merge_signatures(&[forged]),
+            Err(CustodyError::SignatureRejected(_))
+        ));
+        let mut other = psbt.clone();
+        other.tx.lock_time = 1;
+        assert!(matches!(
+            system.merge_signatures(&[other]),
+            Err(CustodyError::InvalidPsbt(_))
+        ));
+        assert_eq!(system.get_psbt(op), Some(&psbt));
+
+        let poor = Unspent(vec![utxo('a', amount!(0.6))]);
+        assert!(matches!(
+            system.build_psbt(op, &poor),
+            Err(CustodyError::InvalidPsbt(_))
+        ));
+        assert!(matches!(
+            system.set_multisig_keys("treasury", &[("alice", "02ab")]),
+            Err(CustodyError::InvalidMultisigKeys(_))
+        ));
+        assert!(Psbt::parse(b"psbt\xff\x00").is_err());
+    }
+
+    #[test]
+    fn test_address_scripts() {
+        let mainnet = BitcoinAddressValidator::mainnet();
+        let segwit = mainnet.script_pubkey(&DESTINATION.to_uppercase()).unwrap();
+        assert_eq!(
+            hex::encode(&segwit),
+            "0014751e76e8199196d454941c45d1b3a323f1433bd6"
+        );
+        assert_eq!(mainnet.segwit_address(0, &segwit[2..]), DESTINATION);
+        assert_eq!(
+            hex::encode(
+                mainnet
+                    .script_pubkey("1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN2")
+                    .unwrap()
+            ),
+            "76a91477bff20c60e522dfaa3350c39b030a5d004e839a88ac"
+        );
+        assert_eq!(
+            hex::encode(
+                mainnet
+                    .script_pubkey("3J98t1WpEZ73CNmQviecrnyiWrnqRhWNLy")
+                    .unwrap()
+            ),
+            "a914b472a266d0bd89c13706a4132ccfb16f7c3b9fcb87"
+        );
+        assert!(matches!(
+            script_pubkey("tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx"),
+            Ok(script) if script.len() == 22
+        ));
+    }
+}
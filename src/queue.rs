@@ -0,0 +1,423 @@
+//! Rate-limited withdrawal release queue.
+//!
+//! Withdrawals placed in the queue are validated on entry and released for
+//! execution by [`CustodySystem::release_due_withdrawals`], at most
+//! [`ReleaseRate::max_per_window`] per window and optionally only during
+//! business hours. Highest priority goes first, then oldest. Operators can
+//! pause the queue, e.g. during a fee spike or a suspected compromise,
+//! without losing queued requests.
+
+use crate::precheck::Authorization;
+use crate::time::{FixedOffset, Timestamp};
+use crate::{Amount, CustodyError, CustodySystem};
+use chrono::{Datelike, Timelike, Weekday};
+use serde::{Deserialize, Serialize};
+
+/// Hours during which the queue releases withdrawals, Monday to Friday
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BusinessHours {
+    /// First hour of the day included, 0-23
+    pub start_hour: u32,
+    /// First hour of the day excluded, 1-24
+    pub end_hour: u32,
+    /// UTC offset the hours are expressed in
+    #[serde(with = "offset_secs")]
+    pub offset: FixedOffset,
+}
+
+impl BusinessHours {
+    /// Returns true if `at` falls within business hours
+    pub fn contains(&self, at: Timestamp) -> bool {
+        let local = at.in_offset(self.offset);
+        !matches!(local.weekday(), Weekday::Sat | Weekday::Sun)
+            && (self.start_hour..self.end_hour).contains(&local.hour())
+    }
+}
+
+/// How fast the queue releases withdrawals
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReleaseRate {
+    /// Maximum releases within any window
+    pub max_per_window: usize,
+    /// Length of the sliding window in seconds
+    pub window_secs: u64,
+    /// Restricts releases to business hours, if set
+    pub business_hours: Option<BusinessHours>,
+}
+
+impl Default for ReleaseRate {
+    fn default() -> Self {
+        Self {
+            max_per_window: 10,
+            window_secs: 60,
+            business_hours: None,
+        }
+    }
+}
+
+/// A withdrawal waiting in the queue
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QueuedWithdrawal {
+    pub id: u64,
+    pub wallet_id: String,
+    pub amount: Amount,
+    pub destination: Option<String>,
+    /// Higher priorities are released first
+    pub priority: i32,
+    pub queued_at: Timestamp,
+}
+
+/// A withdrawal taken off the queue and its execution outcome
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReleasedWithdrawal {
+    pub withdrawal: QueuedWithdrawal,
+    pub outcome: Result<(), CustodyError>,
+}
+
+/// Queue state held by the custody system
+#[derive(Debug, Clone, Default)]
+pub(crate) struct WithdrawalQueue {
+    entries: im::Vector<QueuedWithdrawal>,
+    /// When recent withdrawals were released, oldest first
+    released: im::Vector<Timestamp>,
+    rate: ReleaseRate,
+    paused: bool,
+}
+
+impl WithdrawalQueue {
+    /// Rebuilds a queue from archived entries, with an empty release window
+    #[cfg(feature = "recovery")]
+    pub(crate) fn restored(
+        entries: Vec<QueuedWithdrawal>,
+        rate: ReleaseRate,
+        paused: bool,
+    ) -> Self {
+        Self {
+            entries: entries.into_iter().collect(),
+            released: im::Vector::new(),
+            rate,
+            paused,
+        }
+    }
+}
+
+impl CustodySystem {
+    /// Places a withdrawal in the release queue
+    ///
+    /// The withdrawal is checked now and again on release; it leaves the
+    /// queue with an error if it is no longer valid by then.
+    ///
+    /// # Returns
+    /// The queue id used to reprioritize or cancel the withdrawal
+    pub fn queue_withdrawal(
+        &mut self,
+        wallet_id: &str,
+        amount: Amount,
+        destination: Option<&str>,
+        priority: i32,
+    ) -> Result<u64, CustodyError> {
+        let reasons =
+            self.withdrawal_blockers(wallet_id, None, amount, destination, Authorization::Direct);
+        if let Some(reason) = reasons.into_iter().next() {
+            return Err(reason);
+        }
+        let id = self.allocate_operation_id();
+        self.withdrawal_queue.entries.push_back(QueuedWithdrawal {
+            id,
+            wallet_id: wallet_id.to_string(),
+            amount,
+            destination: destination.map(str::to_string),
+            priority,
+            queued_at: self.current_timestamp(),
+        });
+        Ok(id)
+    }
+
+    /// Returns queued withdrawals in release order
+    pub fn queued_withdrawals(&self) -> Vec<&QueuedWithdrawal> {
+        let mut entries: Vec<_> = self.withdrawal_queue.entries.iter().collect();
+        entries.sort_by_key(|entry| (std::cmp::Reverse(entry.priority), entry.id));
+        entries
+    }
+
+    /// Changes the priority of a queued withdrawal
+    pub fn set_withdrawal_priority(&mut self, id: u64, priority: i32) -> Result<(), CustodyError> {
+        let entry = self
+            .withdrawal_queue
+            .entries
+            .iter_mut()
+            .find(|entry| entry.id == id)
+            .ok_or(CustodyError::QueuedWithdrawalNotFound(id))?;
+        entry.priority = priority;
+        Ok(())
+    }
+
+    /// Removes a withdrawal from the queue without executing it
+    pub fn cancel_queued_withdrawal(&mut self, id: u64) -> Result<QueuedWithdrawal, CustodyError> {
+        let index = self
+            .withdrawal_queue
+            .entries
+            .iter()
+            .position(|entry| entry.id == id)
+            .ok_or(CustodyError::QueuedWithdrawalNotFound(id))?;
+        Ok(self.withdrawal_queue.entries.remove(index))
+    }
+
+    /// Sets how fast the queue releases withdrawals
+    pub fn set_release_rate(&mut self, rate: ReleaseRate) {
+        self.withdrawal_queue.rate = rate;
+    }
+
+    /// Gets the current release rate
+    pub fn release_rate(&self) -> &ReleaseRate {
+        &self.withdrawal_queue.rate
+    }
+
+    /// Stops releases until [`resume_withdrawal_queue`](Self::resume_withdrawal_queue)
+    pub fn pause_withdrawal_queue(&mut self) {
+        self.withdrawal_queue.paused = true;
+    }
+
+    /// Resumes releases after a pause
+    pub fn resume_withdrawal_queue(&mut self) {
+        self.withdrawal_queue.paused = false;
+    }
+
+    /// Returns true if the queue is paused
+    pub fn is_withdrawal_queue_paused(&self) -> bool {
+        self.withdrawal_queue.paused
+    }
+
+    /// Releases as many queued withdrawals as the rate allows at `now` and
+    /// executes them
+    ///
+    /// Call this periodically, e.g. from a scheduler tick. Withdrawals that
+    /// fail their checks on release are dropped from the queue, reported
+    /// with their error, and do not count against the rate.
+    ///
+    /// # Example
+    /// ```
+    /// use securevault::{amount, CustodySystem, ReleaseRate, Timestamp, WalletType};
+    /// let mut system = CustodySystem::new();
+    /// system.create_wallet("w".to_string(), "0x1".to_string(), WalletType::Hot).unwrap();
+    /// system.deposit("w", amount!(10.0)).unwrap();
+    /// system.set_release_rate(ReleaseRate { max_per_window: 1, ..ReleaseRate::default() });
+    ///
+    /// system.queue_withdrawal("w", amount!(1.0), Some("bc1q..."), 0).unwrap();
+    /// system.queue_withdrawal("w", amount!(2.0), Some("bc1q..."), 5).unwrap();
+    ///
+    /// let released = system.release_withdrawals(Timestamp::from_unix(1_700_000_000));
+    /// assert_eq!(released.len(), 1);
+    /// assert_eq!(released[0].withdrawal.amount, amount!(2.0));
+    /// assert_eq!(system.queued_withdrawals().len(), 1);
+    /// ```
+    pub fn release_withdrawals(&mut self, now: Timestamp) -> Vec<ReleasedWithdrawal> {
+        let rate = self.withdrawal_queue.rate.clone();
+        if self.withdrawal_queue.paused
+            || rate
+                .business_hours
+                .as_ref()
+                .is_some_and(|hours| !hours.contains(now))
+        {
+            return Vec::new();
+        }
+
+        let window_start = now.as_unix().saturating_sub(rate.window_secs);
+        self.withdrawal_queue
+            .released
+            .retain(|at| at.as_unix() > window_start);
+
+        let mut released = Vec::new();
+        while self.withdrawal_queue.released.len() < rate.max_per_window {
+            let Some(next) = self.queued_withdrawals().first().map(|entry| entry.id) else {
+                break;
+            };
+            let withdrawal = self
+                .cancel_queued_withdrawal(next)
+                .expect("id taken from the queue");
+            let outcome = self.execute_queued(&withdrawal);
+            if outcome.is_ok() {
+                self.withdrawal_queue.released.push_back(now);
+            }
+            released.push(ReleasedWithdrawal {
+                withdrawal,
+                outcome,
+            });
+        }
+        released
+    }
+
+    /// Releases queued withdrawals as of the system clock's current time
+    ///
+    /// See [`release_withdrawals`](Self::release_withdrawals); a
+    /// [`ManualClock`](crate::time::ManualClock) makes scheduled releases
+    /// deterministic in tests and replays.
+    pub fn release_due_withdrawals(&mut self) -> Vec<ReleasedWithdrawal> {
+        self.release_withdrawals(self.current_timestamp())
+    }
+
+    fn execute_queued(&mut self, withdrawal: &QueuedWithdrawal) -> Result<(), CustodyError> {
+        self.execute_withdrawal(
+            &withdrawal.wallet_id,
+            None,
+            withdrawal.amount,
+            withdrawal.destination.as_deref(),
+            Authorization::Direct,
+        )
+    }
+}
+
+/// Serializes a UTC offset as seconds east of UTC
+mod offset_secs {
+    use crate::time::FixedOffset;
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        offset: &FixedOffset,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_i32(offset.local_minus_utc())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<FixedOffset, D::Error> {
+        let secs = i32::deserialize(deserializer)?;
+        FixedOffset::east_opt(secs).ok_or_else(|| de::Error::custom("UTC offset out of range"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::time::ManualClock;
+    use crate::WalletType;
+    use std::sync::Arc;
+
+    fn system() -> CustodySystem {
+        let mut system = CustodySystem::new();
+        system
+            .create_wallet("a".to_string(), "0x1".to_string(), WalletType::Hot)
+            .unwrap();
+        system.deposit("a", amount!(100.0)).unwrap();
+        system.set_release_rate(ReleaseRate {
+            max_per_window: 2,
+            window_secs: 60,
+            business_hours: None,
+        });
+        system
+    }
+
+    #[test]
+    fn test_release_respects_rate_and_priority() {
+        let mut system = system();
+        let low = system.queue_withdrawal("a", amount!(1.0), None, 0).unwrap();
+        let high = system
+            .queue_withdrawal("a", amount!(2.0), None, 10)
+            .unwrap();
+        let mid = system.queue_withdrawal("a", amount!(3.0), None, 5).unwrap();
+
+        let t0 = Timestamp::from_unix(1_700_000_000);
+        let ids: Vec<u64> = system
+            .release_withdrawals(t0)
+            .iter()
+            .map(|r| r.withdrawal.id)
+            .collect();
+        assert_eq!(ids, vec![high, mid]);
+        assert!(system
+            .release_withdrawals(Timestamp::from_unix(1_700_000_059))
+            .is_empty());
+
+        let released = system.release_withdrawals(Timestamp::from_unix(1_700_000_060));
+        assert_eq!(released[0].withdrawal.id, low);
+        assert_eq!(system.get_wallet("a").unwrap().balance, amount!(94.0));
+    }
+
+    #[test]
+    fn test_pause_and_reprioritize() {
+        let mut system = system();
+        let first = system.queue_withdrawal("a", amount!(1.0), None, 0).unwrap();
+        let second = system.queue_withdrawal("a", amount!(1.0), None, 0).unwrap();
+        system.set_withdrawal_priority(second, 1).unwrap();
+        assert_eq!(system.queued_withdrawals()[0].id, second);
+
+        system.pause_withdrawal_queue();
+        assert!(system.release_due_withdrawals().is_empty());
+        assert_eq!(system.queued_withdrawals().len(), 2);
+
+        system.resume_withdrawal_queue();
+        system.cancel_queued_withdrawal(first).unwrap();
+        assert_eq!(system.release_due_withdrawals().len(), 1);
+        assert_eq!(
+            system.cancel_queued_withdrawal(first),
+            Err(CustodyError::QueuedWithdrawalNotFound(first))
+        );
+    }
+
+    #[test]
+    fn test_invalid_withdrawals_fail_on_release() {
+        let mut system = system();
+        assert!(system
+            .queue_withdrawal("a", amount!(500.0), None, 0)
+            .is_err());
+
+        system
+            .queue_withdrawal("a", amount!(80.0), None, 1)
+            .unwrap();
+        system
+            .queue_withdrawal("a", amount!(80.0), None, 0)
+            .unwrap();
+        let released = system.release_due_withdrawals();
+        assert_eq!(released.len(), 2);
+        assert!(released[0].outcome.is_ok());
+        assert!(matches!(
+            released[1].outcome,
+            Err(CustodyError::InsufficientBalance { .. })
+        ));
+    }
+
+    #[test]
+    fn test_due_releases_follow_the_clock() {
+        let clock = Arc::new(ManualClock::new(Timestamp::from_unix(1_700_000_000)));
+        let mut system = system();
+        system.set_clock(clock.clone());
+        for _ in 0..3 {
+            system.queue_withdrawal("a", amount!(1.0), None, 0).unwrap();
+        }
+        assert_eq!(system.release_due_withdrawals().len(), 2);
+        clock.advance(59);
+        assert!(system.release_due_withdrawals().is_empty());
+        clock.advance(1);
+        assert_eq!(system.release_due_withdrawals().len(), 1);
+    }
+
+    #[test]
+    fn test_business_hours() {
+        let hours = BusinessHours {
+            start_hour: 9,
+            end_hour: 17,
+            offset: FixedOffset::east_opt(0).unwrap(),
+        };
+        // Tuesday 2023-11-14
+        assert!(hours.contains(Timestamp::from_unix(1_699_952_400))); // 09:00
+        assert!(!hours.contains(Timestamp::from_unix(1_699_981_200))); // 17:00
+                                                                       // Saturday 2023-11-18 12:00
+        assert!(!hours.contains(Timestamp::from_unix(1_700_308_800)));
+
+        let mut system = system();
+        system.set_release_rate(ReleaseRate {
+            business_hours: Some(hours),
+            ..system.release_rate().clone()
+        });
+        system.queue_withdrawal("a", amount!(1.0), None, 0).unwrap();
+        assert!(system
+            .release_withdrawals(Timestamp::from_unix(1_700_308_800))
+            .is_empty());
+        assert_eq!(
+            system
+                .release_withdrawals(Timestamp::from_unix(1_699_952_400))
+                .len(),
+            1
+        );
+    }
+}
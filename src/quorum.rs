@@ -0,0 +1,270 @@
+//! Signing quorums.
+//!
+//! Every wallet can carry an m-of-n [`Quorum`] describing how many of its
+//! signers must approve. Defaults are configured per [`WalletType`] (e.g.
+//! hot 1-of-2, cold 3-of-5) and copied onto wallets when they are created,
+//! so changing a default never silently weakens existing wallets.
+//!
+//! Once a wallet has a quorum, replacing it is itself subject to that
+//! quorum: a change executes only after `required` distinct approvers have
+//! signed it. A change of a cold wallet's withdrawal approvers goes through
+//! the same flow, but is signed off by the current approvers.
+
+use crate::{CustodyError, CustodySystem, OperationStatus, WalletType};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fmt;
+
+/// An m-of-n signing requirement
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Quorum {
+    /// Signatures needed
+    pub required: usize,
+    /// Signers holding keys
+    pub signers: usize,
+}
+
+impl Quorum {
+    /// Creates a quorum, rejecting zero thresholds and thresholds above the
+    /// number of signers
+    pub fn new(required: usize, signers: usize) -> Result<Self, CustodyError> {
+        if required == 0 || required > signers {
+            return Err(CustodyError::InvalidThreshold {
+                required,
+                available: signers,
+            });
+        }
+        Ok(Self { required, signers })
+    }
+}
+
+impl fmt::Display for Quorum {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-of-{}", self.required, self.signers)
+    }
+}
+
+/// A proposed replacement of a wallet's quorum
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuorumChange {
+    pub id: u64,
+    pub wallet_id: String,
+    pub quorum: Quorum,
+    /// Replacement withdrawal approvers; `None` leaves them as they are
+    pub approvers: Option<BTreeSet<String>>,
+    /// Distinct approvers, including the proposer
+    pub approvals: BTreeSet<String>,
+    pub status: OperationStatus,
+}
+
+impl CustodySystem {
+    /// Sets the quorum applied to wallets of `wallet_type` created from now
+    /// on
+    pub fn set_default_quorum(&mut self, wallet_type: WalletType, quorum: Quorum) {
+        self.default_quorums.insert(wallet_type, quorum);
+    }
+
+    /// Gets the quorum applied to new wallets of `wallet_type`, if any
+    pub fn default_quorum(&self, wallet_type: &WalletType) -> Option<Quorum> {
+        self.default_quorums.get(wallet_type).copied()
+    }
+
+    /// Proposes a new quorum for a wallet, approved by `approver`
+    ///
+    /// Wallets without a quorum, or whose quorum needs a single signature,
+    /// take the change immediately; otherwise it waits for
+    /// [`approve_quorum_change`](Self::approve_quorum_change).
+    ///
+    /// # Example
+    /// ```
+    /// use securevault::{CustodySystem, OperationStatus, Quorum, WalletType};
+    /// let mut system = CustodySystem::new();
+    /// system.set_default_quorum(WalletType::Cold, Quorum::new(3, 5).unwrap());
+    /// system.create_wallet("vault".to_string(), "bc1q...".to_string(), WalletType::Cold).unwrap();
+    ///
+    /// let id = system
+    ///     .propose_quorum_change("vault", "alice", Quorum::new(2, 3).unwrap())
+    ///     .unwrap();
+    /// system.approve_quorum_change(id, "bob").unwrap();
+    /// let status = system.approve_quorum_change(id, "carol").unwrap();
+    /// assert_eq!(status, OperationStatus::Executed);
+    /// assert_eq!(system.get_wallet("vault").unwrap().quorum.unwrap().to_string(), "2-of-3");
+    /// ```
+    pub fn propose_quorum_change(
+        &mut self,
+        wallet_id: &str,
+        approver: &str,
+        quorum: Quorum,
+    ) -> Result<u64, CustodyError> {
+        if !self.wallet_exists(wallet_id) {
+            return Err(CustodyError::WalletNotFound(wallet_id.to_string()));
+        }
+        Quorum::new(quorum.required, quorum.signers)?;
+        self.open_quorum_change(wallet_id, approver, quorum, None)
+    }
+
+    pub(crate) fn open_quorum_change(
+        &mut self,
+        wallet_id: &str,
+        approver: &str,
+        quorum: Quorum,
+        approvers: Option<BTreeSet<String>>,
+    ) -> Result<u64, CustodyError> {
+        let id = self.allocate_operation_id();
+        self.quorum_changes.insert(
+            id,
+            QuorumChange {
+                id,
+                wallet_id: wallet_id.to_string(),
+                quorum,
+                approvers,
+                approvals: BTreeSet::from([approver.to_string()]),
+                status: OperationStatus::Pending,
+            },
+        );
+        if let Err(err) = self.try_apply_quorum_change(id) {
+            self.quorum_changes.remove(&id);
+            return Err(err);
+        }
+        Ok(id)
+    }
+
+    /// Adds `approver`'s approval to a pending quorum change, applying it
+    /// once the wallet's current quorum is met
+    ///
+    /// A change of withdrawal approvers only takes approvals from the
+    /// current approvers, and applies once a withdrawal would.
+    pub fn approve_quorum_change(
+        &mut self,
+        change_id: u64,
+        approver: &str,
+    ) -> Result<OperationStatus, CustodyError> {
+        let change = self
+            .quorum_changes
+            .get(&change_id)
+            .ok_or(CustodyError::OperationNotFound(change_id))?;
+        if change.status != OperationStatus::Pending {
+            return Err(CustodyError::OperationNotPending(change_id));
+        }
+        if change.approvers.is_some() {
+            self.check_approver(&change.wallet_id, approver)?;
+        }
+        if let Some(change) = self.quorum_changes.get_mut(&change_id) {
+            change.approvals.insert(approver.to_string());
+        }
+        self.try_apply_quorum_change(change_id)
+    }
+
+    /// Gets a quorum change by id
+    pub fn get_quorum_change(&self, change_id: u64) -> Option<&QuorumChange> {
+        self.quorum_changes.get(&change_id)
+    }
+
+    fn try_apply_quorum_change(&mut self, change_id: u64) -> Result<OperationStatus, CustodyError> {
+        let change = self.quorum_changes[&change_id].clone();
+        let (approved, needed) = if change.approvers.is_some() {
+            self.approver_tally(&change.wallet_id, &change.approvals)
+        } else {
+            let needed = self
+                .wallets
+                .get(&change.wallet_id)
+                .and_then(|wallet| wallet.quorum)
+                .map_or(1, |quorum| quorum.required);
+            (change.approvals.len(), needed)
+        };
+        if approved < needed {
+            return Ok(OperationStatus::Pending);
+        }
+
+        if let Some(approvers) = change.approvers {
+            self.withdrawal_approvers
+                .insert(change.wallet_id.clone(), approvers);
+        } else if let Some(mut wallet) = self.wallets.get(&change.wallet_id).cloned() {
+            wallet.quorum = Some(change.quorum);
+            self.commit_wallet(wallet)?;
+        }
+        if let Some(change) = self.quorum_changes.get_mut(&change_id) {
+            change.status = OperationStatus::Executed;
+        }
+        Ok(OperationStatus::Executed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn system() -> CustodySystem {
+        let mut system = CustodySystem::new();
+        system.set_default_quorum(WalletType::Hot, Quorum::new(1, 2).unwrap());
+        system.set_default_quorum(WalletType::Cold, Quorum::new(3, 5).unwrap());
+        system
+            .create_wallet("hot".to_string(), "0x1".to_string(), WalletType::Hot)
+            .unwrap();
+        system
+            .create_wallet("cold".to_string(), "0x2".to_string(), WalletType::Cold)
+            .unwrap();
+        system
+    }
+
+    #[test]
+    fn test_defaults_apply_at_creation() {
+        let mut system = system();
+        assert_eq!(
+            system.get_wallet("cold").unwrap().quorum,
+            Some(Quorum::new(3, 5).unwrap())
+        );
+
+        // Changing a default leaves existing wallets alone
+        system.set_default_quorum(WalletType::Cold, Quorum::new(2, 3).unwrap());
+        assert_eq!(
+            system.get_wallet("cold").unwrap().quorum.unwrap().required,
+            3
+        );
+    }
+
+    #[test]
+    fn test_change_needs_current_quorum() {
+        let mut system = system();
+        let id = system
+            .propose_quorum_change("cold", "alice", Quorum::new(1, 1).unwrap())
+            .unwrap();
+        // Repeated approvals by the same approver do not count twice
+        assert_eq!(
+            system.approve_quorum_change(id, "alice").unwrap(),
+            OperationStatus::Pending
+        );
+        assert_eq!(
+            system.approve_quorum_change(id, "bob").unwrap(),
+            OperationStatus::Pending
+        );
+        assert_eq!(
+            system.approve_quorum_change(id, "carol").unwrap(),
+            OperationStatus::Executed
+        );
+        assert_eq!(
+            system.get_wallet("cold").unwrap().quorum.unwrap().signers,
+            1
+        );
+        assert_eq!(
+            system.approve_quorum_change(id, "dave"),
+            Err(CustodyError::OperationNotPending(id))
+        );
+    }
+
+    #[test]
+    fn test_single_signature_quorum_applies_immediately() {
+        let mut system = system();
+        let id = system
+            .propose_quorum_change("hot", "alice", Quorum::new(2, 3).unwrap())
+            .unwrap();
+        assert_eq!(
+            system.get_quorum_change(id).unwrap().status,
+            OperationStatus::Executed
+        );
+        assert!(matches!(
+            Quorum::new(4, 3),
+            Err(CustodyError::InvalidThreshold { .. })
+        ));
+    }
+}
@@ -0,0 +1,353 @@
+//! Token-bucket rate limits on debits and operator calls.
+//!
+//! A limit of `burst` operations per `per_secs` seconds gives every wallet,
+//! or every operator, a bucket of `burst` tokens that refills steadily at
+//! that rate. Each withdrawal or outgoing transfer takes a token from the
+//! source wallet's bucket; each mutating call made with an API key takes
+//! one from the key's operator bucket, as does
+//! [`CustodySystem::throttle_operator`] for callers identifying operators
+//! their own way. An empty bucket fails the operation with
+//! [`CustodyError::RateLimited`], saying when the next token is due, so a
+//! compromised key cannot drain a wallet through thousands of small
+//! withdrawals.
+
+use crate::time::Timestamp;
+use crate::{AdminAction, CustodyError, CustodySystem};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// At most `burst` operations at once, refilling at `burst` per `per_secs`
+/// seconds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RateLimit {
+    pub burst: u32,
+    pub per_secs: u64,
+}
+
+impl RateLimit {
+    /// Creates a limit of `burst` operations per `per_secs` seconds
+    pub fn new(burst: u32, per_secs: u64) -> Self {
+        Self { burst, per_secs }
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), CustodyError> {
+        if self.burst == 0 || self.per_secs == 0 {
+            return Err(CustodyError::InvalidConfig(format!(
+                "rate limit of {} per {} s allows nothing",
+                self.burst, self.per_secs
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Who a bucket belongs to
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) enum RateSubject {
+    Wallet(String),
+    Operator(String),
+}
+
+impl fmt::Display for RateSubject {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RateSubject::Wallet(id) => write!(f, "wallet '{}'", id),
+            RateSubject::Operator(id) => write!(f, "operator '{}'", id),
+        }
+    }
+}
+
+/// Tokens are counted in units of 1/`per_secs`, so that refilling `burst`
+/// of them per second is exact
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    units: u128,
+    updated: Timestamp,
+}
+
+impl Bucket {
+    fn full(limit: RateLimit, now: Timestamp) -> Self {
+        Self {
+            units: capacity(limit),
+            updated: now,
+        }
+    }
+
+    /// Returns the bucket as of `now`
+    fn refilled(self, limit: RateLimit, now: Timestamp) -> Self {
+        let elapsed = now.as_unix().saturating_sub(self.updated.as_unix()) as u128;
+        Self {
+            units: (self.units + elapsed * limit.burst as u128).min(capacity(limit)),
+            updated: now.max(self.updated),
+        }
+    }
+
+    /// Returns the seconds until a token is available, or `None` if one is
+    fn retry_after(&self, limit: RateLimit) -> Option<u64> {
+        let missing = (limit.per_secs as u128).checked_sub(self.units)?;
+        (missing > 0).then(|| missing.div_ceil(limit.burst as u128) as u64)
+    }
+}
+
+fn capacity(limit: RateLimit) -> u128 {
+    limit.burst as u128 * limit.per_secs as u128
+}
+
+/// Configured limits and the buckets they fill
+#[derive(Debug, Clone, Default)]
+pub(crate) struct RateLimits {
+    wallet: Option<RateLimit>,
+    operator: Option<RateLimit>,
+    buckets: im::HashMap<RateSubject, Bucket>,
+}
+
+impl RateLimits {
+    fn limit(&self, subject: &RateSubject) -> Option<RateLimit> {
+        match subject {
+            RateSubject::Wallet(_) => self.wallet,
+            RateSubject::Operator(_) => self.operator,
+        }
+    }
+
+    fn bucket(&self, subject: &RateSubject, limit: RateLimit, now: Timestamp) -> Bucket {
+        self.buckets
+            .get(subject)
+            .map_or_else(|| Bucket::full(limit, now), |b| b.refilled(limit, now))
+    }
+
+    /// Fails if `subject` has no token left at `now`
+    fn check(&self, subject: &RateSubject, now: Timestamp) -> Result<(), CustodyError> {
+        let Some(limit) = self.limit(subject) else {
+            return Ok(());
+        };
+        match self.bucket(subject, limit, now).retry_after(limit) {
+            Some(retry_after_secs) => Err(CustodyError::RateLimited {
+                subject: subject.to_string(),
+                retry_after_secs,
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Takes a token from `subject`'s bucket, if it is limited
+    fn take(&mut self, subject: RateSubject, now: Timestamp) {
+        let Some(limit) = self.limit(&subject) else {
+            return;
+        };
+        let mut bucket = self.bucket(&subject, limit, now);
+        bucket.units = bucket.units.saturating_sub(limit.per_secs as u128);
+        self.buckets.insert(subject, bucket);
+    }
+
+    fn reset(&mut self, wallets: bool) {
+        self.buckets
+            .retain(|subject, _| matches!(subject, RateSubject::Wallet(_)) != wallets);
+    }
+}
+
+impl CustodySystem {
+    /// Limits withdrawals and outgoing transfers from each wallet, or lifts
+    /// the limit with `None`
+    ///
+    /// # Errors
+    /// [`CustodyError::InvalidConfig`] if the limit allows nothing
+    ///
+    /// # Example
+    /// ```
+    /// use securevault::time::{ManualClock, Timestamp};
+    /// use securevault::{amount, CustodyError, CustodySystem, RateLimit, WalletType};
+    /// use std::sync::Arc;
+    ///
+    /// let clock = Arc::new(ManualClock::new(Timestamp::from_unix(1_700_000_000)));
+    /// let mut system = CustodySystem::new();
+    /// system.set_clock(clock.clone());
+    /// system.create_wallet("w".to_string(), "0x1".to_string(), WalletType::Hot).unwrap();
+    /// system.deposit("w", amount!(10)).unwrap();
+    ///
+    /// // Two withdrawals a minute
+    /// system.set_wallet_rate_limit(Some(RateLimit::new(2, 60))).unwrap();
+    /// system.withdraw("w", amount!(1)).unwrap();
+    /// system.withdraw("w", amount!(1)).unwrap();
+    /// assert_eq!(
+    ///     system.withdraw("w", amount!(1)),
+    ///     Err(CustodyError::RateLimited { subject: "wallet 'w'".to_string(), retry_after_secs: 30 })
+    /// );
+    /// clock.advance(30);
+    /// system.withdraw("w", amount!(1)).unwrap();
+    /// ```
+    pub fn set_wallet_rate_limit(&mut self, limit: Option<RateLimit>) -> Result<(), CustodyError> {
+        self.require_second_operator(AdminAction::SET_WALLET_RATE_LIMIT)?;
+        self.replace_wallet_rate_limit(limit)
+    }
+
+    pub(crate) fn replace_wallet_rate_limit(
+        &mut self,
+        limit: Option<RateLimit>,
+    ) -> Result<(), CustodyError> {
+        limit.as_ref().map(RateLimit::validate).transpose()?;
+        self.rate_limits.wallet = limit;
+        self.rate_limits.reset(true);
+        Ok(())
+    }
+
+    /// Limits the mutating calls each operator makes, or lifts the limit
+    /// with `None`
+    pub fn set_operator_rate_limit(
+        &mut self,
+        limit: Option<RateLimit>,
+    ) -> Result<(), CustodyError> {
+        self.require_second_operator(AdminAction::SET_OPERATOR_RATE_LIMIT)?;
+        self.replace_operator_rate_limit(limit)
+    }
+
+    pub(crate) fn replace_operator_rate_limit(
+        &mut self,
+        limit: Option<RateLimit>,
+    ) -> Result<(), CustodyError> {
+        limit.as_ref().map(RateLimit::validate).transpose()?;
+        self.rate_limits.operator = limit;
+        self.rate_limits.reset(false);
+        Ok(())
+    }
+
+    /// Returns the per-wallet limit, if one is set
+    pub fn wallet_rate_limit(&self) -> Option<RateLimit> {
+        self.rate_limits.wallet
+    }
+
+    /// Returns the per-operator limit, if one is set
+    pub fn operator_rate_limit(&self) -> Option<RateLimit> {
+        self.rate_limits.operator
+    }
+
+    /// Takes a token from `operator`'s bucket
+    ///
+    /// # Errors
+    /// [`CustodyError::RateLimited`] if the operator has none left; nothing
+    /// is taken then
+    pub fn throttle_operator(&mut self, operator: &str) -> Result<(), CustodyError> {
+        let subject = RateSubject::Operator(operator.to_string());
+        let now = self.current_timestamp();
+        self.rate_limits.check(&subject, now)?;
+        self.rate_limits.take(subject, now);
+        Ok(())
+    }
+
+    /// Checks that the wallet may be debited again
+    pub(crate) fn rate_limit_blockers(&self, wallet_id: &str) -> Option<CustodyError> {
+        self.rate_limits
+            .check(
+                &RateSubject::Wallet(wallet_id.to_string()),
+                self.current_timestamp(),
+            )
+            .err()
+    }
+
+    /// Records a debit against the wallet's bucket
+    pub(crate) fn take_wallet_token(&mut self, wallet_id: &str) {
+        let now = self.current_timestamp();
+        self.rate_limits
+            .take(RateSubject::Wallet(wallet_id.to_string()), now);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::time::ManualClock;
+    use crate::WalletType;
+    use std::sync::Arc;
+
+    fn system() -> (CustodySystem, Arc<ManualClock>) {
+        let clock = Arc::new(ManualClock::new(Timestamp::from_unix(1_700_000_000)));
+        let mut system = CustodySystem::new();
+        system.set_clock(clock.clone());
+        for id in ["a", "b"] {
+            system
+                .create_wallet(id.to_string(), id.to_string(), WalletType::Hot)
+                .unwrap();
+            system.deposit(id, amount!(100)).unwrap();
+        }
+        (system, clock)
+    }
+
+    fn retry_after(result: Result<(), CustodyError>) -> u64 {
+        match result {
+            Err(CustodyError::RateLimited {
+                retry_after_secs, ..
+            }) => retry_after_secs,
+            other => panic!("expected a rate limit, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_wallet_bucket_refills() {
+        let (mut system, clock) = system();
+        system
+            .set_wallet_rate_limit(Some(RateLimit::new(3, 10)))
+            .unwrap();
+        system.withdraw("a", amount!(1)).unwrap();
+        system.transfer("a", "b", amount!(1)).unwrap();
+        system.withdraw("a", amount!(1)).unwrap();
+        // One token every 10/3 s
+        assert_eq!(retry_after(system.withdraw("a", amount!(1))), 4);
+        assert_eq!(retry_after(system.transfer("a", "b", amount!(1))), 4);
+        assert!(!system.can_withdraw("a", amount!(1), None).is_allowed());
+        // Deposits and other wallets are not limited
+        system.deposit("a", amount!(1)).unwrap();
+        system.withdraw("b", amount!(1)).unwrap();
+
+        clock.advance(3);
+        assert_eq!(retry_after(system.withdraw("a", amount!(1))), 1);
+        clock.advance(1);
+        system.withdraw("a", amount!(1)).unwrap();
+        // Idle time refills up to the burst and no further
+        clock.advance(3600);
+        for _ in 0..3 {
+            system.withdraw("a", amount!(1)).unwrap();
+        }
+        assert!(system.withdraw("a", amount!(1)).is_err());
+
+        system.set_wallet_rate_limit(None).unwrap();
+        system.withdraw("a", amount!(1)).unwrap();
+        assert!(matches!(
+            system.set_wallet_rate_limit(Some(RateLimit::new(0, 10))),
+            Err(CustodyError::InvalidConfig(_))
+        ));
+    }
+
+    #[test]
+    fn test_operator_buckets() {
+        let (mut system, clock) = system();
+        system
+            .set_operator_rate_limit(Some(RateLimit::new(1, 60)))
+            .unwrap();
+        system.throttle_operator("alice").unwrap();
+        system.throttle_operator("bob").unwrap();
+        assert_eq!(
+            system.throttle_operator("alice"),
+            Err(CustodyError::RateLimited {
+                subject: "operator 'alice'".to_string(),
+                retry_after_secs: 60,
+            })
+        );
+
+        let (key, token) = system
+            .create_api_key("bot", crate::KeyScope::full(), None)
+            .unwrap();
+        let mut keyed = system.with_key(&token).unwrap();
+        keyed.get_wallet("a").unwrap();
+        keyed.deposit("a", amount!(1)).unwrap();
+        assert_eq!(
+            keyed.withdraw("a", amount!(1)),
+            Err(CustodyError::RateLimited {
+                subject: format!("operator '{}'", key.id),
+                retry_after_secs: 60,
+            })
+        );
+        // Reads do not count
+        keyed.get_wallet("a").unwrap();
+        clock.advance(60);
+        keyed.withdraw("a", amount!(1)).unwrap();
+    }
+}
@@ -0,0 +1,217 @@
+//! Reconciliation against externally reported balances.
+//!
+//! The ledger only knows what was recorded through it. Comparing it with
+//! the balances a node or block explorer reports for each address detects
+//! deposits that were never credited and outflows that were never
+//! authorized.
+
+use crate::{Amount, Asset, CustodySystem};
+use std::collections::BTreeMap;
+
+/// Collects externally reported balances and reconciles them with a ledger
+#[derive(Debug, Clone, Default)]
+pub struct Reconciler {
+    reported: BTreeMap<(String, Asset), Amount>,
+}
+
+/// A reported balance that agrees with the ledger
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReconciledBalance {
+    pub address: String,
+    pub asset: Asset,
+    /// Wallets at the address, sorted by id
+    pub wallet_ids: Vec<String>,
+    pub balance: Amount,
+}
+
+/// A reported balance that differs from the ledger
+#[derive(Debug, Clone, PartialEq)]
+pub struct Discrepancy {
+    pub address: String,
+    pub asset: Asset,
+    /// Wallets at the address, sorted by id
+    pub wallet_ids: Vec<String>,
+    /// Balance in the ledger, summed over the wallets at the address
+    pub recorded: Amount,
+    /// Balance reported by the external source
+    pub reported: Amount,
+}
+
+impl Discrepancy {
+    /// Reported minus recorded balance: positive when funds arrived that
+    /// were never credited, negative when funds left without a withdrawal
+    pub fn difference(&self) -> Amount {
+        self.reported - self.recorded
+    }
+}
+
+/// Outcome of a reconciliation
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ReconciliationReport {
+    /// Reported balances that match the ledger
+    pub matched: Vec<ReconciledBalance>,
+    /// Reported balances that do not match the ledger
+    pub discrepancies: Vec<Discrepancy>,
+    /// Reported addresses that no wallet uses
+    pub unknown_addresses: Vec<String>,
+}
+
+impl ReconciliationReport {
+    /// Returns true if every reported balance matches a wallet
+    pub fn is_reconciled(&self) -> bool {
+        self.discrepancies.is_empty() && self.unknown_addresses.is_empty()
+    }
+}
+
+impl Reconciler {
+    /// Creates a reconciler with no reported balances
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records the balance reported for `asset` at `address`, replacing any
+    /// earlier report for the pair
+    pub fn report_balance(
+        &mut self,
+        address: impl Into<String>,
+        asset: Asset,
+        balance: Amount,
+    ) -> &mut Self {
+        self.reported.insert((address.into(), asset), balance);
+        self
+    }
+
+    /// Number of reported balances
+    pub fn len(&self) -> usize {
+        self.reported.len()
+    }
+
+    /// Returns true if no balances were reported
+    pub fn is_empty(&self) -> bool {
+        self.reported.is_empty()
+    }
+
+    /// Compares every reported balance with the wallets at its address
+    ///
+    /// Wallets sharing an address are reconciled together. Addresses that
+    /// were not reported are left out of the report, so partial reports
+    /// from a single chain are fine.
+    ///
+    /// # Example
+    /// ```
+    /// use securevault::{amount, Asset, CustodySystem, Reconciler, WalletType};
+    /// let mut system = CustodySystem::new();
+    /// system.create_wallet("w".to_string(), "0x1".to_string(), WalletType::Hot).unwrap();
+    /// system.deposit("w", amount!(2.0)).unwrap();
+    ///
+    /// let mut reconciler = Reconciler::new();
+    /// reconciler.report_balance("0x1", Asset::Btc, amount!(2.5));
+    /// let report = reconciler.reconcile(&system);
+    /// assert_eq!(report.discrepancies[0].difference(), amount!(0.5));
+    /// ```
+    pub fn reconcile(&self, system: &CustodySystem) -> ReconciliationReport {
+        let mut by_address: BTreeMap<&str, Vec<_>> = BTreeMap::new();
+        for wallet in system.get_all_wallets().values() {
+            by_address
+                .entry(wallet.address.as_str())
+                .or_default()
+                .push(wallet);
+        }
+
+        let mut report = ReconciliationReport::default();
+        for ((address, asset), &reported) in &self.reported {
+            let Some(wallets) = by_address.get_mut(address.as_str()) else {
+                if report.unknown_addresses.last() != Some(address) {
+                    report.unknown_addresses.push(address.clone());
+                }
+                continue;
+            };
+            wallets.sort_by(|a, b| a.id.cmp(&b.id));
+            let wallet_ids = wallets.iter().map(|w| w.id.clone()).collect();
+            let recorded = wallets.iter().map(|w| w.balance_of(asset)).sum();
+            if recorded == reported {
+                report.matched.push(ReconciledBalance {
+                    address: address.clone(),
+                    asset: asset.clone(),
+                    wallet_ids,
+                    balance: reported,
+                });
+            } else {
+                report.discrepancies.push(Discrepancy {
+                    address: address.clone(),
+                    asset: asset.clone(),
+                    wallet_ids,
+                    recorded,
+                    reported,
+                });
+            }
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::WalletType;
+
+    fn system() -> CustodySystem {
+        let mut system = CustodySystem::new();
+        for (id, address) in [("a", "0xa"), ("b", "0xb"), ("c", "0xc")] {
+            system
+                .create_wallet(id.to_string(), address.to_string(), WalletType::Hot)
+                .unwrap();
+            system.deposit(id, amount!(10)).unwrap();
+        }
+        system
+    }
+
+    #[test]
+    fn test_reconcile_classifies_reported_balances() {
+        let system = system();
+        let mut reconciler = Reconciler::new();
+        reconciler
+            .report_balance("0xa", Asset::Btc, amount!(10))
+            .report_balance("0xb", Asset::Btc, amount!(12))
+            .report_balance("0xc", Asset::Btc, amount!(7.5))
+            .report_balance("0xd", Asset::Btc, amount!(1))
+            .report_balance("0xd", Asset::Eth, amount!(1));
+        let report = reconciler.reconcile(&system);
+
+        assert_eq!(report.matched.len(), 1);
+        assert_eq!(report.matched[0].wallet_ids, ["a"]);
+        let differences: Vec<_> = report
+            .discrepancies
+            .iter()
+            .map(|d| (d.address.as_str(), d.difference()))
+            .collect();
+        // A missing deposit and an unauthorized outflow
+        assert_eq!(differences, [("0xb", amount!(2)), ("0xc", amount!(-2.5))]);
+        assert_eq!(report.unknown_addresses, ["0xd"]);
+        assert!(!report.is_reconciled());
+    }
+
+    #[test]
+    fn test_wallets_sharing_an_address_reconcile_together() {
+        let mut system = system();
+        system
+            .create_wallet("a2".to_string(), "0xa".to_string(), WalletType::Cold)
+            .unwrap();
+        system.deposit("a2", amount!(5)).unwrap();
+        let mut reconciler = Reconciler::new();
+        reconciler.report_balance("0xa", Asset::Btc, amount!(15));
+        let report = reconciler.reconcile(&system);
+        assert!(report.is_reconciled());
+        assert_eq!(report.matched[0].wallet_ids, ["a", "a2"]);
+    }
+
+    #[test]
+    fn test_other_assets_compare_against_holdings() {
+        let system = system();
+        let mut reconciler = Reconciler::new();
+        reconciler.report_balance("0xa", Asset::Eth, amount!(3));
+        let report = reconciler.reconcile(&system);
+        assert_eq!(report.discrepancies[0].recorded, Amount::ZERO);
+        assert_eq!(report.discrepancies[0].difference(), amount!(3));
+    }
+}
@@ -0,0 +1,694 @@
+//! Encrypted disaster recovery archives (feature `recovery`).
+//!
+//! [`CustodySystem::export_encrypted_backup`] writes everything needed to
+//! rebuild the custody system elsewhere into one file: the ledger
+//! (wallets and the transaction log), withdrawal policies, quorums,
+//! approvers, joint ownership, whitelists, the two-person rule, rate
+//! limits, the travel-rule threshold and the queue's release rate, and the
+//! operations still in flight (withdrawals awaiting approval, signatures
+//! or release from the queue, unsigned transactions, active holds,
+//! operations held for compliance review and admin actions awaiting a
+//! second operator). [`CustodySystem::restore_encrypted_backup`] brings it
+//! back. Integrations such as signers, oracles and listeners are not part
+//! of the archive and must be set up again.
+//!
+//! The archive is encrypted with ChaCha20-Poly1305 under a key derived
+//! from the passphrase with Argon2id. The file starts with a cleartext
+//! header: magic bytes, the format version and the key derivation
+//! parameters, which the cipher authenticates along with the contents, so
+//! any change to the file is detected. Because the parameters travel with
+//! the archive, later versions can strengthen them and still read older
+//! archives; archives from a newer format version are refused. Parameters
+//! costlier than this build's defaults are refused before any key is
+//! derived, since the header is only authenticated once the key exists.
+
+use crate::queue::WithdrawalQueue;
+use crate::time::Timestamp;
+use crate::wal::write_atomically;
+use crate::whitelist::WhitelistOwner;
+use crate::{
+    Amount, Asset, CustodyError, CustodySystem, FlaggedOperation, FourEyesRule, Hold, HoldStatus,
+    JointOperation, JointOwnership, MultiSigWithdrawal, OperationStatus, PendingWithdrawal,
+    ProposedAction, QueuedWithdrawal, Quorum, RateLimit, ReleaseRate, Snapshot, UnsignedTx,
+    WalletType, WhitelistEntry, WithdrawalPolicy,
+};
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::Path;
+use zeroize::Zeroizing;
+
+/// Format version written by this build
+pub const RECOVERY_FORMAT_VERSION: u32 = 1;
+
+/// First bytes of every archive
+const MAGIC: &[u8; 8] = b"SVRECOV\0";
+
+/// Magic, version, three Argon2 parameters, salt and nonce
+const HEADER_LEN: usize = 8 + 4 + 12 + 16 + 12;
+
+/// Minimum accepted passphrase length
+const MIN_PASSPHRASE_CHARS: usize = 12;
+
+/// Withdrawal controls in force when the archive was taken
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PolicySet {
+    pub default_policies: Vec<(WalletType, WithdrawalPolicy)>,
+    pub wallet_policies: BTreeMap<String, WithdrawalPolicy>,
+    pub default_quorums: Vec<(WalletType, Quorum)>,
+    pub withdrawal_approvers: BTreeMap<String, BTreeSet<String>>,
+    pub joint_ownership: BTreeMap<String, JointOwnership>,
+    pub required_confirmations: u32,
+    pub confirmation_policies: Vec<(Asset, u32)>,
+    #[serde(default)]
+    pub wallet_whitelists: BTreeMap<String, Vec<WhitelistEntry>>,
+    #[serde(default)]
+    pub account_whitelists: BTreeMap<String, Vec<WhitelistEntry>>,
+    /// `None` in archives that predate whitelists; the restoring system
+    /// keeps its own delay
+    #[serde(default)]
+    pub whitelist_delay_secs: Option<u64>,
+    #[serde(default)]
+    pub four_eyes: Option<FourEyesRule>,
+    #[serde(default)]
+    pub travel_rule_threshold: Option<Amount>,
+    #[serde(default)]
+    pub wallet_rate_limit: Option<RateLimit>,
+    #[serde(default)]
+    pub operator_rate_limit: Option<RateLimit>,
+    #[serde(default)]
+    pub release_rate: ReleaseRate,
+    #[serde(default)]
+    pub queue_paused: bool,
+}
+
+/// Operations started but not yet completed
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PendingOperations {
+    pub withdrawals: Vec<PendingWithdrawal>,
+    pub multisig_withdrawals: Vec<MultiSigWithdrawal>,
+    pub joint_operations: Vec<JointOperation>,
+    pub unsigned_txs: Vec<UnsignedTx>,
+    pub holds: Vec<Hold>,
+    #[serde(default)]
+    pub queued_withdrawals: Vec<QueuedWithdrawal>,
+    /// Operations held for compliance review
+    #[serde(default)]
+    pub flagged: Vec<FlaggedOperation>,
+    /// Admin actions awaiting a second operator
+    #[serde(default)]
+    pub admin_actions: Vec<ProposedAction>,
+}
+
+impl PendingOperations {
+    /// Wallets the operations refer to, with a description of each
+    fn wallet_refs(&self) -> impl Iterator<Item = (String, &str)> {
+        let withdrawals = self
+            .withdrawals
+            .iter()
+            .map(|op| (format!("withdrawal {}", op.id), op.wallet_id.as_str()));
+        let multisig = self
+            .multisig_withdrawals
+            .iter()
+            .map(|op| (format!("withdrawal {}", op.id), op.wallet_id.as_str()));
+        let joint = self
+            .joint_operations
+            .iter()
+            .map(|op| (format!("joint operation {}", op.id), op.wallet_id.as_str()));
+        let unsigned = self.unsigned_txs.iter().map(|tx| {
+            (
+                format!("unsigned transaction {}", tx.id),
+                tx.wallet_id.as_str(),
+            )
+        });
+        let holds = self
+            .holds
+            .iter()
+            .map(|hold| (format!("hold {}", hold.id), hold.wallet_id.as_str()));
+        let queued = self.queued_withdrawals.iter().map(|op| {
+            (
+                format!("queued withdrawal {}", op.id),
+                op.wallet_id.as_str(),
+            )
+        });
+        let flagged = self.flagged.iter().flat_map(|op| {
+            let request = &op.request;
+            std::iter::once(request.wallet_id.as_str())
+                .chain(request.to_wallet_id.as_deref())
+                .map(|wallet_id| (format!("flagged operation {}", op.id), wallet_id))
+        });
+        let actions = self.admin_actions.iter().filter_map(|op| {
+            let wallet_id = op.action.wallet_id()?;
+            Some((format!("admin action {}", op.id), wallet_id))
+        });
+        withdrawals
+            .chain(multisig)
+            .chain(joint)
+            .chain(unsigned)
+            .chain(holds)
+            .chain(queued)
+            .chain(flagged)
+            .chain(actions)
+    }
+}
+
+/// Everything a disaster recovery restores
+///
+/// Sections added by later format versions default to empty, so older
+/// archives keep restoring.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecoveryArchive {
+    pub version: u32,
+    pub created_at: Timestamp,
+    pub ledger: Snapshot,
+    #[serde(default)]
+    pub policies: PolicySet,
+    #[serde(default)]
+    pub pending: PendingOperations,
+    /// Next id operations are numbered from
+    #[serde(default)]
+    pub next_operation_id: u64,
+}
+
+impl RecoveryArchive {
+    /// Encrypts the archive under `passphrase`
+    pub fn seal(&self, passphrase: &str) -> Result<Vec<u8>, CustodyError> {
+        if passphrase.chars().count() < MIN_PASSPHRASE_CHARS {
+            return Err(CustodyError::BackupFailed(format!(
+                "passphrase must be at least {} characters",
+                MIN_PASSPHRASE_CHARS
+            )));
+        }
+        let params = Params::default();
+        let mut header = MAGIC.to_vec();
+        header.extend(self.version.to_le_bytes());
+        for param in [params.m_cost(), params.t_cost(), params.p_cost()] {
+            header.extend(param.to_le_bytes());
+        }
+        let mut salt_and_nonce = [0u8; 28];
+        getrandom::getrandom(&mut salt_and_nonce).map_err(backup_failed)?;
+        header.extend(salt_and_nonce);
+
+        let contents = Zeroizing::new(serde_json::to_vec(self).map_err(backup_failed)?);
+        let (salt, nonce) = salt_and_nonce.split_at(16);
+        let sealed = cipher(passphrase, params, salt)?
+            .encrypt(
+                Nonce::from_slice(nonce),
+                Payload {
+                    msg: &contents,
+                    aad: &header,
+                },
+            )
+            .map_err(backup_failed)?;
+        header.extend(sealed);
+        Ok(header)
+    }
+
+    /// Decrypts an archive written by [`seal`](Self::seal)
+    ///
+    /// # Errors
+    /// * [`CustodyError::UnsupportedBackupVersion`] for archives of a
+    ///   newer format
+    /// * [`CustodyError::BackupFailed`] if the passphrase is wrong or the
+    ///   file was altered
+    pub fn open(bytes: &[u8], passphrase: &str) -> Result<Self, CustodyError> {
+        let malformed = || CustodyError::BackupFailed("not a recovery archive".to_string());
+        if bytes.len() < HEADER_LEN || !bytes.starts_with(MAGIC) {
+            return Err(malformed());
+        }
+        let (header, sealed) = bytes.split_at(HEADER_LEN);
+        let word = |index: usize| {
+            let start = MAGIC.len() + 4 * index;
+            u32::from_le_bytes(header[start..start + 4].try_into().expect("4 bytes"))
+        };
+        let version = word(0);
+        if version == 0 || version > RECOVERY_FORMAT_VERSION {
+            return Err(CustodyError::UnsupportedBackupVersion(version));
+        }
+        let (m_cost, t_cost, p_cost) = (word(1), word(2), word(3));
+        if m_cost > Params::DEFAULT_M_COST
+            || t_cost > Params::DEFAULT_T_COST
+            || p_cost > Params::DEFAULT_P_COST
+        {
+            return Err(CustodyError::BackupFailed(
+                "key derivation parameters exceed the supported maximum".to_string(),
+            ));
+        }
+        let params = Params::new(m_cost, t_cost, p_cost, None).map_err(|_| malformed())?;
+        let (salt, nonce) = header[MAGIC.len() + 16..].split_at(16);
+
+        let contents = cipher(passphrase, params, salt)?
+            .decrypt(
+                Nonce::from_slice(nonce),
+                Payload {
+                    msg: sealed,
+                    aad: header,
+                },
+            )
+            .map(Zeroizing::new)
+            .map_err(|_| {
+                CustodyError::BackupFailed("wrong passphrase or corrupted archive".to_string())
+            })?;
+        let archive: RecoveryArchive = serde_json::from_slice(&contents)
+            .map_err(|err| CustodyError::InvalidSnapshot(err.to_string()))?;
+        if archive.version != version {
+            return Err(malformed());
+        }
+        Ok(archive)
+    }
+}
+
+impl CustodySystem {
+    /// Gathers the ledger, policies and pending operations into an archive
+    pub fn recovery_archive(&self) -> RecoveryArchive {
+        let policies = PolicySet {
+            default_policies: self
+                .default_policies
+                .iter()
+                .map(|(wallet_type, policy)| (wallet_type.clone(), policy.clone()))
+                .collect(),
+            wallet_policies: self.wallet_policies.clone().into_iter().collect(),
+            default_quorums: self
+                .default_quorums
+                .iter()
+                .map(|(wallet_type, quorum)| (wallet_type.clone(), *quorum))
+                .collect(),
+            withdrawal_approvers: self.withdrawal_approvers.clone().into_iter().collect(),
+            joint_ownership: self.joint_ownership.clone().into_iter().collect(),
+            required_confirmations: self.required_confirmations,
+            confirmation_policies: self.confirmation_policies.clone().into_iter().collect(),
+            wallet_whitelists: self.archived_whitelists(|owner| match owner {
+                WhitelistOwner::Wallet(id) => Some(id),
+                WhitelistOwner::Account(_) => None,
+            }),
+            account_whitelists: self.archived_whitelists(|owner| match owner {
+                WhitelistOwner::Account(id) => Some(id),
+                WhitelistOwner::Wallet(_) => None,
+            }),
+            whitelist_delay_secs: Some(self.whitelist_delay_secs),
+            four_eyes: self.four_eyes,
+            travel_rule_threshold: self.travel_rule_threshold,
+            wallet_rate_limit: self.wallet_rate_limit(),
+            operator_rate_limit: self.operator_rate_limit(),
+            release_rate: self.release_rate().clone(),
+            queue_paused: self.is_withdrawal_queue_paused(),
+        };
+        let pending = PendingOperations {
+            withdrawals: self
+                .pending_withdrawals
+                .values()
+                .filter(|op| op.status == OperationStatus::Pending)
+                .cloned()
+                .collect(),
+            multisig_withdrawals: self
+                .multisig_withdrawals
+                .values()
+                .filter(|op| op.status == OperationStatus::Pending)
+                .cloned()
+                .collect(),
+            joint_operations: self
+                .joint_operations
+                .values()
+                .filter(|op| op.status == OperationStatus::Pending)
+                .cloned()
+                .collect(),
+            unsigned_txs: self.unsigned_txs.values().cloned().collect(),
+            holds: self
+                .holds
+                .values()
+                .filter(|hold| hold.status == HoldStatus::Active)
+                .cloned()
+                .collect(),
+            queued_withdrawals: self.queued_withdrawals().into_iter().cloned().collect(),
+            flagged: self
+                .flagged_operations
+                .values()
+                .filter(|op| op.status == OperationStatus::Pending)
+                .cloned()
+                .collect(),
+            admin_actions: self.pending_actions().into_iter().cloned().collect(),
+        };
+        RecoveryArchive {
+            version: RECOVERY_FORMAT_VERSION,
+            created_at: self.current_timestamp(),
+            ledger: self.snapshot(),
+            policies,
+            pending,
+            next_operation_id: self.next_operation_id,
+        }
+    }
+
+    /// Writes an encrypted recovery archive to `path`, replacing any file
+    /// there only once the archive is completely written
+    ///
+    /// # Example
+    /// ```
+    /// use securevault::{amount, CustodySystem, WalletType};
+    /// let path = std::env::temp_dir().join(format!("securevault-dr-{}.bin", std::process::id()));
+    /// let mut system = CustodySystem::new();
+    /// system.create_wallet("w".to_string(), "0x1".to_string(), WalletType::Hot).unwrap();
+    /// system.deposit("w", amount!(5)).unwrap();
+    /// system.export_encrypted_backup(&path, "correct horse battery").unwrap();
+    ///
+    /// let mut recovered = CustodySystem::new();
+    /// recovered.restore_encrypted_backup(&path, "correct horse battery").unwrap();
+    /// assert_eq!(recovered.get_wallet("w").unwrap().balance, amount!(5));
+    /// # std::fs::remove_file(&path).unwrap();
+    /// ```
+    pub fn export_encrypted_backup(
+        &self,
+        path: impl AsRef<Path>,
+        passphrase: &str,
+    ) -> Result<(), CustodyError> {
+        let sealed = self.recovery_archive().seal(passphrase)?;
+        write_atomically(path.as_ref(), &sealed)
+    }
+
+    /// Replaces the ledger, policies and pending operations with those of
+    /// the encrypted archive at `path`
+    ///
+    /// Nothing changes unless the archive decrypts, is of a known version,
+    /// passes the audit chain check and only refers to wallets it contains.
+    pub fn restore_encrypted_backup(
+        &mut self,
+        path: impl AsRef<Path>,
+        passphrase: &str,
+    ) -> Result<(), CustodyError> {
+        let sealed = fs::read(path).map_err(backup_failed)?;
+        self.restore_recovery_archive(RecoveryArchive::open(&sealed, passphrase)?)
+    }
+
+    /// Replaces the ledger, policies and pending operations with an
+    /// archive's
+    pub fn restore_recovery_archive(
+        &mut self,
+        archive: RecoveryArchive,
+    ) -> Result<(), CustodyError> {
+        if archive.version == 0 || archive.version > RECOVERY_FORMAT_VERSION {
+            return Err(CustodyError::UnsupportedBackupVersion(archive.version));
+        }
+        let wallets: BTreeSet<&str> = archive
+            .ledger
+            .wallets
+            .iter()
+            .map(|wallet| wallet.id.as_str())
+            .collect();
+        let policy_refs = archive
+            .policies
+            .wallet_policies
+            .keys()
+            .chain(archive.policies.withdrawal_approvers.keys())
+            .chain(archive.policies.joint_ownership.keys())
+            .chain(archive.policies.wallet_whitelists.keys())
+            .map(|wallet_id| ("policy".to_string(), wallet_id.as_str()));
+        if let Some((what, wallet_id)) = policy_refs
+            .chain(archive.pending.wallet_refs())
+            .find(|(_, wallet_id)| !wallets.contains(wallet_id))
+        {
+            return Err(CustodyError::InvalidSnapshot(format!(
+                "{} refers to unknown wallet {}",
+                what, wallet_id
+            )));
+        }
+
+        let limits = [
+            archive.policies.wallet_rate_limit,
+            archive.policies.operator_rate_limit,
+        ];
+        for limit in limits.iter().flatten() {
+            limit.validate()?;
+        }
+
+        self.restore(archive.ledger)?;
+        let policies = archive.policies;
+        self.default_policies = policies.default_policies.into_iter().collect();
+        self.wallet_policies = policies.wallet_policies.into_iter().collect();
+        self.default_quorums = policies.default_quorums.into_iter().collect();
+        self.withdrawal_approvers = policies.withdrawal_approvers.into_iter().collect();
+        self.joint_ownership = policies.joint_ownership.into_iter().collect();
+        self.required_confirmations = policies.required_confirmations;
+        self.confirmation_policies = policies.confirmation_policies.into_iter().collect();
+        let wallet_whitelists = policies
+            .wallet_whitelists
+            .into_iter()
+            .map(|(id, entries)| (WhitelistOwner::Wallet(id), entries));
+        let account_whitelists = policies
+            .account_whitelists
+            .into_iter()
+            .map(|(id, entries)| (WhitelistOwner::Account(id), entries));
+        self.whitelists = wallet_whitelists
+            .chain(account_whitelists)
+            .map(|(owner, entries)| {
+                let entries = entries
+                    .into_iter()
+                    .map(|entry| (entry.address.clone(), entry))
+                    .collect();
+                (owner, entries)
+            })
+            .collect();
+        if let Some(delay) = policies.whitelist_delay_secs {
+            self.whitelist_delay_secs = delay;
+        }
+        self.four_eyes = policies.four_eyes;
+        self.travel_rule_threshold = policies.travel_rule_threshold;
+        self.replace_wallet_rate_limit(policies.wallet_rate_limit)?;
+        self.replace_operator_rate_limit(policies.operator_rate_limit)?;
+
+        let pending = archive.pending;
+        let mut last_id = archive.next_operation_id.saturating_sub(1);
+        self.pending_withdrawals = pending
+            .withdrawals
+            .into_iter()
+            .inspect(|op| last_id = last_id.max(op.id))
+            .map(|op| (op.id, op))
+            .collect();
+        self.multisig_withdrawals = pending
+            .multisig_withdrawals
+            .into_iter()
+            .inspect(|op| last_id = last_id.max(op.id))
+            .map(|op| (op.id, op))
+            .collect();
+        self.joint_operations = pending
+            .joint_operations
+            .into_iter()
+            .inspect(|op| last_id = last_id.max(op.id))
+            .map(|op| (op.id, op))
+            .collect();
+        self.unsigned_txs = pending
+            .unsigned_txs
+            .into_iter()
+            .inspect(|tx| last_id = last_id.max(tx.id))
+            .map(|tx| (tx.id, tx))
+            .collect();
+        self.holds = pending
+            .holds
+            .into_iter()
+            .inspect(|hold| last_id = last_id.max(hold.id.0))
+            .map(|hold| (hold.id, hold))
+            .collect();
+        let queued = pending.queued_withdrawals;
+        last_id = queued.iter().map(|op| op.id).fold(last_id, u64::max);
+        self.withdrawal_queue =
+            WithdrawalQueue::restored(queued, policies.release_rate, policies.queue_paused);
+        self.flagged_operations = pending
+            .flagged
+            .into_iter()
+            .inspect(|op| last_id = last_id.max(op.id))
+            .map(|op| (op.id, op))
+            .collect();
+        self.admin_actions = pending
+            .admin_actions
+            .into_iter()
+            .inspect(|op| last_id = last_id.max(op.id))
+            .map(|op| (op.id, op))
+            .collect();
+        self.next_operation_id = self.next_operation_id.max(last_id + 1);
+        Ok(())
+    }
+
+    /// Collects the whitelists of the owners `owner_id` picks, by owner id
+    fn archived_whitelists(
+        &self,
+        owner_id: impl Fn(&WhitelistOwner) -> Option<&String>,
+    ) -> BTreeMap<String, Vec<WhitelistEntry>> {
+        self.whitelists
+            .iter()
+            .filter_map(|(owner, entries)| {
+                let id = owner_id(owner)?;
+                Some((id.clone(), entries.values().cloned().collect()))
+            })
+            .collect()
+    }
+}
+
+fn cipher(passphrase: &str, params: Params, salt: &[u8]) -> Result<ChaCha20Poly1305, CustodyError> {
+    let mut key = Zeroizing::new([0u8; 32]);
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), salt, key.as_mut())
+        .map_err(backup_failed)?;
+    Ok(ChaCha20Poly1305::new(Key::from_slice(key.as_ref())))
+}
+
+fn backup_failed(err: impl std::fmt::Display) -> CustodyError {
+    CustodyError::BackupFailed(err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PASSPHRASE: &str = "correct horse battery";
+
+    fn system() -> CustodySystem {
+        let mut system = CustodySystem::new();
+        for (id, wallet_type) in [("hot", WalletType::Hot), ("cold", WalletType::Cold)] {
+            system
+                .create_wallet(id.to_string(), format!("0x{}", id), wallet_type)
+                .unwrap();
+            system.deposit(id, amount!(10)).unwrap();
+        }
+        system
+            .set_withdrawal_policy(
+                "hot",
+                WithdrawalPolicy {
+                    max_per_transaction: Some(amount!(5)),
+                    ..WithdrawalPolicy::default()
+                },
+            )
+            .unwrap();
+        system
+            .set_withdrawal_approvers("cold", &["alice", "bob"])
+            .unwrap();
+        system
+            .request_withdrawal("cold", "carol", amount!(3), None)
+            .unwrap();
+        system.place_hold("hot", amount!(2), "payout #7").unwrap();
+        system
+    }
+
+    #[test]
+    fn test_restore_brings_back_policies_and_pending_operations() {
+        let original = system();
+        let archive = original.recovery_archive();
+        let sealed = archive.seal(PASSPHRASE).unwrap();
+        assert_eq!(RecoveryArchive::open(&sealed, PASSPHRASE).unwrap(), archive);
+
+        let mut recovered = CustodySystem::new();
+        recovered
+            .restore_recovery_archive(RecoveryArchive::open(&sealed, PASSPHRASE).unwrap())
+            .unwrap();
+        assert_eq!(recovered.snapshot(), original.snapshot());
+        assert_eq!(recovered.available_balance("hot"), Some(amount!(8)));
+        assert!(recovered.withdraw("hot", amount!(6)).is_err());
+        let pending = recovered.pending_withdrawals("cold");
+        assert_eq!(pending.len(), 1);
+        let op = pending[0].id;
+        recovered.approve_withdrawal(op, "alice").unwrap();
+        recovered.approve_withdrawal(op, "bob").unwrap();
+        assert_eq!(recovered.get_wallet("cold").unwrap().balance, amount!(7));
+        // New operations do not reuse archived ids
+        let hold = recovered.place_hold("hot", amount!(1), "fee").unwrap();
+        assert!(hold.0 > op);
+    }
+
+    #[test]
+    fn test_restore_brings_back_controls_and_queues() {
+        let mut original = system();
+        original.set_whitelist_delay(0);
+        original
+            .whitelist_address("hot", "bc1qpayout", "payouts")
+            .unwrap();
+        original.set_travel_rule_threshold(Some(amount!(1000)));
+        original
+            .set_operator_rate_limit(Some(RateLimit::new(5, 60)))
+            .unwrap();
+        original
+            .queue_withdrawal("hot", amount!(1), Some("bc1qpayout"), 0)
+            .unwrap();
+        original.pause_withdrawal_queue();
+        let mut screener = crate::RuleScreener::new();
+        screener.review_above(amount!(4));
+        original.set_compliance_screener(std::sync::Arc::new(screener));
+        assert!(original.transfer("hot", "cold", amount!(4.5)).is_err());
+        original.clear_compliance_screener();
+        original
+            .set_four_eyes_rule(FourEyesRule::default())
+            .unwrap();
+        let action = crate::AdminAction::FreezeWallet {
+            wallet_id: "hot".to_string(),
+            reason: "review".to_string(),
+        };
+        let action = original.propose_action("alice", action).unwrap();
+
+        let sealed = original.recovery_archive().seal(PASSPHRASE).unwrap();
+        let mut recovered = CustodySystem::new();
+        recovered
+            .restore_recovery_archive(RecoveryArchive::open(&sealed, PASSPHRASE).unwrap())
+            .unwrap();
+        assert_eq!(recovered.whitelisted_addresses("hot").len(), 1);
+        assert_eq!(recovered.travel_rule_threshold(), Some(amount!(1000)));
+        assert_eq!(recovered.operator_rate_limit(), Some(RateLimit::new(5, 60)));
+        assert_eq!(recovered.queued_withdrawals().len(), 1);
+        assert!(recovered.is_withdrawal_queue_paused());
+        assert_eq!(recovered.flagged_operations().len(), 1);
+        assert_eq!(recovered.four_eyes_rule(), Some(&FourEyesRule::default()));
+        assert!(recovered.freeze_wallet("hot", "review").is_err());
+        recovered.confirm_action(action, "bob").unwrap();
+        assert!(recovered.withdraw("hot", amount!(1)).is_err());
+    }
+
+    #[test]
+    fn test_tampering_and_wrong_passphrase_are_detected() {
+        let sealed = system().recovery_archive().seal(PASSPHRASE).unwrap();
+        assert!(matches!(
+            RecoveryArchive::open(&sealed, "wrong horse battery"),
+            Err(CustodyError::BackupFailed(_))
+        ));
+        let mut flipped = sealed.clone();
+        *flipped.last_mut().unwrap() ^= 1;
+        assert!(RecoveryArchive::open(&flipped, PASSPHRASE).is_err());
+        // Lowering the key derivation cost in the header breaks the seal too
+        let mut weakened = sealed.clone();
+        weakened[16] = 1;
+        assert!(RecoveryArchive::open(&weakened, PASSPHRASE).is_err());
+        // Raising it is refused before any key is derived
+        let mut costly = sealed.clone();
+        costly[12..16].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(
+            RecoveryArchive::open(&costly, PASSPHRASE),
+            Err(CustodyError::BackupFailed(
+                "key derivation parameters exceed the supported maximum".to_string()
+            ))
+        );
+
+        let mut newer = sealed.clone();
+        newer[8..12].copy_from_slice(&(RECOVERY_FORMAT_VERSION + 1).to_le_bytes());
+        assert_eq!(
+            RecoveryArchive::open(&newer, PASSPHRASE),
+            Err(CustodyError::UnsupportedBackupVersion(
+                RECOVERY_FORMAT_VERSION + 1
+            ))
+        );
+        assert!(system().recovery_archive().seal("short").is_err());
+    }
+
+    #[test]
+    fn test_dangling_references_leave_the_system_untouched() {
+        let mut archive = system().recovery_archive();
+        archive.ledger.wallets.retain(|wallet| wallet.id != "cold");
+        let mut target = CustodySystem::new();
+        target
+            .create_wallet("x".to_string(), "0x2".to_string(), WalletType::Hot)
+            .unwrap();
+        assert_eq!(
+            target.restore_recovery_archive(archive),
+            Err(CustodyError::InvalidSnapshot(
+                "policy refers to unknown wallet cold".to_string()
+            ))
+        );
+        assert!(target.get_wallet("x").is_some());
+    }
+}
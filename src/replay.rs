@@ -0,0 +1,257 @@
+//! Deterministic replay of the transaction log.
+//!
+//! Balances are a pure function of the transaction log. Replaying a log
+//! (our own or an exported one) and comparing the result with the stored
+//! balances detects lost, duplicated, or tampered records.
+
+use crate::{Amount, Asset, CustodySystem, Transaction};
+use std::collections::BTreeMap;
+
+/// A wallet whose stored balance in an asset differs from the replayed one
+#[derive(Debug, Clone, PartialEq)]
+pub struct BalanceMismatch {
+    pub wallet_id: String,
+    pub asset: Asset,
+    pub stored: Amount,
+    pub replayed: Amount,
+}
+
+/// Outcome of replaying a transaction log
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ReplayReport {
+    /// Number of transactions replayed
+    pub transactions: usize,
+    /// Balances reconstructed from the log, by wallet id and asset
+    pub balances: BTreeMap<(String, Asset), Amount>,
+    /// Wallets whose stored balance does not match the log
+    pub mismatches: Vec<BalanceMismatch>,
+    /// Wallet ids referenced by the log that are not in the system
+    pub unknown_wallets: Vec<String>,
+    /// Positions in the log at which a wallet balance went negative
+    pub overdrafts: Vec<usize>,
+}
+
+impl ReplayReport {
+    /// Returns true if the log fully explains the stored balances
+    pub fn is_consistent(&self) -> bool {
+        self.mismatches.is_empty() && self.unknown_wallets.is_empty() && self.overdrafts.is_empty()
+    }
+}
+
+impl CustodySystem {
+    /// Reconstructs wallet balances, by wallet id and asset, purely from
+    /// `transactions`, in order
+    pub fn replay_balances<'a, I>(transactions: I) -> BTreeMap<(String, Asset), Amount>
+    where
+        I: IntoIterator<Item = &'a Transaction>,
+    {
+        let mut balances = BTreeMap::new();
+        for tx in transactions {
+            for (wallet_id, change) in tx.postings() {
+                apply(balances.entry(key(wallet_id, tx)).or_default(), change);
+            }
+        }
+        balances
+    }
+
+    /// Replays `transactions` and verifies the result against the balances
+    /// stored in this system
+    ///
+    /// # Example
+    /// ```
+    /// use securevault::{amount, CustodySystem, WalletType};
+    /// let mut system = CustodySystem::new();
+    /// system.create_wallet("w".to_string(), "0x1".to_string(), WalletType::Hot).unwrap();
+    /// system.deposit("w", amount!(5.0)).unwrap();
+    ///
+    /// let report = system.replay(system.get_all_transactions());
+    /// assert!(report.is_consistent());
+    /// ```
+    pub fn replay<'a, I>(&self, transactions: I) -> ReplayReport
+    where
+        I: IntoIterator<Item = &'a Transaction>,
+    {
+        let mut report = ReplayReport::default();
+        for (position, tx) in transactions.into_iter().enumerate() {
+            report.transactions += 1;
+            let mut overdrawn = false;
+            for (wallet_id, change) in tx.postings() {
+                let balance = report.balances.entry(key(wallet_id, tx)).or_default();
+                apply(balance, change);
+                overdrawn |= balance.is_negative();
+            }
+            if overdrawn {
+                report.overdrafts.push(position);
+            }
+        }
+
+        for ((wallet_id, asset), replayed) in &report.balances {
+            match self.wallets.get(wallet_id) {
+                None => {
+                    if report.unknown_wallets.last() != Some(wallet_id) {
+                        report.unknown_wallets.push(wallet_id.clone());
+                    }
+                }
+                Some(wallet) if wallet.balance_of(asset) != *replayed => {
+                    report.mismatches.push(BalanceMismatch {
+                        wallet_id: wallet_id.clone(),
+                        asset: asset.clone(),
+                        stored: wallet.balance_of(asset),
+                        replayed: *replayed,
+                    })
+                }
+                Some(_) => {}
+            }
+        }
+
+        for wallet in self.wallets.values() {
+            for (asset, stored) in wallet.balances() {
+                let replayed = (wallet.id.clone(), asset.clone());
+                if !stored.is_zero() && !report.balances.contains_key(&replayed) {
+                    report.mismatches.push(BalanceMismatch {
+                        wallet_id: wallet.id.clone(),
+                        asset: asset.clone(),
+                        stored,
+                        replayed: Amount::ZERO,
+                    });
+                }
+            }
+        }
+        report
+            .mismatches
+            .sort_by(|a, b| (&a.wallet_id, &a.asset).cmp(&(&b.wallet_id, &b.asset)));
+
+        report
+    }
+}
+
+fn key(wallet_id: &str, tx: &Transaction) -> (String, Asset) {
+    (wallet_id.to_string(), tx.asset.clone())
+}
+
+/// Applies a signed change to a running balance. Foreign logs may hold
+/// arbitrary amounts, so overflow saturates instead of panicking; a
+/// saturated balance never matches a stored one.
+fn apply(balance: &mut Amount, change: Amount) {
+    *balance = balance
+        .checked_add(change)
+        .unwrap_or(if change.is_negative() {
+            Amount::MIN
+        } else {
+            Amount::MAX
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::WalletType;
+
+    fn btc(wallet_id: &str) -> (String, Asset) {
+        (wallet_id.to_string(), Asset::Btc)
+    }
+
+    fn system() -> CustodySystem {
+        let mut system = CustodySystem::new();
+        system
+            .create_wallet("a".to_string(), "0x1".to_string(), WalletType::Hot)
+            .unwrap();
+        system
+            .create_wallet("b".to_string(), "0x2".to_string(), WalletType::Cold)
+            .unwrap();
+        system.deposit("a", amount!(10.0)).unwrap();
+        system.transfer("a", "b", amount!(3.0)).unwrap();
+        system
+    }
+
+    #[test]
+    fn test_replay_own_log_is_consistent() {
+        let system = system();
+        let report = system.replay(system.get_all_transactions());
+        assert!(report.is_consistent());
+        assert_eq!(report.transactions, 2);
+        assert_eq!(report.balances[&btc("a")], amount!(7.0));
+        assert_eq!(report.balances[&btc("b")], amount!(3.0));
+    }
+
+    #[test]
+    fn test_replay_detects_missing_transaction() {
+        let system = system();
+        let truncated: Vec<_> = system
+            .get_all_transactions()
+            .iter()
+            .take(1)
+            .cloned()
+            .collect();
+        let report = system.replay(&truncated);
+        assert!(!report.is_consistent());
+        assert_eq!(
+            report.mismatches,
+            vec![
+                BalanceMismatch {
+                    wallet_id: "a".to_string(),
+                    asset: Asset::Btc,
+                    stored: amount!(7.0),
+                    replayed: amount!(10.0),
+                },
+                BalanceMismatch {
+                    wallet_id: "b".to_string(),
+                    asset: Asset::Btc,
+                    stored: amount!(3.0),
+                    replayed: amount!(0.0),
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn test_replay_detects_overdraft_and_unknown_wallet() {
+        let system = system();
+        let mut log: Vec<_> = system.get_all_transactions().iter().cloned().collect();
+        log.swap(0, 1);
+        let mut stray = log[0].clone();
+        stray.wallet_id = "ghost".to_string();
+        log.push(stray);
+
+        let report = system.replay(&log);
+        assert_eq!(report.overdrafts, vec![0, 2]);
+        assert_eq!(report.unknown_wallets, vec!["ghost".to_string()]);
+    }
+
+    #[test]
+    fn test_replay_balances_is_pure() {
+        let system = system();
+        let balances = CustodySystem::replay_balances(system.get_all_transactions());
+        assert_eq!(balances.len(), 2);
+        assert_eq!(balances[&btc("a")], amount!(7.0));
+    }
+
+    #[test]
+    fn test_replay_tracks_each_asset_separately() {
+        let mut system = system();
+        system
+            .deposit_asset("a", &Asset::Eth, amount!(2.0))
+            .unwrap();
+        system
+            .transfer_asset("a", "b", &Asset::Eth, amount!(0.5))
+            .unwrap();
+        let report = system.replay(system.get_all_transactions());
+        assert!(report.is_consistent());
+        assert_eq!(report.balances[&btc("a")], amount!(7.0));
+        assert_eq!(
+            report.balances[&("a".to_string(), Asset::Eth)],
+            amount!(1.5)
+        );
+
+        system
+            .wallets
+            .get_mut("b")
+            .unwrap()
+            .holdings
+            .insert(Asset::Eth, amount!(9));
+        let report = system.replay(system.get_all_transactions());
+        assert_eq!(report.mismatches.len(), 1);
+        assert_eq!(report.mismatches[0].asset, Asset::Eth);
+        assert_eq!(report.mismatches[0].replayed, amount!(0.5));
+    }
+}
@@ -0,0 +1,326 @@
+//! Reversal of booked transactions.
+//!
+//! The ledger is append-only: a mistaken booking is corrected by
+//! [`CustodySystem::reverse_transaction`], which records a new entry
+//! undoing its balance changes. The reversal carries the id of the entry it
+//! reverses under [`REVERSES_METADATA_KEY`], the original gets the
+//! reversal's id under [`REVERSED_BY_METADATA_KEY`], and the reason is kept
+//! as the reversal's memo. A transaction can be reversed once.
+
+use crate::cdc::Change;
+use crate::time::Timestamp;
+use crate::{Amount, Asset, CustodyError, CustodySystem, Transaction, TransactionType};
+
+/// Transaction metadata key holding the id of the entry a reversal undoes
+pub const REVERSES_METADATA_KEY: &str = "reverses";
+
+/// Transaction metadata key holding the id of the reversal of an entry
+pub const REVERSED_BY_METADATA_KEY: &str = "reversed_by";
+
+impl Transaction {
+    /// Returns the id of the transaction this entry reverses, if it is a
+    /// reversal
+    pub fn reverses(&self) -> Option<u64> {
+        linked_id(self, REVERSES_METADATA_KEY)
+    }
+
+    /// Returns the id of the entry reversing this transaction, if any
+    pub fn reversed_by(&self) -> Option<u64> {
+        linked_id(self, REVERSED_BY_METADATA_KEY)
+    }
+}
+
+impl CustodySystem {
+    /// Books an entry undoing transaction `tx_id` and returns its id
+    ///
+    /// A deposit is reversed by a withdrawal, a withdrawal by a deposit,
+    /// a transfer by a transfer back, and a conversion leg by the opposite
+    /// leg, all in the original asset. Reversing either leg of a conversion
+    /// reverses both in one operation; the returned id is that of the
+    /// entry undoing `tx_id`. Reversals are corrections: wallet status,
+    /// policies and screening do not apply, but no available balance may
+    /// go negative, so a reversal fails with
+    /// [`CustodyError::InsufficientBalance`] if the funds have moved on or
+    /// are held.
+    ///
+    /// # Example
+    /// ```
+    /// use securevault::{amount, CustodySystem, WalletType};
+    /// let mut system = CustodySystem::new();
+    /// system.create_wallet("w".to_string(), "0x1".to_string(), WalletType::Hot).unwrap();
+    /// system.deposit("w", amount!(5)).unwrap();
+    ///
+    /// let deposit = system.get_all_transactions()[0].id;
+    /// let reversal = system.reverse_transaction(deposit, "credited to the wrong wallet").unwrap();
+    /// assert_eq!(system.get_wallet("w").unwrap().balance, amount!(0));
+    /// assert_eq!(system.get_transaction(deposit).unwrap().reversed_by(), Some(reversal));
+    /// assert_eq!(system.get_transaction(reversal).unwrap().reverses(), Some(deposit));
+    /// ```
+    pub fn reverse_transaction(&mut self, tx_id: u64, reason: &str) -> Result<u64, CustodyError> {
+        let index = self
+            .index
+            .position(tx_id)
+            .ok_or(CustodyError::TransactionIdNotFound(tx_id))?;
+        // The incoming leg is undone first, so the two reversal entries
+        // pair up like a conversion themselves
+        let mut indices = vec![index];
+        indices.extend(self.conversion_partner(index)?);
+        indices.sort_unstable_by(|a, b| b.cmp(a));
+
+        let first_id = self.next_transaction_id;
+        let mut reversals = Vec::new();
+        let mut marks = Vec::new();
+        for (reversal_id, &index) in (first_id..).zip(&indices) {
+            let original = &self.transactions[index];
+            if original.reversed_by().is_some() {
+                return Err(CustodyError::AlreadyReversed(original.id));
+            }
+            self.check_period_open(original.timestamp)?;
+
+            let mut reversal = reversal_entry(original, self.current_timestamp());
+            reversal.memo = Some(reason.to_string());
+            reversal
+                .metadata
+                .insert(REVERSES_METADATA_KEY.to_string(), original.id.to_string());
+            reversals.push(reversal);
+
+            let mut transaction = original.clone();
+            transaction.metadata.insert(
+                REVERSED_BY_METADATA_KEY.to_string(),
+                reversal_id.to_string(),
+            );
+            transaction.seal();
+            marks.push(Change::TransactionUpdate { index, transaction });
+        }
+
+        // Postings to the same wallet and asset add up before the check
+        let mut postings: Vec<(&str, &Asset, Amount)> = Vec::new();
+        for reversal in &reversals {
+            for (wallet_id, change) in reversal.postings() {
+                match postings
+                    .iter_mut()
+                    .find(|(id, asset, _)| *id == wallet_id && **asset == reversal.asset)
+                {
+                    Some((_, _, total)) => *total += change,
+                    None => postings.push((wallet_id, &reversal.asset, change)),
+                }
+            }
+        }
+        for (wallet_id, asset, change) in postings {
+            let wallet = self
+                .wallets
+                .get(wallet_id)
+                .ok_or_else(|| CustodyError::WalletNotFound(wallet_id.to_string()))?;
+            let available = self.available_of(wallet, asset);
+            let balance = available
+                .checked_add(change)
+                .ok_or(CustodyError::AmountOverflow)?;
+            if balance.is_negative() {
+                return Err(CustodyError::InsufficientBalance {
+                    available,
+                    requested: -change,
+                });
+            }
+        }
+
+        let position = indices.iter().position(|&i| i == index).expect("reversed");
+        self.record_transactions_with(reversals, marks)?;
+        Ok(first_id + position as u64)
+    }
+
+    /// Returns the index of the other leg of the conversion booked at
+    /// `index`, or none if it is not a conversion leg
+    ///
+    /// Conversions and exchange fills book the outgoing leg and then the
+    /// incoming one in the same operation, each naming the other's wallet
+    /// as counterparty.
+    fn conversion_partner(&self, index: usize) -> Result<Option<usize>, CustodyError> {
+        let tx = &self.transactions[index];
+        let partner = match tx.transaction_type {
+            TransactionType::ConversionOut => index.checked_add(1),
+            TransactionType::ConversionIn => index.checked_sub(1),
+            _ => return Ok(None),
+        };
+        let expected = match tx.transaction_type {
+            TransactionType::ConversionOut => TransactionType::ConversionIn,
+            _ => TransactionType::ConversionOut,
+        };
+        partner
+            .filter(|&partner| {
+                self.transactions.get(partner).is_some_and(|leg| {
+                    leg.transaction_type == expected
+                        && tx.counterparty.as_deref() == Some(leg.wallet_id.as_str())
+                        && leg.counterparty.as_deref() == Some(tx.wallet_id.as_str())
+                })
+            })
+            .map(Some)
+            .ok_or(CustodyError::UnpairedConversionLeg(tx.id))
+    }
+}
+
+/// Builds an unrecorded entry with the opposite balance changes of `tx`
+fn reversal_entry(tx: &Transaction, timestamp: Timestamp) -> Transaction {
+    let (wallet_id, transaction_type, counterparty) = match (&tx.transaction_type, &tx.counterparty)
+    {
+        (TransactionType::Transfer, Some(destination)) => (
+            destination.as_str(),
+            TransactionType::Transfer,
+            Some(tx.wallet_id.clone()),
+        ),
+        (TransactionType::Deposit | TransactionType::Reward, _) => {
+            (tx.wallet_id.as_str(), TransactionType::Withdrawal, None)
+        }
+        (TransactionType::ConversionIn, counterparty) => (
+            tx.wallet_id.as_str(),
+            TransactionType::ConversionOut,
+            counterparty.clone(),
+        ),
+        (TransactionType::ConversionOut, counterparty) => (
+            tx.wallet_id.as_str(),
+            TransactionType::ConversionIn,
+            counterparty.clone(),
+        ),
+        _ => (tx.wallet_id.as_str(), TransactionType::Deposit, None),
+    };
+    let mut reversal = Transaction::new(
+        wallet_id,
+        transaction_type,
+        tx.amount,
+        tx.asset.clone(),
+        timestamp,
+    );
+    reversal.counterparty = counterparty;
+    reversal.category = tx.category.clone();
+    reversal
+}
+
+fn linked_id(tx: &Transaction, key: &str) -> Option<u64> {
+    tx.metadata.get(key).and_then(|id| id.parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Amount, StaticRateProvider, WalletType};
+
+    fn system() -> CustodySystem {
+        let mut system = CustodySystem::new();
+        for id in ["a", "b"] {
+            system
+                .create_wallet(id.to_string(), id.to_string(), WalletType::Hot)
+                .unwrap();
+        }
+        system.deposit("a", amount!(10)).unwrap();
+        system
+    }
+
+    #[test]
+    fn test_reversed_transfer_moves_funds_back() {
+        let mut system = system();
+        system.transfer("a", "b", amount!(4)).unwrap();
+        let transfer = system.get_all_transactions().last().unwrap().id;
+        let reversal = system.reverse_transaction(transfer, "wrong payee").unwrap();
+
+        assert_eq!(system.get_wallet("a").unwrap().balance, amount!(10));
+        assert_eq!(system.get_wallet("b").unwrap().balance, Amount::ZERO);
+        let entry = system.get_transaction(reversal).unwrap();
+        assert_eq!(entry.wallet_id, "b");
+        assert_eq!(entry.counterparty.as_deref(), Some("a"));
+        assert_eq!(entry.memo.as_deref(), Some("wrong payee"));
+        // History is kept and both entries still verify
+        assert_eq!(system.get_all_transactions().len(), 3);
+        assert!(system.audit().passed());
+        assert_eq!(
+            system.reverse_transaction(transfer, "again"),
+            Err(CustodyError::AlreadyReversed(transfer))
+        );
+    }
+
+    #[test]
+    fn test_reversal_cannot_overdraw() {
+        let mut system = system();
+        let deposit = system.get_all_transactions()[0].id;
+        system.transfer("a", "b", amount!(7)).unwrap();
+        assert_eq!(
+            system.reverse_transaction(deposit, "duplicate credit"),
+            Err(CustodyError::InsufficientBalance {
+                available: amount!(3),
+                requested: amount!(10),
+            })
+        );
+        assert_eq!(system.get_transaction(deposit).unwrap().reversed_by(), None);
+        assert_eq!(system.get_all_transactions().len(), 2);
+
+        // Held funds are not available to a reversal either
+        system.place_hold("b", amount!(2), "pending card").unwrap();
+        let transfer = system.get_all_transactions()[1].id;
+        assert_eq!(
+            system.reverse_transaction(transfer, "wrong payee"),
+            Err(CustodyError::InsufficientBalance {
+                available: amount!(5),
+                requested: amount!(7),
+            })
+        );
+    }
+
+    #[test]
+    fn test_conversion_is_reversed_as_a_whole() {
+        let mut system = system();
+        system
+            .create_wallet_with_asset(
+                "e".to_string(),
+                "0xe".to_string(),
+                WalletType::Hot,
+                Asset::Eth,
+            )
+            .unwrap();
+        let mut rates = StaticRateProvider::new();
+        rates.set_rate(
+            system.get_wallet("a").unwrap().asset.clone(),
+            Asset::Eth,
+            20.0,
+        );
+        system.convert("a", "e", amount!(4), &rates).unwrap();
+        let credit = system.get_all_transactions().last().unwrap().id;
+
+        let reversal = system.reverse_transaction(credit, "booked twice").unwrap();
+        assert_eq!(system.get_wallet("a").unwrap().balance, amount!(10));
+        assert_eq!(system.get_wallet("e").unwrap().balance, Amount::ZERO);
+        let entry = system.get_transaction(reversal).unwrap();
+        assert_eq!(entry.transaction_type, TransactionType::ConversionOut);
+        assert_eq!(entry.reverses(), Some(credit));
+        assert!(system
+            .get_transaction(credit - 1)
+            .unwrap()
+            .reversed_by()
+            .is_some());
+        assert!(system.audit().passed());
+
+        // The reversal pairs up like a conversion and can be undone whole
+        system
+            .reverse_transaction(reversal, "reversed by mistake")
+            .unwrap();
+        assert_eq!(system.get_wallet("a").unwrap().balance, amount!(6));
+        assert_eq!(system.get_wallet("e").unwrap().balance, amount!(80));
+    }
+
+    #[test]
+    fn test_withdrawal_reversal_recredits_the_asset() {
+        let mut system = system();
+        let usd = Asset::Fiat("USD".to_string());
+        system.deposit_asset("a", &usd, amount!(100)).unwrap();
+        system.withdraw_asset("a", &usd, amount!(60)).unwrap();
+        let withdrawal = system.get_all_transactions().last().unwrap().id;
+        system
+            .reverse_transaction(withdrawal, "bank returned the wire")
+            .unwrap();
+        assert_eq!(
+            system.get_wallet("a").unwrap().balance_of(&usd),
+            amount!(100)
+        );
+        assert_eq!(
+            system.reverse_transaction(99, "unknown"),
+            Err(CustodyError::TransactionIdNotFound(99))
+        );
+    }
+}
@@ -0,0 +1,243 @@
+//! Key rotation and wallet re-keying.
+//!
+//! [`CustodySystem::rotate_wallet_key`] asks the [`KeyGenerator`] set with
+//! [`CustodySystem::set_key_generator`] for a fresh key and moves the
+//! wallet onto its address. Everything the wallet holds is swept from the
+//! old address to the new one: each asset gets a ledger entry recording
+//! the sweep, a transfer whose source and destination are the wallet
+//! itself, so balances are unchanged. The old address is kept in
+//! [`Wallet::deprecated_addresses`] and still routes stray deposits to the
+//! wallet.
+//!
+//! Every rotation is kept in [`CustodySystem::key_rotations`].
+
+use crate::time::Timestamp;
+use crate::{CustodyError, CustodySystem, Transaction, TransactionType, Wallet, WalletStatus};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::Arc;
+
+/// Metadata key of the address a sweep moved funds from
+pub const SWEEP_FROM_METADATA_KEY: &str = "sweep_from";
+
+/// Metadata key of the address a sweep moved funds to
+pub const SWEEP_TO_METADATA_KEY: &str = "sweep_to";
+
+/// Creates signing keys for wallets
+pub trait KeyGenerator: fmt::Debug + Send + Sync {
+    /// Creates a new key for `wallet` and returns its address
+    ///
+    /// The key material stays with the generator, e.g. in an HSM or a
+    /// [`KeyVault`](crate::KeyVault).
+    fn generate_key(&self, wallet: &Wallet) -> Result<String, String>;
+}
+
+/// A completed key rotation
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KeyRotation {
+    pub wallet_id: String,
+    pub old_address: String,
+    pub new_address: String,
+    /// Ledger entries of the sweeps, one per asset held
+    pub sweeps: Vec<u64>,
+    pub rotated_at: Timestamp,
+}
+
+impl CustodySystem {
+    /// Creates rotated keys with `generator` from now on
+    pub fn set_key_generator(&mut self, generator: Arc<dyn KeyGenerator>) {
+        self.key_generator = Some(generator);
+    }
+
+    /// Moves a wallet onto a freshly generated key
+    ///
+    /// Every non-zero balance is swept to the new address and the old
+    /// address is deprecated: it stays routed to the wallet so that late
+    /// deposits are still credited.
+    ///
+    /// # Errors
+    /// * [`CustodyError::KeyGenerationFailed`] without a key generator or
+    ///   if it fails
+    /// * [`CustodyError::WalletFrozen`] or [`CustodyError::WalletClosed`]
+    ///   if the wallet cannot move funds
+    /// * [`CustodyError::AddressInUse`] if the new address already
+    ///   belongs to a wallet
+    ///
+    /// # Example
+    /// ```
+    /// use securevault::{amount, CustodySystem, KeyGenerator, Wallet, WalletType};
+    /// use std::sync::Arc;
+    ///
+    /// #[derive(Debug)]
+    /// struct Suffixed;
+    ///
+    /// impl KeyGenerator for Suffixed {
+    ///     fn generate_key(&self, wallet: &Wallet) -> Result<String, String> {
+    ///         Ok(format!("{}-2", wallet.address))
+    ///     }
+    /// }
+    ///
+    /// let mut system = CustodySystem::new();
+    /// system.create_wallet("w".to_string(), "0x1".to_string(), WalletType::Hot).unwrap();
+    /// system.deposit("w", amount!(5)).unwrap();
+    /// system.set_key_generator(Arc::new(Suffixed));
+    ///
+    /// let rotation = system.rotate_wallet_key("w").unwrap();
+    /// assert_eq!(rotation.new_address, "0x1-2");
+    /// assert_eq!(rotation.sweeps.len(), 1);
+    /// assert_eq!(system.get_wallet("w").unwrap().balance, amount!(5));
+    /// assert_eq!(system.wallet_for_address("0x1"), Some("w"));
+    /// ```
+    pub fn rotate_wallet_key(&mut self, wallet_id: &str) -> Result<KeyRotation, CustodyError> {
+        let wallet = self
+            .get_wallet(wallet_id)
+            .ok_or_else(|| CustodyError::WalletNotFound(wallet_id.to_string()))?;
+        match wallet.status {
+            WalletStatus::Frozen => return Err(CustodyError::WalletFrozen(wallet_id.to_string())),
+            WalletStatus::Closed => return Err(CustodyError::WalletClosed(wallet_id.to_string())),
+            WalletStatus::Active | WalletStatus::Closing => {}
+        }
+        let generator = self
+            .key_generator
+            .as_ref()
+            .ok_or_else(|| CustodyError::KeyGenerationFailed("no key generator set".to_string()))?;
+        let new_address = generator
+            .generate_key(wallet)
+            .map_err(CustodyError::KeyGenerationFailed)?;
+        if let Some(chain) = &wallet.chain {
+            self.validate_address(chain, &new_address)?;
+        }
+        if self.wallet_for_address(&new_address).is_some() {
+            return Err(CustodyError::AddressInUse(new_address));
+        }
+
+        let old_address = wallet.address.clone();
+        let holdings: Vec<_> = wallet
+            .balances()
+            .filter(|(_, balance)| balance.is_positive())
+            .map(|(asset, balance)| (asset.clone(), balance))
+            .collect();
+        let mut sweeps = Vec::new();
+        for (asset, balance) in holdings {
+            let mut tx = Transaction::new(
+                wallet_id,
+                TransactionType::Transfer,
+                balance,
+                asset,
+                self.current_timestamp(),
+            );
+            tx.counterparty = Some(wallet_id.to_string());
+            tx.metadata
+                .insert(SWEEP_FROM_METADATA_KEY.to_string(), old_address.clone());
+            tx.metadata
+                .insert(SWEEP_TO_METADATA_KEY.to_string(), new_address.clone());
+            sweeps.push(self.next_transaction_id);
+            self.record_transaction(tx)?;
+        }
+
+        let mut wallet = self.wallets[wallet_id].clone();
+        wallet.deprecated_addresses.push(old_address.clone());
+        wallet.address = new_address.clone();
+        self.commit_wallet(wallet)?;
+        let rotation = KeyRotation {
+            wallet_id: wallet_id.to_string(),
+            old_address,
+            new_address,
+            sweeps,
+            rotated_at: self.current_timestamp(),
+        };
+        self.key_rotations.push_back(rotation.clone());
+        Ok(rotation)
+    }
+
+    /// Returns every key rotation, oldest first
+    pub fn key_rotations(&self) -> &im::Vector<KeyRotation> {
+        &self.key_rotations
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Asset, WalletType};
+
+    /// Hands out addresses from a fixed list
+    #[derive(Debug)]
+    struct Addresses(Vec<&'static str>);
+
+    impl KeyGenerator for Addresses {
+        fn generate_key(&self, wallet: &Wallet) -> Result<String, String> {
+            let rotated = wallet.deprecated_addresses.len();
+            self.0
+                .get(rotated)
+                .map(|address| address.to_string())
+                .ok_or_else(|| "out of keys".to_string())
+        }
+    }
+
+    fn system() -> CustodySystem {
+        let mut system = CustodySystem::new();
+        for (id, address) in [("a", "addr-a"), ("b", "addr-b")] {
+            system
+                .create_wallet(id.to_string(), address.to_string(), WalletType::Hot)
+                .unwrap();
+        }
+        system.deposit("a", amount!(3)).unwrap();
+        system.deposit_asset("a", &Asset::Eth, amount!(2)).unwrap();
+        system
+    }
+
+    #[test]
+    fn test_rotation_sweeps_every_holding() {
+        let mut system = system();
+        assert_eq!(
+            system.rotate_wallet_key("a"),
+            Err(CustodyError::KeyGenerationFailed(
+                "no key generator set".to_string()
+            ))
+        );
+        system.set_key_generator(Arc::new(Addresses(vec!["addr-a2", "addr-a3"])));
+        let first = system.rotate_wallet_key("a").unwrap();
+        assert_eq!(first.old_address, "addr-a");
+        assert_eq!(first.sweeps.len(), 2);
+        let sweep = system.get_transaction(first.sweeps[0]).unwrap();
+        assert_eq!(sweep.balance_change("a"), amount!(0));
+        assert_eq!(sweep.metadata[SWEEP_TO_METADATA_KEY], "addr-a2");
+
+        system.rotate_wallet_key("a").unwrap();
+        let wallet = system.get_wallet("a").unwrap();
+        assert_eq!(wallet.address, "addr-a3");
+        assert_eq!(wallet.deprecated_addresses, ["addr-a", "addr-a2"]);
+        assert_eq!(wallet.balance, amount!(3));
+        assert_eq!(wallet.balance_of(&Asset::Eth), amount!(2));
+        assert_eq!(system.key_rotations().len(), 2);
+        assert!(system.audit().passed());
+
+        // Stray deposits to a deprecated address still land in the wallet
+        system
+            .ingest_external_deposit("addr-a", amount!(1), "f00d", 6)
+            .unwrap();
+        assert_eq!(system.get_wallet("a").unwrap().balance, amount!(4));
+    }
+
+    #[test]
+    fn test_rotation_refusals() {
+        let mut system = system();
+        system.set_key_generator(Arc::new(Addresses(vec!["addr-b"])));
+        assert_eq!(
+            system.rotate_wallet_key("a"),
+            Err(CustodyError::AddressInUse("addr-b".to_string()))
+        );
+        system.freeze_wallet("a", "compromised").unwrap();
+        assert_eq!(
+            system.rotate_wallet_key("a"),
+            Err(CustodyError::WalletFrozen("a".to_string()))
+        );
+        assert_eq!(
+            system.rotate_wallet_key("c"),
+            Err(CustodyError::WalletNotFound("c".to_string()))
+        );
+        assert!(system.key_rotations().is_empty());
+        assert_eq!(system.get_wallet("a").unwrap().address, "addr-a");
+    }
+}
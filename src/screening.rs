@@ -0,0 +1,485 @@
+//! AML screening of outgoing funds.
+//!
+//! A [`ComplianceScreener`] sees every withdrawal, transfer and conversion
+//! before it is booked, with its destination and amount, and allows, blocks or flags it.
+//! Blocked operations fail with [`CustodyError::ScreeningBlocked`]. Flagged
+//! ones fail with [`CustodyError::FlaggedForReview`] and wait in a review
+//! queue until a compliance officer books them with
+//! [`CustodySystem::approve_flagged`] or drops them with
+//! [`CustodySystem::reject_flagged`].
+//!
+//! Withdrawals that go through an approval workflow (cold wallet
+//! approvers, multisig signers, joint owners) are screened when they are
+//! requested and not again when released. A flagged request only enters
+//! its workflow once a compliance officer clears it.
+
+use crate::precheck::Authorization;
+use crate::time::Timestamp;
+use crate::{
+    Amount, Asset, CustodyError, CustodyEvent, CustodySystem, OperationKind, OperationStatus,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+/// An outgoing operation presented for screening
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScreeningRequest {
+    /// [`OperationKind::Withdrawal`] or [`OperationKind::Transfer`]
+    pub kind: OperationKind,
+    /// Wallet the funds leave
+    pub wallet_id: String,
+    pub asset: Asset,
+    pub amount: Amount,
+    /// External destination of a withdrawal, if it names one, or the
+    /// address of a transfer's destination wallet
+    pub destination: Option<String>,
+    /// Destination wallet of a transfer
+    pub to_wallet_id: Option<String>,
+}
+
+/// A screener's decision
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScreeningVerdict {
+    Allow,
+    /// Hold for manual review, with the reason shown to reviewers
+    Flag(String),
+    /// Refuse, with the reason
+    Block(String),
+}
+
+/// Screens outgoing operations, e.g. against sanctions lists or a chain
+/// analytics provider
+pub trait ComplianceScreener: fmt::Debug + Send + Sync {
+    fn screen(&self, request: &ScreeningRequest) -> ScreeningVerdict;
+}
+
+/// A screener driven by a fixed address list and an amount threshold
+#[derive(Debug, Clone, Default)]
+pub struct RuleScreener {
+    blocked: HashMap<String, String>,
+    review_above: Option<Amount>,
+}
+
+impl RuleScreener {
+    /// Creates a screener that allows everything
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Blocks operations to `address`, giving `reason`
+    pub fn block_address(&mut self, address: &str, reason: &str) {
+        self.blocked.insert(address.to_string(), reason.to_string());
+    }
+
+    /// Flags operations of more than `amount` for review
+    pub fn review_above(&mut self, amount: Amount) {
+        self.review_above = Some(amount);
+    }
+}
+
+impl ComplianceScreener for RuleScreener {
+    fn screen(&self, request: &ScreeningRequest) -> ScreeningVerdict {
+        let blocked = request
+            .destination
+            .as_ref()
+            .and_then(|destination| self.blocked.get(destination));
+        if let Some(reason) = blocked {
+            return ScreeningVerdict::Block(reason.clone());
+        }
+        match self.review_above {
+            Some(limit) if request.amount > limit => {
+                ScreeningVerdict::Flag(format!("amount above review threshold {}", limit))
+            }
+            _ => ScreeningVerdict::Allow,
+        }
+    }
+}
+
+/// An operation held for compliance review
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FlaggedOperation {
+    pub id: u64,
+    pub request: ScreeningRequest,
+    /// The screener's reason for flagging
+    pub reason: String,
+    pub flagged_at: Timestamp,
+    pub status: OperationStatus,
+    pub reviewed_by: Option<String>,
+    /// Reason given for a rejection
+    pub review_note: Option<String>,
+    /// Authorization the operation was submitted with, checked again when
+    /// it is approved
+    authorization: Authorization,
+    /// Approval workflow a cleared withdrawal request enters instead of
+    /// being booked
+    workflow: Option<Workflow>,
+    /// Rate a flagged conversion is booked at once cleared
+    #[serde(default)]
+    conversion_rate: Option<f64>,
+}
+
+/// Approval workflow of a screened withdrawal request
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) enum Workflow {
+    /// Cold wallet approvals, requested by this operator
+    Approval { requester: String },
+    /// Multisig signatures, starting with this signer's
+    MultiSig { signer: String },
+    /// Joint owner signatures, starting with this owner's
+    Joint { owner: String },
+}
+
+impl CustodySystem {
+    /// Screens withdrawals and transfers with `screener` from now on
+    ///
+    /// # Example
+    /// ```
+    /// use securevault::{amount, CustodyError, CustodySystem, RuleScreener, WalletType};
+    /// use std::sync::Arc;
+    /// let mut system = CustodySystem::new();
+    /// system.create_wallet("a".to_string(), "0xa".to_string(), WalletType::Hot).unwrap();
+    /// system.create_wallet("b".to_string(), "0xb".to_string(), WalletType::Hot).unwrap();
+    /// system.deposit("a", amount!(50)).unwrap();
+    ///
+    /// let mut screener = RuleScreener::new();
+    /// screener.review_above(amount!(10));
+    /// system.set_compliance_screener(Arc::new(screener));
+    ///
+    /// let Err(CustodyError::FlaggedForReview(id)) = system.transfer("a", "b", amount!(20)) else {
+    ///     panic!("expected a review");
+    /// };
+    /// system.approve_flagged(id, "compliance-officer").unwrap();
+    /// assert_eq!(system.get_wallet("b").unwrap().balance, amount!(20));
+    /// ```
+    pub fn set_compliance_screener(&mut self, screener: Arc<dyn ComplianceScreener>) {
+        self.compliance_screener = Some(screener);
+    }
+
+    /// Stops screening operations; flagged operations stay queued
+    pub fn clear_compliance_screener(&mut self) {
+        self.compliance_screener = None;
+    }
+
+    /// Gets a flagged operation by id
+    pub fn get_flagged(&self, review_id: u64) -> Option<&FlaggedOperation> {
+        self.flagged_operations.get(&review_id)
+    }
+
+    /// Lists operations awaiting compliance review, oldest first
+    pub fn flagged_operations(&self) -> Vec<&FlaggedOperation> {
+        self.flagged_operations
+            .values()
+            .filter(|flagged| flagged.status == OperationStatus::Pending)
+            .collect()
+    }
+
+    /// Clears a flagged operation and books it
+    ///
+    /// The operation is checked again as it was submitted, without
+    /// screening. If it can no longer execute it stays pending and can be
+    /// rejected. A withdrawal request of an approval workflow is opened
+    /// rather than booked, and still needs its approvals or signatures.
+    pub fn approve_flagged(&mut self, review_id: u64, reviewer: &str) -> Result<(), CustodyError> {
+        let flagged = self.pending_flagged(review_id)?.clone();
+        let request = &flagged.request;
+        if let Some(workflow) = &flagged.workflow {
+            self.open_withdrawal_request(request, workflow)?;
+            self.close_flagged(review_id, OperationStatus::Executed, reviewer, None);
+            return Ok(());
+        }
+        match &request.to_wallet_id {
+            Some(to_id) if request.kind == OperationKind::Conversion => {
+                self.check_conversion(&request.wallet_id, to_id, request.amount)?;
+                let rate = flagged
+                    .conversion_rate
+                    .ok_or(CustodyError::InvalidRate(0.0))?;
+                self.book_conversion(&request.wallet_id, to_id, request.amount, rate)?;
+            }
+            Some(to_id) => {
+                let asset = self.check_transfer(
+                    &request.wallet_id,
+                    to_id,
+                    Some(&request.asset),
+                    request.amount,
+                )?;
+                self.settle_transfer(&request.wallet_id, to_id, asset, request.amount)?;
+            }
+            None => {
+                let reasons = self.withdrawal_blockers(
+                    &request.wallet_id,
+                    Some(&request.asset),
+                    request.amount,
+                    request.destination.as_deref(),
+                    flagged.authorization,
+                );
+                if let Some(reason) = reasons.into_iter().next() {
+                    return Err(reason);
+                }
+                self.settle_withdrawal(&request.wallet_id, request.asset.clone(), request.amount)?;
+            }
+        }
+        self.close_flagged(review_id, OperationStatus::Executed, reviewer, None);
+        Ok(())
+    }
+
+    /// Rejects a flagged operation; it can no longer execute
+    pub fn reject_flagged(
+        &mut self,
+        review_id: u64,
+        reviewer: &str,
+        reason: &str,
+    ) -> Result<(), CustodyError> {
+        self.pending_flagged(review_id)?;
+        self.close_flagged(
+            review_id,
+            OperationStatus::Rejected,
+            reviewer,
+            Some(reason.to_string()),
+        );
+        Ok(())
+    }
+
+    /// Runs the compliance screener, queueing flagged operations
+    pub(crate) fn screen(
+        &mut self,
+        request: ScreeningRequest,
+        authorization: Authorization,
+    ) -> Result<(), CustodyError> {
+        self.screen_with(request, authorization, None)
+    }
+
+    /// Screens a conversion; a flagged one keeps its rate for when it is
+    /// cleared
+    pub(crate) fn screen_conversion(
+        &mut self,
+        request: ScreeningRequest,
+        rate: f64,
+    ) -> Result<(), CustodyError> {
+        let result = self.screen(request, Authorization::Direct);
+        if let Err(CustodyError::FlaggedForReview(id)) = result {
+            if let Some(flagged) = self.flagged_operations.get_mut(&id) {
+                flagged.conversion_rate = Some(rate);
+            }
+        }
+        result
+    }
+
+    /// Screens a withdrawal request of an approval workflow before it is
+    /// opened; a flagged request is opened once cleared
+    pub(crate) fn screen_withdrawal_request(
+        &mut self,
+        wallet_id: &str,
+        amount: Amount,
+        destination: Option<&str>,
+        workflow: Workflow,
+    ) -> Result<(), CustodyError> {
+        let request = ScreeningRequest {
+            kind: OperationKind::Withdrawal,
+            wallet_id: wallet_id.to_string(),
+            asset: self.wallets[wallet_id].asset.clone(),
+            amount,
+            destination: destination.map(str::to_string),
+            to_wallet_id: None,
+        };
+        self.screen_with(request, Authorization::Approved, Some(workflow))
+    }
+
+    /// Opens a cleared withdrawal request in its workflow
+    fn open_withdrawal_request(
+        &mut self,
+        request: &ScreeningRequest,
+        workflow: &Workflow,
+    ) -> Result<u64, CustodyError> {
+        let (wallet_id, amount) = (request.wallet_id.as_str(), request.amount);
+        let destination = request.destination.as_deref();
+        match workflow {
+            Workflow::Approval { requester } => {
+                self.open_approval_request(wallet_id, requester, amount, destination)
+            }
+            Workflow::MultiSig { signer } => {
+                self.open_multisig_withdrawal(wallet_id, signer, amount, destination)
+            }
+            Workflow::Joint { owner } => self.open_joint_withdrawal(wallet_id, owner, amount),
+        }
+    }
+
+    fn screen_with(
+        &mut self,
+        request: ScreeningRequest,
+        authorization: Authorization,
+        workflow: Option<Workflow>,
+    ) -> Result<(), CustodyError> {
+        let Some(screener) = &self.compliance_screener else {
+            return Ok(());
+        };
+        let reason = match screener.screen(&request) {
+            ScreeningVerdict::Allow => return Ok(()),
+            ScreeningVerdict::Block(reason) => return Err(CustodyError::ScreeningBlocked(reason)),
+            ScreeningVerdict::Flag(reason) => reason,
+        };
+
+        let id = self.allocate_operation_id();
+        self.emit(CustodyEvent::OperationFlagged {
+            wallet_id: request.wallet_id.clone(),
+            review_id: id,
+            amount: request.amount,
+            asset: request.asset.clone(),
+            reason: reason.clone(),
+        });
+        self.flagged_operations.insert(
+            id,
+            FlaggedOperation {
+                id,
+                request,
+                reason,
+                flagged_at: self.current_timestamp(),
+                status: OperationStatus::Pending,
+                reviewed_by: None,
+                review_note: None,
+                authorization,
+                workflow,
+                conversion_rate: None,
+            },
+        );
+        Err(CustodyError::FlaggedForReview(id))
+    }
+
+    fn pending_flagged(&self, review_id: u64) -> Result<&FlaggedOperation, CustodyError> {
+        let flagged = self
+            .flagged_operations
+            .get(&review_id)
+            .ok_or(CustodyError::OperationNotFound(review_id))?;
+        if flagged.status != OperationStatus::Pending {
+            return Err(CustodyError::OperationNotPending(review_id));
+        }
+        Ok(flagged)
+    }
+
+    fn close_flagged(
+        &mut self,
+        review_id: u64,
+        status: OperationStatus,
+        reviewer: &str,
+        note: Option<String>,
+    ) {
+        if let Some(flagged) = self.flagged_operations.get_mut(&review_id) {
+            flagged.status = status;
+            flagged.reviewed_by = Some(reviewer.to_string());
+            flagged.review_note = note;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::WalletType;
+
+    fn system() -> CustodySystem {
+        let mut system = CustodySystem::new();
+        for id in ["a", "b"] {
+            system
+                .create_wallet(id.to_string(), format!("0x{}", id), WalletType::Hot)
+                .unwrap();
+        }
+        system.deposit("a", amount!(100)).unwrap();
+        let mut screener = RuleScreener::new();
+        screener.block_address("0xb", "sanctioned address");
+        screener.review_above(amount!(10));
+        system.set_compliance_screener(Arc::new(screener));
+        system
+    }
+
+    #[test]
+    fn test_screener_blocks_and_allows() {
+        let mut system = system();
+        assert_eq!(
+            system.transfer("a", "b", amount!(1)),
+            Err(CustodyError::ScreeningBlocked(
+                "sanctioned address".to_string()
+            ))
+        );
+        system.withdraw("a", amount!(5)).unwrap();
+        assert_eq!(system.get_wallet("a").unwrap().balance, amount!(95));
+        assert!(system.flagged_operations().is_empty());
+    }
+
+    #[test]
+    fn test_flagged_withdrawal_waits_for_review() {
+        let mut system = system();
+        let Err(CustodyError::FlaggedForReview(id)) = system.withdraw("a", amount!(40)) else {
+            panic!("expected a review");
+        };
+        assert_eq!(system.get_wallet("a").unwrap().balance, amount!(100));
+        let flagged = system.flagged_operations()[0];
+        assert_eq!(flagged.request.kind, OperationKind::Withdrawal);
+        assert!(flagged.reason.contains("review threshold"));
+
+        system.approve_flagged(id, "officer").unwrap();
+        assert_eq!(system.get_wallet("a").unwrap().balance, amount!(60));
+        assert_eq!(
+            system.get_flagged(id).unwrap().reviewed_by.as_deref(),
+            Some("officer")
+        );
+        assert_eq!(
+            system.approve_flagged(id, "officer"),
+            Err(CustodyError::OperationNotPending(id))
+        );
+    }
+
+    #[test]
+    fn test_approval_workflow_requests_are_screened() {
+        let mut system = system();
+        system
+            .create_wallet("cold".to_string(), "0xcold".to_string(), WalletType::Cold)
+            .unwrap();
+        system.deposit("cold", amount!(100)).unwrap();
+        system
+            .set_withdrawal_approvers("cold", &["alice", "bob"])
+            .unwrap();
+        assert_eq!(
+            system.request_withdrawal("cold", "ops", amount!(1), Some("0xb")),
+            Err(CustodyError::ScreeningBlocked(
+                "sanctioned address".to_string()
+            ))
+        );
+        assert!(system.pending_withdrawals("cold").is_empty());
+
+        let Err(CustodyError::FlaggedForReview(id)) =
+            system.request_withdrawal("cold", "ops", amount!(40), Some("0xd"))
+        else {
+            panic!("expected a review");
+        };
+        assert!(system.pending_withdrawals("cold").is_empty());
+        system.approve_flagged(id, "officer").unwrap();
+        let request = system.pending_withdrawals("cold")[0].id;
+        assert_eq!(system.get_wallet("cold").unwrap().balance, amount!(100));
+        system.approve_withdrawal(request, "alice").unwrap();
+        system.approve_withdrawal(request, "bob").unwrap();
+        assert_eq!(system.get_wallet("cold").unwrap().balance, amount!(60));
+    }
+
+    #[test]
+    fn test_rejected_operation_never_executes() {
+        let mut system = system();
+        system
+            .create_wallet("c".to_string(), "0xc".to_string(), WalletType::Hot)
+            .unwrap();
+        let Err(CustodyError::FlaggedForReview(id)) = system.transfer("a", "c", amount!(50)) else {
+            panic!("expected a review");
+        };
+        system
+            .reject_flagged(id, "officer", "counterparty unknown")
+            .unwrap();
+        let flagged = system.get_flagged(id).unwrap();
+        assert_eq!(flagged.status, OperationStatus::Rejected);
+        assert_eq!(flagged.review_note.as_deref(), Some("counterparty unknown"));
+        assert_eq!(
+            system.approve_flagged(id, "officer"),
+            Err(CustodyError::OperationNotPending(id))
+        );
+        assert_eq!(system.get_wallet("c").unwrap().balance, Amount::ZERO);
+    }
+}
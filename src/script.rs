@@ -0,0 +1,295 @@
+//! Rhai validation hooks (feature `scripting`).
+//!
+//! Operations teams can attach [Rhai](https://rhai.rs) scripts that run
+//! before every deposit and withdrawal and may block it, e.g.
+//!
+//! ```text
+//! if wallet.wallet_type == "cold" && op.kind == "withdrawal"
+//!     && (op.weekday == "Sat" || op.weekday == "Sun") {
+//!     "cold wallet withdrawals are not allowed on weekends"
+//! }
+//! ```
+//!
+//! Scripts see read-only copies of two maps and nothing else:
+//!
+//! * `wallet`: `id`, `address`, `balance`, `wallet_type` (`"hot"`/`"cold"`/`"multisig"`),
+//!   `asset`, `tags`
+//! * `op`: `kind` (`"deposit"`/`"withdrawal"`), `amount`, `destination`
+//!   (or `()`), `timestamp` (Unix seconds), `weekday` (`"Mon"`..`"Sun"`),
+//!   `hour` (0-23, UTC)
+//!
+//! A script allows the operation by returning `()` or `true`, and blocks it
+//! by returning `false` or a string with the reason. Scripts run with
+//! operation, depth, and size limits; one that fails or exceeds a limit
+//! blocks the operation.
+
+use crate::time::Timestamp;
+use crate::{Amount, CustodyError, CustodySystem, Wallet, WalletType};
+use rhai::{Dynamic, Engine, Map, Scope, AST};
+use std::sync::{Arc, OnceLock};
+
+/// Maximum number of operations a single script run may perform
+const MAX_OPERATIONS: u64 = 50_000;
+
+/// A compiled validation script
+#[derive(Debug)]
+pub(crate) struct ScriptHook {
+    name: String,
+    ast: AST,
+}
+
+fn engine() -> &'static Engine {
+    static ENGINE: OnceLock<Engine> = OnceLock::new();
+    ENGINE.get_or_init(|| {
+        let mut engine = Engine::new();
+        engine
+            .set_max_operations(MAX_OPERATIONS)
+            .set_max_call_levels(32)
+            .set_max_expr_depths(64, 32)
+            .set_max_string_size(4096)
+            .set_max_array_size(1024)
+            .set_max_map_size(256)
+            .disable_symbol("eval");
+        engine
+    })
+}
+
+impl CustodySystem {
+    /// Compiles `source` and attaches it as a pre-transaction hook named
+    /// `name`, replacing any hook with the same name
+    pub fn attach_script_hook(&mut self, name: &str, source: &str) -> Result<(), CustodyError> {
+        let ast = engine()
+            .compile(source)
+            .map_err(|err| CustodyError::ScriptError {
+                hook: name.to_string(),
+                message: err.to_string(),
+            })?;
+        let hook = Arc::new(ScriptHook {
+            name: name.to_string(),
+            ast,
+        });
+        match self.script_hooks.iter().position(|h| h.name == name) {
+            Some(index) => {
+                self.script_hooks.set(index, hook);
+            }
+            None => self.script_hooks.push_back(hook),
+        }
+        Ok(())
+    }
+
+    /// Detaches the hook named `name`, returning true if it existed
+    pub fn detach_script_hook(&mut self, name: &str) -> bool {
+        match self.script_hooks.iter().position(|h| h.name == name) {
+            Some(index) => {
+                self.script_hooks.remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Names of the attached hooks, in evaluation order
+    pub fn script_hooks(&self) -> Vec<&str> {
+        self.script_hooks.iter().map(|h| h.name.as_str()).collect()
+    }
+
+    /// Runs every hook against the operation, collecting rejections
+    pub(crate) fn hook_blockers(
+        &self,
+        wallet: &Wallet,
+        kind: &str,
+        amount: Amount,
+        destination: Option<&str>,
+    ) -> Vec<CustodyError> {
+        if self.script_hooks.is_empty() {
+            return Vec::new();
+        }
+        let wallet_map = wallet_view(wallet);
+        let op_map = operation_view(kind, amount, destination, self.current_timestamp());
+
+        let mut reasons = Vec::new();
+        for hook in &self.script_hooks {
+            let mut scope = Scope::new();
+            scope.push_constant("wallet", wallet_map.clone());
+            scope.push_constant("op", op_map.clone());
+            let result = engine().eval_ast_with_scope::<Dynamic>(&mut scope, &hook.ast);
+            let rejection = match result {
+                Ok(value) if value.is_unit() => None,
+                Ok(value) if value.is_bool() => {
+                    (!value.as_bool().unwrap_or(false)).then(|| "rejected by script".to_string())
+                }
+                Ok(value) if value.is_string() => Some(value.into_string().unwrap_or_default()),
+                Ok(value) => {
+                    reasons.push(CustodyError::ScriptError {
+                        hook: hook.name.clone(),
+                        message: format!("unexpected result of type {}", value.type_name()),
+                    });
+                    continue;
+                }
+                Err(err) => {
+                    reasons.push(CustodyError::ScriptError {
+                        hook: hook.name.clone(),
+                        message: err.to_string(),
+                    });
+                    continue;
+                }
+            };
+            if let Some(reason) = rejection {
+                reasons.push(CustodyError::ScriptRejected {
+                    hook: hook.name.clone(),
+                    reason,
+                });
+            }
+        }
+        reasons
+    }
+}
+
+fn wallet_view(wallet: &Wallet) -> Map {
+    let mut map = Map::new();
+    map.insert("id".into(), wallet.id.clone().into());
+    map.insert("address".into(), wallet.address.clone().into());
+    map.insert("balance".into(), wallet.balance.to_f64().into());
+    let wallet_type = match wallet.wallet_type {
+        WalletType::Hot => "hot",
+        WalletType::Cold => "cold",
+        WalletType::MultiSig { .. } => "multisig",
+    };
+    map.insert("wallet_type".into(), wallet_type.into());
+    map.insert("asset".into(), wallet.asset.symbol().into());
+    let tags: rhai::Array = wallet.tags.iter().map(|t| t.clone().into()).collect();
+    map.insert("tags".into(), tags.into());
+    map
+}
+
+fn operation_view(kind: &str, amount: Amount, destination: Option<&str>, now: Timestamp) -> Map {
+    let mut map = Map::new();
+    map.insert("kind".into(), kind.into());
+    map.insert("amount".into(), amount.to_f64().into());
+    map.insert(
+        "destination".into(),
+        destination.map_or(Dynamic::UNIT, |d| d.into()),
+    );
+    map.insert("timestamp".into(), (now.as_unix() as i64).into());
+    let datetime = now.datetime();
+    map.insert("weekday".into(), datetime.format("%a").to_string().into());
+    map.insert(
+        "hour".into(),
+        (datetime
+            .format("%H")
+            .to_string()
+            .parse::<i64>()
+            .unwrap_or(0))
+        .into(),
+    );
+    map
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn system() -> CustodySystem {
+        let mut system = CustodySystem::new();
+        system
+            .create_wallet("cold".to_string(), "bc1c".to_string(), WalletType::Cold)
+            .unwrap();
+        system
+            .create_wallet("hot".to_string(), "bc1h".to_string(), WalletType::Hot)
+            .unwrap();
+        system.deposit("cold", amount!(10.0)).unwrap();
+        system.deposit("hot", amount!(10.0)).unwrap();
+        system
+    }
+
+    #[test]
+    fn test_script_blocks_withdrawal_with_reason() {
+        let mut system = system();
+        system
+            .attach_script_hook(
+                "hot_limit",
+                r#"if wallet.wallet_type == "hot" && op.kind == "withdrawal" && op.amount > 6.0 {
+                       "hot withdrawals above 6 need approval"
+                   }"#,
+            )
+            .unwrap();
+
+        assert_eq!(
+            system.withdraw("hot", amount!(7.0)),
+            Err(CustodyError::ScriptRejected {
+                hook: "hot_limit".to_string(),
+                reason: "hot withdrawals above 6 need approval".to_string(),
+            })
+        );
+        assert!(!system.can_withdraw("hot", amount!(7.0), None).is_allowed());
+        system.withdraw("hot", amount!(0.5)).unwrap();
+        system.withdraw("hot", amount!(5.0)).unwrap();
+    }
+
+    #[test]
+    fn test_blocked_credit_rolls_back_transfer() {
+        let mut system = system();
+        system
+            .attach_script_hook(
+                "cold_inbound",
+                r#"if wallet.id == "cold" && op.kind == "deposit" { "cold is closed" }"#,
+            )
+            .unwrap();
+
+        assert!(matches!(
+            system.transfer("hot", "cold", amount!(4.0)),
+            Err(CustodyError::ScriptRejected { .. })
+        ));
+        assert_eq!(system.get_wallet("hot").unwrap().balance, amount!(10.0));
+        assert_eq!(system.get_wallet("cold").unwrap().balance, amount!(10.0));
+        assert_eq!(system.get_all_transactions().len(), 2);
+    }
+
+    #[test]
+    fn test_script_sees_operation_fields() {
+        let mut system = system();
+        system
+            .attach_script_hook(
+                "fields",
+                r#"["Mon","Tue","Wed","Thu","Fri","Sat","Sun"].contains(op.weekday)
+                   && op.hour >= 0 && op.hour < 24 && op.timestamp > 0
+                   && op.destination == () && wallet.tags.len() == 0"#,
+            )
+            .unwrap();
+        system.deposit("hot", amount!(1.0)).unwrap();
+        assert!(system.can_withdraw("hot", amount!(1.0), None).is_allowed());
+        assert!(!system
+            .can_withdraw("hot", amount!(1.0), Some("bc1dest"))
+            .is_allowed());
+    }
+
+    #[test]
+    fn test_failing_scripts_block() {
+        let mut system = system();
+        assert!(matches!(
+            system.attach_script_hook("broken", "if {"),
+            Err(CustodyError::ScriptError { .. })
+        ));
+        system.attach_script_hook("spin", "loop { }").unwrap();
+        assert!(matches!(
+            system.deposit("hot", amount!(1.0)),
+            Err(CustodyError::ScriptError { .. })
+        ));
+        assert!(system.detach_script_hook("spin"));
+        assert!(system.script_hooks().is_empty());
+        system.deposit("hot", amount!(1.0)).unwrap();
+    }
+
+    #[test]
+    fn test_scripts_cannot_modify_wallet() {
+        let mut system = system();
+        system
+            .attach_script_hook("tamper", "wallet.balance = 1000.0; true")
+            .unwrap();
+        assert!(matches!(
+            system.deposit("hot", amount!(1.0)),
+            Err(CustodyError::ScriptError { .. })
+        ));
+        assert_eq!(system.get_wallet("hot").unwrap().balance, amount!(10.0));
+    }
+}
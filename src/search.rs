@@ -0,0 +1,276 @@
+//! Global search over wallets and transactions.
+//!
+//! [`CustodySystem::search`] looks a free-text query up in wallet ids,
+//! addresses, tags, memos and metadata values, and in the memos, tags,
+//! references and metadata values of transactions, the way an operator
+//! would use a search box. Matching ignores case. Each wallet or
+//! transaction appears once, under the field it matches best, and results
+//! are ranked: exact matches before prefixes before matches anywhere, and
+//! within each, identifying fields (ids, addresses, references) before
+//! annotations.
+
+use crate::{CustodySystem, Transaction, Wallet};
+use std::cmp::Reverse;
+
+/// Field a search result matched in
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MatchedField {
+    WalletId,
+    /// Current or deprecated address
+    Address,
+    /// External reference of a transaction, e.g. an on-chain hash
+    Reference,
+    Tag(String),
+    Memo,
+    /// Value of the metadata entry with this key
+    Metadata(String),
+}
+
+impl MatchedField {
+    /// How much a match in this field says about what the user is after
+    fn weight(&self) -> u32 {
+        match self {
+            MatchedField::WalletId => 5,
+            MatchedField::Address | MatchedField::Reference => 4,
+            MatchedField::Tag(_) => 3,
+            MatchedField::Memo => 2,
+            MatchedField::Metadata(_) => 1,
+        }
+    }
+}
+
+/// What a search result points at
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SearchItem<'a> {
+    Wallet(&'a Wallet),
+    /// A transaction and its position in the log
+    Transaction {
+        index: usize,
+        transaction: &'a Transaction,
+    },
+}
+
+/// A wallet or transaction matching a search
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchResult<'a> {
+    pub item: SearchItem<'a>,
+    /// The best-matching field
+    pub field: MatchedField,
+    /// Higher scores rank first
+    pub score: u32,
+}
+
+/// How closely a value matches the query
+fn quality(value: &str, query: &str) -> Option<u32> {
+    let value = value.to_lowercase();
+    if value == query {
+        Some(3)
+    } else if value.starts_with(query) {
+        Some(2)
+    } else if value.contains(query) {
+        Some(1)
+    } else {
+        None
+    }
+}
+
+/// Scores the best of `fields` against the query
+fn best_match<'f>(
+    fields: impl Iterator<Item = (MatchedField, &'f str)>,
+    query: &str,
+) -> Option<(MatchedField, u32)> {
+    fields
+        .filter_map(|(field, value)| {
+            let score = quality(value, query)? * 10 + field.weight();
+            Some((field, score))
+        })
+        .max_by_key(|(_, score)| *score)
+}
+
+impl CustodySystem {
+    /// Finds the active wallets and the transactions matching `query`,
+    /// best first
+    ///
+    /// Equal scores rank wallets before transactions, wallets by id and
+    /// transactions newest first. A blank query matches nothing.
+    ///
+    /// # Example
+    /// ```
+    /// use securevault::{amount, CustodySystem, MatchedField, SearchItem, TransactionMetadata, WalletType};
+    /// let mut system = CustodySystem::new();
+    /// system.create_wallet("acme-hot".to_string(), "0xacme".to_string(), WalletType::Hot).unwrap();
+    /// system.create_wallet("treasury".to_string(), "0x2".to_string(), WalletType::Cold).unwrap();
+    /// system
+    ///     .deposit_with_metadata("treasury", amount!(5), TransactionMetadata::new().with_memo("ACME invoice 7"))
+    ///     .unwrap();
+    ///
+    /// let results = system.search("acme");
+    /// assert_eq!(results.len(), 2);
+    /// assert!(matches!(results[0].item, SearchItem::Wallet(wallet) if wallet.id == "acme-hot"));
+    /// assert!(matches!(results[1].item, SearchItem::Transaction { index: 0, .. }));
+    /// assert_eq!(results[1].field, MatchedField::Memo);
+    /// ```
+    pub fn search(&self, query: &str) -> Vec<SearchResult<'_>> {
+        let query = query.trim().to_lowercase();
+        if query.is_empty() {
+            return Vec::new();
+        }
+        let wallets = self.wallets.values().filter_map(|wallet| {
+            let fields = std::iter::once((MatchedField::WalletId, wallet.id.as_str()))
+                .chain(
+                    wallet
+                        .addresses()
+                        .map(|address| (MatchedField::Address, address)),
+                )
+                .chain(wallet_annotations(wallet));
+            let (field, score) = best_match(fields, &query)?;
+            Some(SearchResult {
+                item: SearchItem::Wallet(wallet),
+                field,
+                score,
+            })
+        });
+        let transactions =
+            self.transactions
+                .iter()
+                .enumerate()
+                .filter_map(|(index, transaction)| {
+                    let fields = transaction
+                        .reference
+                        .as_deref()
+                        .map(|reference| (MatchedField::Reference, reference))
+                        .into_iter()
+                        .chain(
+                            transaction
+                                .tags
+                                .iter()
+                                .map(|tag| (MatchedField::Tag(tag.clone()), tag.as_str())),
+                        )
+                        .chain(
+                            transaction
+                                .memo
+                                .as_deref()
+                                .map(|memo| (MatchedField::Memo, memo)),
+                        )
+                        .chain(transaction.metadata.iter().map(|(key, value)| {
+                            (MatchedField::Metadata(key.clone()), value.as_str())
+                        }));
+                    let (field, score) = best_match(fields, &query)?;
+                    Some(SearchResult {
+                        item: SearchItem::Transaction { index, transaction },
+                        field,
+                        score,
+                    })
+                });
+
+        let mut results: Vec<_> = wallets.chain(transactions).collect();
+        results.sort_by_key(|result| {
+            let tiebreak = match result.item {
+                SearchItem::Wallet(wallet) => (0, wallet.id.as_str(), Reverse(0)),
+                SearchItem::Transaction { index, .. } => (1, "", Reverse(index)),
+            };
+            (Reverse(result.score), tiebreak)
+        });
+        results
+    }
+}
+
+fn wallet_annotations(wallet: &Wallet) -> impl Iterator<Item = (MatchedField, &str)> {
+    let tags = wallet
+        .tags
+        .iter()
+        .map(|tag| (MatchedField::Tag(tag.clone()), tag.as_str()));
+    let memo = wallet
+        .memo
+        .as_deref()
+        .map(|memo| (MatchedField::Memo, memo));
+    let metadata = wallet
+        .metadata
+        .iter()
+        .map(|(key, value)| (MatchedField::Metadata(key.clone()), value.as_str()));
+    tags.chain(memo).chain(metadata)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{TransactionMetadata, WalletType};
+
+    fn system() -> CustodySystem {
+        let mut system = CustodySystem::new();
+        for (id, address) in [("ops", "bc1qops"), ("ops-reserve", "bc1qreserve")] {
+            system
+                .create_wallet(id.to_string(), address.to_string(), WalletType::Hot)
+                .unwrap();
+        }
+        system.tag_wallet("ops-reserve", "treasury").unwrap();
+        system
+            .set_wallet_metadata("ops", "desk", Some("Treasury ops"))
+            .unwrap();
+        system
+            .deposit_with_metadata(
+                "ops",
+                amount!(5),
+                TransactionMetadata::new()
+                    .with_memo("treasury top-up")
+                    .with_tag("TICKET-9"),
+            )
+            .unwrap();
+        system
+            .withdraw_with_metadata(
+                "ops",
+                amount!(1),
+                TransactionMetadata::new().with_field("customer", "C-9"),
+            )
+            .unwrap();
+        system
+    }
+
+    fn describe(results: &[SearchResult]) -> Vec<String> {
+        results
+            .iter()
+            .map(|result| match result.item {
+                SearchItem::Wallet(wallet) => format!("wallet {}", wallet.id),
+                SearchItem::Transaction { index, .. } => format!("tx {}", index),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_results_are_ranked() {
+        let system = system();
+        let results = system.search("  Treasury ");
+        assert_eq!(
+            describe(&results),
+            ["wallet ops-reserve", "tx 0", "wallet ops"]
+        );
+        assert_eq!(results[0].field, MatchedField::Tag("treasury".to_string()));
+        // Prefixes of a memo outrank prefixes of a metadata value
+        assert_eq!(results[1].field, MatchedField::Memo);
+        assert_eq!(results[2].field, MatchedField::Metadata("desk".to_string()));
+
+        // The id beats the address prefix of the other wallet
+        let results = system.search("ops");
+        assert_eq!(describe(&results), ["wallet ops", "wallet ops-reserve"]);
+        assert_eq!(results[0].field, MatchedField::WalletId);
+        assert!(results[0].score > results[1].score);
+    }
+
+    #[test]
+    fn test_transaction_fields_and_blank_queries() {
+        let system = system();
+        let results = system.search("9");
+        assert_eq!(describe(&results), ["tx 0", "tx 1"]);
+        assert_eq!(results[0].field, MatchedField::Tag("TICKET-9".to_string()));
+        assert_eq!(
+            results[1].field,
+            MatchedField::Metadata("customer".to_string())
+        );
+        assert_eq!(
+            describe(&system.search("bc1qreserve")),
+            ["wallet ops-reserve"]
+        );
+        assert!(system.search("   ").is_empty());
+        assert!(system.search("nothing").is_empty());
+    }
+}
@@ -0,0 +1,355 @@
+//! Shamir secret sharing of the key vault master key (feature
+//! `key-shares`).
+//!
+//! [`KeyVault::split_master_key`] splits the master key of an unlocked
+//! vault into `count` shares, any `threshold` of which rebuild it, so that
+//! custodians can hold the vault in escrow together without any of them
+//! knowing the passphrase. [`KeyVault::recover_master_key`] rebuilds the
+//! key and unlocks the vault, refusing shares that do not open it.
+//!
+//! Sharing works byte by byte over GF(2^8) with the AES polynomial; share
+//! numbers are the evaluation points 1 to 255. Shares export as:
+//!
+//! * hex: threshold, share number, the 32 share bytes and the first four
+//!   bytes of their SHA-256, as one hex string;
+//! * mnemonic: two words giving the threshold and the share number by
+//!   their position in the BIP-39 English word list, followed by the 24
+//!   BIP-39 words of the share bytes.
+
+use crate::{CustodyError, KeyVault};
+use bip39::{Language, Mnemonic};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use zeroize::Zeroizing;
+
+/// Length of the master key, and so of every share
+const KEY_BYTES: usize = 32;
+
+/// Length of the checksum closing a hex share
+const CHECKSUM_BYTES: usize = 4;
+
+/// One share of a vault master key
+#[derive(Clone, PartialEq, Eq)]
+pub struct KeyShare {
+    threshold: u8,
+    index: u8,
+    value: Zeroizing<[u8; KEY_BYTES]>,
+}
+
+impl fmt::Debug for KeyShare {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeyShare")
+            .field("threshold", &self.threshold)
+            .field("index", &self.index)
+            .finish_non_exhaustive()
+    }
+}
+
+impl KeyShare {
+    /// Number of shares needed to recover the key
+    pub fn threshold(&self) -> u8 {
+        self.threshold
+    }
+
+    /// Share number, from 1
+    pub fn index(&self) -> u8 {
+        self.index
+    }
+
+    /// Encodes the share as hex
+    pub fn to_hex(&self) -> Zeroizing<String> {
+        let mut bytes = Zeroizing::new(Vec::with_capacity(2 + KEY_BYTES + CHECKSUM_BYTES));
+        bytes.extend([self.threshold, self.index]);
+        bytes.extend_from_slice(self.value.as_ref());
+        let checksum = Sha256::digest(bytes.as_slice());
+        bytes.extend_from_slice(&checksum[..CHECKSUM_BYTES]);
+        Zeroizing::new(hex::encode(bytes.as_slice()))
+    }
+
+    /// Decodes a share written by [`to_hex`](Self::to_hex)
+    pub fn from_hex(encoded: &str) -> Result<Self, CustodyError> {
+        let bytes =
+            Zeroizing::new(hex::decode(encoded.trim()).map_err(|_| invalid("share is not hex"))?);
+        if bytes.len() != 2 + KEY_BYTES + CHECKSUM_BYTES {
+            return Err(invalid("share has the wrong length"));
+        }
+        let (body, checksum) = bytes.split_at(2 + KEY_BYTES);
+        if Sha256::digest(body)[..CHECKSUM_BYTES] != *checksum {
+            return Err(invalid("share checksum mismatch"));
+        }
+        Self::new(body[0], body[1], &body[2..])
+    }
+
+    /// Encodes the share as 26 BIP-39 English words
+    pub fn to_mnemonic(&self) -> Zeroizing<String> {
+        let words = Language::English.word_list();
+        let mnemonic = Mnemonic::from_entropy(self.value.as_ref())
+            .expect("32 bytes is a valid BIP-39 entropy length");
+        Zeroizing::new(format!(
+            "{} {} {}",
+            words[usize::from(self.threshold)],
+            words[usize::from(self.index)],
+            mnemonic
+        ))
+    }
+
+    /// Decodes a share written by [`to_mnemonic`](Self::to_mnemonic)
+    pub fn from_mnemonic(phrase: &str) -> Result<Self, CustodyError> {
+        let words: Vec<&str> = phrase.split_whitespace().collect();
+        let [threshold, index, rest @ ..] = words.as_slice() else {
+            return Err(invalid("share has too few words"));
+        };
+        let number = |word: &str| {
+            Language::English
+                .find_word(&word.to_lowercase())
+                .and_then(|position| u8::try_from(position).ok())
+                .ok_or_else(|| invalid(&format!("\"{}\" is not a share header word", word)))
+        };
+        let (threshold, index) = (number(threshold)?, number(index)?);
+        let mnemonic =
+            Mnemonic::parse_in_normalized(Language::English, &rest.join(" ").to_lowercase())
+                .map_err(|err| invalid(&err.to_string()))?;
+        let value = Zeroizing::new(mnemonic.to_entropy());
+        Self::new(threshold, index, &value)
+    }
+
+    fn new(threshold: u8, index: u8, value: &[u8]) -> Result<Self, CustodyError> {
+        if threshold < 2 || index == 0 {
+            return Err(invalid("share header is out of range"));
+        }
+        let value: [u8; KEY_BYTES] = value
+            .try_into()
+            .map_err(|_| invalid("share has the wrong length"))?;
+        Ok(Self {
+            threshold,
+            index,
+            value: Zeroizing::new(value),
+        })
+    }
+}
+
+impl KeyVault {
+    /// Splits the master key into `count` shares, any `threshold` of which
+    /// recover it
+    ///
+    /// # Errors
+    /// * [`CustodyError::VaultLocked`] if the vault is locked
+    /// * [`CustodyError::InvalidKeyShare`] unless
+    ///   `2 <= threshold <= count`
+    ///
+    /// # Example
+    /// ```
+    /// use securevault::{KeyShare, KeyVault};
+    /// let mut vault = KeyVault::create("correct horse battery").unwrap();
+    /// vault.store_key("cold-1", &[7u8; 32]).unwrap();
+    /// let shares = vault.split_master_key(2, 3).unwrap();
+    /// let escrowed: Vec<_> = shares.iter().map(|share| share.to_mnemonic()).collect();
+    ///
+    /// // Disaster recovery: the passphrase is lost, two custodians remain
+    /// let mut restored = KeyVault::from_json(&vault.to_json()).unwrap();
+    /// let shares = [
+    ///     KeyShare::from_mnemonic(&escrowed[0]).unwrap(),
+    ///     KeyShare::from_mnemonic(&escrowed[2]).unwrap(),
+    /// ];
+    /// restored.recover_master_key(&shares).unwrap();
+    /// assert_eq!(restored.with_key("cold-1", |key| key.len()), Ok(32));
+    /// ```
+    pub fn split_master_key(
+        &self,
+        threshold: u8,
+        count: u8,
+    ) -> Result<Vec<KeyShare>, CustodyError> {
+        if threshold < 2 || threshold > count {
+            return Err(invalid(&format!(
+                "cannot require {} of {} shares",
+                threshold, count
+            )));
+        }
+        let master = self.master()?;
+        let mut shares: Vec<KeyShare> = (1..=count)
+            .map(|index| KeyShare {
+                threshold,
+                index,
+                value: Zeroizing::new([0; KEY_BYTES]),
+            })
+            .collect();
+        // One random polynomial per key byte, with the byte as constant term
+        let mut coefficients = Zeroizing::new(vec![0u8; usize::from(threshold)]);
+        for (position, byte) in master.iter().enumerate() {
+            coefficients[0] = *byte;
+            getrandom::getrandom(&mut coefficients[1..])
+                .map_err(|err| CustodyError::KeyVaultFailed(err.to_string()))?;
+            for share in &mut shares {
+                share.value[position] = evaluate(&coefficients, share.index);
+            }
+        }
+        Ok(shares)
+    }
+
+    /// Rebuilds the master key from shares and unlocks the vault with it
+    ///
+    /// # Errors
+    /// [`CustodyError::InvalidKeyShare`] if there are fewer shares than
+    /// their threshold, they come from different splits, or the key they
+    /// rebuild does not open this vault
+    pub fn recover_master_key(&mut self, shares: &[KeyShare]) -> Result<(), CustodyError> {
+        let threshold = shares.first().map_or(2, KeyShare::threshold);
+        if shares.iter().any(|share| share.threshold != threshold) {
+            return Err(invalid("shares have different thresholds"));
+        }
+        if shares.len() < usize::from(threshold) {
+            return Err(invalid(&format!(
+                "{} of {} required shares given",
+                shares.len(),
+                threshold
+            )));
+        }
+        let shares = &shares[..usize::from(threshold)];
+        for (position, share) in shares.iter().enumerate() {
+            if shares[..position]
+                .iter()
+                .any(|other| other.index == share.index)
+            {
+                return Err(invalid(&format!("share {} given twice", share.index)));
+            }
+        }
+
+        // Lagrange interpolation at zero, byte by byte
+        let mut master = Zeroizing::new([0u8; KEY_BYTES]);
+        for share in shares {
+            let mut weight = 1;
+            for other in shares.iter().filter(|other| other.index != share.index) {
+                weight = multiply(weight, divide(other.index, other.index ^ share.index));
+            }
+            for (byte, y) in master.iter_mut().zip(share.value.iter()) {
+                *byte ^= multiply(*y, weight);
+            }
+        }
+        if !self.verifies(&master) {
+            return Err(invalid("shares do not rebuild this vault's master key"));
+        }
+        self.unlock_with(master);
+        Ok(())
+    }
+}
+
+/// Evaluates the polynomial with `coefficients`, constant term first, at
+/// `x`
+fn evaluate(coefficients: &[u8], x: u8) -> u8 {
+    coefficients
+        .iter()
+        .rev()
+        .fold(0, |acc, coefficient| multiply(acc, x) ^ coefficient)
+}
+
+/// Multiplies in GF(2^8) without data-dependent branches
+fn multiply(mut a: u8, mut b: u8) -> u8 {
+    let mut product = 0;
+    for _ in 0..8 {
+        product ^= a & (b & 1).wrapping_neg();
+        a = (a << 1) ^ (0x1b & (a >> 7).wrapping_neg());
+        b >>= 1;
+    }
+    product
+}
+
+/// Divides in GF(2^8); `b` is never zero since share numbers differ
+fn divide(a: u8, b: u8) -> u8 {
+    // b^254 is the inverse of b
+    let mut inverse = 1;
+    let mut power = b;
+    for bit in 0..8 {
+        if (254 >> bit) & 1 == 1 {
+            inverse = multiply(inverse, power);
+        }
+        power = multiply(power, power);
+    }
+    multiply(a, inverse)
+}
+
+fn invalid(reason: &str) -> CustodyError {
+    CustodyError::InvalidKeyShare(reason.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PASSPHRASE: &str = "correct horse battery";
+
+    fn vault() -> KeyVault {
+        let mut vault = KeyVault::create(PASSPHRASE).unwrap();
+        vault.store_key("w", b"secret key").unwrap();
+        vault
+    }
+
+    #[test]
+    fn test_field_arithmetic() {
+        assert_eq!(multiply(0x57, 0x83), 0xc1);
+        for b in 1..=255 {
+            assert_eq!(multiply(divide(1, b), b), 1);
+        }
+        assert_eq!(evaluate(&[7, 0, 1], 2), 7 ^ 4);
+    }
+
+    #[test]
+    fn test_any_threshold_of_shares_recovers() {
+        let vault = vault();
+        let shares = vault.split_master_key(3, 5).unwrap();
+        for picked in [[0, 1, 2], [4, 2, 0], [1, 3, 4]] {
+            let mut restored = KeyVault::from_json(&vault.to_json()).unwrap();
+            let subset: Vec<_> = picked.iter().map(|&i| shares[i].clone()).collect();
+            restored.recover_master_key(&subset).unwrap();
+            assert_eq!(
+                restored.with_key("w", |key| key.to_vec()),
+                Ok(b"secret key".to_vec())
+            );
+        }
+
+        let mut restored = KeyVault::from_json(&vault.to_json()).unwrap();
+        assert!(matches!(
+            restored.recover_master_key(&shares[..2]),
+            Err(CustodyError::InvalidKeyShare(_))
+        ));
+        let twice = [shares[0].clone(), shares[0].clone(), shares[1].clone()];
+        assert!(restored.recover_master_key(&twice).is_err());
+        // Shares of another vault's key rebuild a key that opens nothing here
+        let other = KeyVault::create(PASSPHRASE).unwrap();
+        let foreign = other.split_master_key(3, 3).unwrap();
+        assert_eq!(
+            restored.recover_master_key(&foreign),
+            Err(CustodyError::InvalidKeyShare(
+                "shares do not rebuild this vault's master key".to_string()
+            ))
+        );
+        assert!(restored.is_locked());
+    }
+
+    #[test]
+    fn test_share_encodings_round_trip() {
+        let mut vault = vault();
+        let share = vault.split_master_key(2, 2).unwrap().remove(1);
+        assert_eq!(KeyShare::from_hex(&share.to_hex()), Ok(share.clone()));
+        let phrase = share.to_mnemonic();
+        assert_eq!(phrase.split_whitespace().count(), 26);
+        assert!(phrase.starts_with("able able "));
+        assert_eq!(KeyShare::from_mnemonic(&phrase), Ok(share.clone()));
+        assert!(!format!("{:?}", share).contains(&hex::encode(share.value.as_ref())));
+
+        let mut corrupted = share.to_hex().to_string();
+        corrupted.replace_range(10..11, if &corrupted[10..11] == "0" { "1" } else { "0" });
+        assert_eq!(
+            KeyShare::from_hex(&corrupted),
+            Err(CustodyError::InvalidKeyShare(
+                "share checksum mismatch".to_string()
+            ))
+        );
+        assert!(KeyShare::from_mnemonic("able able zoo").is_err());
+
+        vault.lock();
+        assert_eq!(vault.split_master_key(2, 3), Err(CustodyError::VaultLocked));
+        vault.unlock(PASSPHRASE).unwrap();
+        assert!(vault.split_master_key(4, 3).is_err());
+        assert!(vault.split_master_key(1, 3).is_err());
+    }
+}
@@ -0,0 +1,564 @@
+//! Pluggable transaction signing.
+//!
+//! A withdrawal is split in two steps: [`CustodySystem::prepare_withdrawal`]
+//! checks it and returns an [`UnsignedTx`] whose [`payload`](UnsignedTx::payload)
+//! is handed to a [`Signer`], possibly on another machine, and
+//! [`CustodySystem::submit_signed`] books it once the signature comes back.
+//! A signature from the wallet's key stands in for the approval a cold
+//! wallet otherwise needs, so cold wallets can be spent from by signing
+//! offline.
+//!
+//! Signers are [`CallbackSigner`] for external signing services,
+//! `SoftwareSigner` for in-process secp256k1 keys (feature `signing`),
+//! `HsmSigner`, a stand-in for hardware modules (feature `hsm`),
+//! `HardwareWalletSigner` for Ledger and Trezor devices (feature
+//! `hw-wallet`), and `LocalCeremony` for threshold keys split among
+//! participants (feature `mpc`). As with air-gapped signing, the signature itself is
+//! checked by the chain integration that broadcasts the transaction.
+
+use crate::fees::FeeEstimate;
+use crate::nonces::uses_nonces;
+use crate::precheck::Authorization;
+use crate::time::Timestamp;
+use crate::{Amount, Asset, CustodyError, CustodySystem};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::Arc;
+
+/// A signature over an [`UnsignedTx`] payload
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Signature {
+    /// Key that produced the signature, as reported by the signer
+    pub key_id: String,
+    pub bytes: Vec<u8>,
+}
+
+/// Produces signatures over transaction payloads
+pub trait Signer: Send + Sync {
+    /// Identifies the signing key, e.g. a public key or HSM key label
+    fn key_id(&self) -> String;
+
+    /// Signs `payload`
+    fn sign(&self, payload: &[u8]) -> Result<Signature, CustodyError>;
+}
+
+/// A withdrawal waiting for its signature
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UnsignedTx {
+    /// Unique per request, so a signature cannot be replayed
+    pub id: u64,
+    pub wallet_id: String,
+    pub asset: Asset,
+    pub destination: String,
+    pub amount: Amount,
+    pub prepared_at: Timestamp,
+    /// Account nonce, for withdrawals from Ethereum wallets
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<u64>,
+    /// Network fee quoted when it was prepared
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fee: Option<FeeEstimate>,
+}
+
+impl UnsignedTx {
+    /// Canonical bytes to sign
+    pub fn payload(&self) -> Vec<u8> {
+        let mut payload = format!(
+            "securevault/unsigned-tx/v1\nid={}\nwallet={}\nasset={}\ndestination={}\namount={}\n",
+            self.id,
+            self.wallet_id,
+            self.asset.symbol(),
+            self.destination,
+            self.amount
+        );
+        if let Some(nonce) = self.nonce {
+            payload.push_str(&format!("nonce={}\n", nonce));
+        }
+        if let Some(fee) = &self.fee {
+            payload.push_str(&format!("fee_rate={}\n", fee.rate));
+        }
+        payload.into_bytes()
+    }
+}
+
+/// Signature callback of a [`CallbackSigner`]
+pub type SignCallback = dyn Fn(&[u8]) -> Result<Vec<u8>, String> + Send + Sync;
+
+/// Delegates signing to external code, e.g. a remote signing service
+#[derive(Clone)]
+pub struct CallbackSigner {
+    key_id: String,
+    callback: Arc<SignCallback>,
+}
+
+impl CallbackSigner {
+    /// Creates a signer that calls `callback` with each payload
+    pub fn new(
+        key_id: &str,
+        callback: impl Fn(&[u8]) -> Result<Vec<u8>, String> + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            key_id: key_id.to_string(),
+            callback: Arc::new(callback),
+        }
+    }
+}
+
+impl fmt::Debug for CallbackSigner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CallbackSigner")
+            .field("key_id", &self.key_id)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Signer for CallbackSigner {
+    fn key_id(&self) -> String {
+        self.key_id.clone()
+    }
+
+    fn sign(&self, payload: &[u8]) -> Result<Signature, CustodyError> {
+        let bytes = (self.callback)(payload).map_err(CustodyError::SigningFailed)?;
+        Ok(Signature {
+            key_id: self.key_id.clone(),
+            bytes,
+        })
+    }
+}
+
+#[cfg(feature = "signing")]
+mod software {
+    use super::{Signature, Signer};
+    use crate::CustodyError;
+    use k256::ecdsa::signature::{Signer as _, Verifier as _};
+    use k256::ecdsa::{Signature as EcdsaSignature, SigningKey, VerifyingKey};
+
+    /// In-process secp256k1 ECDSA key
+    ///
+    /// Signatures are 64-byte `r || s` over the SHA-256 of the payload. The
+    /// key is wiped from memory when the signer is dropped.
+    #[derive(Clone)]
+    pub struct SoftwareSigner {
+        key: SigningKey,
+    }
+
+    impl SoftwareSigner {
+        /// Creates a signer from a 32-byte secret key
+        pub fn from_bytes(secret: &[u8]) -> Result<Self, CustodyError> {
+            SigningKey::from_slice(secret)
+                .map(|key| Self { key })
+                .map_err(|_| CustodyError::SigningFailed("invalid secp256k1 secret key".into()))
+        }
+
+        /// Compressed SEC1 public key
+        pub fn public_key(&self) -> Vec<u8> {
+            self.key
+                .verifying_key()
+                .to_encoded_point(true)
+                .as_bytes()
+                .to_vec()
+        }
+
+        /// Returns true if `signature` is valid for `payload` under the
+        /// compressed SEC1 `public_key`
+        pub fn verify(public_key: &[u8], payload: &[u8], signature: &[u8]) -> bool {
+            let (Ok(key), Ok(signature)) = (
+                VerifyingKey::from_sec1_bytes(public_key),
+                EcdsaSignature::from_slice(signature),
+            ) else {
+                return false;
+            };
+            key.verify(payload, &signature).is_ok()
+        }
+    }
+
+    impl std::fmt::Debug for SoftwareSigner {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.debug_struct("SoftwareSigner")
+                .field("key_id", &self.key_id())
+                .finish_non_exhaustive()
+        }
+    }
+
+    impl Signer for SoftwareSigner {
+        fn key_id(&self) -> String {
+            self.public_key()
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect()
+        }
+
+        fn sign(&self, payload: &[u8]) -> Result<Signature, CustodyError> {
+            let signature: EcdsaSignature = self.key.sign(payload);
+            Ok(Signature {
+                key_id: self.key_id(),
+                bytes: signature.to_bytes().to_vec(),
+            })
+        }
+    }
+
+    #[cfg(feature = "keyvault")]
+    impl crate::CustodySystem {
+        /// Loads a wallet's signing key from `vault` into a software signer
+        ///
+        /// Cold wallets refuse while the vault is locked.
+        pub fn vault_signer(
+            &self,
+            vault: &crate::KeyVault,
+            wallet_id: &str,
+        ) -> Result<SoftwareSigner, CustodyError> {
+            self.with_signing_key(vault, wallet_id, SoftwareSigner::from_bytes)?
+        }
+    }
+}
+
+#[cfg(feature = "signing")]
+pub use software::SoftwareSigner;
+
+#[cfg(feature = "hsm")]
+mod hsm {
+    use super::{Signature, Signer};
+    use crate::CustodyError;
+    use sha2::{Digest, Sha256};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    /// Stand-in for a key held in a hardware security module
+    ///
+    /// No vendor module is linked yet: the "signature" is a SHA-256 over the
+    /// slot, key label and payload, which is enough to exercise signing
+    /// workflows and outages but offers no security. Clones share the
+    /// connection state.
+    #[derive(Debug, Clone)]
+    pub struct HsmSigner {
+        slot: u64,
+        label: String,
+        connected: Arc<AtomicBool>,
+    }
+
+    impl HsmSigner {
+        /// Addresses the key `label` in HSM slot `slot`
+        pub fn new(slot: u64, label: &str) -> Self {
+            Self {
+                slot,
+                label: label.to_string(),
+                connected: Arc::new(AtomicBool::new(true)),
+            }
+        }
+
+        /// Simulates the module becoming reachable or unreachable
+        pub fn set_connected(&self, connected: bool) {
+            self.connected.store(connected, Ordering::SeqCst);
+        }
+    }
+
+    impl Signer for HsmSigner {
+        fn key_id(&self) -> String {
+            format!("hsm:{}/{}", self.slot, self.label)
+        }
+
+        fn sign(&self, payload: &[u8]) -> Result<Signature, CustodyError> {
+            if !self.connected.load(Ordering::SeqCst) {
+                return Err(CustodyError::SigningFailed(format!(
+                    "HSM slot {} is unreachable",
+                    self.slot
+                )));
+            }
+            let digest = Sha256::new()
+                .chain_update(self.key_id().as_bytes())
+                .chain_update([0])
+                .chain_update(payload)
+                .finalize();
+            Ok(Signature {
+                key_id: self.key_id(),
+                bytes: digest.to_vec(),
+            })
+        }
+    }
+}
+
+#[cfg(feature = "hsm")]
+pub use hsm::HsmSigner;
+
+impl CustodySystem {
+    /// Checks a withdrawal and prepares it for signing
+    ///
+    /// Nothing is debited until the signature is submitted with
+    /// [`submit_signed`](Self::submit_signed).
+    ///
+    /// # Example
+    /// ```
+    /// use securevault::{amount, CallbackSigner, CustodySystem, Signer, WalletType};
+    /// let mut system = CustodySystem::new();
+    /// system.create_wallet("cold".to_string(), "0x1".to_string(), WalletType::Cold).unwrap();
+    /// system.deposit("cold", amount!(10)).unwrap();
+    ///
+    /// let tx = system.prepare_withdrawal("cold", "0xdest", amount!(4)).unwrap();
+    /// // ... carried to the offline signer and back ...
+    /// let signer = CallbackSigner::new("offline-1", |payload| Ok(payload.to_vec()));
+    /// let signature = signer.sign(&tx.payload()).unwrap();
+    /// system.submit_signed(&tx, &signature).unwrap();
+    /// assert_eq!(system.get_wallet("cold").unwrap().balance, amount!(6));
+    /// ```
+    pub fn prepare_withdrawal(
+        &mut self,
+        wallet_id: &str,
+        destination: &str,
+        amount: Amount,
+    ) -> Result<UnsignedTx, CustodyError> {
+        if let Some(reason) = self
+            .withdrawal_blockers(
+                wallet_id,
+                None,
+                amount,
+                Some(destination),
+                Authorization::Signed,
+            )
+            .into_iter()
+            .next()
+        {
+            return Err(reason);
+        }
+        let asset = self
+            .get_wallet(wallet_id)
+            .expect("checked by withdrawal_blockers")
+            .asset
+            .clone();
+        let fee = self.estimate_fee(&asset)?;
+        let id = self.allocate_operation_id();
+        let nonce = uses_nonces(&asset).then(|| self.assign_nonce(wallet_id, id));
+        let tx = UnsignedTx {
+            id,
+            wallet_id: wallet_id.to_string(),
+            asset,
+            destination: destination.to_string(),
+            amount,
+            prepared_at: self.current_timestamp(),
+            nonce,
+            fee,
+        };
+        self.unsigned_txs.insert(tx.id, tx.clone());
+        Ok(tx)
+    }
+
+    /// Books a prepared withdrawal once it is signed
+    ///
+    /// `tx` must be exactly what [`prepare_withdrawal`](Self::prepare_withdrawal)
+    /// returned and may only be submitted once. If the withdrawal is no
+    /// longer possible it stays pending and can be cancelled; if it is held
+    /// for compliance review, the review decides it.
+    pub fn submit_signed(
+        &mut self,
+        tx: &UnsignedTx,
+        signature: &Signature,
+    ) -> Result<(), CustodyError> {
+        match self.unsigned_txs.get(&tx.id) {
+            Some(pending) if pending == tx => {}
+            Some(_) => {
+                return Err(CustodyError::SignatureRejected(
+                    "transaction does not match the prepared one".to_string(),
+                ))
+            }
+            None => return Err(CustodyError::UnsignedTxNotFound(tx.id)),
+        }
+        if signature.bytes.is_empty() {
+            return Err(CustodyError::SignatureRejected(
+                "signature is empty".to_string(),
+            ));
+        }
+        #[cfg(feature = "hw-wallet")]
+        self.check_device_binding(&tx.wallet_id, signature)?;
+        let result = self.execute_withdrawal(
+            &tx.wallet_id,
+            Some(&tx.asset),
+            tx.amount,
+            Some(&tx.destination),
+            Authorization::Signed,
+        );
+        if matches!(result, Ok(()) | Err(CustodyError::FlaggedForReview(_))) {
+            self.unsigned_txs.remove(&tx.id);
+        }
+        result
+    }
+
+    /// Prepares, signs and submits a withdrawal in one step, for signers
+    /// that are online
+    pub fn withdraw_signed(
+        &mut self,
+        wallet_id: &str,
+        destination: &str,
+        amount: Amount,
+        signer: &dyn Signer,
+    ) -> Result<Signature, CustodyError> {
+        let tx = self.prepare_withdrawal(wallet_id, destination, amount)?;
+        let signed = signer
+            .sign(&tx.payload())
+            .and_then(|signature| self.submit_signed(&tx, &signature).map(|()| signature));
+        if signed.is_err() {
+            self.cancel_unsigned(tx.id);
+        }
+        signed
+    }
+
+    /// Withdrawals prepared but not yet submitted, oldest first
+    pub fn unsigned_transactions(&self) -> Vec<&UnsignedTx> {
+        self.unsigned_txs.values().collect()
+    }
+
+    /// Discards a prepared withdrawal, returning whether it was pending
+    ///
+    /// Its nonce, if any, is handed out again.
+    pub fn cancel_unsigned(&mut self, id: u64) -> bool {
+        let Some(tx) = self.unsigned_txs.remove(&id) else {
+            return false;
+        };
+        if let Some(nonce) = tx.nonce {
+            self.release_nonce(&tx.wallet_id, nonce);
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::WalletType;
+
+    fn system() -> CustodySystem {
+        let mut system = CustodySystem::new();
+        for (id, wallet_type) in [("hot", WalletType::Hot), ("cold", WalletType::Cold)] {
+            system
+                .create_wallet(id.to_string(), format!("0x{}", id), wallet_type)
+                .unwrap();
+            system.deposit(id, amount!(10)).unwrap();
+        }
+        system
+    }
+
+    fn echo() -> CallbackSigner {
+        CallbackSigner::new("echo", |payload| Ok(payload[..8].to_vec()))
+    }
+
+    #[test]
+    fn test_signed_withdrawal_from_cold_wallet() {
+        let mut system = system();
+        assert_eq!(
+            system.withdraw("cold", amount!(1)),
+            Err(CustodyError::ApprovalRequired("cold".to_string()))
+        );
+        let tx = system
+            .prepare_withdrawal("cold", "0xdest", amount!(4))
+            .unwrap();
+        assert_eq!(system.unsigned_transactions(), [&tx]);
+        assert_eq!(system.get_wallet("cold").unwrap().balance, amount!(10));
+
+        let signature = echo().sign(&tx.payload()).unwrap();
+        assert_eq!(signature.key_id, "echo");
+        system.submit_signed(&tx, &signature).unwrap();
+        assert_eq!(system.get_wallet("cold").unwrap().balance, amount!(6));
+        assert_eq!(
+            system.submit_signed(&tx, &signature),
+            Err(CustodyError::UnsignedTxNotFound(tx.id))
+        );
+    }
+
+    #[test]
+    fn test_submission_must_match_prepared_tx() {
+        let mut system = system();
+        let tx = system
+            .prepare_withdrawal("hot", "0xdest", amount!(4))
+            .unwrap();
+        let mut tampered = tx.clone();
+        tampered.amount = amount!(9);
+        let signature = echo().sign(&tampered.payload()).unwrap();
+        assert!(matches!(
+            system.submit_signed(&tampered, &signature),
+            Err(CustodyError::SignatureRejected(_))
+        ));
+        let empty = Signature {
+            key_id: "echo".to_string(),
+            bytes: Vec::new(),
+        };
+        assert!(matches!(
+            system.submit_signed(&tx, &empty),
+            Err(CustodyError::SignatureRejected(_))
+        ));
+        assert!(system.cancel_unsigned(tx.id));
+        assert!(system.unsigned_transactions().is_empty());
+        assert_eq!(
+            system.prepare_withdrawal("hot", "0xdest", amount!(11)),
+            Err(CustodyError::InsufficientBalance {
+                available: amount!(10),
+                requested: amount!(11),
+            })
+        );
+    }
+
+    #[test]
+    fn test_failing_signer_leaves_nothing_pending() {
+        let mut system = system();
+        let offline = CallbackSigner::new("remote", |_| Err("timeout".to_string()));
+        assert_eq!(
+            system.withdraw_signed("hot", "0xdest", amount!(1), &offline),
+            Err(CustodyError::SigningFailed("timeout".to_string()))
+        );
+        assert!(system.unsigned_transactions().is_empty());
+        system
+            .withdraw_signed("hot", "0xdest", amount!(1), &echo())
+            .unwrap();
+        assert_eq!(system.get_wallet("hot").unwrap().balance, amount!(9));
+    }
+
+    #[cfg(feature = "signing")]
+    #[test]
+    fn test_software_signatures_verify() {
+        let signer = SoftwareSigner::from_bytes(&[7; 32]).unwrap();
+        let signature = signer.sign(b"payload").unwrap();
+        assert_eq!(signature.bytes.len(), 64);
+        assert!(SoftwareSigner::verify(
+            &signer.public_key(),
+            b"payload",
+            &signature.bytes
+        ));
+        assert!(!SoftwareSigner::verify(
+            &signer.public_key(),
+            b"other",
+            &signature.bytes
+        ));
+        assert!(SoftwareSigner::from_bytes(&[0; 32]).is_err());
+    }
+
+    #[cfg(all(feature = "signing", feature = "keyvault"))]
+    #[test]
+    fn test_vault_signer_refuses_cold_wallet_while_locked() {
+        let mut system = system();
+        let mut vault = crate::KeyVault::create("correct horse battery").unwrap();
+        vault.store_key("cold", &[7; 32]).unwrap();
+        let signer = system.vault_signer(&vault, "cold").unwrap();
+        system
+            .withdraw_signed("cold", "0xdest", amount!(2), &signer)
+            .unwrap();
+
+        vault.lock();
+        assert!(matches!(
+            system.vault_signer(&vault, "cold"),
+            Err(CustodyError::VaultLocked)
+        ));
+    }
+
+    #[cfg(feature = "hsm")]
+    #[test]
+    fn test_hsm_outage_fails_signing() {
+        let mut system = system();
+        let hsm = HsmSigner::new(1, "cold-key");
+        let signature = system
+            .withdraw_signed("cold", "0xdest", amount!(1), &hsm)
+            .unwrap();
+        assert_eq!(signature.key_id, "hsm:1/cold-key");
+        hsm.set_connected(false);
+        assert!(matches!(
+            system.withdraw_signed("cold", "0xdest", amount!(1), &hsm),
+            Err(CustodyError::SigningFailed(_))
+        ));
+    }
+}
@@ -0,0 +1,247 @@
+//! Accountant-friendly wallet statements.
+//!
+//! Unlike the raw transaction export, a statement covers one calendar
+//! period and reads like a bank statement: an opening balance, dated
+//! entries each followed by the running balance, and a closing balance.
+
+use crate::format::{AmountFormatter, SymbolPosition};
+use crate::i18n::{Label, Locale};
+use crate::time::NaiveDate;
+use crate::{Amount, CustodyError, CustodySystem, TransactionType};
+use std::io::{self, Write};
+
+/// Writes the monthly CSV statement for `wallet_id` to `writer`
+///
+/// Dates are UTC calendar dates. Amounts are signed (credits positive,
+/// debits negative) and written with the asset's full precision and no
+/// thousands separators so spreadsheets parse them as numbers.
+///
+/// # Errors
+/// Returns an [`io::ErrorKind::InvalidInput`] error wrapping a
+/// [`CustodyError`] if the wallet does not exist, an
+/// [`io::ErrorKind::InvalidInput`] error if `month` is not 1–12, and any
+/// error raised by `writer`.
+///
+/// # Example
+/// ```
+/// use securevault::{statements, CustodySystem, WalletType};
+/// let mut system = CustodySystem::new();
+/// system.create_wallet("w".to_string(), "0x1".to_string(), WalletType::Hot).unwrap();
+///
+/// let mut csv = Vec::new();
+/// statements::monthly_csv(&system, "w", 2024, 1, &mut csv).unwrap();
+/// assert!(String::from_utf8(csv).unwrap().starts_with("Date,Description,Amount,Balance"));
+/// ```
+pub fn monthly_csv<W: Write>(
+    system: &CustodySystem,
+    wallet_id: &str,
+    year: i32,
+    month: u32,
+    mut writer: W,
+) -> io::Result<()> {
+    let wallet = system.get_wallet(wallet_id).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            CustodyError::WalletNotFound(wallet_id.to_string()),
+        )
+    })?;
+    let invalid_month = || {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid statement month {}-{:02}", year, month),
+        )
+    };
+    let first = NaiveDate::from_ymd_opt(year, month, 1).ok_or_else(invalid_month)?;
+    let next = if month == 12 {
+        NaiveDate::from_ymd_opt(year + 1, 1, 1)
+    } else {
+        NaiveDate::from_ymd_opt(year, month + 1, 1)
+    }
+    .ok_or_else(invalid_month)?;
+    let last = next.pred_opt().unwrap_or(first);
+
+    let locale = Locale::En;
+    let formatter = AmountFormatter {
+        thousands_separator: None,
+        symbol_position: SymbolPosition::Hidden,
+        ..AmountFormatter::for_locale(locale)
+    };
+    let amount = |value: Amount| formatter.format(value, &wallet.asset);
+
+    writeln!(
+        writer,
+        "{},{},{},{}",
+        Label::Date.text(locale),
+        Label::Description.text(locale),
+        Label::Amount.text(locale),
+        Label::Balance.text(locale)
+    )?;
+
+    let mut balance = Amount::ZERO;
+    let mut opened = false;
+    for tx in system
+        .get_wallet_transactions(wallet_id)
+        .into_iter()
+        .filter(|tx| tx.asset == wallet.asset)
+    {
+        let date = tx.timestamp.datetime().date_naive();
+        if date >= next {
+            break;
+        }
+        let signed = tx.balance_change(wallet_id);
+        if date < first {
+            balance += signed;
+            continue;
+        }
+        if !opened {
+            write_balance_row(
+                &mut writer,
+                first,
+                Label::OpeningBalance,
+                locale,
+                &amount(balance),
+            )?;
+            opened = true;
+        }
+        balance += signed;
+
+        let label = match tx.transaction_type {
+            TransactionType::Deposit => Label::Deposit,
+            TransactionType::Withdrawal => Label::Withdrawal,
+            TransactionType::ConversionOut | TransactionType::ConversionIn => Label::Conversion,
+            TransactionType::Transfer => Label::Transfer,
+            TransactionType::Reward => Label::Reward,
+        };
+        // A transfer names the other wallet from this wallet's side
+        let counterparty = if tx.wallet_id == wallet_id {
+            tx.counterparty.as_ref()
+        } else {
+            Some(&tx.wallet_id)
+        };
+        let description = match counterparty {
+            Some(counterparty) => format!("{} ({})", label.text(locale), counterparty),
+            None => label.text(locale).to_string(),
+        };
+        writeln!(
+            writer,
+            "{},{},{},{}",
+            date.format("%Y-%m-%d"),
+            csv_field(&description),
+            amount(signed),
+            amount(balance)
+        )?;
+    }
+    if !opened {
+        write_balance_row(
+            &mut writer,
+            first,
+            Label::OpeningBalance,
+            locale,
+            &amount(balance),
+        )?;
+    }
+    write_balance_row(
+        &mut writer,
+        last,
+        Label::ClosingBalance,
+        locale,
+        &amount(balance),
+    )?;
+    writer.flush()
+}
+
+fn write_balance_row<W: Write>(
+    writer: &mut W,
+    date: NaiveDate,
+    label: Label,
+    locale: Locale,
+    balance: &str,
+) -> io::Result<()> {
+    writeln!(
+        writer,
+        "{},{},,{}",
+        date.format("%Y-%m-%d"),
+        csv_field(label.text(locale)),
+        balance
+    )
+}
+
+/// Quotes a CSV field if it contains a separator, quote, or line break
+pub(crate) fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::time::Timestamp;
+    use crate::{Transaction, WalletType};
+
+    fn at(date: &str) -> Timestamp {
+        let date = NaiveDate::parse_from_str(date, "%Y-%m-%d").unwrap();
+        Timestamp::from_unix(date.and_hms_opt(12, 0, 0).unwrap().and_utc().timestamp() as u64)
+    }
+
+    fn system() -> CustodySystem {
+        let mut system = CustodySystem::new();
+        system
+            .create_wallet("w".to_string(), "0x1".to_string(), WalletType::Hot)
+            .unwrap();
+        for (date, kind, amount) in [
+            ("2024-01-20", TransactionType::Deposit, amount!(10.0)),
+            ("2024-02-01", TransactionType::Withdrawal, amount!(2.5)),
+            ("2024-02-29", TransactionType::Deposit, amount!(1.0)),
+            ("2024-03-01", TransactionType::Deposit, amount!(5.0)),
+        ] {
+            let tx = Transaction::new("w", kind, amount, crate::Asset::Btc, at(date));
+            system.record_transaction(tx).unwrap();
+        }
+        system
+    }
+
+    fn statement(system: &CustodySystem, year: i32, month: u32) -> String {
+        let mut out = Vec::new();
+        monthly_csv(system, "w", year, month, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_monthly_statement_running_balance() {
+        assert_eq!(
+            statement(&system(), 2024, 2),
+            "Date,Description,Amount,Balance\n\
+             2024-02-01,Opening balance,,10.00000000\n\
+             2024-02-01,Withdrawal,-2.50000000,7.50000000\n\
+             2024-02-29,Deposit,1.00000000,8.50000000\n\
+             2024-02-29,Closing balance,,8.50000000\n"
+        );
+    }
+
+    #[test]
+    fn test_statement_for_quiet_month() {
+        let csv = statement(&system(), 2023, 12);
+        assert!(csv.contains("2023-12-01,Opening balance,,0.00000000\n"));
+        assert!(csv.ends_with("2023-12-31,Closing balance,,0.00000000\n"));
+    }
+
+    #[test]
+    fn test_statement_errors() {
+        let system = system();
+        let err = monthly_csv(&system, "missing", 2024, 1, Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(err.to_string(), "Wallet 'missing' not found");
+        let err = monthly_csv(&system, "w", 2024, 13, Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_csv_field_quoting() {
+        assert_eq!(csv_field("plain"), "plain");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+    }
+}
@@ -0,0 +1,323 @@
+//! Wallet status lifecycle.
+//!
+//! Every wallet starts [`Active`](WalletStatus::Active). Operators can
+//! freeze it, which stops all debits until it is unfrozen; credits to a
+//! frozen wallet are refused too unless
+//! [`CustodySystem::set_frozen_deposits_allowed`] says otherwise. Closing a
+//! wallet refuses further credits; a wallet that still holds funds is
+//! [`Closing`](WalletStatus::Closing) and may only be drained, and once
+//! empty it can be [`Closed`](WalletStatus::Closed) for good.
+//!
+//! ```text
+//! Active <-> Frozen
+//! Active  -> Closing -> Closed
+//! Active  ------------> Closed   (empty wallet)
+//! ```
+//!
+//! Every status change is kept in [`CustodySystem::status_log`].
+
+use crate::time::Timestamp;
+use crate::{AdminAction, CustodyError, CustodyEvent, CustodySystem, Wallet};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Where a wallet is in its lifecycle
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum WalletStatus {
+    /// Accepts credits and debits
+    #[default]
+    Active,
+    /// Refuses debits, and credits unless frozen deposits are allowed
+    Frozen,
+    /// Being wound down: refuses credits, allows debits
+    Closing,
+    /// Refuses everything; final
+    Closed,
+}
+
+impl WalletStatus {
+    /// Returns a stable snake_case name for the status
+    pub fn name(&self) -> &'static str {
+        match self {
+            WalletStatus::Active => "active",
+            WalletStatus::Frozen => "frozen",
+            WalletStatus::Closing => "closing",
+            WalletStatus::Closed => "closed",
+        }
+    }
+}
+
+impl fmt::Display for WalletStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// An entry in the status audit log
+#[derive(Debug, Clone, PartialEq)]
+pub struct StatusChange {
+    pub wallet_id: String,
+    pub from: WalletStatus,
+    pub to: WalletStatus,
+    pub reason: String,
+    pub timestamp: Timestamp,
+}
+
+impl CustodySystem {
+    /// Freezes an active wallet, stopping its withdrawals and transfers out
+    ///
+    /// # Example
+    /// ```
+    /// use securevault::{amount, CustodyError, CustodySystem, WalletStatus, WalletType};
+    /// let mut system = CustodySystem::new();
+    /// system.create_wallet("w".to_string(), "0x1".to_string(), WalletType::Hot).unwrap();
+    /// system.deposit("w", amount!(5)).unwrap();
+    ///
+    /// system.freeze_wallet("w", "court order").unwrap();
+    /// assert_eq!(
+    ///     system.withdraw("w", amount!(1)),
+    ///     Err(CustodyError::WalletFrozen("w".to_string()))
+    /// );
+    /// system.unfreeze_wallet("w", "order lifted").unwrap();
+    /// system.withdraw("w", amount!(1)).unwrap();
+    /// assert_eq!(system.status_log().len(), 2);
+    /// ```
+    pub fn freeze_wallet(&mut self, wallet_id: &str, reason: &str) -> Result<(), CustodyError> {
+        self.require_second_operator(AdminAction::FREEZE_WALLET)?;
+        self.freeze(wallet_id, reason)
+    }
+
+    pub(crate) fn freeze(&mut self, wallet_id: &str, reason: &str) -> Result<(), CustodyError> {
+        self.change_status(wallet_id, WalletStatus::Frozen, reason, |from| {
+            from == WalletStatus::Active
+        })?;
+        self.emit(CustodyEvent::WalletFrozen {
+            wallet_id: wallet_id.to_string(),
+            reason: reason.to_string(),
+        });
+        Ok(())
+    }
+
+    /// Returns a frozen wallet to active
+    pub fn unfreeze_wallet(&mut self, wallet_id: &str, reason: &str) -> Result<(), CustodyError> {
+        self.change_status(wallet_id, WalletStatus::Active, reason, |from| {
+            from == WalletStatus::Frozen
+        })
+    }
+
+    /// Closes a wallet to new funds
+    ///
+    /// An empty wallet is closed outright. One that still holds funds in
+    /// any asset moves to closing; call again once it has been drained to
+    /// close it. Frozen wallets must be unfrozen first.
+    ///
+    /// # Returns
+    /// The wallet's status after the call
+    pub fn close_wallet(
+        &mut self,
+        wallet_id: &str,
+        reason: &str,
+    ) -> Result<WalletStatus, CustodyError> {
+        self.require_second_operator(AdminAction::CLOSE_WALLET)?;
+        self.close(wallet_id, reason)
+    }
+
+    pub(crate) fn close(
+        &mut self,
+        wallet_id: &str,
+        reason: &str,
+    ) -> Result<WalletStatus, CustodyError> {
+        let wallet = self
+            .wallets
+            .get(wallet_id)
+            .ok_or_else(|| CustodyError::WalletNotFound(wallet_id.to_string()))?;
+        let empty = wallet.balances().all(|(_, balance)| balance.is_zero());
+        let target = if empty {
+            WalletStatus::Closed
+        } else {
+            WalletStatus::Closing
+        };
+        if wallet.status == WalletStatus::Closing && target == WalletStatus::Closing {
+            return Ok(WalletStatus::Closing);
+        }
+        self.change_status(wallet_id, target, reason, |from| {
+            matches!(from, WalletStatus::Active | WalletStatus::Closing)
+        })?;
+        Ok(target)
+    }
+
+    /// Sets whether frozen wallets still accept deposits (default: no)
+    pub fn set_frozen_deposits_allowed(&mut self, allowed: bool) {
+        self.frozen_deposits_allowed = allowed;
+    }
+
+    /// Returns every status change, oldest first
+    pub fn status_log(&self) -> &im::Vector<StatusChange> {
+        &self.status_log
+    }
+
+    /// Checks that the wallet's status and the open period let it be
+    /// credited
+    pub(crate) fn credit_blockers(&self, wallet: &Wallet) -> Vec<CustodyError> {
+        let mut reasons = match wallet.status {
+            WalletStatus::Active => Vec::new(),
+            WalletStatus::Frozen if self.frozen_deposits_allowed => Vec::new(),
+            WalletStatus::Frozen => vec![CustodyError::WalletFrozen(wallet.id.clone())],
+            WalletStatus::Closing | WalletStatus::Closed => {
+                vec![CustodyError::WalletClosed(wallet.id.clone())]
+            }
+        };
+        reasons.extend(self.period_blockers());
+        reasons
+    }
+
+    /// Checks that the wallet's status and the open period let it be
+    /// debited
+    pub(crate) fn debit_blockers(&self, wallet: &Wallet) -> Vec<CustodyError> {
+        let mut reasons = match wallet.status {
+            WalletStatus::Active | WalletStatus::Closing => Vec::new(),
+            WalletStatus::Frozen => vec![CustodyError::WalletFrozen(wallet.id.clone())],
+            WalletStatus::Closed => vec![CustodyError::WalletClosed(wallet.id.clone())],
+        };
+        reasons.extend(self.period_blockers());
+        reasons
+    }
+
+    fn change_status(
+        &mut self,
+        wallet_id: &str,
+        to: WalletStatus,
+        reason: &str,
+        allowed_from: impl Fn(WalletStatus) -> bool,
+    ) -> Result<(), CustodyError> {
+        let mut wallet = self
+            .wallets
+            .get(wallet_id)
+            .cloned()
+            .ok_or_else(|| CustodyError::WalletNotFound(wallet_id.to_string()))?;
+        let from = wallet.status;
+        if !allowed_from(from) {
+            return Err(CustodyError::InvalidStatusTransition {
+                wallet_id: wallet_id.to_string(),
+                from,
+                to,
+            });
+        }
+        wallet.status = to;
+        self.commit_wallet(wallet)?;
+        self.status_log.push_back(StatusChange {
+            wallet_id: wallet_id.to_string(),
+            from,
+            to,
+            reason: reason.to_string(),
+            timestamp: self.current_timestamp(),
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Asset, WalletType};
+
+    fn system() -> CustodySystem {
+        let mut system = CustodySystem::new();
+        for id in ["a", "b"] {
+            system
+                .create_wallet(id.to_string(), format!("0x{}", id), WalletType::Hot)
+                .unwrap();
+        }
+        system.deposit("a", amount!(10)).unwrap();
+        system
+    }
+
+    #[test]
+    fn test_frozen_wallet_refuses_debits() {
+        let mut system = system();
+        system.freeze_wallet("a", "investigation").unwrap();
+        let frozen = Err(CustodyError::WalletFrozen("a".to_string()));
+        assert_eq!(system.withdraw("a", amount!(1)), frozen);
+        assert_eq!(system.transfer("a", "b", amount!(1)), frozen);
+        assert!(!system.can_withdraw("a", amount!(1), None).is_allowed());
+        // Nor can it receive
+        assert_eq!(system.deposit("a", amount!(1)), frozen);
+        system.deposit("b", amount!(1)).unwrap();
+        assert_eq!(
+            system.transfer("b", "a", amount!(1)),
+            Err(CustodyError::WalletFrozen("a".to_string()))
+        );
+
+        system.set_frozen_deposits_allowed(true);
+        system.deposit("a", amount!(1)).unwrap();
+        system.transfer("b", "a", amount!(1)).unwrap();
+        assert_eq!(system.get_wallet("a").unwrap().balance, amount!(12));
+
+        assert_eq!(
+            system.freeze_wallet("a", "again"),
+            Err(CustodyError::InvalidStatusTransition {
+                wallet_id: "a".to_string(),
+                from: WalletStatus::Frozen,
+                to: WalletStatus::Frozen,
+            })
+        );
+        system.unfreeze_wallet("a", "cleared").unwrap();
+        system.withdraw("a", amount!(1)).unwrap();
+    }
+
+    #[test]
+    fn test_close_drains_then_closes() {
+        let mut system = system();
+        system.deposit_asset("a", &Asset::Eth, amount!(1)).unwrap();
+        assert_eq!(
+            system.close_wallet("a", "customer left").unwrap(),
+            WalletStatus::Closing
+        );
+        assert_eq!(
+            system.deposit("a", amount!(1)),
+            Err(CustodyError::WalletClosed("a".to_string()))
+        );
+        system.transfer("a", "b", amount!(10)).unwrap();
+        // Still holds ETH
+        assert_eq!(
+            system.close_wallet("a", "customer left").unwrap(),
+            WalletStatus::Closing
+        );
+        system.withdraw_asset("a", &Asset::Eth, amount!(1)).unwrap();
+        assert_eq!(
+            system.close_wallet("a", "drained").unwrap(),
+            WalletStatus::Closed
+        );
+        assert_eq!(system.get_wallet("a").unwrap().status, WalletStatus::Closed);
+        assert!(system.unfreeze_wallet("a", "oops").is_err());
+        assert!(system.close_wallet("a", "again").is_err());
+    }
+
+    #[test]
+    fn test_status_changes_are_logged_and_persisted() {
+        let mut system = system();
+        system.freeze_wallet("a", "aml review").unwrap();
+        system.unfreeze_wallet("a", "review passed").unwrap();
+        system.close_wallet("b", "unused").unwrap();
+
+        let log: Vec<_> = system
+            .status_log()
+            .iter()
+            .map(|change| (change.wallet_id.as_str(), change.from, change.to))
+            .collect();
+        assert_eq!(
+            log,
+            vec![
+                ("a", WalletStatus::Active, WalletStatus::Frozen),
+                ("a", WalletStatus::Frozen, WalletStatus::Active),
+                ("b", WalletStatus::Active, WalletStatus::Closed),
+            ]
+        );
+        assert_eq!(system.status_log()[0].reason, "aml review");
+        assert_eq!(system.snapshot().wallets[1].status, WalletStatus::Closed);
+        assert_eq!(
+            system.freeze_wallet("ghost", "x"),
+            Err(CustodyError::WalletNotFound("ghost".to_string()))
+        );
+    }
+}
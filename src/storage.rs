@@ -0,0 +1,1112 @@
+//! Persistent storage backends.
+//!
+//! A [`Storage`] keeps a durable copy of the ledger: every wallet and the
+//! full transaction log. [`CustodySystem::with_storage`] restores the ledger
+//! from a backend and then writes every mutation through to it, so wallets
+//! and transactions survive a restart.
+//!
+//! Writes ride on the change data capture stream: the wallet upserts,
+//! transaction appends, and transaction updates of an operation are written
+//! to the backend before they are applied to the in-memory ledger. If the
+//! write fails the operation fails with [`CustodyError::StorageFailed`] and
+//! the ledger is left as it was; the failure is also kept in
+//! [`CustodySystem::storage_error`] until a write succeeds.
+//!
+//! The same [`Snapshot`] doubles as a point-in-time backup: write it out
+//! with [`Snapshot::to_json`] and load it back with
+//! [`CustodySystem::restore`], which checks the audit chain first.
+//!
+//! Only the ledger is persisted. Pending joint operations, queued
+//! withdrawals, templates, and other configuration are not.
+//!
+//! Three backends ship with the crate: [`JsonFileStorage`], a single JSON
+//! document rewritten atomically on each change, [`WalStorage`], a
+//! checkpoint plus an fsynced write-ahead log, and `SqliteStorage`
+//! (feature `sqlite`).
+
+use crate::cdc::Change;
+use crate::index::TransactionIndex;
+#[cfg(doc)]
+use crate::WalStorage;
+use crate::{
+    Amount, Asset, CustodyError, CustodySystem, Transaction, TransactionLog, Wallet, WalletMap,
+};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::io::{self, Write as _};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// Format version written by [`Snapshot::to_json`]
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+/// The persisted state of the ledger
+///
+/// Also serves as a point-in-time backup: take one with
+/// [`CustodySystem::snapshot`], write it out with [`to_json`](Self::to_json)
+/// and bring it back with [`CustodySystem::restore`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    /// Format version; documents written before versioning read as 1
+    #[serde(default = "first_version")]
+    pub version: u32,
+    /// Wallets ordered by id, archived ones included
+    pub wallets: Vec<Wallet>,
+    /// The transaction log in recording order
+    pub transactions: Vec<Transaction>,
+}
+
+impl Default for Snapshot {
+    fn default() -> Self {
+        Self {
+            version: SNAPSHOT_VERSION,
+            wallets: Vec::new(),
+            transactions: Vec::new(),
+        }
+    }
+}
+
+impl Snapshot {
+    /// Serializes the snapshot as a JSON document
+    pub fn to_json(&self) -> Result<String, CustodyError> {
+        serde_json::to_string_pretty(self)
+            .map_err(|err| CustodyError::InvalidSnapshot(err.to_string()))
+    }
+
+    /// Parses a document written by [`to_json`](Self::to_json)
+    ///
+    /// Fails with [`CustodyError::UnsupportedSnapshotVersion`] for a
+    /// document written by a newer format.
+    pub fn from_json(json: &str) -> Result<Self, CustodyError> {
+        let snapshot: Snapshot = serde_json::from_str(json)
+            .map_err(|err| CustodyError::InvalidSnapshot(err.to_string()))?;
+        snapshot.check_version()?;
+        Ok(snapshot)
+    }
+
+    fn check_version(&self) -> Result<(), CustodyError> {
+        if self.version == 0 || self.version > SNAPSHOT_VERSION {
+            return Err(CustodyError::UnsupportedSnapshotVersion(self.version));
+        }
+        Ok(())
+    }
+}
+
+/// Checks every wallet balance against a replay of the transaction log
+fn check_balances(wallets: &[Wallet], transactions: &TransactionLog) -> Result<(), CustodyError> {
+    let mismatch = |wallet_id: &str, asset: &Asset, stored: Amount, replayed: Amount| {
+        CustodyError::InvalidSnapshot(format!(
+            "wallet '{}': stored {} balance {} but transaction log gives {}",
+            wallet_id, asset, stored, replayed
+        ))
+    };
+    let mut replayed = CustodySystem::replay_balances(transactions);
+    for wallet in wallets {
+        for (asset, stored) in wallet.balances() {
+            let from_log = replayed
+                .remove(&(wallet.id.clone(), asset.clone()))
+                .unwrap_or_default();
+            if stored != from_log {
+                return Err(mismatch(&wallet.id, asset, stored, from_log));
+            }
+        }
+    }
+    // Whatever the log credits to no stored wallet must have netted out
+    match replayed.into_iter().find(|(_, balance)| !balance.is_zero()) {
+        Some(((wallet_id, asset), balance)) => {
+            Err(mismatch(&wallet_id, &asset, Amount::ZERO, balance))
+        }
+        None => Ok(()),
+    }
+}
+
+fn first_version() -> u32 {
+    1
+}
+
+/// A durable backend for the ledger
+///
+/// Implementations must apply each call completely or not at all.
+pub trait Storage: Send + Sync {
+    /// Reads the stored ledger, or `None` if nothing has been saved yet
+    fn load(&self) -> Result<Option<Snapshot>, CustodyError>;
+
+    /// Replaces everything stored with `snapshot`
+    fn save(&self, snapshot: &Snapshot) -> Result<(), CustodyError>;
+
+    /// Appends a transaction to the end of the stored log
+    fn append_transaction(&self, transaction: &Transaction) -> Result<(), CustodyError>;
+
+    /// Replaces the transaction at `index` in the stored log
+    fn update_transaction(
+        &self,
+        index: usize,
+        transaction: &Transaction,
+    ) -> Result<(), CustodyError>;
+
+    /// Inserts a wallet or replaces the stored wallet with the same id
+    fn put_wallet(&self, wallet: &Wallet) -> Result<(), CustodyError>;
+
+    /// Writes the changes made by one custody operation, in order
+    ///
+    /// The default applies them one by one. Backends that can make several
+    /// writes durable at once should override it, so a crash never keeps
+    /// a transaction without the balances it explains.
+    fn write_changes(&self, changes: &[Change]) -> Result<(), CustodyError> {
+        for change in changes {
+            match change {
+                Change::WalletUpsert { wallet } => self.put_wallet(wallet)?,
+                Change::TransactionAppend { transaction, .. } => {
+                    self.append_transaction(transaction)?
+                }
+                Change::TransactionUpdate { index, transaction } => {
+                    self.update_transaction(*index, transaction)?
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads up to `limit` transactions of the stored log starting at
+    /// position `offset`
+    ///
+    /// The default loads the whole ledger; backends that can page through
+    /// the log should override it.
+    fn read_transactions(
+        &self,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<Transaction>, CustodyError> {
+        let transactions = self.load()?.map(|snapshot| snapshot.transactions);
+        Ok(transactions
+            .unwrap_or_default()
+            .into_iter()
+            .skip(offset)
+            .take(limit)
+            .collect())
+    }
+}
+
+/// Reads a backend's transaction log in fixed-size chunks, oldest first
+///
+/// Created by [`CustodySystem::transaction_chunks`]. Each chunk is read
+/// from the backend when the iterator reaches it, so at most one chunk is
+/// held in memory; a failed read ends the iteration after its error.
+pub struct TransactionChunks {
+    backend: Arc<dyn Storage>,
+    offset: usize,
+    chunk_size: usize,
+    done: bool,
+}
+
+impl TransactionChunks {
+    /// Reads `backend`'s log `chunk_size` transactions at a time
+    ///
+    /// # Panics
+    /// If `chunk_size` is zero
+    pub fn new(backend: Arc<dyn Storage>, chunk_size: usize) -> Self {
+        assert!(chunk_size > 0, "chunk size must be positive");
+        Self {
+            backend,
+            offset: 0,
+            chunk_size,
+            done: false,
+        }
+    }
+}
+
+impl fmt::Debug for TransactionChunks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TransactionChunks")
+            .field("offset", &self.offset)
+            .field("chunk_size", &self.chunk_size)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Iterator for TransactionChunks {
+    type Item = Result<Vec<Transaction>, CustodyError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let chunk = self.backend.read_transactions(self.offset, self.chunk_size);
+        match &chunk {
+            Ok(transactions) if transactions.is_empty() => return None,
+            Ok(transactions) => {
+                self.offset += transactions.len();
+                self.done = transactions.len() < self.chunk_size;
+            }
+            Err(_) => self.done = true,
+        }
+        Some(chunk)
+    }
+}
+
+/// The backend attached to a custody system and its write status
+#[derive(Clone, Default)]
+pub(crate) struct Attached {
+    backend: Option<Arc<dyn Storage>>,
+    error: Option<CustodyError>,
+}
+
+impl fmt::Debug for Attached {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.backend, &self.error) {
+            (None, _) => write!(f, "Attached(none)"),
+            (Some(_), None) => write!(f, "Attached(ok)"),
+            (Some(_), Some(err)) => write!(f, "Attached(failed: {})", err),
+        }
+    }
+}
+
+impl CustodySystem {
+    /// Creates a custody system backed by `storage`
+    ///
+    /// The ledger is restored from the backend if it holds one; otherwise
+    /// the empty ledger is saved to it. From then on every operation writes
+    /// its changes through before applying them, and fails with
+    /// [`CustodyError::StorageFailed`] if it cannot.
+    ///
+    /// # Example
+    /// ```
+    /// use securevault::{amount, CustodySystem, JsonFileStorage, WalletType};
+    /// use std::sync::Arc;
+    ///
+    /// let path = std::env::temp_dir().join(format!("securevault-doc-{}.json", std::process::id()));
+    /// let mut system = CustodySystem::with_storage(Arc::new(JsonFileStorage::new(&path))).unwrap();
+    /// system.create_wallet("w".to_string(), "0x1".to_string(), WalletType::Hot).unwrap();
+    /// system.deposit("w", amount!(2.5)).unwrap();
+    ///
+    /// let restored = CustodySystem::with_storage(Arc::new(JsonFileStorage::new(&path))).unwrap();
+    /// assert_eq!(restored.get_wallet("w").unwrap().balance, amount!(2.5));
+    /// # std::fs::remove_file(&path).unwrap();
+    /// ```
+    pub fn with_storage(storage: Arc<dyn Storage>) -> Result<Self, CustodyError> {
+        let mut system = CustodySystem::new();
+        match storage.load()? {
+            Some(snapshot) => system.restore(snapshot)?,
+            None => storage.save(&system.snapshot())?,
+        }
+        system.storage = Attached {
+            backend: Some(storage),
+            error: None,
+        };
+        Ok(system)
+    }
+
+    /// Returns the ledger as it would be persisted
+    pub fn snapshot(&self) -> Snapshot {
+        let mut wallets: Vec<Wallet> = self
+            .wallets
+            .values()
+            .chain(self.archived_wallets.values())
+            .cloned()
+            .collect();
+        wallets.sort_by(|a, b| a.id.cmp(&b.id));
+        Snapshot {
+            version: SNAPSHOT_VERSION,
+            wallets,
+            transactions: self.transactions.iter().cloned().collect(),
+        }
+    }
+
+    /// Returns the failure of the most recent write, if it failed
+    pub fn storage_error(&self) -> Option<&CustodyError> {
+        self.storage.error.as_ref()
+    }
+
+    /// Saves the whole ledger to the backend
+    ///
+    /// Use to repair a backend after [`storage_error`](Self::storage_error)
+    /// reports a failure. Does nothing if no backend is attached.
+    pub fn sync_storage(&mut self) -> Result<(), CustodyError> {
+        let Some(backend) = self.storage.backend.clone() else {
+            return Ok(());
+        };
+        backend.save(&self.snapshot())?;
+        self.storage.error = None;
+        Ok(())
+    }
+
+    /// Reads the attached backend's transaction log in chunks of
+    /// `chunk_size`, or returns `None` if no backend is attached
+    ///
+    /// Lets exports and analytics walk a large stored log without holding
+    /// all of it at once.
+    ///
+    /// # Panics
+    /// If `chunk_size` is zero
+    pub fn transaction_chunks(&self, chunk_size: usize) -> Option<TransactionChunks> {
+        let backend = self.storage.backend.clone()?;
+        Some(TransactionChunks::new(backend, chunk_size))
+    }
+
+    /// Stops persisting, e.g. for a fork used for what-if analysis
+    pub(crate) fn detach_storage(&mut self) {
+        self.storage = Attached::default();
+    }
+
+    /// Writes the changes of one operation through to the backend
+    pub(crate) fn persist(&mut self, changes: &[Change]) -> Result<(), CustodyError> {
+        let Some(backend) = &self.storage.backend else {
+            return Ok(());
+        };
+        let result = backend.write_changes(changes);
+        self.storage.error = result.clone().err();
+        result
+    }
+
+    /// Replaces the ledger with a snapshot
+    ///
+    /// The snapshot's version, audit chain and balances are checked first;
+    /// balances must match a replay of the snapshot's transaction log. If
+    /// any check fails the ledger is left as it was. Configuration such as policies,
+    /// approvers and listeners is kept. With a backend attached the restored
+    /// ledger is saved to it first; if that fails the ledger is also left as
+    /// it was.
+    ///
+    /// # Example
+    /// ```
+    /// use securevault::{amount, CustodySystem, Snapshot, WalletType};
+    /// let mut system = CustodySystem::new();
+    /// system.create_wallet("w".to_string(), "0x1".to_string(), WalletType::Hot).unwrap();
+    /// system.deposit("w", amount!(5)).unwrap();
+    /// let backup = system.snapshot().to_json().unwrap();
+    ///
+    /// system.withdraw("w", amount!(5)).unwrap();
+    /// system.restore(Snapshot::from_json(&backup).unwrap()).unwrap();
+    /// assert_eq!(system.get_wallet("w").unwrap().balance, amount!(5));
+    /// ```
+    pub fn restore(&mut self, snapshot: Snapshot) -> Result<(), CustodyError> {
+        snapshot.check_version()?;
+        let transactions = snapshot
+            .transactions
+            .into_iter()
+            .collect::<TransactionLog>();
+        let next_transaction_id = transactions.iter().map(|tx| tx.id).max().unwrap_or(0) + 1;
+        let previous_transactions = std::mem::replace(&mut self.transactions, transactions);
+        let previous_next = std::mem::replace(&mut self.next_transaction_id, next_transaction_id);
+        let saved = self
+            .verify_audit_chain()
+            .and_then(|()| check_balances(&snapshot.wallets, &self.transactions))
+            .and_then(|()| {
+                let Some(backend) = self.storage.backend.clone() else {
+                    return Ok(());
+                };
+                let mut wallets = snapshot.wallets.clone();
+                wallets.sort_by(|a, b| a.id.cmp(&b.id));
+                backend.save(&Snapshot {
+                    version: SNAPSHOT_VERSION,
+                    wallets,
+                    transactions: self.transactions.iter().cloned().collect(),
+                })
+            });
+        if let Err(err) = saved {
+            self.transactions = previous_transactions;
+            self.next_transaction_id = previous_next;
+            return Err(err);
+        }
+        self.storage.error = None;
+        self.index = TransactionIndex::build(&self.transactions);
+        let (archived, active): (Vec<Wallet>, Vec<Wallet>) = snapshot
+            .wallets
+            .into_iter()
+            .partition(|wallet| wallet.archived_at.is_some());
+        self.wallets = active
+            .into_iter()
+            .map(|wallet| (wallet.id.clone(), wallet))
+            .collect::<WalletMap>();
+        self.archived_wallets = archived
+            .into_iter()
+            .map(|wallet| (wallet.id.clone(), wallet))
+            .collect::<WalletMap>();
+        Ok(())
+    }
+}
+
+pub(crate) fn storage_failed(err: impl fmt::Display) -> CustodyError {
+    CustodyError::StorageFailed(err.to_string())
+}
+
+/// Stores the ledger as one JSON document
+///
+/// The whole document is rewritten once per operation, to a temporary
+/// file that then replaces the original, so a crash never leaves a
+/// partial write or half an operation behind. Suited to small books and development setups.
+#[derive(Debug)]
+pub struct JsonFileStorage {
+    path: PathBuf,
+    cache: Mutex<Option<Snapshot>>,
+}
+
+impl JsonFileStorage {
+    /// Creates a backend for the document at `path`; the file is created
+    /// on the first save
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            cache: Mutex::new(None),
+        }
+    }
+
+    /// Path of the JSON document
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn read(&self) -> Result<Option<Snapshot>, CustodyError> {
+        match fs::read(&self.path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)
+                .map(Some)
+                .map_err(storage_failed),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(storage_failed(err)),
+        }
+    }
+
+    fn write(&self, snapshot: &Snapshot) -> Result<(), CustodyError> {
+        let json = serde_json::to_vec_pretty(snapshot).map_err(storage_failed)?;
+        let mut staging = self.path.clone().into_os_string();
+        staging.push(".tmp");
+        let staging = PathBuf::from(staging);
+        let mut file = fs::File::create(&staging).map_err(storage_failed)?;
+        file.write_all(&json).map_err(storage_failed)?;
+        file.sync_all().map_err(storage_failed)?;
+        fs::rename(&staging, &self.path).map_err(storage_failed)
+    }
+
+    /// Applies `edit` to the stored document and writes it back
+    fn modify(
+        &self,
+        edit: impl FnOnce(&mut Snapshot) -> Result<(), CustodyError>,
+    ) -> Result<(), CustodyError> {
+        let mut cache = self.cache.lock().unwrap_or_else(|e| e.into_inner());
+        let mut snapshot = match cache.take() {
+            Some(snapshot) => snapshot,
+            None => self.read()?.unwrap_or_default(),
+        };
+        let result = edit(&mut snapshot).and_then(|()| self.write(&snapshot));
+        // On failure the cache stays empty so the next call rereads the file
+        if result.is_ok() {
+            *cache = Some(snapshot);
+        }
+        result
+    }
+}
+
+impl Storage for JsonFileStorage {
+    fn load(&self) -> Result<Option<Snapshot>, CustodyError> {
+        let snapshot = self.read()?;
+        *self.cache.lock().unwrap_or_else(|e| e.into_inner()) = snapshot.clone();
+        Ok(snapshot)
+    }
+
+    fn save(&self, snapshot: &Snapshot) -> Result<(), CustodyError> {
+        self.modify(|stored| {
+            *stored = snapshot.clone();
+            Ok(())
+        })
+    }
+
+    fn append_transaction(&self, transaction: &Transaction) -> Result<(), CustodyError> {
+        self.modify(|stored| {
+            stored.transactions.push(transaction.clone());
+            Ok(())
+        })
+    }
+
+    fn update_transaction(
+        &self,
+        index: usize,
+        transaction: &Transaction,
+    ) -> Result<(), CustodyError> {
+        self.modify(|stored| replace_transaction(stored, index, transaction))
+    }
+
+    fn put_wallet(&self, wallet: &Wallet) -> Result<(), CustodyError> {
+        self.modify(|stored| {
+            put_wallet(stored, wallet);
+            Ok(())
+        })
+    }
+
+    /// Applies the whole batch to the document and rewrites it once
+    fn write_changes(&self, changes: &[Change]) -> Result<(), CustodyError> {
+        self.modify(|stored| {
+            for change in changes {
+                match change {
+                    Change::WalletUpsert { wallet } => put_wallet(stored, wallet),
+                    Change::TransactionAppend { index, transaction } => {
+                        if *index != stored.transactions.len() {
+                            return Err(storage_failed(format!(
+                                "transaction {} appended at position {} of {}",
+                                transaction.id,
+                                index,
+                                stored.transactions.len()
+                            )));
+                        }
+                        stored.transactions.push(transaction.clone());
+                    }
+                    Change::TransactionUpdate { index, transaction } => {
+                        replace_transaction(stored, *index, transaction)?
+                    }
+                }
+            }
+            Ok(())
+        })
+    }
+}
+
+fn replace_transaction(
+    stored: &mut Snapshot,
+    index: usize,
+    transaction: &Transaction,
+) -> Result<(), CustodyError> {
+    let slot = stored
+        .transactions
+        .get_mut(index)
+        .ok_or(CustodyError::TransactionNotFound(index))?;
+    *slot = transaction.clone();
+    Ok(())
+}
+
+fn put_wallet(stored: &mut Snapshot, wallet: &Wallet) {
+    match stored.wallets.binary_search_by(|w| w.id.cmp(&wallet.id)) {
+        Ok(position) => stored.wallets[position] = wallet.clone(),
+        Err(position) => stored.wallets.insert(position, wallet.clone()),
+    }
+}
+
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteStorage;
+
+#[cfg(feature = "sqlite")]
+mod sqlite {
+    use super::{storage_failed, Snapshot, Storage, SNAPSHOT_VERSION};
+    use crate::cdc::Change;
+    use crate::{CustodyError, Transaction, Wallet};
+    use rusqlite::{params, Connection, OptionalExtension};
+    use std::path::Path;
+    use std::sync::Mutex;
+
+    const SCHEMA: &str = "
+        CREATE TABLE IF NOT EXISTS wallets (
+            id   TEXT PRIMARY KEY,
+            data TEXT NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS transactions (
+            position INTEGER PRIMARY KEY,
+            data     TEXT NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS meta (
+            key   TEXT PRIMARY KEY,
+            value TEXT NOT NULL
+        );";
+
+    /// Stores the ledger in a SQLite database (feature `sqlite`)
+    ///
+    /// Wallets and transactions are rows holding their JSON encoding, so
+    /// appends and updates touch a single row. The rows an operation
+    /// touches are written in one SQLite transaction.
+    #[derive(Debug)]
+    pub struct SqliteStorage {
+        connection: Mutex<Connection>,
+    }
+
+    impl SqliteStorage {
+        /// Opens or creates the database at `path`
+        pub fn open(path: impl AsRef<Path>) -> Result<Self, CustodyError> {
+            Self::with_connection(Connection::open(path).map_err(storage_failed)?)
+        }
+
+        /// Opens a private in-memory database, mostly useful in tests
+        pub fn in_memory() -> Result<Self, CustodyError> {
+            Self::with_connection(Connection::open_in_memory().map_err(storage_failed)?)
+        }
+
+        fn with_connection(connection: Connection) -> Result<Self, CustodyError> {
+            connection.execute_batch(SCHEMA).map_err(storage_failed)?;
+            Ok(Self {
+                connection: Mutex::new(connection),
+            })
+        }
+
+        fn connection(&self) -> std::sync::MutexGuard<'_, Connection> {
+            self.connection.lock().unwrap_or_else(|e| e.into_inner())
+        }
+    }
+
+    fn encode<T: serde::Serialize>(value: &T) -> Result<String, CustodyError> {
+        serde_json::to_string(value).map_err(storage_failed)
+    }
+
+    fn decode<T: serde::de::DeserializeOwned>(data: String) -> Result<T, CustodyError> {
+        serde_json::from_str(&data).map_err(storage_failed)
+    }
+
+    fn replace_transaction(
+        connection: &Connection,
+        index: usize,
+        transaction: &Transaction,
+    ) -> Result<(), CustodyError> {
+        let updated = connection
+            .execute(
+                "UPDATE transactions SET data = ?2 WHERE position = ?1",
+                params![index as i64, encode(transaction)?],
+            )
+            .map_err(storage_failed)?;
+        if updated == 0 {
+            return Err(CustodyError::TransactionNotFound(index));
+        }
+        Ok(())
+    }
+
+    fn put_wallet(connection: &Connection, wallet: &Wallet) -> Result<(), CustodyError> {
+        connection
+            .execute(
+                "INSERT OR REPLACE INTO wallets (id, data) VALUES (?1, ?2)",
+                params![wallet.id, encode(wallet)?],
+            )
+            .map_err(storage_failed)?;
+        Ok(())
+    }
+
+    impl Storage for SqliteStorage {
+        fn load(&self) -> Result<Option<Snapshot>, CustodyError> {
+            let connection = self.connection();
+            let saved: Option<String> = connection
+                .query_row("SELECT value FROM meta WHERE key = 'saved'", [], |row| {
+                    row.get(0)
+                })
+                .optional()
+                .map_err(storage_failed)?;
+            if saved.is_none() {
+                return Ok(None);
+            }
+            let rows = |sql: &str| -> Result<Vec<String>, CustodyError> {
+                let mut statement = connection.prepare(sql).map_err(storage_failed)?;
+                let data = statement
+                    .query_map([], |row| row.get(0))
+                    .map_err(storage_failed)?
+                    .collect::<Result<_, _>>()
+                    .map_err(storage_failed)?;
+                Ok(data)
+            };
+            Ok(Some(Snapshot {
+                version: SNAPSHOT_VERSION,
+                wallets: rows("SELECT data FROM wallets ORDER BY id")?
+                    .into_iter()
+                    .map(decode)
+                    .collect::<Result<_, _>>()?,
+                transactions: rows("SELECT data FROM transactions ORDER BY position")?
+                    .into_iter()
+                    .map(decode)
+                    .collect::<Result<_, _>>()?,
+            }))
+        }
+
+        fn save(&self, snapshot: &Snapshot) -> Result<(), CustodyError> {
+            let mut connection = self.connection();
+            let tx = connection.transaction().map_err(storage_failed)?;
+            tx.execute_batch("DELETE FROM wallets; DELETE FROM transactions;")
+                .map_err(storage_failed)?;
+            for wallet in &snapshot.wallets {
+                tx.execute(
+                    "INSERT INTO wallets (id, data) VALUES (?1, ?2)",
+                    params![wallet.id, encode(wallet)?],
+                )
+                .map_err(storage_failed)?;
+            }
+            for (position, transaction) in snapshot.transactions.iter().enumerate() {
+                tx.execute(
+                    "INSERT INTO transactions (position, data) VALUES (?1, ?2)",
+                    params![position as i64, encode(transaction)?],
+                )
+                .map_err(storage_failed)?;
+            }
+            tx.execute(
+                "INSERT OR REPLACE INTO meta (key, value) VALUES ('saved', '1')",
+                [],
+            )
+            .map_err(storage_failed)?;
+            tx.commit().map_err(storage_failed)
+        }
+
+        fn append_transaction(&self, transaction: &Transaction) -> Result<(), CustodyError> {
+            self.connection()
+                .execute(
+                    "INSERT INTO transactions (position, data)
+                     VALUES ((SELECT COUNT(*) FROM transactions), ?1)",
+                    params![encode(transaction)?],
+                )
+                .map_err(storage_failed)?;
+            Ok(())
+        }
+
+        fn update_transaction(
+            &self,
+            index: usize,
+            transaction: &Transaction,
+        ) -> Result<(), CustodyError> {
+            replace_transaction(&self.connection(), index, transaction)
+        }
+
+        fn put_wallet(&self, wallet: &Wallet) -> Result<(), CustodyError> {
+            put_wallet(&self.connection(), wallet)
+        }
+
+        /// Writes the whole batch in one SQLite transaction
+        ///
+        /// Appends go to the position the ledger gave them, so a batch
+        /// retried after a failure cannot duplicate a row.
+        fn write_changes(&self, changes: &[Change]) -> Result<(), CustodyError> {
+            let mut connection = self.connection();
+            let tx = connection.transaction().map_err(storage_failed)?;
+            for change in changes {
+                match change {
+                    Change::WalletUpsert { wallet } => put_wallet(&tx, wallet)?,
+                    Change::TransactionAppend { index, transaction } => {
+                        tx.execute(
+                            "INSERT INTO transactions (position, data) VALUES (?1, ?2)",
+                            params![*index as i64, encode(transaction)?],
+                        )
+                        .map_err(storage_failed)?;
+                    }
+                    Change::TransactionUpdate { index, transaction } => {
+                        replace_transaction(&tx, *index, transaction)?
+                    }
+                }
+            }
+            // Dropping the transaction on an error rolls the batch back
+            tx.commit().map_err(storage_failed)
+        }
+
+        fn read_transactions(
+            &self,
+            offset: usize,
+            limit: usize,
+        ) -> Result<Vec<Transaction>, CustodyError> {
+            let connection = self.connection();
+            let mut statement = connection
+                .prepare(
+                    "SELECT data FROM transactions WHERE position >= ?1
+                     ORDER BY position LIMIT ?2",
+                )
+                .map_err(storage_failed)?;
+            let rows: Vec<String> = statement
+                .query_map(params![offset as i64, limit as i64], |row| row.get(0))
+                .map_err(storage_failed)?
+                .collect::<Result<_, _>>()
+                .map_err(storage_failed)?;
+            rows.into_iter().map(decode).collect()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Category, WalletType};
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("securevault-{}-{}.json", name, std::process::id()))
+    }
+
+    fn exercise(system: &mut CustodySystem) {
+        system
+            .create_wallet("hot".to_string(), "0x1".to_string(), WalletType::Hot)
+            .unwrap();
+        system
+            .create_wallet("cold".to_string(), "0x2".to_string(), WalletType::Cold)
+            .unwrap();
+        system.deposit("hot", amount!(10.0)).unwrap();
+        system.transfer("hot", "cold", amount!(4.25)).unwrap();
+        system
+            .categorize_transaction(0, Some(Category::Operations))
+            .unwrap();
+    }
+
+    fn assert_restored(restored: &CustodySystem, original: &CustodySystem) {
+        assert_eq!(restored.snapshot(), original.snapshot());
+        assert!(restored.audit().passed());
+        assert!(restored.verify_transaction(2).is_ok());
+    }
+
+    /// Records every call and can be told to fail
+    #[derive(Default)]
+    struct Flaky {
+        failing: Mutex<bool>,
+        saves: Mutex<usize>,
+        appended: Mutex<Vec<u64>>,
+    }
+
+    impl Storage for Flaky {
+        fn load(&self) -> Result<Option<Snapshot>, CustodyError> {
+            Ok(None)
+        }
+
+        fn save(&self, _: &Snapshot) -> Result<(), CustodyError> {
+            if *self.failing.lock().unwrap() {
+                return Err(CustodyError::StorageFailed("disk full".to_string()));
+            }
+            *self.saves.lock().unwrap() += 1;
+            Ok(())
+        }
+
+        fn append_transaction(&self, transaction: &Transaction) -> Result<(), CustodyError> {
+            if *self.failing.lock().unwrap() {
+                return Err(CustodyError::StorageFailed("disk full".to_string()));
+            }
+            self.appended.lock().unwrap().push(transaction.id);
+            Ok(())
+        }
+
+        fn update_transaction(&self, _: usize, _: &Transaction) -> Result<(), CustodyError> {
+            Ok(())
+        }
+
+        fn put_wallet(&self, _: &Wallet) -> Result<(), CustodyError> {
+            Ok(())
+        }
+    }
+
+    /// Interrupts a batch with an update of a missing transaction after a
+    /// wallet upsert and an append, and checks nothing of it was stored
+    fn assert_batch_is_atomic(storage: Arc<dyn Storage>) {
+        let mut system = CustodySystem::with_storage(storage.clone()).unwrap();
+        exercise(&mut system);
+        let before = storage.load().unwrap();
+
+        let mut wallet = system.get_wallet("hot").unwrap().clone();
+        wallet.address = "0x9".to_string();
+        let transaction = system.get_all_transactions()[0].clone();
+        let changes = [
+            Change::WalletUpsert { wallet },
+            Change::TransactionAppend {
+                index: 2,
+                transaction: transaction.clone(),
+            },
+            Change::TransactionUpdate {
+                index: 99,
+                transaction,
+            },
+        ];
+        assert!(storage.write_changes(&changes).is_err());
+        assert_eq!(storage.load().unwrap(), before);
+    }
+
+    #[test]
+    fn test_json_file_storage_survives_restart() {
+        let path = temp_path("restart");
+        let _ = fs::remove_file(&path);
+        let mut system =
+            CustodySystem::with_storage(Arc::new(JsonFileStorage::new(&path))).unwrap();
+        exercise(&mut system);
+        assert!(system.storage_error().is_none());
+
+        let mut restored =
+            CustodySystem::with_storage(Arc::new(JsonFileStorage::new(&path))).unwrap();
+        assert_restored(&restored, &system);
+
+        // Ids continue after the restored log
+        restored.deposit("hot", amount!(1.0)).unwrap();
+        assert_eq!(restored.get_all_transactions().last().unwrap().id, 3);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_corrupt_document_is_rejected() {
+        let path = temp_path("corrupt");
+        fs::write(&path, "{not json").unwrap();
+        assert!(matches!(
+            CustodySystem::with_storage(Arc::new(JsonFileStorage::new(&path))),
+            Err(CustodyError::StorageFailed(_))
+        ));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_restore_from_json_backup() {
+        let mut system = CustodySystem::new();
+        exercise(&mut system);
+        let backup = system.snapshot().to_json().unwrap();
+        let original = system.clone();
+
+        system.withdraw("hot", amount!(1.0)).unwrap();
+        system
+            .restore(Snapshot::from_json(&backup).unwrap())
+            .unwrap();
+        assert_restored(&system, &original);
+        system.deposit("hot", amount!(1.0)).unwrap();
+        assert_eq!(system.get_all_transactions().last().unwrap().id, 3);
+
+        // Documents from before versioning read as version 1
+        let legacy = r#"{"wallets": [], "transactions": []}"#;
+        assert_eq!(Snapshot::from_json(legacy).unwrap(), Snapshot::default());
+    }
+
+    #[test]
+    fn test_restore_rejects_bad_snapshots() {
+        let mut system = CustodySystem::new();
+        exercise(&mut system);
+        let original = system.snapshot();
+
+        let newer = Snapshot {
+            version: SNAPSHOT_VERSION + 1,
+            ..Snapshot::default()
+        };
+        let json = newer.to_json().unwrap();
+        assert_eq!(
+            Snapshot::from_json(&json),
+            Err(CustodyError::UnsupportedSnapshotVersion(
+                SNAPSHOT_VERSION + 1
+            ))
+        );
+        assert!(matches!(
+            Snapshot::from_json("{not json"),
+            Err(CustodyError::InvalidSnapshot(_))
+        ));
+
+        let mut tampered = original.clone();
+        tampered.transactions[0].amount = amount!(100.0);
+        assert_eq!(
+            system.restore(tampered),
+            Err(CustodyError::AuditChainBroken(1))
+        );
+        assert_eq!(system.snapshot(), original);
+
+        // Balances that the log does not explain
+        let mut inflated = original.clone();
+        inflated.wallets[0].balance += amount!(1.0);
+        assert!(matches!(
+            system.restore(inflated),
+            Err(CustodyError::InvalidSnapshot(reason)) if reason.contains("transaction log gives")
+        ));
+        let mut orphaned = original.clone();
+        orphaned.wallets.retain(|wallet| wallet.id != "cold");
+        assert!(system.restore(orphaned).is_err());
+        assert_eq!(system.snapshot(), original);
+    }
+
+    #[test]
+    fn test_restore_saves_to_backend() {
+        let storage = Arc::new(Flaky::default());
+        let mut system = CustodySystem::with_storage(storage.clone()).unwrap();
+        exercise(&mut system);
+        let backup = system.snapshot();
+
+        system.withdraw("hot", amount!(1.0)).unwrap();
+        let current = system.snapshot();
+
+        *storage.failing.lock().unwrap() = true;
+        assert!(system.restore(backup.clone()).is_err());
+        assert_eq!(system.snapshot(), current);
+
+        *storage.failing.lock().unwrap() = false;
+        system.restore(backup).unwrap();
+        assert_eq!(system.get_wallet("hot").unwrap().balance, amount!(5.75));
+        assert!(system.storage_error().is_none());
+        assert_eq!(*storage.saves.lock().unwrap(), 2);
+    }
+
+    #[test]
+    fn test_failed_write_fails_the_operation() {
+        let storage = Arc::new(Flaky::default());
+        let mut system = CustodySystem::with_storage(storage.clone()).unwrap();
+        system
+            .create_wallet("w".to_string(), "0x1".to_string(), WalletType::Hot)
+            .unwrap();
+        let before = system.snapshot();
+
+        *storage.failing.lock().unwrap() = true;
+        assert_eq!(
+            system.deposit("w", amount!(1.0)),
+            Err(CustodyError::StorageFailed("disk full".to_string()))
+        );
+        assert_eq!(system.snapshot(), before);
+        assert_eq!(system.latest_sequence(), 1);
+        assert!(system.storage_error().is_some());
+
+        // Later writes go through as soon as the backend recovers
+        *storage.failing.lock().unwrap() = false;
+        system.deposit("w", amount!(1.0)).unwrap();
+        assert!(system.storage_error().is_none());
+        assert_eq!(*storage.appended.lock().unwrap(), vec![1]);
+        assert_eq!(system.get_wallet("w").unwrap().balance, amount!(1.0));
+    }
+
+    #[test]
+    fn test_forks_do_not_persist() {
+        let storage = Arc::new(Flaky::default());
+        let mut system = CustodySystem::with_storage(storage.clone()).unwrap();
+        system
+            .create_wallet("w".to_string(), "0x1".to_string(), WalletType::Hot)
+            .unwrap();
+        let mut plan = system.fork();
+        plan.deposit("w", amount!(1.0)).unwrap();
+        assert!(storage.appended.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_transaction_chunks_page_through_the_stored_log() {
+        let path = temp_path("chunks");
+        let _ = fs::remove_file(&path);
+        assert!(CustodySystem::new().transaction_chunks(2).is_none());
+        let mut system =
+            CustodySystem::with_storage(Arc::new(JsonFileStorage::new(&path))).unwrap();
+        exercise(&mut system);
+        for _ in 0..3 {
+            system.deposit("hot", amount!(1.0)).unwrap();
+        }
+
+        let chunks: Vec<Vec<u64>> = system
+            .transaction_chunks(2)
+            .unwrap()
+            .map(|chunk| chunk.unwrap().iter().map(|tx| tx.id).collect())
+            .collect();
+        assert_eq!(chunks, [vec![1, 2], vec![3, 4], vec![5]]);
+        assert_eq!(system.transaction_chunks(5).unwrap().count(), 1);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_interrupted_batch_leaves_storage_untouched() {
+        let path = temp_path("batch");
+        let _ = fs::remove_file(&path);
+        assert_batch_is_atomic(Arc::new(JsonFileStorage::new(&path)));
+        fs::remove_file(&path).unwrap();
+
+        #[cfg(feature = "sqlite")]
+        {
+            let path = path.with_extension("db");
+            let _ = fs::remove_file(&path);
+            assert_batch_is_atomic(Arc::new(SqliteStorage::open(&path).unwrap()));
+            fs::remove_file(&path).unwrap();
+        }
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_sqlite_storage_survives_restart() {
+        let path = temp_path("sqlite").with_extension("db");
+        let _ = fs::remove_file(&path);
+        let mut system =
+            CustodySystem::with_storage(Arc::new(SqliteStorage::open(&path).unwrap())).unwrap();
+        exercise(&mut system);
+        assert!(system.storage_error().is_none());
+
+        let restored =
+            CustodySystem::with_storage(Arc::new(SqliteStorage::open(&path).unwrap())).unwrap();
+        assert_restored(&restored, &system);
+        let chunks: Vec<usize> = restored
+            .transaction_chunks(1)
+            .unwrap()
+            .map(|chunk| chunk.unwrap().len())
+            .collect();
+        assert_eq!(chunks, [1, 1]);
+        fs::remove_file(&path).unwrap();
+    }
+}
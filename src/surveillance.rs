@@ -0,0 +1,427 @@
+//! Suspicious activity detection.
+//!
+//! Every [`DetectionRule`] added with [`CustodySystem::add_detection_rule`]
+//! sees each transaction right after it is recorded, together with the
+//! system it was recorded in, and may raise an [`Alert`]. Unlike
+//! [screening](crate::ComplianceScreener), rules never stop a transaction;
+//! alerts wait in [`CustodySystem::get_alerts`] until an operator resolves
+//! them.
+//!
+//! Three rules come built in: [`StructuringRule`] for amounts kept just
+//! under a reporting threshold, [`RapidMovementRule`] for funds leaving a
+//! wallet soon after they arrived, and [`DormancyRule`] for a wallet waking
+//! up after a long silence.
+
+use crate::time::Timestamp;
+use crate::{Amount, CustodyError, CustodySystem, Transaction};
+use std::fmt;
+use std::sync::Arc;
+
+/// How urgently an alert needs attention
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    Low,
+    Medium,
+    High,
+}
+
+/// What a rule found in a transaction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    /// Wallet whose activity is suspicious
+    pub wallet_id: String,
+    pub severity: Severity,
+    /// Explanation shown to the operator
+    pub description: String,
+}
+
+/// Examines recorded transactions for suspicious patterns
+pub trait DetectionRule: fmt::Debug + Send + Sync {
+    /// Name the rule's alerts are raised under
+    fn name(&self) -> &str;
+
+    /// Examines `tx`, already recorded in `system`
+    fn evaluate(&self, system: &CustodySystem, tx: &Transaction) -> Option<Finding>;
+}
+
+/// Whether an alert still needs attention
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlertStatus {
+    Open,
+    Resolved,
+}
+
+/// A finding raised against a transaction
+#[derive(Debug, Clone, PartialEq)]
+pub struct Alert {
+    pub id: u64,
+    /// Name of the rule that raised it
+    pub rule: String,
+    pub wallet_id: String,
+    /// Id of the transaction that triggered it
+    pub transaction_id: u64,
+    pub severity: Severity,
+    pub description: String,
+    pub raised_at: Timestamp,
+    pub status: AlertStatus,
+    pub resolved_by: Option<String>,
+    pub resolved_at: Option<Timestamp>,
+    /// The operator's conclusion
+    pub resolution: Option<String>,
+}
+
+/// Returns the wallet's other transactions in the `window_secs` before `tx`
+fn recent<'a>(
+    system: &'a CustodySystem,
+    wallet_id: &str,
+    tx: &Transaction,
+    window_secs: u64,
+) -> impl Iterator<Item = &'a Transaction> {
+    let from = tx.timestamp.as_unix().saturating_sub(window_secs);
+    let (id, asset, until) = (tx.id, tx.asset.clone(), tx.timestamp);
+    system
+        .iter_wallet_transactions(wallet_id)
+        .filter(move |other| {
+            other.id != id
+                && other.asset == asset
+                && other.timestamp.as_unix() >= from
+                && other.timestamp <= until
+        })
+}
+
+/// Flags a wallet that moves several amounts just under a reporting
+/// threshold within a window
+///
+/// "Just under" means at least 90% of the threshold.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StructuringRule {
+    pub threshold: Amount,
+    pub window_secs: u64,
+    /// Number of such amounts, this one included, that raises an alert
+    pub count: usize,
+}
+
+impl StructuringRule {
+    pub fn new(threshold: Amount, window_secs: u64, count: usize) -> Self {
+        Self {
+            threshold,
+            window_secs,
+            count,
+        }
+    }
+
+    fn is_near(&self, amount: Amount) -> bool {
+        let floor = self
+            .threshold
+            .checked_mul_f64(0.9)
+            .unwrap_or(self.threshold);
+        floor <= amount && amount < self.threshold
+    }
+}
+
+impl DetectionRule for StructuringRule {
+    fn name(&self) -> &str {
+        "structuring"
+    }
+
+    fn evaluate(&self, system: &CustodySystem, tx: &Transaction) -> Option<Finding> {
+        if !self.is_near(tx.amount) {
+            return None;
+        }
+        tx.postings().find_map(|(wallet_id, _)| {
+            let near = recent(system, wallet_id, tx, self.window_secs)
+                .filter(|other| self.is_near(other.amount))
+                .count()
+                + 1;
+            (near >= self.count).then(|| Finding {
+                wallet_id: wallet_id.to_string(),
+                severity: Severity::High,
+                description: format!(
+                    "{} amounts just under {} within {} s",
+                    near, self.threshold, self.window_secs
+                ),
+            })
+        })
+    }
+}
+
+/// Flags a debit that, with the wallet's other debits in the window, moves
+/// out most of what was credited in the window
+#[derive(Debug, Clone, PartialEq)]
+pub struct RapidMovementRule {
+    pub window_secs: u64,
+    /// Share of the credits, e.g. `0.9`, whose departure raises an alert
+    pub ratio: f64,
+}
+
+impl RapidMovementRule {
+    pub fn new(window_secs: u64, ratio: f64) -> Self {
+        Self { window_secs, ratio }
+    }
+}
+
+impl DetectionRule for RapidMovementRule {
+    fn name(&self) -> &str {
+        "rapid_movement"
+    }
+
+    fn evaluate(&self, system: &CustodySystem, tx: &Transaction) -> Option<Finding> {
+        let (wallet_id, change) = tx.postings().find(|(_, change)| change.is_negative())?;
+        let (mut credits, mut debits) = (Amount::ZERO, -change);
+        for other in recent(system, wallet_id, tx, self.window_secs) {
+            let change = other.balance_change(wallet_id);
+            if change.is_positive() {
+                credits += change;
+            } else {
+                debits -= change;
+            }
+        }
+        let limit = credits.checked_mul_f64(self.ratio)?;
+        (credits.is_positive() && debits >= limit).then(|| Finding {
+            wallet_id: wallet_id.to_string(),
+            severity: Severity::Medium,
+            description: format!(
+                "{} out of {} received left within {} s",
+                debits, credits, self.window_secs
+            ),
+        })
+    }
+}
+
+/// Flags activity on a wallet that had none for a long time
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DormancyRule {
+    pub dormant_secs: u64,
+}
+
+impl DormancyRule {
+    pub fn new(dormant_secs: u64) -> Self {
+        Self { dormant_secs }
+    }
+}
+
+impl DetectionRule for DormancyRule {
+    fn name(&self) -> &str {
+        "dormant_wallet"
+    }
+
+    fn evaluate(&self, system: &CustodySystem, tx: &Transaction) -> Option<Finding> {
+        tx.postings().find_map(|(wallet_id, _)| {
+            let last = system
+                .iter_wallet_transactions(wallet_id)
+                .filter(|other| other.id != tx.id && other.timestamp <= tx.timestamp)
+                .map(|other| other.timestamp)
+                .max()?;
+            let idle = tx.timestamp.as_unix().saturating_sub(last.as_unix());
+            (idle >= self.dormant_secs).then(|| Finding {
+                wallet_id: wallet_id.to_string(),
+                severity: Severity::Medium,
+                description: format!("first activity in {} s", idle),
+            })
+        })
+    }
+}
+
+impl CustodySystem {
+    /// Evaluates `rule` on every transaction recorded from now on
+    ///
+    /// # Example
+    /// ```
+    /// use securevault::{amount, CustodySystem, Severity, StructuringRule, WalletType};
+    /// use std::sync::Arc;
+    /// let mut system = CustodySystem::new();
+    /// system.create_wallet("w".to_string(), "0x1".to_string(), WalletType::Hot).unwrap();
+    /// system.add_detection_rule(Arc::new(StructuringRule::new(amount!(10000), 86_400, 3)));
+    ///
+    /// for _ in 0..3 {
+    ///     system.deposit("w", amount!(9500)).unwrap();
+    /// }
+    /// let alert = system.get_alerts()[0].clone();
+    /// assert_eq!((alert.rule.as_str(), alert.severity), ("structuring", Severity::High));
+    /// system.resolve_alert(alert.id, "analyst", "payroll, documented").unwrap();
+    /// assert!(system.open_alerts().is_empty());
+    /// ```
+    pub fn add_detection_rule(&mut self, rule: Arc<dyn DetectionRule>) {
+        self.detection_rules.push_back(rule);
+    }
+
+    /// Stops evaluating rules; raised alerts are kept
+    pub fn clear_detection_rules(&mut self) {
+        self.detection_rules.clear();
+    }
+
+    /// Returns every alert, oldest first
+    pub fn get_alerts(&self) -> Vec<&Alert> {
+        self.alerts.values().collect()
+    }
+
+    /// Returns the alerts still open, oldest first
+    pub fn open_alerts(&self) -> Vec<&Alert> {
+        self.alerts
+            .values()
+            .filter(|alert| alert.status == AlertStatus::Open)
+            .collect()
+    }
+
+    /// Gets an alert by id
+    pub fn get_alert(&self, alert_id: u64) -> Option<&Alert> {
+        self.alerts.get(&alert_id)
+    }
+
+    /// Closes an alert with the operator's conclusion
+    ///
+    /// # Errors
+    /// [`CustodyError::AlertNotFound`], and
+    /// [`CustodyError::AlertAlreadyResolved`] if it was closed before
+    pub fn resolve_alert(
+        &mut self,
+        alert_id: u64,
+        operator: &str,
+        resolution: &str,
+    ) -> Result<(), CustodyError> {
+        let now = self.current_timestamp();
+        let alert = self
+            .alerts
+            .get_mut(&alert_id)
+            .ok_or(CustodyError::AlertNotFound(alert_id))?;
+        if alert.status == AlertStatus::Resolved {
+            return Err(CustodyError::AlertAlreadyResolved(alert_id));
+        }
+        alert.status = AlertStatus::Resolved;
+        alert.resolved_by = Some(operator.to_string());
+        alert.resolved_at = Some(now);
+        alert.resolution = Some(resolution.to_string());
+        Ok(())
+    }
+
+    /// Runs the detection rules on the most recently recorded transaction
+    pub(crate) fn detect_suspicious_activity(&mut self) {
+        if self.detection_rules.is_empty() {
+            return;
+        }
+        let Some(tx) = self.transactions.last() else {
+            return;
+        };
+        let raised: Vec<(String, Finding)> = self
+            .detection_rules
+            .iter()
+            .filter_map(|rule| Some((rule.name().to_string(), rule.evaluate(self, tx)?)))
+            .collect();
+        let (transaction_id, raised_at) = (tx.id, self.current_timestamp());
+        for (rule, finding) in raised {
+            let id = self.alerts.keys().next_back().map_or(1, |last| last + 1);
+            self.alerts.insert(
+                id,
+                Alert {
+                    id,
+                    rule,
+                    wallet_id: finding.wallet_id,
+                    transaction_id,
+                    severity: finding.severity,
+                    description: finding.description,
+                    raised_at,
+                    status: AlertStatus::Open,
+                    resolved_by: None,
+                    resolved_at: None,
+                    resolution: None,
+                },
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::time::ManualClock;
+    use crate::WalletType;
+
+    const DAY: u64 = 86_400;
+
+    fn system() -> (CustodySystem, Arc<ManualClock>) {
+        let clock = Arc::new(ManualClock::new(Timestamp::from_unix(1_700_000_000)));
+        let mut system = CustodySystem::new();
+        system.set_clock(clock.clone());
+        for id in ["a", "b"] {
+            system
+                .create_wallet(id.to_string(), id.to_string(), WalletType::Hot)
+                .unwrap();
+        }
+        (system, clock)
+    }
+
+    fn rules(system: &CustodySystem) -> Vec<&str> {
+        system
+            .get_alerts()
+            .iter()
+            .map(|alert| alert.rule.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn test_structuring_and_rapid_movement() {
+        let (mut system, clock) = system();
+        system.add_detection_rule(Arc::new(StructuringRule::new(amount!(10000), DAY, 3)));
+        system.add_detection_rule(Arc::new(RapidMovementRule::new(3600, 0.9)));
+
+        system.deposit("a", amount!(9500)).unwrap();
+        system.deposit("a", amount!(5000)).unwrap();
+        clock.advance(60);
+        system.deposit("a", amount!(9900)).unwrap();
+        assert!(rules(&system).is_empty());
+        system.deposit("a", amount!(9000)).unwrap();
+        assert_eq!(rules(&system), ["structuring"]);
+        assert_eq!(system.get_alerts()[0].wallet_id, "a");
+
+        // Outside the window the earlier deposits no longer count
+        clock.advance(2 * DAY);
+        system.deposit("a", amount!(9999)).unwrap();
+        system.transfer("a", "b", amount!(5000)).unwrap();
+        assert_eq!(rules(&system), ["structuring"]);
+        system.withdraw("a", amount!(4000)).unwrap();
+        assert_eq!(rules(&system), ["structuring", "rapid_movement"]);
+        let alert = system.get_alerts()[1];
+        assert_eq!(
+            (alert.severity, alert.transaction_id),
+            (Severity::Medium, system.get_all_transactions()[6].id)
+        );
+    }
+
+    #[test]
+    fn test_dormancy_and_resolution() {
+        let (mut system, clock) = system();
+        system.deposit("a", amount!(10)).unwrap();
+        system.add_detection_rule(Arc::new(DormancyRule::new(180 * DAY)));
+        clock.advance(179 * DAY);
+        system.deposit("b", amount!(10)).unwrap();
+        system.deposit("a", amount!(10)).unwrap();
+        assert!(system.get_alerts().is_empty());
+
+        clock.advance(180 * DAY);
+        system.transfer("a", "b", amount!(1)).unwrap();
+        let alerts = system.open_alerts();
+        assert_eq!(alerts.len(), 1);
+        let id = alerts[0].id;
+        assert_eq!(alerts[0].wallet_id, "a");
+
+        system
+            .resolve_alert(id, "analyst", "known customer")
+            .unwrap();
+        assert!(system.open_alerts().is_empty());
+        let alert = system.get_alert(id).unwrap();
+        assert_eq!(alert.resolved_by.as_deref(), Some("analyst"));
+        assert_eq!(
+            system.resolve_alert(id, "analyst", "again"),
+            Err(CustodyError::AlertAlreadyResolved(id))
+        );
+        assert_eq!(
+            system.resolve_alert(99, "analyst", ""),
+            Err(CustodyError::AlertNotFound(99))
+        );
+
+        system.clear_detection_rules();
+        clock.advance(365 * DAY);
+        system.deposit("a", amount!(1)).unwrap();
+        assert_eq!(system.get_alerts().len(), 1);
+    }
+}
@@ -0,0 +1,155 @@
+//! Named wallet templates.
+//!
+//! A template captures the configuration shared by a class of wallets so
+//! that operations can create thousands of wallets with identical settings
+//! by name instead of repeating parameters.
+
+use crate::{Asset, CustodyError, CustodySystem, Wallet, WalletType};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+
+/// Configuration applied to wallets created from a template
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WalletTemplate {
+    pub wallet_type: WalletType,
+    pub asset: Asset,
+    /// Tags applied to every wallet created from the template
+    #[serde(default)]
+    pub tags: BTreeSet<String>,
+}
+
+impl WalletTemplate {
+    /// Creates a template for wallets of the given type and asset
+    pub fn new(wallet_type: WalletType, asset: Asset) -> Self {
+        Self {
+            wallet_type,
+            asset,
+            tags: BTreeSet::new(),
+        }
+    }
+
+    /// Preset for operational hot wallets
+    pub fn hot(asset: Asset) -> Self {
+        Self::new(WalletType::Hot, asset).with_tag("hot")
+    }
+
+    /// Preset for long-term cold storage wallets
+    pub fn cold(asset: Asset) -> Self {
+        Self::new(WalletType::Cold, asset).with_tag("cold")
+    }
+
+    /// Adds a tag to the template
+    pub fn with_tag(mut self, tag: &str) -> Self {
+        self.tags.insert(tag.to_string());
+        self
+    }
+}
+
+impl CustodySystem {
+    /// Registers a template under `name`, returning the template it
+    /// replaced, if any
+    pub fn register_template(
+        &mut self,
+        name: &str,
+        template: WalletTemplate,
+    ) -> Option<WalletTemplate> {
+        self.templates.insert(name.to_string(), template)
+    }
+
+    /// Removes a template. Wallets already created from it are unaffected.
+    pub fn remove_template(&mut self, name: &str) -> Option<WalletTemplate> {
+        self.templates.remove(name)
+    }
+
+    /// Gets a template by name
+    pub fn get_template(&self, name: &str) -> Option<&WalletTemplate> {
+        self.templates.get(name)
+    }
+
+    /// Creates a wallet configured from the named template
+    ///
+    /// # Arguments
+    /// * `template` - Name of a registered template
+    /// * `id` - Unique identifier for the wallet
+    /// * `address` - Cryptocurrency address
+    pub fn create_wallet_from_template(
+        &mut self,
+        template: &str,
+        id: String,
+        address: String,
+    ) -> Result<Wallet, CustodyError> {
+        let spec = self
+            .templates
+            .get(template)
+            .cloned()
+            .ok_or_else(|| CustodyError::TemplateNotFound(template.to_string()))?;
+
+        self.create_wallet_with_asset(id.clone(), address, spec.wallet_type, spec.asset)?;
+        let mut wallet = self.wallets[&id].clone();
+        wallet.tags = spec.tags;
+        wallet.template = Some(template.to_string());
+        self.commit_wallet(wallet.clone())?;
+        Ok(wallet)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_create_wallet_from_template() {
+        let mut system = CustodySystem::new();
+        system.register_template(
+            "customer_hot",
+            WalletTemplate::hot(Asset::Eth).with_tag("customer"),
+        );
+
+        let wallet = system
+            .create_wallet_from_template("customer_hot", "c1".to_string(), "0x1".to_string())
+            .unwrap();
+
+        assert_eq!(wallet.wallet_type, WalletType::Hot);
+        assert_eq!(wallet.asset, Asset::Eth);
+        assert!(wallet.tags.contains("customer"));
+        assert!(wallet.tags.contains("hot"));
+        assert_eq!(wallet.template.as_deref(), Some("customer_hot"));
+        assert_eq!(system.get_wallet("c1").unwrap(), &wallet);
+    }
+
+    #[test]
+    fn test_unknown_template() {
+        let mut system = CustodySystem::new();
+        let result =
+            system.create_wallet_from_template("missing", "c1".to_string(), "0x1".to_string());
+        assert_eq!(
+            result,
+            Err(CustodyError::TemplateNotFound("missing".to_string()))
+        );
+        assert_eq!(system.wallet_count(), 0);
+    }
+
+    #[test]
+    fn test_template_does_not_bypass_duplicate_check() {
+        let mut system = CustodySystem::new();
+        system.register_template("vault", WalletTemplate::cold(Asset::Btc));
+        system
+            .create_wallet_from_template("vault", "v1".to_string(), "bc1".to_string())
+            .unwrap();
+        let result =
+            system.create_wallet_from_template("vault", "v1".to_string(), "bc2".to_string());
+        assert!(matches!(result, Err(CustodyError::WalletAlreadyExists(_))));
+    }
+
+    #[test]
+    fn test_replace_and_remove_template() {
+        let mut system = CustodySystem::new();
+        assert!(system
+            .register_template("t", WalletTemplate::hot(Asset::Btc))
+            .is_none());
+        let previous = system.register_template("t", WalletTemplate::cold(Asset::Btc));
+        assert_eq!(previous.unwrap().wallet_type, WalletType::Hot);
+        assert!(system.remove_template("t").is_some());
+        assert!(system.get_template("t").is_none());
+    }
+}
@@ -0,0 +1,395 @@
+//! Tenants sharing one custody system.
+//!
+//! A custodian hosting several clients can give each a [`Tenant`] instead
+//! of running one process per client. A tenant owns the wallet namespace
+//! `<tenant>/`: [`CustodySystem::tenant`] returns a [`TenantSystem`] whose
+//! calls take wallet ids local to the tenant and qualify them, so two
+//! tenants can both have a wallet `"treasury"` and neither can reach the
+//! other's wallets, or wallets outside any tenant. Wallets keep their
+//! qualified id everywhere else, e.g. `"acme/treasury"`.
+//!
+//! Each tenant can have its own withdrawal policies per wallet type, which
+//! take precedence over the system's for its wallets, and a quota on how
+//! many wallets it may hold.
+
+use crate::time::Timestamp;
+use crate::{
+    AdminAction, Amount, Asset, CustodyError, CustodySystem, Transaction, Wallet, WalletType,
+    WithdrawalPolicy,
+};
+use std::collections::HashMap;
+
+/// Separates a tenant id from the local part of a wallet id
+pub const TENANT_SEPARATOR: char = '/';
+
+/// A client hosted in the system
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tenant {
+    pub id: String,
+    pub name: String,
+    pub created_at: Timestamp,
+    /// Most wallets the tenant may hold; `None` for no limit
+    pub wallet_quota: Option<usize>,
+    /// Policies of the tenant's wallets without one of their own, in place
+    /// of the system's defaults
+    pub withdrawal_policies: HashMap<WalletType, WithdrawalPolicy>,
+}
+
+impl CustodySystem {
+    /// Registers a tenant
+    ///
+    /// # Errors
+    /// [`CustodyError::InvalidConfig`] if `id` is empty or contains
+    /// [`TENANT_SEPARATOR`], and [`CustodyError::TenantAlreadyExists`] if
+    /// it is taken
+    pub fn create_tenant(&mut self, id: &str, name: &str) -> Result<(), CustodyError> {
+        if id.is_empty() || id.contains(TENANT_SEPARATOR) {
+            return Err(CustodyError::InvalidConfig(format!(
+                "tenant id '{}' must be non-empty and not contain '{}'",
+                id, TENANT_SEPARATOR
+            )));
+        }
+        if self.tenants.contains_key(id) {
+            return Err(CustodyError::TenantAlreadyExists(id.to_string()));
+        }
+        let tenant = Tenant {
+            id: id.to_string(),
+            name: name.to_string(),
+            created_at: self.current_timestamp(),
+            wallet_quota: None,
+            withdrawal_policies: HashMap::new(),
+        };
+        self.tenants.insert(id.to_string(), tenant);
+        Ok(())
+    }
+
+    /// Gets a tenant by id
+    pub fn get_tenant(&self, id: &str) -> Result<&Tenant, CustodyError> {
+        self.tenants
+            .get(id)
+            .ok_or_else(|| CustodyError::TenantNotFound(id.to_string()))
+    }
+
+    /// Lists every tenant, ordered by id
+    pub fn tenants(&self) -> Vec<&Tenant> {
+        let mut tenants: Vec<_> = self.tenants.values().collect();
+        tenants.sort_by(|a, b| a.id.cmp(&b.id));
+        tenants
+    }
+
+    /// Sets how many wallets a tenant may hold, or lifts the quota with
+    /// `None`; wallets it already holds are kept
+    pub fn set_tenant_wallet_quota(
+        &mut self,
+        id: &str,
+        quota: Option<usize>,
+    ) -> Result<(), CustodyError> {
+        self.tenant_mut(id)?.wallet_quota = quota;
+        Ok(())
+    }
+
+    /// Sets the policy of a tenant's `wallet_type` wallets that have no
+    /// policy of their own
+    pub fn set_tenant_withdrawal_policy(
+        &mut self,
+        id: &str,
+        wallet_type: WalletType,
+        policy: WithdrawalPolicy,
+    ) -> Result<(), CustodyError> {
+        self.require_second_operator(AdminAction::SET_TENANT_WITHDRAWAL_POLICY)?;
+        self.replace_tenant_withdrawal_policy(id, wallet_type, policy)
+    }
+
+    pub(crate) fn replace_tenant_withdrawal_policy(
+        &mut self,
+        id: &str,
+        wallet_type: WalletType,
+        policy: WithdrawalPolicy,
+    ) -> Result<(), CustodyError> {
+        self.tenant_mut(id)?
+            .withdrawal_policies
+            .insert(wallet_type, policy);
+        Ok(())
+    }
+
+    /// Returns the tenant owning a wallet, if it is in a tenant's namespace
+    pub fn tenant_of(&self, wallet_id: &str) -> Option<&Tenant> {
+        let (tenant, _) = wallet_id.split_once(TENANT_SEPARATOR)?;
+        self.tenants.get(tenant)
+    }
+
+    /// Lists a tenant's wallets, ordered by id
+    pub fn tenant_wallets(&self, id: &str) -> Result<Vec<&Wallet>, CustodyError> {
+        self.get_tenant(id)?;
+        let mut wallets: Vec<_> = self
+            .wallets
+            .values()
+            .filter(|wallet| in_namespace(id, &wallet.id))
+            .collect();
+        wallets.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(wallets)
+    }
+
+    /// Lists the transactions booked to a tenant's wallets, transfers into
+    /// them included, oldest first
+    pub fn tenant_transactions(&self, id: &str) -> Result<Vec<&Transaction>, CustodyError> {
+        self.get_tenant(id)?;
+        Ok(self
+            .transactions
+            .iter()
+            .filter(|tx| {
+                tx.postings()
+                    .any(|(wallet_id, _)| in_namespace(id, wallet_id))
+            })
+            .collect())
+    }
+
+    /// Sums a tenant's holdings of `asset`
+    pub fn tenant_balance(&self, id: &str, asset: &Asset) -> Result<Amount, CustodyError> {
+        Ok(self
+            .tenant_wallets(id)?
+            .into_iter()
+            .map(|wallet| wallet.balance_of(asset))
+            .sum())
+    }
+
+    /// Returns a view of the system confined to a tenant's namespace
+    ///
+    /// # Example
+    /// ```
+    /// use securevault::{amount, CustodySystem, WalletType};
+    /// let mut system = CustodySystem::new();
+    /// system.create_tenant("acme", "Acme Corp").unwrap();
+    /// system.create_tenant("globex", "Globex").unwrap();
+    ///
+    /// let mut acme = system.tenant("acme").unwrap();
+    /// acme.create_wallet("treasury", "0x1".to_string(), WalletType::Hot).unwrap();
+    /// acme.deposit("treasury", amount!(5)).unwrap();
+    ///
+    /// let mut globex = system.tenant("globex").unwrap();
+    /// globex.create_wallet("treasury", "0x2".to_string(), WalletType::Hot).unwrap();
+    /// assert_eq!(globex.get_wallet("treasury").unwrap().balance, amount!(0));
+    /// assert_eq!(system.get_wallet("acme/treasury").unwrap().balance, amount!(5));
+    /// ```
+    pub fn tenant(&mut self, id: &str) -> Result<TenantSystem<'_>, CustodyError> {
+        self.get_tenant(id)?;
+        Ok(TenantSystem {
+            system: self,
+            tenant_id: id.to_string(),
+        })
+    }
+
+    /// Returns the policy a tenant sets for the wallet's type, if the
+    /// wallet belongs to a tenant
+    pub(crate) fn tenant_withdrawal_policy(&self, wallet: &Wallet) -> Option<&WithdrawalPolicy> {
+        self.tenant_of(&wallet.id)?
+            .withdrawal_policies
+            .get(&wallet.wallet_type)
+    }
+
+    fn tenant_mut(&mut self, id: &str) -> Result<&mut Tenant, CustodyError> {
+        self.tenants
+            .get_mut(id)
+            .ok_or_else(|| CustodyError::TenantNotFound(id.to_string()))
+    }
+}
+
+fn in_namespace(tenant_id: &str, wallet_id: &str) -> bool {
+    wallet_id
+        .split_once(TENANT_SEPARATOR)
+        .is_some_and(|(tenant, _)| tenant == tenant_id)
+}
+
+/// A custody system seen by one tenant
+///
+/// Wallet ids passed in are local to the tenant.
+pub struct TenantSystem<'a> {
+    system: &'a mut CustodySystem,
+    tenant_id: String,
+}
+
+impl TenantSystem<'_> {
+    /// Returns the tenant
+    pub fn tenant(&self) -> &Tenant {
+        &self.system.tenants[&self.tenant_id]
+    }
+
+    /// Returns the system-wide id of a local wallet id
+    pub fn qualify(&self, wallet_id: &str) -> String {
+        format!("{}{}{}", self.tenant_id, TENANT_SEPARATOR, wallet_id)
+    }
+
+    /// Creates a wallet in the tenant's namespace
+    ///
+    /// # Errors
+    /// [`CustodyError::TenantQuotaExceeded`] if the tenant already holds as
+    /// many wallets as its quota allows, and the errors of
+    /// [`CustodySystem::create_wallet`]
+    pub fn create_wallet(
+        &mut self,
+        id: &str,
+        address: String,
+        wallet_type: WalletType,
+    ) -> Result<Wallet, CustodyError> {
+        if let Some(quota) = self.tenant().wallet_quota {
+            if self.system.tenant_wallets(&self.tenant_id)?.len() >= quota {
+                return Err(CustodyError::TenantQuotaExceeded {
+                    tenant_id: self.tenant_id.clone(),
+                    quota,
+                });
+            }
+        }
+        let id = self.qualify(id);
+        self.system.create_wallet(id, address, wallet_type)
+    }
+
+    pub fn get_wallet(&self, id: &str) -> Option<&Wallet> {
+        self.system.get_wallet(&self.qualify(id))
+    }
+
+    /// Lists the tenant's wallets, ordered by id
+    pub fn wallets(&self) -> Vec<&Wallet> {
+        self.system
+            .tenant_wallets(&self.tenant_id)
+            .expect("checked when the view was created")
+    }
+
+    /// Lists the tenant's transactions, oldest first
+    pub fn transactions(&self) -> Vec<&Transaction> {
+        self.system
+            .tenant_transactions(&self.tenant_id)
+            .expect("checked when the view was created")
+    }
+
+    pub fn get_wallet_transactions(&self, id: &str) -> Vec<&Transaction> {
+        self.system.get_wallet_transactions(&self.qualify(id))
+    }
+
+    pub fn deposit(&mut self, id: &str, amount: Amount) -> Result<(), CustodyError> {
+        let id = self.qualify(id);
+        self.system.deposit(&id, amount)
+    }
+
+    pub fn withdraw(&mut self, id: &str, amount: Amount) -> Result<(), CustodyError> {
+        let id = self.qualify(id);
+        self.system.withdraw(&id, amount)
+    }
+
+    /// Transfers between two of the tenant's wallets
+    pub fn transfer(&mut self, from: &str, to: &str, amount: Amount) -> Result<(), CustodyError> {
+        let (from, to) = (self.qualify(from), self.qualify(to));
+        self.system.transfer(&from, &to, amount)
+    }
+
+    /// Sets a wallet's own policy, replacing the tenant's for its type
+    pub fn set_withdrawal_policy(
+        &mut self,
+        id: &str,
+        policy: WithdrawalPolicy,
+    ) -> Result<(), CustodyError> {
+        let id = self.qualify(id);
+        self.system.set_withdrawal_policy(&id, policy)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn system() -> CustodySystem {
+        let mut system = CustodySystem::new();
+        system
+            .create_wallet("shared".to_string(), "0x0".to_string(), WalletType::Hot)
+            .unwrap();
+        for tenant in ["acme", "globex"] {
+            system.create_tenant(tenant, tenant).unwrap();
+            let mut view = system.tenant(tenant).unwrap();
+            for id in ["a", "b"] {
+                view.create_wallet(id, format!("0x{}", id), WalletType::Hot)
+                    .unwrap();
+                view.deposit(id, amount!(10)).unwrap();
+            }
+        }
+        system
+    }
+
+    #[test]
+    fn test_namespaces_are_isolated() {
+        let mut system = system();
+        let mut acme = system.tenant("acme").unwrap();
+        acme.transfer("a", "b", amount!(4)).unwrap();
+        assert_eq!(acme.get_wallet("b").unwrap().balance, amount!(14));
+        assert_eq!(acme.wallets().len(), 2);
+        assert_eq!(acme.transactions().len(), 3);
+        // Wallets outside the namespace cannot be named
+        assert!(acme.get_wallet("shared").is_none());
+        assert!(acme.get_wallet("globex/a").is_none());
+        assert_eq!(
+            acme.withdraw("shared", amount!(1)),
+            Err(CustodyError::WalletNotFound("acme/shared".to_string()))
+        );
+
+        assert_eq!(
+            system.tenant_balance("globex", &Asset::Btc),
+            Ok(amount!(20))
+        );
+        assert_eq!(system.tenant_transactions("globex").unwrap().len(), 2);
+        // Transfers in count for the receiving tenant
+        system.deposit("shared", amount!(5)).unwrap();
+        system.transfer("shared", "globex/a", amount!(1)).unwrap();
+        let incoming = system.tenant_transactions("globex").unwrap();
+        assert_eq!(incoming.len(), 3);
+        assert_eq!(incoming[2].wallet_id, "shared");
+        assert_eq!(system.tenant_transactions("acme").unwrap().len(), 3);
+        assert_eq!(system.tenant_of("acme/a").unwrap().id, "acme");
+        assert!(system.tenant_of("shared").is_none());
+        assert_eq!(
+            system.create_tenant("acme", "again"),
+            Err(CustodyError::TenantAlreadyExists("acme".to_string()))
+        );
+        assert!(matches!(
+            system.create_tenant("a/b", "bad"),
+            Err(CustodyError::InvalidConfig(_))
+        ));
+        assert!(matches!(
+            system.tenant("initech"),
+            Err(CustodyError::TenantNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_tenant_policies_and_quota() {
+        let mut system = system();
+        system
+            .set_tenant_withdrawal_policy(
+                "acme",
+                WalletType::Hot,
+                WithdrawalPolicy {
+                    max_per_transaction: Some(amount!(1)),
+                    ..WithdrawalPolicy::default()
+                },
+            )
+            .unwrap();
+        assert!(matches!(
+            system.tenant("acme").unwrap().withdraw("a", amount!(2)),
+            Err(CustodyError::PolicyViolation { .. })
+        ));
+        system
+            .tenant("globex")
+            .unwrap()
+            .withdraw("a", amount!(2))
+            .unwrap();
+
+        system.set_tenant_wallet_quota("acme", Some(3)).unwrap();
+        let mut acme = system.tenant("acme").unwrap();
+        acme.create_wallet("c", "0xc".to_string(), WalletType::Cold)
+            .unwrap();
+        assert_eq!(
+            acme.create_wallet("d", "0xd".to_string(), WalletType::Cold),
+            Err(CustodyError::TenantQuotaExceeded {
+                tenant_id: "acme".to_string(),
+                quota: 3,
+            })
+        );
+    }
+}
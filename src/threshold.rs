@@ -0,0 +1,576 @@
+//! Threshold (MPC) signing ceremonies (feature `mpc`).
+//!
+//! With a threshold scheme, no single machine ever holds a wallet's key:
+//! each participant holds a share, and any `threshold` of them produce a
+//! signature together over several rounds of messages. A
+//! [`SigningCeremony`] coordinates one signing: participants register,
+//! then in each round every registered participant sends a
+//! [`RoundMessage`] computed from the messages of the earlier rounds, and
+//! after the last round the [`ThresholdSigner`] combines them into the
+//! signature. The coordinator only relays messages; shares stay with the
+//! [`ThresholdParticipant`]s.
+//!
+//! [`LocalCeremony`] runs a whole ceremony in-process and is a
+//! [`Signer`], so threshold-signed withdrawals go through
+//! [`CustodySystem::withdraw_signed`](crate::CustodySystem::withdraw_signed)
+//! like any other. The only scheme so far is [`MockThresholdScheme`], a
+//! stand-in until a real MPC library is plugged in behind the same traits.
+
+use crate::signer::{Signature, Signer};
+use crate::CustodyError;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeSet;
+use std::fmt;
+use std::sync::Arc;
+
+/// A participant's message in one round of a ceremony
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RoundMessage {
+    pub participant: String,
+    /// Round the message belongs to, counting from 1
+    pub round: usize,
+    pub bytes: Vec<u8>,
+}
+
+/// The coordinator's side of a threshold signature scheme
+pub trait ThresholdSigner: fmt::Debug + Send + Sync {
+    /// Identifies the joint key the signatures verify under
+    fn key_id(&self) -> String;
+
+    /// Participants holding a share of the key
+    fn participants(&self) -> Vec<String>;
+
+    /// How many participants must take part in a signing
+    fn threshold(&self) -> usize;
+
+    /// Number of message rounds before the signature can be combined
+    fn rounds(&self) -> usize;
+
+    /// Combines the messages of all rounds by `group` into the signature
+    /// over `payload`, checking them as the scheme allows
+    fn combine(
+        &self,
+        payload: &[u8],
+        group: &[String],
+        messages: &[RoundMessage],
+    ) -> Result<Vec<u8>, String>;
+}
+
+/// One share holder of a threshold key
+pub trait ThresholdParticipant: fmt::Debug + Send + Sync {
+    /// Name the participant registers under
+    fn id(&self) -> String;
+
+    /// Computes this participant's message for `round` of a signing of
+    /// `payload` by `group`, having received `earlier`, the messages of
+    /// every previous round
+    fn round_message(
+        &self,
+        round: usize,
+        payload: &[u8],
+        group: &[String],
+        earlier: &[RoundMessage],
+    ) -> Result<Vec<u8>, String>;
+}
+
+/// Where a ceremony stands
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CeremonyStatus {
+    /// Participants are registering
+    Registering,
+    /// Collecting the messages of this round
+    Round(usize),
+    /// The signature is ready
+    Complete,
+    /// The messages could not be combined; the ceremony must restart
+    Failed(String),
+}
+
+/// Coordinates the signing of one payload
+#[derive(Debug, Clone)]
+pub struct SigningCeremony {
+    signer: Arc<dyn ThresholdSigner>,
+    payload: Vec<u8>,
+    group: BTreeSet<String>,
+    status: CeremonyStatus,
+    messages: Vec<RoundMessage>,
+    signature: Option<Signature>,
+}
+
+impl SigningCeremony {
+    /// Opens a ceremony for `signer` to sign `payload`
+    pub fn new(signer: Arc<dyn ThresholdSigner>, payload: &[u8]) -> Self {
+        Self {
+            signer,
+            payload: payload.to_vec(),
+            group: BTreeSet::new(),
+            status: CeremonyStatus::Registering,
+            messages: Vec::new(),
+            signature: None,
+        }
+    }
+
+    /// Registers a participant holding a share; registering twice has no
+    /// further effect
+    pub fn register(&mut self, participant: &str) -> Result<(), CustodyError> {
+        if self.status != CeremonyStatus::Registering {
+            return Err(failed("registration is closed"));
+        }
+        if !self.signer.participants().iter().any(|p| p == participant) {
+            return Err(failed(format!(
+                "{} holds no share of {}",
+                participant,
+                self.signer.key_id()
+            )));
+        }
+        self.group.insert(participant.to_string());
+        Ok(())
+    }
+
+    /// Closes registration and opens the first round
+    ///
+    /// # Errors
+    /// [`CustodyError::SigningFailed`] if fewer participants than the
+    /// threshold registered
+    pub fn start(&mut self) -> Result<CeremonyStatus, CustodyError> {
+        if self.status != CeremonyStatus::Registering {
+            return Err(failed("the ceremony has already started"));
+        }
+        let threshold = self.signer.threshold();
+        if self.group.len() < threshold {
+            return Err(failed(format!(
+                "{} of {} participants registered",
+                self.group.len(),
+                threshold
+            )));
+        }
+        self.status = CeremonyStatus::Round(1);
+        Ok(self.status.clone())
+    }
+
+    /// Adds a participant's message for the current round, moving on to
+    /// the next round once every participant has sent theirs and
+    /// combining the signature after the last
+    pub fn submit(&mut self, message: RoundMessage) -> Result<CeremonyStatus, CustodyError> {
+        let CeremonyStatus::Round(round) = self.status else {
+            return Err(failed("no round is open"));
+        };
+        if message.round != round {
+            return Err(failed(format!(
+                "message for round {} during round {}",
+                message.round, round
+            )));
+        }
+        if !self.group.contains(&message.participant) {
+            return Err(failed(format!("{} is not signing", message.participant)));
+        }
+        if self
+            .round_messages(round)
+            .any(|m| m.participant == message.participant)
+        {
+            return Err(failed(format!(
+                "{} already sent round {}",
+                message.participant, round
+            )));
+        }
+        self.messages.push(message);
+        if !self.awaiting().is_empty() {
+            return Ok(self.status.clone());
+        }
+
+        if round < self.signer.rounds() {
+            self.status = CeremonyStatus::Round(round + 1);
+            return Ok(self.status.clone());
+        }
+        match self
+            .signer
+            .combine(&self.payload, &self.group(), &self.messages)
+        {
+            Ok(bytes) => {
+                self.signature = Some(Signature {
+                    key_id: self.signer.key_id(),
+                    bytes,
+                });
+                self.status = CeremonyStatus::Complete;
+                Ok(self.status.clone())
+            }
+            Err(reason) => {
+                self.status = CeremonyStatus::Failed(reason.clone());
+                Err(CustodyError::SigningFailed(reason))
+            }
+        }
+    }
+
+    /// Returns where the ceremony stands
+    pub fn status(&self) -> &CeremonyStatus {
+        &self.status
+    }
+
+    /// Registered participants, in name order
+    pub fn group(&self) -> Vec<String> {
+        self.group.iter().cloned().collect()
+    }
+
+    /// Participants yet to send their message for the current round
+    pub fn awaiting(&self) -> Vec<&str> {
+        let CeremonyStatus::Round(round) = self.status else {
+            return Vec::new();
+        };
+        self.group
+            .iter()
+            .map(String::as_str)
+            .filter(|participant| {
+                !self
+                    .round_messages(round)
+                    .any(|m| m.participant == *participant)
+            })
+            .collect()
+    }
+
+    /// Messages of the rounds completed so far, to relay to participants
+    pub fn completed_messages(&self) -> Vec<&RoundMessage> {
+        let current = match self.status {
+            CeremonyStatus::Round(round) => round,
+            _ => usize::MAX,
+        };
+        self.messages.iter().filter(|m| m.round < current).collect()
+    }
+
+    /// Payload being signed
+    pub fn payload(&self) -> &[u8] {
+        &self.payload
+    }
+
+    /// Returns the signature once the ceremony is complete
+    pub fn signature(&self) -> Option<&Signature> {
+        self.signature.as_ref()
+    }
+
+    fn round_messages(&self, round: usize) -> impl Iterator<Item = &RoundMessage> {
+        self.messages.iter().filter(move |m| m.round == round)
+    }
+}
+
+fn failed(reason: impl Into<String>) -> CustodyError {
+    CustodyError::SigningFailed(reason.into())
+}
+
+/// Runs whole ceremonies in-process with participants at hand
+#[derive(Debug, Clone)]
+pub struct LocalCeremony {
+    signer: Arc<dyn ThresholdSigner>,
+    participants: Vec<Arc<dyn ThresholdParticipant>>,
+}
+
+impl LocalCeremony {
+    /// Signs with `signer`, `participants` taking part
+    pub fn new(
+        signer: Arc<dyn ThresholdSigner>,
+        participants: Vec<Arc<dyn ThresholdParticipant>>,
+    ) -> Self {
+        Self {
+            signer,
+            participants,
+        }
+    }
+}
+
+impl Signer for LocalCeremony {
+    fn key_id(&self) -> String {
+        self.signer.key_id()
+    }
+
+    fn sign(&self, payload: &[u8]) -> Result<Signature, CustodyError> {
+        let mut ceremony = SigningCeremony::new(self.signer.clone(), payload);
+        for participant in &self.participants {
+            ceremony.register(&participant.id())?;
+        }
+        let mut status = ceremony.start()?;
+        while let CeremonyStatus::Round(round) = status {
+            let earlier: Vec<RoundMessage> =
+                ceremony.completed_messages().into_iter().cloned().collect();
+            let group = ceremony.group();
+            for participant in &self.participants {
+                let bytes = participant
+                    .round_message(round, payload, &group, &earlier)
+                    .map_err(CustodyError::SigningFailed)?;
+                status = ceremony.submit(RoundMessage {
+                    participant: participant.id(),
+                    round,
+                    bytes,
+                })?;
+            }
+        }
+        ceremony
+            .signature()
+            .cloned()
+            .ok_or_else(|| failed("the ceremony ended without a signature"))
+    }
+}
+
+/// Stand-in for a threshold ECDSA scheme
+///
+/// No MPC library is linked yet. Shares are hashes of a seed, round one
+/// commits to each share and round two reveals a share-bound response that
+/// the coordinator checks against the commitments; the "signature" is a
+/// SHA-256 over the key and payload, the same whichever participants sign.
+/// It exercises ceremonies, dropouts and misbehaving participants but
+/// offers no security: the coordinator knows every share.
+#[derive(Debug, Clone)]
+pub struct MockThresholdScheme {
+    key_id: String,
+    seed: Vec<u8>,
+    participants: Vec<String>,
+    threshold: usize,
+}
+
+impl MockThresholdScheme {
+    /// Shares the key `key_id` among `participants`, `threshold` of whom
+    /// must sign
+    pub fn new(key_id: &str, participants: &[&str], threshold: usize) -> Self {
+        Self {
+            key_id: key_id.to_string(),
+            seed: Sha256::digest(key_id.as_bytes()).to_vec(),
+            participants: participants.iter().map(|p| p.to_string()).collect(),
+            threshold,
+        }
+    }
+
+    /// Returns the share holder `id`, if it is a participant
+    pub fn participant(&self, id: &str) -> Option<MockParticipant> {
+        self.participants
+            .iter()
+            .any(|p| p == id)
+            .then(|| MockParticipant {
+                id: id.to_string(),
+                share: self.share(id),
+            })
+    }
+
+    /// The signature any `threshold` participants produce over `payload`
+    pub fn expected_signature(&self, payload: &[u8]) -> Vec<u8> {
+        Sha256::new()
+            .chain_update(b"signature")
+            .chain_update(self.key_id.as_bytes())
+            .chain_update([0])
+            .chain_update(payload)
+            .finalize()
+            .to_vec()
+    }
+
+    fn share(&self, id: &str) -> [u8; 32] {
+        Sha256::new()
+            .chain_update(&self.seed)
+            .chain_update(id.as_bytes())
+            .finalize()
+            .into()
+    }
+}
+
+impl ThresholdSigner for MockThresholdScheme {
+    fn key_id(&self) -> String {
+        format!("mpc:{}", self.key_id)
+    }
+
+    fn participants(&self) -> Vec<String> {
+        self.participants.clone()
+    }
+
+    fn threshold(&self) -> usize {
+        self.threshold
+    }
+
+    fn rounds(&self) -> usize {
+        2
+    }
+
+    fn combine(
+        &self,
+        payload: &[u8],
+        group: &[String],
+        messages: &[RoundMessage],
+    ) -> Result<Vec<u8>, String> {
+        let commitments: Vec<RoundMessage> =
+            messages.iter().filter(|m| m.round == 1).cloned().collect();
+        for id in group {
+            let share = self.share(id);
+            let sent = |round: usize| {
+                messages
+                    .iter()
+                    .find(|m| m.round == round && m.participant == *id)
+                    .map(|m| m.bytes.as_slice())
+            };
+            if sent(1) != Some(commitment(&share, payload).as_slice())
+                || sent(2) != Some(response(&share, payload, &commitments).as_slice())
+            {
+                return Err(format!("{} sent an invalid share", id));
+            }
+        }
+        Ok(self.expected_signature(payload))
+    }
+}
+
+/// Share holder of a [`MockThresholdScheme`] key
+#[derive(Clone)]
+pub struct MockParticipant {
+    id: String,
+    share: [u8; 32],
+}
+
+impl fmt::Debug for MockParticipant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MockParticipant")
+            .field("id", &self.id)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ThresholdParticipant for MockParticipant {
+    fn id(&self) -> String {
+        self.id.clone()
+    }
+
+    fn round_message(
+        &self,
+        round: usize,
+        payload: &[u8],
+        _group: &[String],
+        earlier: &[RoundMessage],
+    ) -> Result<Vec<u8>, String> {
+        match round {
+            1 => Ok(commitment(&self.share, payload).to_vec()),
+            2 => Ok(response(&self.share, payload, earlier).to_vec()),
+            _ => Err(format!("no round {}", round)),
+        }
+    }
+}
+
+fn commitment(share: &[u8; 32], payload: &[u8]) -> [u8; 32] {
+    Sha256::new()
+        .chain_update(b"commit")
+        .chain_update(share)
+        .chain_update(payload)
+        .finalize()
+        .into()
+}
+
+/// Binds the share to every commitment, in participant order
+fn response(share: &[u8; 32], payload: &[u8], commitments: &[RoundMessage]) -> [u8; 32] {
+    let mut commitments: Vec<&RoundMessage> = commitments.iter().filter(|m| m.round == 1).collect();
+    commitments.sort_by(|a, b| a.participant.cmp(&b.participant));
+    let mut hasher = Sha256::new()
+        .chain_update(b"respond")
+        .chain_update(share)
+        .chain_update(payload);
+    for message in commitments {
+        hasher.update(&message.bytes);
+    }
+    hasher.finalize().into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CustodySystem, WalletType};
+
+    fn scheme() -> MockThresholdScheme {
+        MockThresholdScheme::new("treasury", &["alice", "bob", "carol"], 2)
+    }
+
+    fn local(scheme: &MockThresholdScheme, ids: &[&str]) -> LocalCeremony {
+        LocalCeremony::new(
+            Arc::new(scheme.clone()),
+            ids.iter()
+                .map(|id| {
+                    Arc::new(scheme.participant(id).unwrap()) as Arc<dyn ThresholdParticipant>
+                })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_any_quorum_signs_withdrawals() {
+        let scheme = scheme();
+        let mut system = CustodySystem::new();
+        system
+            .create_wallet("cold".to_string(), "0x1".to_string(), WalletType::Cold)
+            .unwrap();
+        system.deposit("cold", amount!(10)).unwrap();
+
+        let tx = system
+            .prepare_withdrawal("cold", "0xdest", amount!(1))
+            .unwrap();
+        let by_alice_bob = local(&scheme, &["alice", "bob"])
+            .sign(&tx.payload())
+            .unwrap();
+        let by_bob_carol = local(&scheme, &["bob", "carol"])
+            .sign(&tx.payload())
+            .unwrap();
+        assert_eq!(by_alice_bob, by_bob_carol);
+        assert_eq!(by_alice_bob.key_id, "mpc:treasury");
+        assert_eq!(by_alice_bob.bytes, scheme.expected_signature(&tx.payload()));
+        system.submit_signed(&tx, &by_alice_bob).unwrap();
+
+        assert!(matches!(
+            system.withdraw_signed("cold", "0xdest", amount!(1), &local(&scheme, &["carol"])),
+            Err(CustodyError::SigningFailed(_))
+        ));
+        system
+            .withdraw_signed(
+                "cold",
+                "0xdest",
+                amount!(1),
+                &local(&scheme, &["alice", "carol"]),
+            )
+            .unwrap();
+        assert_eq!(system.get_wallet("cold").unwrap().balance, amount!(8));
+    }
+
+    #[test]
+    fn test_ceremony_round_coordination() {
+        let scheme = scheme();
+        let mut ceremony = SigningCeremony::new(Arc::new(scheme.clone()), b"payload");
+        assert!(ceremony.register("mallory").is_err());
+        ceremony.register("alice").unwrap();
+        ceremony.register("bob").unwrap();
+        assert_eq!(ceremony.start().unwrap(), CeremonyStatus::Round(1));
+        assert!(ceremony.register("carol").is_err());
+
+        let alice = scheme.participant("alice").unwrap();
+        let bob = scheme.participant("bob").unwrap();
+        let message =
+            |participant: &MockParticipant, round, earlier: &[RoundMessage]| RoundMessage {
+                participant: participant.id(),
+                round,
+                bytes: participant
+                    .round_message(round, b"payload", &[], earlier)
+                    .unwrap(),
+            };
+        assert_eq!(
+            ceremony.submit(message(&alice, 1, &[])).unwrap(),
+            CeremonyStatus::Round(1)
+        );
+        assert_eq!(ceremony.awaiting(), ["bob"]);
+        assert!(ceremony.submit(message(&alice, 1, &[])).is_err());
+        assert!(ceremony.submit(message(&bob, 2, &[])).is_err());
+        assert_eq!(
+            ceremony.submit(message(&bob, 1, &[])).unwrap(),
+            CeremonyStatus::Round(2)
+        );
+
+        let earlier: Vec<RoundMessage> =
+            ceremony.completed_messages().into_iter().cloned().collect();
+        assert_eq!(earlier.len(), 2);
+        ceremony.submit(message(&alice, 2, &earlier)).unwrap();
+        // bob responds as if alice had committed to something else
+        let mut tampered = earlier.clone();
+        tampered[0].bytes[0] ^= 1;
+        assert!(matches!(
+            ceremony.submit(message(&bob, 2, &tampered)),
+            Err(CustodyError::SigningFailed(reason)) if reason == "bob sent an invalid share"
+        ));
+        assert!(matches!(ceremony.status(), CeremonyStatus::Failed(_)));
+        assert!(ceremony.signature().is_none());
+    }
+}
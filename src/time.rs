@@ -0,0 +1,277 @@
+//! Typed, timezone-aware timestamps.
+//!
+//! Timestamps are stored in UTC and serialize as Unix epoch seconds, so
+//! records written before the typed representation existed remain readable.
+//! Rendering and end-of-day cutoffs take an explicit UTC offset.
+//!
+//! A custody system reads the time from a [`Clock`]; a [`ManualClock`]
+//! replaces the system clock in simulations and tests.
+
+use chrono::{DateTime, Days, TimeZone, Utc};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+pub use chrono::{FixedOffset, NaiveDate};
+
+/// A point in time with second precision
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Timestamp(DateTime<Utc>);
+
+impl Timestamp {
+    /// The Unix epoch
+    pub const EPOCH: Timestamp = Timestamp(DateTime::UNIX_EPOCH);
+
+    /// Returns the current time
+    #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+    pub fn now() -> Self {
+        use std::time::{SystemTime, UNIX_EPOCH};
+        let secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        Self::from_unix(secs)
+    }
+
+    /// Returns the current time
+    ///
+    /// `SystemTime` is unavailable in the browser, so this asks the
+    /// JavaScript host.
+    #[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+    pub fn now() -> Self {
+        Self::from_unix((js_sys::Date::now() / 1000.0) as u64)
+    }
+
+    /// Creates a timestamp from Unix epoch seconds
+    pub fn from_unix(secs: u64) -> Self {
+        let secs = i64::try_from(secs).unwrap_or(i64::MAX);
+        Self(DateTime::from_timestamp(secs, 0).unwrap_or(DateTime::<Utc>::MAX_UTC))
+    }
+
+    /// Returns the Unix epoch seconds
+    pub fn as_unix(&self) -> u64 {
+        self.0.timestamp().max(0) as u64
+    }
+
+    /// Returns the underlying UTC datetime
+    pub fn datetime(&self) -> DateTime<Utc> {
+        self.0
+    }
+
+    /// Returns this instant as local time at the given UTC offset
+    pub fn in_offset(&self, offset: FixedOffset) -> DateTime<FixedOffset> {
+        self.0.with_timezone(&offset)
+    }
+
+    /// Renders the timestamp at the given UTC offset using a `strftime`
+    /// style format string
+    pub fn format_in(&self, offset: FixedOffset, format: &str) -> String {
+        self.in_offset(offset).format(format).to_string()
+    }
+
+    /// Returns the calendar date of this instant at the given UTC offset.
+    /// Statements and end-of-day processing bucket transactions by this date.
+    pub fn business_date(&self, offset: FixedOffset) -> NaiveDate {
+        self.in_offset(offset).date_naive()
+    }
+
+    /// Returns the first instant of `date` at the given UTC offset
+    pub fn start_of_day(date: NaiveDate, offset: FixedOffset) -> Self {
+        let local = date.and_hms_opt(0, 0, 0).expect("midnight is valid");
+        let instant = offset
+            .from_local_datetime(&local)
+            .single()
+            .expect("fixed offsets are unambiguous");
+        Self(instant.with_timezone(&Utc))
+    }
+
+    /// Returns the end-of-day cutoff for `date` at the given UTC offset:
+    /// the first instant of the following day. Transactions strictly before
+    /// the cutoff belong to `date`.
+    pub fn day_cutoff(date: NaiveDate, offset: FixedOffset) -> Self {
+        let next = date.checked_add_days(Days::new(1)).unwrap_or(date);
+        Self::start_of_day(next, offset)
+    }
+}
+
+/// Returns the UTC offset `hours:minutes` east of UTC (negative for west)
+pub fn utc_offset(hours: i32, minutes: i32) -> Option<FixedOffset> {
+    let sign = if hours < 0 { -1 } else { 1 };
+    FixedOffset::east_opt(hours * 3600 + sign * minutes * 60)
+}
+
+/// Source of the current time
+pub trait Clock: fmt::Debug + Send + Sync {
+    fn now(&self) -> Timestamp;
+}
+
+/// The host's clock
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Timestamp {
+        Timestamp::now()
+    }
+}
+
+/// A clock that only moves when told to
+///
+/// # Example
+/// ```
+/// use securevault::time::{Clock, ManualClock, Timestamp};
+/// let clock = ManualClock::new(Timestamp::from_unix(1_700_000_000));
+/// clock.advance(60);
+/// assert_eq!(clock.now().as_unix(), 1_700_000_060);
+/// ```
+#[derive(Debug, Default)]
+pub struct ManualClock(AtomicU64);
+
+impl ManualClock {
+    /// Creates a clock showing `start`
+    pub fn new(start: Timestamp) -> Self {
+        Self(AtomicU64::new(start.as_unix()))
+    }
+
+    /// Sets the time shown
+    pub fn set(&self, at: Timestamp) {
+        self.0.store(at.as_unix(), Ordering::SeqCst);
+    }
+
+    /// Moves the clock forward by `secs` seconds
+    pub fn advance(&self, secs: u64) {
+        self.0.fetch_add(secs, Ordering::SeqCst);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Timestamp {
+        Timestamp::from_unix(self.0.load(Ordering::SeqCst))
+    }
+}
+
+impl crate::CustodySystem {
+    /// Reads the time from `clock` from now on, e.g. a [`ManualClock`] to
+    /// replay a scenario or a host-provided clock in the browser
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    /// Returns the current time according to the system's clock
+    pub fn now(&self) -> Timestamp {
+        self.current_timestamp()
+    }
+}
+
+impl Default for Timestamp {
+    fn default() -> Self {
+        Self::EPOCH
+    }
+}
+
+impl From<u64> for Timestamp {
+    fn from(secs: u64) -> Self {
+        Self::from_unix(secs)
+    }
+}
+
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0.format("%Y-%m-%dT%H:%M:%SZ"))
+    }
+}
+
+impl Serialize for Timestamp {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(self.as_unix())
+    }
+}
+
+impl<'de> Deserialize<'de> for Timestamp {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        u64::deserialize(deserializer).map(Self::from_unix)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unix_round_trip() {
+        let ts = Timestamp::from_unix(1_700_000_000);
+        assert_eq!(ts.as_unix(), 1_700_000_000);
+        assert_eq!(ts.to_string(), "2023-11-14T22:13:20Z");
+        assert!(Timestamp::now() > ts);
+    }
+
+    #[test]
+    fn test_system_reads_injected_clock() {
+        let clock = Arc::new(ManualClock::new(Timestamp::from_unix(1_000)));
+        let mut system = crate::CustodySystem::new();
+        system.set_clock(clock.clone());
+        system
+            .create_wallet("w".to_string(), "0x1".to_string(), crate::WalletType::Hot)
+            .unwrap();
+        system.deposit("w", amount!(1)).unwrap();
+        clock.advance(90);
+        system.deposit("w", amount!(1)).unwrap();
+
+        let times: Vec<u64> = system
+            .iter_transactions()
+            .map(|tx| tx.timestamp.as_unix())
+            .collect();
+        assert_eq!(times, [1_000, 1_090]);
+        assert_eq!(
+            system.get_wallet("w").unwrap().created_at,
+            Timestamp::from_unix(1_000)
+        );
+    }
+
+    #[test]
+    fn test_render_in_offset() {
+        let ts = Timestamp::from_unix(1_700_000_000);
+        let sao_paulo = utc_offset(-3, 0).unwrap();
+        assert_eq!(
+            ts.format_in(sao_paulo, "%Y-%m-%d %H:%M %z"),
+            "2023-11-14 19:13 -0300"
+        );
+        let kolkata = utc_offset(5, 30).unwrap();
+        assert_eq!(ts.format_in(kolkata, "%H:%M"), "03:43");
+    }
+
+    #[test]
+    fn test_business_date_depends_on_offset() {
+        let ts = Timestamp::from_unix(1_700_000_000);
+        let tokyo = utc_offset(9, 0).unwrap();
+        assert_eq!(
+            ts.business_date(tokyo),
+            NaiveDate::from_ymd_opt(2023, 11, 15).unwrap()
+        );
+        assert_eq!(
+            ts.business_date(utc_offset(0, 0).unwrap()),
+            NaiveDate::from_ymd_opt(2023, 11, 14).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_day_cutoff() {
+        let date = NaiveDate::from_ymd_opt(2023, 11, 14).unwrap();
+        let new_york = utc_offset(-5, 0).unwrap();
+        let cutoff = Timestamp::day_cutoff(date, new_york);
+        assert_eq!(cutoff.to_string(), "2023-11-15T05:00:00Z");
+        assert_eq!(
+            Timestamp::start_of_day(date, new_york).to_string(),
+            "2023-11-14T05:00:00Z"
+        );
+    }
+
+    #[test]
+    fn test_serializes_as_epoch_seconds() {
+        let ts = Timestamp::from_unix(42);
+        let json = serde_json::to_string(&ts).unwrap();
+        assert_eq!(json, "42");
+        assert_eq!(serde_json::from_str::<Timestamp>("42").unwrap(), ts);
+    }
+}
@@ -0,0 +1,286 @@
+//! Travel-rule information on outbound transfers.
+//!
+//! Under the FATF travel rule a VASP sending funds must pass on who sent
+//! them and who receives them. [`CustodySystem::withdraw_with_travel_rule`]
+//! attaches a [`TravelRuleInfo`] to a withdrawal to an external address,
+//! stored in the transaction's metadata under keys starting with
+//! [`TRAVEL_RULE_METADATA_PREFIX`] and read back with
+//! [`Transaction::travel_rule`]. Exports carry it for compliance reporting.
+//!
+//! Once a threshold is set with
+//! [`CustodySystem::set_travel_rule_threshold`], withdrawals to an external
+//! address of at least that amount need the information, with both
+//! parties' names and account references filled in.
+//! [`CustodySystem::withdraw_to`] refuses them; below the threshold the
+//! information is optional and not validated. The threshold is in the
+//! units of the withdrawn asset.
+
+use crate::precheck::Authorization;
+use crate::{Amount, CustodyError, CustodySystem, Transaction};
+use serde::{Deserialize, Serialize};
+
+/// Prefix of the transaction metadata keys holding travel-rule information
+pub const TRAVEL_RULE_METADATA_PREFIX: &str = "travel_rule.";
+
+/// One side of a transfer
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Party {
+    /// Legal name of the person or entity
+    pub name: String,
+    /// The VASP holding the account, `None` for an unhosted wallet
+    pub vasp: Option<String>,
+    /// Account number or wallet address at the VASP
+    pub account_reference: String,
+}
+
+impl Party {
+    /// Creates a party held at `vasp`
+    pub fn new(name: &str, vasp: Option<&str>, account_reference: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            vasp: vasp.map(str::to_string),
+            account_reference: account_reference.to_string(),
+        }
+    }
+}
+
+/// Originator and beneficiary of an outbound transfer
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TravelRuleInfo {
+    pub originator: Party,
+    pub beneficiary: Party,
+}
+
+impl TravelRuleInfo {
+    /// Creates the information for a transfer from `originator` to
+    /// `beneficiary`
+    pub fn new(originator: Party, beneficiary: Party) -> Self {
+        Self {
+            originator,
+            beneficiary,
+        }
+    }
+
+    /// Checks that both parties' names and account references are set
+    ///
+    /// # Errors
+    /// [`CustodyError::IncompleteTravelRule`] naming the first blank field,
+    /// e.g. `beneficiary.name`
+    pub fn validate(&self) -> Result<(), CustodyError> {
+        for (role, party) in [
+            ("originator", &self.originator),
+            ("beneficiary", &self.beneficiary),
+        ] {
+            for (field, value) in [
+                ("name", &party.name),
+                ("account_reference", &party.account_reference),
+            ] {
+                if value.trim().is_empty() {
+                    return Err(CustodyError::IncompleteTravelRule(format!(
+                        "{}.{}",
+                        role, field
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the metadata entries the information is stored as
+    pub(crate) fn to_fields(&self) -> Vec<(String, String)> {
+        let mut fields = Vec::new();
+        for (role, party) in [
+            ("originator", &self.originator),
+            ("beneficiary", &self.beneficiary),
+        ] {
+            let key = |field: &str| format!("{}{}.{}", TRAVEL_RULE_METADATA_PREFIX, role, field);
+            fields.push((key("name"), party.name.clone()));
+            if let Some(vasp) = &party.vasp {
+                fields.push((key("vasp"), vasp.clone()));
+            }
+            fields.push((key("account_reference"), party.account_reference.clone()));
+        }
+        fields
+    }
+}
+
+impl Transaction {
+    /// Returns the travel-rule information attached to the transaction, if
+    /// any
+    pub fn travel_rule(&self) -> Option<TravelRuleInfo> {
+        let party = |role: &str| {
+            let field = |name: &str| {
+                self.metadata
+                    .get(&format!("{}{}.{}", TRAVEL_RULE_METADATA_PREFIX, role, name))
+                    .cloned()
+            };
+            Some(Party {
+                name: field("name")?,
+                vasp: field("vasp"),
+                account_reference: field("account_reference")?,
+            })
+        };
+        Some(TravelRuleInfo {
+            originator: party("originator")?,
+            beneficiary: party("beneficiary")?,
+        })
+    }
+}
+
+impl CustodySystem {
+    /// Sets the amount from which withdrawals to an external address need
+    /// travel-rule information, or lifts the requirement with `None`
+    pub fn set_travel_rule_threshold(&mut self, threshold: Option<Amount>) {
+        self.travel_rule_threshold = threshold;
+    }
+
+    /// Returns the travel-rule threshold, if one is set
+    pub fn travel_rule_threshold(&self) -> Option<Amount> {
+        self.travel_rule_threshold
+    }
+
+    /// Withdraws funds to an external `destination` and attaches the
+    /// transfer's originator and beneficiary to the transaction
+    ///
+    /// At or above the travel-rule threshold the information must be
+    /// complete; see [`TravelRuleInfo::validate`].
+    ///
+    /// # Example
+    /// ```
+    /// use securevault::{amount, CustodyError, CustodySystem, Party, TravelRuleInfo, WalletType};
+    /// let mut system = CustodySystem::new();
+    /// system.create_wallet("w".to_string(), "0x1".to_string(), WalletType::Hot).unwrap();
+    /// system.deposit("w", amount!(10)).unwrap();
+    /// system.set_travel_rule_threshold(Some(amount!(1)));
+    ///
+    /// assert!(matches!(
+    ///     system.withdraw_to("w", amount!(2), "bc1qpeer"),
+    ///     Err(CustodyError::TravelRuleRequired { .. })
+    /// ));
+    /// let info = TravelRuleInfo::new(
+    ///     Party::new("Alice Smith", Some("SecureVault"), "C-1001"),
+    ///     Party::new("Bob Jones", Some("Peer Exchange"), "bc1qpeer"),
+    /// );
+    /// system.withdraw_with_travel_rule("w", amount!(2), "bc1qpeer", info.clone()).unwrap();
+    /// assert_eq!(system.get_all_transactions()[1].travel_rule(), Some(info));
+    /// ```
+    pub fn withdraw_with_travel_rule(
+        &mut self,
+        id: &str,
+        amount: Amount,
+        destination: &str,
+        info: TravelRuleInfo,
+    ) -> Result<(), CustodyError> {
+        if let Some(reason) = self.travel_rule_blockers(amount, Some(&info)) {
+            return Err(reason);
+        }
+        self.execute_withdrawal(id, None, amount, Some(destination), Authorization::Direct)?;
+        self.update_last_transaction(|tx| tx.metadata.extend(info.to_fields()))
+    }
+
+    /// Checks a withdrawal to an external address against the travel-rule
+    /// threshold
+    pub(crate) fn travel_rule_blockers(
+        &self,
+        amount: Amount,
+        info: Option<&TravelRuleInfo>,
+    ) -> Option<CustodyError> {
+        let threshold = self.travel_rule_threshold.filter(|t| amount >= *t)?;
+        match info {
+            Some(info) => info.validate().err(),
+            None => Some(CustodyError::TravelRuleRequired { amount, threshold }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::WalletType;
+
+    fn system() -> CustodySystem {
+        let mut system = CustodySystem::new();
+        system
+            .create_wallet("w".to_string(), "0x1".to_string(), WalletType::Hot)
+            .unwrap();
+        system.deposit("w", amount!(100)).unwrap();
+        system.set_travel_rule_threshold(Some(amount!(10)));
+        system
+    }
+
+    fn info() -> TravelRuleInfo {
+        TravelRuleInfo::new(
+            Party::new("Alice Smith", Some("SecureVault"), "C-1001"),
+            Party::new("Bob Jones", None, "bc1qbob"),
+        )
+    }
+
+    #[test]
+    fn test_threshold() {
+        let mut system = system();
+        system.withdraw_to("w", amount!(9.99), "bc1qbob").unwrap();
+        assert_eq!(
+            system.withdraw_to("w", amount!(10), "bc1qbob"),
+            Err(CustodyError::TravelRuleRequired {
+                amount: amount!(10),
+                threshold: amount!(10),
+            })
+        );
+
+        let mut incomplete = info();
+        incomplete.beneficiary.account_reference = " ".to_string();
+        assert_eq!(
+            system.withdraw_with_travel_rule("w", amount!(10), "bc1qbob", incomplete.clone()),
+            Err(CustodyError::IncompleteTravelRule(
+                "beneficiary.account_reference".to_string()
+            ))
+        );
+        assert_eq!(system.get_wallet("w").unwrap().balance, amount!(90.01));
+
+        // Below the threshold partial information is recorded as given
+        system
+            .withdraw_with_travel_rule("w", amount!(1), "bc1qbob", incomplete.clone())
+            .unwrap();
+        assert_eq!(
+            system.get_all_transactions()[2].travel_rule(),
+            Some(incomplete)
+        );
+
+        system.set_travel_rule_threshold(None);
+        system.withdraw_to("w", amount!(50), "bc1qbob").unwrap();
+    }
+
+    #[test]
+    fn test_info_survives_in_metadata() {
+        let mut system = system();
+        system
+            .withdraw_with_travel_rule("w", amount!(20), "bc1qbob", info())
+            .unwrap();
+        let tx = system.get_all_transactions()[1].clone();
+        assert_eq!(tx.travel_rule(), Some(info()));
+        assert_eq!(tx.metadata["travel_rule.originator.vasp"], "SecureVault");
+        assert!(!tx.metadata.contains_key("travel_rule.beneficiary.vasp"));
+        assert_eq!(system.get_all_transactions()[0].travel_rule(), None);
+
+        let mut csv = Vec::new();
+        system
+            .export_transactions(
+                crate::ExportFormat::Csv,
+                &crate::TransactionFilter::default(),
+                &mut csv,
+            )
+            .unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert!(csv.lines().nth(1).unwrap().ends_with(",,,,,,"));
+        assert!(csv
+            .lines()
+            .nth(2)
+            .unwrap()
+            .ends_with(",Alice Smith,SecureVault,C-1001,Bob Jones,,bc1qbob"));
+        // A failed withdrawal records nothing
+        assert!(system
+            .withdraw_with_travel_rule("w", amount!(1000), "bc1qbob", info())
+            .is_err());
+        assert_eq!(system.get_all_transactions().len(), 2);
+    }
+}
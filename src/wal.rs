@@ -0,0 +1,429 @@
+//! Write-ahead log persistence.
+//!
+//! [`WalStorage`] keeps the ledger in a directory holding a checkpoint, the
+//! ledger as of the last compaction, and a log of the changes made since.
+//! Each custody operation appends one line with all of its changes and
+//! fsyncs it before the transaction enters the in-memory ledger, so an
+//! acknowledged operation survives a crash and a torn final line is all a
+//! crash can leave behind. [`CustodySystem::recover`] replays the log on
+//! startup and drops such a line.
+//!
+//! Compaction folds the log into a new checkpoint and starts an empty log.
+//! Checkpoint and log carry a generation number; a log older than the
+//! checkpoint has already been folded into it, so a crash halfway through
+//! compaction never replays a change twice.
+//!
+//! # Format
+//!
+//! `checkpoint.json` holds `{"generation": 3, "snapshot": {...}}`.
+//! `wal.jsonl` starts with a `{"generation": 3}` header, followed by one
+//! JSON array of [`Change`]s per operation:
+//!
+//! ```text
+//! {"generation":3}
+//! [{"op":"transaction_append","index":7,"transaction":{...}},{"op":"wallet_upsert","wallet":{...}}]
+//! ```
+
+use crate::cdc::Change;
+use crate::storage::storage_failed;
+use crate::{CustodyError, CustodySystem, Snapshot, Storage, Transaction, Wallet};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write as _};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+const CHECKPOINT_FILE: &str = "checkpoint.json";
+const LOG_FILE: &str = "wal.jsonl";
+
+#[derive(Serialize, Deserialize)]
+struct Checkpoint {
+    generation: u64,
+    snapshot: Snapshot,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Header {
+    generation: u64,
+}
+
+/// Stores the ledger as a checkpoint plus a write-ahead log of changes
+///
+/// Appends cost one small write and an fsync; the checkpoint is only
+/// rewritten by [`compact`](Self::compact) and by saves of the whole
+/// ledger, such as [`CustodySystem::sync_storage`].
+#[derive(Debug)]
+pub struct WalStorage {
+    dir: PathBuf,
+    state: Mutex<State>,
+}
+
+#[derive(Debug)]
+struct State {
+    generation: u64,
+    log: File,
+    /// Length of the ledger's transaction log, for appends made through
+    /// [`Storage::append_transaction`]
+    transactions: usize,
+}
+
+impl WalStorage {
+    /// Opens the ledger stored in `dir`, creating the directory if needed
+    ///
+    /// A torn final log line left by a crash is truncated away, as is a
+    /// log already folded into the checkpoint. A log whose header cannot be
+    /// read, or names a generation the checkpoint has not reached, fails
+    /// with [`CustodyError::StorageFailed`] and is left untouched.
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self, CustodyError> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir).map_err(storage_failed)?;
+        let checkpoint = read_checkpoint(&dir)?;
+        let generation = checkpoint.as_ref().map_or(0, |c| c.generation);
+
+        let log_path = dir.join(LOG_FILE);
+        let contents = match fs::read(&log_path) {
+            Ok(bytes) => bytes,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(err) => return Err(storage_failed(err)),
+        };
+        // Everything after the last newline is an incomplete write
+        let complete = contents
+            .iter()
+            .rposition(|&byte| byte == b'\n')
+            .map_or(0, |end| end + 1);
+        let header = if contents.is_empty() {
+            None
+        } else {
+            let line = contents[..complete]
+                .split(|&byte| byte == b'\n')
+                .next()
+                .unwrap_or_default();
+            let header = serde_json::from_slice::<Header>(line).map_err(|err| {
+                storage_failed(format!("unreadable header in {}: {}", LOG_FILE, err))
+            })?;
+            if header.generation > generation {
+                return Err(storage_failed(format!(
+                    "{} is at generation {} but the checkpoint only at {}",
+                    LOG_FILE, header.generation, generation
+                )));
+            }
+            Some(header)
+        };
+        if header.is_some_and(|header| header.generation == generation) {
+            if complete < contents.len() {
+                let file = OpenOptions::new()
+                    .write(true)
+                    .open(&log_path)
+                    .map_err(storage_failed)?;
+                file.set_len(complete as u64).map_err(storage_failed)?;
+                file.sync_all().map_err(storage_failed)?;
+            }
+        } else {
+            write_atomically(&log_path, &header_line(generation)?)?;
+        }
+
+        let storage = Self {
+            state: Mutex::new(State {
+                generation,
+                log: open_log(&log_path)?,
+                transactions: 0,
+            }),
+            dir,
+        };
+        let transactions = storage
+            .load()?
+            .map_or(0, |snapshot| snapshot.transactions.len());
+        storage.state().transactions = transactions;
+        Ok(storage)
+    }
+
+    /// Directory holding the checkpoint and the log
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Folds the log into a new checkpoint and starts an empty log
+    pub fn compact(&self) -> Result<(), CustodyError> {
+        let mut state = self.state();
+        let snapshot = self.read()?.unwrap_or_default();
+        self.checkpoint(&mut state, &snapshot)
+    }
+
+    /// Reads the checkpoint and replays the log on top of it
+    fn read(&self) -> Result<Option<Snapshot>, CustodyError> {
+        let checkpoint = read_checkpoint(&self.dir)?;
+        let log = fs::read_to_string(self.dir.join(LOG_FILE)).map_err(storage_failed)?;
+        let mut lines = log.lines().skip(1).peekable();
+        if checkpoint.is_none() && lines.peek().is_none() {
+            return Ok(None);
+        }
+        let mut snapshot = checkpoint.map(|c| c.snapshot).unwrap_or_default();
+        for line in lines {
+            let changes: Vec<Change> = serde_json::from_str(line).map_err(storage_failed)?;
+            for change in changes {
+                replay(&mut snapshot, change)?;
+            }
+        }
+        Ok(Some(snapshot))
+    }
+
+    /// Writes `snapshot` as the next generation's checkpoint, then replaces
+    /// the log with an empty one of that generation
+    fn checkpoint(&self, state: &mut State, snapshot: &Snapshot) -> Result<(), CustodyError> {
+        let generation = state.generation + 1;
+        let json = serde_json::to_vec(&Checkpoint {
+            generation,
+            snapshot: snapshot.clone(),
+        })
+        .map_err(storage_failed)?;
+        write_atomically(&self.dir.join(CHECKPOINT_FILE), &json)?;
+        let log_path = self.dir.join(LOG_FILE);
+        write_atomically(&log_path, &header_line(generation)?)?;
+        state.log = open_log(&log_path)?;
+        state.generation = generation;
+        state.transactions = snapshot.transactions.len();
+        Ok(())
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Storage for WalStorage {
+    fn load(&self) -> Result<Option<Snapshot>, CustodyError> {
+        let _state = self.state();
+        self.read()
+    }
+
+    fn save(&self, snapshot: &Snapshot) -> Result<(), CustodyError> {
+        let mut state = self.state();
+        self.checkpoint(&mut state, snapshot)
+    }
+
+    fn append_transaction(&self, transaction: &Transaction) -> Result<(), CustodyError> {
+        let index = self.state().transactions;
+        self.write_changes(&[Change::TransactionAppend {
+            index,
+            transaction: transaction.clone(),
+        }])
+    }
+
+    fn update_transaction(
+        &self,
+        index: usize,
+        transaction: &Transaction,
+    ) -> Result<(), CustodyError> {
+        self.write_changes(&[Change::TransactionUpdate {
+            index,
+            transaction: transaction.clone(),
+        }])
+    }
+
+    fn put_wallet(&self, wallet: &Wallet) -> Result<(), CustodyError> {
+        self.write_changes(&[Change::WalletUpsert {
+            wallet: wallet.clone(),
+        }])
+    }
+
+    fn write_changes(&self, changes: &[Change]) -> Result<(), CustodyError> {
+        let mut line = serde_json::to_vec(changes).map_err(storage_failed)?;
+        line.push(b'\n');
+        let mut state = self.state();
+        // One write per operation, so a crash tears at most this line
+        state.log.write_all(&line).map_err(storage_failed)?;
+        state.log.sync_data().map_err(storage_failed)?;
+        state.transactions += changes
+            .iter()
+            .filter(|change| matches!(change, Change::TransactionAppend { .. }))
+            .count();
+        Ok(())
+    }
+}
+
+impl CustodySystem {
+    /// Opens the write-ahead log in `dir` and rebuilds the ledger from it
+    ///
+    /// Equivalent to [`with_storage`](Self::with_storage) with a
+    /// [`WalStorage`]: the log is replayed on top of the last checkpoint
+    /// and every later operation is appended to it.
+    ///
+    /// # Example
+    /// ```
+    /// use securevault::{amount, CustodySystem, WalletType};
+    ///
+    /// let dir = std::env::temp_dir().join(format!("securevault-wal-doc-{}", std::process::id()));
+    /// let mut system = CustodySystem::recover(&dir).unwrap();
+    /// system.create_wallet("w".to_string(), "0x1".to_string(), WalletType::Hot).unwrap();
+    /// system.deposit("w", amount!(2.5)).unwrap();
+    /// drop(system);
+    ///
+    /// let recovered = CustodySystem::recover(&dir).unwrap();
+    /// assert_eq!(recovered.get_wallet("w").unwrap().balance, amount!(2.5));
+    /// # std::fs::remove_dir_all(&dir).unwrap();
+    /// ```
+    pub fn recover(dir: impl AsRef<Path>) -> Result<Self, CustodyError> {
+        Self::with_storage(Arc::new(WalStorage::open(dir)?))
+    }
+}
+
+fn replay(snapshot: &mut Snapshot, change: Change) -> Result<(), CustodyError> {
+    match change {
+        Change::WalletUpsert { wallet } => {
+            match snapshot.wallets.binary_search_by(|w| w.id.cmp(&wallet.id)) {
+                Ok(position) => snapshot.wallets[position] = wallet,
+                Err(position) => snapshot.wallets.insert(position, wallet),
+            }
+        }
+        Change::TransactionAppend { transaction, .. } => snapshot.transactions.push(transaction),
+        Change::TransactionUpdate { index, transaction } => {
+            let slot = snapshot
+                .transactions
+                .get_mut(index)
+                .ok_or(CustodyError::TransactionNotFound(index))?;
+            *slot = transaction;
+        }
+    }
+    Ok(())
+}
+
+fn read_checkpoint(dir: &Path) -> Result<Option<Checkpoint>, CustodyError> {
+    match fs::read(dir.join(CHECKPOINT_FILE)) {
+        Ok(bytes) => serde_json::from_slice(&bytes)
+            .map(Some)
+            .map_err(storage_failed),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(storage_failed(err)),
+    }
+}
+
+fn header_line(generation: u64) -> Result<Vec<u8>, CustodyError> {
+    let mut line = serde_json::to_vec(&Header { generation }).map_err(storage_failed)?;
+    line.push(b'\n');
+    Ok(line)
+}
+
+fn open_log(path: &Path) -> Result<File, CustodyError> {
+    OpenOptions::new()
+        .append(true)
+        .open(path)
+        .map_err(storage_failed)
+}
+
+/// Replaces `path` with `contents` through a synced temporary file
+pub(crate) fn write_atomically(path: &Path, contents: &[u8]) -> Result<(), CustodyError> {
+    let mut staging = path.to_path_buf().into_os_string();
+    staging.push(".tmp");
+    let staging = PathBuf::from(staging);
+    let mut file = File::create(&staging).map_err(storage_failed)?;
+    file.write_all(contents).map_err(storage_failed)?;
+    file.sync_all().map_err(storage_failed)?;
+    fs::rename(&staging, path).map_err(storage_failed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::WalletType;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("securevault-wal-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn populate(system: &mut CustodySystem) {
+        for id in ["a", "b"] {
+            system
+                .create_wallet(id.to_string(), id.to_string(), WalletType::Hot)
+                .unwrap();
+        }
+        system.deposit("a", amount!(10)).unwrap();
+        system.transfer("a", "b", amount!(4)).unwrap();
+        let transfer = system.get_all_transactions()[1].id;
+        system.reverse_transaction(transfer, "wrong payee").unwrap();
+    }
+
+    #[test]
+    fn test_recover_replays_the_log() {
+        let dir = temp_dir("replay");
+        let mut system = CustodySystem::recover(&dir).unwrap();
+        populate(&mut system);
+        let expected = system.snapshot();
+        drop(system);
+
+        let recovered = CustodySystem::recover(&dir).unwrap();
+        assert_eq!(recovered.snapshot(), expected);
+        assert!(recovered.audit().passed());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_torn_final_line_is_dropped() {
+        let dir = temp_dir("torn");
+        let mut system = CustodySystem::recover(&dir).unwrap();
+        populate(&mut system);
+        let expected = system.snapshot();
+        drop(system);
+
+        // A crash in the middle of appending the next operation
+        let mut log = open_log(&dir.join(LOG_FILE)).unwrap();
+        log.write_all(br#"[{"op":"wallet_upsert","wal"#).unwrap();
+        drop(log);
+
+        let mut recovered = CustodySystem::recover(&dir).unwrap();
+        assert_eq!(recovered.snapshot(), expected);
+        recovered.deposit("b", amount!(1)).unwrap();
+        let expected = recovered.snapshot();
+        drop(recovered);
+        assert_eq!(CustodySystem::recover(&dir).unwrap().snapshot(), expected);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_unreadable_log_header_is_left_alone() {
+        let dir = temp_dir("header");
+        let mut system = CustodySystem::recover(&dir).unwrap();
+        populate(&mut system);
+        drop(system);
+
+        let log_path = dir.join(LOG_FILE);
+        let log = fs::read_to_string(&log_path).unwrap();
+        let (_, operations) = log.split_once('\n').unwrap();
+        for header in ["{\"generation\":", "{\"generation\":7}"] {
+            let damaged = format!("{}\n{}", header, operations);
+            fs::write(&log_path, &damaged).unwrap();
+            assert!(matches!(
+                WalStorage::open(&dir),
+                Err(CustodyError::StorageFailed(_))
+            ));
+            assert_eq!(fs::read_to_string(&log_path).unwrap(), damaged);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_compaction_folds_the_log_into_a_checkpoint() {
+        let dir = temp_dir("compact");
+        let storage = Arc::new(WalStorage::open(&dir).unwrap());
+        let mut system = CustodySystem::with_storage(storage.clone()).unwrap();
+        populate(&mut system);
+        storage.compact().unwrap();
+        let log = fs::read_to_string(dir.join(LOG_FILE)).unwrap();
+        assert_eq!(log.lines().count(), 1);
+
+        system.withdraw("a", amount!(1)).unwrap();
+        let expected = system.snapshot();
+        drop(system);
+        assert_eq!(CustodySystem::recover(&dir).unwrap().snapshot(), expected);
+
+        // A crash after the checkpoint but before the log was replaced
+        // leaves a log of the previous generation, which is ignored
+        let stale = fs::read(dir.join(LOG_FILE)).unwrap();
+        WalStorage::open(&dir).unwrap().compact().unwrap();
+        fs::write(dir.join(LOG_FILE), stale).unwrap();
+        assert_eq!(CustodySystem::recover(&dir).unwrap().snapshot(), expected);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
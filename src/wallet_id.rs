@@ -0,0 +1,206 @@
+//! Deterministic wallet id generation.
+//!
+//! Instead of choosing ids themselves, services can derive them from what
+//! the wallet is for: the customer, the asset, and a purpose. Every service
+//! using the same [`IdScheme`] derives the same id for the same inputs, so
+//! concurrent creators converge on one wallet instead of racing.
+//!
+//! Schemes are registered by name on the custody system. The built-in
+//! [`HashedIdScheme`] is registered as `"hashed"`.
+
+use crate::{Asset, CustodyError, CustodySystem, Wallet, WalletType};
+use std::fmt;
+use std::sync::Arc;
+
+/// Name under which [`HashedIdScheme`] is registered by default
+pub const DEFAULT_ID_SCHEME: &str = "hashed";
+
+/// What a wallet is for; the input to id derivation
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct IdInput {
+    pub customer_id: String,
+    pub asset: Asset,
+    /// Free-form purpose, e.g. `deposit` or `savings`
+    pub purpose: String,
+}
+
+/// Derives wallet ids from [`IdInput`]s
+///
+/// Implementations must be pure: the same input always yields the same id,
+/// in every process and release.
+pub trait IdScheme: fmt::Debug + Send + Sync {
+    fn derive(&self, input: &IdInput) -> String;
+}
+
+/// Ids of the form `<prefix>_<16 hex digits>`, from a 64-bit FNV-1a hash of
+/// the customer id, asset symbol, and purpose
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HashedIdScheme {
+    pub prefix: String,
+}
+
+impl Default for HashedIdScheme {
+    fn default() -> Self {
+        Self {
+            prefix: "w".to_string(),
+        }
+    }
+}
+
+impl IdScheme for HashedIdScheme {
+    fn derive(&self, input: &IdInput) -> String {
+        const OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+        const PRIME: u64 = 0x0000_0100_0000_01b3;
+        let mut hash = OFFSET;
+        let fields = [
+            input.customer_id.as_str(),
+            input.asset.symbol(),
+            input.purpose.as_str(),
+        ];
+        for field in fields {
+            // Length-prefix each field so ("ab", "c") != ("a", "bc")
+            for byte in (field.len() as u64)
+                .to_be_bytes()
+                .iter()
+                .chain(field.as_bytes())
+            {
+                hash ^= *byte as u64;
+                hash = hash.wrapping_mul(PRIME);
+            }
+        }
+        format!("{}_{:016x}", self.prefix, hash)
+    }
+}
+
+impl CustodySystem {
+    /// Registers an id scheme under `name`, replacing any previous one
+    pub fn register_id_scheme(&mut self, name: &str, scheme: Arc<dyn IdScheme>) {
+        self.id_schemes.insert(name.to_string(), scheme);
+    }
+
+    /// Derives the id `scheme` assigns to `input`, without creating anything
+    pub fn derive_wallet_id(&self, scheme: &str, input: &IdInput) -> Result<String, CustodyError> {
+        self.id_schemes
+            .get(scheme)
+            .map(|scheme| scheme.derive(input))
+            .ok_or_else(|| CustodyError::IdSchemeNotFound(scheme.to_string()))
+    }
+
+    /// Creates a wallet whose id is derived from `input` by the named scheme
+    ///
+    /// # Errors
+    /// * [`CustodyError::WalletAlreadyExists`] if a wallet was already
+    ///   derived from the same input (another service won the race)
+    /// * [`CustodyError::IdCollision`] if the derived id is taken by a
+    ///   wallet created for something else
+    pub fn create_wallet_derived(
+        &mut self,
+        scheme: &str,
+        input: &IdInput,
+        address: String,
+        wallet_type: WalletType,
+    ) -> Result<Wallet, CustodyError> {
+        let id = self.derive_wallet_id(scheme, input)?;
+        if self.wallet_id_taken(&id) {
+            return Err(match self.derived_ids.get(&id) {
+                Some(existing) if existing == input => CustodyError::WalletAlreadyExists(id),
+                _ => CustodyError::IdCollision(id),
+            });
+        }
+        let wallet =
+            self.create_wallet_with_asset(id.clone(), address, wallet_type, input.asset.clone())?;
+        self.derived_ids.insert(id, input.clone());
+        Ok(wallet)
+    }
+
+    /// Returns the input a wallet id was derived from, if it was derived
+    pub fn wallet_id_input(&self, wallet_id: &str) -> Option<&IdInput> {
+        self.derived_ids.get(wallet_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input(customer: &str, purpose: &str) -> IdInput {
+        IdInput {
+            customer_id: customer.to_string(),
+            asset: Asset::Btc,
+            purpose: purpose.to_string(),
+        }
+    }
+
+    /// Maps every input to the same id
+    #[derive(Debug)]
+    struct Constant;
+
+    impl IdScheme for Constant {
+        fn derive(&self, _: &IdInput) -> String {
+            "fixed".to_string()
+        }
+    }
+
+    #[test]
+    fn test_hashed_ids_are_stable_and_distinct() {
+        let scheme = HashedIdScheme::default();
+        let id = scheme.derive(&input("cust_1", "deposit"));
+        assert_eq!(id, scheme.derive(&input("cust_1", "deposit")));
+        assert_eq!(id.len(), 2 + 16);
+        assert_ne!(id, scheme.derive(&input("cust_1", "savings")));
+        assert_ne!(
+            scheme.derive(&input("ab", "c")),
+            scheme.derive(&input("a", "bc"))
+        );
+    }
+
+    #[test]
+    fn test_concurrent_creators_converge() {
+        let mut system = CustodySystem::new();
+        let request = input("cust_1", "deposit");
+        let wallet = system
+            .create_wallet_derived(
+                DEFAULT_ID_SCHEME,
+                &request,
+                "bc1a".to_string(),
+                WalletType::Hot,
+            )
+            .unwrap();
+        assert_eq!(system.wallet_id_input(&wallet.id), Some(&request));
+
+        let second = system.create_wallet_derived(
+            DEFAULT_ID_SCHEME,
+            &request,
+            "bc1b".to_string(),
+            WalletType::Hot,
+        );
+        assert_eq!(second, Err(CustodyError::WalletAlreadyExists(wallet.id)));
+    }
+
+    #[test]
+    fn test_collision_detected() {
+        let mut system = CustodySystem::new();
+        system.register_id_scheme("constant", Arc::new(Constant));
+        system
+            .create_wallet_derived(
+                "constant",
+                &input("a", "x"),
+                "1".to_string(),
+                WalletType::Hot,
+            )
+            .unwrap();
+        assert_eq!(
+            system.create_wallet_derived(
+                "constant",
+                &input("b", "x"),
+                "2".to_string(),
+                WalletType::Hot
+            ),
+            Err(CustodyError::IdCollision("fixed".to_string()))
+        );
+        assert_eq!(
+            system.derive_wallet_id("missing", &input("a", "x")),
+            Err(CustodyError::IdSchemeNotFound("missing".to_string()))
+        );
+    }
+}
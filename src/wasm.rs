@@ -0,0 +1,129 @@
+//! JavaScript bindings for running the ledger in the browser.
+//!
+//! [`WasmCustodySystem`] wraps a [`CustodySystem`] for `wasm-bindgen`,
+//! exported to JavaScript as `CustodySystem`. Amounts cross the boundary
+//! as decimal strings, since a JavaScript number cannot hold 18 fractional
+//! digits exactly, and wallets, transactions and snapshots as JSON text.
+//! Errors are thrown as `Error`s carrying the [`CustodyError`] message.
+//!
+//! The bindings read the time from a [`ManualClock`], so a simulation
+//! controls it with `setTime` and `advanceTime` rather than racing the
+//! host's clock. File storage compiles for the browser but has no file
+//! system to write to; persist snapshots from JavaScript instead.
+
+use crate::time::{ManualClock, Timestamp};
+use crate::{Amount, CustodyError, CustodySystem, Snapshot, WalletType};
+use std::sync::Arc;
+use wasm_bindgen::prelude::*;
+
+/// A custody system driven from JavaScript
+#[wasm_bindgen(js_name = CustodySystem)]
+pub struct WasmCustodySystem {
+    system: CustodySystem,
+    clock: Arc<ManualClock>,
+}
+
+#[wasm_bindgen(js_class = CustodySystem)]
+impl WasmCustodySystem {
+    /// Creates an empty system whose clock starts at `now` (Unix seconds)
+    #[wasm_bindgen(constructor)]
+    pub fn new(now: u64) -> Self {
+        let clock = Arc::new(ManualClock::new(Timestamp::from_unix(now)));
+        let mut system = CustodySystem::new();
+        system.set_clock(clock.clone());
+        Self { system, clock }
+    }
+
+    /// Sets the simulated time (Unix seconds)
+    #[wasm_bindgen(js_name = setTime)]
+    pub fn set_time(&self, now: u64) {
+        self.clock.set(Timestamp::from_unix(now));
+    }
+
+    /// Moves the simulated time forward by `secs` seconds
+    #[wasm_bindgen(js_name = advanceTime)]
+    pub fn advance_time(&self, secs: u64) {
+        self.clock.advance(secs);
+    }
+
+    /// Creates a `"hot"` or `"cold"` wallet and returns it as JSON
+    #[wasm_bindgen(js_name = createWallet)]
+    pub fn create_wallet(
+        &mut self,
+        id: String,
+        address: String,
+        wallet_type: &str,
+    ) -> Result<String, JsError> {
+        let wallet_type = match wallet_type {
+            "hot" => WalletType::Hot,
+            "cold" => WalletType::Cold,
+            other => return Err(JsError::new(&format!("unknown wallet type: {}", other))),
+        };
+        let wallet = self.system.create_wallet(id, address, wallet_type)?;
+        to_json(&wallet)
+    }
+
+    pub fn deposit(&mut self, wallet_id: &str, amount: &str) -> Result<(), JsError> {
+        Ok(self.system.deposit(wallet_id, parse_amount(amount)?)?)
+    }
+
+    pub fn withdraw(&mut self, wallet_id: &str, amount: &str) -> Result<(), JsError> {
+        Ok(self.system.withdraw(wallet_id, parse_amount(amount)?)?)
+    }
+
+    pub fn transfer(&mut self, from: &str, to: &str, amount: &str) -> Result<(), JsError> {
+        Ok(self.system.transfer(from, to, parse_amount(amount)?)?)
+    }
+
+    /// Returns a wallet's balance as a decimal string
+    pub fn balance(&self, wallet_id: &str) -> Result<String, JsError> {
+        Ok(self.wallet(wallet_id)?.balance.to_string())
+    }
+
+    /// Returns a wallet as JSON
+    #[wasm_bindgen(js_name = getWallet)]
+    pub fn get_wallet(&self, wallet_id: &str) -> Result<String, JsError> {
+        to_json(self.wallet(wallet_id)?)
+    }
+
+    /// Returns the wallet's transactions as a JSON array, oldest first
+    #[wasm_bindgen(js_name = getWalletTransactions)]
+    pub fn get_wallet_transactions(&self, wallet_id: &str) -> Result<String, JsError> {
+        to_json(&self.system.get_wallet_transactions(wallet_id))
+    }
+
+    /// Returns the whole transaction log as a JSON array
+    #[wasm_bindgen(js_name = getAllTransactions)]
+    pub fn get_all_transactions(&self) -> Result<String, JsError> {
+        let transactions: Vec<_> = self.system.iter_transactions().collect();
+        to_json(&transactions)
+    }
+
+    /// Returns a JSON snapshot of the state, for `restore`
+    pub fn snapshot(&self) -> Result<String, JsError> {
+        Ok(self.system.snapshot().to_json()?)
+    }
+
+    /// Replaces the state with a JSON snapshot
+    pub fn restore(&mut self, json: &str) -> Result<(), JsError> {
+        Ok(self.system.restore(Snapshot::from_json(json)?)?)
+    }
+}
+
+impl WasmCustodySystem {
+    fn wallet(&self, wallet_id: &str) -> Result<&crate::Wallet, CustodyError> {
+        self.system
+            .get_wallet(wallet_id)
+            .ok_or_else(|| CustodyError::WalletNotFound(wallet_id.to_string()))
+    }
+}
+
+fn parse_amount(amount: &str) -> Result<Amount, JsError> {
+    amount
+        .parse()
+        .map_err(|err: crate::ParseAmountError| JsError::new(&err.to_string()))
+}
+
+fn to_json<T: serde::Serialize + ?Sized>(value: &T) -> Result<String, JsError> {
+    serde_json::to_string(value).map_err(|err| JsError::new(&err.to_string()))
+}
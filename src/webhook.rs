@@ -0,0 +1,569 @@
+//! Signed webhooks for custody events (feature `webhooks`).
+//!
+//! A [`WebhookDispatcher`] is an [`EventListener`]: each committed event
+//! that matches an endpoint's [`EventFilter`] is queued as a JSON delivery.
+//! [`process_due`](WebhookDispatcher::process_due), called directly or by a
+//! [`WebhookWorker`] thread, POSTs due deliveries and reschedules failed ones
+//! with exponential backoff, so a slow receiver never holds up the ledger.
+//!
+//! Every request carries `X-SecureVault-Signature: sha256=<hex>`, the
+//! HMAC-SHA256 of the body under the endpoint's secret, along with the
+//! event kind and a delivery id receivers can deduplicate on.
+
+use crate::http;
+use crate::time::Timestamp;
+use crate::{CustodyEvent, EventListener, Uuid};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// Header carrying the HMAC-SHA256 of the body
+pub const SIGNATURE_HEADER: &str = "X-SecureVault-Signature";
+
+/// Header carrying the event kind
+pub const EVENT_HEADER: &str = "X-SecureVault-Event";
+
+/// Header carrying the delivery id, stable across retries
+pub const DELIVERY_HEADER: &str = "X-SecureVault-Delivery";
+
+/// Sends webhook requests
+pub trait WebhookTransport: Send + Sync {
+    /// POSTs `body` to `url`, succeeding only on a 2xx response
+    fn post(&self, url: &str, headers: &[(&str, String)], body: &[u8]) -> Result<(), String>;
+}
+
+/// Minimal HTTP/1.1 client for plain `http://` endpoints
+///
+/// Endpoints behind TLS need a transport built on a full HTTP client.
+#[derive(Debug, Clone)]
+pub struct HttpTransport {
+    pub timeout: Duration,
+}
+
+impl Default for HttpTransport {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+impl WebhookTransport for HttpTransport {
+    fn post(&self, url: &str, headers: &[(&str, String)], body: &[u8]) -> Result<(), String> {
+        let mut headers = headers.to_vec();
+        headers.push(("Content-Type", "application/json".to_string()));
+        let response = http::send("POST", url, &headers, body, self.timeout)?;
+        if (200..300).contains(&response.status) {
+            Ok(())
+        } else {
+            Err(format!("HTTP {}", response.status))
+        }
+    }
+}
+
+/// Which events an endpoint receives; empty sets match everything
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EventFilter {
+    /// Event kinds, see [`CustodyEvent::kind`]
+    pub kinds: BTreeSet<String>,
+    /// Wallets whose events are sent
+    pub wallet_ids: BTreeSet<String>,
+}
+
+impl EventFilter {
+    /// Matches every event
+    pub fn all() -> Self {
+        Self::default()
+    }
+
+    /// Restricts to an event kind; may be repeated
+    pub fn kind(mut self, kind: &str) -> Self {
+        self.kinds.insert(kind.to_string());
+        self
+    }
+
+    /// Restricts to a wallet's events; may be repeated
+    pub fn wallet(mut self, wallet_id: &str) -> Self {
+        self.wallet_ids.insert(wallet_id.to_string());
+        self
+    }
+
+    /// Returns true if the endpoint should receive `event`
+    pub fn matches(&self, event: &CustodyEvent) -> bool {
+        let counterparty = match event {
+            CustodyEvent::TransferCompleted { to_wallet_id, .. } => Some(to_wallet_id.as_str()),
+            _ => None,
+        };
+        let wallet_match = self.wallet_ids.is_empty()
+            || std::iter::once(event.wallet_id())
+                .chain(counterparty)
+                .any(|id| self.wallet_ids.contains(id));
+        (self.kinds.is_empty() || self.kinds.contains(event.kind())) && wallet_match
+    }
+}
+
+/// How often and how patiently failed deliveries are retried
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Attempts before a delivery is given up, including the first
+    pub max_attempts: u32,
+    /// Wait after the first failure; doubles after each further failure
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 8,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(600),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Wait before the attempt following `failures` failed ones
+    pub fn backoff(&self, failures: u32) -> Duration {
+        let factor = 1u32
+            .checked_shl(failures.saturating_sub(1))
+            .unwrap_or(u32::MAX);
+        self.initial_backoff
+            .saturating_mul(factor)
+            .min(self.max_backoff)
+    }
+}
+
+/// A queued or abandoned webhook request
+#[derive(Debug, Clone, PartialEq)]
+pub struct WebhookDelivery {
+    pub id: Uuid,
+    pub endpoint_id: u64,
+    pub url: String,
+    pub event_kind: &'static str,
+    pub body: String,
+    pub attempts: u32,
+    /// Error of the latest attempt
+    pub last_error: Option<String>,
+    next_attempt: Instant,
+}
+
+struct Endpoint {
+    url: String,
+    secret: String,
+    filter: EventFilter,
+}
+
+#[derive(Default)]
+struct State {
+    endpoints: BTreeMap<u64, Endpoint>,
+    next_endpoint_id: u64,
+    queue: VecDeque<WebhookDelivery>,
+    failed: Vec<WebhookDelivery>,
+}
+
+/// Queues custody events for registered webhook endpoints and delivers them
+pub struct WebhookDispatcher {
+    transport: Arc<dyn WebhookTransport>,
+    retry: RetryPolicy,
+    state: Mutex<State>,
+}
+
+impl fmt::Debug for WebhookDispatcher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = self.state();
+        f.debug_struct("WebhookDispatcher")
+            .field("endpoints", &state.endpoints.len())
+            .field("queued", &state.queue.len())
+            .field("failed", &state.failed.len())
+            .finish()
+    }
+}
+
+impl WebhookDispatcher {
+    /// Creates a dispatcher sending through `transport`
+    ///
+    /// # Example
+    /// ```
+    /// use securevault::{amount, CustodySystem, EventFilter, HttpTransport, WalletType, WebhookDispatcher};
+    /// use std::sync::Arc;
+    ///
+    /// let webhooks = Arc::new(WebhookDispatcher::new(Arc::new(HttpTransport::default())));
+    /// webhooks.register(
+    ///     "http://accounting.internal/hooks/custody",
+    ///     "shared-secret",
+    ///     EventFilter::all().kind("deposit_received"),
+    /// );
+    /// let mut system = CustodySystem::new();
+    /// system.subscribe(webhooks.clone());
+    /// system.create_wallet("w".to_string(), "0x1".to_string(), WalletType::Hot).unwrap();
+    /// system.deposit("w", amount!(5)).unwrap();
+    /// assert_eq!(webhooks.queued().len(), 1);
+    /// ```
+    pub fn new(transport: Arc<dyn WebhookTransport>) -> Self {
+        Self::with_retry_policy(transport, RetryPolicy::default())
+    }
+
+    /// Creates a dispatcher with a custom retry policy
+    pub fn with_retry_policy(transport: Arc<dyn WebhookTransport>, retry: RetryPolicy) -> Self {
+        Self {
+            transport,
+            retry,
+            state: Mutex::new(State {
+                next_endpoint_id: 1,
+                ..State::default()
+            }),
+        }
+    }
+
+    /// Registers an endpoint, returning its id
+    pub fn register(&self, url: &str, secret: &str, filter: EventFilter) -> u64 {
+        let mut state = self.state();
+        let id = state.next_endpoint_id;
+        state.next_endpoint_id += 1;
+        state.endpoints.insert(
+            id,
+            Endpoint {
+                url: url.to_string(),
+                secret: secret.to_string(),
+                filter,
+            },
+        );
+        id
+    }
+
+    /// Removes an endpoint and drops its queued deliveries, returning
+    /// whether it was registered
+    pub fn unregister(&self, endpoint_id: u64) -> bool {
+        let mut state = self.state();
+        state
+            .queue
+            .retain(|delivery| delivery.endpoint_id != endpoint_id);
+        state.endpoints.remove(&endpoint_id).is_some()
+    }
+
+    /// Deliveries waiting for their first or next attempt
+    pub fn queued(&self) -> Vec<WebhookDelivery> {
+        self.state().queue.iter().cloned().collect()
+    }
+
+    /// Deliveries abandoned after exhausting the retry policy
+    pub fn failed(&self) -> Vec<WebhookDelivery> {
+        self.state().failed.clone()
+    }
+
+    /// Attempts every delivery that is due, returning how many succeeded
+    pub fn process_due(&self) -> usize {
+        let now = Instant::now();
+        let due: Vec<WebhookDelivery> = {
+            let mut state = self.state();
+            let (due, waiting): (Vec<_>, Vec<_>) = state
+                .queue
+                .drain(..)
+                .partition(|delivery| delivery.next_attempt <= now);
+            state.queue = waiting.into();
+            due
+        };
+
+        let mut delivered = 0;
+        for mut delivery in due {
+            let Some(secret) = self
+                .state()
+                .endpoints
+                .get(&delivery.endpoint_id)
+                .map(|endpoint| endpoint.secret.clone())
+            else {
+                // Unregistered while the attempt was pending
+                continue;
+            };
+            let headers = [
+                (SIGNATURE_HEADER, sign(&secret, delivery.body.as_bytes())),
+                (EVENT_HEADER, delivery.event_kind.to_string()),
+                (DELIVERY_HEADER, delivery.id.to_string()),
+            ];
+            delivery.attempts += 1;
+            match self
+                .transport
+                .post(&delivery.url, &headers, delivery.body.as_bytes())
+            {
+                Ok(()) => delivered += 1,
+                Err(err) => {
+                    delivery.last_error = Some(err);
+                    let mut state = self.state();
+                    if delivery.attempts >= self.retry.max_attempts {
+                        state.failed.push(delivery);
+                    } else {
+                        delivery.next_attempt =
+                            Instant::now() + self.retry.backoff(delivery.attempts);
+                        state.queue.push_back(delivery);
+                    }
+                }
+            }
+        }
+        delivered
+    }
+
+    /// Requeues abandoned deliveries for immediate retry, returning how many
+    pub fn retry_failed(&self) -> usize {
+        let mut state = self.state();
+        let failed = std::mem::take(&mut state.failed);
+        let count = failed.len();
+        let now = Instant::now();
+        state
+            .queue
+            .extend(failed.into_iter().map(|delivery| WebhookDelivery {
+                attempts: 0,
+                next_attempt: now,
+                ..delivery
+            }));
+        count
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl EventListener for WebhookDispatcher {
+    fn on_event(&self, event: &CustodyEvent) {
+        let body = serde_json::json!({
+            "event": event.kind(),
+            "created_at": Timestamp::now(),
+            "data": event,
+        })
+        .to_string();
+        let now = Instant::now();
+        let mut state = self.state();
+        let deliveries: Vec<WebhookDelivery> = state
+            .endpoints
+            .iter()
+            .filter(|(_, endpoint)| endpoint.filter.matches(event))
+            .map(|(id, endpoint)| WebhookDelivery {
+                id: Uuid::new_v4(),
+                endpoint_id: *id,
+                url: endpoint.url.clone(),
+                event_kind: event.kind(),
+                body: body.clone(),
+                attempts: 0,
+                last_error: None,
+                next_attempt: now,
+            })
+            .collect();
+        state.queue.extend(deliveries);
+    }
+}
+
+/// Background thread calling [`WebhookDispatcher::process_due`]
+///
+/// The thread stops when the worker is dropped.
+pub struct WebhookWorker {
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl WebhookWorker {
+    /// Starts delivering for `dispatcher`, polling every `interval`
+    pub fn spawn(dispatcher: Arc<WebhookDispatcher>, interval: Duration) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let flag = stop.clone();
+        let handle = std::thread::spawn(move || {
+            while !flag.load(Ordering::SeqCst) {
+                dispatcher.process_due();
+                std::thread::sleep(interval);
+            }
+        });
+        Self {
+            stop,
+            handle: Some(handle),
+        }
+    }
+}
+
+impl Drop for WebhookWorker {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// Value of the signature header for `body`
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CustodySystem, WalletType};
+    use std::io::{BufRead, BufReader, Write as _};
+    use std::net::TcpListener;
+
+    struct Sent {
+        url: String,
+        headers: Vec<(String, String)>,
+        body: String,
+    }
+
+    /// Records requests and fails the first `failures` of them
+    #[derive(Default)]
+    struct Recorder {
+        failures: Mutex<u32>,
+        sent: Mutex<Vec<Sent>>,
+    }
+
+    impl WebhookTransport for Recorder {
+        fn post(&self, url: &str, headers: &[(&str, String)], body: &[u8]) -> Result<(), String> {
+            let mut failures = self.failures.lock().unwrap();
+            if *failures > 0 {
+                *failures -= 1;
+                return Err("HTTP 503".to_string());
+            }
+            let headers = headers
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.clone()))
+                .collect();
+            self.sent.lock().unwrap().push(Sent {
+                url: url.to_string(),
+                headers,
+                body: String::from_utf8(body.to_vec()).unwrap(),
+            });
+            Ok(())
+        }
+    }
+
+    fn immediate() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::ZERO,
+            max_backoff: Duration::ZERO,
+        }
+    }
+
+    fn system(webhooks: &Arc<WebhookDispatcher>) -> CustodySystem {
+        let mut system = CustodySystem::new();
+        system.subscribe(webhooks.clone());
+        for id in ["a", "b"] {
+            system
+                .create_wallet(id.to_string(), id.to_string(), WalletType::Hot)
+                .unwrap();
+        }
+        system
+    }
+
+    #[test]
+    fn test_filtered_signed_deliveries() {
+        let transport = Arc::new(Recorder::default());
+        let webhooks = Arc::new(WebhookDispatcher::with_retry_policy(
+            transport.clone(),
+            immediate(),
+        ));
+        webhooks.register("http://ledger/all", "s1", EventFilter::all());
+        webhooks.register(
+            "http://ledger/b",
+            "s2",
+            EventFilter::all().kind("transfer_completed").wallet("b"),
+        );
+        let mut system = system(&webhooks);
+        system.deposit("a", amount!(5)).unwrap();
+        system.transfer("a", "b", amount!(2)).unwrap();
+        system.withdraw("b", amount!(1)).unwrap();
+        assert_eq!(webhooks.process_due(), 6);
+
+        let sent = transport.sent.lock().unwrap();
+        let to_b: Vec<_> = sent
+            .iter()
+            .filter(|sent| sent.url == "http://ledger/b")
+            .collect();
+        assert_eq!(to_b.len(), 1);
+        let Sent { headers, body, .. } = to_b[0];
+        let header = |name: &str| &headers.iter().find(|(n, _)| n == name).unwrap().1;
+        assert_eq!(header(EVENT_HEADER), "transfer_completed");
+        assert_eq!(header(SIGNATURE_HEADER), &sign("s2", body.as_bytes()));
+        let payload: serde_json::Value = serde_json::from_str(body).unwrap();
+        assert_eq!(payload["data"]["TransferCompleted"]["to_wallet_id"], "b");
+    }
+
+    #[test]
+    fn test_retries_with_backoff_then_gives_up() {
+        let transport = Arc::new(Recorder {
+            failures: Mutex::new(4),
+            ..Recorder::default()
+        });
+        let webhooks = Arc::new(WebhookDispatcher::with_retry_policy(
+            transport.clone(),
+            immediate(),
+        ));
+        webhooks.register(
+            "http://ledger",
+            "s",
+            EventFilter::all().kind("deposit_received"),
+        );
+        let mut system = system(&webhooks);
+        system.deposit("a", amount!(1)).unwrap();
+
+        for _ in 0..3 {
+            assert_eq!(webhooks.process_due(), 0);
+        }
+        assert!(webhooks.queued().is_empty());
+        let failed = webhooks.failed();
+        assert_eq!(failed[0].attempts, 3);
+        assert_eq!(failed[0].last_error.as_deref(), Some("HTTP 503"));
+
+        assert_eq!(webhooks.retry_failed(), 1);
+        assert_eq!(webhooks.process_due(), 0);
+        assert_eq!(webhooks.process_due(), 1);
+        assert_eq!(transport.sent.lock().unwrap().len(), 1);
+
+        let policy = RetryPolicy::default();
+        assert_eq!(policy.backoff(1), Duration::from_secs(1));
+        assert_eq!(policy.backoff(4), Duration::from_secs(8));
+        assert_eq!(policy.backoff(40), Duration::from_secs(600));
+    }
+
+    #[test]
+    fn test_http_transport_posts_and_checks_status() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let mut requests = Vec::new();
+            for status in ["204 No Content", "500 Internal Server Error"] {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(&stream);
+                let mut request = String::new();
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line == "\r\n" {
+                        break;
+                    }
+                    request.push_str(&line);
+                }
+                write!(&stream, "HTTP/1.1 {}\r\nContent-Length: 0\r\n\r\n", status).unwrap();
+                requests.push(request);
+            }
+            requests
+        });
+
+        let transport = HttpTransport::default();
+        let headers = [(SIGNATURE_HEADER, "sha256=00".to_string())];
+        assert_eq!(transport.post(&url, &headers, b"{}"), Ok(()));
+        assert_eq!(
+            transport.post(&url, &headers, b"{}"),
+            Err("HTTP 500".to_string())
+        );
+        let requests = server.join().unwrap();
+        assert!(requests[0].starts_with("POST /hook HTTP/1.1\r\n"));
+        assert!(requests[0].contains("X-SecureVault-Signature: sha256=00\r\n"));
+        assert!(transport.post("https://example.com", &[], b"").is_err());
+    }
+}
@@ -0,0 +1,346 @@
+//! Withdrawal address whitelists.
+//!
+//! A wallet, or a whole [`Account`](crate::Account), can be restricted to
+//! paying out to approved addresses. Once it has a whitelist, every
+//! withdrawal, whether direct, queued, approved or signed, fails unless it
+//! names a destination on the wallet's or its account's whitelist that is
+//! active. Withdrawals without a destination, including captured holds and
+//! joint wallet withdrawals, are refused. A newly added address only becomes
+//! active after a delay (24 hours by default), so an attacker who slips an
+//! address in cannot use it before someone notices.
+//!
+//! A whitelist stays in force once created, even if every address is
+//! removed from it. Transfers between wallets of the system do not leave
+//! custody and are not restricted.
+
+use crate::time::Timestamp;
+use crate::{AdminAction, CustodyError, CustodySystem};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Default time between whitelisting an address and being able to use it
+pub const DEFAULT_WHITELIST_DELAY_SECS: u64 = 24 * 60 * 60;
+
+/// An approved withdrawal destination
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WhitelistEntry {
+    pub address: String,
+    pub label: String,
+    pub added_at: Timestamp,
+    /// Withdrawals to the address are refused before this time
+    pub active_from: Timestamp,
+}
+
+impl WhitelistEntry {
+    /// Returns true if withdrawals to the address are allowed at `now`
+    pub fn is_active(&self, now: Timestamp) -> bool {
+        now >= self.active_from
+    }
+}
+
+/// Whose whitelist an address is on
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub(crate) enum WhitelistOwner {
+    Wallet(String),
+    Account(String),
+}
+
+/// Whitelisted addresses by owner
+pub(crate) type Whitelists = im::HashMap<WhitelistOwner, BTreeMap<String, WhitelistEntry>>;
+
+impl CustodySystem {
+    /// Sets the delay before addresses whitelisted from now on become
+    /// active (default [`DEFAULT_WHITELIST_DELAY_SECS`])
+    pub fn set_whitelist_delay(&mut self, secs: u64) {
+        self.whitelist_delay_secs = secs;
+    }
+
+    /// Adds an address to a wallet's whitelist, putting the whitelist in
+    /// force if it is the wallet's first
+    ///
+    /// The address is checked against the wallet's chain, if it has one.
+    ///
+    /// # Returns
+    /// When the address becomes usable
+    ///
+    /// # Example
+    /// ```
+    /// use securevault::time::{ManualClock, Timestamp};
+    /// use securevault::{amount, CustodyError, CustodySystem, WalletType};
+    /// use std::sync::Arc;
+    ///
+    /// let clock = Arc::new(ManualClock::new(Timestamp::from_unix(1_700_000_000)));
+    /// let mut system = CustodySystem::new();
+    /// system.set_clock(clock.clone());
+    /// system.create_wallet("w".to_string(), "0x1".to_string(), WalletType::Hot).unwrap();
+    /// system.deposit("w", amount!(10)).unwrap();
+    ///
+    /// system.whitelist_address("w", "bc1qpayroll", "payroll").unwrap();
+    /// assert!(matches!(
+    ///     system.withdraw_to("w", amount!(1), "bc1qpayroll"),
+    ///     Err(CustodyError::WhitelistNotActive { .. })
+    /// ));
+    /// assert!(matches!(
+    ///     system.withdraw_to("w", amount!(1), "bc1qelsewhere"),
+    ///     Err(CustodyError::AddressNotWhitelisted { .. })
+    /// ));
+    ///
+    /// clock.advance(24 * 60 * 60);
+    /// system.withdraw_to("w", amount!(1), "bc1qpayroll").unwrap();
+    /// ```
+    pub fn whitelist_address(
+        &mut self,
+        wallet_id: &str,
+        address: &str,
+        label: &str,
+    ) -> Result<Timestamp, CustodyError> {
+        let wallet = self
+            .get_wallet(wallet_id)
+            .ok_or_else(|| CustodyError::WalletNotFound(wallet_id.to_string()))?;
+        if let Some(chain) = &wallet.chain {
+            self.validate_address(chain, address)?;
+        }
+        Ok(self.add_to_whitelist(
+            WhitelistOwner::Wallet(wallet_id.to_string()),
+            address,
+            label,
+        ))
+    }
+
+    /// Adds an address to an account's whitelist, which applies to every
+    /// wallet in the account
+    pub fn whitelist_account_address(
+        &mut self,
+        account_id: &str,
+        address: &str,
+        label: &str,
+    ) -> Result<Timestamp, CustodyError> {
+        self.get_account(account_id)?;
+        Ok(self.add_to_whitelist(
+            WhitelistOwner::Account(account_id.to_string()),
+            address,
+            label,
+        ))
+    }
+
+    /// Removes an address from a wallet's whitelist; the whitelist stays in
+    /// force
+    ///
+    /// # Returns
+    /// The removed entry, if the address was on the whitelist
+    pub fn remove_whitelisted_address(
+        &mut self,
+        wallet_id: &str,
+        address: &str,
+    ) -> Result<Option<WhitelistEntry>, CustodyError> {
+        self.require_second_operator(AdminAction::REMOVE_WHITELISTED_ADDRESS)?;
+        Ok(self.remove_from_whitelist(WhitelistOwner::Wallet(wallet_id.to_string()), address))
+    }
+
+    /// Removes an address from an account's whitelist
+    pub fn remove_account_whitelisted_address(
+        &mut self,
+        account_id: &str,
+        address: &str,
+    ) -> Result<Option<WhitelistEntry>, CustodyError> {
+        self.require_second_operator(AdminAction::REMOVE_ACCOUNT_WHITELISTED_ADDRESS)?;
+        Ok(self.remove_from_whitelist(WhitelistOwner::Account(account_id.to_string()), address))
+    }
+
+    pub(crate) fn remove_from_whitelist(
+        &mut self,
+        owner: WhitelistOwner,
+        address: &str,
+    ) -> Option<WhitelistEntry> {
+        self.whitelists.get_mut(&owner)?.remove(address)
+    }
+
+    /// Lists the addresses a wallet may pay out to, its own and its
+    /// account's, active or not, ordered by address
+    pub fn whitelisted_addresses(&self, wallet_id: &str) -> Vec<&WhitelistEntry> {
+        let mut entries: Vec<_> = self
+            .whitelist_owners(wallet_id)
+            .into_iter()
+            .filter_map(|owner| self.whitelists.get(&owner))
+            .flat_map(|entries| entries.values())
+            .collect();
+        entries.sort_by(|a, b| a.address.cmp(&b.address));
+        entries
+    }
+
+    /// Returns true if the wallet, or its account, has a whitelist in force
+    pub fn has_whitelist(&self, wallet_id: &str) -> bool {
+        self.whitelist_owners(wallet_id)
+            .iter()
+            .any(|owner| self.whitelists.contains_key(owner))
+    }
+
+    /// Checks a withdrawal destination against the wallet's whitelists
+    pub(crate) fn whitelist_blockers(
+        &self,
+        wallet_id: &str,
+        destination: Option<&str>,
+    ) -> Option<CustodyError> {
+        if !self.has_whitelist(wallet_id) {
+            return None;
+        }
+        let Some(destination) = destination.filter(|d| !d.trim().is_empty()) else {
+            return Some(CustodyError::DestinationRequired(wallet_id.to_string()));
+        };
+        let now = self.current_timestamp();
+        let entries: Vec<_> = self
+            .whitelisted_addresses(wallet_id)
+            .into_iter()
+            .filter(|entry| entry.address == destination)
+            .collect();
+        if entries.iter().any(|entry| entry.is_active(now)) {
+            return None;
+        }
+        Some(match entries.iter().map(|entry| entry.active_from).min() {
+            Some(active_from) => CustodyError::WhitelistNotActive {
+                address: destination.to_string(),
+                active_from,
+            },
+            None => CustodyError::AddressNotWhitelisted {
+                wallet_id: wallet_id.to_string(),
+                address: destination.to_string(),
+            },
+        })
+    }
+
+    fn whitelist_owners(&self, wallet_id: &str) -> Vec<WhitelistOwner> {
+        let mut owners = vec![WhitelistOwner::Wallet(wallet_id.to_string())];
+        if let Some(account) = self.wallets.get(wallet_id).and_then(|w| w.account.clone()) {
+            owners.push(WhitelistOwner::Account(account));
+        }
+        owners
+    }
+
+    fn add_to_whitelist(&mut self, owner: WhitelistOwner, address: &str, label: &str) -> Timestamp {
+        let added_at = self.current_timestamp();
+        let active_from =
+            Timestamp::from_unix(added_at.as_unix().saturating_add(self.whitelist_delay_secs));
+        let entries = self.whitelists.entry(owner).or_default();
+        // Re-adding an address keeps its original activation time
+        let entry = entries
+            .entry(address.to_string())
+            .or_insert_with(|| WhitelistEntry {
+                address: address.to_string(),
+                label: label.to_string(),
+                added_at,
+                active_from,
+            });
+        entry.label = label.to_string();
+        entry.active_from
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::time::ManualClock;
+    use crate::WalletType;
+    use std::sync::Arc;
+
+    const DAY: u64 = DEFAULT_WHITELIST_DELAY_SECS;
+
+    fn system() -> (CustodySystem, Arc<ManualClock>) {
+        let clock = Arc::new(ManualClock::new(Timestamp::from_unix(1_700_000_000)));
+        let mut system = CustodySystem::new();
+        system.set_clock(clock.clone());
+        for id in ["a", "b"] {
+            system
+                .create_wallet(id.to_string(), id.to_string(), WalletType::Hot)
+                .unwrap();
+            system.deposit(id, amount!(100)).unwrap();
+        }
+        (system, clock)
+    }
+
+    #[test]
+    fn test_activation_delay() {
+        let (mut system, clock) = system();
+        // Without a whitelist any destination goes
+        system.withdraw_to("a", amount!(1), "bc1qanywhere").unwrap();
+
+        let active_from = system
+            .whitelist_address("a", "bc1qdest", "cold storage")
+            .unwrap();
+        assert_eq!(active_from.as_unix(), 1_700_000_000 + DAY);
+        assert_eq!(
+            system.withdraw_to("a", amount!(1), "bc1qdest"),
+            Err(CustodyError::WhitelistNotActive {
+                address: "bc1qdest".to_string(),
+                active_from,
+            })
+        );
+        assert!(!system
+            .can_withdraw("a", amount!(1), Some("bc1qdest"))
+            .is_allowed());
+
+        clock.advance(DAY);
+        system.withdraw_to("a", amount!(1), "bc1qdest").unwrap();
+        // Re-adding does not restart the delay
+        system
+            .whitelist_address("a", "bc1qdest", "renamed")
+            .unwrap();
+        system.withdraw_to("a", amount!(1), "bc1qdest").unwrap();
+
+        // The whitelist outlives its last address
+        system.remove_whitelisted_address("a", "bc1qdest").unwrap();
+        assert_eq!(
+            system.withdraw_to("a", amount!(1), "bc1qdest"),
+            Err(CustodyError::AddressNotWhitelisted {
+                wallet_id: "a".to_string(),
+                address: "bc1qdest".to_string(),
+            })
+        );
+        // Other wallets and internal transfers are unaffected
+        system.withdraw_to("b", amount!(1), "bc1qanywhere").unwrap();
+        system.transfer("a", "b", amount!(1)).unwrap();
+    }
+
+    #[test]
+    fn test_withdrawals_without_destination_are_refused() {
+        let (mut system, _) = system();
+        let hold = system.place_hold("a", amount!(5), "payout").unwrap();
+        system
+            .whitelist_address("a", "bc1qdest", "cold storage")
+            .unwrap();
+        let refused = CustodyError::DestinationRequired("a".to_string());
+        assert_eq!(system.withdraw("a", amount!(1)), Err(refused.clone()));
+        assert_eq!(
+            system.withdraw_asset("a", &crate::Asset::Btc, amount!(1)),
+            Err(refused.clone())
+        );
+        assert_eq!(system.capture_hold(hold), Err(refused));
+        assert_eq!(system.get_wallet("a").unwrap().balance, amount!(100));
+        system.withdraw("b", amount!(1)).unwrap();
+    }
+
+    #[test]
+    fn test_account_whitelist_covers_its_wallets() {
+        let (mut system, clock) = system();
+        system.create_account("acme", "Acme", None).unwrap();
+        system.assign_wallet("a", "acme").unwrap();
+        system.set_whitelist_delay(0);
+        system
+            .whitelist_account_address("acme", "bc1qacme", "treasury")
+            .unwrap();
+        assert!(system.has_whitelist("a"));
+        assert!(!system.has_whitelist("b"));
+        system.withdraw_to("a", amount!(1), "bc1qacme").unwrap();
+
+        // Queued withdrawals are checked on entry
+        assert!(matches!(
+            system.queue_withdrawal("a", amount!(1), Some("bc1qother"), 0),
+            Err(CustodyError::AddressNotWhitelisted { .. })
+        ));
+        clock.advance(1);
+        assert_eq!(system.whitelisted_addresses("a").len(), 1);
+        assert_eq!(
+            system.whitelist_account_address("nobody", "bc1q", ""),
+            Err(CustodyError::AccountNotFound("nobody".to_string()))
+        );
+    }
+}